@@ -0,0 +1,19 @@
+// a fullscreen shader for `--shader assets/plasma.metal`, drawn with `main_image` for every pixel
+// of the view. the prelude of the renderer declares `ShaderInputs` and includes metal_stdlib
+using namespace metal;
+
+float4 main_image(float2 frag_coord, constant ShaderInputs& inputs) {
+    float2 uv = (2 * frag_coord - inputs.resolution) / inputs.resolution.y;
+    // dragging with the left button moves the center of the waves
+    if (inputs.mouse.z > 0) {
+        uv -= (2 * inputs.mouse.xy - inputs.resolution) / inputs.resolution.y;
+    }
+    float time = inputs.time;
+    float bass = inputs.audio_bands[0];
+    float value = sin(uv.x * 3 + time)
+        + sin((uv.y * 3 + time) * 0.7)
+        + sin((uv.x + uv.y) * 2.5 + time * 1.3)
+        + sin(length(uv) * (4 + bass * 4) - time * 2);
+    float3 color = 0.5 + 0.5 * cos(value * 1.5 + float3(0, 2, 4));
+    return float4(color * (0.8 + bass * 0.4), 1);
+}
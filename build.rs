@@ -0,0 +1,40 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// precompiles src/triangle.metal into a .metallib with the xcode metal toolchain, the library
+// embeds it so startup skips the runtime compilation and shader errors fail the build
+fn main() {
+    println!("cargo:rerun-if-changed=src/triangle.metal");
+    println!("cargo:rustc-check-cfg=cfg(precompiled_shaders)");
+
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("macos") {
+        return;
+    }
+
+    // the toolchain only ships with xcode, without it the shaders are compiled at runtime
+    let toolchain = Command::new("xcrun").args(["-sdk", "macosx", "--find", "metal"]).output();
+    if !toolchain.is_ok_and(|output| output.status.success()) {
+        println!("cargo:warning=Metal toolchain not found, shaders are compiled at runtime.");
+        return;
+    }
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let air = out_dir.join("triangle.air");
+    let metallib = out_dir.join("triangle.metallib");
+    xcrun(&["metal", "-c", "src/triangle.metal", "-o"], &air);
+    xcrun(&["metallib", air.to_str().unwrap(), "-o"], &metallib);
+    println!("cargo:rustc-cfg=precompiled_shaders");
+}
+
+fn xcrun(args: &[&str], output: &Path) {
+    let status = Command::new("xcrun")
+        .args(["-sdk", "macosx"])
+        .args(args)
+        .arg(output)
+        .status()
+        .expect("Failed to run xcrun.");
+    assert!(status.success(), "Failed to build {}.", output.display());
+}
@@ -0,0 +1,271 @@
+// the settings of the example, from an optional toml file overridden by the command line. they
+// apply on top of the renderer configuration saved by the last run
+use std::{fmt, path::Path};
+
+use rust_tao_metal::{
+    DeviceSelector, FrameLimiter, GameLoop, LoopMode, RenderPath, RendererConfig, SleepStrategy,
+};
+use serde::Deserialize;
+
+// read when it's there, `--config` names another file
+pub const CONFIG_FILE: &str = "config.toml";
+
+pub const USAGE: &str = "\
+Usage: rust-tao-metal [options] [scene] [environment map]
+
+Options:
+  --config <path>    read the settings from a toml file, config.toml by default
+  --width <points>   the width of the main window
+  --height <points>  the height of the main window
+  --title <title>    the title of the main window
+  --vsync            wait for the refresh of the screen to show a frame
+  --no-vsync         show frames as soon as they're done
+  --transparent      show the desktop behind the geometry
+  --msaa <samples>   the sample count of multisampling, 1 turns it off
+  --deferred         light the scene from a g-buffer
+  --device <device>  low-power, high-performance, removable or a part of the name of a gpu
+  --validation       turn on the metal api validation
+  --camera           outline the frames of the camera, with the video feature
+  --screen <source>  capture main, a display id or a window, with screen-capture
+  --share-surface    copy the frames into an IOSurface other processes open by its id
+  --game-loop        tick every frame, even while no events come
+  --max-fps <rate>   the most ticks per second of --game-loop
+  --shader <path>    draw the view with the main_image of a shadertoy style .metal file
+  --help             print this help";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    // holds what's wrong with the arguments
+    Argument(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, error) => write!(f, "Failed to read {path}: {error}"),
+            ConfigError::Parse(path, error) => write!(f, "Failed to parse {path}: {error}"),
+            ConfigError::Argument(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// what's missing keeps the saved configuration, or the defaults of the renderer on the first run
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // the content size of the main window in points
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub title: Option<String>,
+    pub vsync: Option<bool>,
+    pub msaa: Option<usize>,
+    // draws the scene into a g-buffer and lights it after, forward when false
+    pub deferred: Option<bool>,
+    // clears the main window to transparent instead of drawing the gradient
+    pub transparent: Option<bool>,
+    // as `--device` takes it, METAL_DEVICE when missing
+    pub device: Option<String>,
+    // checks the use of the metal api, which slows every call down
+    pub validation: bool,
+    // captures the default camera and outlines the edges of its frames over the textured quad
+    pub camera: bool,
+    // captures the main display, a display by its id or the first window whose title or
+    // application contains the text over the textured quad
+    pub screen: Option<String>,
+    // copies the frames into an IOSurface, printing the id other processes open it with
+    pub share_surface: bool,
+    // ticks the gamepad and redraws the windows every frame instead of waiting for events
+    pub game_loop: bool,
+    pub max_fps: Option<f64>,
+    // a .metal file defining `main_image`, which draws the main window in place of the scene,
+    // like assets/plasma.metal
+    pub shader: Option<String>,
+    // a .gltf or .glb file and the panorama around it, only from the command line
+    #[serde(skip)]
+    pub scene: Option<String>,
+    #[serde(skip)]
+    pub environment_map: Option<String>,
+    #[serde(skip)]
+    pub help: bool,
+}
+
+impl Config {
+    // the settings of the file the arguments name, or of `CONFIG_FILE` when it's there, with
+    // the arguments on top. `args` without the name of the program
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let args: Vec<String> = args.into_iter().collect();
+        let path = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .map(|index| {
+                args.get(index + 1)
+                    .cloned()
+                    .ok_or_else(|| missing_value("--config"))
+            })
+            .transpose()?;
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(CONFIG_FILE).exists() => Self::from_file(CONFIG_FILE)?,
+            None => Self::default(),
+        };
+        config.parse_args(args)?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::Io(path.to_owned(), error))?;
+        toml::from_str(&text).map_err(|error| ConfigError::Parse(path.to_owned(), error))
+    }
+
+    fn parse_args(&mut self, args: Vec<String>) -> Result<(), ConfigError> {
+        let mut args = args.into_iter();
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| missing_value(&arg));
+            match arg.as_str() {
+                // read by `load`
+                "--config" => {
+                    value()?;
+                }
+                "--width" => self.width = Some(parse_value(&arg, &value()?)?),
+                "--height" => self.height = Some(parse_value(&arg, &value()?)?),
+                "--title" => self.title = Some(value()?),
+                "--vsync" => self.vsync = Some(true),
+                "--no-vsync" => self.vsync = Some(false),
+                "--transparent" => self.transparent = Some(true),
+                "--msaa" => self.msaa = Some(parse_value(&arg, &value()?)?),
+                "--deferred" => self.deferred = Some(true),
+                "--device" => self.device = Some(value()?),
+                "--validation" => self.validation = true,
+                "--camera" => self.camera = true,
+                "--screen" => self.screen = Some(value()?),
+                "--share-surface" => self.share_surface = true,
+                "--game-loop" => self.game_loop = true,
+                "--max-fps" => self.max_fps = Some(parse_value(&arg, &value()?)?),
+                "--shader" => self.shader = Some(value()?),
+                "--help" | "-h" => self.help = true,
+                _ if arg.starts_with('-') => {
+                    return Err(ConfigError::Argument(format!("Unknown option {arg}")))
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        self.scene = positional.next();
+        self.environment_map = positional.next();
+        if let Some(arg) = positional.next() {
+            return Err(ConfigError::Argument(format!("Unexpected argument {arg}")));
+        }
+        Ok(())
+    }
+
+    // the gpu of `device`, or of METAL_DEVICE without it, the system default without either
+    pub fn device_selector(&self) -> DeviceSelector {
+        let device = self
+            .device
+            .clone()
+            .or_else(|| std::env::var("METAL_DEVICE").ok());
+        match device.as_deref() {
+            None => DeviceSelector::SystemDefault,
+            Some("low-power") => DeviceSelector::LowPower,
+            Some("high-performance") => DeviceSelector::HighPerformance,
+            Some("removable") => DeviceSelector::Removable,
+            Some(name) => DeviceSelector::Name(name.to_owned()),
+        }
+    }
+
+    // continuous with `--game-loop`, limited to `max_fps` by waiting in the event loop
+    pub fn game_loop(&self) -> GameLoop {
+        let mode = if self.game_loop {
+            LoopMode::Continuous
+        } else {
+            LoopMode::Wait
+        };
+        let frame_limiter = self
+            .max_fps
+            .map(|max_fps| FrameLimiter::new(max_fps, SleepStrategy::WaitUntil));
+        GameLoop::new(mode).with_frame_limiter(frame_limiter)
+    }
+
+    // turns the validation on through the environment metal reads when it creates the first
+    // device, so it has to happen before anything touches metal
+    pub fn apply_validation(&self) {
+        if self.validation {
+            std::env::set_var("MTL_DEBUG_LAYER", "1");
+        }
+    }
+
+    // the window size, the vsync, the multisampling, the render path and the transparency set
+    // on top of `renderer_config`
+    pub fn apply(&self, renderer_config: &mut RendererConfig) {
+        if let Some(width) = self.width {
+            renderer_config.window_size[0] = width;
+        }
+        if let Some(height) = self.height {
+            renderer_config.window_size[1] = height;
+        }
+        if let Some(vsync) = self.vsync {
+            renderer_config.vsync = vsync;
+        }
+        if let Some(msaa) = self.msaa {
+            renderer_config.sample_count = msaa;
+        }
+        if let Some(deferred) = self.deferred {
+            renderer_config.render_path = if deferred {
+                RenderPath::Deferred
+            } else {
+                RenderPath::Forward
+            };
+        }
+        if let Some(transparent) = self.transparent {
+            renderer_config.transparent = transparent;
+        }
+    }
+
+    // puts the values of `saved` back in place of the ones `apply` overrode, so the settings of
+    // a run, like a one-off `--no-vsync` or `--msaa 1`, don't end up in the saved configuration.
+    // a value changed since the launch, from the menu or by resizing the window, is kept, it
+    // differs from the one `session` started with
+    pub fn restore(
+        &self,
+        renderer_config: &mut RendererConfig,
+        session: &RendererConfig,
+        saved: &RendererConfig,
+    ) {
+        let width = renderer_config.window_size[0] == session.window_size[0];
+        if self.width.is_some() && width {
+            renderer_config.window_size[0] = saved.window_size[0];
+        }
+        let height = renderer_config.window_size[1] == session.window_size[1];
+        if self.height.is_some() && height {
+            renderer_config.window_size[1] = saved.window_size[1];
+        }
+        if self.vsync.is_some() && renderer_config.vsync == session.vsync {
+            renderer_config.vsync = saved.vsync;
+        }
+        if self.msaa.is_some() && renderer_config.sample_count == session.sample_count {
+            renderer_config.sample_count = saved.sample_count;
+        }
+        if self.deferred.is_some() && renderer_config.render_path == session.render_path {
+            renderer_config.render_path = saved.render_path;
+        }
+        if self.transparent.is_some() && renderer_config.transparent == session.transparent {
+            renderer_config.transparent = saved.transparent;
+        }
+    }
+}
+
+fn missing_value(option: &str) -> ConfigError {
+    ConfigError::Argument(format!("Missing the value of {option}"))
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::Argument(format!("Invalid value {value} for {option}")))
+}
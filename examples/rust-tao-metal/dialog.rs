@@ -0,0 +1,53 @@
+// the open and save panels of the example. they're modal and appkit only shows them on the main
+// thread, called from another one they wait for the main thread to run them
+use std::path::PathBuf;
+
+use objc2::{msg_send, rc::Retained};
+use objc2_app_kit::{NSModalResponseOK, NSOpenPanel, NSSavePanel};
+use objc2_foundation::{run_on_main, NSArray, NSString, NSURL};
+use objc2_uniform_type_identifiers::UTType;
+
+// the file picked to open, none when the panel was cancelled. only files with one of
+// `extensions` can be picked
+pub fn open_file(message: &str, extensions: &[&str]) -> Option<PathBuf> {
+    run_on_main(|mtm| {
+        let panel = unsafe { NSOpenPanel::openPanel(mtm) };
+        unsafe {
+            panel.setCanChooseFiles(true);
+            panel.setCanChooseDirectories(false);
+            panel.setAllowsMultipleSelection(false);
+        }
+        run_modal(&panel, message, extensions)
+    })
+}
+
+// where to save a file, none when the panel was cancelled. the name field starts with
+// `file_name`, the panel adds the first of `extensions` to a name without one
+pub fn save_file(message: &str, file_name: &str, extensions: &[&str]) -> Option<PathBuf> {
+    run_on_main(|mtm| {
+        let panel = unsafe { NSSavePanel::savePanel(mtm) };
+        unsafe { panel.setNameFieldStringValue(&NSString::from_str(file_name)) };
+        run_modal(&panel, message, extensions)
+    })
+}
+
+fn run_modal(panel: &NSSavePanel, message: &str, extensions: &[&str]) -> Option<PathBuf> {
+    // the content types of the extensions, objc2-app-kit has no binding for their setter
+    let content_types: Vec<Retained<UTType>> = extensions
+        .iter()
+        .filter_map(|extension| unsafe {
+            UTType::typeWithFilenameExtension(&NSString::from_str(extension))
+        })
+        .collect();
+    let content_types = NSArray::from_vec(content_types);
+    unsafe {
+        let _: () = msg_send![panel, setAllowedContentTypes: &*content_types];
+        panel.setMessage(Some(&NSString::from_str(message)));
+        if panel.runModal() != NSModalResponseOK {
+            return None;
+        }
+    }
+    let url: Retained<NSURL> = unsafe { panel.URL() }?;
+    let path = unsafe { url.path() }?;
+    Some(PathBuf::from(path.to_string()))
+}
@@ -0,0 +1,2440 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    io::ErrorKind,
+    path::Path,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use gilrs::{Axis, Gilrs};
+use image::{Rgba, Rgba32FImage};
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_foundation::NSSize;
+#[cfg(feature = "core-image")]
+use objc2_foundation::{NSNumber, NSObjectNSKeyValueCoding, NSString};
+use objc2_metal::{
+    MTLClearColor, MTLDevice, MTLOrigin, MTLPackedFloat3, MTLPixelFormat, MTLRegion,
+    MTLResourceOptions, MTLSamplerAddressMode, MTLSamplerDescriptor, MTLSamplerMinMagFilter,
+    MTLSamplerMipFilter, MTLSize, MTLStorageMode, MTLTexture, MTLTextureDescriptor, MTLViewport,
+};
+#[cfg(feature = "egui")]
+use rust_tao_metal::egui;
+#[cfg(feature = "imgui")]
+use rust_tao_metal::imgui;
+#[cfg(feature = "core-image")]
+use rust_tao_metal::objc2_core_image::{kCIInputIntensityKey, CIFilter};
+#[cfg(feature = "ecs")]
+use rust_tao_metal::{hecs, RenderAssets, Transform};
+#[cfg(feature = "metalfx")]
+use rust_tao_metal::{Upscaler, UpscalingQuality};
+#[cfg(all(feature = "screen-capture", target_os = "macos"))]
+use rust_tao_metal::{ScreenCapture, ScreenSource};
+#[cfg(feature = "video")]
+use rust_tao_metal::{CameraCapture, VideoPlayer};
+use rust_tao_metal::{
+    available_devices, ArgumentTable, Assets, Backend, Background, BindlessTable, BlendMode,
+    ColorSpace, ComputeFilter, CullMode, CursorMode, DebugDraw, DepthFormat, DepthStencilBuilder,
+    DeviceSelector, DirectionalLight, DrawList, EntityId, FillMode, FrameStats, Handle, InputState,
+    InstanceData, Light, LoadAction, LoopMode, Material, Mesh, MeshData, MeshId, MetalRenderer,
+    PixelFormat, PointLight, PostProcess, PrimitiveType, Projection, RasterizationRates,
+    RedrawMode, RenderHandle, RenderPass, RenderPath, RenderTarget, RenderTargetBuilder,
+    RendererConfig, RendererError, Scene, SceneGraph, ShaderOptions, SpotLight, Sprite,
+    SpriteBatch, StencilFace, TextStyle, TextureError, TileConfig, VertexInput, VertexLayout,
+    Winding,
+};
+use tao::{
+    dpi::LogicalPosition,
+    event::{ElementState, Event, MouseButton, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopBuilder},
+    keyboard::{KeyCode, ModifiersState},
+    window::{Fullscreen, Window, WindowBuilder, WindowId},
+};
+#[cfg(target_os = "macos")]
+use tao::platform::macos::WindowExtMacOS;
+use tracing_subscriber::EnvFilter;
+
+mod config;
+#[cfg(target_os = "macos")]
+mod dialog;
+mod menu;
+
+use config::{Config, USAGE};
+#[cfg(target_os = "macos")]
+use menu::Menu;
+use menu::MenuCommand;
+
+// drawn over the main window
+const CONTROLS_HINT: &str =
+    "Drag to orbit the camera, drag with the right button to pan and scroll to zoom.";
+
+// set to false to leave Escape and Cmd+Q to the application instead of quitting
+const QUIT_SHORTCUTS: bool = true;
+
+// the standard macOS ways of quitting an app
+fn is_quit_shortcut(key: KeyCode, modifiers: ModifiersState) -> bool {
+    match key {
+        KeyCode::Escape => modifiers.is_empty(),
+        KeyCode::KeyQ => modifiers == ModifiersState::SUPER,
+        _ => false,
+    }
+}
+
+// how a window covers the screen, cycled through with Cmd+F or F11
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum WindowMode {
+    Windowed,
+    // covers the screen without a space of its own, instantly and under the menu bar. only on
+    // macos
+    #[cfg_attr(target_os = "ios", allow(dead_code))]
+    Borderless,
+    // the native fullscreen of macOS, animated into its own space
+    Fullscreen,
+}
+
+fn is_window_mode_shortcut(key: KeyCode, modifiers: ModifiersState) -> bool {
+    match key {
+        KeyCode::KeyF => modifiers == ModifiersState::SUPER,
+        KeyCode::F11 => modifiers.is_empty(),
+        _ => false,
+    }
+}
+
+fn window_mode(window: &Window) -> WindowMode {
+    #[cfg(target_os = "macos")]
+    if window.simple_fullscreen() {
+        return WindowMode::Borderless;
+    }
+    if window.fullscreen().is_some() {
+        WindowMode::Fullscreen
+    } else {
+        WindowMode::Windowed
+    }
+}
+
+// switches to the next window mode. borderless comes before native fullscreen, since the window
+// can only leave the borderless mode instantly and the simple fullscreen can't be entered while
+// native fullscreen is still animating out
+fn cycle_window_mode(window: &Window, renderer: &MetalRenderer) {
+    let mode = match window_mode(window) {
+        #[cfg(target_os = "macos")]
+        WindowMode::Windowed => {
+            window.set_simple_fullscreen(true);
+            WindowMode::Borderless
+        }
+        #[cfg(target_os = "macos")]
+        WindowMode::Borderless => {
+            window.set_simple_fullscreen(false);
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+            WindowMode::Fullscreen
+        }
+        // there's no simple fullscreen on ios
+        #[cfg(target_os = "ios")]
+        WindowMode::Windowed | WindowMode::Borderless => {
+            window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+            WindowMode::Fullscreen
+        }
+        WindowMode::Fullscreen => {
+            window.set_fullscreen(None);
+            WindowMode::Windowed
+        }
+    };
+    eprintln!("Window mode: {mode:?}");
+    // the simple fullscreen changes the frame without an animation, resize the view with it.
+    // the native transitions report their sizes as they go
+    renderer.resize();
+}
+
+// applies a command of the menu bar to the window in front and its renderer, quitting is up
+// to the event loop
+fn handle_menu_command(window: &Window, renderer: &MetalRenderer, command: MenuCommand) {
+    match command {
+        // in and out of the native fullscreen, from the borderless mode as well
+        MenuCommand::ToggleFullscreen => match window_mode(window) {
+            WindowMode::Fullscreen => window.set_fullscreen(None),
+            _ => {
+                #[cfg(target_os = "macos")]
+                window.set_simple_fullscreen(false);
+                window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                renderer.resize();
+            }
+        },
+        MenuCommand::ToggleVsync => {
+            let vsync = !renderer.vsync();
+            renderer.set_vsync(vsync);
+            eprintln!("VSync: {vsync}");
+        }
+        MenuCommand::CaptureFrame => {
+            if let Err(error) = renderer.capture_next_frame("frame.gputrace") {
+                eprintln!("{error}");
+            }
+        }
+        // there's no save panel on ios
+        MenuCommand::Screenshot => {
+            #[cfg(target_os = "macos")]
+            if let Some(path) = dialog::save_file("Save a screenshot", "screenshot.png", &["png"]) {
+                save_screenshot(renderer, &path);
+            }
+        }
+        // the models go into the main window, opened by the event loop
+        MenuCommand::OpenModel | MenuCommand::Quit => (),
+    }
+}
+
+// saves the next frame as a png, on demand views draw it when the window redraws
+fn save_screenshot(renderer: &MetalRenderer, path: &Path) {
+    let name = path.display().to_string();
+    renderer.capture_frame(path, move |result| match result {
+        Ok(()) => eprintln!("Saved {name}"),
+        Err(error) => eprintln!("{error}"),
+    });
+}
+
+// reports where the time of the last measured frame went
+fn print_frame_stats(frame_stats: FrameStats) {
+    let timings = [
+        ("CPU frame time", frame_stats.cpu_frame_time),
+        ("GPU frame time", frame_stats.gpu_frame_time),
+        ("GPU pass time", frame_stats.gpu_pass_time),
+        ("GPU compute time", frame_stats.gpu_compute_time),
+        ("Present interval", frame_stats.present_interval),
+        ("Present latency", frame_stats.present_latency),
+    ];
+    for (name, time) in timings {
+        match time {
+            Some(time) => eprintln!("{name}: {:.3} ms", time * 1e3),
+            None => eprintln!("{name}: not measured"),
+        }
+    }
+    eprintln!(
+        "Draws: {} encoded, {} culled",
+        frame_stats.draws, frame_stats.culled_draws
+    );
+    eprintln!(
+        "Frames: {} presented, {} missed, {} dropped",
+        frame_stats.presented_frames, frame_stats.missed_frames, frame_stats.dropped_frames
+    );
+    eprintln!(
+        "GPU memory: {:.1} of {:.1} MB",
+        frame_stats.allocated_memory as f64 / (1 << 20) as f64,
+        frame_stats.memory_budget as f64 / (1 << 20) as f64
+    );
+}
+
+// example key bindings for switching the renderer settings at runtime
+fn handle_key_pressed(renderer: &MetalRenderer, key: KeyCode, modifiers: ModifiersState) {
+    // combinations with Cmd are reserved for application shortcuts
+    if modifiers.super_key() {
+        return;
+    }
+
+    match key {
+        // cycle through the drawable pixel formats
+        KeyCode::KeyP => {
+            let pixel_format = match renderer.pixel_format() {
+                PixelFormat::Bgra8Unorm => PixelFormat::Bgra8UnormSrgb,
+                PixelFormat::Bgra8UnormSrgb => PixelFormat::Rgba16Float,
+                PixelFormat::Rgba16Float => PixelFormat::Bgra8Unorm,
+            };
+            renderer.set_pixel_format(pixel_format);
+            if pixel_format == PixelFormat::Rgba16Float {
+                let headroom = renderer.edr_headroom();
+                eprintln!(
+                    "EDR headroom: {:.2}, up to {:.2}",
+                    headroom.current, headroom.potential
+                );
+            }
+        }
+        // switch the drawable between the sRGB and the Display P3 gamut
+        KeyCode::KeyD => {
+            let color_space = match renderer.color_space() {
+                ColorSpace::Srgb => ColorSpace::DisplayP3,
+                ColorSpace::DisplayP3 => ColorSpace::Srgb,
+            };
+            renderer.set_color_space(color_space);
+            eprintln!("Color space: {color_space:?}");
+        }
+        // cycle through the primitive types
+        KeyCode::KeyT => {
+            let primitive_type = match renderer.primitive_type() {
+                PrimitiveType::Triangle => PrimitiveType::TriangleStrip,
+                PrimitiveType::TriangleStrip => PrimitiveType::Point,
+                PrimitiveType::Point => PrimitiveType::Line,
+                PrimitiveType::Line => PrimitiveType::LineStrip,
+                PrimitiveType::LineStrip => PrimitiveType::Triangle,
+            };
+            renderer.set_primitive_type(primitive_type);
+        }
+        // cycle through the cull modes and flip the front facing winding
+        KeyCode::KeyK => {
+            let cull_mode = match renderer.cull_mode() {
+                CullMode::None => CullMode::Back,
+                CullMode::Back => CullMode::Front,
+                CullMode::Front => CullMode::None,
+            };
+            renderer.set_cull_mode(cull_mode);
+        }
+        KeyCode::KeyF => {
+            let front_facing = match renderer.front_facing() {
+                Winding::Clockwise => Winding::CounterClockwise,
+                Winding::CounterClockwise => Winding::Clockwise,
+            };
+            renderer.set_front_facing(front_facing);
+        }
+        // switch between a black and an animated gradient background
+        KeyCode::KeyG => {
+            let background = if renderer.has_gradient_background() {
+                Background::Solid { color: [0., 0., 0.] }
+            } else {
+                EXAMPLE_GRADIENT
+            };
+            renderer.set_background(background);
+        }
+        // toggle wireframe rendering
+        KeyCode::KeyW => {
+            let fill_mode = match renderer.fill_mode() {
+                FillMode::Fill => FillMode::Lines,
+                FillMode::Lines => FillMode::Fill,
+            };
+            renderer.set_fill_mode(fill_mode);
+        }
+        // toggle 4x multisampling
+        KeyCode::KeyM => {
+            let sample_count = if renderer.sample_count() == 1 { 4 } else { 1 };
+            renderer.set_sample_count(sample_count);
+        }
+        // switch between the 2d and the 3d view
+        KeyCode::KeyO => {
+            let mut camera = renderer.camera();
+            camera.projection = match camera.projection {
+                Projection::Orthographic => Projection::PERSPECTIVE,
+                Projection::Perspective { .. } => Projection::Orthographic,
+            };
+            renderer.set_camera(camera);
+        }
+        // cycle the frame rate cap through 30, 60 and 120 frames per second
+        KeyCode::KeyL => {
+            let frames_per_second = match renderer.preferred_frames_per_second() {
+                30 => 60,
+                60 => 120,
+                _ => 30,
+            };
+            renderer.set_preferred_frames_per_second(frames_per_second);
+            eprintln!("Frame rate cap: {frames_per_second}");
+        }
+        // toggle vsync, without it and a cap above the refresh rate the frames run uncapped
+        KeyCode::KeyY => {
+            let vsync = !renderer.vsync();
+            renderer.set_vsync(vsync);
+            eprintln!("VSync: {vsync}");
+        }
+        // toggle pacing the frames at the 24 frames per second of film, a ProMotion display
+        // lowers its refresh rate to match
+        KeyCode::KeyJ => {
+            let minimum_frame_duration = match renderer.minimum_frame_duration() {
+                Some(_) => None,
+                None => Some(1. / 24.),
+            };
+            renderer.set_minimum_frame_duration(minimum_frame_duration);
+        }
+        // switch between the rasterized frame and the ray traced mesh, on devices with ray
+        // tracing
+        KeyCode::KeyU => {
+            renderer.set_ray_tracing(!renderer.is_ray_tracing());
+            eprintln!("Ray tracing: {}", renderer.is_ray_tracing());
+        }
+        // toggle the shadows of the directional light
+        KeyCode::KeyS => {
+            if let Some(mut light) = renderer.directional_light() {
+                light.cast_shadows = !light.cast_shadows;
+                renderer.set_directional_light(Some(light));
+                eprintln!("Shadows: {}", light.cast_shadows);
+            }
+        }
+        // toggle the post-processing chain, and its tone mapping, bloom and antialiasing
+        // stages with 1, 2 and 3
+        KeyCode::KeyA => {
+            let post_process = match renderer.post_process() {
+                Some(_) => None,
+                None => Some(PostProcess::default()),
+            };
+            renderer.set_post_process(post_process);
+            eprintln!("Post-processing: {post_process:?}");
+        }
+        KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 => {
+            if let Some(mut post_process) = renderer.post_process() {
+                match key {
+                    KeyCode::Digit1 => post_process.tonemap = !post_process.tonemap,
+                    KeyCode::Digit2 => post_process.bloom = !post_process.bloom,
+                    _ => post_process.fxaa = !post_process.fxaa,
+                }
+                renderer.set_post_process(Some(post_process));
+                eprintln!("Post-processing: {post_process:?}");
+            }
+        }
+        // switch between lighting the scene as it's drawn and from a g-buffer
+        KeyCode::Digit4 => {
+            let render_path = match renderer.render_path() {
+                RenderPath::Forward => RenderPath::Deferred,
+                RenderPath::Deferred => RenderPath::Forward,
+            };
+            renderer.set_render_path(render_path);
+            eprintln!("Render path: {render_path:?}");
+        }
+        // switch between hdr in the Display P3 gamut and sdr in sRGB in one go
+        KeyCode::Digit5 => {
+            let (pixel_format, color_space) = match renderer.pixel_format() {
+                PixelFormat::Rgba16Float => (PixelFormat::Bgra8UnormSrgb, ColorSpace::Srgb),
+                _ => (PixelFormat::Rgba16Float, ColorSpace::DisplayP3),
+            };
+            renderer.set_drawable_format(pixel_format, color_space);
+            eprintln!("Drawable format: {pixel_format:?} in {color_space:?}");
+        }
+        // rasterize the edges of the frame at a quarter of the rate of its center
+        KeyCode::Digit6 => {
+            if !renderer.supports_rasterization_rate_maps() {
+                eprintln!("Variable rasterization rates aren't supported by the gpu");
+                return;
+            }
+            let rates = match renderer.rasterization_rates() {
+                Some(_) => None,
+                None => Some(RasterizationRates::foveated((0.5, 0.5), 0.25)),
+            };
+            eprintln!("Foveated rendering: {}", rates.is_some());
+            renderer.set_rasterization_rates(rates);
+        }
+        // light a loaded scene with a ring of colored point lights and a spot light above it
+        KeyCode::Digit7 => {
+            let lights = if renderer.lights().is_empty() {
+                ring_lights()
+            } else {
+                Vec::new()
+            };
+            eprintln!("Local lights: {}", lights.len());
+            renderer.set_lights(lights);
+        }
+        // capture the sound of the input device, its bass sways the gradient background
+        #[cfg(feature = "audio")]
+        KeyCode::Digit9 => {
+            if renderer.is_capturing_audio() {
+                renderer.stop_audio_capture();
+            } else if let Err(error) = renderer.start_audio_capture() {
+                eprintln!("{error}");
+            }
+            eprintln!("Audio capture: {}", renderer.is_capturing_audio());
+        }
+        // cycle the upscaling through off, spatial and temporal
+        #[cfg(feature = "metalfx")]
+        KeyCode::KeyN => {
+            let upscaler = match renderer.upscaler() {
+                None => Some(Upscaler::Spatial),
+                Some(Upscaler::Spatial) => Some(Upscaler::Temporal),
+                Some(Upscaler::Temporal) => None,
+            };
+            renderer.set_upscaler(upscaler);
+            eprintln!("Upscaler: {upscaler:?}");
+        }
+        // cycle the resolution the upscaled frames are rendered at
+        #[cfg(feature = "metalfx")]
+        KeyCode::KeyZ => {
+            let quality = match renderer.upscaling_quality() {
+                UpscalingQuality::Performance => UpscalingQuality::Balanced,
+                UpscalingQuality::Balanced => UpscalingQuality::Quality,
+                UpscalingQuality::Quality => UpscalingQuality::UltraQuality,
+                UpscalingQuality::UltraQuality => UpscalingQuality::Performance,
+            };
+            renderer.set_upscaling_quality(quality);
+            eprintln!("Upscaling quality: {quality:?}");
+        }
+        // print what the renderer negotiated
+        KeyCode::KeyI => eprintln!("{}", renderer.info()),
+        // switch between drawing continuously and on demand
+        KeyCode::KeyR => {
+            let redraw_mode = match renderer.redraw_mode() {
+                RedrawMode::Continuous => RedrawMode::OnDemand,
+                RedrawMode::OnDemand => RedrawMode::Continuous,
+            };
+            renderer.set_redraw_mode(redraw_mode);
+        }
+        // toggle a magnifier, the middle of the view shows the scene zoomed in twice
+        KeyCode::KeyV => {
+            if renderer.has_custom_viewport() {
+                renderer.reset_viewport();
+            } else {
+                let NSSize { width, height } = renderer.drawable_size();
+                renderer.set_viewport(
+                    -width / 2.,
+                    -height / 2.,
+                    width * 2.,
+                    height * 2.,
+                    0.,
+                    1.,
+                );
+                renderer.set_scissor(
+                    (width / 4.) as usize,
+                    (height / 4.) as usize,
+                    (width / 2.) as usize,
+                    (height / 2.) as usize,
+                );
+            }
+        }
+        // grow and shrink the points
+        KeyCode::Equal => {
+            let point_size = renderer.point_size();
+            renderer.set_point_size(point_size + 1.);
+        }
+        KeyCode::Minus => {
+            let point_size = renderer.point_size();
+            renderer.set_point_size((point_size - 1.).max(1.));
+        }
+        // capture the next frame for the Xcode gpu debugger, or the next few with Shift
+        KeyCode::KeyC => {
+            let result = if modifiers.shift_key() {
+                renderer.trigger_gpu_capture("frames.gputrace", 3)
+            } else {
+                renderer.capture_next_frame("frame.gputrace")
+            };
+            if let Err(error) = result {
+                eprintln!("{error}");
+            }
+        }
+        KeyCode::KeyX => save_screenshot(renderer, Path::new("screenshot.png")),
+        // start or stop recording the frames into the recording directory
+        #[cfg(feature = "recording")]
+        KeyCode::KeyE => match renderer.stop_recording() {
+            Some(Ok(frame_count)) => eprintln!("Recorded {frame_count} frames"),
+            Some(Err(error)) => eprintln!("{error}"),
+            None => match renderer.start_recording("recording") {
+                Ok(()) => eprintln!("Recording into recording/"),
+                Err(error) => eprintln!("Failed to start recording: {error}"),
+            },
+        },
+        // run the frames through a sepia tone and a vignette of core image, or stop
+        #[cfg(feature = "core-image")]
+        KeyCode::KeyQ => {
+            if renderer.image_filters().is_empty() {
+                renderer.set_image_filters(vintage_filters());
+            } else {
+                renderer.set_image_filters(Vec::new());
+            }
+        }
+        // show or hide the frame rate overlay
+        KeyCode::KeyH => renderer.set_overlay_visible(!renderer.is_overlay_visible()),
+        // toggle the gpu benchmark mode and report the last measurement when leaving it
+        KeyCode::KeyB => {
+            if renderer.is_benchmarking() {
+                print_frame_stats(renderer.frame_stats());
+                eprintln!("{}", renderer.graph_memory_stats());
+                renderer.set_benchmark_mode(false);
+            } else {
+                renderer.set_benchmark_mode(true);
+            }
+        }
+        _ => (),
+    }
+}
+
+// a chain of filters of the core image library, the sepia tone takes the frame and the vignette
+// the toned frame
+#[cfg(feature = "core-image")]
+fn vintage_filters() -> Vec<Retained<CIFilter>> {
+    let filter = |name: &str, intensity: f64| unsafe {
+        let filter = CIFilter::filterWithName(&NSString::from_str(name))?;
+        filter.setValue_forKey(Some(&NSNumber::new_f64(intensity)), kCIInputIntensityKey);
+        Some(filter)
+    };
+    [filter("CISepiaTone", 0.7), filter("CIVignette", 1.5)]
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// a dark quad covering most of the view, drawn as a triangle strip
+fn background_vertices() -> [VertexInput; 4] {
+    let vertex = |x, y, shade| VertexInput {
+        position: MTLPackedFloat3 { x, y, z: 0. },
+        color: MTLPackedFloat3 {
+            x: shade,
+            y: shade,
+            z: shade,
+        },
+    };
+    [
+        vertex(-0.9, -0.9, 0.1),
+        vertex(0.9, -0.9, 0.1),
+        vertex(-0.9, 0.9, 0.3),
+        vertex(0.9, 0.9, 0.3),
+    ]
+}
+
+// how the sticks of a gamepad move the camera
+struct GamepadSettings {
+    // stick deflections below this are ignored, sticks rarely rest at exactly zero
+    dead_zone: f32,
+    // at full deflection the left stick pans this many half view heights per second, the
+    // right stick zooms by `1 + sensitivity` per second
+    sensitivity: f32,
+}
+
+const GAMEPAD_SETTINGS: GamepadSettings = GamepadSettings {
+    dead_zone: 0.15,
+    sensitivity: 1.,
+};
+
+// pans with the left stick and zooms with the right stick of the first connected gamepad
+// for `elapsed` seconds. moving the camera redraws a view drawing on demand
+fn apply_gamepad(
+    gilrs: &Gilrs,
+    settings: &GamepadSettings,
+    elapsed: f32,
+    renderer: &MetalRenderer,
+) {
+    let Some((_, gamepad)) = gilrs.gamepads().next() else {
+        return;
+    };
+    let axis = |axis| {
+        let value = gamepad.value(axis);
+        if value.abs() < settings.dead_zone {
+            0.
+        } else {
+            value
+        }
+    };
+    let (pan_x, pan_y) = (axis(Axis::LeftStickX), axis(Axis::LeftStickY));
+    let zoom = axis(Axis::RightStickY);
+    if pan_x == 0. && pan_y == 0. && zoom == 0. {
+        return;
+    }
+
+    let mut camera = renderer.camera();
+    let speed = settings.sensitivity * elapsed;
+    camera.pan(pan_x * speed, pan_y * speed);
+    camera.zoom *= (1. + settings.sensitivity).powf(zoom * elapsed);
+    renderer.set_camera(camera);
+}
+
+// radians the camera orbits per point the cursor is dragged
+const ORBIT_SPEED: f32 = 0.01;
+
+// radians the flying camera turns per unit the mouse moves, and units it flies per second
+const LOOK_SPEED: f32 = 0.003;
+const FLY_SPEED: f32 = 2.;
+
+// turns the camera with the mouse while the cursor is locked, flies it forward and back with
+// W and S, sideways with A and D and up and down with E and Q
+fn fly_camera(renderer: &MetalRenderer, input: &InputState, elapsed: f32) {
+    let (dx, dy) = input.mouse_motion();
+    let axis = |positive, negative| {
+        let direction = input.is_key_down(positive) as i32 - input.is_key_down(negative) as i32;
+        direction as f32 * FLY_SPEED * elapsed
+    };
+    let mut camera = renderer.camera();
+    camera.look(-dx as f32 * LOOK_SPEED, dy as f32 * LOOK_SPEED);
+    camera.fly(
+        axis(KeyCode::KeyD, KeyCode::KeyA),
+        axis(KeyCode::KeyE, KeyCode::KeyQ),
+        axis(KeyCode::KeyW, KeyCode::KeyS),
+    );
+    if camera != renderer.camera() {
+        renderer.set_camera(camera);
+    }
+}
+
+// locks the cursor to fly the camera or releases it again
+fn toggle_fly_camera(window: &Window, renderer: &MetalRenderer) {
+    let mode = if renderer.cursor_mode() == CursorMode::Locked {
+        CursorMode::Normal
+    } else {
+        CursorMode::Locked
+    };
+    match renderer.set_cursor_mode(window, mode) {
+        Ok(()) if mode == CursorMode::Locked => {
+            eprintln!("Flying the camera, press Escape or 8 to release the cursor.")
+        }
+        Ok(()) => (),
+        Err(error) => eprintln!("Failed to lock the cursor: {error}"),
+    }
+}
+
+// the draws the cursor picks, named by `entity_name`
+const TINTED_QUAD: EntityId = 1;
+const SHADOW_SCENE: EntityId = 2;
+const SPARSE_QUAD: EntityId = 3;
+
+fn entity_name(entity: EntityId) -> &'static str {
+    match entity {
+        TINTED_QUAD => "the tinted quad",
+        SHADOW_SCENE => "the shadowed scene",
+        SPARSE_QUAD => "the sparse texture",
+        _ => "an unknown entity",
+    }
+}
+
+// reports clicks on the geometry and the entities picked under the cursor, orbits the camera
+// while dragging with the left button, pans it with the right button and zooms by 10% per line
+// scrolled. the camera flies instead while the cursor is locked
+fn update_view(renderer: &MetalRenderer, input: &InputState, elapsed: f32) {
+    if renderer.cursor_mode() == CursorMode::Locked {
+        fly_camera(renderer, input, elapsed);
+        return;
+    }
+    // the mouse is left to the ui while it's over one of its windows
+    #[cfg(feature = "egui")]
+    if renderer.egui_context().wants_pointer_input() {
+        return;
+    }
+    #[cfg(feature = "imgui")]
+    if renderer.with_imgui_context(|context| context.io().want_capture_mouse) == Some(true) {
+        return;
+    }
+    let Some((x, y)) = input.cursor_position() else {
+        return;
+    };
+    // picked while hovering, the id under the cursor is read back by the time it's clicked
+    let picked = renderer.pick(x, y);
+    if input.was_button_pressed(MouseButton::Left) {
+        let point = renderer.world_from_screen(x, y);
+        if renderer.hit_test_triangle(point) {
+            eprintln!("Hit the triangle at ({:.2}, {:.2}).", point.0, point.1);
+        }
+        if let Some(entity) = picked {
+            eprintln!("Picked {}.", entity_name(entity));
+        }
+    }
+
+    let mut camera = renderer.camera();
+    let (dx, dy) = input.cursor_delta();
+    if input.is_button_down(MouseButton::Left) {
+        camera.orbit(-dx as f32 * ORBIT_SPEED, dy as f32 * ORBIT_SPEED);
+    }
+    // the point of the z = 0 plane under the cursor follows it
+    if input.is_button_down(MouseButton::Right) {
+        let from = renderer.world_from_screen(x - dx, y - dy);
+        let to = renderer.world_from_screen(x, y);
+        camera.target[0] -= to.0 - from.0;
+        camera.target[1] -= to.1 - from.1;
+    }
+    camera.zoom *= 1.1f32.powf(input.scroll().1);
+    if camera != renderer.camera() {
+        renderer.set_camera(camera);
+    }
+}
+
+// where the example keeps the configuration of its main window between runs
+const CONFIG_PATH: &str = "renderer.json";
+
+fn load_config(path: &str) -> Option<RendererConfig> {
+    let json = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json)
+        .inspect_err(|error| eprintln!("Failed to parse {path}: {error}"))
+        .ok()
+}
+
+fn save_config(path: &str, config: &RendererConfig) {
+    let json = serde_json::to_string_pretty(config).expect("Failed to serialize the config.");
+    if let Err(error) = std::fs::write(path, json) {
+        eprintln!("Failed to write {path}: {error}");
+    }
+}
+
+// dusk blue fading into a warm orange
+const EXAMPLE_GRADIENT: Background = Background::Gradient {
+    top: [0.1, 0.2, 0.5],
+    bottom: [0.9, 0.5, 0.2],
+    speed: 0.5,
+};
+
+// how many vertices `compute_wave` lays out along its line
+const WAVE_VERTEX_COUNT: usize = 128;
+
+const PARTICLE_COUNT: usize = 200_000;
+
+const SPRITE_COUNT: usize = 8;
+
+// a quad in the top right corner made of two triangles with opposite windings, culling
+// hides one half of it
+fn two_sided_quad_vertices() -> [VertexInput; 6] {
+    let vertex = |x, y| VertexInput {
+        position: MTLPackedFloat3 { x, y, z: 0. },
+        color: MTLPackedFloat3 {
+            x: 1.,
+            y: 0.8,
+            z: 0.2,
+        },
+    };
+    [
+        // clockwise
+        vertex(0.5, 0.5),
+        vertex(0.5, 0.85),
+        vertex(0.85, 0.85),
+        // counter clockwise
+        vertex(0.5, 0.5),
+        vertex(0.85, 0.5),
+        vertex(0.85, 0.85),
+    ]
+}
+
+// a pale floor on the left with a blue card floating in front of it, drawn lit by the
+// directional light. the card is the last 6 vertices, it casts its shadow on the floor
+fn shadow_scene_vertices() -> [VertexInput; 12] {
+    let vertex = |x, y, z, [red, green, blue]: [f32; 3]| VertexInput {
+        position: MTLPackedFloat3 { x, y, z },
+        color: MTLPackedFloat3 {
+            x: red,
+            y: green,
+            z: blue,
+        },
+    };
+    let quad = |(left, bottom), (right, top), z, color| {
+        [
+            vertex(left, bottom, z, color),
+            vertex(right, bottom, z, color),
+            vertex(right, top, z, color),
+            vertex(left, bottom, z, color),
+            vertex(right, top, z, color),
+            vertex(left, top, z, color),
+        ]
+    };
+    let floor = quad((-0.85, 0.), (-0.45, 0.4), 0., [0.8, 0.8, 0.75]);
+    let card = quad((-0.75, 0.15), (-0.6, 0.3), -0.2, [0.3, 0.5, 1.]);
+    let mut vertices = [floor[0]; 12];
+    vertices[..6].copy_from_slice(&floor);
+    vertices[6..].copy_from_slice(&card);
+    vertices
+}
+
+// the point lights of a ring about the origin cycling through the hues, with a white spot
+// light shining down on its center
+fn ring_lights() -> Vec<Light> {
+    const COUNT: usize = 24;
+    let hue = |angle: f32, offset: f32| 0.5 + 0.5 * (angle + offset).cos();
+    let ring = (0..COUNT).map(|index| {
+        let angle = index as f32 / COUNT as f32 * std::f32::consts::TAU;
+        Light::Point(PointLight {
+            position: [1.5 * angle.cos(), 0.3, 1.5 * angle.sin()],
+            color: [hue(angle, 0.), hue(angle, 2.1), hue(angle, 4.2)],
+            intensity: 0.5,
+            range: 1.,
+        })
+    });
+    ring.chain([Light::Spot(SpotLight::default())]).collect()
+}
+
+// a small white triangle about the origin, moved into place by its instances
+fn instanced_triangle_vertices() -> [VertexInput; 3] {
+    let vertex = |x, y| VertexInput {
+        position: MTLPackedFloat3 { x, y, z: 0. },
+        color: MTLPackedFloat3 {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        },
+    };
+    [vertex(-0.05, -0.05), vertex(0.05, -0.05), vertex(0., 0.05)]
+}
+
+// a row of triangles along the bottom of the view, fading from red to blue
+fn triangle_row_instances() -> Vec<InstanceData> {
+    let count = 8;
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / (count - 1) as f32;
+            let mut instance = InstanceData::default();
+            instance.transform[3][0] = -0.7 + 1.4 * t;
+            instance.transform[3][1] = -0.85;
+            instance.color = [1. - t, 0.3, t, 1.];
+            instance
+        })
+        .collect()
+}
+
+// a grid of thin gray lines with thicker x and y axes in red and green
+// a grid of triangles lying flat below the view, colored by where they lie
+fn triangle_field_instances() -> Vec<InstanceData> {
+    let count = 64;
+    let mut instances = Vec::with_capacity(count * count);
+    for row in 0..count {
+        for column in 0..count {
+            let [u, v] = [column, row].map(|i| i as f32 / (count - 1) as f32);
+            instances.push(InstanceData {
+                // the triangle's y axis points along -z
+                transform: [
+                    [1., 0., 0., 0.],
+                    [0., 0., -1., 0.],
+                    [0., 1., 0., 0.],
+                    [-4. + 8. * u, -1.2, -4. + 8. * v, 1.],
+                ],
+                color: [u, 0.5, v, 1.],
+            });
+        }
+    }
+    instances
+}
+
+fn draw_grid(renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+    let gray = MTLPackedFloat3 {
+        x: 0.4,
+        y: 0.4,
+        z: 0.4,
+    };
+    let mut line = |from, to, width, color| {
+        renderer.draw_lines(render_pass, &[from, to], width, color);
+    };
+    for i in 1..8 {
+        let offset = i as f32 / 4. - 1.;
+        line((offset, -1., 0.), (offset, 1., 0.), 1., gray);
+        line((-1., offset, 0.), (1., offset, 0.), 1., gray);
+    }
+
+    let red = MTLPackedFloat3 {
+        x: 1.,
+        y: 0.,
+        z: 0.,
+    };
+    let green = MTLPackedFloat3 {
+        x: 0.,
+        y: 1.,
+        z: 0.,
+    };
+    line((0., 0., 0.), (0.9, 0., 0.), 3., red);
+    line((0., 0., 0.), (0., 0.9, 0.), 3., green);
+}
+
+// the resources of the `MaterialArguments` struct in triangle.metal: a 2x2 checker texture,
+// a repeating nearest-neighbour sampler and a tint color
+fn create_material_arguments(renderer: &MetalRenderer) -> ArgumentTable {
+    let device = renderer.device();
+
+    let texture_descriptor = unsafe {
+        MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+            MTLPixelFormat::RGBA8Unorm,
+            2,
+            2,
+            false,
+        )
+    };
+    let texture = device
+        .newTextureWithDescriptor(&texture_descriptor)
+        .expect("Failed to create a texture.");
+    let texels: [[u8; 4]; 4] = [
+        [255, 255, 255, 255],
+        [128, 128, 128, 255],
+        [128, 128, 128, 255],
+        [255, 255, 255, 255],
+    ];
+    let region = MTLRegion {
+        origin: MTLOrigin { x: 0, y: 0, z: 0 },
+        size: MTLSize {
+            width: 2,
+            height: 2,
+            depth: 1,
+        },
+    };
+    unsafe {
+        texture.replaceRegion_mipmapLevel_withBytes_bytesPerRow(
+            region,
+            0,
+            NonNull::from(&texels).cast::<c_void>(),
+            2 * 4,
+        )
+    };
+
+    let sampler_descriptor = MTLSamplerDescriptor::new();
+    sampler_descriptor.setMinFilter(MTLSamplerMinMagFilter::Nearest);
+    sampler_descriptor.setMagFilter(MTLSamplerMinMagFilter::Nearest);
+    sampler_descriptor.setSAddressMode(MTLSamplerAddressMode::Repeat);
+    sampler_descriptor.setTAddressMode(MTLSamplerAddressMode::Repeat);
+    // samplers have to opt into being referenced from argument buffers
+    sampler_descriptor.setSupportArgumentBuffers(true);
+    let sampler = device
+        .newSamplerStateWithDescriptor(&sampler_descriptor)
+        .expect("Failed to create a sampler.");
+
+    let tint: [f32; 4] = [0.6, 0.7, 1.0, 1.0];
+    let tint_buffer = unsafe {
+        device.newBufferWithBytes_length_options(
+            NonNull::from(&tint).cast::<c_void>(),
+            core::mem::size_of_val(&tint),
+            MTLResourceOptions::MTLResourceStorageModeShared,
+        )
+    }
+    .expect("Failed to create a tint buffer.");
+
+    let mut arguments = renderer.create_argument_table("fragment_material", 0);
+    arguments.set_texture(0, &texture);
+    arguments.set_sampler(1, &sampler);
+    arguments.set_buffer(2, &tint_buffer);
+    arguments
+}
+
+// the cubes circling above the scene, moved by `run_simulation`
+const RING_CUBES: usize = 6;
+const SIMULATION_TICK: Duration = Duration::from_millis(33);
+
+// moves a ring of `cube`s on a thread of its own and hands their draws to the renderer every
+// tick, linked by lines, until the renderer is gone
+fn run_simulation(render_handle: RenderHandle, cube: MeshId) {
+    let start = Instant::now();
+    loop {
+        let time = start.elapsed().as_secs_f32();
+        let positions: Vec<[f32; 3]> = (0..RING_CUBES)
+            .map(|i| {
+                let angle = time * 0.5 + i as f32 / RING_CUBES as f32 * std::f32::consts::TAU;
+                let bob = (time * 2. + i as f32).sin() * 0.1;
+                [angle.cos() * 1.3, 0.9 + bob, angle.sin() * 1.3]
+            })
+            .collect();
+        let mut draw_list = DrawList::new();
+        for (i, &[x, y, z]) in positions.iter().enumerate() {
+            let transform = [
+                [1., 0., 0., 0.],
+                [0., 1., 0., 0.],
+                [0., 0., 1., 0.],
+                [x, y, z, 1.],
+            ];
+            draw_list.draw(cube, None, transform);
+            let next = positions[(i + 1) % RING_CUBES];
+            draw_list.line([x, y, z], next, [0.3, 0.8, 1.]);
+        }
+        if !render_handle.submit_draws(draw_list) {
+            return;
+        }
+        std::thread::sleep(SIMULATION_TICK);
+    }
+}
+
+// a white quad along the bottom of the view starting at `left`, drawn as a triangle strip for
+// `vertex_quad`
+#[cfg(feature = "ecs")]
+const DIAMOND_COUNT: usize = 9;
+
+// a diamond about the origin, white so the materials of the entities color it
+#[cfg(feature = "ecs")]
+fn diamond_vertices() -> [VertexInput; 4] {
+    let vertex = |x, y| VertexInput {
+        position: MTLPackedFloat3 { x, y, z: 0. },
+        color: MTLPackedFloat3 {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        },
+    };
+    [
+        vertex(0., -0.04),
+        vertex(0.03, 0.),
+        vertex(0., 0.04),
+        vertex(-0.03, 0.),
+    ]
+}
+
+// the center of the hexagon of assets/hexagon.obj
+const HEXAGON_CENTER: [f32; 2] = [-0.68, 0.68];
+
+// moves `origin` to `position`, turned by `angle` radians and scaled by `scale` about it
+fn placement(origin: [f32; 2], position: [f32; 2], angle: f32, scale: f32) -> [[f32; 4]; 4] {
+    let (sin, cos) = angle.sin_cos();
+    let [x, y] = origin;
+    [
+        [cos * scale, sin * scale, 0., 0.],
+        [-sin * scale, cos * scale, 0., 0.],
+        [0., 0., scale, 0.],
+        [
+            position[0] - (cos * x - sin * y) * scale,
+            position[1] - (sin * x + cos * y) * scale,
+            0.,
+            1.,
+        ],
+    ]
+}
+
+fn textured_quad_vertices(left: f32) -> [VertexInput; 4] {
+    let vertex = |x, y| VertexInput {
+        position: MTLPackedFloat3 { x, y, z: 0. },
+        color: MTLPackedFloat3 {
+            x: 1.,
+            y: 1.,
+            z: 1.,
+        },
+    };
+    let right = left + 0.35;
+    [
+        vertex(left, -0.85),
+        vertex(right, -0.85),
+        vertex(left, -0.5),
+        vertex(right, -0.5),
+    ]
+}
+
+// a quad a little larger than the one of `textured_quad_vertices`, in a golden color
+fn picture_frame_vertices(left: f32) -> [VertexInput; 4] {
+    let vertex = |x, y| VertexInput {
+        position: MTLPackedFloat3 { x, y, z: 0. },
+        color: MTLPackedFloat3 {
+            x: 0.9,
+            y: 0.7,
+            z: 0.2,
+        },
+    };
+    let (left, right) = (left - 0.015, left + 0.365);
+    [
+        vertex(left, -0.865),
+        vertex(right, -0.865),
+        vertex(left, -0.485),
+        vertex(right, -0.485),
+    ]
+}
+
+// loads the texture of the example from the assets, a gpu compressed texture.ktx2 exported
+// next to texture.png takes precedence over it
+// an equirectangular sky fading from a bright horizon to a deeper blue overhead, over a dark
+// ground. the sun near the horizon is brighter than 1 for the bloom to pick up
+fn example_sky() -> Rgba32FImage {
+    let (width, height) = (1024, 512);
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        let longitude = (x as f32 + 0.5) / width as f32 * 2. * std::f32::consts::PI;
+        let elevation = 0.5 - (y as f32 + 0.5) / height as f32;
+        let color = if elevation < 0. {
+            [0.12, 0.11, 0.1]
+        } else {
+            let blend = (elevation * 2.).sqrt();
+            [0.75 - 0.55 * blend, 0.85 - 0.45 * blend, 1. - 0.15 * blend]
+        };
+        // a disc a few degrees wide
+        let sun = ((longitude - 1.).powi(2) + (elevation - 0.08).powi(2)).sqrt() < 0.03;
+        let [red, green, blue] = if sun { [8., 7.5, 6.] } else { color };
+        Rgba([red, green, blue, 1.])
+    })
+}
+
+fn load_example_texture(
+    renderer: &MetalRenderer,
+) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+    let assets = concat!(env!("CARGO_MANIFEST_DIR"), "/assets");
+    match renderer.load_ktx2_texture(format!("{assets}/texture.ktx2")) {
+        Err(TextureError::Io(error)) if error.kind() == ErrorKind::NotFound => {
+            renderer.load_texture(format!("{assets}/texture.png"))
+        }
+        result => result,
+    }
+}
+
+// the texels of a region of a checkerboard with squares of 64 texels at every mipmap level,
+// tinted by the level so the ones the sparse texture streams in stand out
+fn checkerboard_texels(level: usize, region: MTLRegion) -> Vec<u8> {
+    let tint = [
+        [255, 255, 255],
+        [255, 160, 160],
+        [160, 255, 160],
+        [160, 160, 255],
+    ][level % 4];
+    let mut texels = Vec::with_capacity(region.size.width * region.size.height * 4);
+    for y in region.origin.y..region.origin.y + region.size.height {
+        for x in region.origin.x..region.origin.x + region.size.width {
+            let shade = if (x / 64 + y / 64) % 2 == 0 { 1. } else { 0.3 };
+            texels.extend(tint.map(|channel| (channel as f32 * shade) as u8));
+            texels.push(255);
+        }
+    }
+    texels
+}
+
+// a bindless table holding just `texture`
+fn create_bindless_texture(
+    renderer: &MetalRenderer,
+    texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+) -> Rc<BindlessTable> {
+    let mut table = renderer.create_bindless_table();
+    table.add_texture(texture);
+    Rc::new(table)
+}
+
+// imports the scene at `path` in place of the current one and plays its first animation. it's
+// viewed in 3d from a little above, surrounded by the panorama at `environment_map` or a
+// generated sky
+fn load_scene(
+    renderer: &MetalRenderer,
+    current_scene: &RefCell<Option<Rc<Scene>>>,
+    path: &Path,
+    environment_map: Option<&str>,
+) {
+    let scene = match renderer.load_scene(path) {
+        Ok(scene) => scene,
+        Err(error) => {
+            eprintln!("Failed to import {}: {error}", path.display());
+            return;
+        }
+    };
+    if !scene.animation_clips().is_empty() {
+        scene.play(0);
+    }
+    *current_scene.borrow_mut() = Some(Rc::new(scene));
+    let mut camera = renderer.camera();
+    camera.projection = Projection::PERSPECTIVE;
+    camera.orbit(0.5, 0.3);
+    renderer.set_camera(camera);
+    let environment_map = match environment_map {
+        Some(path) => renderer.load_environment_map(path),
+        None => renderer.create_environment_map(&example_sky()),
+    };
+    match environment_map {
+        Ok(environment_map) => renderer.set_skybox(Some(&environment_map)),
+        Err(error) => eprintln!("{error}"),
+    }
+}
+
+// the resources of the `TextureArguments` struct in triangle.metal: `texture` and a trilinear
+// sampler clamping to its edges
+fn create_texture_arguments(
+    renderer: &MetalRenderer,
+    texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+) -> ArgumentTable {
+    let sampler_descriptor = MTLSamplerDescriptor::new();
+    sampler_descriptor.setMinFilter(MTLSamplerMinMagFilter::Linear);
+    sampler_descriptor.setMagFilter(MTLSamplerMinMagFilter::Linear);
+    sampler_descriptor.setMipFilter(MTLSamplerMipFilter::Linear);
+    sampler_descriptor.setSAddressMode(MTLSamplerAddressMode::ClampToEdge);
+    sampler_descriptor.setTAddressMode(MTLSamplerAddressMode::ClampToEdge);
+    sampler_descriptor.setSupportArgumentBuffers(true);
+    let sampler = renderer
+        .device()
+        .newSamplerStateWithDescriptor(&sampler_descriptor)
+        .expect("Failed to create a sampler.");
+
+    let mut arguments = renderer.create_argument_table("fragment_textured", 0);
+    arguments.set_texture(0, texture);
+    arguments.set_sampler(1, &sampler);
+    arguments
+}
+
+// ends the example when metal isn't usable, there is nothing to draw with
+fn exit_with_error(error: RendererError) -> ! {
+    eprintln!("Failed to set up the renderer: {error}");
+    std::process::exit(1)
+}
+
+// the gpu named by the config or METAL_DEVICE, which take low-power, high-performance,
+// removable or a part of the name of a gpu, the system default without either
+fn device_selector(config: &Config) -> DeviceSelector {
+    for device in available_devices() {
+        eprintln!(
+            "Found device {} (low power: {}, removable: {}, unified memory: {}).",
+            device.name, device.low_power, device.removable, device.unified_memory
+        );
+    }
+    config.device_selector()
+}
+
+// renders the default scene without a window into the png at `path`, as a thumbnail
+fn render_thumbnail(path: &str, config: &Config) {
+    let renderer = MetalRenderer::new_headless(256, 256);
+    renderer.set_device_selector(device_selector(config));
+    if let Err(error) = renderer.init() {
+        exit_with_error(error);
+    }
+    renderer.set_background(EXAMPLE_GRADIENT);
+    renderer.set_depth_format(Some(DepthFormat::Depth32Float));
+    let saved = renderer
+        .render_offscreen()
+        .and_then(|image| Ok(image.save(path)?));
+    match saved {
+        Ok(()) => eprintln!("Saved a thumbnail to {path}."),
+        Err(error) => eprintln!("Failed to render a thumbnail: {error}"),
+    }
+}
+
+// runs the image at `path` through blur, edge and threshold kernels without a window, saving
+// each result next to it with the name of the filters appended
+fn filter_image(path: &str, config: &Config) {
+    let renderer = MetalRenderer::new_headless(1, 1);
+    renderer.set_device_selector(device_selector(config));
+    if let Err(error) = renderer.init() {
+        exit_with_error(error);
+    }
+    let texture = match renderer.load_texture(path) {
+        Ok(texture) => texture,
+        Err(error) => {
+            eprintln!("Failed to load {path}: {error}");
+            std::process::exit(1)
+        }
+    };
+    let blur = ComputeFilter::GaussianBlur { sigma: 2. };
+    let threshold = ComputeFilter::Threshold { level: 0.2 };
+    let chains: [(&str, &[ComputeFilter]); 3] = [
+        ("blur", &[blur]),
+        ("edges", &[blur, ComputeFilter::Sobel]),
+        ("threshold", &[blur, ComputeFilter::Sobel, threshold]),
+    ];
+    let stem = Path::new(path).with_extension("");
+    for (name, filters) in chains {
+        let output = format!("{}-{name}.png", stem.display());
+        let filtered = renderer.apply_compute_filters(&texture, filters);
+        let saved = renderer
+            .read_texture(&filtered)
+            .and_then(|image| Ok(image.save(&output)?));
+        match saved {
+            Ok(()) => eprintln!("Saved {output}."),
+            Err(error) => eprintln!("Failed to filter {path}: {error}"),
+        }
+    }
+}
+
+// creates a window together with the renderer drawing into it with `backend`, on the device of
+// `shared_renderer` when there is one or the one `device_selector` picks
+fn create_window(
+    event_loop: &EventLoop<MenuCommand>,
+    title: &str,
+    backend: Backend,
+    shared_renderer: Option<&MetalRenderer>,
+    device_selector: DeviceSelector,
+) -> (Window, Retained<MetalRenderer>) {
+    let window = WindowBuilder::new()
+        .with_title(title)
+        .build(event_loop)
+        .unwrap();
+
+    // the other windows attach through their window handle, as a window of any other library
+    // would
+    let renderer = match shared_renderer {
+        None => MetalRenderer::new(&window),
+        Some(_) => MetalRenderer::from_window_handle(&window),
+    };
+    let renderer = renderer.unwrap_or_else(|error| exit_with_error(error));
+    renderer.set_update_callback(update_view);
+    renderer.set_backend(backend);
+    match shared_renderer {
+        Some(shared_renderer) => renderer.share_device(shared_renderer),
+        None => renderer.set_device_selector(device_selector),
+    }
+    // the example builds its geometry and textures once, it's left without them on another gpu
+    renderer.on_device_change(|renderer| {
+        let name = renderer.device_info().name;
+        eprintln!("Moved to {name}, restart the example to get its resources back.");
+    });
+    if let Err(error) = renderer.init() {
+        exit_with_error(error);
+    }
+
+    (window, renderer)
+}
+
+#[allow(clippy::single_match)]
+#[allow(clippy::collapsible_match)]
+fn main() {
+    // the diagnostics of the renderer go to stderr, RUST_LOG picks the levels shown
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    // the settings of config.toml or the file given with --config, then the command line
+    let config = Config::load(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{error}\n\n{USAGE}");
+        std::process::exit(2)
+    });
+    if config.help {
+        println!("{USAGE}");
+        return;
+    }
+    config.apply_validation();
+
+    // METAL_THUMBNAIL renders a frame offscreen instead of opening the windows
+    if let Ok(path) = std::env::var("METAL_THUMBNAIL") {
+        render_thumbnail(&path, &config);
+        return;
+    }
+    // METAL_FILTERS filters the image at the path with compute kernels
+    if let Ok(path) = std::env::var("METAL_FILTERS") {
+        filter_image(&path, &config);
+        return;
+    }
+
+    // the commands of the menu bar come in as user events
+    let event_loop = EventLoopBuilder::<MenuCommand>::with_user_event().build();
+
+    // every window has its own renderer, looked up by the id of the window an event targets
+    let mut renderers: HashMap<WindowId, (Window, Retained<MetalRenderer>)> = HashMap::new();
+
+    // restore the main window as it was left, its configuration is saved when it's closed,
+    // with the settings on top. the settings only last for the run, they're left out of what's
+    // saved unless the menu changed them since. the defaults on the first run turn on
+    // multisampling. the backend of the config is used for every window, it has to be picked
+    // before they're created
+    let saved_config = load_config(CONFIG_PATH).unwrap_or_default();
+    let mut renderer_config = saved_config.clone();
+    config.apply(&mut renderer_config);
+    let title = config.title.as_deref().unwrap_or("A fantastic window!");
+    let (window, renderer) = create_window(
+        &event_loop,
+        title,
+        renderer_config.backend,
+        None,
+        device_selector(&config),
+    );
+    let main_window_id = window.id();
+    renderer.apply_config(&renderer_config);
+    // the menu bar acts on the window in front, the main window until another one is focused
+    #[cfg(target_os = "macos")]
+    let menu = Menu::install(event_loop.create_proxy(), "rust-tao-metal");
+    #[cfg(target_os = "macos")]
+    menu.set_vsync(renderer.vsync());
+    let mut key_window_id = main_window_id;
+    // the desktop shows through a transparent window where the gradient would be
+    if !renderer_config.transparent {
+        renderer.set_background(EXAMPLE_GRADIENT);
+    }
+    // the stencil marks the picture for the frame drawn around it
+    renderer.set_depth_format(Some(DepthFormat::Depth32FloatStencil8));
+    // name the faulted encoders and add the shader logs when the gpu fails a frame, debug builds
+    // do it anyway
+    if config.validation {
+        renderer.set_enhanced_command_buffer_errors(true);
+    }
+    // compile for the latest language version of the system, the mesh shaders need metal 3, and
+    // sway the gradient further than the shader default
+    let shader_options = renderer.set_shader_options(&ShaderOptions {
+        language_version: None,
+        preprocessor_macros: HashMap::from([(
+            "GRADIENT_SWAY_AMOUNT".to_owned(),
+            "0.4".to_owned(),
+        )]),
+        ..Default::default()
+    });
+    if let Err(error) = shader_options {
+        eprintln!("Keeping the default shader options: {error}");
+    }
+    // edits to the shaders of the source tree show up without restarting
+    let shader_path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/triangle.metal");
+    if let Err(error) = renderer.watch_shaders(shader_path) {
+        eprintln!("Shader hot reload is unavailable: {error}");
+    }
+    // the shader of --shader draws the whole view instead, reloaded when it's saved
+    if let Some(shader) = &config.shader {
+        if let Err(error) = renderer.load_fullscreen_shader(shader) {
+            eprintln!("Failed to load {shader}: {error}");
+        }
+    }
+    // the glowing wave compiles its pipeline on a metal thread, the first frames draw it in gray
+    renderer.prepare_pipeline_state(
+        &renderer
+            .pipeline_descriptor("vertex_glow", "fragment_main")
+            .with_blend_mode(BlendMode::Additive),
+    );
+    // layer the triangle on top of a grid and a textured background quad, next to a quad
+    // showing the effect of culling, a quad with an image and a mesh loaded from the assets
+    let background = renderer.create_vertex_buffer(&background_vertices());
+    let two_sided_quad = renderer.create_vertex_buffer(&two_sided_quad_vertices());
+    let quad_tint = renderer.create_gpu_buffer(&[[1f32, 0.85, 0.7, 1.]]);
+    let background_material = Rc::new(create_material_arguments(&renderer));
+    let textured_quad = renderer.create_vertex_buffer(&textured_quad_vertices(-0.85));
+    // the bottom right corner shows the geometry rendered into a texture in the same frame,
+    // the target is recreated whenever the formats or the sample count of the view change
+    let picture_quad = renderer.create_vertex_buffer(&textured_quad_vertices(0.5));
+    let picture_frame = renderer.create_vertex_buffer(&picture_frame_vertices(0.5));
+    // the picture marks its pixels with a 1 in the stencil, the frame behind it is only drawn
+    // where there's none so only its border around the picture shows
+    let marking = DepthStencilBuilder::new()
+        .with_stencil(StencilFace::replace())
+        .build(&renderer);
+    let unmarked = DepthStencilBuilder::new()
+        .with_stencil(StencilFace::not_equal())
+        .build(&renderer);
+    let picture: RefCell<Option<(RenderTarget, Rc<ArgumentTable>)>> = RefCell::new(None);
+    // the samples of the quad that were visible, the picture isn't redrawn while it's covered
+    let picture_queries = Rc::new(renderer.create_occlusion_queries(1));
+    let instanced_triangle = renderer.create_vertex_buffer(&instanced_triangle_vertices());
+    // a light shining into the view from the bottom left, the card drawn from it shadows
+    // the floor
+    let shadow_scene = renderer.create_vertex_buffer(&shadow_scene_vertices());
+    renderer.set_directional_light(Some(DirectionalLight {
+        direction: [0.4, -0.4, 1.],
+        shadow_extent: 1.5,
+        ..Default::default()
+    }));
+    let triangle_instances = renderer.create_gpu_buffer(&triangle_row_instances());
+    // a field of triangles on a floor below the view, culled on the gpu. zooming out or
+    // orbiting brings the ones in view
+    let triangle_field = Rc::new(renderer.create_culled_instances(
+        Rc::new(renderer.create_mesh(&instanced_triangle_vertices(), &[0, 1, 2])),
+        &triangle_field_instances(),
+    ));
+    // a cube, a sphere and a torus standing on the floor, in the colors of their normals
+    let primitives = [
+        (MeshData::cube(0.4), [-0.8, -1., -1.5]),
+        (MeshData::uv_sphere(0.2, 24, 12), [0., -1., -1.5]),
+        (MeshData::torus(0.2, 0.07, 32, 12), [0.8, -1.13, -1.5]),
+    ]
+    .map(|(shape, [x, y, z])| {
+        let instance = InstanceData {
+            transform: [
+                [1., 0., 0., 0.],
+                [0., 1., 0., 0.],
+                [0., 0., 1., 0.],
+                [x, y, z, 1.],
+            ],
+            ..Default::default()
+        };
+        (
+            renderer.create_mesh(&shape.vertices, &shape.indices),
+            renderer.create_gpu_buffer(&[instance]),
+        )
+    });
+    // a ring of cubes above the scene, simulated on another thread
+    let ring_cube = MeshData::cube(0.12);
+    let ring_cube = renderer.add_shared_mesh(Rc::new(
+        renderer.create_mesh(&ring_cube.vertices, &ring_cube.indices),
+    ));
+    let render_handle = renderer.render_handle();
+    std::thread::spawn(move || run_simulation(render_handle, ring_cube));
+    let mesh_path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/hexagon.obj");
+    let mesh = renderer
+        .load_mesh(mesh_path)
+        .inspect_err(|error| eprintln!("{error}"))
+        .ok()
+        .map(Rc::new);
+    // the mesh with a moon circling it and a smaller moon circling that one, nodes of a scene
+    // graph the render callback turns
+    let hexagon_system = mesh.as_ref().map(|mesh| {
+        let material = |color| {
+            Some(Rc::new(Material {
+                pipeline_state: renderer.render_pipeline_state("vertex_instanced", "fragment_main"),
+                fragment_arguments: None,
+                color,
+            }))
+        };
+        let identity = InstanceData::default().transform;
+        let mut graph = SceneGraph::new();
+        let hexagon = graph.add_node(None, identity);
+        let orbit = graph.add_node(Some(hexagon), identity);
+        let moon = graph.add_node(Some(orbit), identity);
+        let little_moon = graph.add_node(Some(moon), identity);
+        let tints = [[1., 0.85, 0.6, 1.], [0.6, 0.8, 1., 1.], [1., 0.6, 0.8, 1.]];
+        for (node, tint) in [hexagon, moon, little_moon].into_iter().zip(tints) {
+            graph.set_mesh(node, Some(mesh.clone()));
+            graph.set_material(node, material(tint));
+        }
+        RefCell::new((
+            graph,
+            [orbit, moon, little_moon],
+            [hexagon, moon, little_moon],
+        ))
+    });
+    let hexagon_system = Rc::new(hexagon_system);
+    // a ring of diamonds, entities of an ecs world drawn as one instanced draw per material
+    #[cfg(feature = "ecs")]
+    let (world, world_assets) = {
+        let mut assets = RenderAssets::new();
+        let mesh = renderer.create_mesh(&diamond_vertices(), &[0, 1, 2, 0, 2, 3]);
+        let diamond = assets.add_mesh(Rc::new(mesh));
+        let materials = [[0.4, 1., 0.6, 1.], [1., 0.9, 0.3, 1.]].map(|color| {
+            assets.add_material(Rc::new(Material {
+                pipeline_state: renderer.render_pipeline_state("vertex_instanced", "fragment_main"),
+                fragment_arguments: None,
+                color,
+            }))
+        });
+        let mut world = hecs::World::new();
+        for i in 0..DIAMOND_COUNT {
+            let angle = i as f32 / DIAMOND_COUNT as f32 * std::f32::consts::TAU;
+            let transform = Transform {
+                translation: [0.6 + 0.15 * angle.cos(), 0.4 + 0.15 * angle.sin(), 0.],
+                ..Default::default()
+            };
+            // every third diamond keeps the white of the default material
+            match i % 3 {
+                2 => world.spawn((transform, diamond)),
+                _ => world.spawn((transform, diamond, materials[i % 3])),
+            };
+        }
+        (RefCell::new(world), assets)
+    };
+    // the mesh is also built for ray tracing where the device can trace it
+    if let Some(mesh) = mesh.as_ref().filter(|_| renderer.supports_raytracing()) {
+        renderer.set_ray_traced_scene(renderer.build_acceleration_structure(mesh));
+    }
+    // a .gltf or .glb file passed on the command line or dropped onto the window replaces the
+    // spinning triangle
+    let scene: Rc<RefCell<Option<Rc<Scene>>>> = Rc::default();
+    if let Some(path) = &config.scene {
+        load_scene(
+            &renderer,
+            &scene,
+            Path::new(path),
+            config.environment_map.as_deref(),
+        );
+    }
+    // a loaded scene casts its own shadows in place of the card
+    renderer.set_shadow_callback({
+        let shadow_scene = shadow_scene.clone();
+        let scene = scene.clone();
+        move |renderer, render_pass| match &*scene.borrow() {
+            Some(scene) => scene.draw_shadows(renderer, render_pass),
+            None => {
+                render_pass.draw(
+                    &renderer.shadow_pipeline_state("vertex_main"),
+                    &shadow_scene,
+                    PrimitiveType::Triangle,
+                    6..12,
+                );
+            }
+        }
+    });
+    // the meshes dropped onto the window are loaded in the background and watched, the hexagon
+    // and its moons take the shape of one once it loaded and again whenever its file is saved
+    let meshes = Rc::new(RefCell::new(Assets::<Mesh>::new()));
+    if let Err(error) = meshes.borrow_mut().watch() {
+        eprintln!("Failed to watch the meshes: {error}");
+    }
+    meshes.borrow_mut().on_load({
+        let hexagon_system = hexagon_system.clone();
+        move |renderer, handle: &Handle<Mesh>| {
+            let Some(mesh) = handle.get() else {
+                return;
+            };
+            if let Some(hexagon_system) = &*hexagon_system {
+                let (graph, _, nodes) = &mut *hexagon_system.borrow_mut();
+                for node in nodes {
+                    graph.set_mesh(*node, Some(mesh.clone()));
+                }
+            }
+            if renderer.supports_raytracing() {
+                renderer.set_ray_traced_scene(renderer.build_acceleration_structure(&mesh));
+            }
+        }
+    });
+    // the handle of the last dropped mesh, which keeps it loaded
+    let dropped_mesh: Rc<RefCell<Option<Handle<Mesh>>>> = Rc::default();
+    // the first animation of the scene plays in a loop, space pauses it and tab blends into
+    // the next one. it's advanced by fixed steps, so it plays the same at any frame rate
+    renderer.set_fixed_update_callback({
+        let scene = scene.clone();
+        move |_, step| {
+            if let Some(scene) = scene.borrow().as_ref() {
+                scene.advance(step);
+            }
+        }
+    });
+    renderer.set_update_callback({
+        let scene = scene.clone();
+        let meshes = meshes.clone();
+        move |renderer, input, elapsed| {
+            update_view(renderer, input, elapsed);
+            meshes.borrow_mut().update(renderer);
+            let scene = scene.borrow();
+            let Some(scene) = scene
+                .as_ref()
+                .filter(|scene| !scene.animation_clips().is_empty())
+            else {
+                return;
+            };
+            let current_clip = scene.current_clip().unwrap_or_default();
+            if input.was_key_pressed(KeyCode::Space) {
+                if scene.is_playing() {
+                    scene.pause();
+                } else {
+                    scene.play(current_clip);
+                }
+            }
+            if input.was_key_pressed(KeyCode::Tab) {
+                let next_clip = (current_clip + 1) % scene.animation_clips().len();
+                scene.blend(next_clip, 0.5);
+                let name = scene.animation_clips()[next_clip].name();
+                eprintln!("Animation: {next_clip} {name}");
+            }
+        }
+    });
+    let texture = load_example_texture(&renderer)
+        .inspect_err(|error| eprintln!("{error}"))
+        .ok();
+    // the textured quad in the bottom left picks its texture from a bindless table, drawn as
+    // the single instance of an instanced draw
+    let bindless_table = texture
+        .as_ref()
+        .map(|texture| create_bindless_texture(&renderer, texture));
+    let bindless_table = Rc::new(RefCell::new(bindless_table));
+    // a checkerboard too large for the memory it's given, streamed in by the distance of the
+    // camera and drawn in the bottom middle
+    let sparse_texture = renderer
+        .create_sparse_texture(
+            8192,
+            8192,
+            MTLPixelFormat::RGBA8Unorm,
+            32 << 20,
+            checkerboard_texels,
+        )
+        .map(|sparse_texture| {
+            let arguments = Rc::new(create_texture_arguments(
+                &renderer,
+                sparse_texture.texture(),
+            ));
+            RefCell::new((sparse_texture, arguments))
+        });
+    let sparse_quad = renderer.create_vertex_buffer(&textured_quad_vertices(-0.175));
+    // a video dropped onto the window plays in a loop over the textured quad
+    #[cfg(feature = "video")]
+    let video: Rc<RefCell<Option<VideoPlayer>>> = Rc::default();
+    // the camera of --camera, a kernel outlines the edges of its frames into a texture drawn
+    // over the textured quad
+    #[cfg(feature = "video")]
+    let camera: Option<RefCell<CameraCapture>> = config
+        .camera
+        .then(|| renderer.open_camera())
+        .and_then(|camera| camera.inspect_err(|error| eprintln!("{error}")).ok())
+        .map(|camera| {
+            camera.start();
+            RefCell::new(camera)
+        });
+    #[cfg(feature = "video")]
+    let camera_edges = Rc::new(RefCell::new(
+        None::<Retained<ProtocolObject<dyn MTLTexture>>>,
+    ));
+    // the display or the window of --screen over the textured quad, under the camera
+    #[cfg(all(feature = "screen-capture", target_os = "macos"))]
+    let screen: Option<RefCell<ScreenCapture>> = config
+        .screen
+        .as_deref()
+        .map(|source| match source {
+            "main" => ScreenSource::MainDisplay,
+            _ => source.parse().map_or_else(
+                |_| ScreenSource::Window(source.to_owned()),
+                ScreenSource::Display,
+            ),
+        })
+        .and_then(|source| {
+            let screen = renderer.capture_screen(source);
+            screen.inspect_err(|error| eprintln!("{error}")).ok()
+        })
+        .map(RefCell::new);
+    // the frames copied into a surface other processes open by its id, replaced by one of the
+    // new size or format when the drawable changes
+    if config.share_surface {
+        let share_surface = |renderer: &MetalRenderer, size: NSSize| {
+            let pixel_format = match renderer.pixel_format() {
+                PixelFormat::Bgra8Unorm => MTLPixelFormat::BGRA8Unorm,
+                PixelFormat::Bgra8UnormSrgb => MTLPixelFormat::BGRA8Unorm_sRGB,
+                PixelFormat::Rgba16Float => MTLPixelFormat::RGBA16Float,
+            };
+            let (width, height) = (size.width as usize, size.height as usize);
+            match renderer.create_shared_surface(width, height, pixel_format) {
+                Ok(surface) => {
+                    eprintln!("Sharing the frames in IOSurface {}.", surface.id());
+                    renderer.set_output_surface(Some(surface));
+                }
+                Err(error) => eprintln!("{error}"),
+            }
+        };
+        share_surface(&renderer, renderer.drawable_size());
+        renderer.on_resize(share_surface);
+        renderer.on_drawable_format_change(move |renderer, _, _| {
+            share_surface(renderer, renderer.drawable_size());
+        });
+    }
+    let quad_instance = renderer.create_gpu_buffer(&[InstanceData::default()]);
+    // a row of spinning sprites along the top of the view, batched into a single draw. an
+    // image dropped onto the window replaces the texture of the sprites and the quad
+    let sprite_arguments = texture
+        .as_ref()
+        .map(|texture| renderer.create_sprite_arguments(texture));
+    let sprite_arguments = Rc::new(RefCell::new(sprite_arguments));
+    let sprites = RefCell::new(SpriteBatch::new());
+    // the bounds of the row of instanced triangles, and a ground grid with the world axes under
+    // a loaded scene
+    let debug_draw = RefCell::new(DebugDraw::new());
+    let start_time = Instant::now();
+    // a line whose vertices a kernel moves every frame, the draws read them after it ran. they
+    // live in an untracked heap, so the kernel updates a fence the draws wait for, and waits
+    // for the one the draws of the frame before update
+    let wave_allocator = renderer.create_untracked_gpu_allocator(1 << 16);
+    let wave = wave_allocator.allocate_buffer(
+        core::mem::size_of::<VertexInput>() * WAVE_VERTEX_COUNT,
+        MTLStorageMode::Private,
+    );
+    let wave_written = renderer.create_fence();
+    let wave_drawn = renderer.create_fence();
+    // a fountain of particles rising from the bottom of the view, simulated by a kernel
+    let mut particles = renderer.create_particle_system(PARTICLE_COUNT);
+    particles.emitter = [0., -0.8, 0.];
+    particles.gravity = 1.5;
+    let particles = Rc::new(particles);
+    renderer.set_compute_callback({
+        let wave = wave.clone();
+        let (wave_written, wave_drawn) = (wave_written.clone(), wave_drawn.clone());
+        let particles = particles.clone();
+        let triangle_field = triangle_field.clone();
+        #[cfg(feature = "video")]
+        let camera_edges = camera_edges.clone();
+        move |renderer, compute_pass| {
+            compute_pass
+                .set_label("Simulation")
+                .wait_for_fence(&wave_drawn)
+                .update_fence(&wave_written)
+                .push_debug_group("Wave")
+                .dispatch(
+                    &renderer.compute_pipeline_state("compute_wave"),
+                    (WAVE_VERTEX_COUNT, 1, 1),
+                )
+                .with_buffer(1, wave.buffer())
+                .pop_debug_group();
+            particles.update(renderer, compute_pass);
+            triangle_field.cull(renderer, compute_pass);
+            #[cfg(feature = "video")]
+            if let Some(frame) = camera
+                .as_ref()
+                .and_then(|camera| camera.borrow_mut().update().cloned())
+            {
+                let (width, height) = (frame.width(), frame.height());
+                let mut camera_edges = camera_edges.borrow_mut();
+                if !camera_edges
+                    .as_ref()
+                    .is_some_and(|edges| edges.width() == width && edges.height() == height)
+                {
+                    let edges =
+                        renderer.create_storage_texture(width, height, MTLPixelFormat::RGBA8Unorm);
+                    *camera_edges = Some(edges);
+                }
+                if let Some(edges) = &*camera_edges {
+                    compute_pass
+                        .dispatch(
+                            &renderer.compute_pipeline_state("camera_edges"),
+                            (width, height, 1),
+                        )
+                        .with_texture(0, &frame)
+                        .with_texture(1, edges);
+                }
+            }
+        }
+    });
+    // a reminder of the mouse controls in the top left corner, wrapped into a narrow column
+    let font = renderer.create_font(None, 13.);
+    let hint_style = TextStyle {
+        color: [1., 1., 1., 0.8],
+        max_width: Some(220.),
+        ..Default::default()
+    };
+    // a shader, a mesh, a scene or an image dropped onto the window or opened from the menu is
+    // loaded in place of the ones of the example
+    let load_asset = {
+        let scene = scene.clone();
+        let dropped_mesh = dropped_mesh.clone();
+        let bindless_table = bindless_table.clone();
+        let sprite_arguments = sprite_arguments.clone();
+        #[cfg(feature = "video")]
+        let video = video.clone();
+        move |renderer: &MetalRenderer, path: &Path| {
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(str::to_ascii_lowercase);
+            match extension.as_deref() {
+                // watched from then on, saving the file reloads it again
+                Some("metal") => {
+                    if let Err(error) = renderer.load_shader_file(path) {
+                        eprintln!("Failed to load {}: {error}", path.display());
+                    }
+                }
+                Some("gltf" | "glb") => load_scene(renderer, &scene, path, None),
+                // the hexagon and its moons take the shape of the mesh once it loaded
+                Some("obj") => *dropped_mesh.borrow_mut() = Some(meshes.borrow_mut().load(path)),
+                Some("png" | "jpg" | "jpeg" | "ktx2") => {
+                    // a large image is copied on the upload queue, only the next frame waits for
+                    // it on the gpu
+                    let texture = if extension.as_deref() == Some("ktx2") {
+                        renderer.load_ktx2_texture(path)
+                    } else {
+                        image::open(path)
+                            .map_err(TextureError::Decode)
+                            .and_then(|image| {
+                                let upload_queue = renderer.upload_queue();
+                                let image = image.into_rgba8();
+                                let (texture, value) =
+                                    renderer.upload_texture(&upload_queue, &image)?;
+                                renderer.wait_for_queue(&upload_queue, value);
+                                Ok(texture)
+                            })
+                    };
+                    match texture {
+                        Ok(texture) => {
+                            let table = create_bindless_texture(renderer, &texture);
+                            *bindless_table.borrow_mut() = Some(table);
+                            let arguments = renderer.create_sprite_arguments(&texture);
+                            *sprite_arguments.borrow_mut() = Some(arguments);
+                        }
+                        Err(error) => eprintln!("{error}"),
+                    }
+                }
+                #[cfg(feature = "video")]
+                Some("mp4" | "mov" | "m4v") => match renderer.open_video(path) {
+                    Ok(mut player) => {
+                        player.set_looping(true);
+                        player.play();
+                        *video.borrow_mut() = Some(player);
+                    }
+                    Err(error) => eprintln!("{error}"),
+                },
+                _ => eprintln!(
+                    "Can't load {}, it's no shader, mesh, scene or image",
+                    path.display()
+                ),
+            }
+        }
+    };
+    // the frames follow the edge of the window while it's resized, presented with the core
+    // animation transaction only then
+    renderer.set_smooth_live_resize(true);
+    // the frames of this window in gpu captures, the ones of the second window go unnamed
+    renderer.set_label(Some("Main window"));
+    // every frame counts up an event the cpu waits for on exit, so the frames still in flight
+    // are counted
+    let frames_done = renderer.create_shared_event();
+    let frame_number = Rc::new(Cell::new(0));
+    let frame_events = (frames_done.clone(), frame_number.clone());
+    renderer.set_render_callback(move |renderer, render_pass| {
+        let (frames_done, frame_number) = &frame_events;
+        frame_number.set(frame_number.get() + 1);
+        renderer.signal_event(frames_done, frame_number.get());
+        // the gradient covers every pixel, clearing them first would be wasted
+        if renderer.has_gradient_background() {
+            render_pass.with_color_load_action(LoadAction::DontCare);
+        }
+        render_pass
+            .push_debug_group("Background")
+            .draw(
+                &renderer.render_pipeline_state("vertex_main", "fragment_material"),
+                &background,
+                PrimitiveType::TriangleStrip,
+                0..4,
+            )
+            .with_fragment_arguments(&background_material);
+        draw_grid(renderer, render_pass);
+        render_pass.pop_debug_group();
+        particles.draw(renderer, render_pass);
+        triangle_field.draw(renderer, render_pass, None);
+        for (mesh, instance) in &primitives {
+            render_pass
+                .draw_mesh(
+                    &renderer.render_pipeline_state("vertex_instanced", "fragment_main"),
+                    mesh,
+                )
+                .with_instances(instance);
+        }
+        // the wave glows where it crosses the grid, past white on HDR screens
+        let additive = renderer
+            .pipeline_descriptor("vertex_glow", "fragment_main")
+            .with_blend_mode(BlendMode::Additive);
+        render_pass
+            .wait_for_fence(&wave_written)
+            .update_fence(&wave_drawn)
+            .draw(
+                &renderer.render_pipeline_state_for(&additive),
+                wave.buffer(),
+                PrimitiveType::LineStrip,
+                0..WAVE_VERTEX_COUNT,
+            );
+        // fetched through a vertex descriptor rather than by the shader, and tinted with a
+        // buffer bound to the argument named `tint` wherever the shader has it
+        let attributes = renderer
+            .pipeline_descriptor("vertex_attributes", "fragment_tinted")
+            .with_vertex_layout(VertexLayout::of::<VertexInput>(1));
+        let attributes = renderer.render_pipeline_state_for(&attributes);
+        render_pass
+            .draw(&attributes, &two_sided_quad, PrimitiveType::Triangle, 0..6)
+            .with_pick_id(TINTED_QUAD);
+        if let Some(tint) = renderer
+            .shader_bindings(&attributes)
+            .and_then(|bindings| bindings.buffer("tint"))
+        {
+            render_pass.with_buffer(tint, quad_tint.buffer());
+        }
+        render_pass
+            .draw(
+                &renderer.render_pipeline_state("vertex_shadowed", "fragment_shadowed"),
+                &shadow_scene,
+                PrimitiveType::Triangle,
+                0..12,
+            )
+            .with_pick_id(SHADOW_SCENE);
+        if let Some(bindless_table) = &*bindless_table.borrow() {
+            render_pass
+                .draw_instanced(
+                    &renderer.render_pipeline_state("vertex_bindless", "fragment_bindless"),
+                    &textured_quad,
+                    &quad_instance,
+                    PrimitiveType::TriangleStrip,
+                    0..4,
+                )
+                .with_bindless_table(bindless_table);
+        }
+        if let Some(sparse_texture) = &sparse_texture {
+            let (sparse_texture, arguments) = &mut *sparse_texture.borrow_mut();
+            // the closer the camera, the more detailed the levels kept in memory
+            sparse_texture.request_for_distance(1. / renderer.camera().zoom, 0.125);
+            renderer.update_residency(sparse_texture);
+            render_pass
+                .draw(
+                    &renderer.render_pipeline_state("vertex_quad", "fragment_textured"),
+                    &sparse_quad,
+                    PrimitiveType::TriangleStrip,
+                    0..4,
+                )
+                .with_fragment_arguments(arguments)
+                .with_pick_id(SPARSE_QUAD);
+        }
+        // the outlines of the camera cover the screen, which covers the video
+        #[cfg(feature = "video")]
+        let quad_texture = camera_edges.borrow().clone();
+        #[cfg(all(feature = "screen-capture", target_os = "macos"))]
+        let quad_texture = quad_texture.or_else(|| screen.as_ref()?.borrow_mut().update().cloned());
+        #[cfg(feature = "video")]
+        if let Some(texture) = quad_texture.or_else(|| {
+            let mut video = video.borrow_mut();
+            video.as_mut().and_then(VideoPlayer::update).cloned()
+        }) {
+            let pipeline_state = renderer.render_pipeline_state("vertex_quad", "fragment_video");
+            if let Some(slot) = renderer
+                .shader_bindings(&pipeline_state)
+                .and_then(|bindings| bindings.texture("video"))
+            {
+                render_pass
+                    .draw(
+                        &pipeline_state,
+                        &textured_quad,
+                        PrimitiveType::TriangleStrip,
+                        0..4,
+                    )
+                    .with_texture(slot, &texture);
+            }
+        }
+        let mut picture = picture.borrow_mut();
+        if !picture
+            .as_ref()
+            .is_some_and(|(target, _)| target.is_compatible_with(renderer))
+        {
+            // only its resolved color is sampled, the rest stays in tile memory
+            let mut target = RenderTargetBuilder::new(256, 256)
+                .with_memoryless_attachments()
+                .build(renderer);
+            target.set_label("Picture");
+            target.clear_color = MTLClearColor {
+                red: 0.1,
+                green: 0.1,
+                blue: 0.2,
+                alpha: 1.,
+            };
+            let arguments = Rc::new(create_texture_arguments(renderer, target.texture()));
+            // the picture is drawn again if the os discards it under memory pressure
+            renderer.register_purgeable(ProtocolObject::from_ref(&**target.texture()));
+            *picture = Some((target, arguments));
+        }
+        if let Some((target, arguments)) = picture.as_ref() {
+            let texture = ProtocolObject::from_ref(&**target.texture());
+            let discarded = !renderer.reclaim_purgeable(texture);
+            if discarded || picture_queries.samples_passed(0) != Some(0) {
+                renderer.draw_geometry(render_pass.render_to(target).set_label("Picture"));
+            }
+            render_pass
+                .with_occlusion_queries(&picture_queries)
+                .draw(
+                    &renderer.render_pipeline_state("vertex_quad", "fragment_textured"),
+                    &picture_quad,
+                    PrimitiveType::TriangleStrip,
+                    0..4,
+                )
+                .with_fragment_arguments(arguments)
+                .with_occlusion_query(0)
+                .with_depth_stencil(&marking, 1)
+                .draw(
+                    &renderer.pipeline_state(),
+                    &picture_frame,
+                    PrimitiveType::TriangleStrip,
+                    0..4,
+                )
+                .with_depth_stencil(&unmarked, 1);
+        }
+        if let Some(hexagon_system) = &*hexagon_system {
+            let (graph, [orbit, moon, little_moon], _) = &mut *hexagon_system.borrow_mut();
+            let time = start_time.elapsed().as_secs_f32();
+            let [x, y] = HEXAGON_CENTER;
+            // the moons are placed in the space of their parents, the little moon's orbit
+            // turns with the spin of the moon
+            graph.set_transform(*orbit, placement(HEXAGON_CENTER, [x, y], 0.5 * time, 1.));
+            graph.set_transform(*moon, placement(HEXAGON_CENTER, [x + 0.3, y], 2. * time, 0.35));
+            let (sin, cos) = (3. * time).sin_cos();
+            let position = [x + 0.3 * cos, y + 0.3 * sin];
+            graph.set_transform(*little_moon, placement(HEXAGON_CENTER, position, 0., 0.5));
+            graph.draw(renderer, render_pass);
+        }
+        #[cfg(feature = "ecs")]
+        {
+            let mut world = world.borrow_mut();
+            let (sin, cos) = (0.5 * start_time.elapsed().as_secs_f32()).sin_cos();
+            for (_, transform) in world.query_mut::<&mut Transform>() {
+                transform.rotation = [0., 0., sin, cos];
+            }
+            renderer.draw_world(&world, &world_assets, render_pass);
+        }
+        render_pass.draw_instanced(
+            &renderer.render_pipeline_state("vertex_instanced", "fragment_main"),
+            &instanced_triangle,
+            &triangle_instances,
+            PrimitiveType::Triangle,
+            0..3,
+        );
+        // the spinning triangle goes through an object and a mesh shader where the device has
+        // them
+        match &*scene.borrow() {
+            Some(scene) => {
+                // a glTF scene can bring hundreds of primitives, their draws are split between
+                // two threads
+                scene.draw(renderer, render_pass);
+                render_pass.encode_parallel(2);
+            }
+            None => renderer.draw_geometry_meshlets(render_pass),
+        }
+        if let Some(sprite_arguments) = &*sprite_arguments.borrow() {
+            let mut sprites = sprites.borrow_mut();
+            let time = start_time.elapsed().as_secs_f32();
+            for i in 0..SPRITE_COUNT {
+                let x = (i as f32 / (SPRITE_COUNT - 1) as f32 - 0.5) * 0.8;
+                let sprite = Sprite::new([x, 0.8], [0.1, 0.1], time + i as f32)
+                    .with_tint([1., 1. - i as f32 / SPRITE_COUNT as f32, 1.]);
+                sprites.push(sprite_arguments, sprite);
+            }
+            sprites.flush(renderer, render_pass);
+        }
+        let mut debug_draw = debug_draw.borrow_mut();
+        debug_draw.aabb([-0.85, -1., -0.05], [0.85, -0.7, 0.05], [1., 1., 0.]);
+        if scene.borrow().is_some() {
+            debug_draw.grid([0., 0., 0.], 4., 16, [0.5, 0.5, 0.5]);
+            debug_draw.axes(&InstanceData::default().transform, 1.);
+        }
+        debug_draw.flush(renderer, render_pass);
+        // faint scanlines over the scene, the text below stays clear of them
+        if renderer.supports_tile_shading() {
+            render_pass
+                .with_tile_config(TileConfig {
+                    tile_width: 32,
+                    tile_height: 16,
+                    ..Default::default()
+                })
+                .dispatch_tiles(
+                    &renderer.tile_pipeline_state("tile_scanlines"),
+                    &renderer.frame_buffer(&[0.1f32]),
+                );
+        }
+        if let Some(font) = &font {
+            renderer.draw_text(font, CONTROLS_HINT, (16., 16.), &hint_style);
+        }
+    });
+    // the frame timings, in a Dear ImGui window
+    #[cfg(feature = "imgui")]
+    renderer.set_imgui_callback(|renderer, ui| {
+        ui.window("Frame")
+            .position([16., 48.], imgui::Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                let frame_stats = renderer.frame_stats();
+                let timings = [
+                    ("CPU frame time", frame_stats.cpu_frame_time),
+                    ("GPU frame time", frame_stats.gpu_frame_time),
+                ];
+                for (name, time) in timings {
+                    match time {
+                        Some(time) => ui.text(format!("{name}: {:.3} ms", time * 1e3)),
+                        None => ui.text(format!("{name}: not measured")),
+                    }
+                }
+                let mut overlay_visible = renderer.is_overlay_visible();
+                if ui.checkbox("Frame rate overlay", &mut overlay_visible) {
+                    renderer.set_overlay_visible(overlay_visible);
+                }
+            });
+    });
+    // count the frames the gpu finished, reported on exit
+    let completed_frames = Arc::new(AtomicUsize::new(0));
+    let frame_counter = completed_frames.clone();
+    renderer.on_frame_complete(move || {
+        frame_counter.fetch_add(1, Ordering::Relaxed);
+    });
+    // a few of the settings the keys change, as a ui drawn over the frames
+    #[cfg(feature = "egui")]
+    renderer.set_egui_callback(|renderer, context| {
+        egui::Window::new("Settings").show(context, |ui| {
+            let mut overlay_visible = renderer.is_overlay_visible();
+            if ui.checkbox(&mut overlay_visible, "Frame rate").changed() {
+                renderer.set_overlay_visible(overlay_visible);
+            }
+            let mut fill_mode = renderer.fill_mode();
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut fill_mode, FillMode::Fill, "Fill");
+                ui.radio_value(&mut fill_mode, FillMode::Lines, "Lines");
+            });
+            if fill_mode != renderer.fill_mode() {
+                renderer.set_fill_mode(fill_mode);
+            }
+            let mut point_size = renderer.point_size();
+            if ui
+                .add(egui::Slider::new(&mut point_size, 1.0..=64.0).text("Point size"))
+                .changed()
+            {
+                renderer.set_point_size(point_size);
+            }
+        });
+    });
+    renderers.insert(window.id(), (window, renderer));
+
+    // a second window showing the same geometry as points, it renders on the device and
+    // queue of the main window
+    let (window, renderer) = create_window(
+        &event_loop,
+        "Another fantastic window!",
+        renderer_config.backend,
+        Some(&renderers[&main_window_id].1),
+        DeviceSelector::SystemDefault,
+    );
+    window.set_outer_position(LogicalPosition::new(64., 64.));
+    renderer.set_primitive_type(PrimitiveType::Point);
+    renderer.set_point_size(16.);
+    renderer.set_min_content_size(NSSize::new(128., 128.));
+    // nothing animates here, so only draw when something changed
+    renderer.set_redraw_mode(RedrawMode::OnDemand);
+    // split the view, the left half shows the geometry and the right half a magnified copy
+    // rendered into a small texture by a pass of the render graph before it
+    renderer.set_render_graph_callback(|renderer, graph| {
+        let NSSize { width, height } = renderer.drawable_size();
+        let viewport = move |x, size| MTLViewport {
+            originX: x + (width / 2. - size) / 2.,
+            originY: (height - size) / 2.,
+            width: size,
+            height: size,
+            znear: 0.,
+            zfar: 1.,
+        };
+        let size = (width / 2.).min(height);
+        let points = graph.create_texture(64, 64);
+        graph.add_pass("points", points, |renderer, _, render_pass| {
+            renderer.draw_geometry(render_pass);
+        });
+        let backbuffer = graph.backbuffer();
+        graph
+            .add_pass("view", backbuffer, move |renderer, resources, render_pass| {
+                // the geometry goes into the left half, the copy sets a viewport of its own
+                render_pass.set_viewport(viewport(0., size));
+                renderer.draw_geometry(render_pass);
+                let Some(texture) = resources.texture(points) else {
+                    return;
+                };
+                let white = MTLPackedFloat3 {
+                    x: 1.,
+                    y: 1.,
+                    z: 1.,
+                };
+                let corner = |x, y| VertexInput {
+                    position: MTLPackedFloat3 { x, y, z: 0. },
+                    color: white,
+                };
+                let quad = [corner(-1., -1.), corner(1., -1.), corner(-1., 1.), corner(1., 1.)];
+                let arguments = Rc::new(create_texture_arguments(renderer, texture));
+                render_pass
+                    .draw(
+                        &renderer.render_pipeline_state("vertex_quad", "fragment_textured"),
+                        &renderer.frame_buffer(&quad),
+                        PrimitiveType::TriangleStrip,
+                        0..4,
+                    )
+                    .with_fragment_arguments(&arguments)
+                    .with_viewport(viewport(width / 2., size));
+            })
+            .reads(points);
+    });
+    renderers.insert(window.id(), (window, renderer));
+
+    let mut modifiers = ModifiersState::empty();
+
+    // gamepads are optional, the example runs without them
+    let mut gilrs = Gilrs::new()
+        .inspect_err(|error| eprintln!("Gamepads are unavailable: {error}"))
+        .ok();
+    let mut game_loop = config.game_loop();
+    let game_loop_mode = game_loop.mode();
+
+    event_loop.run(move |event, _, control_flow| {
+        tracing::trace!(?event);
+
+        // tick every frame with --game-loop or while a gamepad is connected to read its sticks,
+        // otherwise idle. a gamepad connected while idling is noticed with the next event. the
+        // renderers stop drawing while their windows are hidden, nothing ticks then either
+        let gamepad_connected = gilrs
+            .as_ref()
+            .is_some_and(|gilrs| gilrs.gamepads().next().is_some());
+        let visible = renderers
+            .values()
+            .any(|(_, renderer)| !renderer.is_occluded());
+        // the camera flies while a key is held down, which sends no events
+        let flying = renderers
+            .values()
+            .any(|(_, renderer)| renderer.cursor_mode() == CursorMode::Locked);
+        let continuous = game_loop_mode == LoopMode::Continuous || gamepad_connected || flying;
+        game_loop.set_mode(if continuous && visible {
+            LoopMode::Continuous
+        } else {
+            LoopMode::Wait
+        });
+        *control_flow = game_loop.control_flow();
+
+        match event {
+            Event::WindowEvent {
+                window_id, event, ..
+            } => {
+                if let Some((_, renderer)) = renderers.get(&window_id) {
+                    renderer.handle_window_event(&event);
+                }
+                match event {
+                    WindowEvent::CloseRequested => {
+                        // dropping the window and its renderer closes only this window
+                        if let Some((_, renderer)) = renderers.remove(&window_id) {
+                            if window_id == main_window_id {
+                                let mut current_config = renderer.current_config();
+                                config.restore(
+                                    &mut current_config,
+                                    &renderer_config,
+                                    &saved_config,
+                                );
+                                save_config(CONFIG_PATH, &current_config);
+                            }
+                        }
+                        if renderers.is_empty() {
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                    // a window moved to a screen of another resolution changes its scale factor
+                    // without changing its size in points, the drawable still has to follow
+                    WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                        if let Some((_, renderer)) = renderers.get(&window_id) {
+                            renderer.resize();
+                        }
+                    }
+                    WindowEvent::ModifiersChanged(new_modifiers) => modifiers = new_modifiers,
+                    WindowEvent::DroppedFile(path) if window_id == main_window_id => {
+                        if let Some((window, renderer)) = renderers.get(&window_id) {
+                            load_asset(renderer, &path);
+                            window.request_redraw();
+                        }
+                    }
+                    // a window in the background leaves the cursor to the others
+                    WindowEvent::Focused(false) => {
+                        if let Some((window, renderer)) = renderers.get(&window_id) {
+                            if renderer.cursor_mode() != CursorMode::Normal {
+                                let _ = renderer.set_cursor_mode(window, CursorMode::Normal);
+                            }
+                        }
+                    }
+                    WindowEvent::Focused(true) => {
+                        key_window_id = window_id;
+                        #[cfg(target_os = "macos")]
+                        if let Some((_, renderer)) = renderers.get(&window_id) {
+                            menu.set_vsync(renderer.vsync());
+                        }
+                    }
+                    // the update callback reacts to the mouse, the views drawing on demand need a
+                    // frame for it to run
+                    WindowEvent::CursorMoved { .. }
+                    | WindowEvent::MouseInput { .. }
+                    | WindowEvent::MouseWheel { .. } => {
+                        if let Some((window, _)) = renderers.get(&window_id) {
+                            window.request_redraw();
+                        }
+                    }
+                    WindowEvent::KeyboardInput { event, .. }
+                        if event.state == ElementState::Pressed && !event.repeat =>
+                    {
+                        let flying = renderers.get(&window_id).is_some_and(|(_, renderer)| {
+                            renderer.cursor_mode() == CursorMode::Locked
+                        });
+                        // escape releases the cursor of the flying camera rather than quitting
+                        if QUIT_SHORTCUTS
+                            && !flying
+                            && is_quit_shortcut(event.physical_key, modifiers)
+                        {
+                            *control_flow = ControlFlow::Exit;
+                        } else if let Some((window, renderer)) = renderers.get(&window_id) {
+                            if is_window_mode_shortcut(event.physical_key, modifiers) {
+                                cycle_window_mode(window, renderer);
+                            } else if event.physical_key == KeyCode::Digit8
+                                || flying && event.physical_key == KeyCode::Escape
+                            {
+                                toggle_fly_camera(window, renderer);
+                            } else if !flying {
+                                // the keys move the flying camera instead
+                                handle_key_pressed(renderer, event.physical_key, modifiers);
+                            }
+                            window.request_redraw();
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            // the mouse motion turns the flying camera of the window in focus
+            Event::DeviceEvent { event, .. } => {
+                if let Some((_, renderer)) = renderers.get(&key_window_id) {
+                    renderer.handle_device_event(&event);
+                }
+            }
+            Event::UserEvent(command) => {
+                if command == MenuCommand::Quit {
+                    *control_flow = ControlFlow::Exit;
+                } else if command == MenuCommand::OpenModel {
+                    #[cfg(target_os = "macos")]
+                    if let Some(path) = dialog::open_file("Open a model", &["gltf", "glb", "obj"]) {
+                        if let Some((window, renderer)) = renderers.get(&main_window_id) {
+                            load_asset(renderer, &path);
+                            window.request_redraw();
+                        }
+                    }
+                } else if let Some((window, renderer)) = renderers.get(&key_window_id) {
+                    handle_menu_command(window, renderer, command);
+                    #[cfg(target_os = "macos")]
+                    menu.set_vsync(renderer.vsync());
+                    window.request_redraw();
+                }
+            }
+            Event::RedrawRequested(window_id) => {
+                if let Some((_, renderer)) = renderers.get(&window_id) {
+                    renderer.redraw();
+                }
+            }
+            Event::MainEventsCleared => {
+                let Some(elapsed) = game_loop.tick() else {
+                    return;
+                };
+                // the views draw on demand, every tick of the game loop asks them for a frame
+                if game_loop_mode == LoopMode::Continuous {
+                    for (window, _) in renderers.values() {
+                        window.request_redraw();
+                    }
+                }
+                if let Some(gilrs) = gilrs.as_mut() {
+                    // the gamepad state is only updated while draining its events
+                    while gilrs.next_event().is_some() {}
+                    for (_, renderer) in renderers.values() {
+                        apply_gamepad(gilrs, &GAMEPAD_SETTINGS, elapsed, renderer);
+                    }
+                }
+            }
+            Event::LoopDestroyed => {
+                frames_done.wait(frame_number.get(), Duration::from_secs(1));
+                eprintln!(
+                    "Completed {} frames.",
+                    completed_frames.load(Ordering::Relaxed)
+                );
+            }
+            _ => (),
+        }
+    });
+}
@@ -0,0 +1,195 @@
+// the menu bar of the example, its items are sent to the event loop as user events and applied
+// to the renderer of the key window
+#[cfg(target_os = "macos")]
+use objc2::{
+    declare_class, msg_send_id, mutability::MainThreadOnly, rc::Retained, sel, ClassType,
+    DeclaredClass,
+};
+#[cfg(target_os = "macos")]
+use objc2_app_kit::{
+    NSApplication, NSControlStateValueOff, NSControlStateValueOn, NSEventModifierFlags, NSMenu,
+    NSMenuItem,
+};
+#[cfg(target_os = "macos")]
+use objc2_foundation::{MainThreadMarker, NSObject, NSObjectProtocol, NSString};
+#[cfg(target_os = "macos")]
+use tao::event_loop::EventLoopProxy;
+
+// what the items of the menu do, there's no menu bar on ios
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(target_os = "ios", allow(dead_code))]
+pub enum MenuCommand {
+    OpenModel,
+    ToggleFullscreen,
+    ToggleVsync,
+    CaptureFrame,
+    Screenshot,
+    Quit,
+}
+
+#[cfg(target_os = "macos")]
+impl MenuCommand {
+    const ALL: [MenuCommand; 6] = [
+        MenuCommand::OpenModel,
+        MenuCommand::ToggleFullscreen,
+        MenuCommand::ToggleVsync,
+        MenuCommand::CaptureFrame,
+        MenuCommand::Screenshot,
+        MenuCommand::Quit,
+    ];
+
+    // the tag of its menu item
+    fn tag(self) -> isize {
+        Self::ALL
+            .iter()
+            .position(|command| *command == self)
+            .unwrap() as isize
+    }
+
+    fn from_tag(tag: isize) -> Option<Self> {
+        Self::ALL.get(usize::try_from(tag).ok()?).copied()
+    }
+}
+
+#[cfg(target_os = "macos")]
+declare_class!(
+    // the target of the menu items, forwards the command of the chosen one to the event loop
+    struct MenuTarget;
+
+    // SAFETY:
+    // - The superclass NSObject does not have any subclassing requirements.
+    // - Main thread only mutability is correct, menu actions are sent on the main thread.
+    // - `MenuTarget` does not implement `Drop`.
+    unsafe impl ClassType for MenuTarget {
+        type Super = NSObject;
+        type Mutability = MainThreadOnly;
+        const NAME: &'static str = "MenuTarget";
+    }
+
+    impl DeclaredClass for MenuTarget {
+        type Ivars = EventLoopProxy<MenuCommand>;
+    }
+
+    unsafe impl NSObjectProtocol for MenuTarget {}
+
+    unsafe impl MenuTarget {
+        #[method(menuCommand:)]
+        fn menu_command(&self, item: &NSMenuItem) {
+            if let Some(command) = MenuCommand::from_tag(unsafe { item.tag() }) {
+                // the event loop is gone while the app quits
+                let _ = self.ivars().send_event(command);
+            }
+        }
+    }
+);
+
+// the menu bar of the app, it only lasts as long as this
+#[cfg(target_os = "macos")]
+pub struct Menu {
+    // the menu items only hold on to their target weakly
+    _target: Retained<MenuTarget>,
+    vsync_item: Retained<NSMenuItem>,
+}
+
+#[cfg(target_os = "macos")]
+impl Menu {
+    // installs the menu bar, the application menu with quit, a file menu opening models, a view
+    // menu with the full screen and the vsync and a frame menu with the captures
+    pub fn install(proxy: EventLoopProxy<MenuCommand>, app_name: &str) -> Self {
+        let mtm = MainThreadMarker::new().expect("Failed to get the main thread.");
+        let target: Retained<MenuTarget> = {
+            let this = mtm.alloc().set_ivars(proxy);
+            unsafe { msg_send_id![super(this), init] }
+        };
+        let item = |title: &str, key: &str, modifiers: NSEventModifierFlags, command| {
+            let item = unsafe {
+                NSMenuItem::initWithTitle_action_keyEquivalent(
+                    mtm.alloc(),
+                    &NSString::from_str(title),
+                    Some(sel!(menuCommand:)),
+                    &NSString::from_str(key),
+                )
+            };
+            item.setKeyEquivalentModifierMask(modifiers);
+            unsafe {
+                item.setTarget(Some(&target));
+                item.setTag(MenuCommand::tag(command));
+            }
+            item
+        };
+        let command_key = NSEventModifierFlags::NSEventModifierFlagCommand;
+
+        // the first menu is the one named after the app, whatever its title
+        let app_menu = submenu(mtm, app_name);
+        app_menu.addItem(&item(
+            &format!("Quit {app_name}"),
+            "q",
+            command_key,
+            MenuCommand::Quit,
+        ));
+
+        let file_menu = submenu(mtm, "File");
+        file_menu.addItem(&item(
+            "Open Model…",
+            "o",
+            command_key,
+            MenuCommand::OpenModel,
+        ));
+
+        let view_menu = submenu(mtm, "View");
+        view_menu.addItem(&item(
+            "Toggle Full Screen",
+            "f",
+            command_key | NSEventModifierFlags::NSEventModifierFlagControl,
+            MenuCommand::ToggleFullscreen,
+        ));
+        let vsync_item = item(
+            "Vertical Sync",
+            "",
+            NSEventModifierFlags::empty(),
+            MenuCommand::ToggleVsync,
+        );
+        view_menu.addItem(&vsync_item);
+
+        let frame_menu = submenu(mtm, "Frame");
+        frame_menu.addItem(&item(
+            "Capture GPU Frame",
+            "c",
+            command_key | NSEventModifierFlags::NSEventModifierFlagOption,
+            MenuCommand::CaptureFrame,
+        ));
+        frame_menu.addItem(&item(
+            "Save Screenshot…",
+            "s",
+            command_key | NSEventModifierFlags::NSEventModifierFlagShift,
+            MenuCommand::Screenshot,
+        ));
+
+        let main_menu = submenu(mtm, "");
+        for menu in [app_menu, file_menu, view_menu, frame_menu] {
+            let menu_item = NSMenuItem::new(mtm);
+            menu_item.setSubmenu(Some(&menu));
+            main_menu.addItem(&menu_item);
+        }
+        NSApplication::sharedApplication(mtm).setMainMenu(Some(&main_menu));
+        Self {
+            _target: target,
+            vsync_item,
+        }
+    }
+
+    // checks the vsync item when the key window waits for the display
+    pub fn set_vsync(&self, vsync: bool) {
+        let state = if vsync {
+            NSControlStateValueOn
+        } else {
+            NSControlStateValueOff
+        };
+        unsafe { self.vsync_item.setState(state) };
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn submenu(mtm: MainThreadMarker, title: &str) -> Retained<NSMenu> {
+    unsafe { NSMenu::initWithTitle(mtm.alloc(), &NSString::from_str(title)) }
+}
@@ -0,0 +1,54 @@
+// the triangle of the renderer in a winit window, run with `cargo run --example winit --features
+// winit`. the renderer is set up and fed the events like for a tao window
+use objc2::rc::Retained;
+use rust_tao_metal::{
+    winit::{
+        application::ApplicationHandler,
+        event::WindowEvent,
+        event_loop::{ActiveEventLoop, EventLoop},
+        window::{Window, WindowId},
+    },
+    DepthFormat, MetalRenderer,
+};
+
+#[derive(Default)]
+struct App {
+    window: Option<(Window, Retained<MetalRenderer>)>,
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        let attributes = Window::default_attributes().with_title("A winit window");
+        let window = event_loop
+            .create_window(attributes)
+            .expect("Failed to create a window.");
+        let renderer = MetalRenderer::new_winit(&window).expect("Failed to create the renderer.");
+        renderer.set_logger(|level, message| eprintln!("[{level:?}] {message}"));
+        renderer.init().expect("Failed to initialize the renderer.");
+        renderer.set_depth_format(Some(DepthFormat::Depth32Float));
+        self.window = Some((window, renderer));
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        let Some((_, renderer)) = &self.window else {
+            return;
+        };
+        renderer.handle_winit_event(&event);
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => renderer.resize(),
+            WindowEvent::RedrawRequested => renderer.redraw(),
+            _ => (),
+        }
+    }
+}
+
+fn main() {
+    let event_loop = EventLoop::new().expect("Failed to create an event loop.");
+    event_loop
+        .run_app(&mut App::default())
+        .expect("Failed to run the event loop.");
+}
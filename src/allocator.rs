@@ -0,0 +1,377 @@
+use core::{cell::RefCell, fmt, ops::Range};
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLBuffer, MTLDevice, MTLHazardTrackingMode, MTLHeap, MTLHeapDescriptor, MTLHeapType,
+    MTLResourceOptions, MTLStorageMode, MTLTexture, MTLTextureDescriptor,
+};
+
+use crate::MetalRenderer;
+
+type Buffer = Retained<ProtocolObject<dyn MTLBuffer>>;
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the size of the heaps of an allocator, unless it's created with another
+pub const DEFAULT_HEAP_SIZE: usize = 64 << 20;
+
+pub(crate) fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align.max(1)) * align.max(1)
+}
+
+struct Heap {
+    heap: Retained<ProtocolObject<dyn MTLHeap>>,
+    storage_mode: MTLStorageMode,
+    hazard_tracking: MTLHazardTrackingMode,
+    // the ranges nothing is placed in, ordered by their offset. neighbouring ranges are
+    // merged, so a heap without allocations has a single one covering it
+    free: RefCell<Vec<Range<usize>>>,
+}
+
+impl Heap {
+    fn size(&self) -> usize {
+        self.heap.size()
+    }
+
+    // takes `size` bytes at an offset aligned to `align` from the first free range they fit
+    fn take(&self, size: usize, align: usize) -> Option<Range<usize>> {
+        let mut free = self.free.borrow_mut();
+        let index = free
+            .iter()
+            .position(|range| align_up(range.start, align) + size <= range.end)?;
+        let range = free.remove(index);
+        let start = align_up(range.start, align);
+        let remainders = [range.start..start, start + size..range.end];
+        for (i, remainder) in remainders.into_iter().filter(|r| !r.is_empty()).enumerate() {
+            free.insert(index + i, remainder);
+        }
+        Some(start..start + size)
+    }
+
+    fn release(&self, range: Range<usize>) {
+        let mut free = self.free.borrow_mut();
+        let index = free.partition_point(|free| free.start < range.start);
+        free.insert(index, range);
+        // merge with the following range, then with the preceding one
+        if index + 1 < free.len() && free[index].end == free[index + 1].start {
+            free[index].end = free.remove(index + 1).end;
+        }
+        if index > 0 && free[index - 1].end == free[index].start {
+            free[index - 1].end = free.remove(index).end;
+        }
+    }
+}
+
+// a range of a heap, given back to it once the last resource placed in it is gone
+struct Block {
+    heap: Rc<Heap>,
+    range: Range<usize>,
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        self.heap.release(self.range.clone());
+    }
+}
+
+// memory of a heap of a `GpuAllocator` resources are placed in. resources placed in the same
+// allocation alias each other, only one of them holds meaningful contents at a time. the gpu
+// may still use the resources once the allocation is dropped, the heaps are tracked so the
+// passes using the memory next wait for them, unless the allocator is untracked
+#[derive(Clone)]
+pub struct HeapAllocation(Rc<Block>);
+
+impl HeapAllocation {
+    pub fn size(&self) -> usize {
+        self.0.range.len()
+    }
+
+    // the offset in the heap, resources placed in the allocation are aligned relative to it
+    pub fn offset(&self) -> usize {
+        self.0.range.start
+    }
+
+    pub fn storage_mode(&self) -> MTLStorageMode {
+        self.0.heap.storage_mode
+    }
+
+    pub fn hazard_tracking(&self) -> MTLHazardTrackingMode {
+        self.0.heap.hazard_tracking
+    }
+
+    // a texture at `offset` into the allocation, none when it doesn't fit or the offset isn't
+    // aligned as it needs. the storage mode of `descriptor` is set to the one of the heap
+    pub fn new_texture(&self, descriptor: &MTLTextureDescriptor, offset: usize) -> Option<Texture> {
+        let heap = &self.0.heap.heap;
+        descriptor.setStorageMode(self.storage_mode());
+        let size_and_align = heap
+            .device()
+            .heapTextureSizeAndAlignWithDescriptor(descriptor);
+        let start = self.offset() + offset;
+        if !start.is_multiple_of(size_and_align.align.max(1))
+            || offset + size_and_align.size > self.size()
+        {
+            return None;
+        }
+        unsafe { heap.newTextureWithDescriptor_offset(descriptor, start) }
+    }
+
+    // a buffer of `length` bytes at `offset` into the allocation, none when it doesn't fit or
+    // the offset isn't aligned as it needs
+    pub fn new_buffer(&self, length: usize, offset: usize) -> Option<Buffer> {
+        let heap = &self.0.heap.heap;
+        let options = resource_options(self.storage_mode(), self.0.heap.hazard_tracking);
+        let size_and_align = heap
+            .device()
+            .heapBufferSizeAndAlignWithLength_options(length, options);
+        let start = self.offset() + offset;
+        if !start.is_multiple_of(size_and_align.align.max(1))
+            || offset + size_and_align.size > self.size()
+        {
+            return None;
+        }
+        unsafe { heap.newBufferWithLength_options_offset(length, options, start) }
+    }
+}
+
+fn resource_options(
+    storage_mode: MTLStorageMode,
+    hazard_tracking: MTLHazardTrackingMode,
+) -> MTLResourceOptions {
+    let storage_mode = match storage_mode {
+        MTLStorageMode::Shared => MTLResourceOptions::MTLResourceStorageModeShared,
+        MTLStorageMode::Managed => MTLResourceOptions::MTLResourceStorageModeManaged,
+        _ => MTLResourceOptions::MTLResourceStorageModePrivate,
+    };
+    let hazard_tracking = match hazard_tracking {
+        MTLHazardTrackingMode::Untracked => {
+            MTLResourceOptions::MTLResourceHazardTrackingModeUntracked
+        }
+        _ => MTLResourceOptions::MTLResourceHazardTrackingModeTracked,
+    };
+    storage_mode | hazard_tracking
+}
+
+// a buffer placed in a heap, its memory is given back with the last clone
+#[derive(Clone)]
+pub struct HeapBuffer {
+    buffer: Buffer,
+    allocation: HeapAllocation,
+}
+
+impl HeapBuffer {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn allocation(&self) -> &HeapAllocation {
+        &self.allocation
+    }
+}
+
+// a texture placed in a heap, its memory is given back with the last clone
+#[derive(Clone)]
+pub struct HeapTexture {
+    texture: Texture,
+    allocation: HeapAllocation,
+}
+
+impl HeapTexture {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn allocation(&self) -> &HeapAllocation {
+        &self.allocation
+    }
+}
+
+// how much of the heaps of an allocator is used
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AllocatorStats {
+    pub heap_count: usize,
+    // the bytes of all the heaps
+    pub reserved: usize,
+    // the bytes handed out, alignment padding aside
+    pub allocated: usize,
+    // the largest allocation that fits without creating another heap
+    pub largest_free_range: usize,
+    // the share of the free bytes outside the largest free range of their heap, 0 when the
+    // free memory of every heap is in one piece
+    pub fragmentation: f32,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let megabytes = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+        writeln!(f, "Heaps: {}", self.heap_count)?;
+        writeln!(
+            f,
+            "Allocated: {:.1} of {:.1} MB",
+            megabytes(self.allocated),
+            megabytes(self.reserved)
+        )?;
+        writeln!(
+            f,
+            "Largest free range: {:.1} MB",
+            megabytes(self.largest_free_range)
+        )?;
+        write!(f, "Fragmentation: {:.0}%", self.fragmentation * 1e2)
+    }
+}
+
+// places buffers and textures in large placement heaps instead of allocating each on its own,
+// which saves the allocations and lets resources used at different times share memory. a
+// resource larger than the heap size gets a heap of its own
+pub struct GpuAllocator {
+    device: Retained<ProtocolObject<dyn MTLDevice>>,
+    heap_size: usize,
+    // whether metal orders the passes using the resources, or `GpuFence`s have to
+    hazard_tracking: MTLHazardTrackingMode,
+    heaps: RefCell<Vec<Rc<Heap>>>,
+}
+
+impl GpuAllocator {
+    // `size` bytes aligned to `align` in a heap of `storage_mode`, which can't be memoryless
+    pub fn allocate(
+        &self,
+        size: usize,
+        align: usize,
+        storage_mode: MTLStorageMode,
+    ) -> HeapAllocation {
+        let mut heaps = self.heaps.borrow_mut();
+        let taken = heaps
+            .iter()
+            .filter(|heap| heap.storage_mode == storage_mode)
+            .find_map(|heap| Some((heap.clone(), heap.take(size, align)?)));
+        let (heap, range) = match taken {
+            Some(taken) => taken,
+            None => {
+                let heap = Rc::new(self.create_heap(size.max(self.heap_size), storage_mode));
+                heaps.push(heap.clone());
+                let range = heap
+                    .take(size, align)
+                    .expect("Failed to allocate from a new heap.");
+                (heap, range)
+            }
+        };
+        HeapAllocation(Rc::new(Block { heap, range }))
+    }
+
+    fn create_heap(&self, size: usize, storage_mode: MTLStorageMode) -> Heap {
+        let descriptor = unsafe { MTLHeapDescriptor::new() };
+        descriptor.setType(MTLHeapType::Placement);
+        descriptor.setSize(size);
+        descriptor.setStorageMode(storage_mode);
+        descriptor.setHazardTrackingMode(self.hazard_tracking);
+        let heap = self
+            .device
+            .newHeapWithDescriptor(&descriptor)
+            .expect("Failed to create a heap.");
+        let size = heap.size();
+        Heap {
+            heap,
+            storage_mode,
+            hazard_tracking: self.hazard_tracking,
+            free: RefCell::new(core::iter::once(0..size).collect()),
+        }
+    }
+
+    // a buffer of `length` bytes in a heap of `storage_mode`
+    pub fn allocate_buffer(&self, length: usize, storage_mode: MTLStorageMode) -> HeapBuffer {
+        let size_and_align = self.device.heapBufferSizeAndAlignWithLength_options(
+            length,
+            resource_options(storage_mode, self.hazard_tracking),
+        );
+        let allocation = self.allocate(size_and_align.size, size_and_align.align, storage_mode);
+        let buffer = allocation
+            .new_buffer(length, 0)
+            .expect("Failed to place a buffer in a heap.");
+        HeapBuffer { buffer, allocation }
+    }
+
+    // a texture in a heap of the storage mode of `descriptor`
+    pub fn allocate_texture(&self, descriptor: &MTLTextureDescriptor) -> HeapTexture {
+        let size_and_align = self
+            .device
+            .heapTextureSizeAndAlignWithDescriptor(descriptor);
+        let allocation = self.allocate(
+            size_and_align.size,
+            size_and_align.align,
+            descriptor.storageMode(),
+        );
+        let texture = allocation
+            .new_texture(descriptor, 0)
+            .expect("Failed to place a texture in a heap.");
+        HeapTexture {
+            texture,
+            allocation,
+        }
+    }
+
+    // a texture in the memory of `allocation`, aliasing the resources already placed in it.
+    // none when it doesn't fit
+    pub fn allocate_texture_aliasing(
+        &self,
+        descriptor: &MTLTextureDescriptor,
+        allocation: &HeapAllocation,
+    ) -> Option<HeapTexture> {
+        Some(HeapTexture {
+            texture: allocation.new_texture(descriptor, 0)?,
+            allocation: allocation.clone(),
+        })
+    }
+
+    // releases the heaps nothing is placed in anymore
+    pub fn trim(&self) {
+        self.heaps
+            .borrow_mut()
+            .retain(|heap| Rc::strong_count(heap) > 1);
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let heaps = self.heaps.borrow();
+        let mut stats = AllocatorStats {
+            heap_count: heaps.len(),
+            ..Default::default()
+        };
+        let mut fragmented = 0;
+        for heap in heaps.iter() {
+            let free = heap.free.borrow();
+            let free_size: usize = free.iter().map(|range| range.len()).sum();
+            let largest = free.iter().map(|range| range.len()).max().unwrap_or(0);
+            stats.reserved += heap.size();
+            stats.allocated += heap.size() - free_size;
+            stats.largest_free_range = stats.largest_free_range.max(largest);
+            fragmented += free_size - largest;
+        }
+        let free_size = stats.reserved - stats.allocated;
+        if free_size > 0 {
+            stats.fragmentation = fragmented as f32 / free_size as f32;
+        }
+        stats
+    }
+}
+
+impl MetalRenderer {
+    // an allocator placing resources in heaps of `heap_size` bytes, `DEFAULT_HEAP_SIZE` suits
+    // most uses
+    pub fn create_gpu_allocator(&self, heap_size: usize) -> GpuAllocator {
+        GpuAllocator {
+            device: self.device(),
+            heap_size,
+            hazard_tracking: MTLHazardTrackingMode::Tracked,
+            heaps: RefCell::default(),
+        }
+    }
+
+    // an allocator whose resources metal doesn't track, which saves the cost of tracking them.
+    // the passes using them have to be ordered with `GpuFence`s, and the frames using them
+    // with the cpu
+    pub fn create_untracked_gpu_allocator(&self, heap_size: usize) -> GpuAllocator {
+        GpuAllocator {
+            hazard_tracking: MTLHazardTrackingMode::Untracked,
+            ..self.create_gpu_allocator(heap_size)
+        }
+    }
+}
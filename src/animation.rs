@@ -0,0 +1,418 @@
+use crate::camera::{multiply, Matrix, IDENTITY};
+
+// the joints moving a vertex of a skinned mesh and how much each of them does,
+// `SkinVertex` in triangle.metal. the joints index the matrices of the mesh's skin and the
+// weights add up to 1
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct SkinVertex {
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+}
+
+// the local transform of a node relative to its parent, the rotation is a unit quaternion
+// with w last
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct NodeTransform {
+    pub(crate) translation: [f32; 3],
+    pub(crate) rotation: [f32; 4],
+    pub(crate) scale: [f32; 3],
+}
+
+impl NodeTransform {
+    pub(crate) fn from_gltf(transform: gltf::scene::Transform) -> Self {
+        let (translation, rotation, scale) = transform.decomposed();
+        NodeTransform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    // scales, then rotates, then translates
+    pub(crate) fn matrix(&self) -> Matrix {
+        let [x, y, z, w] = self.rotation;
+        let [sx, sy, sz] = self.scale;
+        let [tx, ty, tz] = self.translation;
+        [
+            [
+                (1. - 2. * (y * y + z * z)) * sx,
+                2. * (x * y + z * w) * sx,
+                2. * (x * z - y * w) * sx,
+                0.,
+            ],
+            [
+                2. * (x * y - z * w) * sy,
+                (1. - 2. * (x * x + z * z)) * sy,
+                2. * (y * z + x * w) * sy,
+                0.,
+            ],
+            [
+                2. * (x * z + y * w) * sz,
+                2. * (y * z - x * w) * sz,
+                (1. - 2. * (x * x + y * y)) * sz,
+                0.,
+            ],
+            [tx, ty, tz, 1.],
+        ]
+    }
+
+    // `weight` of the way from `self` to `other`, the rotations along the shorter arc
+    fn blend(&self, other: &Self, weight: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * weight;
+        NodeTransform {
+            translation: [0, 1, 2].map(|i| lerp(self.translation[i], other.translation[i])),
+            rotation: nlerp(self.rotation, other.rotation, weight),
+            scale: [0, 1, 2].map(|i| lerp(self.scale[i], other.scale[i])),
+        }
+    }
+}
+
+// a normalized linear blend of two quaternions, close enough to a slerp between the nearby
+// rotations of keyframes and of blended poses
+fn nlerp(a: [f32; 4], b: [f32; 4], weight: f32) -> [f32; 4] {
+    let dot: f32 = (0..4).map(|i| a[i] * b[i]).sum();
+    let sign = if dot < 0. { -1. } else { 1. };
+    normalize_quaternion([0, 1, 2, 3].map(|i| a[i] + (b[i] * sign - a[i]) * weight))
+}
+
+fn normalize_quaternion(quaternion: [f32; 4]) -> [f32; 4] {
+    let length = quaternion
+        .iter()
+        .map(|value| value * value)
+        .sum::<f32>()
+        .sqrt();
+    quaternion.map(|value| value / length.max(f32::EPSILON))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+// the keyframes of one property of one node
+struct Channel {
+    node: usize,
+    property: Property,
+    interpolation: gltf::animation::Interpolation,
+    times: Vec<f32>,
+    // the 3 component properties leave w at 0. cubic splines have an in tangent, a value and
+    // an out tangent per keyframe
+    values: Vec<[f32; 4]>,
+}
+
+impl Channel {
+    // the value at `time`, held before the first and after the last keyframe
+    fn sample(&self, time: f32) -> [f32; 4] {
+        use gltf::animation::Interpolation;
+        let cubic = self.interpolation == Interpolation::CubicSpline;
+        let value = |keyframe: usize| {
+            if cubic {
+                self.values[keyframe * 3 + 1]
+            } else {
+                self.values[keyframe]
+            }
+        };
+        let next = self.times.partition_point(|&keyframe| keyframe <= time);
+        if next == 0 {
+            return value(0);
+        }
+        if next == self.times.len() {
+            return value(next - 1);
+        }
+        let previous = next - 1;
+        let interval = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / interval.max(f32::EPSILON);
+        let blended = match self.interpolation {
+            Interpolation::Step => value(previous),
+            Interpolation::Linear => {
+                if self.property == Property::Rotation {
+                    return nlerp(value(previous), value(next), t);
+                }
+                let [a, b] = [value(previous), value(next)];
+                [0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t)
+            }
+            // a hermite spline through the values with the tangents scaled to the interval
+            Interpolation::CubicSpline => {
+                let [a, b] = [value(previous), value(next)];
+                let out_tangent = self.values[previous * 3 + 2];
+                let in_tangent = self.values[next * 3];
+                let [t2, t3] = [t * t, t * t * t];
+                [0, 1, 2, 3].map(|i| {
+                    (2. * t3 - 3. * t2 + 1.) * a[i]
+                        + (t3 - 2. * t2 + t) * interval * out_tangent[i]
+                        + (-2. * t3 + 3. * t2) * b[i]
+                        + (t3 - t2) * interval * in_tangent[i]
+                })
+            }
+        };
+        if self.property == Property::Rotation {
+            normalize_quaternion(blended)
+        } else {
+            blended
+        }
+    }
+}
+
+// an animation of a glTF scene, the keyframes of the transforms of its nodes
+pub struct AnimationClip {
+    name: String,
+    duration: f32,
+    channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    // reads the translation, rotation and scale channels of `animation`, the morph target
+    // weights are left out
+    pub(crate) fn read(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> Self {
+        use gltf::animation::util::ReadOutputs;
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let (Some(times), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+                continue;
+            };
+            let vector = |[x, y, z]: [f32; 3]| [x, y, z, 0.];
+            let (property, values): (Property, Vec<[f32; 4]>) = match outputs {
+                ReadOutputs::Translations(values) => {
+                    (Property::Translation, values.map(vector).collect())
+                }
+                ReadOutputs::Rotations(values) => (Property::Rotation, values.into_f32().collect()),
+                ReadOutputs::Scales(values) => (Property::Scale, values.map(vector).collect()),
+                ReadOutputs::MorphTargetWeights(_) => continue,
+            };
+            let times: Vec<f32> = times.collect();
+            let interpolation = channel.sampler().interpolation();
+            let keyframe_values = match interpolation {
+                gltf::animation::Interpolation::CubicSpline => 3,
+                _ => 1,
+            };
+            if times.is_empty() || values.len() < times.len() * keyframe_values {
+                continue;
+            }
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                property,
+                interpolation,
+                times,
+                values,
+            });
+        }
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last())
+            .fold(0., |duration: f32, &time| duration.max(time));
+        AnimationClip {
+            name: animation.name().unwrap_or_default().to_owned(),
+            duration,
+            channels,
+        }
+    }
+
+    // the name in the file, empty when it has none
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // in seconds, to the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    // overrides the properties of the nodes of `pose` the clip animates with their values at
+    // `time`
+    fn apply(&self, time: f32, pose: &mut [NodeTransform]) {
+        for channel in &self.channels {
+            let Some(transform) = pose.get_mut(channel.node) else {
+                continue;
+            };
+            let [x, y, z, w] = channel.sample(time);
+            match channel.property {
+                Property::Translation => transform.translation = [x, y, z],
+                Property::Rotation => transform.rotation = [x, y, z, w],
+                Property::Scale => transform.scale = [x, y, z],
+            }
+        }
+    }
+}
+
+// the joints of a skin and the inverse of their world transforms in the pose the mesh was
+// bound in
+pub(crate) struct Skin {
+    pub(crate) joints: Vec<usize>,
+    pub(crate) inverse_bind_matrices: Vec<Matrix>,
+}
+
+impl Skin {
+    pub(crate) fn read(skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Self {
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+            Some(matrices) => matrices.collect(),
+            None => vec![IDENTITY; joints.len()],
+        };
+        Skin {
+            joints,
+            inverse_bind_matrices,
+        }
+    }
+}
+
+// the node hierarchy of a scene, indexed like the nodes of the file
+pub(crate) struct Skeleton {
+    pub(crate) rest_pose: Vec<NodeTransform>,
+    pub(crate) parents: Vec<Option<usize>>,
+    pub(crate) skins: Vec<Skin>,
+    // applied over the root nodes, the scene is fitted into the view with it
+    pub(crate) root: Matrix,
+}
+
+impl Skeleton {
+    // the world transforms of the nodes in `pose`
+    pub(crate) fn world_transforms(&self, pose: &[NodeTransform]) -> Vec<Matrix> {
+        fn resolve(
+            node: usize,
+            skeleton: &Skeleton,
+            pose: &[NodeTransform],
+            world: &mut [Option<Matrix>],
+        ) -> Matrix {
+            if let Some(transform) = world[node] {
+                return transform;
+            }
+            let parent = match skeleton.parents[node] {
+                Some(parent) => resolve(parent, skeleton, pose, world),
+                None => skeleton.root,
+            };
+            let transform = multiply(&parent, &pose[node].matrix());
+            world[node] = Some(transform);
+            transform
+        }
+        let mut world = vec![None; pose.len()];
+        (0..pose.len())
+            .map(|node| resolve(node, self, pose, &mut world))
+            .collect()
+    }
+
+    // the matrices moving the vertices of each skin from the pose they were bound in to `pose`
+    pub(crate) fn joint_matrices(&self, pose: &[NodeTransform]) -> Vec<Vec<Matrix>> {
+        let world = self.world_transforms(pose);
+        self.skins
+            .iter()
+            .map(|skin| {
+                skin.joints
+                    .iter()
+                    .zip(&skin.inverse_bind_matrices)
+                    .map(|(&joint, inverse_bind_matrix)| {
+                        multiply(&world[joint], inverse_bind_matrix)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+// a clip and how far it played
+#[derive(Copy, Clone, Debug)]
+struct ClipTime {
+    clip: usize,
+    time: f32,
+}
+
+// the clips playing in a scene, a second one while they're blended
+#[derive(Default)]
+pub(crate) struct AnimationPlayer {
+    current: Option<ClipTime>,
+    // the clip faded out by a blend, with the seconds the blend ran and lasts
+    previous: Option<(ClipTime, f32, f32)>,
+    paused: bool,
+}
+
+impl AnimationPlayer {
+    pub(crate) fn play(&mut self, clip: usize) {
+        if self.current.map(|current| current.clip) != Some(clip) {
+            self.current = Some(ClipTime { clip, time: 0. });
+            self.previous = None;
+        }
+        self.paused = false;
+    }
+
+    pub(crate) fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub(crate) fn is_playing(&self) -> bool {
+        self.current.is_some() && !self.paused
+    }
+
+    pub(crate) fn current_clip(&self) -> Option<usize> {
+        self.current.map(|current| current.clip)
+    }
+
+    pub(crate) fn blend(&mut self, clip: usize, seconds: f32) {
+        let Some(current) = self.current else {
+            self.play(clip);
+            return;
+        };
+        if current.clip == clip {
+            return;
+        }
+        self.previous = Some((current, 0., seconds));
+        self.current = Some(ClipTime { clip, time: 0. });
+        self.paused = false;
+    }
+
+    // moves the clips `seconds` ahead, looping them
+    pub(crate) fn advance(&mut self, seconds: f32, clips: &[AnimationClip]) {
+        if self.paused {
+            return;
+        }
+        let advance = |clip_time: &mut ClipTime| {
+            let duration = clips[clip_time.clip].duration;
+            clip_time.time = if duration > 0. {
+                (clip_time.time + seconds) % duration
+            } else {
+                0.
+            };
+        };
+        if let Some(current) = &mut self.current {
+            advance(current);
+        }
+        if let Some((previous, elapsed, duration)) = &mut self.previous {
+            advance(previous);
+            *elapsed += seconds;
+            if *elapsed >= *duration {
+                self.previous = None;
+            }
+        }
+    }
+
+    // the transforms of the nodes with the clips applied over the rest pose
+    pub(crate) fn pose(
+        &self,
+        clips: &[AnimationClip],
+        rest_pose: &[NodeTransform],
+    ) -> Vec<NodeTransform> {
+        let pose_at = |clip_time: ClipTime| {
+            let mut pose = rest_pose.to_vec();
+            clips[clip_time.clip].apply(clip_time.time, &mut pose);
+            pose
+        };
+        let Some(current) = self.current else {
+            return rest_pose.to_vec();
+        };
+        let pose = pose_at(current);
+        match self.previous {
+            Some((previous, elapsed, duration)) => {
+                let weight = (elapsed / duration.max(f32::EPSILON)).min(1.);
+                pose_at(previous)
+                    .iter()
+                    .zip(&pose)
+                    .map(|(from, to)| from.blend(to, weight))
+                    .collect()
+            }
+            None => pose,
+        }
+    }
+}
@@ -0,0 +1,433 @@
+// meshes, textures and shader libraries loaded from files in the background. `Assets::load`
+// hands out a handle right away while a loader thread reads and decodes the file, and
+// `Assets::update` creates the resource on the device once the data is in, so the frames keep
+// drawing without it meanwhile. the handles are counted, an asset is dropped with its last
+// handle, and watched files are loaded again when they change, swapping the resource in place
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use image::{ImageError, RgbaImage};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{MTLLibrary, MTLTexture};
+
+use crate::{
+    mesh::read_obj, Compilation, CompilationError, LogLevel, Mesh, MeshError, MetalRenderer,
+    TextureError, VertexInput,
+};
+
+// the threads reading and decoding the files of an `Assets`
+const LOADER_THREADS: usize = 2;
+
+#[derive(Debug)]
+pub enum AssetError {
+    Io(io::Error),
+    Texture(TextureError),
+    Mesh(MeshError),
+    Shader(CompilationError),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::Io(error) => write!(f, "Failed to read the asset: {error}"),
+            AssetError::Texture(error) => error.fmt(f),
+            AssetError::Mesh(error) => error.fmt(f),
+            AssetError::Shader(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+impl From<io::Error> for AssetError {
+    fn from(error: io::Error) -> Self {
+        AssetError::Io(error)
+    }
+}
+
+impl From<TextureError> for AssetError {
+    fn from(error: TextureError) -> Self {
+        AssetError::Texture(error)
+    }
+}
+
+impl From<ImageError> for AssetError {
+    fn from(error: ImageError) -> Self {
+        AssetError::Texture(TextureError::Decode(error))
+    }
+}
+
+impl From<MeshError> for AssetError {
+    fn from(error: MeshError) -> Self {
+        AssetError::Mesh(error)
+    }
+}
+
+// the resource made of the data of an asset on the main thread, right away or by metal. a
+// copying resource is ready once the upload queue of the renderer completed the value
+pub enum Upload<T> {
+    Done(Result<T, AssetError>),
+    Compiling(Compilation<T>),
+    Copying(T, u64),
+}
+
+// a resource `Assets` loads from files. `decode` runs on a loader thread, it reads and decodes
+// the file into data that can be sent between threads, which the metal objects can't, and
+// `upload` creates the resource from it on the main thread
+pub trait Asset: Sized + 'static {
+    type Data: Send + 'static;
+
+    fn decode(path: &Path) -> Result<Self::Data, AssetError>;
+
+    fn upload(renderer: &MetalRenderer, data: Self::Data) -> Upload<Self>;
+}
+
+// a Wavefront OBJ file, like `load_mesh`
+impl Asset for Mesh {
+    type Data = (Vec<VertexInput>, Vec<u32>);
+
+    fn decode(path: &Path) -> Result<Self::Data, AssetError> {
+        Ok(read_obj(path)?)
+    }
+
+    fn upload(renderer: &MetalRenderer, (vertices, indices): Self::Data) -> Upload<Self> {
+        Upload::Done(Ok(renderer.create_mesh(&vertices, &indices)))
+    }
+}
+
+// a PNG or JPEG image, like `load_texture` but copied into gpu memory on the upload queue
+impl Asset for Retained<ProtocolObject<dyn MTLTexture>> {
+    type Data = RgbaImage;
+
+    fn decode(path: &Path) -> Result<Self::Data, AssetError> {
+        Ok(image::open(path)?.into_rgba8())
+    }
+
+    fn upload(renderer: &MetalRenderer, image: Self::Data) -> Upload<Self> {
+        match renderer.upload_texture(&renderer.upload_queue(), &image) {
+            Ok((texture, value)) => Upload::Copying(texture, value),
+            Err(error) => Upload::Done(Err(error.into())),
+        }
+    }
+}
+
+// the source of a shader library, compiled on a metal thread with the shader options of the
+// renderer. it doesn't replace the library of the renderer, see `load_shader_file` for that
+impl Asset for Retained<ProtocolObject<dyn MTLLibrary>> {
+    type Data = String;
+
+    fn decode(path: &Path) -> Result<Self::Data, AssetError> {
+        Ok(fs::read_to_string(path)?)
+    }
+
+    fn upload(renderer: &MetalRenderer, source: Self::Data) -> Upload<Self> {
+        let shader_options = renderer.ivars().shader_options.borrow().clone();
+        Upload::Compiling(renderer.compile_library_async(&source, &shader_options))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AssetState {
+    Loading,
+    Loaded,
+    // the last load failed, a loaded resource stays until one succeeds
+    Failed,
+}
+
+struct Slot<T> {
+    path: PathBuf,
+    resource: RefCell<Option<Rc<T>>>,
+    state: Cell<AssetState>,
+}
+
+// an asset of `Assets`, kept loaded as long as a clone of it is around
+pub struct Handle<T> {
+    slot: Rc<Slot<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Handle {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.slot, &other.slot)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("path", &self.slot.path)
+            .field("state", &self.slot.state.get())
+            .finish()
+    }
+}
+
+impl<T> Handle<T> {
+    // the resource, none until it's loaded. a hot reload swaps it, the draws should get it
+    // from the handle every frame rather than holding on to it
+    pub fn get(&self) -> Option<Rc<T>> {
+        self.slot.resource.borrow().clone()
+    }
+
+    pub fn state(&self) -> AssetState {
+        self.slot.state.get()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.slot.path
+    }
+}
+
+// the data a loader thread decoded from the file of a path
+type Decoded<T> = (PathBuf, Result<<T as Asset>::Data, AssetError>);
+
+// watches the directories of the files of `Assets::watch`
+struct AssetWatcher {
+    watcher: RecommendedWatcher,
+    directories: HashSet<PathBuf>,
+    // set by the watcher thread
+    changed: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+pub type LoadHook<T> = Box<dyn Fn(&MetalRenderer, &Handle<T>)>;
+
+// the assets of one type, loaded by threads of their own. `update` has to be called every
+// frame, e.g. from the update callback, to create the resources of the files loaded since
+pub struct Assets<T: Asset> {
+    slots: HashMap<PathBuf, Weak<Slot<T>>>,
+    // dropping it stops the loader threads
+    jobs: Sender<PathBuf>,
+    decoded: Receiver<Decoded<T>>,
+    compiling: Vec<(Weak<Slot<T>>, Compilation<T>)>,
+    copying: Vec<(Weak<Slot<T>>, T, u64)>,
+    watcher: Option<AssetWatcher>,
+    load_hooks: Vec<LoadHook<T>>,
+}
+
+impl<T: Asset> Default for Assets<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Asset> Assets<T> {
+    pub fn new() -> Self {
+        let (jobs, queue) = mpsc::channel::<PathBuf>();
+        let (sender, decoded) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..LOADER_THREADS {
+            let queue = queue.clone();
+            let sender: Sender<Decoded<T>> = sender.clone();
+            thread::spawn(move || loop {
+                // the lock is released before decoding, so the threads decode side by side
+                let Ok(path) = queue.lock().unwrap().recv() else {
+                    return;
+                };
+                let data = T::decode(&path);
+                if sender.send((path, data)).is_err() {
+                    return;
+                }
+            });
+        }
+        Assets {
+            slots: HashMap::new(),
+            jobs,
+            decoded,
+            compiling: Vec::new(),
+            copying: Vec::new(),
+            watcher: None,
+            load_hooks: Vec::new(),
+        }
+    }
+
+    // the handle of the file at `path`, loading in the background. the assets still held are
+    // shared, loading the same file again returns the handle it already has
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Handle<T> {
+        let path = fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().to_owned());
+        if let Some(slot) = self.slots.get(&path).and_then(Weak::upgrade) {
+            return Handle { slot };
+        }
+        let slot = Rc::new(Slot {
+            path: path.clone(),
+            resource: RefCell::new(None),
+            state: Cell::new(AssetState::Loading),
+        });
+        self.slots.insert(path.clone(), Rc::downgrade(&slot));
+        self.watch_directory(&path);
+        self.queue(path);
+        Handle { slot }
+    }
+
+    // the number of assets with handles left
+    pub fn len(&self) -> usize {
+        self.slots
+            .values()
+            .filter(|slot| slot.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // loads the files of the assets again when they change on disk, and the ones loaded
+    // later. the resources are swapped once the new ones are created, a file failing to load
+    // keeps the one before
+    pub fn watch(&mut self) -> notify::Result<()> {
+        let changed = Arc::new(Mutex::new(HashSet::new()));
+        let watcher = notify::recommended_watcher({
+            let changed = changed.clone();
+            move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if event.kind.is_create() || event.kind.is_modify() {
+                    changed.lock().unwrap().extend(event.paths);
+                }
+            }
+        })?;
+        self.watcher = Some(AssetWatcher {
+            watcher,
+            directories: HashSet::new(),
+            changed,
+        });
+        let paths: Vec<PathBuf> = self.slots.keys().cloned().collect();
+        for path in paths {
+            self.watch_directory(&path);
+        }
+        Ok(())
+    }
+
+    // called from `update` with the handle of an asset each time its resource is swapped in,
+    // once it first loaded and after every reload, e.g. to rebuild what was made from it
+    pub fn on_load(&mut self, load_hook: impl Fn(&MetalRenderer, &Handle<T>) + 'static) {
+        self.load_hooks.push(Box::new(load_hook));
+    }
+
+    // creates the resources of the files decoded since the last call, of the shaders that
+    // finished compiling and of the textures that finished copying, queues the changed files
+    // of watched assets and forgets the ones without handles
+    pub fn update(&mut self, renderer: &MetalRenderer) {
+        self.slots.retain(|_, slot| slot.strong_count() > 0);
+        while let Ok((path, data)) = self.decoded.try_recv() {
+            let Some(slot) = self.slots.get(&path).and_then(Weak::upgrade) else {
+                continue;
+            };
+            match data.map(|data| T::upload(renderer, data)) {
+                Ok(Upload::Done(result)) => self.finish(renderer, &slot, result),
+                Ok(Upload::Compiling(compilation)) => {
+                    self.compiling.push((Rc::downgrade(&slot), compilation));
+                }
+                Ok(Upload::Copying(resource, value)) => {
+                    self.copying.push((Rc::downgrade(&slot), resource, value));
+                }
+                Err(error) => self.finish(renderer, &slot, Err(error)),
+            }
+        }
+
+        let mut compiling = std::mem::take(&mut self.compiling);
+        compiling.retain(|(slot, compilation)| {
+            let Some(slot) = slot.upgrade() else {
+                return false;
+            };
+            let Some(result) = compilation.try_take() else {
+                return true;
+            };
+            self.finish(renderer, &slot, result.map_err(AssetError::Shader));
+            false
+        });
+        self.compiling.extend(compiling);
+
+        if !self.copying.is_empty() {
+            let upload_queue = renderer.upload_queue();
+            for (slot, resource, value) in std::mem::take(&mut self.copying) {
+                let Some(live) = slot.upgrade() else {
+                    continue;
+                };
+                if upload_queue.is_complete(value) {
+                    self.finish(renderer, &live, Ok(resource));
+                } else {
+                    self.copying.push((slot, resource, value));
+                }
+            }
+        }
+
+        let changed = match &self.watcher {
+            Some(watcher) => std::mem::take(&mut *watcher.changed.lock().unwrap()),
+            None => HashSet::new(),
+        };
+        for path in changed {
+            if self.slots.contains_key(&path) {
+                let message = format!("{} changed, reloading it.", path.display());
+                renderer.log(LogLevel::Info, &message);
+                self.queue(path);
+            }
+        }
+    }
+
+    fn queue(&self, path: PathBuf) {
+        // the loader threads only stop with the sender
+        self.jobs
+            .send(path)
+            .expect("Failed to queue an asset, the loader threads stopped.");
+    }
+
+    fn watch_directory(&mut self, path: &Path) {
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        // editors often save by replacing the file, so the directory is watched instead
+        let directory = path.parent().unwrap_or(path).to_owned();
+        if watcher.directories.contains(&directory) {
+            return;
+        }
+        if watcher
+            .watcher
+            .watch(&directory, RecursiveMode::NonRecursive)
+            .is_ok()
+        {
+            watcher.directories.insert(directory);
+        }
+    }
+
+    // swaps in the resource of a load, a failed one keeps the resource the asset has
+    fn finish(&self, renderer: &MetalRenderer, slot: &Rc<Slot<T>>, result: Result<T, AssetError>) {
+        let resource = match result {
+            Ok(resource) => resource,
+            Err(error) => {
+                let message = format!("Failed to load {}: {error}.", slot.path.display());
+                renderer.log(LogLevel::Warn, &message);
+                slot.state.set(AssetState::Failed);
+                return;
+            }
+        };
+        slot.resource.replace(Some(Rc::new(resource)));
+        slot.state.set(AssetState::Loaded);
+        renderer.log(LogLevel::Debug, &format!("Loaded {}.", slot.path.display()));
+        // a view drawing on demand draws the asset
+        renderer.set_needs_redraw();
+        let handle = Handle { slot: slot.clone() };
+        for load_hook in &self.load_hooks {
+            load_hook(renderer, &handle);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// what a render pass does with the contents of its attachments at its start and its end, in
+// place of the clears and stores of the view or the render target
+use objc2::rc::Retained;
+use objc2_foundation::NSCopying;
+use objc2_metal::{
+    MTLClearColor, MTLLoadAction, MTLRenderPassAttachmentDescriptor, MTLRenderPassDescriptor,
+    MTLStoreAction,
+};
+
+use crate::RenderPass;
+
+// what an attachment holds at the start of a pass
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoadAction {
+    // the clear color or the clear depth
+    Clear,
+    // what it held at the end of the last pass into it
+    Load,
+    // anything, for passes drawing over every pixel anyway
+    DontCare,
+}
+
+impl LoadAction {
+    fn mtl_load_action(self) -> MTLLoadAction {
+        match self {
+            LoadAction::Clear => MTLLoadAction::Clear,
+            LoadAction::Load => MTLLoadAction::Load,
+            LoadAction::DontCare => MTLLoadAction::DontCare,
+        }
+    }
+}
+
+// whether an attachment keeps what the pass drew into it, multisampled color is resolved
+// either way
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StoreAction {
+    Store,
+    DontCare,
+}
+
+impl StoreAction {
+    fn mtl_store_action(self, resolved: bool) -> MTLStoreAction {
+        match (self, resolved) {
+            (StoreAction::Store, false) => MTLStoreAction::Store,
+            (StoreAction::Store, true) => MTLStoreAction::StoreAndMultisampleResolve,
+            (StoreAction::DontCare, false) => MTLStoreAction::DontCare,
+            (StoreAction::DontCare, true) => MTLStoreAction::MultisampleResolve,
+        }
+    }
+}
+
+// the actions and clear values a pass sets, the ones left out keep those of its target
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct AttachmentActions {
+    color_load: Option<LoadAction>,
+    color_store: Option<StoreAction>,
+    clear_color: Option<MTLClearColor>,
+    depth_load: Option<LoadAction>,
+    depth_store: Option<StoreAction>,
+    clear_depth: Option<f64>,
+}
+
+impl AttachmentActions {
+    // a copy of `pass_descriptor` with the actions set, none when there are none to set
+    pub(crate) fn pass_descriptor(
+        &self,
+        pass_descriptor: &MTLRenderPassDescriptor,
+    ) -> Option<Retained<MTLRenderPassDescriptor>> {
+        if self.color_load.is_none()
+            && self.color_store.is_none()
+            && self.clear_color.is_none()
+            && self.depth_load.is_none()
+            && self.depth_store.is_none()
+            && self.clear_depth.is_none()
+        {
+            return None;
+        }
+        let descriptor = pass_descriptor.copy();
+        let color_attachment = unsafe { descriptor.colorAttachments().objectAtIndexedSubscript(0) };
+        set_actions(&color_attachment, self.color_load, self.color_store);
+        if let Some(clear_color) = self.clear_color {
+            color_attachment.setClearColor(clear_color);
+        }
+        let depth_attachment = descriptor.depthAttachment();
+        set_actions(&depth_attachment, self.depth_load, self.depth_store);
+        if let Some(clear_depth) = self.clear_depth {
+            depth_attachment.setClearDepth(clear_depth);
+        }
+        // the stencil lives in the depth texture of the formats having one
+        let stencil_attachment = descriptor.stencilAttachment();
+        if stencil_attachment.texture().is_some() {
+            set_actions(&stencil_attachment, self.depth_load, self.depth_store);
+        }
+        Some(descriptor)
+    }
+}
+
+fn set_actions(
+    attachment: &MTLRenderPassAttachmentDescriptor,
+    load_action: Option<LoadAction>,
+    store_action: Option<StoreAction>,
+) {
+    if let Some(load_action) = load_action {
+        attachment.setLoadAction(load_action.mtl_load_action());
+    }
+    if let Some(store_action) = store_action {
+        let resolved = attachment.resolveTexture().is_some();
+        attachment.setStoreAction(store_action.mtl_store_action(resolved));
+    }
+}
+
+impl RenderPass {
+    // clears the color of the pass to `clear_color` instead of the clear color of the renderer
+    // or of the target
+    pub fn with_clear_color(&mut self, clear_color: MTLClearColor) -> &mut Self {
+        self.attachment_actions.clear_color = Some(clear_color);
+        self
+    }
+
+    // clears the depth of the pass to `clear_depth` instead of the far plane at 1, e.g. to 0
+    // for a reversed depth
+    pub fn with_clear_depth(&mut self, clear_depth: f64) -> &mut Self {
+        self.attachment_actions.clear_depth = Some(clear_depth);
+        self
+    }
+
+    // what the color attachment holds at the start of the pass. loading a drawable brings back
+    // the frame last drawn into that drawable, a few frames old as the layer cycles through
+    // them, while a multisampled view loads the samples it stored in the last frame.
+    // memoryless attachments can only be cleared or left as they are
+    pub fn with_color_load_action(&mut self, load_action: LoadAction) -> &mut Self {
+        self.attachment_actions.color_load = Some(load_action);
+        self
+    }
+
+    // whether the color attachment keeps what the pass drew, e.g. the samples of a
+    // multisampled view for the next frame to load
+    pub fn with_color_store_action(&mut self, store_action: StoreAction) -> &mut Self {
+        self.attachment_actions.color_store = Some(store_action);
+        self
+    }
+
+    // what the depth and the stencil attachments hold at the start of the pass, passes
+    // without them ignore it
+    pub fn with_depth_load_action(&mut self, load_action: LoadAction) -> &mut Self {
+        self.attachment_actions.depth_load = Some(load_action);
+        self
+    }
+
+    pub fn with_depth_store_action(&mut self, store_action: StoreAction) -> &mut Self {
+        self.attachment_actions.depth_store = Some(store_action);
+        self
+    }
+}
@@ -0,0 +1,247 @@
+// the sound of the default input device, captured with cpal and split into frequency bands
+// every frame. the shaders get the levels of the bands in the scene properties, which makes
+// visuals reacting to the music playing or the microphone a matter of a few lines of shader
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BuildStreamError, DefaultStreamConfigError, Device, FromSample, PlayStreamError, SampleFormat,
+    SizedSample, Stream, StreamConfig, StreamError,
+};
+use objc2::DeclaredClass;
+
+use crate::{LogLevel, MetalRenderer, AUDIO_BANDS};
+
+// the samples the spectrum of a frame is taken of, the latest 1024 are about 20ms at 48kHz
+const FFT_SIZE: usize = 1024;
+
+// the bands are spaced evenly in octaves between these, in Hz
+const LOWEST_FREQUENCY: f32 = 40.;
+const HIGHEST_FREQUENCY: f32 = 16000.;
+
+// the decibels below full scale a band goes from 1 down to 0 over
+const DYNAMIC_RANGE: f32 = 60.;
+
+// a band rises to a louder level at once and falls back by this rate per second, so the levels
+// don't flicker with every frame
+const FALL_RATE: f32 = 6.;
+
+#[derive(Debug)]
+pub enum AudioError {
+    NoInputDevice,
+    Config(DefaultStreamConfigError),
+    UnsupportedFormat(SampleFormat),
+    Build(BuildStreamError),
+    Play(PlayStreamError),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::NoInputDevice => write!(f, "There's no audio input device"),
+            AudioError::Config(error) => {
+                write!(f, "Failed to configure the audio input: {error}")
+            }
+            AudioError::UnsupportedFormat(format) => {
+                write!(f, "Unsupported audio sample format: {format}")
+            }
+            AudioError::Build(error) => write!(f, "Failed to open the audio input: {error}"),
+            AudioError::Play(error) => write!(f, "Failed to start the audio input: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+// a capture in progress
+pub(crate) struct AudioCapture {
+    // capturing until it's dropped
+    _stream: Stream,
+    sample_rate: f32,
+    // the latest samples, mixed down to mono, written by the thread of the stream
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    // the first error of the stream, reported with the next frame
+    error: Arc<Mutex<Option<StreamError>>>,
+    bands: [f32; AUDIO_BANDS],
+    last_analysis: Option<Instant>,
+}
+
+// the input stream of `device` in samples of `T`, converted to f32 and mixed down
+fn build_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    samples: &Arc<Mutex<VecDeque<f32>>>,
+    error: &Arc<Mutex<Option<StreamError>>>,
+) -> Result<Stream, BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels.max(1) as usize;
+    let (samples, error) = (samples.clone(), error.clone());
+    device.build_input_stream(
+        config,
+        move |data: &[T], _| {
+            let mut samples = samples.lock().unwrap();
+            for frame in data.chunks(channels) {
+                let sum: f32 = frame.iter().map(|sample| sample.to_sample::<f32>()).sum();
+                samples.push_back(sum / frame.len() as f32);
+            }
+            let excess = samples.len().saturating_sub(FFT_SIZE);
+            samples.drain(..excess);
+        },
+        move |stream_error| {
+            error.lock().unwrap().get_or_insert(stream_error);
+        },
+        None,
+    )
+}
+
+// the magnitudes of the lower half of the spectrum of `samples`, whose length is a power of two,
+// by an iterative radix-2 fft
+fn spectrum(samples: &[f32]) -> Vec<f32> {
+    let n = samples.len();
+    let bits = n.trailing_zeros();
+    // the butterflies take the samples in bit reversed order
+    let mut real: Vec<f32> = (0..n)
+        .map(|i| samples[i.reverse_bits() >> (usize::BITS - bits)])
+        .collect();
+    let mut imaginary = vec![0.; n];
+    let mut size = 2;
+    while size <= n {
+        let angle = -2. * PI / size as f32;
+        for start in (0..n).step_by(size) {
+            for k in 0..size / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + size / 2);
+                let twiddled_real = real[b] * cos - imaginary[b] * sin;
+                let twiddled_imaginary = real[b] * sin + imaginary[b] * cos;
+                real[b] = real[a] - twiddled_real;
+                imaginary[b] = imaginary[a] - twiddled_imaginary;
+                real[a] += twiddled_real;
+                imaginary[a] += twiddled_imaginary;
+            }
+        }
+        size *= 2;
+    }
+    (0..n / 2).map(|i| real[i].hypot(imaginary[i])).collect()
+}
+
+impl AudioCapture {
+    // the levels of the bands in the latest samples, from 0 for silence to 1 for full scale
+    fn levels(&self) -> [f32; AUDIO_BANDS] {
+        let mut samples: Vec<f32> = self.samples.lock().unwrap().iter().copied().collect();
+        // the first frames come before the stream filled the window
+        samples.resize(FFT_SIZE, 0.);
+        // a hann window keeps the edges of the window from smearing the spectrum
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample *= 0.5 - 0.5 * (2. * PI * i as f32 / FFT_SIZE as f32).cos();
+        }
+        let magnitudes = spectrum(&samples);
+
+        let highest = HIGHEST_FREQUENCY.min(self.sample_rate / 2.);
+        let ratio = (highest / LOWEST_FREQUENCY).powf(1. / AUDIO_BANDS as f32);
+        let bin = |frequency: f32| (frequency * FFT_SIZE as f32 / self.sample_rate) as usize;
+        std::array::from_fn(|band| {
+            let low = LOWEST_FREQUENCY * ratio.powi(band as i32);
+            let start = bin(low).clamp(1, magnitudes.len() - 1);
+            let end = bin(low * ratio).clamp(start + 1, magnitudes.len());
+            let peak = magnitudes[start..end]
+                .iter()
+                .fold(0f32, |peak, magnitude| peak.max(*magnitude));
+            // a full scale sine peaks at a quarter of the window through the hann window
+            let amplitude = peak * 4. / FFT_SIZE as f32;
+            let decibels = 20. * amplitude.max(f32::MIN_POSITIVE).log10();
+            ((decibels + DYNAMIC_RANGE) / DYNAMIC_RANGE).clamp(0., 1.)
+        })
+    }
+}
+
+impl MetalRenderer {
+    // captures the default input device, like a microphone or a loopback device playing music,
+    // and hands the levels of its frequency bands to the shaders from the next frame on. the
+    // bands go from the lowest to the highest frequencies in `audio_bands` of the scene
+    // properties, spaced evenly in octaves from 40Hz to 16kHz. macOS asks the user for the
+    // access to the microphone the first time, a bundled application needs an
+    // `NSMicrophoneUsageDescription` in its Info.plist for it
+    pub fn start_audio_capture(&self) -> Result<(), AudioError> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .ok_or(AudioError::NoInputDevice)?;
+        let config = device.default_input_config().map_err(AudioError::Config)?;
+        let samples = Arc::new(Mutex::new(VecDeque::with_capacity(FFT_SIZE)));
+        let error = Arc::new(Mutex::new(None));
+        let (sample_format, config) = (config.sample_format(), config.config());
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, &samples, &error),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, &samples, &error),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, &samples, &error),
+            SampleFormat::I32 => build_stream::<i32>(&device, &config, &samples, &error),
+            sample_format => return Err(AudioError::UnsupportedFormat(sample_format)),
+        }
+        .map_err(AudioError::Build)?;
+        stream.play().map_err(AudioError::Play)?;
+        self.ivars().audio.replace(Some(AudioCapture {
+            _stream: stream,
+            sample_rate: config.sample_rate.0 as f32,
+            samples,
+            error,
+            bands: [0.; AUDIO_BANDS],
+            last_analysis: None,
+        }));
+        Ok(())
+    }
+
+    // stops the capture, the bands are silent from the next frame on
+    pub fn stop_audio_capture(&self) {
+        self.ivars().audio.take();
+    }
+
+    pub fn is_capturing_audio(&self) -> bool {
+        self.ivars().audio.borrow().is_some()
+    }
+
+    // the levels of the bands as of the latest frame, all 0 without a capture
+    pub fn audio_bands(&self) -> [f32; AUDIO_BANDS] {
+        let audio = self.ivars().audio.borrow();
+        audio
+            .as_ref()
+            .map_or([0.; AUDIO_BANDS], |audio| audio.bands)
+    }
+
+    // the levels of the bands for the frame being recorded. a stream that failed stops the
+    // capture
+    pub(crate) fn analyze_audio(&self) -> [f32; AUDIO_BANDS] {
+        let mut audio = self.ivars().audio.borrow_mut();
+        let Some(capture) = audio.as_mut() else {
+            return [0.; AUDIO_BANDS];
+        };
+        let error = capture.error.lock().unwrap().take();
+        if let Some(error) = error {
+            drop(audio);
+            self.log(
+                LogLevel::Warn,
+                &format!("The audio capture failed: {error}."),
+            );
+            self.stop_audio_capture();
+            return [0.; AUDIO_BANDS];
+        }
+        let now = Instant::now();
+        let elapsed = capture
+            .last_analysis
+            .replace(now)
+            .map_or(0., |last_analysis| (now - last_analysis).as_secs_f32());
+        let fall = (-FALL_RATE * elapsed).exp();
+        let levels = capture.levels();
+        for (band, level) in capture.bands.iter_mut().zip(levels) {
+            *band = level.max(*band * fall);
+        }
+        capture.bands
+    }
+}
@@ -0,0 +1,166 @@
+use std::ptr::NonNull;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_foundation::NSString;
+use objc2_metal::{
+    MTLArgumentEncoder, MTLBuffer, MTLDevice, MTLFunction, MTLGPUFamily, MTLLibrary,
+    MTLRenderCommandEncoder, MTLRenderStages, MTLResource, MTLResourceID, MTLResourceOptions,
+    MTLResourceUsage, MTLTexture,
+};
+
+use crate::MetalRenderer;
+
+// the lengths of the arrays of `BindlessResources` in triangle.metal, the limits of argument
+// buffers on devices of the first tier
+pub const BINDLESS_TEXTURE_CAPACITY: usize = 128;
+pub const BINDLESS_BUFFER_CAPACITY: usize = 64;
+
+// the vertex and fragment shader argument buffer index of the bindless table
+pub(crate) const BINDLESS_BUFFER_INDEX: usize = 5;
+
+// the index of a texture in `BindlessResources::textures`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub u32);
+
+// the index of a buffer in `BindlessResources::buffers`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(pub u32);
+
+// every texture and buffer the shaders of a draw may pick from by index, in one argument buffer
+// bound to the vertex and the fragment shader at `[[buffer(5)]]`. metal 3 devices take the gpu
+// addresses and resource ids written straight into it, older ones encode them with an argument
+// encoder. metal doesn't see which entries a shader reads, so every resource of the table is
+// made resident for the draws using it
+pub struct BindlessTable {
+    buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    // none when the entries are written directly
+    encoder: Option<Retained<ProtocolObject<dyn MTLArgumentEncoder>>>,
+    textures: Vec<Retained<ProtocolObject<dyn MTLTexture>>>,
+    buffers: Vec<Retained<ProtocolObject<dyn MTLBuffer>>>,
+}
+
+impl BindlessTable {
+    // writes the texture into the next free entry, none when the table is full. draws already
+    // committed may read the table, the added entry is only seen by the ones after it
+    pub fn add_texture(
+        &mut self,
+        texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+    ) -> Option<TextureHandle> {
+        let index = self.textures.len();
+        if index == BINDLESS_TEXTURE_CAPACITY {
+            return None;
+        }
+        match &self.encoder {
+            Some(encoder) => unsafe { encoder.setTexture_atIndex(Some(texture), index) },
+            None => unsafe {
+                let entries = self.buffer.contents().cast::<MTLResourceID>();
+                entries.as_ptr().add(index).write(texture.gpuResourceID());
+            },
+        }
+        self.textures.push(texture.clone());
+        Some(TextureHandle(index as u32))
+    }
+
+    // same as `add_texture` for a buffer, read through a device pointer
+    pub fn add_buffer(
+        &mut self,
+        buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+    ) -> Option<BufferHandle> {
+        let index = self.buffers.len();
+        if index == BINDLESS_BUFFER_CAPACITY {
+            return None;
+        }
+        let id = BINDLESS_TEXTURE_CAPACITY + index;
+        match &self.encoder {
+            Some(encoder) => unsafe { encoder.setBuffer_offset_atIndex(Some(buffer), 0, id) },
+            None => unsafe {
+                let entries = self.buffer.contents().cast::<u64>();
+                entries.as_ptr().add(id).write(buffer.gpuAddress());
+            },
+        }
+        self.buffers.push(buffer.clone());
+        Some(BufferHandle(index as u32))
+    }
+
+    // whether the entries are gpu addresses written without an encoder, on metal 3 devices
+    pub fn is_direct(&self) -> bool {
+        self.encoder.is_none()
+    }
+
+    pub fn texture_count(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    // makes every resource of the table resident with a single call and binds it to both stages
+    pub(crate) fn bind(&self, encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>) {
+        let textures = self.textures.iter().map(|texture| {
+            let resource: &ProtocolObject<dyn MTLResource> = texture.as_ref().as_ref();
+            NonNull::from(resource)
+        });
+        let buffers = self.buffers.iter().map(|buffer| {
+            let resource: &ProtocolObject<dyn MTLResource> = buffer.as_ref().as_ref();
+            NonNull::from(resource)
+        });
+        let mut resources: Vec<_> = textures.chain(buffers).collect();
+        if !resources.is_empty() {
+            unsafe {
+                encoder.useResources_count_usage_stages(
+                    NonNull::new(resources.as_mut_ptr()).unwrap(),
+                    resources.len(),
+                    MTLResourceUsage::Read,
+                    MTLRenderStages::MTLRenderStageVertex | MTLRenderStages::MTLRenderStageFragment,
+                )
+            };
+        }
+        unsafe {
+            encoder.setVertexBuffer_offset_atIndex(Some(&self.buffer), 0, BINDLESS_BUFFER_INDEX);
+            encoder.setFragmentBuffer_offset_atIndex(Some(&self.buffer), 0, BINDLESS_BUFFER_INDEX);
+        }
+    }
+}
+
+impl MetalRenderer {
+    // whether the device takes gpu addresses in argument buffers, bindless tables work on older
+    // devices as well through an argument encoder
+    pub fn supports_metal3(&self) -> bool {
+        self.device().supportsFamily(MTLGPUFamily::Metal3)
+    }
+
+    // creates an empty bindless table, for shaders taking `BindlessResources` at `[[buffer(5)]]`
+    // like `fragment_bindless`
+    pub fn create_bindless_table(&self) -> BindlessTable {
+        let device = self.device();
+        let (length, encoder) = if self.supports_metal3() {
+            // a resource id or an address for every entry, zeroed by metal
+            let length = (BINDLESS_TEXTURE_CAPACITY + BINDLESS_BUFFER_CAPACITY) * 8;
+            (length, None)
+        } else {
+            let function = self
+                .library()
+                .ok()
+                .and_then(|library| {
+                    library.newFunctionWithName(&NSString::from_str("fragment_bindless"))
+                })
+                .expect("Failed to find the bindless table function.");
+            let encoder =
+                unsafe { function.newArgumentEncoderWithBufferIndex(BINDLESS_BUFFER_INDEX) };
+            (encoder.encodedLength(), Some(encoder))
+        };
+        let buffer = device
+            .newBufferWithLength_options(length, MTLResourceOptions::MTLResourceStorageModeShared)
+            .expect("Failed to create a bindless table.");
+        if let Some(encoder) = &encoder {
+            unsafe { encoder.setArgumentBuffer_offset(Some(&buffer), 0) };
+        }
+        BindlessTable {
+            buffer,
+            encoder,
+            textures: Vec::new(),
+            buffers: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::f32::consts::FRAC_PI_2;
+
+// a column major 4x4 matrix, the layout of a metal float4x4
+pub(crate) type Matrix = [[f32; 4]; 4];
+
+pub(crate) const IDENTITY: Matrix = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+// the product of two column major matrices
+pub(crate) fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.; 4]; 4];
+    for (column, b_column) in product.iter_mut().zip(b) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b_column[k]).sum();
+        }
+    }
+    product
+}
+
+// the inverse of a matrix by gauss-jordan elimination, the identity for one that can't be
+// inverted like the projection of a view without size
+pub(crate) fn invert(matrix: &Matrix) -> Matrix {
+    // the columns of the matrix are reduced to the identity, the same operations turn the
+    // identity into the inverse. on the columns rather than the rows, which inverts all the same
+    let mut matrix = *matrix;
+    let mut inverse = IDENTITY;
+    for column in 0..4 {
+        let pivot = (column..4)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))
+            .unwrap_or(column);
+        if matrix[pivot][column].abs() <= f32::EPSILON {
+            return IDENTITY;
+        }
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = 1. / matrix[column][column];
+        for row in 0..4 {
+            matrix[column][row] *= scale;
+            inverse[column][row] *= scale;
+        }
+        for other in (0..4).filter(|&other| other != column) {
+            let factor = matrix[other][column];
+            for row in 0..4 {
+                matrix[other][row] -= factor * matrix[column][row];
+                inverse[other][row] -= factor * inverse[column][row];
+            }
+        }
+    }
+    inverse
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// how the view space is mapped to clip space
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    // a 2d view, the view is `2 / zoom` units high and keeps the depth of the geometry, which
+    // 2d scenes use to layer their shapes
+    Orthographic,
+    // a 3d view, `fov_y` radians high, of what lies between `near` and `far` units of the eye
+    Perspective { fov_y: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    pub const PERSPECTIVE: Projection = Projection::Perspective {
+        fov_y: std::f32::consts::FRAC_PI_3,
+        near: 0.01,
+        far: 100.,
+    };
+}
+
+// a view orbiting `target`, the shaders get its view and projection as a single matrix
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    // the point shown in the middle of the view
+    pub target: [f32; 3],
+    // magnification, values above 1 zoom in. perspective cameras move closer to the target
+    pub zoom: f32,
+    // the rotation about the y axis and the elevation of the eye, in radians. at zero the
+    // camera looks down the negative z axis
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            target: [0., 0., 0.],
+            zoom: 1.,
+            yaw: 0.,
+            pitch: 0.,
+            projection: Projection::Orthographic,
+        }
+    }
+}
+
+impl Camera {
+    // the directions to the right of, above and behind the view
+    fn axes(&self) -> [[f32; 3]; 3] {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        [
+            [cos_yaw, 0., -sin_yaw],
+            [-sin_yaw * sin_pitch, cos_pitch, -cos_yaw * sin_pitch],
+            [sin_yaw * cos_pitch, sin_pitch, cos_yaw * cos_pitch],
+        ]
+    }
+
+    // the target fills the height of a perspective view at zoom 1 like it does orthographically
+    fn eye(&self) -> [f32; 3] {
+        match self.projection {
+            Projection::Orthographic => self.target,
+            Projection::Perspective { fov_y, .. } => {
+                let distance = 1. / (self.zoom * (fov_y / 2.).tan());
+                let back = self.axes()[2];
+                [0, 1, 2].map(|i| self.target[i] + back[i] * distance)
+            }
+        }
+    }
+
+    // where the shading sees the scene from, the eye of perspective views as a point with a w
+    // of 1 and the direction towards orthographic views, which look along it everywhere, with
+    // a w of 0
+    pub(crate) fn view_origin(&self) -> [f32; 4] {
+        match self.projection {
+            Projection::Orthographic => {
+                let [x, y, z] = self.axes()[2];
+                [x, y, z, 0.]
+            }
+            Projection::Perspective { .. } => {
+                let [x, y, z] = self.eye();
+                [x, y, z, 1.]
+            }
+        }
+    }
+
+    pub fn view_matrix(&self) -> Matrix {
+        let [right, up, back] = self.axes();
+        let eye = self.eye();
+        [
+            [right[0], up[0], back[0], 0.],
+            [right[1], up[1], back[1], 0.],
+            [right[2], up[2], back[2], 0.],
+            [-dot(right, eye), -dot(up, eye), -dot(back, eye), 1.],
+        ]
+    }
+
+    // maps the view to metal's clip space, where the depth ranges from 0 to 1. `aspect` is the
+    // width of the viewport over its height, both projections keep their proportions when it
+    // changes, the height of the view stays put and the width follows
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix {
+        match self.projection {
+            Projection::Orthographic => [
+                [self.zoom / aspect, 0., 0., 0.],
+                [0., self.zoom, 0., 0.],
+                [0., 0., 1., 0.],
+                [0., 0., 0., 1.],
+            ],
+            Projection::Perspective { fov_y, near, far } => {
+                let scale = 1. / (fov_y / 2.).tan();
+                [
+                    [scale / aspect, 0., 0., 0.],
+                    [0., scale, 0., 0.],
+                    [0., 0., far / (near - far), -1.],
+                    [0., 0., near * far / (near - far), 0.],
+                ]
+            }
+        }
+    }
+
+    pub fn view_projection_matrix(&self, aspect: f32) -> Matrix {
+        multiply(&self.projection_matrix(aspect), &self.view_matrix())
+    }
+
+    // rotates the eye about the target, the elevation stops short of the poles
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        let limit = FRAC_PI_2 - 0.01;
+        self.yaw += yaw;
+        self.pitch = (self.pitch + pitch).clamp(-limit, limit);
+    }
+
+    // moves the target across the view in units of half the view height, so it moves the same
+    // distance on screen whatever the zoom
+    pub fn pan(&mut self, x: f32, y: f32) {
+        let [right, up, _] = self.axes();
+        for i in 0..3 {
+            self.target[i] += (right[i] * x + up[i] * y) / self.zoom;
+        }
+    }
+
+    // turns the view about the eye rather than the target, like a head turning, for cameras
+    // flying through the scene. the elevation stops short of the poles like with `orbit`
+    pub fn look(&mut self, yaw: f32, pitch: f32) {
+        let eye = self.eye();
+        self.orbit(yaw, pitch);
+        let moved = self.eye();
+        for i in 0..3 {
+            self.target[i] += eye[i] - moved[i];
+        }
+    }
+
+    // moves the eye and the target along the right, up and forward directions of the view, in
+    // world units
+    pub fn fly(&mut self, right: f32, up: f32, forward: f32) {
+        let [right_axis, up_axis, back] = self.axes();
+        for i in 0..3 {
+            self.target[i] += right_axis[i] * right + up_axis[i] * up - back[i] * forward;
+        }
+    }
+
+    // the ray through a position in normalized device coordinates as its origin and a direction
+    // that isn't normalized, both linear in the position. orthographic rays start on the view
+    // plane through the target
+    pub(crate) fn ray(&self, (x, y): (f32, f32), aspect: f32) -> ([f32; 3], [f32; 3]) {
+        let [right, up, back] = self.axes();
+        match self.projection {
+            Projection::Orthographic => {
+                let x = x * aspect;
+                let offset = [0, 1, 2].map(|i| (right[i] * x + up[i] * y) / self.zoom);
+                (
+                    [0, 1, 2].map(|i| self.target[i] + offset[i]),
+                    back.map(|v| -v),
+                )
+            }
+            Projection::Perspective { fov_y, .. } => {
+                let x = x * aspect;
+                let height = (fov_y / 2.).tan();
+                let direction = [0, 1, 2].map(|i| (right[i] * x + up[i] * y) * height - back[i]);
+                (self.eye(), direction)
+            }
+        }
+    }
+
+    // the point on the z = 0 plane under a position in normalized device coordinates, or the
+    // point of the view plane when the z = 0 plane is seen edge on
+    pub(crate) fn unproject(&self, position: (f32, f32), aspect: f32) -> (f32, f32) {
+        let (origin, direction) = self.ray(position, aspect);
+        if direction[2].abs() < 1e-6 {
+            return (origin[0], origin[1]);
+        }
+        let t = -origin[2] / direction[2];
+        (origin[0] + direction[0] * t, origin[1] + direction[1] * t)
+    }
+}
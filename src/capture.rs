@@ -0,0 +1,348 @@
+// the frames of a camera captured by AVFoundation into metal textures, for processing them with
+// kernels or drawing them like a video. the session delivers the frames on a queue of its own,
+// the latest one waits there until `update` picks it up on the main thread
+use std::sync::{Arc, Mutex};
+
+use block2::RcBlock;
+
+use core::{
+    ffi::{c_char, c_void, CStr},
+    ptr::{self, NonNull},
+};
+
+use core_foundation::base::{CFRelease, CFRetain};
+use objc2::{
+    class, declare_class, msg_send, msg_send_id,
+    mutability::InteriorMutable,
+    rc::{Allocated, Retained},
+    runtime::{AnyObject, NSObject, NSObjectProtocol, ProtocolObject},
+    ClassType, DeclaredClass, Encoding, RefEncode,
+};
+use objc2_foundation::{
+    NSError, NSNotification, NSNotificationCenter, NSNotificationName, NSString,
+};
+use objc2_metal::MTLTexture;
+
+use crate::{
+    video::{pixel_buffer_attributes, CVBuffer, VideoTextures},
+    MetalRenderer, VideoError,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the `AVAuthorizationStatus` of an application the camera is off limits for
+const AUTHORIZATION_RESTRICTED: isize = 1;
+const AUTHORIZATION_DENIED: isize = 2;
+
+// opaque core media sample buffers, a captured frame with its timing
+#[repr(C)]
+pub(crate) struct CMSampleBuffer {
+    _private: [u8; 0],
+}
+
+unsafe impl RefEncode for CMSampleBuffer {
+    const ENCODING_REF: Encoding =
+        Encoding::Pointer(&Encoding::Struct("opaqueCMSampleBuffer", &[]));
+}
+
+// opaque dispatch queues, objective-c objects like the dispatch data of the shader library
+#[repr(C)]
+pub(crate) struct DispatchQueue {
+    _private: [u8; 0],
+}
+
+unsafe impl RefEncode for DispatchQueue {
+    const ENCODING_REF: Encoding = Encoding::Object;
+}
+
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {
+    static AVMediaTypeVideo: &'static NSString;
+    static AVCaptureSessionRuntimeErrorNotification: &'static NSNotificationName;
+    static AVCaptureSessionErrorKey: &'static NSString;
+}
+
+#[link(name = "CoreMedia", kind = "framework")]
+extern "C" {
+    fn CMSampleBufferGetImageBuffer(sample_buffer: *mut CMSampleBuffer) -> *mut CVBuffer;
+}
+
+extern "C" {
+    fn dispatch_queue_create(label: *const c_char, attributes: *const c_void)
+        -> *mut DispatchQueue;
+    fn dispatch_release(object: *mut DispatchQueue);
+}
+
+// the serial queue the frames of a capture are delivered on
+pub(crate) struct CaptureQueue(NonNull<DispatchQueue>);
+
+impl CaptureQueue {
+    pub(crate) fn new(label: &CStr) -> Self {
+        let queue = unsafe { dispatch_queue_create(label.as_ptr(), ptr::null()) };
+        CaptureQueue(NonNull::new(queue).expect("Failed to create a capture queue."))
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut DispatchQueue {
+        self.0.as_ptr()
+    }
+}
+
+impl Drop for CaptureQueue {
+    fn drop(&mut self) {
+        unsafe { dispatch_release(self.0.as_ptr()) };
+    }
+}
+
+// a captured pixel buffer, retained until it's wrapped into a texture
+pub(crate) struct PixelBuffer(pub(crate) NonNull<CVBuffer>);
+
+// core video buffers are reference counted atomically, they can be released on any thread
+unsafe impl Send for PixelBuffer {}
+
+impl Drop for PixelBuffer {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0.as_ptr().cast()) };
+    }
+}
+
+// what the delegate of a capture hands over to the main thread
+#[derive(Default)]
+pub(crate) struct CaptureState {
+    // only the latest frame is kept, the ones the main thread didn't pick up in time are dropped
+    latest_frame: Mutex<Option<PixelBuffer>>,
+    // why the capture stopped
+    error: Mutex<Option<String>>,
+}
+
+impl CaptureState {
+    pub(crate) fn take_frame(&self) -> Option<PixelBuffer> {
+        self.latest_frame.lock().unwrap().take()
+    }
+
+    pub(crate) fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_error(&self, error: &NSError) {
+        *self.error.lock().unwrap() = Some(error.localizedDescription().to_string());
+    }
+
+    // keeps the image of `sample_buffer`, some sample buffers only report the capture status
+    fn keep_frame(&self, sample_buffer: *mut CMSampleBuffer) {
+        let image = unsafe { CMSampleBufferGetImageBuffer(sample_buffer) };
+        if let Some(image) = NonNull::new(image) {
+            // the sample buffer is reused once the delegate returns
+            unsafe { CFRetain(image.as_ptr().cast()) };
+            *self.latest_frame.lock().unwrap() = Some(PixelBuffer(image));
+        }
+    }
+}
+
+// the `SCStreamOutputType` of the frames of the screen rather than of its audio
+pub(crate) const STREAM_OUTPUT_SCREEN: isize = 0;
+
+declare_class!(
+    // the sample buffer delegate of the video output of a camera, and the output and the
+    // delegate of the stream of a screen capture. it's called on the capture queue
+    pub(crate) struct CaptureDelegate;
+
+    unsafe impl ClassType for CaptureDelegate {
+        type Super = NSObject;
+        type Mutability = InteriorMutable;
+        const NAME: &'static str = "MetalRendererCaptureDelegate";
+    }
+
+    impl DeclaredClass for CaptureDelegate {
+        type Ivars = Arc<CaptureState>;
+    }
+
+    unsafe impl NSObjectProtocol for CaptureDelegate {}
+
+    unsafe impl CaptureDelegate {
+        #[method(captureOutput:didOutputSampleBuffer:fromConnection:)]
+        fn did_output_sample_buffer(
+            &self,
+            _output: &AnyObject,
+            sample_buffer: *mut CMSampleBuffer,
+            _connection: &AnyObject,
+        ) {
+            self.ivars().keep_frame(sample_buffer);
+        }
+
+        #[method(stream:didOutputSampleBuffer:ofType:)]
+        fn stream_did_output_sample_buffer(
+            &self,
+            _stream: &AnyObject,
+            sample_buffer: *mut CMSampleBuffer,
+            output_type: isize,
+        ) {
+            if output_type == STREAM_OUTPUT_SCREEN {
+                self.ivars().keep_frame(sample_buffer);
+            }
+        }
+
+        #[method(stream:didStopWithError:)]
+        fn stream_did_stop(&self, _stream: &AnyObject, error: &NSError) {
+            self.ivars().set_error(error);
+        }
+    }
+);
+
+impl CaptureDelegate {
+    pub(crate) fn new(state: Arc<CaptureState>) -> Retained<Self> {
+        let delegate = Self::alloc().set_ivars(state);
+        unsafe { msg_send_id![super(delegate), init] }
+    }
+}
+
+// the default camera capturing into textures, stopped until `start`
+pub struct CameraCapture {
+    session: Retained<AnyObject>,
+    output: Retained<AnyObject>,
+    // kept alive for the output, which doesn't own its delegate
+    _delegate: Retained<CaptureDelegate>,
+    _queue: CaptureQueue,
+    // takes the errors the session reports while it runs
+    error_observer: Retained<NSObject>,
+    state: Arc<CaptureState>,
+    textures: VideoTextures,
+}
+
+impl CameraCapture {
+    // turns the camera on, blocks until it runs
+    pub fn start(&self) {
+        unsafe { msg_send![&self.session, startRunning] }
+    }
+
+    pub fn stop(&self) {
+        unsafe { msg_send![&self.session, stopRunning] }
+    }
+
+    pub fn is_running(&self) -> bool {
+        unsafe { msg_send![&self.session, isRunning] }
+    }
+
+    // picks up the latest frame of the camera, called once a frame before using the texture.
+    // returns the texture of the latest frame, none until the first one is captured
+    pub fn update(&mut self) -> Option<&Texture> {
+        if let Some(pixel_buffer) = self.state.take_frame() {
+            self.textures.push(pixel_buffer.0);
+        }
+        self.texture()
+    }
+
+    // the texture of the latest frame, in place until the next one is picked up
+    pub fn texture(&self) -> Option<&Texture> {
+        self.textures.latest()
+    }
+
+    // why the camera stopped, e.g. another application took it over
+    pub fn error(&self) -> Option<String> {
+        self.state.error()
+    }
+}
+
+impl Drop for CameraCapture {
+    fn drop(&mut self) {
+        self.stop();
+        unsafe {
+            NSNotificationCenter::defaultCenter().removeObserver(&self.error_observer);
+            let _: () = msg_send![
+                &self.output,
+                setSampleBufferDelegate: None::<&AnyObject>,
+                queue: None::<&DispatchQueue>
+            ];
+        }
+    }
+}
+
+impl MetalRenderer {
+    // opens the default camera of the system, e.g. the built-in one of a laptop, capturing
+    // into textures of the device of the renderer. asks the user for the access the first
+    // time, a bundled application needs an `NSCameraUsageDescription` in its Info.plist for it
+    pub fn open_camera(&self) -> Result<CameraCapture, VideoError> {
+        let textures = VideoTextures::new(&self.device())?;
+        unsafe {
+            let status: isize = msg_send![
+                class!(AVCaptureDevice),
+                authorizationStatusForMediaType: AVMediaTypeVideo
+            ];
+            if matches!(status, AUTHORIZATION_RESTRICTED | AUTHORIZATION_DENIED) {
+                return Err(VideoError::CameraDenied);
+            }
+            let device: Option<Retained<AnyObject>> = msg_send_id![
+                class!(AVCaptureDevice),
+                defaultDeviceWithMediaType: AVMediaTypeVideo
+            ];
+            let device = device.ok_or(VideoError::NoCamera)?;
+            let input: Result<Retained<AnyObject>, Retained<NSError>> = msg_send_id![
+                class!(AVCaptureDeviceInput),
+                deviceInputWithDevice: &*device,
+                error: _
+            ];
+            let input = input
+                .map_err(|error| VideoError::Camera(error.localizedDescription().to_string()))?;
+
+            let state = Arc::<CaptureState>::default();
+            let delegate = CaptureDelegate::new(state.clone());
+            let queue = CaptureQueue::new(c"rust-tao-metal.camera");
+
+            let output: Allocated<AnyObject> =
+                msg_send_id![class!(AVCaptureVideoDataOutput), alloc];
+            let output: Retained<AnyObject> = msg_send_id![output, init];
+            let _: () = msg_send![&output, setVideoSettings: &*pixel_buffer_attributes()];
+            let _: () = msg_send![&output, setAlwaysDiscardsLateVideoFrames: true];
+            let _: () = msg_send![
+                &output,
+                setSampleBufferDelegate: &*delegate,
+                queue: queue.as_ptr()
+            ];
+
+            let session: Allocated<AnyObject> = msg_send_id![class!(AVCaptureSession), alloc];
+            let session: Retained<AnyObject> = msg_send_id![session, init];
+            let _: () = msg_send![&session, beginConfiguration];
+            let can_add: bool = msg_send![&session, canAddInput: &*input];
+            if can_add {
+                let _: () = msg_send![&session, addInput: &*input];
+            }
+            let can_add_output: bool = msg_send![&session, canAddOutput: &*output];
+            if can_add_output {
+                let _: () = msg_send![&session, addOutput: &*output];
+            }
+            let _: () = msg_send![&session, commitConfiguration];
+            let error_handler = RcBlock::new({
+                let state = state.clone();
+                move |notification: NonNull<NSNotification>| {
+                    let user_info = notification.as_ref().userInfo();
+                    let error: Option<Retained<NSError>> = user_info.and_then(|user_info| {
+                        msg_send_id![&user_info, objectForKey: AVCaptureSessionErrorKey]
+                    });
+                    if let Some(error) = error {
+                        state.set_error(&error);
+                    }
+                }
+            });
+            let error_observer = NSNotificationCenter::defaultCenter()
+                .addObserverForName_object_queue_usingBlock(
+                    Some(AVCaptureSessionRuntimeErrorNotification),
+                    Some(&session),
+                    None,
+                    &error_handler,
+                );
+            let capture = CameraCapture {
+                session,
+                output,
+                _delegate: delegate,
+                _queue: queue,
+                error_observer,
+                state,
+                textures,
+            };
+            if !can_add || !can_add_output {
+                let message = "The session can't capture from the camera.".to_owned();
+                return Err(VideoError::Camera(message));
+            }
+            Ok(capture)
+        }
+    }
+}
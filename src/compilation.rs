@@ -0,0 +1,429 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use block2::RcBlock;
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::{NSError, NSString};
+use objc2_metal::{MTLDevice, MTLLibrary, MTLRenderPipelineDescriptor, MTLRenderPipelineState};
+
+use crate::{
+    compile_options, LogLevel, MetalRenderer, PipelineDescriptor, RendererError, ShaderOptions,
+    VertexLayout,
+};
+
+type Library = Retained<ProtocolObject<dyn MTLLibrary>>;
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+pub(crate) type LibraryCompilation = Compilation<Library>;
+
+// drawn in place of the pipelines whose shaders are still compiling, the vertices in a flat gray
+const PLACEHOLDER_SOURCE: &str = r#"
+struct SceneProperties {
+    metal::float4x4 view_projection;
+    float time;
+    float point_size;
+};
+
+struct VertexInput {
+    metal::packed_float3 position;
+    metal::packed_float3 color;
+};
+
+struct PlaceholderOutput {
+    metal::float4 position [[position]];
+    float point_size [[point_size]];
+};
+
+vertex PlaceholderOutput vertex_placeholder(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    PlaceholderOutput out;
+    out.position = properties.view_projection * metal::float4(vertices[vertex_idx].position, 1);
+    out.point_size = properties.point_size;
+    return out;
+}
+
+fragment metal::float4 fragment_placeholder() {
+    return metal::float4(0.5, 0.5, 0.5, 1);
+}
+"#;
+
+// a shader library or a pipeline metal failed to compile, holds the reason
+#[derive(Clone, Debug)]
+pub struct CompilationError(pub String);
+
+impl fmt::Display for CompilationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compilation failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for CompilationError {}
+
+struct State<T> {
+    finished: bool,
+    result: Option<Result<T, CompilationError>>,
+    // the warnings of a successful compilation, metal reports them like an error
+    warnings: Option<String>,
+    waker: Option<Waker>,
+}
+
+// a compilation running on a metal thread. it's polled with `try_take` from the render loop,
+// blocked on with `wait` or awaited as a future, the result is handed out once
+pub struct Compilation<T> {
+    shared: Arc<(Mutex<State<T>>, Condvar)>,
+}
+
+impl<T> Compilation<T> {
+    fn new() -> Self {
+        let state = State {
+            finished: false,
+            result: None,
+            warnings: None,
+            waker: None,
+        };
+        Compilation {
+            shared: Arc::new((Mutex::new(state), Condvar::new())),
+        }
+    }
+
+    // a compilation that failed before it could start
+    fn failed(error: CompilationError) -> Self {
+        let compilation = Self::new();
+        let mut state = compilation.shared.0.lock().unwrap();
+        state.finished = true;
+        state.result = Some(Err(error));
+        drop(state);
+        compilation
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.0.lock().unwrap().finished
+    }
+
+    // the result once the compilation is done, none while it's running or after it was taken
+    pub fn try_take(&self) -> Option<Result<T, CompilationError>> {
+        self.shared.0.lock().unwrap().result.take()
+    }
+
+    // blocks until the compilation is done and takes the result
+    pub fn wait(&self) -> Result<T, CompilationError> {
+        let (state, condvar) = &*self.shared;
+        let mut state = condvar
+            .wait_while(state.lock().unwrap(), |state| !state.finished)
+            .unwrap();
+        state
+            .result
+            .take()
+            .expect("Failed to wait for a compilation, its result was already taken.")
+    }
+
+    pub fn warnings(&self) -> Option<String> {
+        self.shared.0.lock().unwrap().warnings.clone()
+    }
+}
+
+impl<P: ?Sized + 'static> Compilation<Retained<ProtocolObject<P>>> {
+    // the completion handler of metal, called on one of its own threads with the object or the
+    // error
+    fn completion_handler(&self) -> RcBlock<dyn Fn(*mut ProtocolObject<P>, *mut NSError)> {
+        let shared = self.shared.clone();
+        RcBlock::new(move |object: *mut ProtocolObject<P>, error: *mut NSError| {
+            let object = unsafe { Retained::retain(object) };
+            let description =
+                unsafe { error.as_ref() }.map(|error| error.localizedDescription().to_string());
+            let (state, condvar) = &*shared;
+            let mut state = state.lock().unwrap();
+            match object {
+                Some(object) => {
+                    state.result = Some(Ok(object));
+                    state.warnings = description;
+                }
+                None => {
+                    let description = description.unwrap_or_default();
+                    state.result = Some(Err(CompilationError(description)));
+                }
+            }
+            state.finished = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            condvar.notify_all();
+        })
+    }
+}
+
+impl<T> Future for Compilation<T> {
+    type Output = Result<T, CompilationError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.0.lock().unwrap();
+        if !state.finished {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => panic!("Failed to poll a compilation, its result was already taken."),
+        }
+    }
+}
+
+// a pipeline prepared with `prepare_pipeline_state`
+pub(crate) struct PendingPipeline {
+    pipeline_state: Compilation<PipelineState>,
+    mtl_descriptor: Retained<MTLRenderPipelineDescriptor>,
+}
+
+#[derive(Default)]
+pub(crate) struct PendingPipelines {
+    // prepared while the shader library still compiles, started once it's done
+    queued: Vec<PipelineDescriptor>,
+    compiling: HashMap<PipelineDescriptor, PendingPipeline>,
+}
+
+impl MetalRenderer {
+    // compiles `source` on a metal thread, the library doesn't replace the one in use
+    pub fn compile_library_async(
+        &self,
+        source: &str,
+        shader_options: &ShaderOptions,
+    ) -> Compilation<Library> {
+        let compilation = Compilation::new();
+        let completion_handler = compilation.completion_handler();
+        let compile_options = compile_options(shader_options);
+        // metal copies the block and calls it on one of its own threads
+        unsafe {
+            self.device()
+                .newLibraryWithSource_options_completionHandler(
+                    &NSString::from_str(source),
+                    Some(&compile_options),
+                    &*completion_handler as *const _ as *mut _,
+                )
+        };
+        compilation
+    }
+
+    // builds the pipeline of `descriptor` with the shader library on a metal thread, it isn't
+    // added to the cache of `render_pipeline_state_for`
+    pub fn create_pipeline_state_async(
+        &self,
+        descriptor: &PipelineDescriptor,
+    ) -> Compilation<PipelineState> {
+        let library = match self.library() {
+            Ok(library) => library,
+            Err(error) => return Compilation::failed(CompilationError(error.to_string())),
+        };
+        let mtl_descriptor = self.mtl_pipeline_descriptor(&library, descriptor);
+        self.compile_pipeline_state(&mtl_descriptor)
+    }
+
+    fn compile_pipeline_state(
+        &self,
+        mtl_descriptor: &MTLRenderPipelineDescriptor,
+    ) -> Compilation<PipelineState> {
+        let compilation = Compilation::new();
+        let completion_handler = compilation.completion_handler();
+        unsafe {
+            self.device()
+                .newRenderPipelineStateWithDescriptor_completionHandler(
+                    mtl_descriptor,
+                    &*completion_handler as *const _ as *mut _,
+                )
+        };
+        compilation
+    }
+
+    // starts building the pipeline of `descriptor` on a metal thread. `render_pipeline_state_for`
+    // draws with a placeholder until it's done instead of building it when first used
+    pub fn prepare_pipeline_state(&self, descriptor: &PipelineDescriptor) {
+        if self
+            .ivars()
+            .pipeline_cache
+            .borrow()
+            .get(descriptor)
+            .is_some()
+        {
+            return;
+        }
+        let mut pending_pipelines = self.ivars().pending_pipelines.borrow_mut();
+        if pending_pipelines.compiling.contains_key(descriptor)
+            || pending_pipelines.queued.contains(descriptor)
+        {
+            return;
+        }
+        pending_pipelines.queued.push(descriptor.clone());
+        drop(pending_pipelines);
+        self.start_pending_pipelines();
+    }
+
+    // starts the queued pipelines once the shader library is there
+    fn start_pending_pipelines(&self) {
+        if self.ivars().pending_library.borrow().is_some()
+            || self.ivars().library.borrow().is_none()
+        {
+            return;
+        }
+        let Ok(library) = self.library() else {
+            return;
+        };
+        let queued = core::mem::take(&mut self.ivars().pending_pipelines.borrow_mut().queued);
+        for descriptor in queued {
+            let mtl_descriptor = self.mtl_pipeline_descriptor(&library, &descriptor);
+            let pending_pipeline = PendingPipeline {
+                pipeline_state: self.compile_pipeline_state(&mtl_descriptor),
+                mtl_descriptor,
+            };
+            let mut pending_pipelines = self.ivars().pending_pipelines.borrow_mut();
+            pending_pipelines
+                .compiling
+                .insert(descriptor, pending_pipeline);
+        }
+    }
+
+    // prepares the pipelines that were compiling again with a new shader library
+    pub(crate) fn restart_pending_pipelines(&self) {
+        let mut pending_pipelines = self.ivars().pending_pipelines.borrow_mut();
+        let compiling: Vec<_> = pending_pipelines
+            .compiling
+            .drain()
+            .map(|(key, _)| key)
+            .collect();
+        pending_pipelines.queued.extend(compiling);
+        drop(pending_pipelines);
+        self.start_pending_pipelines();
+    }
+
+    // whether the shader library or a prepared pipeline is still compiling
+    pub(crate) fn is_compiling(&self) -> bool {
+        self.ivars().pending_library.borrow().is_some()
+            || !self.ivars().pending_pipelines.borrow().compiling.is_empty()
+    }
+
+    // swaps in the library compiled by `init` once it's done, false while it's still compiling
+    // or when it failed to compile. the error is logged and the frames keep drawing with the
+    // placeholders until a library is loaded or compiled again
+    pub(crate) fn finish_library_compilation(&self) -> bool {
+        if let Some(pending_library) = self.ivars().pending_library.take() {
+            let Some(result) = pending_library.try_take() else {
+                self.ivars().pending_library.replace(Some(pending_library));
+                return false;
+            };
+            // the error was logged with the diagnostics of the compiler
+            let _ = self.install_library(result, pending_library.warnings());
+        }
+        self.ivars().library.borrow().is_some()
+    }
+
+    // blocks until the shaders `init` started compiling are done, so a failed compilation is
+    // reported with the diagnostics of the compiler rather than when a pipeline is first used
+    pub fn finish_shader_compilation(&self) -> Result<(), RendererError> {
+        let Some(pending_library) = self.ivars().pending_library.take() else {
+            return Ok(());
+        };
+        self.install_library(pending_library.wait(), pending_library.warnings())
+    }
+
+    pub(crate) fn install_library(
+        &self,
+        result: Result<Library, CompilationError>,
+        warnings: Option<String>,
+    ) -> Result<(), RendererError> {
+        match result {
+            Ok(library) => {
+                self.log_compilation(true, warnings);
+                self.ivars().library.replace(Some(library));
+                self.start_pending_pipelines();
+                Ok(())
+            }
+            Err(error) => {
+                self.log_compilation(false, Some(error.0.clone()));
+                Err(error.into())
+            }
+        }
+    }
+
+    // whether the pipeline of `descriptor` can be built or was added to the cache, false while
+    // the shader library or the prepared pipeline are still compiling
+    pub(crate) fn finish_compilations(&self, descriptor: &PipelineDescriptor) -> bool {
+        if !self.finish_library_compilation() {
+            return false;
+        }
+        let mut pending_pipelines = self.ivars().pending_pipelines.borrow_mut();
+        if pending_pipelines.queued.contains(descriptor) {
+            return false;
+        }
+        let Some(pending_pipeline) = pending_pipelines.compiling.get(descriptor) else {
+            return true;
+        };
+        let Some(result) = pending_pipeline.pipeline_state.try_take() else {
+            return false;
+        };
+        let pending_pipeline = pending_pipelines.compiling.remove(descriptor).unwrap();
+        drop(pending_pipelines);
+        match result {
+            Ok(pipeline_state) => {
+                if let Some(pipeline_archive) = self.ivars().pipeline_archive.borrow().as_ref() {
+                    if let Err(error) = pipeline_archive.add(&pending_pipeline.mtl_descriptor) {
+                        self.log(LogLevel::Warn, &format!("{error}."));
+                    }
+                }
+                self.ivars()
+                    .pipeline_cache
+                    .borrow_mut()
+                    .insert(descriptor.clone(), pipeline_state);
+            }
+            // built again when it's used, which reports the error
+            Err(error) => self.log(LogLevel::Warn, &format!("{error}.")),
+        }
+        true
+    }
+
+    // draws the vertices of a pipeline still compiling in gray, with the formats and the
+    // blending of `descriptor`
+    pub(crate) fn placeholder_pipeline_state(
+        &self,
+        descriptor: &PipelineDescriptor,
+    ) -> Result<PipelineState, RendererError> {
+        let descriptor = PipelineDescriptor {
+            vertex_function: "vertex_placeholder".to_owned(),
+            fragment_function: "fragment_placeholder".to_owned(),
+            vertex_layout: VertexLayout::default(),
+            ..descriptor.clone()
+        };
+        if let Some(pipeline_state) = self.ivars().placeholder_pipelines.borrow().get(&descriptor) {
+            return Ok(pipeline_state);
+        }
+        let library = self.placeholder_library()?;
+        let pipeline_state = self.create_pipeline_state_with_library(&library, &descriptor)?;
+        self.ivars()
+            .placeholder_pipelines
+            .borrow_mut()
+            .insert(descriptor, pipeline_state.clone());
+        Ok(pipeline_state)
+    }
+
+    // the library of the placeholder pipelines, compiled on first use
+    pub(crate) fn placeholder_library(&self) -> Result<Library, RendererError> {
+        if let Some(library) = self.ivars().placeholder_library.borrow().as_ref() {
+            return Ok(library.clone());
+        }
+        let library = self
+            .device()
+            .newLibraryWithSource_options_error(&NSString::from_str(PLACEHOLDER_SOURCE), None)
+            .map_err(|error| {
+                RendererError::ShaderCompilation(error.localizedDescription().to_string())
+            })?;
+        self.ivars()
+            .placeholder_library
+            .replace(Some(library.clone()));
+        Ok(library)
+    }
+}
@@ -0,0 +1,280 @@
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSString;
+use objc2_metal::{
+    MTLAccelerationStructure, MTLBuffer, MTLCommandBuffer, MTLCommandEncoder,
+    MTLComputeCommandEncoder, MTLComputePassDescriptor, MTLComputePipelineState, MTLDevice,
+    MTLIndirectCommandBuffer, MTLLibrary, MTLResourceUsage, MTLSize, MTLTexture,
+};
+
+use crate::{
+    debug_labels::{encode_debug_groups, popped, pushed, DebugGroups},
+    sync::{GpuFence, PassFences},
+    LogLevel, MetalRenderer,
+};
+
+// a dispatch recorded into a compute pass
+struct DispatchItem {
+    pipeline_state: Retained<ProtocolObject<dyn MTLComputePipelineState>>,
+    // the grid of threads, one kernel invocation each
+    threads: MTLSize,
+    // bound to the kernel argument buffers at the indices they're paired with
+    buffers: Vec<(usize, Retained<ProtocolObject<dyn MTLBuffer>>)>,
+    textures: Vec<(usize, Retained<ProtocolObject<dyn MTLTexture>>)>,
+    acceleration_structures: Vec<(
+        usize,
+        Retained<ProtocolObject<dyn MTLAccelerationStructure>>,
+    )>,
+    // written by the kernel through an argument buffer
+    indirect_command_buffers: Vec<Retained<ProtocolObject<dyn MTLIndirectCommandBuffer>>>,
+    debug_groups: DebugGroups,
+}
+
+// the dispatches of one compute command encoder, encoded in the order they were added before
+// the render pass of the frame. metal orders the passes by the resources they share, so draws
+// can read what the kernels wrote, as vertex buffers or through argument buffers. resources of
+// an untracked heap are ordered with fences instead
+#[derive(Default)]
+pub struct ComputePass {
+    items: Vec<DispatchItem>,
+    // the fences the dispatches wait for and update
+    fences: PassFences,
+    label: Option<String>,
+    // the debug groups open while recording, see `push_debug_group`
+    debug_groups: DebugGroups,
+}
+
+impl ComputePass {
+    // runs the kernel of `pipeline_state` once per thread of a `threads` grid. the threadgroups
+    // are as large as the pipeline allows and the ones at the edges of the grid are cut to fit,
+    // so kernels can use `[[threads_per_grid]]` as the size of their data
+    pub fn dispatch(
+        &mut self,
+        pipeline_state: &Retained<ProtocolObject<dyn MTLComputePipelineState>>,
+        (width, height, depth): (usize, usize, usize),
+    ) -> &mut Self {
+        self.items.push(DispatchItem {
+            pipeline_state: pipeline_state.clone(),
+            threads: MTLSize {
+                width,
+                height,
+                depth,
+            },
+            buffers: Vec::new(),
+            textures: Vec::new(),
+            acceleration_structures: Vec::new(),
+            indirect_command_buffers: Vec::new(),
+            debug_groups: self.debug_groups.clone(),
+        });
+        self
+    }
+
+    // binds `buffer` to the kernel argument buffer at `index` for the last recorded dispatch.
+    // the scene properties are bound at index 0
+    pub fn with_buffer(
+        &mut self,
+        index: usize,
+        buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.buffers.push((index, buffer.clone()));
+        }
+        self
+    }
+
+    // binds `texture` to the kernel texture at `index` for the last recorded dispatch. kernels
+    // can only write textures created with `create_storage_texture`
+    pub fn with_texture(
+        &mut self,
+        index: usize,
+        texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.textures.push((index, texture.clone()));
+        }
+        self
+    }
+
+    // binds `acceleration_structure` to the kernel argument buffer at `index` for the last
+    // recorded dispatch, binding it makes the primitives of a primitive acceleration structure
+    // resident
+    pub fn with_acceleration_structure(
+        &mut self,
+        index: usize,
+        acceleration_structure: &Retained<ProtocolObject<dyn MTLAccelerationStructure>>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.acceleration_structures
+                .push((index, acceleration_structure.clone()));
+        }
+        self
+    }
+
+    // makes `indirect_command_buffer` resident for the last recorded dispatch, which encodes
+    // commands into it through an argument buffer bound with `with_buffer`
+    pub(crate) fn with_indirect_command_buffer(
+        &mut self,
+        indirect_command_buffer: &Retained<ProtocolObject<dyn MTLIndirectCommandBuffer>>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.indirect_command_buffers
+                .push(indirect_command_buffer.clone());
+        }
+        self
+    }
+
+    // the dispatches of the pass wait for the passes updating `fence` before it, which
+    // resources of an untracked heap need
+    pub fn wait_for_fence(&mut self, fence: &GpuFence) -> &mut Self {
+        self.fences.waits.push(fence.clone());
+        self
+    }
+
+    // signals `fence` once the dispatches of the pass are done, e.g. for the draws reading
+    // what they wrote in an untracked heap
+    pub fn update_fence(&mut self, fence: &GpuFence) -> &mut Self {
+        self.fences.updates.push(fence.clone());
+        self
+    }
+
+    // names the encoder of the pass, "Compute pass" without a label
+    pub fn set_label(&mut self, label: &str) -> &mut Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    // the dispatches recorded until the matching `pop_debug_group` are grouped under `name`,
+    // like the draws of `RenderPass::push_debug_group`
+    pub fn push_debug_group(&mut self, name: &str) -> &mut Self {
+        self.debug_groups = pushed(&self.debug_groups, name);
+        self
+    }
+
+    pub fn pop_debug_group(&mut self) -> &mut Self {
+        self.debug_groups = popped(&self.debug_groups);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // creates a compute encoder for `pass_descriptor` and encodes all the dispatches into it,
+    // returns false if the encoder couldn't be created. the passes of other queues run without
+    // scene properties
+    pub(crate) fn encode(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        pass_descriptor: &MTLComputePassDescriptor,
+        scene_properties: Option<&ProtocolObject<dyn MTLBuffer>>,
+    ) -> bool {
+        let Some(encoder) =
+            (unsafe { command_buffer.computeCommandEncoderWithDescriptor(pass_descriptor) })
+        else {
+            return false;
+        };
+        let label = self.label.as_deref().unwrap_or("Compute pass");
+        encoder.setLabel(Some(&NSString::from_str(label)));
+        unsafe { encoder.setBuffer_offset_atIndex(scene_properties, 0, 0) };
+        self.fences.encode_compute_waits(&encoder);
+
+        let mut debug_groups: &[String] = &[];
+        for item in &self.items {
+            encode_debug_groups(&*encoder, debug_groups, &item.debug_groups);
+            debug_groups = &item.debug_groups;
+            encoder.setComputePipelineState(&item.pipeline_state);
+            for (index, buffer) in &item.buffers {
+                unsafe { encoder.setBuffer_offset_atIndex(Some(buffer), 0, *index) };
+            }
+            for (index, texture) in &item.textures {
+                unsafe { encoder.setTexture_atIndex(Some(texture), *index) };
+            }
+            for (index, acceleration_structure) in &item.acceleration_structures {
+                unsafe {
+                    encoder.setAccelerationStructure_atBufferIndex(
+                        Some(acceleration_structure),
+                        *index,
+                    )
+                };
+            }
+            for indirect_command_buffer in &item.indirect_command_buffers {
+                encoder.useResource_usage(
+                    indirect_command_buffer.as_ref().as_ref(),
+                    MTLResourceUsage::Write,
+                );
+            }
+            // a 2d block of whole simd groups, or a row of threads for 1d grids
+            let width = item.pipeline_state.threadExecutionWidth();
+            let max_threads = item.pipeline_state.maxTotalThreadsPerThreadgroup();
+            let threads_per_threadgroup = if item.threads.height > 1 {
+                MTLSize {
+                    width,
+                    height: max_threads / width,
+                    depth: 1,
+                }
+            } else {
+                MTLSize {
+                    width: max_threads,
+                    height: 1,
+                    depth: 1,
+                }
+            };
+            encoder.dispatchThreads_threadsPerThreadgroup(item.threads, threads_per_threadgroup);
+        }
+        encode_debug_groups(&*encoder, debug_groups, &[]);
+        self.fences.encode_compute_updates(&encoder);
+        encoder.endEncoding();
+        true
+    }
+}
+
+pub(crate) type ComputeCallback = Box<dyn Fn(&MetalRenderer, &mut ComputePass)>;
+
+impl MetalRenderer {
+    // returns the pipeline for the kernel `function_name`, it's created on first use and
+    // rebuilt after the shader library changes
+    pub fn compute_pipeline_state(
+        &self,
+        function_name: &str,
+    ) -> Retained<ProtocolObject<dyn MTLComputePipelineState>> {
+        if let Some(pipeline_state) = self
+            .ivars()
+            .compute_pipeline_states
+            .borrow()
+            .get(function_name)
+        {
+            return pipeline_state.clone();
+        }
+
+        let function = self
+            .library()
+            .ok()
+            .and_then(|library| library.newFunctionWithName(&NSString::from_str(function_name)))
+            .expect("Failed to find the kernel function.");
+        let pipeline_state = self
+            .device()
+            .newComputePipelineStateWithFunction_error(&function)
+            .inspect_err(|error| {
+                self.log(
+                    LogLevel::Error,
+                    &format!("Pipeline creation failed: {}", error.localizedDescription()),
+                )
+            })
+            .expect("Failed to create a compute pipeline state.");
+        self.ivars()
+            .compute_pipeline_states
+            .borrow_mut()
+            .insert(function_name.to_owned(), pipeline_state.clone());
+        pipeline_state
+    }
+
+    // called every frame before the render callback to record the dispatches of the frame,
+    // they run in the same command buffer ahead of the draws
+    pub fn set_compute_callback(
+        &self,
+        compute_callback: impl Fn(&Self, &mut ComputePass) + 'static,
+    ) {
+        self.ivars()
+            .compute_callback
+            .replace(Some(Box::new(compute_callback)));
+    }
+}
@@ -0,0 +1,142 @@
+// core image filters run over the frames before they're presented, the filter library of apple
+// in place of post-processing shaders. core image renders on the command queue of the renderer
+// with the command buffer of the frame, from a copy of the drawable back into it
+use objc2::{
+    msg_send,
+    rc::Retained,
+    runtime::{AnyObject, ProtocolObject},
+    DeclaredClass,
+};
+use objc2_core_image::{kCIImageColorSpace, kCIInputImageKey, CIContext, CIFilter, CIImage};
+use objc2_foundation::{CGPoint, CGRect, CGSize, NSDictionary, NSObjectNSKeyValueCoding};
+use objc2_metal::{
+    MTLBlitCommandEncoder, MTLCommandBuffer, MTLCommandEncoder, MTLDevice, MTLStorageMode,
+    MTLTexture, MTLTextureDescriptor, MTLTextureUsage,
+};
+
+use crate::{CGColorSpaceCreateWithName, CGColorSpaceRelease, LogLevel, MetalRenderer};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+#[derive(Default)]
+pub(crate) struct ImageFilterState {
+    filters: Vec<Retained<CIFilter>>,
+    // created on the command queue of the renderer with the first filtered frame
+    context: Option<Retained<CIContext>>,
+    // the copy of the frame the filters read while core image writes the drawable
+    source: Option<Texture>,
+}
+
+impl ImageFilterState {
+    // drops the context and the copy of the frame, they're created again with the next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.context = None;
+        self.source = None;
+    }
+}
+
+impl MetalRenderer {
+    // runs the frames through `filters` before they're presented, each filter takes the output
+    // of the one before it as its input image. the text, the ui and the overlay are drawn over
+    // the filtered frames. the filters read the drawables, which stop being framebuffer only
+    pub fn set_image_filters(&self, filters: Vec<Retained<CIFilter>>) {
+        if !filters.is_empty() {
+            if let Some(surface) = self.ivars().surface.get() {
+                surface.set_framebuffer_only(false);
+            }
+        }
+        self.ivars().image_filters.borrow_mut().filters = filters;
+    }
+
+    pub fn image_filters(&self) -> Vec<Retained<CIFilter>> {
+        self.ivars().image_filters.borrow().filters.clone()
+    }
+
+    // filters what the passes of the frame drew into `drawable_texture`. a drawable created
+    // while the drawables were still framebuffer only can't be read, that frame stays unfiltered
+    pub(crate) fn encode_image_filters(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) {
+        let mut state = self.ivars().image_filters.borrow_mut();
+        if state.filters.is_empty() || drawable_texture.isFramebufferOnly() {
+            return;
+        }
+        let (width, height) = (drawable_texture.width(), drawable_texture.height());
+        let pixel_format = drawable_texture.pixelFormat();
+        let reusable = state.source.as_ref().is_some_and(|source| {
+            source.width() == width
+                && source.height() == height
+                && source.pixelFormat() == pixel_format
+        });
+        if !reusable {
+            let descriptor = unsafe {
+                MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                    pixel_format,
+                    width,
+                    height,
+                    false,
+                )
+            };
+            descriptor.setUsage(MTLTextureUsage::ShaderRead);
+            descriptor.setStorageMode(MTLStorageMode::Private);
+            state.source = self.device().newTextureWithDescriptor(&descriptor);
+        }
+        let Some(source) = state.source.clone() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a texture for the image filters.",
+            );
+            return;
+        };
+        let Some(encoder) = command_buffer.blitCommandEncoder() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a blit encoder for the image filters.",
+            );
+            return;
+        };
+        unsafe { encoder.copyFromTexture_toTexture(drawable_texture, &source) };
+        encoder.endEncoding();
+
+        let context = state
+            .context
+            .get_or_insert_with(|| unsafe {
+                CIContext::contextWithMTLCommandQueue(&self.command_queue())
+            })
+            .clone();
+        unsafe {
+            // the frame is read and written in the color space of the drawables
+            let color_space =
+                CGColorSpaceCreateWithName(self.color_space().cg_name(self.pixel_format()));
+            let options = Retained::retain(color_space.cast::<AnyObject>()).map(|color_space| {
+                NSDictionary::from_vec(&[kCIImageColorSpace], vec![color_space])
+            });
+            let image = CIImage::imageWithMTLTexture_options(&source, options.as_deref());
+            if let Some(image) = image {
+                // the pixels past the edges repeat the ones on them, so blurs don't darken
+                // the edges of the frame
+                let mut image = image.imageByClampingToExtent();
+                for filter in &state.filters {
+                    filter.setValue_forKey(Some(&image), kCIInputImageKey);
+                    if let Some(output) = filter.outputImage() {
+                        image = output;
+                    }
+                    // the filter would keep the frame alive otherwise
+                    filter.setValue_forKey(None, kCIInputImageKey);
+                }
+                let bounds = CGRect::new(CGPoint::ZERO, CGSize::new(width as f64, height as f64));
+                let _: () = msg_send![
+                    &context,
+                    render: &*image,
+                    toMTLTexture: drawable_texture,
+                    commandBuffer: command_buffer,
+                    bounds: bounds,
+                    colorSpace: color_space
+                ];
+            }
+            CGColorSpaceRelease(color_space);
+        }
+    }
+}
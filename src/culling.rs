@@ -0,0 +1,115 @@
+use crate::{camera::Matrix, RenderPass, VertexInput};
+
+// an axis aligned box, the bounds of a mesh in the space of its vertices or of a draw in the
+// world
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl BoundingBox {
+    // the box around `points`, an empty one at the origin without any
+    fn around(mut points: impl Iterator<Item = [f32; 3]>) -> Self {
+        let first = points.next().unwrap_or([0.; 3]);
+        points.fold(
+            BoundingBox {
+                min: first,
+                max: first,
+            },
+            |bounds, point| BoundingBox {
+                min: [0, 1, 2].map(|i| bounds.min[i].min(point[i])),
+                max: [0, 1, 2].map(|i| bounds.max[i].max(point[i])),
+            },
+        )
+    }
+
+    pub(crate) fn around_vertices(vertices: &[VertexInput]) -> Self {
+        Self::around(vertices.iter().map(|vertex| {
+            let position = vertex.position;
+            [position.x, position.y, position.z]
+        }))
+    }
+
+    // the smallest box around both
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: [0, 1, 2].map(|i| self.min[i].min(other.min[i])),
+            max: [0, 1, 2].map(|i| self.max[i].max(other.max[i])),
+        }
+    }
+
+    fn corners(&self) -> [[f32; 3]; 8] {
+        core::array::from_fn(|corner| {
+            [0, 1, 2].map(|i| {
+                if corner & (1 << i) == 0 {
+                    self.min[i]
+                } else {
+                    self.max[i]
+                }
+            })
+        })
+    }
+
+    // the box around this one after `transform`, column major. it grows with the rotations
+    pub fn transformed(&self, transform: &[[f32; 4]; 4]) -> BoundingBox {
+        Self::around(self.corners().into_iter().map(|corner| {
+            let [x, y, z, _] = transform_point(transform, corner);
+            [x, y, z]
+        }))
+    }
+
+    // whether all of the box lies beyond one of the planes of the view volume of
+    // `view_projection`, where metal clips from -w to w across the view and from 0 to w in depth
+    pub(crate) fn is_outside_view(&self, view_projection: &Matrix) -> bool {
+        let corners = self
+            .corners()
+            .map(|corner| transform_point(view_projection, corner));
+        let planes: [fn(&[f32; 4]) -> bool; 6] = [
+            |[x, _, _, w]| *x < -w,
+            |[x, _, _, w]| *x > *w,
+            |[_, y, _, w]| *y < -w,
+            |[_, y, _, w]| *y > *w,
+            |[_, _, z, _]| *z < 0.,
+            |[_, _, z, w]| *z > *w,
+        ];
+        planes.iter().any(|is_beyond| corners.iter().all(is_beyond))
+    }
+}
+
+// the point `transform` moves `[x, y, z]` to, before the divide by w
+fn transform_point(transform: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 4] {
+    [0, 1, 2, 3]
+        .map(|i| transform[0][i] * x + transform[1][i] * y + transform[2][i] * z + transform[3][i])
+}
+
+impl RenderPass {
+    // the world space box the last recorded draw stays within, it's left out of the frames
+    // where the box lies outside the view. draws without bounds are always encoded
+    pub fn with_bounds(&mut self, bounds: BoundingBox) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.bounds = Some(bounds);
+        }
+        self
+    }
+
+    // drops the draws with bounds outside the view of `view_projection`, also from the passes
+    // into render targets, which see the scene through the same camera. returns how many
+    // draws are left and how many were dropped
+    pub(crate) fn cull(&mut self, view_projection: &Matrix) -> (usize, usize) {
+        let count = self.items.len();
+        self.items.retain(|item| {
+            !item
+                .bounds
+                .is_some_and(|bounds| bounds.is_outside_view(view_projection))
+        });
+        let mut counts = (self.items.len(), count - self.items.len());
+        let offscreen = self.offscreen.iter_mut().map(|(_, pass)| pass);
+        let gbuffer = self.deferred.iter_mut().map(|pass| &mut pass.render_pass);
+        for render_pass in offscreen.chain(gbuffer) {
+            let (drawn, culled) = render_pass.cull(view_projection);
+            counts = (counts.0 + drawn, counts.1 + culled);
+        }
+        counts
+    }
+}
@@ -0,0 +1,117 @@
+use objc2_metal::MTLPackedFloat3;
+
+use crate::{camera::Matrix, MetalRenderer, PrimitiveType, RenderPass, VertexInput};
+
+fn vertex([x, y, z]: [f32; 3], [red, green, blue]: [f32; 3]) -> VertexInput {
+    VertexInput {
+        position: MTLPackedFloat3 { x, y, z },
+        color: MTLPackedFloat3 {
+            x: red,
+            y: green,
+            z: blue,
+        },
+    }
+}
+
+// the point `transform` moves `[x, y, z]` to
+fn transform_point(transform: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    let row = |i: usize| {
+        transform[0][i] * x + transform[1][i] * y + transform[2][i] * z + transform[3][i]
+    };
+    let w = row(3);
+    [row(0) / w, row(1) / w, row(2) / w]
+}
+
+// collects one pixel wide lines over a frame to show bounds, transforms and positions while
+// debugging. `flush` draws them all at once with `vertex_debug` and empties it, so they're
+// added again every frame they should be seen
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<VertexInput>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, from: [f32; 3], to: [f32; 3], color: [f32; 3]) {
+        self.vertices.push(vertex(from, color));
+        self.vertices.push(vertex(to, color));
+    }
+
+    // the edges of the axis aligned box between the corners `min` and `max`
+    pub fn aabb(&mut self, min: [f32; 3], max: [f32; 3], color: [f32; 3]) {
+        let corner = |i: usize| {
+            [
+                if i & 1 == 0 { min[0] } else { max[0] },
+                if i & 2 == 0 { min[1] } else { max[1] },
+                if i & 4 == 0 { min[2] } else { max[2] },
+            ]
+        };
+        // every pair of corners differing in a single axis
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corner(i), corner(i | axis), color);
+                }
+            }
+        }
+    }
+
+    // the x, y and z axes of `transform` in red, green and blue, `length` long before it's
+    // applied
+    pub fn axes(&mut self, transform: &Matrix, length: f32) {
+        let origin = transform_point(transform, [0., 0., 0.]);
+        let axes = [
+            ([length, 0., 0.], [1., 0., 0.]),
+            ([0., length, 0.], [0., 1., 0.]),
+            ([0., 0., length], [0., 0., 1.]),
+        ];
+        for (end, color) in axes {
+            self.line(origin, transform_point(transform, end), color);
+        }
+    }
+
+    // a square grid of `size` centered on `center` in the xz plane, the ground with y up, split
+    // into `divisions` cells along each side
+    pub fn grid(&mut self, center: [f32; 3], size: f32, divisions: usize, color: [f32; 3]) {
+        let divisions = divisions.max(1);
+        let half = size / 2.;
+        for i in 0..=divisions {
+            let offset = i as f32 / divisions as f32 * size - half;
+            self.line(
+                [center[0] + offset, center[1], center[2] - half],
+                [center[0] + offset, center[1], center[2] + half],
+                color,
+            );
+            self.line(
+                [center[0] - half, center[1], center[2] + offset],
+                [center[0] + half, center[1], center[2] + offset],
+                color,
+            );
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    // records the lines collected since the previous flush into `render_pass`
+    pub fn flush(&mut self, renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        render_pass.draw(
+            &renderer.render_pipeline_state("vertex_debug", "fragment_main"),
+            &renderer.frame_buffer(&self.vertices),
+            PrimitiveType::Line,
+            0..self.vertices.len(),
+        );
+        self.vertices.clear();
+    }
+}
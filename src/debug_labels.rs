@@ -0,0 +1,97 @@
+// names for the gpu captures of xcode and the faults of command buffers. the passes label
+// their encoders and group their draws and dispatches into nested debug groups, the frames
+// label their command buffers after the renderer and the resources of the application can be
+// labeled one by one, so a capture reads like the code that recorded it
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass, Message};
+use objc2_foundation::NSString;
+use objc2_metal::{MTLCommandBuffer, MTLCommandEncoder, MTLResource};
+
+use crate::{GpuBuffer, MetalRenderer, RenderPass};
+
+// the debug groups a draw or a dispatch was recorded in, outermost first
+pub(crate) type DebugGroups = Rc<[String]>;
+
+pub(crate) fn pushed(groups: &DebugGroups, name: &str) -> DebugGroups {
+    groups.iter().cloned().chain([name.to_owned()]).collect()
+}
+
+pub(crate) fn popped(groups: &DebugGroups) -> DebugGroups {
+    groups[..groups.len().saturating_sub(1)].into()
+}
+
+// closes the groups of `open` the next command isn't in and opens the ones of `groups` that
+// aren't open yet
+pub(crate) fn encode_debug_groups(
+    encoder: &(impl MTLCommandEncoder + Message),
+    open: &[String],
+    groups: &[String],
+) {
+    let shared = open
+        .iter()
+        .zip(groups)
+        .take_while(|(open, group)| open == group)
+        .count();
+    for _ in shared..open.len() {
+        encoder.popDebugGroup();
+    }
+    for group in &groups[shared..] {
+        encoder.pushDebugGroup(&NSString::from_str(group));
+    }
+}
+
+pub(crate) fn label_resource(resource: &(impl MTLResource + Message), label: &str) {
+    resource.setLabel(Some(&NSString::from_str(label)));
+}
+
+impl RenderPass {
+    // names the encoder of the pass, "Render pass" without a label. the passes of a render
+    // graph are labeled with their names
+    pub fn set_label(&mut self, label: &str) -> &mut Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    // the draws recorded until the matching `pop_debug_group` are grouped under `name`, inside
+    // the groups already open. a group without draws doesn't show up
+    pub fn push_debug_group(&mut self, name: &str) -> &mut Self {
+        self.debug_groups = pushed(&self.debug_groups, name);
+        self
+    }
+
+    pub fn pop_debug_group(&mut self) -> &mut Self {
+        self.debug_groups = popped(&self.debug_groups);
+        self
+    }
+
+    pub(crate) fn encoder_label(&self) -> Retained<NSString> {
+        NSString::from_str(self.label.as_deref().unwrap_or("Render pass"))
+    }
+}
+
+impl<T: Copy> GpuBuffer<T> {
+    pub fn set_label(&self, label: &str) {
+        label_resource(&**self.buffer(), label);
+    }
+}
+
+impl MetalRenderer {
+    // names the command buffers of the frames, e.g. after the window when several renderers
+    // share a capture. None leaves them as "Frame"
+    pub fn set_label(&self, label: Option<&str>) {
+        self.ivars().label.replace(label.map(str::to_owned));
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self.ivars().label.borrow().clone()
+    }
+
+    pub(crate) fn label_frame(&self, command_buffer: &ProtocolObject<dyn MTLCommandBuffer>) {
+        let label = match self.ivars().label.borrow().as_deref() {
+            Some(label) => format!("{label} frame"),
+            None => "Frame".to_owned(),
+        };
+        command_buffer.setLabel(Some(&NSString::from_str(&label)));
+    }
+}
@@ -0,0 +1,396 @@
+// the deferred path: the surfaces of a frame are written into a g-buffer of their albedo,
+// normal and depth first and lit once per pixel after it. apple gpus light the g-buffer in the
+// pass filling it, reading it from tile memory, the others write it out and light it in the
+// pass of the frame. either way the lit surfaces end up in the pass of the frame with their
+// depth, so the forward draws after them are depth tested against them. the g-buffer isn't
+// multisampled, and the frames of a render graph or of ray tracing stay forward
+use std::collections::HashMap;
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSSize;
+use objc2_metal::{
+    MTLBuffer, MTLClearColor, MTLCompareFunction, MTLCullMode, MTLDepthStencilDescriptor,
+    MTLDepthStencilState, MTLDevice, MTLLoadAction, MTLPixelFormat, MTLPrimitiveType,
+    MTLRenderCommandEncoder, MTLRenderPassDescriptor, MTLRenderPipelineState, MTLStoreAction,
+    MTLTexture, MTLTextureUsage, MTLTriangleFillMode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{invert, Matrix},
+    shadow::{ShadowMap, SHADOW_MAP_INDEX},
+    target::{attachment_texture, transient_attachment_texture},
+    MetalRenderer, PipelineDescriptor, RenderPass,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+type DepthStencilState = Retained<ProtocolObject<dyn MTLDepthStencilState>>;
+
+// the attachments of the g-buffer, `GBufferOutput` in triangle.metal. the lit surfaces at 0,
+// the albedo with the metalness in alpha, the world normal with the roughness in w and the
+// depth of the surfaces, which the lighting reads like the others
+const LIGHTING_FORMAT: MTLPixelFormat = MTLPixelFormat::RGBA16Float;
+const ALBEDO_FORMAT: MTLPixelFormat = MTLPixelFormat::RGBA8Unorm_sRGB;
+const NORMAL_FORMAT: MTLPixelFormat = MTLPixelFormat::RGBA16Float;
+const DEPTH_FORMAT: MTLPixelFormat = MTLPixelFormat::R32Float;
+// the depth buffer testing the surfaces of the g-buffer
+const DEPTH_BUFFER_FORMAT: MTLPixelFormat = MTLPixelFormat::Depth32Float;
+
+// how the frames of the render callback light their surfaces
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenderPath {
+    // every draw lights its fragments as it's drawn
+    #[default]
+    Forward,
+    // the draws into `RenderPass::deferred_pass` fill a g-buffer, lit once per pixel after it.
+    // it pays off for scenes where many fragments are drawn over each other
+    Deferred,
+}
+
+// the view the lighting reconstructs the positions of the surfaces with,
+// `DeferredProperties` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct DeferredProperties {
+    inverse_view_projection: Matrix,
+    // the origin and the size of the viewport in pixels
+    viewport: [f32; 4],
+}
+
+// the textures of the g-buffer, recreated when the size of the frames changes
+struct GBuffer {
+    size: (usize, usize),
+    // lit in tile memory, only the lighting and the depth are written out
+    tiled: bool,
+    lighting: Texture,
+    albedo: Texture,
+    normal: Texture,
+    depth: Texture,
+    depth_buffer: Texture,
+}
+
+impl GBuffer {
+    fn new(device: &ProtocolObject<dyn MTLDevice>, size: (usize, usize), tiled: bool) -> Self {
+        let usage = MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead;
+        let stored = |pixel_format| attachment_texture(device, pixel_format, size, 1, usage);
+        let transient = |pixel_format| transient_attachment_texture(device, pixel_format, size);
+        let (lighting, albedo, normal) = if tiled {
+            (
+                stored(LIGHTING_FORMAT),
+                transient(ALBEDO_FORMAT),
+                transient(NORMAL_FORMAT),
+            )
+        } else {
+            (
+                transient(LIGHTING_FORMAT),
+                stored(ALBEDO_FORMAT),
+                stored(NORMAL_FORMAT),
+            )
+        };
+        GBuffer {
+            size,
+            tiled,
+            lighting,
+            albedo,
+            normal,
+            depth: stored(DEPTH_FORMAT),
+            depth_buffer: transient(DEPTH_BUFFER_FORMAT),
+        }
+    }
+
+    // clears the g-buffer, keeping the attachments the pass of the frame reads
+    fn pass_descriptor(&self) -> Retained<MTLRenderPassDescriptor> {
+        let descriptor = MTLRenderPassDescriptor::renderPassDescriptor();
+        let attachments = [
+            (&self.lighting, self.tiled),
+            (&self.albedo, !self.tiled),
+            (&self.normal, !self.tiled),
+            (&self.depth, true),
+        ];
+        for (index, (texture, stored)) in attachments.into_iter().enumerate() {
+            let attachment = unsafe {
+                descriptor
+                    .colorAttachments()
+                    .objectAtIndexedSubscript(index)
+            };
+            attachment.setTexture(Some(texture));
+            attachment.setLoadAction(MTLLoadAction::Clear);
+            // the depth starts at the far plane, where the lighting leaves the background
+            let far = if texture.pixelFormat() == DEPTH_FORMAT {
+                1.
+            } else {
+                0.
+            };
+            attachment.setClearColor(MTLClearColor {
+                red: far,
+                green: 0.,
+                blue: 0.,
+                alpha: 0.,
+            });
+            attachment.setStoreAction(if stored {
+                MTLStoreAction::Store
+            } else {
+                MTLStoreAction::DontCare
+            });
+        }
+        let depth_attachment = descriptor.depthAttachment();
+        depth_attachment.setTexture(Some(&self.depth_buffer));
+        depth_attachment.setLoadAction(MTLLoadAction::Clear);
+        depth_attachment.setStoreAction(MTLStoreAction::DontCare);
+        depth_attachment.setClearDepth(1.);
+        descriptor
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct DeferredState {
+    render_path: RenderPath,
+    // the pipelines drawing into the g-buffer by their vertex and fragment functions
+    pipeline_states: HashMap<(String, String), PipelineState>,
+    gbuffer: Option<GBuffer>,
+    // the depth tests of the lighting, passing everywhere and writing the depth of the surfaces
+    // into the pass of the frame or leaving the one of the g-buffer
+    depth_stencil_states: Option<[DepthStencilState; 2]>,
+}
+
+impl DeferredState {
+    pub(crate) fn clear_pipeline_states(&mut self) {
+        self.pipeline_states.clear();
+    }
+
+    // drops the textures and the pipelines, they're created again with the next deferred frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.pipeline_states.clear();
+        self.gbuffer = None;
+        self.depth_stencil_states = None;
+    }
+}
+
+// the pass filling the g-buffer, encoded ahead of the pass of the frame
+pub(crate) struct DeferredPass {
+    pub(crate) render_pass: RenderPass,
+    pub(crate) pass_descriptor: Retained<MTLRenderPassDescriptor>,
+}
+
+// the draw lighting the g-buffer across the viewport, or copying what was lit into the pass of
+// the frame
+pub(crate) struct DeferredLighting {
+    pipeline_state: PipelineState,
+    textures: Vec<Texture>,
+    depth_stencil_state: DepthStencilState,
+    properties: DeferredProperties,
+    shadow_map: Option<ShadowMap>,
+    // drawn after the draws of the pass filling the g-buffer, before the draws of the pass of
+    // the frame otherwise
+    pub(crate) after_draws: bool,
+}
+
+impl DeferredLighting {
+    pub(crate) fn encode(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+    ) {
+        encoder.setRenderPipelineState(&self.pipeline_state);
+        encoder.setDepthStencilState(Some(&self.depth_stencil_state));
+        encoder.setCullMode(MTLCullMode::None);
+        encoder.setTriangleFillMode(MTLTriangleFillMode::Fill);
+        unsafe {
+            encoder.setFragmentBuffer_offset_atIndex(Some(scene_properties), 0, 0);
+            encoder.setFragmentBytes_length_atIndex(
+                NonNull::from(&self.properties).cast::<c_void>(),
+                core::mem::size_of_val(&self.properties),
+                1,
+            );
+            for (index, texture) in self.textures.iter().enumerate() {
+                encoder.setFragmentTexture_atIndex(Some(texture), index);
+            }
+            if let Some((shadow_map, sampler)) = &self.shadow_map {
+                encoder.setFragmentTexture_atIndex(Some(shadow_map), SHADOW_MAP_INDEX);
+                encoder.setFragmentSamplerState_atIndex(Some(sampler), SHADOW_MAP_INDEX);
+            }
+            // one triangle covering the viewport, the shader generates its vertices
+            encoder.drawPrimitives_vertexStart_vertexCount(MTLPrimitiveType::Triangle, 0, 3);
+        }
+    }
+}
+
+impl RenderPass {
+    // the pass filling the g-buffer of a deferred frame, none in forward frames. its draws
+    // need pipelines from `MetalRenderer::gbuffer_pipeline_state`, like `Scene::draw` picks
+    pub fn deferred_pass(&mut self) -> Option<&mut RenderPass> {
+        self.deferred
+            .as_mut()
+            .map(|deferred| &mut deferred.render_pass)
+    }
+}
+
+impl MetalRenderer {
+    pub fn render_path(&self) -> RenderPath {
+        self.ivars().deferred.borrow().render_path
+    }
+
+    // the path of the frames of the render callback from the next frame on. deferred frames
+    // need a depth buffer, without one they're drawn forward
+    pub fn set_render_path(&self, render_path: RenderPath) {
+        self.ivars().deferred.borrow_mut().render_path = render_path;
+        self.set_needs_redraw();
+    }
+
+    // the pipeline of a draw into the g-buffer, with `fragment_gbuffer` for the surfaces of
+    // `vertex_pbr`. it's created on first use
+    pub fn gbuffer_pipeline_state(
+        &self,
+        vertex_function: &str,
+        fragment_function: &str,
+    ) -> PipelineState {
+        let key = (vertex_function.to_owned(), fragment_function.to_owned());
+        if let Some(pipeline_state) = self.ivars().deferred.borrow().pipeline_states.get(&key) {
+            return pipeline_state.clone();
+        }
+        let descriptor = PipelineDescriptor {
+            color_format: LIGHTING_FORMAT,
+            depth_format: Some(DEPTH_BUFFER_FORMAT),
+            sample_count: 1,
+            ..self.pipeline_descriptor(vertex_function, fragment_function)
+        };
+        let library = self
+            .library()
+            .expect("Failed to create a g-buffer pipeline state.");
+        let pipeline_descriptor = self.mtl_pipeline_descriptor(&library, &descriptor);
+        let formats = [(1, ALBEDO_FORMAT), (2, NORMAL_FORMAT), (3, DEPTH_FORMAT)];
+        for (index, pixel_format) in formats {
+            unsafe {
+                pipeline_descriptor
+                    .colorAttachments()
+                    .objectAtIndexedSubscript(index)
+            }
+            .setPixelFormat(pixel_format);
+        }
+        let pipeline_state = self
+            .device()
+            .newRenderPipelineStateWithDescriptor_error(&pipeline_descriptor)
+            .expect("Failed to create a g-buffer pipeline state.");
+        self.ivars()
+            .deferred
+            .borrow_mut()
+            .pipeline_states
+            .insert(key, pipeline_state.clone());
+        pipeline_state
+    }
+
+    // sets up the g-buffer pass of a deferred frame in `render_pass`, with its culling, fill
+    // mode, viewport and depth test, and the lighting of it. nothing happens in forward frames
+    pub(crate) fn prepare_deferred(
+        &self,
+        render_pass: &mut RenderPass,
+        render_size: NSSize,
+        view_projection: &Matrix,
+        shadow_map: Option<ShadowMap>,
+    ) {
+        if self.render_path() != RenderPath::Deferred || self.depth_format().is_none() {
+            return;
+        }
+        let size = (render_size.width as usize, render_size.height as usize);
+        let tiled = self.supports_tile_shading();
+        let ([lighting, albedo, normal, depth], pass_descriptor) = {
+            let mut state = self.ivars().deferred.borrow_mut();
+            let current = state
+                .gbuffer
+                .as_ref()
+                .is_some_and(|gbuffer| gbuffer.size == size && gbuffer.tiled == tiled);
+            if !current {
+                state.gbuffer = Some(GBuffer::new(&self.device(), size, tiled));
+            }
+            let gbuffer = state.gbuffer.as_ref().unwrap();
+            let textures = [
+                &gbuffer.lighting,
+                &gbuffer.albedo,
+                &gbuffer.normal,
+                &gbuffer.depth,
+            ]
+            .map(|texture| texture.clone());
+            (textures, gbuffer.pass_descriptor())
+        };
+        let [writing, keeping] = self.deferred_depth_stencil_states();
+
+        let viewport = render_pass
+            .viewport
+            .unwrap_or_else(|| self.viewport(render_size));
+        let properties = DeferredProperties {
+            inverse_view_projection: invert(view_projection),
+            viewport: [
+                viewport.originX as f32,
+                viewport.originY as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+            ],
+        };
+        let mut gbuffer_pass = RenderPass {
+            cull_mode: render_pass.cull_mode,
+            front_facing: render_pass.front_facing,
+            fill_mode: render_pass.fill_mode,
+            viewport: Some(viewport),
+            scissor: render_pass.scissor,
+            depth_stencil_state: render_pass.depth_stencil_state.clone(),
+            label: Some("G-buffer pass".to_owned()),
+            ..Default::default()
+        };
+        let resolve = if tiled {
+            gbuffer_pass.deferred_lighting = Some(DeferredLighting {
+                pipeline_state: self
+                    .gbuffer_pipeline_state("vertex_fullscreen", "fragment_deferred_tile"),
+                textures: Vec::new(),
+                depth_stencil_state: keeping,
+                properties,
+                shadow_map,
+                after_draws: true,
+            });
+            DeferredLighting {
+                pipeline_state: self
+                    .render_pipeline_state("vertex_fullscreen", "fragment_deferred_composite"),
+                textures: vec![lighting, depth],
+                depth_stencil_state: writing,
+                properties,
+                shadow_map: None,
+                after_draws: false,
+            }
+        } else {
+            DeferredLighting {
+                pipeline_state: self
+                    .render_pipeline_state("vertex_fullscreen", "fragment_deferred_lighting"),
+                textures: vec![albedo, normal, depth],
+                depth_stencil_state: writing,
+                properties,
+                shadow_map,
+                after_draws: false,
+            }
+        };
+        render_pass.deferred_lighting = Some(resolve);
+        render_pass.deferred = Some(Box::new(DeferredPass {
+            render_pass: gbuffer_pass,
+            pass_descriptor,
+        }));
+    }
+
+    fn deferred_depth_stencil_states(&self) -> [DepthStencilState; 2] {
+        self.ivars()
+            .deferred
+            .borrow_mut()
+            .depth_stencil_states
+            .get_or_insert_with(|| {
+                [true, false].map(|write| {
+                    let descriptor = unsafe { MTLDepthStencilDescriptor::new() };
+                    descriptor.setDepthCompareFunction(MTLCompareFunction::Always);
+                    descriptor.setDepthWriteEnabled(write);
+                    self.device()
+                        .newDepthStencilStateWithDescriptor(&descriptor)
+                        .expect("Failed to create the deferred depth stencil state.")
+                })
+            })
+            .clone()
+    }
+}
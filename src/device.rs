@@ -0,0 +1,394 @@
+#[cfg(target_os = "macos")]
+use core::ptr::NonNull;
+use std::{
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(target_os = "macos")]
+use block2::{Block, RcBlock};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+#[cfg(target_os = "macos")]
+use objc2_foundation::{NSArray, NSObject, NSString};
+use objc2_metal::{MTLCommandBuffer, MTLCreateSystemDefaultDevice, MTLDevice};
+#[cfg(target_os = "macos")]
+use objc2_metal::{
+    MTLCopyAllDevices, MTLDeviceRemovalRequestedNotification, MTLDeviceWasAddedNotification,
+    MTLDeviceWasRemovedNotification, MTLRemoveDeviceObserver,
+};
+
+use crate::{pipeline_cache::default_archive_path, FrameAllocator, LogLevel, MetalRenderer};
+
+type Device = Retained<ProtocolObject<dyn MTLDevice>>;
+
+#[cfg(target_os = "macos")]
+type DeviceHandler = Block<dyn Fn(NonNull<ProtocolObject<dyn MTLDevice>>, NonNull<NSString>)>;
+
+// the variant taking an observer isn't in the bindings
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn MTLCopyAllDevicesWithObserver(
+        observer: *mut *mut NSObject,
+        handler: &DeviceHandler,
+    ) -> *mut NSArray<ProtocolObject<dyn MTLDevice>>;
+}
+
+// a gpu of the mac as `available_devices` lists it
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    // identifies the gpu in the io registry, stable while the mac runs
+    pub registry_id: u64,
+    // an integrated gpu, which draws less power than a discrete one
+    pub low_power: bool,
+    // an external gpu, which can be unplugged while in use
+    pub removable: bool,
+    // not connected to any display
+    pub headless: bool,
+    // shares its memory with the cpu, the apple silicon and integrated gpus
+    pub unified_memory: bool,
+    // the bytes the gpu can use before its performance suffers
+    pub memory_budget: u64,
+}
+
+impl DeviceInfo {
+    fn new(device: &ProtocolObject<dyn MTLDevice>) -> Self {
+        Self {
+            name: device.name().to_string(),
+            registry_id: device.registryID(),
+            low_power: device.isLowPower(),
+            removable: device.isRemovable(),
+            headless: device.isHeadless(),
+            unified_memory: device.hasUnifiedMemory(),
+            memory_budget: device.recommendedMaxWorkingSetSize(),
+        }
+    }
+}
+
+// the gpus of the mac, the system default first
+pub fn available_devices() -> Vec<DeviceInfo> {
+    all_devices()
+        .iter()
+        .map(|device| DeviceInfo::new(device))
+        .collect()
+}
+
+fn system_default_device() -> Option<Device> {
+    unsafe { Retained::from_raw(MTLCreateSystemDefaultDevice()) }
+}
+
+#[cfg(target_os = "macos")]
+fn all_devices() -> Vec<Device> {
+    let devices = unsafe { Retained::from_raw(MTLCopyAllDevices().as_ptr()) };
+    let mut devices = devices.map_or_else(Vec::new, |devices| devices.to_vec_retained());
+    if let Some(default) = system_default_device() {
+        let default_id = default.registryID();
+        devices.sort_by_key(|device| device.registryID() != default_id);
+    }
+    devices
+}
+
+// an ios device has a single gpu
+#[cfg(target_os = "ios")]
+fn all_devices() -> Vec<Device> {
+    system_default_device().into_iter().collect()
+}
+
+// which gpu `MetalRenderer::init` renders on, set with `MetalRenderer::set_device_selector`. when
+// no gpu matches the system default is used
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    // the gpu macos picks, the discrete one of macs with two while on power
+    #[default]
+    SystemDefault,
+    // an integrated gpu, to save the battery
+    LowPower,
+    // a discrete or external gpu, the one with the most memory when there are several
+    HighPerformance,
+    // an external gpu
+    Removable,
+    // the first gpu with the string in its name, ignoring case
+    Name(String),
+    // the gpu with the `DeviceInfo::registry_id`
+    RegistryId(u64),
+}
+
+impl DeviceSelector {
+    // the device the selector picks, none without a match
+    pub fn select(&self) -> Option<Device> {
+        let devices = || all_devices().into_iter();
+        match self {
+            DeviceSelector::SystemDefault => system_default_device(),
+            DeviceSelector::LowPower => devices().find(|device| device.isLowPower()),
+            DeviceSelector::HighPerformance => devices()
+                .filter(|device| !device.isLowPower())
+                .max_by_key(|device| device.recommendedMaxWorkingSetSize()),
+            DeviceSelector::Removable => devices().find(|device| device.isRemovable()),
+            DeviceSelector::Name(name) => {
+                let name = name.to_lowercase();
+                devices().find(|device| device.name().to_string().to_lowercase().contains(&name))
+            }
+            DeviceSelector::RegistryId(registry_id) => {
+                devices().find(|device| device.registryID() == *registry_id)
+            }
+        }
+    }
+}
+
+impl MetalRenderer {
+    // picks the gpu `init` creates the renderer on, only before it. a renderer sharing the
+    // device of another ignores it
+    pub fn set_device_selector(&self, selector: DeviceSelector) {
+        if self.ivars().device.borrow().is_some() {
+            self.log(
+                LogLevel::Warn,
+                "The device can only be selected before init.",
+            );
+            return;
+        }
+        self.ivars().device_selector.replace(selector);
+    }
+
+    // the device the selector picks, falling back to the system default
+    pub(crate) fn select_device(&self) -> Option<Device> {
+        let selector = self.ivars().device_selector.borrow();
+        selector.select().or_else(|| {
+            let message = format!("No device matches {selector:?}, using the system default.");
+            self.log(LogLevel::Warn, &message);
+            system_default_device()
+        })
+    }
+
+    // the attributes of the device the renderer uses
+    pub fn device_info(&self) -> DeviceInfo {
+        DeviceInfo::new(&self.device())
+    }
+}
+
+// what happened to a gpu, as the os notifies it. only macos has gpus that come and go
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(target_os = "ios", allow(dead_code))]
+enum DeviceEvent {
+    Added,
+    // the user asked to unplug it, it still works until it's released
+    RemovalRequested,
+    // unplugged without warning, the work on it fails
+    Removed,
+}
+
+// the gpus plugged in and out while the renderer runs. the os notifies them on a thread of its
+// own, they're picked up by the next frame
+pub(crate) struct DeviceObserver {
+    #[cfg(target_os = "macos")]
+    observer: Retained<NSObject>,
+    // the registry id and the name of the device of every notification since the last frame
+    events: Arc<Mutex<Vec<(u64, String, DeviceEvent)>>>,
+}
+
+impl DeviceObserver {
+    #[cfg(target_os = "macos")]
+    pub(crate) fn new() -> Option<Self> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let handler = RcBlock::new({
+            let events = events.clone();
+            move |device: NonNull<ProtocolObject<dyn MTLDevice>>, name: NonNull<NSString>| {
+                let (device, name) = unsafe { (device.as_ref(), name.as_ref()) };
+                let event = unsafe {
+                    if name == MTLDeviceWasAddedNotification {
+                        DeviceEvent::Added
+                    } else if name == MTLDeviceRemovalRequestedNotification {
+                        DeviceEvent::RemovalRequested
+                    } else if name == MTLDeviceWasRemovedNotification {
+                        DeviceEvent::Removed
+                    } else {
+                        return;
+                    }
+                };
+                let mut events = events.lock().unwrap();
+                events.push((device.registryID(), device.name().to_string(), event));
+            }
+        });
+        let mut observer = core::ptr::null_mut();
+        unsafe {
+            let devices = MTLCopyAllDevicesWithObserver(&mut observer, &handler);
+            drop(Retained::from_raw(devices));
+            Some(Self {
+                observer: Retained::retain(observer)?,
+                events,
+            })
+        }
+    }
+
+    // there's nothing to observe on ios
+    #[cfg(target_os = "ios")]
+    pub(crate) fn new() -> Option<Self> {
+        None
+    }
+
+    fn take_events(&self) -> Vec<(u64, String, DeviceEvent)> {
+        core::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for DeviceObserver {
+    fn drop(&mut self) {
+        unsafe { MTLRemoveDeviceObserver(&self.observer) };
+    }
+}
+
+impl MetalRenderer {
+    // called once the renderer moved to another gpu, after its device was unplugged. the
+    // buffers, textures and pipelines of the application belong to the old device and have to
+    // be created again, along with what was handed to the renderer like the skybox, the
+    // acceleration structure or a library from `load_library`. a shadow casting light stays
+    pub fn on_device_change(&self, device_change_handler: impl Fn(&Self) + 'static) {
+        self.ivars()
+            .device_change_handler
+            .replace(Some(Box::new(device_change_handler)));
+    }
+
+    // handles the gpus plugged in and out since the last frame, before it touches the device
+    pub(crate) fn check_devices(&self) {
+        let Some(device_observer) = self.ivars().device_observer.get() else {
+            return;
+        };
+        let events = device_observer.take_events();
+        let registry_id = self.device().registryID();
+        let mut removed = false;
+        for (id, name, event) in events {
+            match event {
+                DeviceEvent::Added => {
+                    self.log(LogLevel::Info, &format!("Device {name} was added."))
+                }
+                DeviceEvent::RemovalRequested | DeviceEvent::Removed if id == registry_id => {
+                    removed = true
+                }
+                DeviceEvent::RemovalRequested | DeviceEvent::Removed => {
+                    self.log(LogLevel::Info, &format!("Device {name} was removed."))
+                }
+            }
+        }
+        if removed {
+            self.handle_device_removal(registry_id);
+        }
+    }
+
+    // moves the renderer off the removed device, onto the one the selector picks or any other
+    fn handle_device_removal(&self, removed: u64) {
+        let selected = self.ivars().device_selector.borrow().select();
+        let Some(device) = selected
+            .filter(|device| device.registryID() != removed)
+            .or_else(|| {
+                all_devices()
+                    .into_iter()
+                    .find(|device| device.registryID() != removed)
+            })
+        else {
+            let message = "The device is being removed and there's no other to render on.";
+            self.log(LogLevel::Error, message);
+            return;
+        };
+        // the frames in flight finish on the old device, or fail once it's gone
+        if let Some(command_buffer) = self.ivars().last_command_buffer.take() {
+            unsafe { command_buffer.waitUntilCompleted() };
+        }
+        let message = format!(
+            "Device {} is being removed, moving to {}.",
+            self.device().name(),
+            device.name()
+        );
+        self.log(LogLevel::Warn, &message);
+        self.move_to_device(device);
+    }
+
+    // recreates the queue, the view binding, the shaders and the pipelines on `device`, and
+    // gives up the rest of what was created on the old one
+    pub(crate) fn move_to_device(&self, device: Device) {
+        let command_queue = device
+            .newCommandQueue()
+            .expect("Failed to create a command queue.");
+        self.ivars().device.replace(Some(device.clone()));
+        self.ivars().command_queue.replace(Some(command_queue));
+        let frames = FrameAllocator::new(&device).expect("Failed to create a uniform buffer.");
+        self.ivars().frames.replace(Some(Rc::new(frames)));
+        self.ivars().surface.get().unwrap().set_device(&device);
+
+        // the pipelines wait for the library again, drawn with placeholders meanwhile
+        self.ivars().pending_library.replace(None);
+        self.ivars().placeholder_library.replace(None);
+        self.ivars().placeholder_pipelines.borrow_mut().clear();
+        if self.load_shaders().is_err() {
+            return;
+        }
+        self.ivars().pipeline_archive.replace(None);
+        if let Some(path) = default_archive_path(&device) {
+            self.open_pipeline_archive(path);
+        }
+        self.clear_pipeline_caches();
+
+        self.ivars().gpu_timer.replace(None);
+        self.ivars().graph_textures.borrow_mut().clear();
+        self.ivars().graph_allocator.replace(None);
+        self.ivars().purgeable.borrow_mut().clear();
+        self.ivars().shadows.borrow_mut().release_device_resources();
+        self.ivars().lights.borrow_mut().release_device_resources();
+        self.ivars().skybox.take();
+        self.ivars().depth_stencil_states.borrow_mut().clear();
+        self.ivars().frame_events.borrow_mut().clear();
+        self.ivars().upload_queue.take();
+        self.move_output_surface(&device);
+        self.ivars()
+            .deferred
+            .borrow_mut()
+            .release_device_resources();
+        self.ivars()
+            .post_process
+            .borrow_mut()
+            .release_device_resources();
+        self.ivars()
+            .rasterization_rate
+            .borrow_mut()
+            .release_device_resources();
+        self.ivars()
+            .picking
+            .borrow_mut()
+            .release_device_resources();
+        #[cfg(feature = "metalfx")]
+        self.ivars()
+            .upscaling
+            .borrow_mut()
+            .release_device_resources();
+        #[cfg(feature = "core-image")]
+        self.ivars()
+            .image_filters
+            .borrow_mut()
+            .release_device_resources();
+        self.ivars()
+            .ray_tracing
+            .borrow_mut()
+            .release_device_resources();
+        #[cfg(feature = "egui")]
+        self.ivars().egui.take();
+        #[cfg(feature = "imgui")]
+        self.ivars().imgui.replace(None);
+
+        // the geometry of `set_vertices` is uploaded again, the depth stencil state and the
+        // default pipeline are recreated with the formats of the view
+        let vertices = self
+            .ivars()
+            .vertex_buffer
+            .borrow()
+            .as_ref()
+            .map(|vertex_buffer| vertex_buffer.contents().to_vec());
+        if let Some(vertices) = vertices {
+            self.set_vertices(&vertices);
+        }
+        self.set_depth_format(self.depth_format());
+        self.set_pixel_format(self.pixel_format());
+
+        if let Some(device_change_handler) = self.ivars().device_change_handler.borrow().as_ref() {
+            device_change_handler(self);
+        }
+    }
+}
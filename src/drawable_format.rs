@@ -0,0 +1,73 @@
+// switching the format of the drawables while the renderer runs, e.g. between sdr and hdr or
+// between the sRGB and the Display P3 gamut. the pipelines the renderer cached for the format
+// it leaves are built again for the new one right away, through the same pipeline cache, so
+// the frames after the switch don't stall on them one by one
+use objc2::DeclaredClass;
+use objc2_metal::MTLPixelFormat;
+
+use crate::{ColorSpace, LogLevel, MetalRenderer, PipelineDescriptor, PixelFormat};
+
+pub(crate) type DrawableFormatHandler = Box<dyn Fn(&MetalRenderer, PixelFormat, ColorSpace)>;
+
+impl MetalRenderer {
+    // switches the drawable to `pixel_format` tagged with `color_space` in one go, rather than
+    // reconfiguring it twice with `set_pixel_format` and `set_color_space`
+    pub fn set_drawable_format(&self, pixel_format: PixelFormat, color_space: ColorSpace) {
+        let previous_format = (self.pixel_format(), self.color_space());
+        let previous_color_format = self.color_format();
+        self.configure_drawable(pixel_format, color_space);
+        self.ivars().pixel_format.set(pixel_format);
+        self.ivars().color_space.set(color_space);
+        let message = format!("Using pixel format {pixel_format:?} in {color_space:?}.");
+        self.log(LogLevel::Info, &message);
+        // the pipelines are cached by pixel format, build the default one right away
+        self.pipeline_state();
+        if previous_format == (pixel_format, color_space) {
+            return;
+        }
+        self.rebuild_pipelines(previous_color_format);
+        self.set_needs_redraw();
+        if let Some(drawable_format_handler) =
+            self.ivars().drawable_format_handler.borrow().as_ref()
+        {
+            drawable_format_handler(self, pixel_format, color_space);
+        }
+    }
+
+    // called with the new format after the drawable switched to it, to recreate resources that
+    // have to match the drawable like an output surface or the render targets it's copied from
+    pub fn on_drawable_format_change(
+        &self,
+        drawable_format_handler: impl Fn(&Self, PixelFormat, ColorSpace) + 'static,
+    ) {
+        self.ivars()
+            .drawable_format_handler
+            .replace(Some(Box::new(drawable_format_handler)));
+    }
+
+    // builds the pipelines cached for `previous_color_format` in the color format the draws
+    // have now. a post-processed frame draws in its own format either way
+    fn rebuild_pipelines(&self, previous_color_format: MTLPixelFormat) {
+        let color_format = self.color_format();
+        if color_format == previous_color_format {
+            return;
+        }
+        let descriptors = self
+            .ivars()
+            .pipeline_cache
+            .borrow()
+            .descriptors_with_color_format(previous_color_format);
+        for descriptor in descriptors {
+            let descriptor = PipelineDescriptor {
+                color_format,
+                ..descriptor
+            };
+            // the draws of the pipeline report it again when they use it
+            if let Err(error) = self.try_render_pipeline_state_for(&descriptor) {
+                self.log(LogLevel::Warn, &format!("{error}."));
+            }
+        }
+        let message = format!("Rebuilt the pipelines for {color_format:?}.");
+        self.log(LogLevel::Debug, &message);
+    }
+}
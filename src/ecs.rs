@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, rc::Rc};
+
+use hecs::World;
+
+use crate::{
+    animation::NodeTransform, scene_graph::draw_instances, Camera, InstanceData, Material, Mesh,
+    MetalRenderer, RenderPass,
+};
+
+// where an entity is drawn, the rotation is a unit quaternion with w last
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: [0.; 3],
+            rotation: [0., 0., 0., 1.],
+            scale: [1.; 3],
+        }
+    }
+}
+
+impl Transform {
+    // scales, then rotates, then translates. column major
+    pub fn matrix(&self) -> [[f32; 4]; 4] {
+        NodeTransform {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale: self.scale,
+        }
+        .matrix()
+    }
+}
+
+// a mesh of `RenderAssets`. the components of a world have to be sendable between threads,
+// which the meshes and materials holding metal objects aren't, so the entities refer to them
+// through handles
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshHandle(usize);
+
+// a material of `RenderAssets`, entities without one are drawn with `vertex_instanced` and
+// `fragment_main`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialHandle(usize);
+
+// the meshes and materials the entities of a world refer to
+#[derive(Default)]
+pub struct RenderAssets {
+    meshes: Vec<Rc<Mesh>>,
+    materials: Vec<Rc<Material>>,
+}
+
+impl RenderAssets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_mesh(&mut self, mesh: Rc<Mesh>) -> MeshHandle {
+        self.meshes.push(mesh);
+        MeshHandle(self.meshes.len() - 1)
+    }
+
+    pub fn add_material(&mut self, material: Rc<Material>) -> MaterialHandle {
+        self.materials.push(material);
+        MaterialHandle(self.materials.len() - 1)
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> &Rc<Mesh> {
+        &self.meshes[handle.0]
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> &Rc<Material> {
+        &self.materials[handle.0]
+    }
+}
+
+impl MetalRenderer {
+    // records the draws of the entities of `world` with a `Transform` and a `MeshHandle`,
+    // built anew from a query every frame. the entities sharing a mesh and a material are
+    // drawn together as the instances of a single draw, ordered by their handles
+    pub fn draw_world(&self, world: &World, assets: &RenderAssets, render_pass: &mut RenderPass) {
+        let mut draws: BTreeMap<_, Vec<InstanceData>> = BTreeMap::new();
+        let mut query = world.query::<(&Transform, &MeshHandle, Option<&MaterialHandle>)>();
+        for (_, (transform, &mesh, material)) in query.iter() {
+            let material = material.copied();
+            let color = material.map_or([1.; 4], |material| assets.material(material).color);
+            draws
+                .entry((mesh, material))
+                .or_default()
+                .push(InstanceData {
+                    transform: transform.matrix(),
+                    color,
+                });
+        }
+        for ((mesh, material), instances) in draws {
+            let material = material.map(|material| assets.material(material).as_ref());
+            draw_instances(self, render_pass, assets.mesh(mesh), material, &instances);
+        }
+    }
+
+    // views the frames through the `Camera` of the first entity with one, keeping the camera
+    // when there is none. called from the update callback, before the frame is recorded
+    pub fn sync_camera(&self, world: &World) {
+        let mut query = world.query::<&Camera>();
+        if let Some((_, &camera)) = query.iter().next() {
+            self.set_camera(camera);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::{collections::HashMap, time::Instant};
+
+use egui::{
+    epaint::Primitive, ClippedPrimitive, Context, Event, ImageData, Modifiers, MouseWheelUnit,
+    PointerButton, Pos2, RawInput, Rect, TextureFilter, TextureId, TextureOptions, TextureWrapMode,
+    TexturesDelta, Vec2, ViewportId,
+};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLDevice, MTLPixelFormat, MTLRenderPipelineState, MTLSamplerAddressMode, MTLSamplerDescriptor,
+    MTLSamplerMinMagFilter, MTLSamplerState, MTLTexture,
+};
+use tao::{
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{Key, KeyCode, ModifiersState},
+};
+
+use crate::{
+    ui::{
+        clipboard_text, create_ui_texture, scissor_rect, set_clipboard_text, upload_ui_texture,
+        UiDraw, UiMesh, UiVertex,
+    },
+    LogLevel, MetalRenderer,
+};
+
+// a texture egui draws with and the sampler its options ask for
+type EguiTexture = (
+    Retained<ProtocolObject<dyn MTLTexture>>,
+    Retained<ProtocolObject<dyn MTLSamplerState>>,
+);
+
+pub(crate) type EguiCallback = Box<dyn Fn(&MetalRenderer, &Context)>;
+
+fn egui_modifiers(modifiers: ModifiersState) -> Modifiers {
+    Modifiers {
+        alt: modifiers.alt_key(),
+        ctrl: modifiers.control_key(),
+        shift: modifiers.shift_key(),
+        mac_cmd: modifiers.super_key(),
+        command: modifiers.super_key(),
+    }
+}
+
+// the egui key of a physical key, KeyA is A and Digit1 is Num1
+fn egui_physical_key(key: KeyCode) -> Option<egui::Key> {
+    let name = format!("{key:?}");
+    egui::Key::from_name(name.strip_prefix("Key").unwrap_or(&name))
+}
+
+// the egui key of a key of the keyboard layout
+fn egui_logical_key(key: &Key) -> Option<egui::Key> {
+    match key {
+        Key::Character(character) => egui::Key::from_name(character),
+        key => egui::Key::from_name(&format!("{key:?}")),
+    }
+}
+
+// the input collected for the next egui frame, and the gpu resources of its output
+#[derive(Default)]
+pub(crate) struct EguiState {
+    context: Context,
+    events: Vec<Event>,
+    modifiers: Modifiers,
+    // in points, egui needs it for the button events
+    pointer_position: Option<Pos2>,
+    focused: bool,
+    start_time: Option<Instant>,
+    textures: HashMap<TextureId, EguiTexture>,
+    // created for the pixel format of the drawable
+    pipeline_state: Option<(
+        MTLPixelFormat,
+        Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    )>,
+}
+
+impl EguiState {
+    // drops the pipeline, it's recreated from the current shader library on the next frame
+    pub(crate) fn clear_pipeline_state(&mut self) {
+        self.pipeline_state = None;
+    }
+
+    // translates a window event into the egui events it stands for
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent, scale_factor: f64) {
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = egui_modifiers(*modifiers),
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = position.to_logical::<f32>(scale_factor);
+                let position = Pos2::new(position.x, position.y);
+                self.pointer_position = Some(position);
+                self.events.push(Event::PointerMoved(position));
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.pointer_position = None;
+                self.events.push(Event::PointerGone);
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => PointerButton::Primary,
+                    MouseButton::Right => PointerButton::Secondary,
+                    MouseButton::Middle => PointerButton::Middle,
+                    _ => return,
+                };
+                if let Some(pos) = self.pointer_position {
+                    self.events.push(Event::PointerButton {
+                        pos,
+                        button,
+                        pressed: *state == ElementState::Pressed,
+                        modifiers: self.modifiers,
+                    });
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (unit, delta) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (MouseWheelUnit::Line, Vec2::new(*x, *y)),
+                    MouseScrollDelta::PixelDelta(position) => {
+                        let position = position.to_logical::<f32>(scale_factor);
+                        (MouseWheelUnit::Point, Vec2::new(position.x, position.y))
+                    }
+                    _ => return,
+                };
+                self.events.push(Event::MouseWheel {
+                    unit,
+                    delta,
+                    modifiers: self.modifiers,
+                });
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                let physical_key = egui_physical_key(event.physical_key);
+                let key = egui_logical_key(&event.logical_key).or(physical_key);
+                // the clipboard shortcuts are events of their own
+                if pressed && self.modifiers.command {
+                    match key {
+                        Some(egui::Key::C) => self.events.push(Event::Copy),
+                        Some(egui::Key::X) => self.events.push(Event::Cut),
+                        Some(egui::Key::V) => {
+                            if let Some(text) = clipboard_text() {
+                                self.events.push(Event::Paste(text));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                if let Some(key) = key {
+                    self.events.push(Event::Key {
+                        key,
+                        physical_key,
+                        pressed,
+                        repeat: false,
+                        modifiers: self.modifiers,
+                    });
+                }
+                // typed text, without the control characters of keys like Enter or Backspace
+                // and the letters of shortcuts
+                if let Some(text) = event.text.filter(|text| {
+                    pressed
+                        && !self.modifiers.command
+                        && !self.modifiers.ctrl
+                        && !text.chars().any(char::is_control)
+                }) {
+                    self.events.push(Event::Text(text.to_owned()));
+                }
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                self.events.push(Event::WindowFocused(*focused));
+            }
+            _ => (),
+        }
+    }
+
+    // the input of the next egui frame for a view of `size` points, taking the collected events
+    fn take_input(&mut self, size: Vec2, pixels_per_point: f32) -> RawInput {
+        let start_time = *self.start_time.get_or_insert_with(Instant::now);
+        let mut raw_input = RawInput {
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, size)),
+            max_texture_side: Some(16384),
+            time: Some(start_time.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            focused: self.focused,
+            ..Default::default()
+        };
+        raw_input
+            .viewports
+            .entry(ViewportId::ROOT)
+            .or_default()
+            .native_pixels_per_point = Some(pixels_per_point);
+        raw_input
+    }
+
+    // creates and updates the textures egui asked for ahead of drawing with them
+    fn set_textures(
+        &mut self,
+        device: &ProtocolObject<dyn MTLDevice>,
+        textures_delta: &TexturesDelta,
+    ) {
+        for (texture_id, image_delta) in &textures_delta.set {
+            let [width, height] = image_delta.image.size();
+            // the texels stay sRGB encoded, egui blends in that space
+            let pixels: Vec<u8> = match &image_delta.image {
+                ImageData::Color(image) => image
+                    .pixels
+                    .iter()
+                    .flat_map(|color| color.to_array())
+                    .collect(),
+                ImageData::Font(image) => image
+                    .srgba_pixels(None)
+                    .flat_map(|color| color.to_array())
+                    .collect(),
+            };
+            if image_delta.pos.is_none() {
+                let texture = create_ui_texture(device, width, height);
+                let sampler = create_egui_sampler(device, image_delta.options);
+                self.textures.insert(*texture_id, (texture, sampler));
+            }
+            let Some((texture, _)) = self.textures.get(texture_id) else {
+                continue;
+            };
+            let [x, y] = image_delta.pos.unwrap_or([0, 0]);
+            upload_ui_texture(texture, (x, y), (width, height), &pixels);
+        }
+    }
+}
+
+fn create_egui_sampler(
+    device: &ProtocolObject<dyn MTLDevice>,
+    options: TextureOptions,
+) -> Retained<ProtocolObject<dyn MTLSamplerState>> {
+    let filter = |filter| match filter {
+        TextureFilter::Nearest => MTLSamplerMinMagFilter::Nearest,
+        TextureFilter::Linear => MTLSamplerMinMagFilter::Linear,
+    };
+    let address_mode = match options.wrap_mode {
+        TextureWrapMode::ClampToEdge => MTLSamplerAddressMode::ClampToEdge,
+        TextureWrapMode::Repeat => MTLSamplerAddressMode::Repeat,
+        TextureWrapMode::MirroredRepeat => MTLSamplerAddressMode::MirrorRepeat,
+    };
+    let descriptor = MTLSamplerDescriptor::new();
+    descriptor.setMinFilter(filter(options.minification));
+    descriptor.setMagFilter(filter(options.magnification));
+    descriptor.setSAddressMode(address_mode);
+    descriptor.setTAddressMode(address_mode);
+    device
+        .newSamplerStateWithDescriptor(&descriptor)
+        .expect("Failed to create an egui sampler.")
+}
+
+impl MetalRenderer {
+    // builds the ui of every frame with egui, drawn over the scene after the render callback.
+    // the window events handed to `handle_window_event` drive it, applications can check
+    // `egui_context().wants_pointer_input()` to leave the input the ui uses alone
+    pub fn set_egui_callback(&self, egui_callback: impl Fn(&Self, &Context) + 'static) {
+        self.ivars()
+            .egui_callback
+            .replace(Some(Box::new(egui_callback)));
+    }
+
+    // the context the ui is built with, to change its style or its fonts
+    pub fn egui_context(&self) -> Context {
+        self.ivars().egui.borrow().context.clone()
+    }
+
+    fn egui_pipeline_state(
+        &self,
+        pixel_format: MTLPixelFormat,
+    ) -> Option<Retained<ProtocolObject<dyn MTLRenderPipelineState>>> {
+        if let Some((format, pipeline_state)) = &self.ivars().egui.borrow().pipeline_state {
+            if *format == pixel_format {
+                return Some(pipeline_state.clone());
+            }
+        }
+        let pipeline_state =
+            self.create_overlay_pipeline_state("vertex_ui", "fragment_egui", pixel_format)?;
+        self.ivars().egui.borrow_mut().pipeline_state =
+            Some((pixel_format, pipeline_state.clone()));
+        Some(pipeline_state)
+    }
+
+    // runs the egui callback and tessellates the ui for `drawable_texture` while the frame is
+    // recorded, returns None without a callback
+    pub(crate) fn record_egui(
+        &self,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> Option<UiDraw> {
+        let egui_callback = self.ivars().egui_callback.borrow();
+        let egui_callback = egui_callback.as_ref()?;
+
+        let pixels_per_point = self.scale_factor() as f32;
+        let drawable_size = (drawable_texture.width(), drawable_texture.height());
+        let screen_size = Vec2::new(
+            drawable_size.0 as f32 / pixels_per_point,
+            drawable_size.1 as f32 / pixels_per_point,
+        );
+        // the callback may use the renderer, so the state isn't borrowed while it runs
+        let (context, raw_input) = {
+            let mut egui = self.ivars().egui.borrow_mut();
+            (
+                egui.context.clone(),
+                egui.take_input(screen_size, pixels_per_point),
+            )
+        };
+        let full_output = context.run(raw_input, |context| egui_callback(self, context));
+        if !full_output.platform_output.copied_text.is_empty() {
+            set_clipboard_text(&full_output.platform_output.copied_text);
+        }
+        let primitives = context.tessellate(full_output.shapes, full_output.pixels_per_point);
+
+        let pixel_format = drawable_texture.pixelFormat();
+        let pipeline_state = self.egui_pipeline_state(pixel_format)?;
+        let mut egui = self.ivars().egui.borrow_mut();
+        egui.set_textures(&self.device(), &full_output.textures_delta);
+
+        let pixels_per_point = full_output.pixels_per_point;
+        let mut meshes = Vec::new();
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in primitives
+        {
+            let Primitive::Mesh(mesh) = primitive else {
+                self.log(LogLevel::Warn, "Egui paint callbacks are not supported.");
+                continue;
+            };
+            let Some((texture, sampler)) = egui.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            let clip_rect = clip_rect * pixels_per_point;
+            let clip_rect = [
+                clip_rect.min.x,
+                clip_rect.min.y,
+                clip_rect.max.x,
+                clip_rect.max.y,
+            ];
+            let Some(scissor_rect) = scissor_rect(clip_rect, drawable_size) else {
+                continue;
+            };
+            if mesh.indices.is_empty() {
+                continue;
+            }
+            let vertices: Vec<UiVertex> = mesh
+                .vertices
+                .iter()
+                .map(|vertex| UiVertex {
+                    position: [vertex.pos.x, vertex.pos.y],
+                    uv: [vertex.uv.x, vertex.uv.y],
+                    color: vertex.color.to_array(),
+                })
+                .collect();
+            meshes.push(UiMesh {
+                scissor_rect,
+                vertices: self.frame_buffer(&vertices),
+                indices: self.frame_buffer(&mesh.indices),
+                vertex_offset: 0,
+                index_offset: 0,
+                index_count: mesh.indices.len(),
+                texture: texture.clone(),
+                sampler: sampler.clone(),
+            });
+        }
+        // the meshes of this frame hold on to the textures egui is done with
+        for texture_id in &full_output.textures_delta.free {
+            egui.textures.remove(texture_id);
+        }
+
+        Some(UiDraw::new(
+            pipeline_state,
+            meshes,
+            screen_size.into(),
+            pixel_format,
+        ))
+    }
+}
@@ -0,0 +1,127 @@
+// image processing with compute kernels, from blurring a texture to finding its edges. the
+// filters of a chain run one after the other in a compute pass of their own, ahead of the
+// frames committed after it, and write half float textures like the ones of the frames
+use core::{ffi::c_void, ptr::NonNull};
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLCommandBuffer, MTLCommandEncoder, MTLCommandQueue, MTLComputeCommandEncoder,
+    MTLComputePipelineState, MTLPixelFormat, MTLSize, MTLTexture,
+};
+
+use crate::{LogLevel, MetalRenderer};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// a filter of `MetalRenderer::apply_compute_filters`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComputeFilter {
+    // a gaussian blur with a standard deviation of `sigma` pixels, blurring the rows and then
+    // the columns
+    GaussianBlur { sigma: f32 },
+    // the strength of the edges of the luminance as a gray level, from a 3x3 sobel operator
+    Sobel,
+    // white where the luminance reaches `level` and black below it, keeping the alpha
+    Threshold { level: f32 },
+}
+
+// the arguments of the filter kernels, as `FilterProperties` in the shaders
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct FilterProperties {
+    // the step between the taps of a blur, along the rows or the columns
+    direction: [i32; 2],
+    sigma: f32,
+    level: f32,
+}
+
+impl ComputeFilter {
+    // the kernels running the filter and their properties, a dispatch each
+    fn dispatches(&self) -> Vec<(&'static str, FilterProperties)> {
+        match *self {
+            ComputeFilter::GaussianBlur { sigma } => [[1, 0], [0, 1]]
+                .into_iter()
+                .map(|direction| {
+                    let properties = FilterProperties {
+                        direction,
+                        sigma: sigma.max(0.),
+                        ..Default::default()
+                    };
+                    ("filter_gaussian_blur", properties)
+                })
+                .collect(),
+            ComputeFilter::Sobel => vec![("filter_sobel", FilterProperties::default())],
+            ComputeFilter::Threshold { level } => {
+                let properties = FilterProperties {
+                    level,
+                    ..Default::default()
+                };
+                vec![("filter_threshold", properties)]
+            }
+        }
+    }
+}
+
+impl MetalRenderer {
+    // runs `texture` through `filters`, each one reading the output of the one before it, and
+    // returns a new texture with the result. it's written by the gpu ahead of the frames
+    // committed after this call, `read_texture` waits for it. without filters the texture is
+    // returned as it is
+    pub fn apply_compute_filters(&self, texture: &Texture, filters: &[ComputeFilter]) -> Texture {
+        let dispatches: Vec<_> = filters.iter().flat_map(ComputeFilter::dispatches).collect();
+        if dispatches.is_empty() {
+            return texture.clone();
+        }
+        let (width, height) = (texture.width(), texture.height());
+        // the dispatches write these in turns, reading the one written before
+        let mut targets = [
+            self.create_storage_texture(width, height, MTLPixelFormat::RGBA16Float),
+            self.create_storage_texture(width, height, MTLPixelFormat::RGBA16Float),
+        ];
+        let command_buffer = self
+            .command_queue()
+            .commandBuffer()
+            .expect("Failed to create a command buffer.");
+        let encoder = command_buffer
+            .computeCommandEncoder()
+            .expect("Failed to create a compute encoder.");
+        let mut input = texture.clone();
+        for (function_name, properties) in &dispatches {
+            let pipeline_state = self.compute_pipeline_state(function_name);
+            encoder.setComputePipelineState(&pipeline_state);
+            unsafe {
+                encoder.setTexture_atIndex(Some(&input), 0);
+                encoder.setTexture_atIndex(Some(&targets[0]), 1);
+                encoder.setBytes_length_atIndex(
+                    NonNull::from(properties).cast::<c_void>(),
+                    core::mem::size_of::<FilterProperties>(),
+                    0,
+                );
+            }
+            // a thread per pixel
+            let threads_width = pipeline_state.threadExecutionWidth();
+            let threads_per_threadgroup = MTLSize {
+                width: threads_width,
+                height: pipeline_state.maxTotalThreadsPerThreadgroup() / threads_width,
+                depth: 1,
+            };
+            let threads = MTLSize {
+                width,
+                height,
+                depth: 1,
+            };
+            encoder.dispatchThreads_threadsPerThreadgroup(threads, threads_per_threadgroup);
+            input = targets[0].clone();
+            targets.swap(0, 1);
+        }
+        encoder.endEncoding();
+        command_buffer.commit();
+
+        let message = format!(
+            "Filtered a {width}x{height} texture with {} kernel dispatches.",
+            dispatches.len()
+        );
+        self.log(LogLevel::Debug, &message);
+        input
+    }
+}
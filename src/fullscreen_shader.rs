@@ -0,0 +1,305 @@
+// a fragment shader of the application drawing the whole frame by itself, the way shadertoy
+// runs them. the file only defines `main_image`, which gets the position of a pixel and the
+// inputs of the frame like the time, the resolution and the mouse. it's compiled on a metal
+// thread, and again whenever it's saved, the frames keep the last shader that compiled
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSSize;
+use objc2_metal::{MTLLibrary, MTLRenderPipelineState};
+use tao::event::MouseButton;
+
+use crate::{
+    reflection::BoundResource, BindingSlot, Compilation, LogLevel, MetalRenderer,
+    PipelineDescriptor, PrimitiveType, RenderPass, AUDIO_BANDS,
+};
+
+type Library = Retained<ProtocolObject<dyn MTLLibrary>>;
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+
+// put ahead of the source of the file, the line directive numbers its lines from 1 again for
+// the errors of the compiler
+const PRELUDE: &str = r#"
+#include <metal_stdlib>
+
+// the inputs of `main_image`, the uniforms of shadertoy
+struct ShaderInputs {
+    // the size of the view in pixels
+    metal::float2 resolution;
+    // the seconds since the shader was loaded, and since the frame before
+    float time;
+    float time_delta;
+    // where the cursor was last dragged with the left button in xy and where the button went
+    // down in zw, in pixels from the bottom left. z is negative while the button is up and w
+    // after the frame it went down
+    metal::float4 mouse;
+    // the levels of the frequency bands of the captured sound, all 0 without a capture
+    float audio_bands[8];
+    // the frames drawn since the shader was loaded
+    uint frame;
+};
+
+// the color of the pixel at `frag_coord`, in pixels from the bottom left, defined by the file
+metal::float4 main_image(metal::float2 frag_coord, constant ShaderInputs& inputs);
+
+struct FullscreenShaderOutput {
+    metal::float4 position [[position]];
+};
+
+// a triangle covering the whole viewport, like `vertex_fullscreen`
+vertex FullscreenShaderOutput vertex_fullscreen_shader(uint vertex_idx [[vertex_id]]) {
+    metal::float2 uv = metal::float2((vertex_idx << 1) & 2, vertex_idx & 2);
+    FullscreenShaderOutput out;
+    out.position = metal::float4(uv * 2 - 1, 0, 1);
+    return out;
+}
+
+fragment metal::float4 fragment_fullscreen_shader(
+    FullscreenShaderOutput in [[stage_in]],
+    constant ShaderInputs& inputs [[buffer(1)]]
+) {
+    return main_image(metal::float2(in.position.x, inputs.resolution.y - in.position.y), inputs);
+}
+"#;
+
+// the index of the inputs in `fragment_fullscreen_shader`
+const INPUTS_INDEX: usize = 1;
+
+// `ShaderInputs` of the prelude
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ShaderInputs {
+    resolution: [f32; 2],
+    time: f32,
+    time_delta: f32,
+    mouse: [f32; 4],
+    audio_bands: [f32; AUDIO_BANDS],
+    frame: u32,
+    // the float4 aligns the shader's struct to 16 bytes
+    _padding: [u32; 3],
+}
+
+pub(crate) struct FullscreenShader {
+    path: PathBuf,
+    // stops watching when dropped
+    _watcher: RecommendedWatcher,
+    // set by the watcher thread when the file changed
+    changed: Arc<AtomicBool>,
+    compiling: Option<Compilation<Library>>,
+    library: Option<Library>,
+    // built from the library for the formats of the view, again when they change
+    pipeline: Option<(PipelineDescriptor, PipelineState)>,
+    // the time of the frames when the shader was loaded and as of the last frame
+    start_time: Option<f32>,
+    last_time: f32,
+    frame: u32,
+    // the last position dragged to and where the button went down, and whether it's down
+    mouse: [f32; 4],
+    mouse_down: bool,
+}
+
+impl MetalRenderer {
+    // draws every frame with the `main_image` of the file at `path` in place of the draws of
+    // the application, until `unload_fullscreen_shader`. the file is compiled with the shader
+    // options of the renderer after `PRELUDE`, which declares `main_image` and its inputs, and
+    // watched for changes like with `watch_shaders`. a view drawing on demand keeps drawing
+    // while it runs, since the shader animates with the time
+    pub fn load_fullscreen_shader(&self, path: impl AsRef<Path>) -> notify::Result<()> {
+        // editors often save by replacing the file, so the directory is watched instead
+        let path = fs::canonicalize(path)?;
+        let changed = Arc::new(AtomicBool::new(true));
+        let mut watcher = notify::recommended_watcher({
+            let path = path.clone();
+            let changed = changed.clone();
+            move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let modified = event.kind.is_create() || event.kind.is_modify();
+                if modified && event.paths.contains(&path) {
+                    changed.store(true, Ordering::Release);
+                }
+            }
+        })?;
+        let directory = path.parent().unwrap_or(&path);
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+        let message = format!("Running {} as a fullscreen shader.", path.display());
+        self.log(LogLevel::Info, &message);
+        self.ivars()
+            .fullscreen_shader
+            .replace(Some(FullscreenShader {
+                path,
+                _watcher: watcher,
+                changed,
+                compiling: None,
+                library: None,
+                pipeline: None,
+                start_time: None,
+                last_time: 0.,
+                frame: 0,
+                mouse: [0.; 4],
+                mouse_down: false,
+            }));
+        self.set_needs_redraw();
+        Ok(())
+    }
+
+    // goes back to the draws of the application
+    pub fn unload_fullscreen_shader(&self) {
+        if self.ivars().fullscreen_shader.take().is_some() {
+            self.set_needs_redraw();
+        }
+    }
+
+    pub fn has_fullscreen_shader(&self) -> bool {
+        self.ivars().fullscreen_shader.borrow().is_some()
+    }
+
+    // swaps in the library of a finished compilation and starts the next one once the file
+    // changed again, only one compilation runs at a time
+    fn compile_fullscreen_shader(&self, shader: &mut FullscreenShader) {
+        if let Some(compilation) = &shader.compiling {
+            let Some(result) = compilation.try_take() else {
+                return;
+            };
+            match result {
+                Ok(library) => {
+                    self.log_compilation(true, compilation.warnings());
+                    shader.library = Some(library);
+                    shader.pipeline = None;
+                }
+                Err(error) => self.log_compilation(false, Some(error.0)),
+            }
+            shader.compiling = None;
+        }
+
+        if !shader.changed.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let source = match fs::read_to_string(&shader.path) {
+            Ok(source) => source,
+            Err(error) => {
+                let message = format!("Failed to read {}: {error}.", shader.path.display());
+                self.log(LogLevel::Warn, &message);
+                return;
+            }
+        };
+        let source = format!("{PRELUDE}\n#line 1 \"{}\"\n{source}", shader.path.display());
+        let shader_options = self.ivars().shader_options.borrow().clone();
+        shader.compiling = Some(self.compile_library_async(&source, &shader_options));
+    }
+
+    // the pipeline of the compiled shader for the formats of the view, none until the first
+    // compilation succeeded
+    fn fullscreen_shader_pipeline(&self, shader: &mut FullscreenShader) -> Option<PipelineState> {
+        let library = shader.library.as_ref()?;
+        let descriptor =
+            self.pipeline_descriptor("vertex_fullscreen_shader", "fragment_fullscreen_shader");
+        if let Some((built, pipeline_state)) = &shader.pipeline {
+            if *built == descriptor {
+                return Some(pipeline_state.clone());
+            }
+        }
+        match self.create_pipeline_state_with_library(library, &descriptor) {
+            Ok(pipeline_state) => {
+                shader.pipeline = Some((descriptor, pipeline_state.clone()));
+                Some(pipeline_state)
+            }
+            Err(error) => {
+                self.log(LogLevel::Error, &format!("{error}."));
+                // not built again until the shader compiles again
+                shader.library = None;
+                None
+            }
+        }
+    }
+
+    // records the draw of the shader into the pass of a frame of `render_size`. the pass stays
+    // clear while the shader compiles
+    pub(crate) fn record_fullscreen_shader(
+        &self,
+        render_pass: &mut RenderPass,
+        render_size: NSSize,
+        audio_bands: [f32; AUDIO_BANDS],
+    ) {
+        let mut fullscreen_shader = self.ivars().fullscreen_shader.borrow_mut();
+        let Some(shader) = fullscreen_shader.as_mut() else {
+            return;
+        };
+        self.set_needs_redraw();
+        self.compile_fullscreen_shader(shader);
+        let Some(pipeline_state) = self.fullscreen_shader_pipeline(shader) else {
+            return;
+        };
+
+        let viewport = self.viewport(render_size);
+        let resolution = [viewport.width as f32, viewport.height as f32];
+        let input = self.ivars().input.borrow();
+        let dragged = input
+            .cursor_position()
+            .filter(|_| input.is_button_down(MouseButton::Left));
+        let pressed = input.was_button_pressed(MouseButton::Left);
+        drop(input);
+        if let Some((x, y)) = dragged {
+            let scale_factor = self.scale_factor();
+            let x = (x * scale_factor - viewport.originX) as f32;
+            let y = resolution[1] - (y * scale_factor - viewport.originY) as f32;
+            let [_, _, click_x, click_y] = shader.mouse;
+            let (click_x, click_y) = if shader.mouse_down {
+                (click_x, click_y)
+            } else {
+                (x, y)
+            };
+            shader.mouse = [x, y, click_x, click_y];
+        }
+        shader.mouse_down = dragged.is_some();
+        let [x, y, click_x, click_y] = shader.mouse;
+        let mouse = [
+            x,
+            y,
+            if shader.mouse_down { click_x } else { -click_x },
+            if shader.mouse_down && pressed {
+                click_y
+            } else {
+                -click_y
+            },
+        ];
+
+        let time = self.frames().time.get();
+        let start_time = *shader.start_time.get_or_insert(time);
+        let inputs = ShaderInputs {
+            resolution,
+            time: time - start_time,
+            time_delta: if shader.frame == 0 {
+                0.
+            } else {
+                time - shader.last_time
+            },
+            mouse,
+            audio_bands,
+            frame: shader.frame,
+            _padding: [0; 3],
+        };
+        shader.last_time = time;
+        shader.frame += 1;
+
+        let inputs = self.frame_buffer(&[inputs]);
+        render_pass.draw(&pipeline_state, &inputs, PrimitiveType::Triangle, 0..3);
+        if let Some(item) = render_pass.items.last_mut() {
+            let slot = BindingSlot {
+                vertex: None,
+                fragment: Some(INPUTS_INDEX),
+            };
+            item.bindings.push((slot, BoundResource::Buffer(inputs)));
+        }
+    }
+}
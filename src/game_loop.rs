@@ -0,0 +1,162 @@
+// the pace of the event loop for applications that tick a simulation every frame. tao waits for
+// the next event by default, which stops the ticks while nothing happens to the windows; a
+// continuous loop polls instead, or waits until the next tick when the frame rate is limited
+use std::time::{Duration, Instant};
+
+use tao::event_loop::ControlFlow;
+
+// how the event loop waits between events
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    // until the next event, ticking once per batch of events
+    Wait,
+    // not at all, ticking as often as the frame limiter allows
+    Continuous,
+}
+
+// how a frame limiter waits for the next tick. sleeping frees the core but wakes up late by up
+// to a tick of the scheduler, spinning is on time but keeps the core busy
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SleepStrategy {
+    // the event loop waits until the tick, handling the events coming before it
+    #[default]
+    WaitUntil,
+    // the thread sleeps until the tick, the events wait for it
+    Sleep,
+    // the thread spins until the tick
+    Spin,
+    // the thread sleeps until the duration before the tick and spins for the rest
+    SleepThenSpin(Duration),
+}
+
+// caps the ticks of a continuous loop at a frame rate
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameLimiter {
+    frame_time: Duration,
+    sleep_strategy: SleepStrategy,
+}
+
+impl FrameLimiter {
+    pub fn new(frames_per_second: f64, sleep_strategy: SleepStrategy) -> Self {
+        FrameLimiter {
+            frame_time: Duration::from_secs_f64(1. / frames_per_second.max(1.)),
+            sleep_strategy,
+        }
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    pub fn sleep_strategy(&self) -> SleepStrategy {
+        self.sleep_strategy
+    }
+
+    // blocks until `deadline` the way of the strategy, the event loop does the waiting for
+    // `WaitUntil`
+    fn wait(&self, deadline: Instant) {
+        let sleep_until = |deadline: Instant| {
+            if let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                std::thread::sleep(remaining);
+            }
+        };
+        let spin_until = |deadline: Instant| {
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        };
+        match self.sleep_strategy {
+            SleepStrategy::WaitUntil => (),
+            SleepStrategy::Sleep => sleep_until(deadline),
+            SleepStrategy::Spin => spin_until(deadline),
+            SleepStrategy::SleepThenSpin(margin) => {
+                sleep_until(deadline.checked_sub(margin).unwrap_or(deadline));
+                spin_until(deadline);
+            }
+        }
+    }
+}
+
+// when the simulation ticks. the event handler sets the control flow of the loop from
+// `control_flow` for every event and calls `tick` from `Event::MainEventsCleared`
+#[derive(Clone, Debug)]
+pub struct GameLoop {
+    mode: LoopMode,
+    frame_limiter: Option<FrameLimiter>,
+    last_tick: Instant,
+    next_tick: Instant,
+}
+
+impl GameLoop {
+    pub fn new(mode: LoopMode) -> Self {
+        let now = Instant::now();
+        GameLoop {
+            mode,
+            frame_limiter: None,
+            last_tick: now,
+            next_tick: now,
+        }
+    }
+
+    pub fn with_frame_limiter(mut self, frame_limiter: Option<FrameLimiter>) -> Self {
+        self.frame_limiter = frame_limiter;
+        self
+    }
+
+    pub fn mode(&self) -> LoopMode {
+        self.mode
+    }
+
+    // e.g. continuous only while the windows are visible
+    pub fn set_mode(&mut self, mode: LoopMode) {
+        self.mode = mode;
+    }
+
+    pub fn frame_limiter(&self) -> Option<FrameLimiter> {
+        self.frame_limiter
+    }
+
+    // none ticks as often as the loop goes around
+    pub fn set_frame_limiter(&mut self, frame_limiter: Option<FrameLimiter>) {
+        self.frame_limiter = frame_limiter;
+    }
+
+    pub fn control_flow(&self) -> ControlFlow {
+        match (self.mode, self.frame_limiter) {
+            (LoopMode::Wait, _) => ControlFlow::Wait,
+            (LoopMode::Continuous, Some(frame_limiter))
+                if frame_limiter.sleep_strategy == SleepStrategy::WaitUntil =>
+            {
+                ControlFlow::WaitUntil(self.next_tick)
+            }
+            (LoopMode::Continuous, _) => ControlFlow::Poll,
+        }
+    }
+
+    // the seconds since the last tick when it's time for the next one. a limited continuous
+    // loop waits for it first, or skips the events coming before it with `WaitUntil`
+    pub fn tick(&mut self) -> Option<f32> {
+        if let (LoopMode::Continuous, Some(frame_limiter)) = (self.mode, self.frame_limiter) {
+            if frame_limiter.sleep_strategy == SleepStrategy::WaitUntil {
+                if Instant::now() < self.next_tick {
+                    return None;
+                }
+            } else {
+                frame_limiter.wait(self.next_tick);
+            }
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_tick).as_secs_f32();
+        self.last_tick = now;
+        // a tick coming more than a frame late starts the count over rather than rushing the
+        // ticks after it to catch up
+        let frame_time = self
+            .frame_limiter
+            .map_or(Duration::ZERO, |frame_limiter| frame_limiter.frame_time);
+        self.next_tick += frame_time;
+        if self.next_tick < now {
+            self.next_tick = now + frame_time;
+        }
+        Some(elapsed)
+    }
+}
@@ -0,0 +1,245 @@
+// the frames the gpu failed, reported by the completion handlers of their command buffers, and
+// the recovery from the faults that leave the queue or the device unusable
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use core::ptr::NonNull;
+
+use objc2::{
+    msg_send_id,
+    rc::Retained,
+    runtime::{AnyObject, ProtocolObject},
+    DeclaredClass,
+};
+use objc2_foundation::{NSArray, NSFastEnumeration, NSFastEnumerationState, NSString};
+use objc2_metal::{
+    MTLCommandBuffer, MTLCommandBufferDescriptor, MTLCommandBufferEncoderInfo,
+    MTLCommandBufferEncoderInfoErrorKey, MTLCommandBufferError, MTLCommandBufferErrorOption,
+    MTLCommandEncoderErrorState, MTLCommandQueue, MTLDevice,
+};
+
+use crate::{FrameAllocator, LogLevel, MetalRenderer};
+
+// the recoveries in a row after which the renderer stops trying, a gpu that keeps failing
+// wouldn't get anywhere with more
+const MAX_RECOVERIES: usize = 3;
+// how close to the last one a recovery counts as in a row
+const RECOVERY_WINDOW: Duration = Duration::from_secs(5);
+
+// what went wrong with a command buffer, taken in its completion handler
+pub(crate) struct CommandBufferFault {
+    error: MTLCommandBufferError,
+    message: String,
+}
+
+// how much has to be created again after a fault
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Recovery {
+    // the command buffer failed on its own, the next frame can go ahead
+    None,
+    // the gpu hung on the work of the queue, a new queue and new pipelines get it going again
+    Queue,
+    // the gpu restarted, everything created on the device is gone
+    Device,
+}
+
+impl CommandBufferFault {
+    // the kind of `MTLCommandBufferError`, the description of metal, the encoders that faulted
+    // and the logs of the shaders, the last two with enhanced command buffer errors
+    pub(crate) fn new(command_buffer: &ProtocolObject<dyn MTLCommandBuffer>) -> Self {
+        let Some(error) = (unsafe { command_buffer.error() }) else {
+            return Self {
+                error: MTLCommandBufferError::None,
+                message: "an unknown error".to_owned(),
+            };
+        };
+        let code = MTLCommandBufferError(error.code() as usize);
+        let kind = match code {
+            MTLCommandBufferError::Internal => "an internal error",
+            MTLCommandBufferError::Timeout => "a timeout",
+            MTLCommandBufferError::PageFault => "a page fault",
+            MTLCommandBufferError::AccessRevoked => "its access revoked",
+            MTLCommandBufferError::NotPermitted => "a privileged operation",
+            MTLCommandBufferError::OutOfMemory => "no memory left",
+            MTLCommandBufferError::InvalidResource => "an invalid resource",
+            MTLCommandBufferError::Memoryless => "a memoryless attachment out of tile memory",
+            MTLCommandBufferError::DeviceRemoved => "its device removed",
+            MTLCommandBufferError::StackOverflow => "a stack overflow",
+            _ => "an error",
+        };
+        let mut message = format!("{kind}: {}", error.localizedDescription());
+
+        let encoders = unsafe {
+            error
+                .userInfo()
+                .objectForKey(MTLCommandBufferEncoderInfoErrorKey)
+        };
+        if let Some(encoders) = encoders {
+            // SAFETY: metal puts an array of encoder infos under the key
+            let encoders: Retained<NSArray<ProtocolObject<dyn MTLCommandBufferEncoderInfo>>> =
+                unsafe { Retained::cast(encoders) };
+            let faulted: Vec<String> = encoders
+                .iter()
+                .filter(|encoder| {
+                    (unsafe { encoder.errorState() }) == MTLCommandEncoderErrorState::Faulted
+                })
+                .map(|encoder| unsafe { encoder.label() }.to_string())
+                .collect();
+            if !faulted.is_empty() {
+                message += &format!(" in {}", faulted.join(", "));
+            }
+        }
+        for log in function_logs(command_buffer) {
+            message += &format!("\n{log}");
+        }
+        Self {
+            error: code,
+            message,
+        }
+    }
+
+    fn recovery(&self) -> Recovery {
+        match self.error {
+            MTLCommandBufferError::Timeout | MTLCommandBufferError::PageFault => Recovery::Queue,
+            MTLCommandBufferError::Internal | MTLCommandBufferError::AccessRevoked => {
+                Recovery::Device
+            }
+            // a removed device is left by `check_devices`
+            _ => Recovery::None,
+        }
+    }
+}
+
+// the descriptions of the messages the shaders of `command_buffer` logged. the log container is
+// only enumerable, through the fast enumeration protocol
+fn function_logs(command_buffer: &ProtocolObject<dyn MTLCommandBuffer>) -> Vec<String> {
+    let logs = unsafe { command_buffer.logs() };
+    let mut state = NSFastEnumerationState {
+        state: 0,
+        itemsPtr: core::ptr::null_mut(),
+        mutationsPtr: core::ptr::null_mut(),
+        extra: [0; 5],
+    };
+    let mut buffer: [*mut AnyObject; 16] = [core::ptr::null_mut(); 16];
+    let mut descriptions = Vec::new();
+    loop {
+        let count = unsafe {
+            logs.countByEnumeratingWithState_objects_count(
+                NonNull::from(&mut state),
+                NonNull::new(buffer.as_mut_ptr()).unwrap(),
+                buffer.len(),
+            )
+        };
+        if count == 0 {
+            return descriptions;
+        }
+        // the items are in the buffer or wherever the container points to instead
+        for index in 0..count {
+            let log = unsafe { &**state.itemsPtr.add(index) };
+            let description: Retained<NSString> = unsafe { msg_send_id![log, description] };
+            descriptions.push(description.to_string());
+        }
+    }
+}
+
+impl MetalRenderer {
+    // names the encoders that faulted and adds the logs of the shaders to the errors of the
+    // frames the gpu fails, at some cost to the performance of the gpu. on by default in debug
+    // builds
+    pub fn set_enhanced_command_buffer_errors(&self, enabled: bool) {
+        self.ivars().enhanced_command_buffer_errors.set(enabled);
+    }
+
+    pub fn enhanced_command_buffer_errors(&self) -> bool {
+        self.ivars().enhanced_command_buffer_errors.get()
+    }
+
+    // a command buffer for a frame, reporting the status of its encoders when it fails with
+    // enhanced command buffer errors
+    pub(crate) fn frame_command_buffer(
+        &self,
+        command_queue: &ProtocolObject<dyn MTLCommandQueue>,
+    ) -> Option<Retained<ProtocolObject<dyn MTLCommandBuffer>>> {
+        if !self.enhanced_command_buffer_errors() {
+            return command_queue.commandBuffer();
+        }
+        unsafe {
+            let descriptor = MTLCommandBufferDescriptor::new();
+            descriptor.setErrorOptions(MTLCommandBufferErrorOption::EncoderExecutionStatus);
+            command_queue.commandBufferWithDescriptor(&descriptor)
+        }
+    }
+
+    // logs the frames the gpu failed since the last one and recovers from the worst of their
+    // faults, so that a hang or a restart of the gpu doesn't leave the view on a frozen frame
+    pub(crate) fn check_gpu_faults(&self) {
+        let faults = core::mem::take(&mut *self.ivars().command_buffer_faults.lock().unwrap());
+        let mut recovery = Recovery::None;
+        for fault in faults {
+            self.log(
+                LogLevel::Error,
+                &format!("The GPU failed a frame with {}.", fault.message),
+            );
+            recovery = recovery.max(fault.recovery());
+        }
+        if recovery == Recovery::None {
+            return;
+        }
+
+        let now = Instant::now();
+        let in_a_row = self
+            .ivars()
+            .last_gpu_recovery
+            .get()
+            .is_some_and(|last| now.duration_since(last) < RECOVERY_WINDOW);
+        let recoveries = if in_a_row {
+            self.ivars().gpu_recoveries.get() + 1
+        } else {
+            1
+        };
+        self.ivars().gpu_recoveries.set(recoveries);
+        self.ivars().last_gpu_recovery.set(Some(now));
+        if recoveries > MAX_RECOVERIES {
+            if recoveries == MAX_RECOVERIES + 1 {
+                let message = "The GPU keeps failing frames, giving up on recovering from it.";
+                self.log(LogLevel::Error, message);
+            }
+            return;
+        }
+
+        // the frames in flight finish, or fail as well, before anything they use is replaced
+        if let Some(command_buffer) = self.ivars().last_command_buffer.take() {
+            unsafe { command_buffer.waitUntilCompleted() };
+        }
+        match recovery {
+            Recovery::None => {}
+            Recovery::Queue => {
+                self.log(
+                    LogLevel::Warn,
+                    "Recreating the command queue and the pipelines after a GPU hang.",
+                );
+                let device = self.device();
+                let command_queue = device
+                    .newCommandQueue()
+                    .expect("Failed to create a command queue.");
+                self.ivars().command_queue.replace(Some(command_queue));
+                let frames =
+                    FrameAllocator::new(&device).expect("Failed to create a uniform buffer.");
+                self.ivars().frames.replace(Some(Rc::new(frames)));
+                self.ivars().placeholder_pipelines.borrow_mut().clear();
+                self.clear_pipeline_caches();
+            }
+            Recovery::Device => {
+                self.log(
+                    LogLevel::Warn,
+                    "Recreating the resources of the device after a GPU restart.",
+                );
+                self.move_to_device(self.device());
+            }
+        }
+        // what failed while waiting is covered by the recovery
+        self.ivars().command_buffer_faults.lock().unwrap().clear();
+    }
+}
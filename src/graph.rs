@@ -0,0 +1,460 @@
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::{NSCopying, NSSize};
+use objc2_metal::{
+    MTLBuffer, MTLClearColor, MTLCommandBuffer, MTLLoadAction, MTLRenderPassDescriptor,
+    MTLRenderPipelineState, MTLStoreAction, MTLTexture,
+};
+
+use crate::{
+    allocator::{AllocatorStats, GpuAllocator, DEFAULT_HEAP_SIZE},
+    GradientProperties, LogLevel, MetalRenderer, RenderPass, RenderTarget, RenderTargetBuilder,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+type RecordPass = Box<dyn FnOnce(&MetalRenderer, &GraphResources, &mut RenderPass)>;
+
+pub(crate) type RenderGraphCallback = Box<dyn Fn(&MetalRenderer, &mut RenderGraph)>;
+
+// a texture passes of a `RenderGraph` render into and sample, either the drawable or a
+// transient texture that only lives for the frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GraphTexture(usize);
+
+// the drawable, the transient textures follow it
+const BACKBUFFER: GraphTexture = GraphTexture(0);
+
+// a pass of a `RenderGraph`, recorded once the graph resolved its textures
+pub struct GraphPass {
+    name: String,
+    target: GraphTexture,
+    reads: Vec<GraphTexture>,
+    clear_color: MTLClearColor,
+    record: RecordPass,
+}
+
+impl GraphPass {
+    // declares that the draws of the pass sample `texture`, the pass runs after every pass
+    // rendering into it
+    pub fn reads(&mut self, texture: GraphTexture) -> &mut Self {
+        self.reads.push(texture);
+        self
+    }
+
+    // the color a transient target is cleared to when this pass is the first to render into
+    // it, the drawable is cleared to the clear color of the view
+    pub fn with_clear_color(&mut self, clear_color: MTLClearColor) -> &mut Self {
+        self.clear_color = clear_color;
+        self
+    }
+}
+
+// the passes of a frame with the textures they render into and sample. the graph orders the
+// passes by what they read, skips the ones nothing on screen depends on, allocates the
+// transient textures for the part of the frame they're used in and picks the load and store
+// actions of every pass, passes rendering into the same texture run in the order they were
+// added
+pub struct RenderGraph {
+    // the sizes of the transient textures in pixels
+    sizes: Vec<(usize, usize)>,
+    passes: Vec<GraphPass>,
+}
+
+impl RenderGraph {
+    pub fn backbuffer(&self) -> GraphTexture {
+        BACKBUFFER
+    }
+
+    // a texture with the pixel format, depth format and sample count of the view, so the
+    // pipelines of the renderer can draw into it
+    pub fn create_texture(&mut self, width: usize, height: usize) -> GraphTexture {
+        self.sizes.push((width, height));
+        GraphTexture(self.sizes.len())
+    }
+
+    // adds a pass rendering into `target`, `record` records its draws once the textures of
+    // the frame are allocated
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        target: GraphTexture,
+        record: impl FnOnce(&MetalRenderer, &GraphResources, &mut RenderPass) + 'static,
+    ) -> &mut GraphPass {
+        self.passes.push(GraphPass {
+            name: name.to_owned(),
+            target,
+            reads: Vec::new(),
+            clear_color: MTLClearColor {
+                red: 0.,
+                green: 0.,
+                blue: 0.,
+                alpha: 1.,
+            },
+            record: Box::new(record),
+        });
+        self.passes.last_mut().unwrap()
+    }
+
+    // the passes that have to run before `pass`: every other pass rendering into a texture it
+    // reads and the pass rendering into its target before it
+    fn dependencies(&self, pass: usize) -> Vec<usize> {
+        let target = self.passes[pass].target;
+        let mut dependencies: Vec<usize> = self.passes[..pass]
+            .iter()
+            .rposition(|earlier| earlier.target == target)
+            .into_iter()
+            .collect();
+        for &texture in &self.passes[pass].reads {
+            dependencies.extend(
+                (0..self.passes.len())
+                    .filter(|&other| other != pass && self.passes[other].target == texture),
+            );
+        }
+        dependencies
+    }
+
+    // the passes in the order they run, without the ones the drawable doesn't depend on.
+    // the name of a pass in a cycle of passes reading each other's targets on failure
+    fn execution_order(&self) -> Result<Vec<usize>, &str> {
+        let dependencies: Vec<Vec<usize>> = (0..self.passes.len())
+            .map(|pass| self.dependencies(pass))
+            .collect();
+
+        let mut live: Vec<bool> = self
+            .passes
+            .iter()
+            .map(|pass| pass.target == BACKBUFFER)
+            .collect();
+        let mut pending: Vec<usize> = (0..self.passes.len()).filter(|&pass| live[pass]).collect();
+        while let Some(pass) = pending.pop() {
+            for &dependency in &dependencies[pass] {
+                if !live[dependency] {
+                    live[dependency] = true;
+                    pending.push(dependency);
+                }
+            }
+        }
+
+        // the first pass in the order they were added whose dependencies all ran goes next
+        let mut order = Vec::new();
+        let mut done = vec![false; self.passes.len()];
+        let live_count = live.iter().filter(|&&live| live).count();
+        while order.len() < live_count {
+            let next = (0..self.passes.len()).find(|&pass| {
+                live[pass] && !done[pass] && dependencies[pass].iter().all(|&other| done[other])
+            });
+            let Some(next) = next else {
+                let pass = (0..self.passes.len())
+                    .find(|&pass| live[pass] && !done[pass])
+                    .unwrap();
+                return Err(&self.passes[pass].name);
+            };
+            done[next] = true;
+            order.push(next);
+        }
+        Ok(order)
+    }
+}
+
+// the transient textures of the graph while its passes are recorded
+pub struct GraphResources {
+    // render targets kept by the renderer between frames
+    allocations: Vec<RenderTarget>,
+    // the allocation of every transient texture, textures used in parts of the frame that
+    // don't overlap share one
+    textures: Vec<Option<usize>>,
+}
+
+impl GraphResources {
+    // the texture to sample `texture` from, none for the drawable and textures of passes
+    // that don't run
+    pub fn texture(&self, texture: GraphTexture) -> Option<&Texture> {
+        let allocation = (*self.textures.get(texture.0)?)?;
+        Some(self.allocations[allocation].texture())
+    }
+}
+
+fn target_size(target: &RenderTarget) -> (usize, usize) {
+    (target.width(), target.height())
+}
+
+// where a recorded pass of the frame renders
+pub(crate) enum FrameTarget {
+    // the drawable, through the pass descriptor of the view. `first` passes clear it and
+    // passes with `later_write` keep it for the passes after them
+    Backbuffer { first: bool, later_write: bool },
+    Texture(Retained<MTLRenderPassDescriptor>),
+}
+
+pub(crate) struct FramePass {
+    pub(crate) target: FrameTarget,
+    pub(crate) render_pass: RenderPass,
+}
+
+// loads what the previous pass into the same attachments stored unless the pass is the first
+// one, and keeps it for the next unless it's the last
+fn set_actions(descriptor: &MTLRenderPassDescriptor, first: bool, later_write: bool) {
+    let color_attachment = unsafe { descriptor.colorAttachments().objectAtIndexedSubscript(0) };
+    let depth_attachment = descriptor.depthAttachment();
+    let stencil_attachment = descriptor.stencilAttachment();
+    if !first {
+        color_attachment.setLoadAction(MTLLoadAction::Load);
+        depth_attachment.setLoadAction(MTLLoadAction::Load);
+        stencil_attachment.setLoadAction(MTLLoadAction::Load);
+    }
+    if later_write {
+        color_attachment.setStoreAction(match color_attachment.resolveTexture() {
+            Some(_) => MTLStoreAction::StoreAndMultisampleResolve,
+            None => MTLStoreAction::Store,
+        });
+        depth_attachment.setStoreAction(MTLStoreAction::Store);
+        stencil_attachment.setStoreAction(MTLStoreAction::Store);
+    }
+}
+
+impl FramePass {
+    // creates the encoder of the pass and encodes its draws, `pass_descriptor` is the one of
+    // the view. returns false if an encoder couldn't be created
+    pub(crate) fn encode(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        pass_descriptor: &Retained<MTLRenderPassDescriptor>,
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+    ) -> bool {
+        let descriptor = match &self.target {
+            FrameTarget::Backbuffer {
+                first: true,
+                later_write: false,
+            } => pass_descriptor.clone(),
+            &FrameTarget::Backbuffer { first, later_write } => {
+                let descriptor = pass_descriptor.copy();
+                set_actions(&descriptor, first, later_write);
+                // the gpu pass time measures the first pass into the drawable
+                if !first {
+                    unsafe {
+                        descriptor
+                            .sampleBufferAttachments()
+                            .objectAtIndexedSubscript(0)
+                            .setSampleBuffer(None)
+                    };
+                }
+                descriptor
+            }
+            FrameTarget::Texture(descriptor) => descriptor.clone(),
+        };
+        self.render_pass
+            .encode(command_buffer, &descriptor, scene_properties)
+    }
+}
+
+impl MetalRenderer {
+    // the heaps the transient textures of the render graph are placed in
+    fn graph_allocator(&self) -> Rc<GpuAllocator> {
+        self.ivars()
+            .graph_allocator
+            .borrow_mut()
+            .get_or_insert_with(|| Rc::new(self.create_gpu_allocator(DEFAULT_HEAP_SIZE)))
+            .clone()
+    }
+
+    // how much of the heaps of the render graph its transient textures use
+    pub fn graph_memory_stats(&self) -> AllocatorStats {
+        self.graph_allocator().stats()
+    }
+
+    // replaces the single pass into the drawable with the passes `render_graph_callback` adds
+    // to the graph of every frame, the render callback isn't called anymore
+    pub fn set_render_graph_callback(
+        &self,
+        render_graph_callback: impl Fn(&Self, &mut RenderGraph) + 'static,
+    ) {
+        self.ivars()
+            .render_graph_callback
+            .replace(Some(Box::new(render_graph_callback)));
+    }
+
+    // records the passes of the frame from the render graph callback, the first pass into the
+    // drawable draws `background` first
+    pub(crate) fn record_render_graph(
+        &self,
+        render_graph_callback: &RenderGraphCallback,
+        drawable_size: NSSize,
+        background: Option<(
+            Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+            GradientProperties,
+        )>,
+    ) -> Vec<FramePass> {
+        let mut graph = RenderGraph {
+            sizes: Vec::new(),
+            passes: Vec::new(),
+        };
+        render_graph_callback(self, &mut graph);
+
+        let order = match graph.execution_order() {
+            Ok(order) => order,
+            Err(name) => {
+                self.log(
+                    LogLevel::Error,
+                    &format!("The render graph has a cycle through the pass {name}."),
+                );
+                Vec::new()
+            }
+        };
+
+        // the range of positions in the order every transient texture is used in
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; graph.sizes.len() + 1];
+        for (position, &pass) in order.iter().enumerate() {
+            let pass = &graph.passes[pass];
+            for texture in pass.reads.iter().chain([&pass.target]) {
+                match lifetimes.get_mut(texture.0) {
+                    Some(Some((_, last))) => *last = position,
+                    Some(lifetime) => *lifetime = Some((position, position)),
+                    None => self.log(
+                        LogLevel::Warn,
+                        &format!("The pass {} uses a texture of another graph.", pass.name),
+                    ),
+                }
+            }
+        }
+        lifetimes[BACKBUFFER.0] = None;
+
+        // hand out the textures kept from the previous frames, allocations become free again
+        // after the last pass using them
+        let mut kept: Vec<RenderTarget> = self
+            .ivars()
+            .graph_textures
+            .take()
+            .into_iter()
+            .filter(|allocation| allocation.is_compatible_with(self))
+            .collect();
+        let mut resources = GraphResources {
+            allocations: Vec::new(),
+            textures: vec![None; lifetimes.len()],
+        };
+        let mut free: Vec<usize> = Vec::new();
+        // the allocations aliasing the memory of another one, which can't both be handed out
+        // in the next frame
+        let mut aliases: Vec<usize> = Vec::new();
+        for position in 0..order.len() {
+            for (texture, lifetime) in lifetimes.iter().enumerate() {
+                if lifetime.is_some_and(|(first, _)| first == position) {
+                    let size = graph.sizes[texture - 1];
+                    let reused = free.iter().position(|&allocation| {
+                        target_size(&resources.allocations[allocation]) == size
+                    });
+                    let allocation = match reused {
+                        Some(index) => free.swap_remove(index),
+                        None => {
+                            let kept_target = kept
+                                .iter()
+                                .position(|kept| target_size(kept) == size)
+                                .map(|index| kept.swap_remove(index));
+                            let builder = RenderTargetBuilder::new(size.0, size.1);
+                            // otherwise the memory of a free allocation of another size, when
+                            // the target fits into it
+                            let aliased = || {
+                                free.iter().enumerate().find_map(|(index, &allocation)| {
+                                    let memory = resources.allocations[allocation].allocation()?;
+                                    Some((index, builder.build_aliasing(self, memory)?))
+                                })
+                            };
+                            let target = match kept_target {
+                                Some(target) => target,
+                                None => match aliased() {
+                                    Some((index, target)) => {
+                                        free.swap_remove(index);
+                                        aliases.push(resources.allocations.len());
+                                        target
+                                    }
+                                    None => builder.build_in(self, &self.graph_allocator()),
+                                },
+                            };
+                            resources.allocations.push(target);
+                            resources.allocations.len() - 1
+                        }
+                    };
+                    resources.textures[texture] = Some(allocation);
+                }
+            }
+            for (texture, lifetime) in lifetimes.iter().enumerate() {
+                if lifetime.is_some_and(|(_, last)| last == position) {
+                    free.extend(resources.textures[texture]);
+                }
+            }
+        }
+
+        // record the passes with the actions of their place in the order
+        let mut passes: Vec<Option<GraphPass>> = graph.passes.into_iter().map(Some).collect();
+        let targets: Vec<GraphTexture> = order
+            .iter()
+            .map(|&pass| passes[pass].as_ref().unwrap().target)
+            .collect();
+        let mut background = background;
+        let mut frame_passes = Vec::new();
+        for (position, &pass) in order.iter().enumerate() {
+            let pass = passes[pass].take().unwrap();
+            let first = !targets[..position].contains(&pass.target);
+            let later_write = targets[position + 1..].contains(&pass.target);
+            let (target, mut render_pass) = if pass.target == BACKBUFFER {
+                let mut render_pass = self.frame_render_pass(drawable_size);
+                render_pass.background = background.take();
+                (FrameTarget::Backbuffer { first, later_write }, render_pass)
+            } else {
+                let Some(&Some(allocation)) = resources.textures.get(pass.target.0) else {
+                    continue;
+                };
+                let target = &mut resources.allocations[allocation];
+                target.clear_color = pass.clear_color;
+                // the textures are named after the pass rendering into them first
+                if first {
+                    target.set_label(&pass.name);
+                }
+                let descriptor = target.pass_descriptor();
+                set_actions(&descriptor, first, later_write);
+                let render_pass = RenderPass {
+                    cull_mode: self.ivars().cull_mode.get(),
+                    front_facing: self.ivars().front_facing.get(),
+                    fill_mode: self.ivars().fill_mode.get(),
+                    depth_stencil_state: target
+                        .has_depth()
+                        .then(|| self.ivars().depth_stencil_state.borrow().clone())
+                        .flatten(),
+                    ..Default::default()
+                };
+                (FrameTarget::Texture(descriptor), render_pass)
+            };
+            render_pass.set_label(&pass.name);
+            (pass.record)(self, &resources, &mut render_pass);
+            frame_passes.push(FramePass {
+                target,
+                render_pass,
+            });
+        }
+
+        // the drawable is still cleared when no pass renders into it
+        if !targets.contains(&BACKBUFFER) {
+            let mut render_pass = self.frame_render_pass(drawable_size);
+            render_pass.background = background;
+            frame_passes.push(FramePass {
+                target: FrameTarget::Backbuffer {
+                    first: true,
+                    later_write: false,
+                },
+                render_pass,
+            });
+        }
+
+        // keep the allocations for the next frame, the ones it didn't use are released
+        let allocations = resources
+            .allocations
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !aliases.contains(index))
+            .map(|(_, allocation)| allocation)
+            .collect();
+        self.ivars().graph_textures.replace(allocations);
+        frame_passes
+    }
+}
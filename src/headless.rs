@@ -0,0 +1,60 @@
+use image::RgbaImage;
+use objc2::{rc::Retained, DeclaredClass};
+use objc2_foundation::NSSize;
+
+use crate::{LogLevel, MetalRenderer, ScreenshotError};
+
+impl MetalRenderer {
+    // a renderer without a window, drawing `width` by `height` pixels into textures of its own.
+    // it only draws when asked to with `render_offscreen` or `redraw`, for tests, thumbnails
+    // and servers. the backend it's given is ignored, it still has to be initialized with
+    // `init`
+    pub fn new_headless(width: usize, height: usize) -> Retained<Self> {
+        let renderer = Self::with_window(None);
+        renderer
+            .ivars()
+            .drawable_size
+            .set(NSSize::new(width as f64, height as f64));
+        renderer
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.ivars().window.get().is_none()
+    }
+
+    // resizes the frames of a headless renderer, calling the resize handler like a resized
+    // window would
+    pub fn set_offscreen_size(&self, width: usize, height: usize) {
+        let Some(surface) = self.ivars().surface.get() else {
+            self.log(
+                LogLevel::Warn,
+                "The offscreen size can only be set after init.",
+            );
+            return;
+        };
+        let size = NSSize::new(width as f64, height as f64);
+        if surface.set_offscreen_size(size) {
+            self.drawable_size_changed(size);
+        }
+    }
+
+    // draws a frame of a headless renderer and returns its pixels, blocking until the gpu is
+    // done with it. the shaders are waited for, while the pipelines prepared with
+    // `prepare_pipeline_state` may still be drawn with placeholders
+    pub fn render_offscreen(&self) -> Result<RgbaImage, ScreenshotError> {
+        if let Err(error) = self.finish_shader_compilation() {
+            self.log(LogLevel::Error, &format!("{error}."));
+        }
+        let previous = self.ivars().last_command_buffer.borrow().clone();
+        self.render_frame();
+        let last = self.ivars().last_command_buffer.borrow().clone();
+        let drawn = last
+            .is_some_and(|last| previous.is_none_or(|previous| !core::ptr::eq(&*previous, &*last)));
+        let surface = self.ivars().surface.get();
+        let output = surface.and_then(|surface| surface.offscreen_output());
+        match output {
+            Some(output) if drawn => self.read_texture(&output),
+            _ => Err(ScreenshotError::NoFrame),
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use std::time::Instant;
+
+use imgui::{
+    BackendFlags, ClipboardBackend, Context, DrawCmd, DrawCmdParams, Key,
+    MouseButton as ImguiButton, TextureId, Textures, Ui,
+};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLDevice, MTLPixelFormat, MTLRenderPipelineState, MTLSamplerAddressMode, MTLSamplerDescriptor,
+    MTLSamplerMinMagFilter, MTLSamplerState, MTLTexture,
+};
+use tao::{
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, ModifiersState},
+};
+
+use crate::{
+    input::POINTS_PER_LINE,
+    ui::{
+        clipboard_text, create_ui_texture, scissor_rect, set_clipboard_text, upload_ui_texture,
+        UiDraw, UiMesh, UiVertex,
+    },
+    LogLevel, MetalRenderer,
+};
+
+pub(crate) type ImguiCallback = Box<dyn Fn(&MetalRenderer, &Ui)>;
+
+// copies and pastes through the general pasteboard
+struct Pasteboard;
+
+impl ClipboardBackend for Pasteboard {
+    fn get(&mut self) -> Option<String> {
+        clipboard_text()
+    }
+
+    fn set(&mut self, value: &str) {
+        set_clipboard_text(value);
+    }
+}
+
+fn imgui_key(key: KeyCode) -> Option<Key> {
+    Some(match key {
+        KeyCode::Tab => Key::Tab,
+        KeyCode::ArrowLeft => Key::LeftArrow,
+        KeyCode::ArrowRight => Key::RightArrow,
+        KeyCode::ArrowUp => Key::UpArrow,
+        KeyCode::ArrowDown => Key::DownArrow,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Space => Key::Space,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Escape => Key::Escape,
+        KeyCode::ControlLeft => Key::LeftCtrl,
+        KeyCode::ShiftLeft => Key::LeftShift,
+        KeyCode::AltLeft => Key::LeftAlt,
+        KeyCode::SuperLeft => Key::LeftSuper,
+        KeyCode::ControlRight => Key::RightCtrl,
+        KeyCode::ShiftRight => Key::RightShift,
+        KeyCode::AltRight => Key::RightAlt,
+        KeyCode::SuperRight => Key::RightSuper,
+        KeyCode::Digit0 => Key::Alpha0,
+        KeyCode::Digit1 => Key::Alpha1,
+        KeyCode::Digit2 => Key::Alpha2,
+        KeyCode::Digit3 => Key::Alpha3,
+        KeyCode::Digit4 => Key::Alpha4,
+        KeyCode::Digit5 => Key::Alpha5,
+        KeyCode::Digit6 => Key::Alpha6,
+        KeyCode::Digit7 => Key::Alpha7,
+        KeyCode::Digit8 => Key::Alpha8,
+        KeyCode::Digit9 => Key::Alpha9,
+        KeyCode::KeyA => Key::A,
+        KeyCode::KeyB => Key::B,
+        KeyCode::KeyC => Key::C,
+        KeyCode::KeyD => Key::D,
+        KeyCode::KeyE => Key::E,
+        KeyCode::KeyF => Key::F,
+        KeyCode::KeyG => Key::G,
+        KeyCode::KeyH => Key::H,
+        KeyCode::KeyI => Key::I,
+        KeyCode::KeyJ => Key::J,
+        KeyCode::KeyK => Key::K,
+        KeyCode::KeyL => Key::L,
+        KeyCode::KeyM => Key::M,
+        KeyCode::KeyN => Key::N,
+        KeyCode::KeyO => Key::O,
+        KeyCode::KeyP => Key::P,
+        KeyCode::KeyQ => Key::Q,
+        KeyCode::KeyR => Key::R,
+        KeyCode::KeyS => Key::S,
+        KeyCode::KeyT => Key::T,
+        KeyCode::KeyU => Key::U,
+        KeyCode::KeyV => Key::V,
+        KeyCode::KeyW => Key::W,
+        KeyCode::KeyX => Key::X,
+        KeyCode::KeyY => Key::Y,
+        KeyCode::KeyZ => Key::Z,
+        KeyCode::F1 => Key::F1,
+        KeyCode::F2 => Key::F2,
+        KeyCode::F3 => Key::F3,
+        KeyCode::F4 => Key::F4,
+        KeyCode::F5 => Key::F5,
+        KeyCode::F6 => Key::F6,
+        KeyCode::F7 => Key::F7,
+        KeyCode::F8 => Key::F8,
+        KeyCode::F9 => Key::F9,
+        KeyCode::F10 => Key::F10,
+        KeyCode::F11 => Key::F11,
+        KeyCode::F12 => Key::F12,
+        KeyCode::Quote => Key::Apostrophe,
+        KeyCode::Comma => Key::Comma,
+        KeyCode::Minus => Key::Minus,
+        KeyCode::Period => Key::Period,
+        KeyCode::Slash => Key::Slash,
+        KeyCode::Semicolon => Key::Semicolon,
+        KeyCode::Equal => Key::Equal,
+        KeyCode::BracketLeft => Key::LeftBracket,
+        KeyCode::Backslash => Key::Backslash,
+        KeyCode::BracketRight => Key::RightBracket,
+        KeyCode::Backquote => Key::GraveAccent,
+        KeyCode::Numpad0 => Key::Keypad0,
+        KeyCode::Numpad1 => Key::Keypad1,
+        KeyCode::Numpad2 => Key::Keypad2,
+        KeyCode::Numpad3 => Key::Keypad3,
+        KeyCode::Numpad4 => Key::Keypad4,
+        KeyCode::Numpad5 => Key::Keypad5,
+        KeyCode::Numpad6 => Key::Keypad6,
+        KeyCode::Numpad7 => Key::Keypad7,
+        KeyCode::Numpad8 => Key::Keypad8,
+        KeyCode::Numpad9 => Key::Keypad9,
+        KeyCode::NumpadDecimal => Key::KeypadDecimal,
+        KeyCode::NumpadDivide => Key::KeypadDivide,
+        KeyCode::NumpadMultiply => Key::KeypadMultiply,
+        KeyCode::NumpadSubtract => Key::KeypadSubtract,
+        KeyCode::NumpadAdd => Key::KeypadAdd,
+        KeyCode::NumpadEnter => Key::KeypadEnter,
+        KeyCode::NumpadEqual => Key::KeypadEqual,
+        _ => return None,
+    })
+}
+
+// the imgui context of a renderer and the gpu resources of its output
+pub(crate) struct ImguiState {
+    context: Context,
+    // the font atlas and the textures registered with `register_imgui_texture`
+    textures: Textures<Retained<ProtocolObject<dyn MTLTexture>>>,
+    // whether the font atlas has to be uploaded again before the next frame
+    fonts_changed: bool,
+    sampler: Option<Retained<ProtocolObject<dyn MTLSamplerState>>>,
+    last_frame: Option<Instant>,
+    modifiers: ModifiersState,
+    // created for the pixel format of the drawable
+    pipeline_state: Option<(
+        MTLPixelFormat,
+        Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    )>,
+}
+
+impl ImguiState {
+    fn new() -> Self {
+        let mut context = Context::create();
+        context.set_clipboard_backend(Pasteboard);
+        context.set_platform_name(Some("rust-tao-metal".to_owned()));
+        context.set_renderer_name(Some("rust-tao-metal".to_owned()));
+        // the meshes of a draw list share its buffers
+        context
+            .io_mut()
+            .backend_flags
+            .insert(BackendFlags::RENDERER_HAS_VTX_OFFSET);
+        ImguiState {
+            context,
+            textures: Textures::new(),
+            fonts_changed: true,
+            sampler: None,
+            last_frame: None,
+            modifiers: ModifiersState::empty(),
+            pipeline_state: None,
+        }
+    }
+
+    // drops the pipeline, it's recreated from the current shader library on the next frame
+    pub(crate) fn clear_pipeline_state(&mut self) {
+        self.pipeline_state = None;
+    }
+
+    // queues a window event for the next imgui frame
+    pub(crate) fn handle_event(&mut self, event: &WindowEvent, scale_factor: f64) {
+        let io = self.context.io_mut();
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                io.add_key_event(Key::ModCtrl, modifiers.control_key());
+                io.add_key_event(Key::ModShift, modifiers.shift_key());
+                io.add_key_event(Key::ModAlt, modifiers.alt_key());
+                io.add_key_event(Key::ModSuper, modifiers.super_key());
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = position.to_logical::<f32>(scale_factor);
+                io.add_mouse_pos_event([position.x, position.y]);
+            }
+            WindowEvent::CursorLeft { .. } => io.add_mouse_pos_event([-f32::MAX, -f32::MAX]),
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => ImguiButton::Left,
+                    MouseButton::Right => ImguiButton::Right,
+                    MouseButton::Middle => ImguiButton::Middle,
+                    _ => return,
+                };
+                io.add_mouse_button_event(button, *state == ElementState::Pressed);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let wheel = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => [*x, *y],
+                    MouseScrollDelta::PixelDelta(position) => {
+                        let position = position.to_logical::<f64>(scale_factor);
+                        [
+                            (position.x / POINTS_PER_LINE) as f32,
+                            (position.y / POINTS_PER_LINE) as f32,
+                        ]
+                    }
+                    _ => return,
+                };
+                io.add_mouse_wheel_event(wheel);
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                let pressed = event.state == ElementState::Pressed;
+                if let Some(key) = imgui_key(event.physical_key) {
+                    io.add_key_event(key, pressed);
+                }
+                // typed text, without the control characters of keys like Enter or Backspace
+                // and the letters of shortcuts
+                if let Some(text) = event.text.filter(|text| {
+                    pressed
+                        && !self.modifiers.super_key()
+                        && !self.modifiers.control_key()
+                        && !text.chars().any(char::is_control)
+                }) {
+                    text.chars()
+                        .for_each(|character| io.add_input_character(character));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // uploads the font atlas after the fonts were changed, and creates the sampler of the ui
+    fn prepare_textures(&mut self, device: &ProtocolObject<dyn MTLDevice>) {
+        if self.sampler.is_none() {
+            let descriptor = MTLSamplerDescriptor::new();
+            descriptor.setMinFilter(MTLSamplerMinMagFilter::Linear);
+            descriptor.setMagFilter(MTLSamplerMinMagFilter::Linear);
+            descriptor.setSAddressMode(MTLSamplerAddressMode::ClampToEdge);
+            descriptor.setTAddressMode(MTLSamplerAddressMode::ClampToEdge);
+            self.sampler = Some(
+                device
+                    .newSamplerStateWithDescriptor(&descriptor)
+                    .expect("Failed to create the imgui sampler."),
+            );
+        }
+        if !self.fonts_changed {
+            return;
+        }
+        self.fonts_changed = false;
+        let fonts = self.context.fonts();
+        let atlas = fonts.build_rgba32_texture();
+        let size = (atlas.width as usize, atlas.height as usize);
+        let texture = create_ui_texture(device, size.0, size.1);
+        upload_ui_texture(&texture, (0, 0), size, atlas.data);
+        if self
+            .textures
+            .replace(fonts.tex_id, texture.clone())
+            .is_none()
+        {
+            fonts.tex_id = self.textures.insert(texture);
+        }
+    }
+}
+
+impl MetalRenderer {
+    // builds a Dear ImGui ui every frame, drawn over the scene after the render callback. the
+    // window events handed to `handle_window_event` drive it. the context is borrowed while
+    // the callback runs, so it can't call `with_imgui_context` or `register_imgui_texture`.
+    // imgui has a single context per thread, only one renderer can draw it
+    pub fn set_imgui_callback(&self, imgui_callback: impl Fn(&Self, &Ui) + 'static) {
+        self.ivars()
+            .imgui
+            .borrow_mut()
+            .get_or_insert_with(ImguiState::new);
+        self.ivars()
+            .imgui_callback
+            .replace(Some(Box::new(imgui_callback)));
+    }
+
+    // calls `f` with the imgui context to change its style or fonts, or to check whether the
+    // ui wants the input with `io().want_capture_mouse`. the font atlas is uploaded again
+    // before the next frame. returns None before `set_imgui_callback` created the context
+    pub fn with_imgui_context<R>(&self, f: impl FnOnce(&mut Context) -> R) -> Option<R> {
+        let mut imgui = self.ivars().imgui.borrow_mut();
+        let imgui = imgui.as_mut()?;
+        imgui.fonts_changed = true;
+        Some(f(&mut imgui.context))
+    }
+
+    // makes `texture` available to `Ui::image`, it's drawn with the colors it stores. the
+    // texture is kept until it's unregistered
+    pub fn register_imgui_texture(
+        &self,
+        texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+    ) -> TextureId {
+        let mut imgui = self.ivars().imgui.borrow_mut();
+        let imgui = imgui.get_or_insert_with(ImguiState::new);
+        imgui.textures.insert(texture.clone())
+    }
+
+    pub fn unregister_imgui_texture(&self, texture_id: TextureId) {
+        if let Some(imgui) = self.ivars().imgui.borrow_mut().as_mut() {
+            imgui.textures.remove(texture_id);
+        }
+    }
+
+    fn imgui_pipeline_state(
+        &self,
+        pixel_format: MTLPixelFormat,
+    ) -> Option<Retained<ProtocolObject<dyn MTLRenderPipelineState>>> {
+        if let Some(imgui) = self.ivars().imgui.borrow().as_ref() {
+            if let Some((format, pipeline_state)) = &imgui.pipeline_state {
+                if *format == pixel_format {
+                    return Some(pipeline_state.clone());
+                }
+            }
+        }
+        let pipeline_state =
+            self.create_overlay_pipeline_state("vertex_ui", "fragment_imgui", pixel_format)?;
+        if let Some(imgui) = self.ivars().imgui.borrow_mut().as_mut() {
+            imgui.pipeline_state = Some((pixel_format, pipeline_state.clone()));
+        }
+        Some(pipeline_state)
+    }
+
+    // runs the imgui callback and uploads its draw lists for `drawable_texture` while the frame
+    // is recorded, returns None without a callback
+    pub(crate) fn record_imgui(
+        &self,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> Option<UiDraw> {
+        let imgui_callback = self.ivars().imgui_callback.borrow();
+        let imgui_callback = imgui_callback.as_ref()?;
+
+        let pixel_format = drawable_texture.pixelFormat();
+        let pipeline_state = self.imgui_pipeline_state(pixel_format)?;
+        let mut imgui = self.ivars().imgui.borrow_mut();
+        let imgui = imgui.as_mut()?;
+        imgui.prepare_textures(&self.device());
+
+        let scale = self.scale_factor() as f32;
+        let drawable_size = (drawable_texture.width(), drawable_texture.height());
+        let screen_size = [
+            drawable_size.0 as f32 / scale,
+            drawable_size.1 as f32 / scale,
+        ];
+        let now = Instant::now();
+        let io = imgui.context.io_mut();
+        io.update_delta_time(now - imgui.last_frame.unwrap_or(now));
+        io.display_size = screen_size;
+        io.display_framebuffer_scale = [scale, scale];
+        imgui.last_frame = Some(now);
+
+        imgui_callback(self, imgui.context.new_frame());
+        let draw_data = imgui.context.render();
+
+        let sampler = imgui.sampler.clone()?;
+        let mut meshes = Vec::new();
+        for draw_list in draw_data.draw_lists() {
+            let vertices: Vec<UiVertex> = draw_list
+                .vtx_buffer()
+                .iter()
+                .map(|vertex| UiVertex {
+                    position: vertex.pos,
+                    uv: vertex.uv,
+                    color: vertex.col,
+                })
+                .collect();
+            let indices: Vec<u32> = draw_list
+                .idx_buffer()
+                .iter()
+                .map(|&index| index.into())
+                .collect();
+            if indices.is_empty() {
+                continue;
+            }
+            let vertices = self.frame_buffer(&vertices);
+            let indices = self.frame_buffer(&indices);
+            for command in draw_list.commands() {
+                let DrawCmd::Elements {
+                    count,
+                    cmd_params:
+                        DrawCmdParams {
+                            clip_rect,
+                            texture_id,
+                            vtx_offset,
+                            idx_offset,
+                        },
+                } = command
+                else {
+                    if let DrawCmd::RawCallback { .. } = command {
+                        self.log(LogLevel::Warn, "Imgui draw callbacks are not supported.");
+                    }
+                    continue;
+                };
+                let Some(texture) = imgui.textures.get(texture_id) else {
+                    self.log(LogLevel::Warn, "Imgui drew with an unregistered texture.");
+                    continue;
+                };
+                // the clip rect in pixels, relative to the top left corner of the view
+                let [left, top] = draw_data.display_pos;
+                let clip_rect = [
+                    (clip_rect[0] - left) * scale,
+                    (clip_rect[1] - top) * scale,
+                    (clip_rect[2] - left) * scale,
+                    (clip_rect[3] - top) * scale,
+                ];
+                let Some(scissor_rect) = scissor_rect(clip_rect, drawable_size) else {
+                    continue;
+                };
+                meshes.push(UiMesh {
+                    scissor_rect,
+                    vertices: vertices.clone(),
+                    indices: indices.clone(),
+                    vertex_offset: vtx_offset,
+                    index_offset: idx_offset,
+                    index_count: count,
+                    texture: texture.clone(),
+                    sampler: sampler.clone(),
+                });
+            }
+        }
+
+        Some(UiDraw::new(
+            pipeline_state,
+            meshes,
+            screen_size,
+            pixel_format,
+        ))
+    }
+}
@@ -0,0 +1,206 @@
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_foundation::NSString;
+use objc2_metal::{
+    MTLArgumentEncoder, MTLBuffer, MTLDevice, MTLFunction, MTLGPUFamily, MTLIndirectCommandBuffer,
+    MTLIndirectCommandBufferDescriptor, MTLIndirectCommandType, MTLLibrary, MTLResourceOptions,
+};
+
+use crate::{
+    scene_graph::draw_instances, BoundingBox, ComputePass, GpuBuffer, InstanceData, LogLevel,
+    Material, Mesh, MetalRenderer, PipelineDescriptor, RenderPass,
+};
+
+// the instances `cull_instances` tests against the view and the draws it encodes for them,
+// `CullingProperties` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct CullingProperties {
+    bounds_min: [f32; 3],
+    instance_count: u32,
+    bounds_max: [f32; 3],
+    index_count: u32,
+}
+
+// the buffer index of the argument buffer `cull_instances` encodes the draws through
+const ARGUMENTS_INDEX: usize = 3;
+
+// the commands of the visible instances and the argument buffer the kernel reaches them by
+struct IndirectCommands {
+    command_buffer: Retained<ProtocolObject<dyn MTLIndirectCommandBuffer>>,
+    arguments: Retained<ProtocolObject<dyn MTLBuffer>>,
+}
+
+// many copies of a mesh culled against the view on the gpu. `cull` records a dispatch testing
+// the box of every instance and encoding a draw of the visible ones into an indirect command
+// buffer, which `draw` executes in the same frame, so the cpu never touches the instances
+// after they're uploaded. devices that can't encode draws from kernels draw them all as one
+// instanced draw instead, culled by the cpu as a whole
+pub struct CulledInstances {
+    mesh: Rc<Mesh>,
+    instances: GpuBuffer<InstanceData>,
+    // the box around all the instances, for the draws culled by the cpu
+    bounds: BoundingBox,
+    indirect_commands: Option<IndirectCommands>,
+}
+
+impl CulledInstances {
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    // whether the instances are culled on the gpu, false on devices without the support
+    pub fn is_gpu_driven(&self) -> bool {
+        self.indirect_commands.is_some()
+    }
+
+    // records the culling of the instances against the view of the frame, from the compute
+    // callback
+    pub fn cull(&self, renderer: &MetalRenderer, compute_pass: &mut ComputePass) {
+        let Some(indirect_commands) = &self.indirect_commands else {
+            return;
+        };
+        let properties = renderer.frame_buffer(&[CullingProperties {
+            bounds_min: self.mesh.bounds.min,
+            instance_count: self.instances.len() as u32,
+            bounds_max: self.mesh.bounds.max,
+            index_count: self.mesh.index_count as u32,
+        }]);
+        compute_pass
+            .dispatch(
+                &renderer.compute_pipeline_state("cull_instances"),
+                (self.instances.len(), 1, 1),
+            )
+            .with_buffer(1, self.instances.buffer())
+            .with_buffer(2, &properties)
+            .with_buffer(ARGUMENTS_INDEX, &indirect_commands.arguments)
+            .with_buffer(4, &self.mesh.index_buffer)
+            .with_indirect_command_buffer(&indirect_commands.command_buffer);
+    }
+
+    // draws the instances `cull` left visible with the pipeline of `material`, which has to be
+    // built with `indirect_commands`, or with `vertex_instanced` and `fragment_main` without
+    // one. they're culled against the camera of the frame, passes seeing the scene from
+    // elsewhere like the shadow pass would miss the instances it doesn't see
+    pub fn draw(
+        &self,
+        renderer: &MetalRenderer,
+        render_pass: &mut RenderPass,
+        material: Option<&Material>,
+    ) {
+        let Some(indirect_commands) = &self.indirect_commands else {
+            draw_instances(
+                renderer,
+                render_pass,
+                &self.mesh,
+                material,
+                self.instances.contents(),
+            );
+            return;
+        };
+        let pipeline_state = match material {
+            Some(material) => material.pipeline_state.clone(),
+            None => renderer.render_pipeline_state_for(&PipelineDescriptor {
+                indirect_commands: true,
+                ..renderer.pipeline_descriptor("vertex_instanced", "fragment_main")
+            }),
+        };
+        render_pass
+            .draw_mesh(&pipeline_state, &self.mesh)
+            .with_instances(&self.instances)
+            .with_bounds(self.bounds);
+        if let Some(fragment_arguments) =
+            material.and_then(|material| material.fragment_arguments.as_ref())
+        {
+            render_pass.with_fragment_arguments(fragment_arguments);
+        }
+        if let Some(item) = render_pass.items.last_mut() {
+            item.indirect_commands = Some(indirect_commands.command_buffer.clone());
+        }
+    }
+
+    // the box around all the instances
+    pub fn bounds(&self) -> BoundingBox {
+        self.bounds
+    }
+}
+
+impl MetalRenderer {
+    // whether kernels can encode draws into indirect command buffers
+    pub fn supports_indirect_commands(&self) -> bool {
+        let device = self.device();
+        device.supportsFamily(MTLGPUFamily::Apple4) || device.supportsFamily(MTLGPUFamily::Mac2)
+    }
+
+    // uploads `instances` of `mesh` for culling on the gpu where the device supports it
+    pub fn create_culled_instances(
+        &self,
+        mesh: Rc<Mesh>,
+        instances: &[InstanceData],
+    ) -> CulledInstances {
+        let bounds = instances
+            .iter()
+            .map(|instance| mesh.bounds.transformed(&instance.transform))
+            .reduce(|bounds, instance_bounds| bounds.union(&instance_bounds))
+            .unwrap_or(mesh.bounds);
+        let indirect_commands = if self.supports_indirect_commands() {
+            Some(self.create_indirect_commands(instances.len()))
+        } else {
+            self.log(
+                LogLevel::Info,
+                "Indirect command buffers are unsupported, culling the instances on the cpu.",
+            );
+            None
+        };
+        CulledInstances {
+            mesh,
+            instances: self.create_gpu_buffer(instances),
+            bounds,
+            indirect_commands,
+        }
+    }
+
+    // an indirect command buffer of `count` indexed draws, which take the pipeline and the
+    // buffers of the render encoder executing them
+    fn create_indirect_commands(&self, count: usize) -> IndirectCommands {
+        let device = self.device();
+        let descriptor = unsafe { MTLIndirectCommandBufferDescriptor::new() };
+        descriptor.setCommandTypes(MTLIndirectCommandType::DrawIndexed);
+        descriptor.setInheritPipelineState(true);
+        descriptor.setInheritBuffers(true);
+        let command_buffer = unsafe {
+            device.newIndirectCommandBufferWithDescriptor_maxCommandCount_options(
+                &descriptor,
+                count.max(1),
+                MTLResourceOptions::MTLResourceStorageModePrivate,
+            )
+        }
+        .expect("Failed to create an indirect command buffer.");
+
+        let function = self
+            .library()
+            .ok()
+            .and_then(|library| library.newFunctionWithName(&NSString::from_str("cull_instances")))
+            .expect("Failed to find the culling kernel.");
+        let encoder = unsafe { function.newArgumentEncoderWithBufferIndex(ARGUMENTS_INDEX) };
+        let arguments = device
+            .newBufferWithLength_options(
+                encoder.encodedLength(),
+                MTLResourceOptions::MTLResourceStorageModeShared,
+            )
+            .expect("Failed to create an argument buffer.");
+        unsafe {
+            encoder.setArgumentBuffer_offset(Some(&arguments), 0);
+            encoder.setIndirectCommandBuffer_atIndex(Some(&command_buffer), 0);
+        }
+        IndirectCommands {
+            command_buffer,
+            arguments,
+        }
+    }
+}
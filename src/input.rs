@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use objc2::DeclaredClass;
+use tao::{
+    error::ExternalError,
+    event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::{KeyCode, ModifiersState},
+    window::Window,
+};
+
+use crate::MetalRenderer;
+
+// how many lines a trackpad scrolls per point
+pub(crate) const POINTS_PER_LINE: f64 = 10.;
+
+// the keyboard, mouse and touch state of a window, collected from its events and handed to the
+// update callback every frame. the changes since the last frame are cleared after each update
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    keys_down: HashSet<KeyCode>,
+    keys_pressed: HashSet<KeyCode>,
+    modifiers: ModifiersState,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    // in points from the top left corner of the view
+    cursor_position: Option<(f64, f64)>,
+    cursor_delta: (f64, f64),
+    // in the units of the mouse, from its device events
+    mouse_motion: (f64, f64),
+    // in lines, positive away from the user and to the right
+    scroll: (f32, f32),
+    // the fingers on the screen by their touch id, in points from the top left corner of the
+    // view
+    touches: HashMap<u64, (f64, f64)>,
+    // the first finger down, it moves the cursor and holds the left button like a mouse would
+    primary_touch: Option<u64>,
+}
+
+impl InputState {
+    pub fn is_key_down(&self, key: KeyCode) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    // whether `key` went down since the last update, key repeats don't count
+    pub fn was_key_pressed(&self, key: KeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn was_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    // None until the cursor entered the view
+    pub fn cursor_position(&self) -> Option<(f64, f64)> {
+        self.cursor_position
+    }
+
+    // how far the cursor moved since the last update, in points
+    pub fn cursor_delta(&self) -> (f64, f64) {
+        self.cursor_delta
+    }
+
+    // how far the mouse moved since the last update in the units of the device, unaffected by
+    // the edges of the screen. unlike the cursor delta it keeps coming while the cursor is
+    // locked, which makes it the one to turn a camera with
+    pub fn mouse_motion(&self) -> (f64, f64) {
+        self.mouse_motion
+    }
+
+    // how far the wheel or trackpad scrolled since the last update, in lines
+    pub fn scroll(&self) -> (f32, f32) {
+        self.scroll
+    }
+
+    // the positions of the fingers on the screen by their touch id
+    pub fn touches(&self) -> &HashMap<u64, (f64, f64)> {
+        &self.touches
+    }
+
+    fn handle_event(&mut self, event: &WindowEvent, scale_factor: f64) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => match event.state {
+                ElementState::Pressed => self.key_pressed(event.physical_key, event.repeat),
+                ElementState::Released => self.key_released(event.physical_key),
+                _ => (),
+            },
+            WindowEvent::ModifiersChanged(modifiers) => self.set_modifiers(*modifiers),
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => self.button_pressed(*button),
+                ElementState::Released => self.button_released(*button),
+                _ => (),
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_moved(position.to_logical::<f64>(scale_factor).into())
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor_left(),
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => self.scrolled((*x, *y)),
+                MouseScrollDelta::PixelDelta(position) => {
+                    self.scrolled_points(position.to_logical::<f64>(scale_factor).into())
+                }
+                _ => (),
+            },
+            WindowEvent::Touch(touch) => {
+                let position = touch.location.to_logical::<f64>(scale_factor).into();
+                match touch.phase {
+                    TouchPhase::Started => self.touch_started(touch.id, position),
+                    TouchPhase::Moved => self.touch_moved(touch.id, position),
+                    TouchPhase::Ended | TouchPhase::Cancelled => self.touch_ended(touch.id),
+                    _ => (),
+                }
+            }
+            WindowEvent::Focused(false) => self.focus_lost(),
+            _ => (),
+        }
+    }
+
+    // the events of the windowing libraries, once translated to the keys and buttons of tao
+
+    pub(crate) fn key_pressed(&mut self, key: KeyCode, repeat: bool) {
+        if !repeat {
+            self.keys_down.insert(key);
+            self.keys_pressed.insert(key);
+        }
+    }
+
+    pub(crate) fn key_released(&mut self, key: KeyCode) {
+        self.keys_down.remove(&key);
+    }
+
+    pub(crate) fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub(crate) fn button_pressed(&mut self, button: MouseButton) {
+        self.buttons_down.insert(button);
+        self.buttons_pressed.insert(button);
+    }
+
+    pub(crate) fn button_released(&mut self, button: MouseButton) {
+        self.buttons_down.remove(&button);
+    }
+
+    // `position` in points
+    pub(crate) fn cursor_moved(&mut self, position: (f64, f64)) {
+        if let Some((x, y)) = self.cursor_position {
+            self.cursor_delta.0 += position.0 - x;
+            self.cursor_delta.1 += position.1 - y;
+        }
+        self.cursor_position = Some(position);
+    }
+
+    pub(crate) fn cursor_left(&mut self) {
+        self.cursor_position = None;
+    }
+
+    // `delta` in the units of the mouse
+    pub(crate) fn mouse_moved(&mut self, delta: (f64, f64)) {
+        self.mouse_motion.0 += delta.0;
+        self.mouse_motion.1 += delta.1;
+    }
+
+    // `lines` scrolled by a wheel
+    pub(crate) fn scrolled(&mut self, lines: (f32, f32)) {
+        self.scroll.0 += lines.0;
+        self.scroll.1 += lines.1;
+    }
+
+    // `points` scrolled by a trackpad
+    pub(crate) fn scrolled_points(&mut self, points: (f64, f64)) {
+        let lines = (points.0 / POINTS_PER_LINE, points.1 / POINTS_PER_LINE);
+        self.scrolled((lines.0 as f32, lines.1 as f32));
+    }
+
+    // `position` in points. the first finger down acts as the mouse, the cursor jumps to it
+    // without a delta and the left button goes down
+    pub(crate) fn touch_started(&mut self, id: u64, position: (f64, f64)) {
+        self.touches.insert(id, position);
+        if self.primary_touch.is_none() {
+            self.primary_touch = Some(id);
+            self.cursor_position = Some(position);
+            self.button_pressed(MouseButton::Left);
+        }
+    }
+
+    pub(crate) fn touch_moved(&mut self, id: u64, position: (f64, f64)) {
+        self.touches.insert(id, position);
+        if self.primary_touch == Some(id) {
+            self.cursor_moved(position);
+        }
+    }
+
+    // a cancelled touch ends like a lifted one
+    pub(crate) fn touch_ended(&mut self, id: u64) {
+        self.touches.remove(&id);
+        if self.primary_touch == Some(id) {
+            self.primary_touch = None;
+            self.button_released(MouseButton::Left);
+            self.cursor_left();
+        }
+    }
+
+    // the releases go to the window in focus, forget what was held down
+    pub(crate) fn focus_lost(&mut self) {
+        self.keys_down.clear();
+        self.buttons_down.clear();
+        self.modifiers = ModifiersState::empty();
+        self.touches.clear();
+        self.primary_touch = None;
+    }
+
+    fn end_update(&mut self) {
+        self.keys_pressed.clear();
+        self.buttons_pressed.clear();
+        self.cursor_delta = (0., 0.);
+        self.mouse_motion = (0., 0.);
+        self.scroll = (0., 0.);
+    }
+}
+
+// how the cursor behaves over the window of `MetalRenderer::set_cursor_mode`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CursorMode {
+    #[default]
+    Normal,
+    // hidden while it's over the window
+    Hidden,
+    // kept from leaving the window. macOS has no way to confine it, tao holds it in place there
+    Confined,
+    // held in place and hidden, only the mouse motion tells where the mouse goes, like for a
+    // camera flying through the scene
+    Locked,
+}
+
+pub(crate) type UpdateCallback = Box<dyn Fn(&MetalRenderer, &InputState, f32)>;
+
+impl MetalRenderer {
+    // feeds an event of the renderer's window into its input state
+    pub fn handle_window_event(&self, event: &WindowEvent) {
+        let scale_factor = self.scale_factor();
+        self.ivars()
+            .input
+            .borrow_mut()
+            .handle_event(event, scale_factor);
+        #[cfg(feature = "egui")]
+        self.ivars()
+            .egui
+            .borrow_mut()
+            .handle_event(event, scale_factor);
+        #[cfg(feature = "imgui")]
+        if let Some(imgui) = self.ivars().imgui.borrow_mut().as_mut() {
+            imgui.handle_event(event, scale_factor);
+        }
+    }
+
+    // feeds a device event into the input state, the motion of the mouse comes with these
+    pub fn handle_device_event(&self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta, .. } = event {
+            self.ivars().input.borrow_mut().mouse_moved(*delta);
+        }
+    }
+
+    // grabs and hides the cursor over `window`, the window of the renderer. the mode is kept
+    // when tao can't grab the cursor, like on iOS. the application releases it again, when the
+    // window loses the focus for one
+    pub fn set_cursor_mode(&self, window: &Window, mode: CursorMode) -> Result<(), ExternalError> {
+        let grab = matches!(mode, CursorMode::Confined | CursorMode::Locked);
+        window.set_cursor_grab(grab)?;
+        window.set_cursor_visible(matches!(mode, CursorMode::Normal | CursorMode::Confined));
+        self.ivars().cursor_mode.set(mode);
+        Ok(())
+    }
+
+    pub fn cursor_mode(&self) -> CursorMode {
+        self.ivars().cursor_mode.get()
+    }
+
+    // called before the draws of every frame are recorded with the input since the previous
+    // frame and the seconds passed since then, after the fixed updates of the frame
+    pub fn set_update_callback(&self, update_callback: impl Fn(&Self, &InputState, f32) + 'static) {
+        self.ivars()
+            .update_callback
+            .replace(Some(Box::new(update_callback)));
+    }
+
+    pub(crate) fn update(&self) {
+        let now = Instant::now();
+        let last_update = self.ivars().last_update.replace(Some(now));
+        let elapsed = last_update.map_or(0., |last_update| (now - last_update).as_secs_f32());
+        self.fixed_update(elapsed);
+        // the callback gets a copy, leaving the input free for `handle_window_event`
+        let input = self.ivars().input.borrow().clone();
+        if let Some(update_callback) = self.ivars().update_callback.borrow().as_ref() {
+            update_callback(self, &input, elapsed);
+        }
+        self.ivars().input.borrow_mut().end_update();
+    }
+}
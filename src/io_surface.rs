@@ -0,0 +1,359 @@
+// textures backed by IOSurfaces, memory other processes map without copying it, e.g. a
+// Syphon-style client or the compositor of an electron host. a renderer can render into a
+// surface through a `RenderTarget` of its texture, or copy every frame into one it presents to
+use std::fmt;
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use core_foundation::base::{CFRelease, CFRetain};
+use objc2::{
+    msg_send_id, rc::Retained, runtime::ProtocolObject, DeclaredClass, Encoding, RefEncode,
+};
+use objc2_foundation::{NSDictionary, NSNumber, NSString};
+use objc2_metal::{
+    MTLBlitCommandEncoder, MTLCommandBuffer, MTLCommandEncoder, MTLDevice, MTLOrigin,
+    MTLPixelFormat, MTLSize, MTLTexture, MTLTextureDescriptor, MTLTextureUsage,
+};
+
+use crate::{LogLevel, MetalRenderer};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// opaque io surfaces
+#[repr(C)]
+pub(crate) struct IOSurface {
+    _private: [u8; 0],
+}
+
+unsafe impl RefEncode for IOSurface {
+    const ENCODING_REF: Encoding = Encoding::Pointer(&Encoding::Struct("__IOSurface", &[]));
+}
+
+#[link(name = "IOSurface", kind = "framework")]
+extern "C" {
+    static kIOSurfaceWidth: &'static NSString;
+    static kIOSurfaceHeight: &'static NSString;
+    static kIOSurfaceBytesPerElement: &'static NSString;
+    static kIOSurfacePixelFormat: &'static NSString;
+    #[cfg(target_os = "macos")]
+    static kIOSurfaceIsGlobal: &'static NSString;
+    fn IOSurfaceCreate(properties: &NSDictionary<NSString, NSNumber>) -> *mut IOSurface;
+    fn IOSurfaceLookup(id: u32) -> *mut IOSurface;
+    fn IOSurfaceLookupFromMachPort(port: u32) -> *mut IOSurface;
+    fn IOSurfaceCreateMachPort(surface: *mut IOSurface) -> u32;
+    fn IOSurfaceGetID(surface: *mut IOSurface) -> u32;
+    fn IOSurfaceGetWidth(surface: *mut IOSurface) -> usize;
+    fn IOSurfaceGetHeight(surface: *mut IOSurface) -> usize;
+    fn IOSurfaceGetPixelFormat(surface: *mut IOSurface) -> u32;
+}
+
+// the pixel formats of the surfaces textures are created for, as four character codes with
+// the bytes of a pixel. 'RGhA' is the half float one
+const PIXEL_FORMATS: [(MTLPixelFormat, u32, usize); 3] = [
+    (MTLPixelFormat::BGRA8Unorm, u32::from_be_bytes(*b"BGRA"), 4),
+    (
+        MTLPixelFormat::BGRA8Unorm_sRGB,
+        u32::from_be_bytes(*b"BGRA"),
+        4,
+    ),
+    (MTLPixelFormat::RGBA16Float, u32::from_be_bytes(*b"RGhA"), 8),
+];
+
+#[derive(Debug)]
+pub enum SharedSurfaceError {
+    // only BGRA8Unorm, its srgb variant and RGBA16Float surfaces are created
+    UnsupportedFormat(MTLPixelFormat),
+    // the four character code of an opened surface without a metal pixel format
+    UnsupportedSurfaceFormat(u32),
+    Create,
+    // no surface has the id or the port, or it was released by the process sharing it
+    NotFound,
+}
+
+impl fmt::Display for SharedSurfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SharedSurfaceError::UnsupportedFormat(pixel_format) => {
+                write!(
+                    f,
+                    "Shared surfaces can't have the pixel format {pixel_format:?}."
+                )
+            }
+            SharedSurfaceError::UnsupportedSurfaceFormat(format) => {
+                let code = String::from_utf8_lossy(&format.to_be_bytes()).into_owned();
+                write!(f, "The surface has the unsupported pixel format '{code}'.")
+            }
+            SharedSurfaceError::Create => write!(f, "Failed to create a shared surface."),
+            SharedSurfaceError::NotFound => write!(f, "The shared surface doesn't exist."),
+        }
+    }
+}
+
+impl std::error::Error for SharedSurfaceError {}
+
+// an IOSurface and a texture of the device of the renderer using its memory. clones share the
+// surface, it's released with the last one
+pub struct SharedSurface {
+    surface: NonNull<IOSurface>,
+    texture: Texture,
+}
+
+impl Clone for SharedSurface {
+    fn clone(&self) -> Self {
+        unsafe { CFRetain(self.surface.as_ptr().cast()) };
+        SharedSurface {
+            surface: self.surface,
+            texture: self.texture.clone(),
+        }
+    }
+}
+
+impl Drop for SharedSurface {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.surface.as_ptr().cast()) };
+    }
+}
+
+impl SharedSurface {
+    // takes over the reference of `surface`, released when wrapping it fails. the texture has
+    // `pixel_format`, or the one of the four character code of the surface without it
+    fn new(
+        device: &ProtocolObject<dyn MTLDevice>,
+        surface: *mut IOSurface,
+        pixel_format: Option<MTLPixelFormat>,
+    ) -> Result<Self, SharedSurfaceError> {
+        let surface = NonNull::new(surface).ok_or(SharedSurfaceError::NotFound)?;
+        let texture = match pixel_format {
+            Some(pixel_format) => surface_texture(device, surface, pixel_format),
+            None => surface_pixel_format(surface)
+                .and_then(|pixel_format| surface_texture(device, surface, pixel_format)),
+        };
+        let texture = texture.inspect_err(|_| unsafe { CFRelease(surface.as_ptr().cast()) })?;
+        Ok(SharedSurface { surface, texture })
+    }
+
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn width(&self) -> usize {
+        self.texture.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.texture.height()
+    }
+
+    // the IOSurfaceRef, for handing the surface to apis taking one
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.surface.as_ptr().cast()
+    }
+
+    // the id other processes open the surface with `open_shared_surface`. sandboxed ones can't
+    // look surfaces up by id, they take a mach port
+    pub fn id(&self) -> u32 {
+        unsafe { IOSurfaceGetID(self.surface.as_ptr()) }
+    }
+
+    // a send right to the surface, for passing it to another process in a mach or xpc message,
+    // which opens it with `open_shared_surface_port`. the right is the caller's to deallocate
+    pub fn create_mach_port(&self) -> u32 {
+        unsafe { IOSurfaceCreateMachPort(self.surface.as_ptr()) }
+    }
+
+    // the surface with a texture of `device` in place of the one it has
+    fn with_device(&self, device: &ProtocolObject<dyn MTLDevice>) -> Option<Self> {
+        let texture = surface_texture(device, self.surface, self.texture.pixelFormat()).ok()?;
+        Some(SharedSurface {
+            texture,
+            ..self.clone()
+        })
+    }
+}
+
+fn surface_pixel_format(surface: NonNull<IOSurface>) -> Result<MTLPixelFormat, SharedSurfaceError> {
+    let format = unsafe { IOSurfaceGetPixelFormat(surface.as_ptr()) };
+    PIXEL_FORMATS
+        .into_iter()
+        .find(|(_, surface_format, _)| *surface_format == format)
+        .map(|(pixel_format, ..)| pixel_format)
+        .ok_or(SharedSurfaceError::UnsupportedSurfaceFormat(format))
+}
+
+// a texture using the memory of `surface`, usable as a render target and by shaders
+fn surface_texture(
+    device: &ProtocolObject<dyn MTLDevice>,
+    surface: NonNull<IOSurface>,
+    pixel_format: MTLPixelFormat,
+) -> Result<Texture, SharedSurfaceError> {
+    let surface = surface.as_ptr();
+    let (width, height) = unsafe { (IOSurfaceGetWidth(surface), IOSurfaceGetHeight(surface)) };
+    let descriptor = unsafe {
+        MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+            pixel_format,
+            width,
+            height,
+            false,
+        )
+    };
+    descriptor.setUsage(
+        MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead | MTLTextureUsage::ShaderWrite,
+    );
+    let texture: Option<Texture> = unsafe {
+        msg_send_id![
+            device,
+            newTextureWithDescriptor: &*descriptor,
+            iosurface: surface,
+            plane: 0usize
+        ]
+    };
+    texture.ok_or(SharedSurfaceError::Create)
+}
+
+impl MetalRenderer {
+    // a new surface of `width` by `height` pixels to share with other processes
+    pub fn create_shared_surface(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_format: MTLPixelFormat,
+    ) -> Result<SharedSurface, SharedSurfaceError> {
+        let (_, format, bytes_per_pixel) = PIXEL_FORMATS
+            .into_iter()
+            .find(|(supported, ..)| *supported == pixel_format)
+            .ok_or(SharedSurfaceError::UnsupportedFormat(pixel_format))?;
+        #[cfg_attr(target_os = "ios", allow(unused_mut))]
+        let mut keys = unsafe {
+            vec![
+                kIOSurfaceWidth,
+                kIOSurfaceHeight,
+                kIOSurfaceBytesPerElement,
+                kIOSurfacePixelFormat,
+            ]
+        };
+        #[cfg_attr(target_os = "ios", allow(unused_mut))]
+        let mut values = vec![
+            NSNumber::new_usize(width),
+            NSNumber::new_usize(height),
+            NSNumber::new_usize(bytes_per_pixel),
+            NSNumber::new_u32(format),
+        ];
+        // looked up by id from other processes
+        #[cfg(target_os = "macos")]
+        {
+            keys.push(unsafe { kIOSurfaceIsGlobal });
+            values.push(NSNumber::new_bool(true));
+        }
+        let properties = NSDictionary::from_vec(&keys, values);
+        let surface = unsafe { IOSurfaceCreate(&properties) };
+        if surface.is_null() {
+            return Err(SharedSurfaceError::Create);
+        }
+        SharedSurface::new(&self.device(), surface, Some(pixel_format))
+    }
+
+    // the surface of another process with the id of `SharedSurface::id`
+    pub fn open_shared_surface(&self, id: u32) -> Result<SharedSurface, SharedSurfaceError> {
+        SharedSurface::new(&self.device(), unsafe { IOSurfaceLookup(id) }, None)
+    }
+
+    // the surface of another process sent as a mach port by `SharedSurface::create_mach_port`
+    pub fn open_shared_surface_port(&self, port: u32) -> Result<SharedSurface, SharedSurfaceError> {
+        SharedSurface::new(
+            &self.device(),
+            unsafe { IOSurfaceLookupFromMachPort(port) },
+            None,
+        )
+    }
+
+    // wraps an IOSurfaceRef the application got elsewhere, e.g. from the host embedding it
+    //
+    // SAFETY: `surface` has to be a valid IOSurfaceRef, it's retained by the shared surface
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn wrap_shared_surface(
+        &self,
+        surface: *mut c_void,
+    ) -> Result<SharedSurface, SharedSurfaceError> {
+        let surface = surface.cast::<IOSurface>();
+        if !surface.is_null() {
+            CFRetain(surface.cast());
+        }
+        SharedSurface::new(&self.device(), surface, None)
+    }
+
+    // copies every frame into `surface` once it's drawn, where another process picks it up,
+    // until it's set to none. the frames are copied into its top left corner, they need the
+    // pixel format of the surface
+    pub fn set_output_surface(&self, surface: Option<SharedSurface>) {
+        if surface.is_some() {
+            if let Some(view_surface) = self.ivars().surface.get() {
+                view_surface.set_framebuffer_only(false);
+            }
+        }
+        self.ivars().output_surface.replace(surface);
+    }
+
+    pub fn output_surface(&self) -> Option<SharedSurface> {
+        self.ivars().output_surface.borrow().clone()
+    }
+
+    // the output surface with a texture of the device the renderer moved to
+    pub(crate) fn move_output_surface(&self, device: &ProtocolObject<dyn MTLDevice>) {
+        let mut output_surface = self.ivars().output_surface.borrow_mut();
+        *output_surface = output_surface
+            .as_ref()
+            .and_then(|surface| surface.with_device(device));
+    }
+
+    // copies the drawable into the output surface at the end of the frame
+    pub(crate) fn encode_output_surface(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) {
+        let output_surface = self.ivars().output_surface.borrow();
+        let Some(surface) = output_surface.as_ref() else {
+            return;
+        };
+        // a drawable created before the surface was set can't be copied from
+        if drawable_texture.isFramebufferOnly() {
+            return;
+        }
+        if drawable_texture.pixelFormat() != surface.texture.pixelFormat() {
+            drop(output_surface);
+            self.ivars().output_surface.replace(None);
+            self.log(
+                LogLevel::Warn,
+                "The output surface was dropped, it doesn't have the pixel format of the frames.",
+            );
+            return;
+        }
+        let Some(encoder) = command_buffer.blitCommandEncoder() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a blit encoder for the output surface.",
+            );
+            return;
+        };
+        let size = MTLSize {
+            width: drawable_texture.width().min(surface.width()),
+            height: drawable_texture.height().min(surface.height()),
+            depth: 1,
+        };
+        let origin = MTLOrigin { x: 0, y: 0, z: 0 };
+        unsafe {
+            encoder.copyFromTexture_sourceSlice_sourceLevel_sourceOrigin_sourceSize_toTexture_destinationSlice_destinationLevel_destinationOrigin(
+                drawable_texture,
+                0,
+                0,
+                origin,
+                size,
+                &surface.texture,
+                0,
+                0,
+                origin,
+            )
+        };
+        encoder.endEncoding();
+    }
+}
@@ -0,0 +1,4273 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use core::{
+    cell::{Cell, OnceCell, RefCell},
+    ffi::c_void,
+    ptr::NonNull,
+};
+
+use block2::RcBlock;
+use dispatch::Semaphore;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
+use serde::{Deserialize, Serialize};
+use objc2::{
+    declare_class, msg_send_id, mutability::MainThreadOnly, rc::{Retained, Weak},
+    runtime::{AnyObject, ProtocolObject}, ClassType, DeclaredClass, Encoding, RefEncode,
+};
+use objc2_foundation::{
+    MainThreadMarker, NSDictionary, NSError, NSObject, NSObjectProtocol, NSOperatingSystemVersion,
+    NSProcessInfo, NSRange, NSSize, NSString, NSURL,
+};
+use objc2_metal::{
+    MTLArgumentEncoder, MTLBinding, MTLBindingType, MTLBuffer, MTLBufferBinding,
+    MTLCaptureDescriptor, MTLCaptureDestination, MTLCaptureManager, MTLClearColor,
+    MTLCommandBuffer, MTLCommandBufferStatus, MTLCommandEncoder,
+    MTLCommandQueue,
+    MTLCommonCounterSetTimestamp, MTLCompareFunction, MTLCompileOptions, MTLComputePassDescriptor,
+    MTLComputePipelineState, MTLCounterSampleBuffer, MTLCounterSampleBufferDescriptor,
+    MTLCounterSamplingPoint, MTLCounterSet, MTLCullMode,
+    MTLDepthStencilDescriptor, MTLDepthStencilState, MTLDevice, MTLDrawable, MTLFunction,
+    MTLIndexType, MTLIndirectCommandBuffer, MTLLanguageVersion, MTLLibrary, MTLPackedFloat3,
+    MTLParallelRenderCommandEncoder, MTLPipelineOption, MTLPixelFormat, MTLPrimitiveType,
+    MTLRenderCommandEncoder, MTLRenderPassDescriptor, MTLRenderPipelineDescriptor,
+    MTLRenderPipelineReflection, MTLRenderPipelineState, MTLRenderStages, MTLResource,
+    MTLResourceOptions, MTLResourceUsage, MTLSamplerState, MTLScissorRect, MTLSize, MTLStorageMode,
+    MTLTexture, MTLTriangleFillMode, MTLViewport, MTLVisibilityResultMode, MTLWinding,
+};
+#[cfg(target_os = "macos")]
+use objc2_metal_kit::{MTKView, MTKViewDelegate};
+use objc2_quartz_core::CAMetalLayer;
+
+use tao::window::Window;
+
+mod allocator;
+mod animation;
+mod assets;
+mod attachments;
+#[cfg(feature = "audio")]
+mod audio;
+mod bindless;
+#[cfg(feature = "video")]
+mod capture;
+mod camera;
+mod compilation;
+mod compute;
+#[cfg(feature = "core-image")]
+mod core_image;
+mod culling;
+mod debug_draw;
+mod debug_labels;
+mod deferred;
+mod device;
+mod drawable_format;
+mod filters;
+mod fullscreen_shader;
+mod game_loop;
+mod gpu_fault;
+#[cfg(feature = "ecs")]
+mod ecs;
+#[cfg(feature = "egui")]
+mod egui_metal;
+mod graph;
+mod headless;
+mod indirect;
+#[cfg(feature = "imgui")]
+mod imgui_metal;
+mod input;
+mod lights;
+mod io_surface;
+mod live_resize;
+mod mesh;
+mod memory;
+mod mesh_shader;
+mod occlusion;
+#[cfg(feature = "metalfx")]
+mod metalfx;
+mod overlay;
+mod pacing;
+mod particles;
+mod pbr;
+mod picking;
+mod pipeline_cache;
+mod queues;
+mod platform;
+mod post_process;
+mod primitives;
+mod rasterization_rate;
+mod reflection;
+mod render_handle;
+#[cfg(feature = "recording")]
+mod recording;
+mod rt;
+mod scene;
+mod scene_graph;
+#[cfg(all(feature = "screen-capture", target_os = "macos"))]
+mod screen_capture;
+mod screenshot;
+mod shadow;
+mod snapshot;
+mod skybox;
+mod sparse_texture;
+mod sprites;
+mod stencil;
+mod surface;
+mod sync;
+mod target;
+mod text;
+mod tile;
+mod time;
+mod texture;
+#[cfg(any(feature = "egui", feature = "imgui"))]
+mod ui;
+mod vertex;
+#[cfg(feature = "video")]
+mod video;
+mod viewports;
+mod visibility;
+#[cfg(feature = "winit")]
+mod winit_window;
+
+pub use allocator::{
+    AllocatorStats, GpuAllocator, HeapAllocation, HeapBuffer, HeapTexture, DEFAULT_HEAP_SIZE,
+};
+pub use animation::{AnimationClip, SkinVertex};
+pub use assets::{Asset, AssetError, AssetState, Assets, Handle, LoadHook, Upload};
+pub use attachments::{LoadAction, StoreAction};
+#[cfg(feature = "audio")]
+pub use audio::AudioError;
+pub use bindless::{
+    BindlessTable, BufferHandle, TextureHandle, BINDLESS_BUFFER_CAPACITY,
+    BINDLESS_TEXTURE_CAPACITY,
+};
+#[cfg(feature = "video")]
+pub use capture::CameraCapture;
+pub use camera::{Camera, Projection};
+pub use compilation::{Compilation, CompilationError};
+pub use compute::ComputePass;
+pub use culling::BoundingBox;
+pub use debug_draw::DebugDraw;
+pub use deferred::RenderPath;
+pub use device::{available_devices, DeviceInfo, DeviceSelector};
+pub use filters::ComputeFilter;
+pub use game_loop::{FrameLimiter, GameLoop, LoopMode, SleepStrategy};
+#[cfg(feature = "ecs")]
+pub use ecs::{MaterialHandle, MeshHandle, RenderAssets, Transform};
+pub use graph::{GraphPass, GraphResources, GraphTexture, RenderGraph};
+pub use indirect::CulledInstances;
+pub use input::{CursorMode, InputState};
+pub use io_surface::{SharedSurface, SharedSurfaceError};
+pub use lights::{Light, PointLight, SpotLight};
+pub use mesh::{Mesh, MeshError};
+pub use memory::MemoryPressure;
+pub use mesh_shader::MeshPipelineDescriptor;
+pub use occlusion::OcclusionQueries;
+#[cfg(feature = "metalfx")]
+pub use metalfx::{Upscaler, UpscalingQuality};
+pub use particles::ParticleSystem;
+pub use pbr::{PbrMaterial, SurfaceVertex};
+pub use pipeline_cache::{
+    BlendMode, BlendState, PipelineArchiveError, PipelineCache, PipelineDescriptor,
+    VertexAttribute, VertexLayout,
+};
+pub use picking::EntityId;
+pub use post_process::PostProcess;
+pub use primitives::MeshData;
+pub use queues::GpuQueue;
+pub use rasterization_rate::{RasterizationRateMap, RasterizationRates};
+pub use reflection::{BindingSlot, ShaderBindings};
+pub use render_handle::{DrawList, MaterialId, MeshId, RenderHandle};
+pub use rt::AccelerationStructure;
+pub use scene::Scene;
+pub use scene_graph::{Material, NodeId, SceneGraph};
+#[cfg(all(feature = "screen-capture", target_os = "macos"))]
+pub use screen_capture::{ScreenCapture, ScreenSource};
+pub use screenshot::ScreenshotError;
+pub use shadow::DirectionalLight;
+pub use snapshot::{compare_images, SnapshotDiff, SnapshotError, SnapshotTolerance};
+pub use sparse_texture::{SparseTexture, SparseTile, TileLoader};
+pub use sprites::{Sprite, SpriteBatch};
+pub use stencil::{DepthStencilBuilder, StencilFace};
+pub use surface::Backend;
+pub use sync::{GpuEvent, GpuFence};
+pub use target::{RenderTarget, RenderTargetBuilder};
+pub use text::{Font, TextAlign, TextStyle};
+pub use tile::{TileConfig, TilePipelineDescriptor};
+pub use time::{Time, DEFAULT_FIXED_TIMESTEP};
+pub use texture::TextureError;
+// called by the expansion of `impl_vertex!`
+#[doc(hidden)]
+pub use vertex::field_format;
+pub use vertex::{Vertex, VertexFormat};
+#[cfg(feature = "video")]
+pub use video::{VideoError, VideoPlayer};
+pub use viewports::MAX_VIEWPORTS;
+// the egui version the ui is built with
+#[cfg(feature = "egui")]
+pub use egui;
+// the imgui version the ui is built with
+#[cfg(feature = "imgui")]
+pub use imgui;
+// the core image bindings the filters of `set_image_filters` come from
+#[cfg(feature = "core-image")]
+pub use objc2_core_image;
+// the hecs version the worlds of `draw_world` are built with
+#[cfg(feature = "ecs")]
+pub use hecs;
+// the winit version `new_winit` takes the windows of
+#[cfg(feature = "winit")]
+pub use winit;
+
+use camera::Matrix;
+use compute::ComputeCallback;
+use deferred::{DeferredLighting, DeferredPass, DeferredState};
+#[cfg(feature = "core-image")]
+use core_image::ImageFilterState;
+#[cfg(feature = "egui")]
+use egui_metal::{EguiCallback, EguiState};
+use graph::{FramePass, FrameTarget, RenderGraphCallback};
+#[cfg(feature = "imgui")]
+use imgui_metal::{ImguiCallback, ImguiState};
+use overlay::Overlay;
+use pacing::SharedFramePacing;
+#[cfg(feature = "recording")]
+use recording::Recording;
+#[cfg(feature = "audio")]
+use audio::AudioCapture;
+use input::UpdateCallback;
+use lights::{CulledLights, LightState, LightTiles};
+use compilation::{LibraryCompilation, PendingPipelines};
+use debug_labels::{encode_debug_groups, DebugGroups};
+use device::DeviceObserver;
+use drawable_format::DrawableFormatHandler;
+use fullscreen_shader::FullscreenShader;
+use gpu_fault::CommandBufferFault;
+use memory::MemoryPressureSource;
+use mesh_shader::MeshPipelineStates;
+use tile::TilePipelineStates;
+use time::FixedUpdateCallback;
+use visibility::VisibilityObserver;
+use attachments::AttachmentActions;
+#[cfg(feature = "metalfx")]
+use metalfx::UpscalingState;
+use rt::RayTracing;
+use pipeline_cache::{default_archive_path, PipelineArchive};
+use platform::{handle_view, retained_window, NativeView, NativeWindow, NativeWindowExt};
+use picking::PickingState;
+use post_process::PostProcessState;
+use rasterization_rate::RasterizationRateState;
+use reflection::{BoundResource, PipelineBindings};
+use render_handle::RenderCommands;
+use screenshot::PendingScreenshot;
+use shadow::{LightProperties, ShadowMap, ShadowPass, ShadowState, SHADOW_MAP_INDEX};
+use skybox::{Skybox, SkyboxState};
+use surface::Surface;
+use sync::{FrameEvents, PassFences};
+use text::TextState;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SceneProperties {
+    view_projection: Matrix,
+    time: f32,
+    point_size: f32,
+    // the current `EdrHeadroom`, 1 unless the drawable is HDR
+    edr_headroom: f32,
+    // the float4x4 aligns the shader's struct to 16 bytes
+    _padding: f32,
+    light: LightProperties,
+    // the `Camera::view_origin` the shading sees the scene from
+    view_origin: [f32; 4],
+    // the levels of the frequency bands of the captured sound, all 0 without a capture
+    audio_bands: [f32; AUDIO_BANDS],
+}
+
+// the frequency bands of `MetalRenderer::start_audio_capture`, `AUDIO_BANDS` in triangle.metal
+pub(crate) const AUDIO_BANDS: usize = 8;
+
+// the parameters of `fragment_gradient` in triangle.metal
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+struct GradientProperties {
+    top: [f32; 3],
+    bottom: [f32; 3],
+    speed: f32,
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct VertexInput {
+    pub position: MTLPackedFloat3,
+    pub color: MTLPackedFloat3,
+}
+
+// the per-instance data of `vertex_instanced`, one for every copy of an instanced draw
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct InstanceData {
+    // column major, applied to the vertex positions before the camera
+    pub transform: [[f32; 4]; 4],
+    // multiplied with the vertex colors
+    pub color: [f32; 4],
+}
+
+impl Default for InstanceData {
+    fn default() -> Self {
+        Self {
+            transform: camera::IDENTITY,
+            color: [1.; 4],
+        }
+    }
+}
+
+// opaque CoreGraphics color space, encoded so that `msg_send!` accepts it as a `CGColorSpaceRef`
+#[repr(C)]
+pub(crate) struct CGColorSpace {
+    _private: [u8; 0],
+}
+
+unsafe impl RefEncode for CGColorSpace {
+    const ENCODING_REF: Encoding = Encoding::Pointer(&Encoding::Struct("CGColorSpace", &[]));
+}
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    static kCGColorSpaceSRGB: *const c_void;
+    static kCGColorSpaceDisplayP3: *const c_void;
+    static kCGColorSpaceExtendedLinearSRGB: *const c_void;
+    static kCGColorSpaceExtendedLinearDisplayP3: *const c_void;
+    fn CGColorSpaceCreateWithName(name: *const c_void) -> *mut CGColorSpace;
+    fn CGColorSpaceRelease(space: *mut CGColorSpace);
+}
+
+// opaque dispatch data, an objective-c object that `newLibraryWithData:error:` takes
+#[repr(C)]
+struct DispatchData {
+    _private: [u8; 0],
+}
+
+unsafe impl RefEncode for DispatchData {
+    const ENCODING_REF: Encoding = Encoding::Object;
+}
+
+extern "C" {
+    fn dispatch_data_create(
+        buffer: *const c_void,
+        size: usize,
+        queue: *const c_void,
+        destructor: *const c_void,
+    ) -> *mut DispatchData;
+    fn dispatch_release(object: *mut DispatchData);
+}
+
+// the shader library precompiled by build.rs, when the metal toolchain was available
+#[cfg(precompiled_shaders)]
+const PRECOMPILED_LIBRARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/triangle.metallib"));
+
+// color formats the drawable and the pipeline can be configured with
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelFormat {
+    // 8-bit BGRA, values are written to the drawable as-is
+    Bgra8Unorm,
+    // 8-bit BGRA, linear shader output is encoded to sRGB on write
+    Bgra8UnormSrgb,
+    // 16-bit float RGBA in an extended linear color space for HDR/EDR output
+    Rgba16Float,
+}
+
+// the gamut the drawable is tagged with, the colors the shaders output are matched from it to
+// the screen. the 8-bit formats take the sRGB transfer function in both, so in
+// `PixelFormat::Bgra8UnormSrgb` linear shader output is encoded correctly for either
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    // the wider gamut of recent apple displays, sRGB colors look oversaturated in it
+    DisplayP3,
+}
+
+impl ColorSpace {
+    // the name of the CGColorSpace of the drawables in `pixel_format`, linear with an extended
+    // range for HDR
+    fn cg_name(self, pixel_format: PixelFormat) -> *const c_void {
+        unsafe {
+            match (self, pixel_format.is_hdr()) {
+                (ColorSpace::Srgb, false) => kCGColorSpaceSRGB,
+                (ColorSpace::DisplayP3, false) => kCGColorSpaceDisplayP3,
+                (ColorSpace::Srgb, true) => kCGColorSpaceExtendedLinearSRGB,
+                (ColorSpace::DisplayP3, true) => kCGColorSpaceExtendedLinearDisplayP3,
+            }
+        }
+    }
+}
+
+impl PixelFormat {
+    fn mtl_pixel_format(self) -> MTLPixelFormat {
+        match self {
+            PixelFormat::Bgra8Unorm => MTLPixelFormat::BGRA8Unorm,
+            PixelFormat::Bgra8UnormSrgb => MTLPixelFormat::BGRA8Unorm_sRGB,
+            PixelFormat::Rgba16Float => MTLPixelFormat::RGBA16Float,
+        }
+    }
+
+    fn is_hdr(self) -> bool {
+        self == PixelFormat::Rgba16Float
+    }
+}
+
+// depth buffer formats the view and the pipelines can be configured with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthFormat {
+    // 32-bit float depth
+    Depth32Float,
+    // 32-bit float depth and an 8-bit stencil in the same texture
+    Depth32FloatStencil8,
+}
+
+impl DepthFormat {
+    fn mtl_pixel_format(self) -> MTLPixelFormat {
+        match self {
+            DepthFormat::Depth32Float => MTLPixelFormat::Depth32Float,
+            DepthFormat::Depth32FloatStencil8 => MTLPixelFormat::Depth32Float_Stencil8,
+        }
+    }
+}
+
+// primitive topology used to assemble the vertices
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PrimitiveType {
+    Point,
+    Line,
+    LineStrip,
+    Triangle,
+    TriangleStrip,
+}
+
+impl PrimitiveType {
+    fn mtl_primitive_type(self) -> MTLPrimitiveType {
+        match self {
+            PrimitiveType::Point => MTLPrimitiveType::Point,
+            PrimitiveType::Line => MTLPrimitiveType::Line,
+            PrimitiveType::LineStrip => MTLPrimitiveType::LineStrip,
+            PrimitiveType::Triangle => MTLPrimitiveType::Triangle,
+            PrimitiveType::TriangleStrip => MTLPrimitiveType::TriangleStrip,
+        }
+    }
+}
+
+// which faces of the triangles are discarded before rasterization
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CullMode {
+    #[default]
+    None,
+    Front,
+    Back,
+}
+
+impl CullMode {
+    fn mtl_cull_mode(self) -> MTLCullMode {
+        match self {
+            CullMode::None => MTLCullMode::None,
+            CullMode::Front => MTLCullMode::Front,
+            CullMode::Back => MTLCullMode::Back,
+        }
+    }
+}
+
+// the vertex order of front facing triangles in clip space, metal defaults to clockwise
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Winding {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+}
+
+impl Winding {
+    fn mtl_winding(self) -> MTLWinding {
+        match self {
+            Winding::Clockwise => MTLWinding::Clockwise,
+            Winding::CounterClockwise => MTLWinding::CounterClockwise,
+        }
+    }
+}
+
+// when the view renders its frames
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RedrawMode {
+    // at the display refresh rate, driven by the view
+    Continuous,
+    // only when the view is resized or exposed and when `redraw` is called
+    OnDemand,
+}
+
+// how the shader library is compiled
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderOptions {
+    // pins the metal shading language version, the newest one the os supports when none
+    pub language_version: Option<MTLLanguageVersion>,
+    // trades ieee 754 conformance of the floating point math for speed
+    pub fast_math_enabled: bool,
+    // defined before compiling, like `#define NAME VALUE`, to select shader variants
+    pub preprocessor_macros: HashMap<String, String>,
+}
+
+impl Default for ShaderOptions {
+    // the metal defaults
+    fn default() -> Self {
+        Self {
+            language_version: None,
+            fast_math_enabled: true,
+            preprocessor_macros: HashMap::new(),
+        }
+    }
+}
+
+// what fills the view behind the geometry
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Background {
+    // the clear color of the view
+    Solid { color: [f64; 3] },
+    // a vertical gradient from `bottom` to `top` drawn before the geometry, swaying back and
+    // forth `speed` radians per second
+    Gradient {
+        top: [f32; 3],
+        bottom: [f32; 3],
+        speed: f32,
+    },
+}
+
+// how triangles are rasterized
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillMode {
+    #[default]
+    Fill,
+    // only the edges, for inspecting meshes
+    Lines,
+}
+
+impl FillMode {
+    fn mtl_triangle_fill_mode(self) -> MTLTriangleFillMode {
+        match self {
+            FillMode::Fill => MTLTriangleFillMode::Fill,
+            FillMode::Lines => MTLTriangleFillMode::Lines,
+        }
+    }
+}
+
+// the settings of a window and its renderer worth keeping between runs. missing fields take
+// their defaults and unknown ones are ignored, so files written by other versions still load
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RendererConfig {
+    // content size in points
+    pub window_size: [f64; 2],
+    // bottom left corner of the window frame in screen coordinates, centered when missing
+    pub window_position: Option<[f64; 2]>,
+    pub clear_color: [f64; 4],
+    pub pixel_format: PixelFormat,
+    pub color_space: ColorSpace,
+    pub sample_count: usize,
+    pub fill_mode: FillMode,
+    pub render_path: RenderPath,
+    pub backend: Backend,
+    // how often the view draws continuously, capped by the refresh rate of the screen
+    pub preferred_frames_per_second: isize,
+    // when off, frames are shown as soon as they're done instead of with the next refresh,
+    // which lets the frame rate go past the refresh rate at the cost of tearing
+    pub vsync: bool,
+    // 2 or 3, fewer drawables lower the latency, more keep the gpu busy
+    pub maximum_drawable_count: usize,
+    // whether what's behind the window shows through where the frames are transparent
+    pub transparent: bool,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            window_size: [800., 600.],
+            window_position: None,
+            clear_color: [0., 0., 0., 1.],
+            pixel_format: PixelFormat::Bgra8Unorm,
+            color_space: ColorSpace::Srgb,
+            // every metal gpu supports 4x multisampling
+            sample_count: 4,
+            fill_mode: FillMode::Fill,
+            render_path: RenderPath::Forward,
+            backend: Backend::MetalKit,
+            preferred_frames_per_second: 60,
+            vsync: true,
+            maximum_drawable_count: 3,
+            transparent: false,
+        }
+    }
+}
+
+// severity of a message passed to the logging hook
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+pub type Logger = Box<dyn Fn(LogLevel, &str)>;
+
+// what the renderer negotiated with the device, the view and the display, for diagnostics
+#[derive(Clone, Debug)]
+pub struct RendererInfo {
+    pub device_name: String,
+    pub color_pixel_format: MTLPixelFormat,
+    pub depth_stencil_pixel_format: MTLPixelFormat,
+    pub sample_count: usize,
+    // in pixels
+    pub drawable_size: NSSize,
+    // the highest refresh rate of the screen showing the window, none while it's offscreen
+    pub max_frames_per_second: Option<isize>,
+}
+
+impl fmt::Display for RendererInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Device: {}", self.device_name)?;
+        writeln!(f, "Color pixel format: {}", self.color_pixel_format.0)?;
+        writeln!(f, "Depth stencil pixel format: {}", self.depth_stencil_pixel_format.0)?;
+        writeln!(f, "Sample count: {}", self.sample_count)?;
+        writeln!(
+            f,
+            "Drawable size: {}x{}",
+            self.drawable_size.width, self.drawable_size.height
+        )?;
+        match self.max_frames_per_second {
+            Some(frames_per_second) => write!(f, "Max refresh rate: {frames_per_second} Hz"),
+            None => write!(f, "Max refresh rate: offscreen"),
+        }
+    }
+}
+
+// how many times brighter than SDR white the screen of the window shows colors, as the
+// largest component value of the extended range formats. 1 on SDR screens
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EdrHeadroom {
+    // what the screen shows right now, it changes with the brightness and the content on screen
+    pub current: f64,
+    // the most the screen could show at any brightness
+    pub potential: f64,
+    // the most the screen shows with reference presets, where the brightness is fixed
+    pub reference: f64,
+}
+
+impl Default for EdrHeadroom {
+    fn default() -> Self {
+        Self {
+            current: 1.,
+            potential: 1.,
+            reference: 1.,
+        }
+    }
+}
+
+// timings of the most recently measured frame, in seconds, and the memory of the device
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+    // cpu time spent recording, encoding and submitting the last frame, the waits for a
+    // drawable and for a free frame slot aside
+    pub cpu_frame_time: Option<f64>,
+    // gpu time the command buffer of the last completed frame took from start to end
+    pub gpu_frame_time: Option<f64>,
+    // gpu time spent in the render pass, measured in benchmark mode
+    pub gpu_pass_time: Option<f64>,
+    // gpu time spent in the compute pass, measured in benchmark mode for frames dispatching
+    // kernels
+    pub gpu_compute_time: Option<f64>,
+    // the draws of the last frame and its shadow pass that were encoded, and the ones left
+    // out because their bounds lay outside the view
+    pub draws: usize,
+    pub culled_draws: usize,
+    // the bytes of all the resources of the device, and the bytes it can use before its
+    // performance suffers
+    pub allocated_memory: usize,
+    pub memory_budget: usize,
+    // the seconds between the last two frames shown on the screen, and from presenting the last
+    // one to showing it
+    pub present_interval: Option<f64>,
+    pub present_latency: Option<f64>,
+    // the frames shown, the ones shown a refresh or more later than the frame rate asked for,
+    // and the ones never shown because a newer frame replaced them
+    pub presented_frames: u64,
+    pub missed_frames: u64,
+    pub dropped_frames: u64,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    // writing gpu traces requires `METAL_CAPTURE_ENABLED=1` in the environment or the Info.plist
+    NotEnabled,
+    // a capture has already been requested or is in progress
+    AlreadyCapturing,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::NotEnabled => write!(
+                f,
+                "GPU trace capture is not enabled, set METAL_CAPTURE_ENABLED=1 before launching"
+            ),
+            CaptureError::AlreadyCapturing => write!(f, "A GPU capture is already in progress"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+// a precompiled shader library metal failed to load, holds the reason it reported
+#[derive(Debug)]
+pub struct LibraryError(pub String);
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to load the shader library: {}", self.0)
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+// why the renderer couldn't be set up or a shader library or a pipeline couldn't be built
+#[derive(Clone, Debug)]
+pub enum RendererError {
+    // the tao window isn't backed by an NSWindow or a UIWindow, or the view of a window handle
+    // isn't in one
+    WindowUnavailable,
+    // the window handle is from another platform than AppKit on macos or UIKit on ios
+    UnsupportedWindowHandle,
+    // metal has no device, e.g. in a virtual machine without gpu access
+    DeviceUnavailable,
+    CommandQueueUnavailable,
+    // a buffer the renderer needs couldn't be allocated on the device
+    BufferUnavailable,
+    // `init` was called on a renderer that's already initialized
+    AlreadyInitialized,
+    // holds the diagnostics of the metal compiler
+    ShaderCompilation(String),
+    // holds the reason metal reported
+    PipelineCreation(String),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::WindowUnavailable => {
+                write!(f, "Failed to get the native window of the window")
+            }
+            RendererError::UnsupportedWindowHandle => {
+                write!(f, "Only AppKit and UIKit window handles are supported")
+            }
+            RendererError::DeviceUnavailable => write!(f, "No Metal device available"),
+            RendererError::CommandQueueUnavailable => {
+                write!(f, "Failed to create a command queue")
+            }
+            RendererError::BufferUnavailable => write!(f, "Failed to create a buffer"),
+            RendererError::AlreadyInitialized => write!(f, "The renderer is already initialized"),
+            RendererError::ShaderCompilation(description) => {
+                write!(f, "Shader compilation failed: {description}")
+            }
+            RendererError::PipelineCreation(description) => {
+                write!(f, "Pipeline creation failed: {description}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RendererError {}
+
+impl From<CompilationError> for RendererError {
+    fn from(error: CompilationError) -> Self {
+        RendererError::ShaderCompilation(error.0)
+    }
+}
+
+// stops the running gpu capture when the frame is done, including dropped frames
+struct CaptureGuard;
+
+impl Drop for CaptureGuard {
+    fn drop(&mut self) {
+        unsafe { MTLCaptureManager::sharedCaptureManager() }.stopCapture();
+    }
+}
+
+// samples gpu timestamps at the start and end of the render pass, and of the compute pass
+// after them
+struct GpuTimer {
+    sample_buffer: Retained<ProtocolObject<dyn MTLCounterSampleBuffer>>,
+    // the command buffer whose passes write the sample buffer
+    pending: Option<Retained<ProtocolObject<dyn MTLCommandBuffer>>>,
+    // whether the pending frame had a compute pass writing its samples
+    compute_sampled: bool,
+    // cpu and gpu timestamps taken together to convert gpu ticks to nanoseconds
+    cpu_timestamp: u64,
+    gpu_timestamp: u64,
+}
+
+// a hot reload of the shaders in progress
+struct ShaderCompilation {
+    source: String,
+    library: LibraryCompilation,
+}
+
+// watches a shader source file for `MetalRenderer::watch_shaders`
+struct ShaderWatcher {
+    path: PathBuf,
+    // stops watching when dropped
+    _watcher: RecommendedWatcher,
+    // set by the watcher thread when the file changed
+    changed: Arc<AtomicBool>,
+    compiling: Option<ShaderCompilation>,
+}
+
+// the number of frames the cpu may record ahead of the gpu
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+// a shared buffer with a copy of `data`. metal has no empty buffers, an empty slice gets a
+// buffer of a byte rather than one copied from its dangling pointer
+pub(crate) fn new_shared_buffer<T: Copy>(
+    device: &ProtocolObject<dyn MTLDevice>,
+    data: &[T],
+) -> Option<Retained<ProtocolObject<dyn MTLBuffer>>> {
+    let options = MTLResourceOptions::MTLResourceStorageModeShared;
+    if data.is_empty() {
+        return device.newBufferWithLength_options(1, options);
+    }
+    unsafe {
+        device.newBufferWithBytes_length_options(
+            NonNull::from(data).cast::<c_void>(),
+            core::mem::size_of_val(data),
+            options,
+        )
+    }
+}
+
+// a frame the cpu records while the gpu may still be busy with the ones before it
+struct FrameSlot {
+    // the scene properties the shaders read
+    scene_properties: Retained<ProtocolObject<dyn MTLBuffer>>,
+    // the buffers handed out by `FrameAllocator::allocate`, reused in the same order by the
+    // next frame recorded into this slot
+    buffers: RefCell<Vec<Retained<ProtocolObject<dyn MTLBuffer>>>>,
+    allocated: Cell<usize>,
+}
+
+// the per-frame data of the renderer. it's written to a ring of slots, one per frame in flight,
+// so the cpu never overwrites the data of a frame the gpu is still reading
+struct FrameAllocator {
+    device: Retained<ProtocolObject<dyn MTLDevice>>,
+    slots: Vec<FrameSlot>,
+    index: Cell<usize>,
+    // counts the free slots, signaled when the gpu completes a frame
+    frames_in_flight: Semaphore,
+    // whether a frame is being recorded, allocations outside of one can't be recycled
+    recording: Cell<bool>,
+    start_time: Instant,
+    // seconds since the renderer started as of the latest frame, what the shaders animate by
+    time: Cell<f32>,
+}
+
+impl FrameAllocator {
+    fn new(device: &Retained<ProtocolObject<dyn MTLDevice>>) -> Result<Self, RendererError> {
+        let slots = (0..MAX_FRAMES_IN_FLIGHT)
+            .map(|_| {
+                let scene_properties = device
+                    .newBufferWithLength_options(
+                        core::mem::size_of::<SceneProperties>(),
+                        MTLResourceOptions::MTLResourceStorageModeShared,
+                    )
+                    .ok_or(RendererError::BufferUnavailable)?;
+                Ok(FrameSlot {
+                    scene_properties,
+                    buffers: RefCell::default(),
+                    allocated: Cell::new(0),
+                })
+            })
+            .collect::<Result<_, RendererError>>()?;
+        Ok(Self {
+            device: device.clone(),
+            slots,
+            index: Cell::new(0),
+            frames_in_flight: Semaphore::new(MAX_FRAMES_IN_FLIGHT as u32),
+            recording: Cell::new(false),
+            start_time: Instant::now(),
+            time: Cell::new(0.),
+        })
+    }
+
+    // blocks until the gpu is done with the oldest slot and starts recording a new frame into
+    // it. the slot must be given back with `release` once the gpu completes the frame
+    #[allow(clippy::too_many_arguments)]
+    fn acquire(
+        &self,
+        view_projection: Matrix,
+        point_size: f32,
+        edr_headroom: f32,
+        light: LightProperties,
+        view_origin: [f32; 4],
+        audio_bands: [f32; AUDIO_BANDS],
+        fixed_time: Option<f32>,
+    ) -> Retained<ProtocolObject<dyn MTLBuffer>> {
+        self.frames_in_flight.wait();
+        let time = fixed_time.unwrap_or_else(|| self.start_time.elapsed().as_secs_f32());
+        self.time.set(time);
+        let scene_properties = SceneProperties {
+            view_projection,
+            time: self.time.get(),
+            point_size,
+            edr_headroom,
+            _padding: 0.,
+            light,
+            view_origin,
+            audio_bands,
+        };
+        let index = (self.index.get() + 1) % self.slots.len();
+        self.index.set(index);
+        let slot = &self.slots[index];
+        slot.allocated.set(0);
+        self.recording.set(true);
+        let buffer = &slot.scene_properties;
+        unsafe { buffer.contents().cast::<SceneProperties>().write(scene_properties) };
+        buffer.clone()
+    }
+
+    // ends the recording of the frame, the following allocations get buffers of their own
+    fn finish(&self) {
+        self.recording.set(false);
+    }
+
+    // copies `data` into a buffer that stays untouched until the gpu completed the frame
+    fn allocate<T: Copy>(&self, data: &[T]) -> Retained<ProtocolObject<dyn MTLBuffer>> {
+        let length = core::mem::size_of_val(data).max(1);
+        if !self.recording.get() {
+            return new_shared_buffer(&self.device, data).expect("Failed to create a frame buffer.");
+        }
+
+        let slot = &self.slots[self.index.get()];
+        let mut buffers = slot.buffers.borrow_mut();
+        let index = slot.allocated.get();
+        slot.allocated.set(index + 1);
+        // frames usually allocate the same sizes in the same order, so the buffer the previous
+        // frame got at this position tends to fit
+        if buffers.get(index).is_none_or(|buffer| buffer.length() < length) {
+            let options = MTLResourceOptions::MTLResourceStorageModeShared;
+            let buffer = self
+                .device
+                .newBufferWithLength_options(length, options)
+                .expect("Failed to create a frame buffer.");
+            match buffers.get_mut(index) {
+                Some(previous) => *previous = buffer,
+                None => buffers.push(buffer),
+            }
+        }
+        let buffer = &buffers[index];
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                buffer.contents().cast::<T>().as_ptr(),
+                data.len(),
+            )
+        };
+        buffer.clone()
+    }
+
+    fn release(&self) {
+        self.finish();
+        self.frames_in_flight.signal();
+    }
+}
+
+// the default geometry, a triangle with red, green and blue corners
+fn triangle_vertices() -> [VertexInput; 3] {
+    [
+        VertexInput {
+            position: MTLPackedFloat3 {
+                x: -f32::sqrt(3.0) / 4.0,
+                y: -0.25,
+                z: 0.,
+            },
+            color: MTLPackedFloat3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+        },
+        VertexInput {
+            position: MTLPackedFloat3 {
+                x: f32::sqrt(3.0) / 4.0,
+                y: -0.25,
+                z: 0.,
+            },
+            color: MTLPackedFloat3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+        },
+        VertexInput {
+            position: MTLPackedFloat3 {
+                x: 0.,
+                y: 0.5,
+                z: 0.,
+            },
+            color: MTLPackedFloat3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+        },
+    ]
+}
+
+// expands a polyline into a triangle strip `width` pixels wide for a view of `viewport_size`
+// pixels, metal only rasterizes lines a single pixel wide. the joints are mitered
+fn thick_line_vertices(
+    points: &[(f32, f32, f32)],
+    width: f32,
+    color: MTLPackedFloat3,
+    viewport_size: NSSize,
+) -> Vec<VertexInput> {
+    if points.len() < 2 {
+        return Vec::new();
+    }
+
+    // the offsets are computed in pixels, so the width doesn't depend on the aspect ratio
+    let scale = (
+        viewport_size.width as f32 / 2.,
+        viewport_size.height as f32 / 2.,
+    );
+    let mut normal = (0., 1.);
+    let normals: Vec<(f32, f32)> = points
+        .windows(2)
+        .map(|segment| {
+            let dx = (segment[1].0 - segment[0].0) * scale.0;
+            let dy = (segment[1].1 - segment[0].1) * scale.1;
+            let length = (dx * dx + dy * dy).sqrt();
+            // repeated points keep the direction of the previous segment
+            if length > 0. {
+                normal = (-dy / length, dx / length);
+            }
+            normal
+        })
+        .collect();
+
+    let half_width = width / 2.;
+    points
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &(x, y, z))| {
+            let before = normals[i.saturating_sub(1)];
+            let after = normals[i.min(normals.len() - 1)];
+            // the miter bisects the joint, a line doubling back on itself has none
+            let (mx, my) = (before.0 + after.0, before.1 + after.1);
+            let miter_length = (mx * mx + my * my).sqrt();
+            let (mx, my) = if miter_length > 1e-3 {
+                (mx / miter_length, my / miter_length)
+            } else {
+                after
+            };
+            // stretch the miter to keep the segments `width` wide, but limit the spikes of
+            // sharp joints
+            let extent = half_width / (mx * after.0 + my * after.1).max(0.25);
+            let offset = (mx * extent / scale.0, my * extent / scale.1);
+            let vertex = |side: f32| VertexInput {
+                position: MTLPackedFloat3 {
+                    x: x + side * offset.0,
+                    y: y + side * offset.1,
+                    z,
+                },
+                color,
+            };
+            [vertex(1.), vertex(-1.)]
+        })
+        .collect()
+}
+
+// whether the point `p` lies inside the triangle `a`, `b`, `c` of either winding, from the
+// barycentric coordinates of `p`
+fn triangle_contains(p: (f32, f32), a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> bool {
+    let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+    if area == 0. {
+        return false;
+    }
+    let u = ((b.0 - p.0) * (c.1 - p.1) - (c.0 - p.0) * (b.1 - p.1)) / area;
+    let v = ((c.0 - p.0) * (a.1 - p.1) - (a.0 - p.0) * (c.1 - p.1)) / area;
+    let w = 1. - u - v;
+    u >= 0. && v >= 0. && w >= 0.
+}
+
+// a vertex buffer read by a shader with a different size than its `#[repr(C)]` struct
+#[derive(Debug)]
+struct LayoutMismatch {
+    argument: String,
+    index: usize,
+    shader_size: usize,
+    rust_size: usize,
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Vertex buffer {} at index {} is {} bytes in the shader but {} bytes in Rust.",
+            self.argument, self.index, self.shader_size, self.rust_size
+        )
+    }
+}
+
+impl std::error::Error for LayoutMismatch {}
+
+// compares the sizes of the structs a vertex function reads from the buffers `RenderPass`
+// binds with their rust counterparts, adding a field on one side only garbles the rendering
+fn validate_vertex_buffer_layouts(
+    reflection: &MTLRenderPipelineReflection,
+) -> Result<(), LayoutMismatch> {
+    for binding in unsafe { reflection.vertexBindings() }.iter_retained() {
+        if !unsafe { binding.isUsed() } || unsafe { binding.r#type() } != MTLBindingType::Buffer {
+            continue;
+        }
+        let index = unsafe { binding.index() };
+        let rust_size = match index {
+            0 => core::mem::size_of::<SceneProperties>(),
+            1 => core::mem::size_of::<VertexInput>(),
+            2 => core::mem::size_of::<[f32; 2]>(),
+            3 => core::mem::size_of::<InstanceData>(),
+            _ => continue,
+        };
+        // SAFETY: bindings of buffer type are MTLBufferBindings
+        let buffer_binding: Retained<ProtocolObject<dyn MTLBufferBinding>> =
+            unsafe { Retained::cast(binding) };
+        // the size of the struct a buffer pointer points to
+        let shader_size = unsafe { buffer_binding.bufferDataSize() };
+        if shader_size != rust_size {
+            return Err(LayoutMismatch {
+                argument: unsafe { buffer_binding.name() }.to_string(),
+                index,
+                shader_size,
+                rust_size,
+            });
+        }
+    }
+    Ok(())
+}
+
+// a resource referenced from an argument buffer
+enum ArgumentResource {
+    Texture(Retained<ProtocolObject<dyn MTLTexture>>),
+    // samplers are not resources and only need to outlive the argument buffer
+    Sampler(#[allow(dead_code)] Retained<ProtocolObject<dyn MTLSamplerState>>),
+    Buffer(Retained<ProtocolObject<dyn MTLBuffer>>),
+}
+
+// a set of textures, samplers, buffers and constants bound to a shader with a single buffer
+// binding, encoded into an argument buffer for the argument struct of a shader function
+pub struct ArgumentTable {
+    encoder: Retained<ProtocolObject<dyn MTLArgumentEncoder>>,
+    buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    // keeps the encoded resources alive, keyed by their `[[id(n)]]`
+    resources: HashMap<usize, ArgumentResource>,
+}
+
+impl ArgumentTable {
+    // creates an argument buffer for the argument struct `function` takes at `buffer_index`
+    fn new(
+        device: &ProtocolObject<dyn MTLDevice>,
+        function: &ProtocolObject<dyn MTLFunction>,
+        buffer_index: usize,
+    ) -> Self {
+        let encoder = unsafe { function.newArgumentEncoderWithBufferIndex(buffer_index) };
+        let buffer = device
+            .newBufferWithLength_options(
+                encoder.encodedLength().max(1),
+                MTLResourceOptions::MTLResourceStorageModeShared,
+            )
+            .expect("Failed to create an argument buffer.");
+        unsafe { encoder.setArgumentBuffer_offset(Some(&buffer), 0) };
+
+        ArgumentTable {
+            encoder,
+            buffer,
+            resources: HashMap::new(),
+        }
+    }
+
+    pub fn set_texture(
+        &mut self,
+        index: usize,
+        texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+    ) {
+        unsafe { self.encoder.setTexture_atIndex(Some(texture), index) };
+        self.resources
+            .insert(index, ArgumentResource::Texture(texture.clone()));
+    }
+
+    pub fn set_sampler(
+        &mut self,
+        index: usize,
+        sampler: &Retained<ProtocolObject<dyn MTLSamplerState>>,
+    ) {
+        unsafe { self.encoder.setSamplerState_atIndex(Some(sampler), index) };
+        self.resources
+            .insert(index, ArgumentResource::Sampler(sampler.clone()));
+    }
+
+    pub fn set_buffer(&mut self, index: usize, buffer: &Retained<ProtocolObject<dyn MTLBuffer>>) {
+        unsafe { self.encoder.setBuffer_offset_atIndex(Some(buffer), 0, index) };
+        self.resources
+            .insert(index, ArgumentResource::Buffer(buffer.clone()));
+    }
+
+    // writes `value` into the constant at `[[id(index)]]`, e.g. a `float4x4` member. the
+    // argument buffer is shared with the frames in flight, so it's only changed between draws
+    // the gpu is done with
+    pub fn set_constant<T: Copy>(&mut self, index: usize, value: &T) {
+        unsafe {
+            let constant = self.encoder.constantDataAtIndex(index).cast::<T>();
+            constant.as_ptr().write_unaligned(*value);
+        }
+    }
+
+    // makes the resources the argument buffer references resident for `stages`, metal doesn't
+    // track them through the argument buffer
+    fn use_resources(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+        stages: MTLRenderStages,
+    ) {
+        for resource in self.resources.values() {
+            let resource: &ProtocolObject<dyn MTLResource> = match resource {
+                ArgumentResource::Texture(texture) => texture.as_ref().as_ref(),
+                ArgumentResource::Buffer(buffer) => buffer.as_ref().as_ref(),
+                ArgumentResource::Sampler(_) => continue,
+            };
+            encoder.useResource_usage_stages(resource, MTLResourceUsage::Read, stages);
+        }
+    }
+
+    // binds the argument buffer to the fragment shader argument buffer at `index`
+    fn bind_fragment(&self, encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>, index: usize) {
+        self.use_resources(encoder, MTLRenderStages::MTLRenderStageFragment);
+        unsafe { encoder.setFragmentBuffer_offset_atIndex(Some(&self.buffer), 0, index) };
+    }
+
+    // binds the argument buffer to the vertex shader argument buffer at `index`
+    fn bind_vertex(&self, encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>, index: usize) {
+        self.use_resources(encoder, MTLRenderStages::MTLRenderStageVertex);
+        unsafe { encoder.setVertexBuffer_offset_atIndex(Some(&self.buffer), 0, index) };
+    }
+}
+
+// an array of `T` in a buffer shared with the gpu, allocated once and bound without copying
+pub struct GpuBuffer<T: Copy> {
+    buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    len: usize,
+    _elements: PhantomData<T>,
+}
+
+impl<T: Copy> GpuBuffer<T> {
+    fn new(device: &ProtocolObject<dyn MTLDevice>, data: &[T]) -> Self {
+        let buffer = new_shared_buffer(device, data).expect("Failed to create a buffer.");
+        GpuBuffer {
+            buffer,
+            len: data.len(),
+            _elements: PhantomData,
+        }
+    }
+
+    // replaces the contents with `data`, in place unless the buffer is too small. draws that
+    // were already committed keep reading the buffer, so the gpu must be done with them unless
+    // the buffer grows
+    pub fn update(&mut self, data: &[T]) {
+        if self.buffer.length() < core::mem::size_of_val(data) {
+            *self = Self::new(&self.buffer.device(), data);
+            return;
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.buffer.contents().cast::<T>().as_ptr(),
+                data.len(),
+            )
+        };
+        self.len = data.len();
+    }
+
+    pub fn buffer(&self) -> &Retained<ProtocolObject<dyn MTLBuffer>> {
+        &self.buffer
+    }
+
+    pub fn contents(&self) -> &[T] {
+        let elements = self.buffer.contents().cast::<T>();
+        unsafe { core::slice::from_raw_parts(elements.as_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// the vertex weights and the joint matrices of a skinned draw
+type SkinBuffers = (
+    Retained<ProtocolObject<dyn MTLBuffer>>,
+    Retained<ProtocolObject<dyn MTLBuffer>>,
+);
+
+// a draw call recorded into a render pass
+#[derive(Clone)]
+struct DrawItem {
+    pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    vertex_buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    primitive_type: PrimitiveType,
+    // the range of indices instead for draws with an index buffer
+    vertex_range: Range<usize>,
+    // 32-bit indices into the vertex buffer
+    index_buffer: Option<Retained<ProtocolObject<dyn MTLBuffer>>>,
+    // bound to the vertex shader argument buffer at index 2, one float2 per vertex
+    texture_coordinates: Option<Retained<ProtocolObject<dyn MTLBuffer>>>,
+    // bound to the vertex shader argument buffer at index 6, one `SurfaceVertex` per vertex
+    surface_attributes: Option<Retained<ProtocolObject<dyn MTLBuffer>>>,
+    // the `SkinVertex` of every vertex and the joint matrices, bound to the vertex shader
+    // argument buffers at index 7 and 8
+    skin: Option<SkinBuffers>,
+    // bound to the vertex shader argument buffer at index 3 with the number of instances
+    instances: Option<(Retained<ProtocolObject<dyn MTLBuffer>>, usize)>,
+    // bound to the vertex shader argument buffer at index 4
+    vertex_arguments: Option<Rc<ArgumentTable>>,
+    // bound to the fragment shader argument buffer at index 0
+    fragment_arguments: Option<Rc<ArgumentTable>>,
+    // bound to both stages at index 5
+    bindless_table: Option<Rc<BindlessTable>>,
+    // overrides the viewport of the pass for this draw
+    viewport: Option<MTLViewport>,
+    // where the draw lies in the world, for leaving it out when that's outside the view
+    bounds: Option<BoundingBox>,
+    // the draws of the instances a culling kernel encoded, executed in place of drawing them
+    // all
+    indirect_commands: Option<Retained<ProtocolObject<dyn MTLIndirectCommandBuffer>>>,
+    // the threadgroups of the grid and the threads of an object and a mesh threadgroup of a
+    // draw through a mesh pipeline, which gets the vertex buffer at index 1 of both stages
+    mesh_threadgroups: Option<(MTLSize, MTLSize, MTLSize)>,
+    // a dispatch of the tile function of the pipeline rather than a draw, which gets the
+    // vertex buffer at index 1
+    tile_dispatch: bool,
+    // the query of the pass counting the samples of the draw that pass the depth test
+    occlusion_query: Option<usize>,
+    // replaces the depth stencil state of the pass, with the stencil reference value
+    depth_stencil: Option<(Retained<ProtocolObject<dyn MTLDepthStencilState>>, u32)>,
+    // the resources bound to the slots of arguments found by name, after the ones above
+    bindings: Vec<(BindingSlot, BoundResource)>,
+    // the entity the draw is picked as, see `with_pick_id`
+    pick_id: Option<EntityId>,
+    debug_groups: DebugGroups,
+}
+
+// the draw calls of one render command encoder, encoded in the order they were added
+#[derive(Default)]
+pub struct RenderPass {
+    items: Vec<DrawItem>,
+    cull_mode: CullMode,
+    front_facing: Winding,
+    fill_mode: FillMode,
+    viewport: Option<MTLViewport>,
+    // must lie within the render target
+    scissor: Option<MTLScissorRect>,
+    // the viewports and scissor rects the vertex functions pick from, the first of them are
+    // `viewport` and `scissor`
+    viewports: Vec<MTLViewport>,
+    scissors: Vec<MTLScissorRect>,
+    // a full screen gradient drawn before all the other draws
+    background: Option<(
+        Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        GradientProperties,
+    )>,
+    depth_stencil_state: Option<Retained<ProtocolObject<dyn MTLDepthStencilState>>>,
+    // passes into render targets, encoded before this one so its draws can sample them
+    offscreen: Vec<(RenderTarget, RenderPass)>,
+    // the map of the directional light and its comparison sampler
+    shadow_map: Option<ShadowMap>,
+    // the local lights of the frame and the ones reaching each of its tiles
+    culled_lights: Option<CulledLights>,
+    skybox: Option<Skybox>,
+    // the number of threads the draws are encoded on, on the main thread below two
+    parallel_chunks: usize,
+    // the tiles of a pass with tile dispatches, the defaults of metal without
+    tile_config: Option<TileConfig>,
+    // the queries the draws with an `occlusion_query` count their samples into
+    occlusion_queries: Option<Rc<OcclusionQueries>>,
+    // the clears, loads and stores set in place of the ones of the target
+    attachment_actions: AttachmentActions,
+    // the pass filling the g-buffer of a deferred frame, encoded before this one
+    deferred: Option<Box<DeferredPass>>,
+    // the lighting of the g-buffer, drawn over the background
+    deferred_lighting: Option<DeferredLighting>,
+    // the fences the draws wait for and update
+    fences: PassFences,
+    // names the encoder, see `set_label`
+    label: Option<String>,
+    // the debug groups open while recording, see `push_debug_group`
+    debug_groups: DebugGroups,
+}
+
+impl RenderPass {
+    pub fn draw(
+        &mut self,
+        pipeline_state: &Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        vertex_buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+        primitive_type: PrimitiveType,
+        vertex_range: Range<usize>,
+    ) -> &mut Self {
+        self.items.push(DrawItem {
+            pipeline_state: pipeline_state.clone(),
+            vertex_buffer: vertex_buffer.clone(),
+            primitive_type,
+            vertex_range,
+            index_buffer: None,
+            texture_coordinates: None,
+            surface_attributes: None,
+            skin: None,
+            instances: None,
+            vertex_arguments: None,
+            fragment_arguments: None,
+            bindless_table: None,
+            viewport: None,
+            bounds: None,
+            indirect_commands: None,
+            mesh_threadgroups: None,
+            tile_dispatch: false,
+            occlusion_query: None,
+            depth_stencil: None,
+            bindings: Vec::new(),
+            pick_id: None,
+            debug_groups: self.debug_groups.clone(),
+        });
+        self
+    }
+
+    // draws `threadgroups` object threadgroups of a pipeline from `mesh_pipeline_state`, or
+    // mesh threadgroups when it has no object function. both stages get the scene properties at
+    // index 0 and `buffer` at index 1
+    pub fn draw_mesh_threadgroups(
+        &mut self,
+        pipeline_state: &Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+        threadgroups: (usize, usize, usize),
+        threads_per_object_threadgroup: (usize, usize, usize),
+        threads_per_mesh_threadgroup: (usize, usize, usize),
+    ) -> &mut Self {
+        let size = |(width, height, depth)| MTLSize {
+            width,
+            height,
+            depth,
+        };
+        self.draw(pipeline_state, buffer, PrimitiveType::Triangle, 0..0);
+        if let Some(item) = self.items.last_mut() {
+            item.mesh_threadgroups = Some((
+                size(threadgroups),
+                size(threads_per_object_threadgroup),
+                size(threads_per_mesh_threadgroup),
+            ));
+        }
+        self
+    }
+
+    // draws a copy of the vertices in `vertex_range` for each of `instances`, the vertex
+    // function reads the `InstanceData` of its copy like `vertex_instanced`
+    pub fn draw_instanced(
+        &mut self,
+        pipeline_state: &Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        vertex_buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+        instances: &GpuBuffer<InstanceData>,
+        primitive_type: PrimitiveType,
+        vertex_range: Range<usize>,
+    ) -> &mut Self {
+        self.draw(pipeline_state, vertex_buffer, primitive_type, vertex_range)
+            .with_instances(instances)
+    }
+
+    // draws the vertices `index_buffer` lists in `index_range`, the indices are 32-bit
+    pub fn draw_indexed(
+        &mut self,
+        pipeline_state: &Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        vertex_buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+        index_buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+        primitive_type: PrimitiveType,
+        index_range: Range<usize>,
+    ) -> &mut Self {
+        self.draw(pipeline_state, vertex_buffer, primitive_type, index_range);
+        if let Some(item) = self.items.last_mut() {
+            item.index_buffer = Some(index_buffer.clone());
+        }
+        self
+    }
+
+    // binds an argument table to the vertex shader of the last recorded draw, for vertex
+    // functions that take one at `[[buffer(4)]]` like `vertex_transformed`
+    pub fn with_vertex_arguments(&mut self, arguments: &Rc<ArgumentTable>) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.vertex_arguments = Some(arguments.clone());
+        }
+        self
+    }
+
+    // binds an argument table to the fragment shader of the last recorded draw
+    pub fn with_fragment_arguments(&mut self, arguments: &Rc<ArgumentTable>) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.fragment_arguments = Some(arguments.clone());
+        }
+        self
+    }
+
+    // lets the shaders of the last recorded draw pick from the resources of `table`
+    pub fn with_bindless_table(&mut self, table: &Rc<BindlessTable>) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.bindless_table = Some(table.clone());
+        }
+        self
+    }
+
+    // gives the vertices of the last recorded draw texture coordinates, for vertex functions
+    // that read them from a separate buffer like `vertex_textured_mesh`
+    pub fn with_texture_coordinates(
+        &mut self,
+        texture_coordinates: &Retained<ProtocolObject<dyn MTLBuffer>>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.texture_coordinates = Some(texture_coordinates.clone());
+        }
+        self
+    }
+
+    // gives the vertices of the last recorded draw the normals, tangents and texture coordinates
+    // `vertex_pbr` shades them with
+    pub fn with_surface_attributes(
+        &mut self,
+        surface_attributes: &GpuBuffer<SurfaceVertex>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.surface_attributes = Some(surface_attributes.buffer().clone());
+        }
+        self
+    }
+
+    // moves the vertices of the last recorded draw with the joints of a skin, for vertex
+    // functions like `vertex_pbr_skinned`. `joint_matrices` holds a column major float4x4 per
+    // joint, like a `frame_buffer` of the pose of the frame
+    pub fn with_skin(
+        &mut self,
+        skin_vertices: &GpuBuffer<SkinVertex>,
+        joint_matrices: &Retained<ProtocolObject<dyn MTLBuffer>>,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.skin = Some((skin_vertices.buffer().clone(), joint_matrices.clone()));
+        }
+        self
+    }
+
+    // turns the last recorded draw into a copy for each of `instances`, for instancing meshes
+    pub fn with_instances(&mut self, instances: &GpuBuffer<InstanceData>) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.instances = Some((instances.buffer().clone(), instances.len()));
+        }
+        self
+    }
+
+    // renders the last recorded draw into `viewport` instead of the viewport of the pass
+    pub fn with_viewport(&mut self, viewport: MTLViewport) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.viewport = Some(viewport);
+        }
+        self
+    }
+
+    // encodes the draws of the pass on `chunks` threads, each filling a sub-encoder of a
+    // parallel encoder with its share of the draws in order. it pays off for passes with
+    // thousands of draws, for the few of most passes the threads cost more than they save
+    pub fn encode_parallel(&mut self, chunks: usize) -> &mut Self {
+        self.parallel_chunks = chunks;
+        self
+    }
+
+    // creates an encoder for `pass_descriptor` and encodes all the draws into it,
+    // returns false if the encoder couldn't be created
+    fn encode(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        pass_descriptor: &MTLRenderPassDescriptor,
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+    ) -> bool {
+        for (target, render_pass) in &self.offscreen {
+            if !render_pass.encode(command_buffer, &target.pass_descriptor(), scene_properties) {
+                return false;
+            }
+        }
+        if let Some(deferred) = &self.deferred {
+            let pass_descriptor = &deferred.pass_descriptor;
+            if !deferred
+                .render_pass
+                .encode(command_buffer, pass_descriptor, scene_properties)
+            {
+                return false;
+            }
+        }
+
+        let actions_descriptor = self.attachment_actions.pass_descriptor(pass_descriptor);
+        let pass_descriptor = actions_descriptor.as_deref().unwrap_or(pass_descriptor);
+        let tiled_descriptor = self
+            .tile_config
+            .map(|tile_config| tile_config.pass_descriptor(pass_descriptor));
+        let pass_descriptor = tiled_descriptor.as_deref().unwrap_or(pass_descriptor);
+        // the draws only count their samples in the frames writing the queries
+        let queries = self
+            .occlusion_queries
+            .as_ref()
+            .filter(|queries| queries.begin(command_buffer));
+        let queried_descriptor = queries.map(|queries| queries.pass_descriptor(pass_descriptor));
+        let pass_descriptor = queried_descriptor.as_deref().unwrap_or(pass_descriptor);
+        let counting = queries.is_some();
+        if self.parallel_chunks > 1 {
+            return self.encode_parallel_chunks(
+                command_buffer,
+                pass_descriptor,
+                scene_properties,
+                counting,
+            );
+        }
+        let Some(encoder) = command_buffer.renderCommandEncoderWithDescriptor(pass_descriptor)
+        else {
+            return false;
+        };
+        // names the pass in the faults of enhanced command buffer errors and in gpu captures
+        encoder.setLabel(Some(&self.encoder_label()));
+        self.encode_chunk(&encoder, &self.items, scene_properties, (true, true), counting);
+        encoder.endEncoding();
+        true
+    }
+
+    // splits the draws into `parallel_chunks` runs of sub-encoders of a parallel encoder, each
+    // encoded by a thread of its own. metal executes the sub-encoders in the order they were
+    // created, whichever thread finishes first
+    fn encode_parallel_chunks(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        pass_descriptor: &MTLRenderPassDescriptor,
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+        counting: bool,
+    ) -> bool {
+        let Some(parallel_encoder) =
+            command_buffer.parallelRenderCommandEncoderWithDescriptor(pass_descriptor)
+        else {
+            return false;
+        };
+        parallel_encoder.setLabel(Some(&self.encoder_label()));
+        let chunk_size = self.items.len().div_ceil(self.parallel_chunks).max(1);
+        let mut chunks: Vec<_> = self.items.chunks(chunk_size).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let encoders: Option<Vec<_>> = chunks
+            .iter()
+            .map(|_| parallel_encoder.renderCommandEncoder())
+            .collect();
+        let Some(encoders) = encoders else {
+            parallel_encoder.endEncoding();
+            return false;
+        };
+        let last = chunks.len() - 1;
+        std::thread::scope(|scope| {
+            for (i, (chunk, encoder)) in chunks.into_iter().zip(encoders).enumerate() {
+                let work = ChunkWork {
+                    render_pass: self,
+                    chunk,
+                    encoder,
+                    scene_properties,
+                };
+                scope.spawn(move || work.encode((i == 0, i == last), counting));
+            }
+        });
+        parallel_encoder.endEncoding();
+        true
+    }
+
+    // sets the state of the pass on `encoder` and encodes `items` with it. the first chunk of
+    // the pass starts with the background and the deferred lighting, the last one ends with a
+    // depth tested skybox or the lighting of a g-buffer pass. with `counting` the draws with an
+    // occlusion query count their samples
+    fn encode_chunk(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+        items: &[DrawItem],
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+        (first, last): (bool, bool),
+        counting: bool,
+    ) {
+        if first {
+            self.fences.encode_render_waits(encoder);
+        }
+        let multiple_viewports = self.has_multiple_viewports(encoder);
+        self.encode_scissors(encoder, multiple_viewports);
+
+        // the background comes first, before the culling and fill mode of the geometry apply
+        if let Some((pipeline_state, gradient)) = self.background.as_ref().filter(|_| first) {
+            encoder.setRenderPipelineState(pipeline_state);
+            self.encode_viewports(encoder, None, multiple_viewports);
+            unsafe {
+                encoder.setFragmentBuffer_offset_atIndex(Some(scene_properties), 0, 0);
+                encoder.setFragmentBytes_length_atIndex(
+                    NonNull::from(gradient).cast::<c_void>(),
+                    core::mem::size_of_val(gradient),
+                    1,
+                );
+                // one triangle covering the viewport, the shader generates its vertices
+                encoder.drawPrimitives_vertexStart_vertexCount(MTLPrimitiveType::Triangle, 0, 3);
+            }
+        }
+
+        // the surfaces of a deferred frame are lit over the background, writing their depth
+        let lighting = self.deferred_lighting.as_ref();
+        if let Some(lighting) = lighting.filter(|lighting| first && !lighting.after_draws) {
+            self.encode_viewports(encoder, None, multiple_viewports);
+            lighting.encode(encoder, scene_properties);
+        }
+
+        // a skybox without a depth buffer to test against is drawn over the background
+        let skybox = self.skybox.as_ref();
+        if let Some(skybox) = skybox.filter(|skybox| first && !skybox.is_depth_tested()) {
+            skybox.encode(encoder);
+        }
+
+        // likewise the depth test, the background leaves the depth buffer at the far plane
+        if let Some(depth_stencil_state) = &self.depth_stencil_state {
+            encoder.setDepthStencilState(Some(depth_stencil_state));
+        }
+        encoder.setCullMode(self.cull_mode.mtl_cull_mode());
+        encoder.setFrontFacingWinding(self.front_facing.mtl_winding());
+        encoder.setTriangleFillMode(self.fill_mode.mtl_triangle_fill_mode());
+
+        // bind the scene properties to the vertex shader argument buffer at index 0
+        unsafe { encoder.setVertexBuffer_offset_atIndex(Some(scene_properties), 0, 0) };
+        // and the shadow map of the directional light to the fragment shaders
+        if let Some((shadow_map, sampler)) = &self.shadow_map {
+            unsafe {
+                encoder.setFragmentTexture_atIndex(Some(shadow_map), SHADOW_MAP_INDEX);
+                encoder.setFragmentSamplerState_atIndex(Some(sampler), SHADOW_MAP_INDEX);
+            }
+        }
+        // and the lights of the tiles
+        self.encode_lights(encoder);
+
+        // consecutive draws sharing a bindless table only bind it once
+        let mut bindless_table: Option<&Rc<BindlessTable>> = None;
+        // whether the last draw replaced the depth stencil state of the pass
+        let mut own_depth_stencil = false;
+
+        let drawn = |item: &&DrawItem| {
+            item.mesh_threadgroups.is_some() || item.tile_dispatch || !item.vertex_range.is_empty()
+        };
+        let mut debug_groups: &[String] = &[];
+        for item in items.iter().filter(drawn) {
+            encode_debug_groups(encoder, debug_groups, &item.debug_groups);
+            debug_groups = &item.debug_groups;
+            // bind the vertex buffer to the vertex shader argument buffer at index 1
+            encoder.setRenderPipelineState(&item.pipeline_state);
+            self.encode_viewports(encoder, item.viewport, multiple_viewports);
+            if let Some(vertex_arguments) = &item.vertex_arguments {
+                vertex_arguments.bind_vertex(encoder, 4);
+            }
+            if let Some(fragment_arguments) = &item.fragment_arguments {
+                fragment_arguments.bind_fragment(encoder, 0);
+            }
+            if counting {
+                // a count of 8 bytes per query
+                let (mode, offset) = match item.occlusion_query {
+                    Some(query) => (MTLVisibilityResultMode::Counting, query * 8),
+                    None => (MTLVisibilityResultMode::Disabled, 0),
+                };
+                encoder.setVisibilityResultMode_offset(mode, offset);
+            }
+            match &item.depth_stencil {
+                Some((depth_stencil_state, reference)) => {
+                    encoder.setDepthStencilState(Some(depth_stencil_state));
+                    encoder.setStencilReferenceValue(*reference);
+                    own_depth_stencil = true;
+                }
+                None if own_depth_stencil => {
+                    encoder.setDepthStencilState(self.depth_stencil_state.as_deref());
+                    encoder.setStencilReferenceValue(0);
+                    own_depth_stencil = false;
+                }
+                None => (),
+            }
+            if let Some(table) = &item.bindless_table {
+                if !bindless_table.is_some_and(|bound| Rc::ptr_eq(bound, table)) {
+                    table.bind(encoder);
+                    bindless_table = Some(table);
+                }
+            }
+            if let Some((threadgroups, object_threads, mesh_threads)) = item.mesh_threadgroups {
+                unsafe {
+                    encoder.setObjectBuffer_offset_atIndex(Some(scene_properties), 0, 0);
+                    encoder.setObjectBuffer_offset_atIndex(Some(&item.vertex_buffer), 0, 1);
+                    encoder.setMeshBuffer_offset_atIndex(Some(scene_properties), 0, 0);
+                    encoder.setMeshBuffer_offset_atIndex(Some(&item.vertex_buffer), 0, 1);
+                    encoder.drawMeshThreadgroups_threadsPerObjectThreadgroup_threadsPerMeshThreadgroup(
+                        threadgroups,
+                        object_threads,
+                        mesh_threads,
+                    );
+                }
+                continue;
+            }
+            if item.tile_dispatch {
+                unsafe {
+                    encoder.setTileBuffer_offset_atIndex(Some(scene_properties), 0, 0);
+                    encoder.setTileBuffer_offset_atIndex(Some(&item.vertex_buffer), 0, 1);
+                    let threadgroup_memory_length = self
+                        .tile_config
+                        .map_or(0, |tile_config| tile_config.threadgroup_memory_length);
+                    if threadgroup_memory_length > 0 {
+                        encoder.setThreadgroupMemoryLength_offset_atIndex(
+                            threadgroup_memory_length,
+                            0,
+                            0,
+                        );
+                    }
+                    encoder.dispatchThreadsPerTile(MTLSize {
+                        width: encoder.tileWidth(),
+                        height: encoder.tileHeight(),
+                        depth: 1,
+                    });
+                }
+                continue;
+            }
+            unsafe { encoder.setVertexBuffer_offset_atIndex(Some(&item.vertex_buffer), 0, 1) };
+            if let Some(texture_coordinates) = &item.texture_coordinates {
+                unsafe { encoder.setVertexBuffer_offset_atIndex(Some(texture_coordinates), 0, 2) };
+            }
+            if let Some(surface_attributes) = &item.surface_attributes {
+                unsafe { encoder.setVertexBuffer_offset_atIndex(Some(surface_attributes), 0, 6) };
+            }
+            if let Some((skin_vertices, joint_matrices)) = &item.skin {
+                unsafe {
+                    encoder.setVertexBuffer_offset_atIndex(Some(skin_vertices), 0, 7);
+                    encoder.setVertexBuffer_offset_atIndex(Some(joint_matrices), 0, 8);
+                }
+            }
+            // draws without instances render a single copy
+            let instance_count = match &item.instances {
+                Some((instance_buffer, instance_count)) => {
+                    unsafe { encoder.setVertexBuffer_offset_atIndex(Some(instance_buffer), 0, 3) };
+                    *instance_count
+                }
+                None => 1,
+            };
+            for (slot, resource) in &item.bindings {
+                resource.encode(encoder, *slot);
+            }
+            if let Some(indirect_commands) = &item.indirect_commands {
+                // the commands only point at the index buffer, it's made resident here
+                if let Some(index_buffer) = &item.index_buffer {
+                    encoder.useResource_usage_stages(
+                        index_buffer.as_ref().as_ref(),
+                        MTLResourceUsage::Read,
+                        MTLRenderStages::MTLRenderStageVertex,
+                    );
+                }
+                let range = NSRange::new(0, instance_count);
+                unsafe { encoder.executeCommandsInBuffer_withRange(indirect_commands, range) };
+                continue;
+            }
+            let primitive_type = item.primitive_type.mtl_primitive_type();
+            match &item.index_buffer {
+                Some(index_buffer) => unsafe {
+                    encoder.drawIndexedPrimitives_indexCount_indexType_indexBuffer_indexBufferOffset_instanceCount(
+                        primitive_type,
+                        item.vertex_range.len(),
+                        MTLIndexType::UInt32,
+                        index_buffer,
+                        item.vertex_range.start * core::mem::size_of::<u32>(),
+                        instance_count,
+                    )
+                },
+                None => unsafe {
+                    encoder.drawPrimitives_vertexStart_vertexCount_instanceCount(
+                        primitive_type,
+                        item.vertex_range.start,
+                        item.vertex_range.len(),
+                        instance_count,
+                    )
+                },
+            }
+        }
+        encode_debug_groups(encoder, debug_groups, &[]);
+
+        // otherwise it's drawn where the draws left the depth buffer at the far plane
+        if let Some(skybox) = skybox.filter(|skybox| last && skybox.is_depth_tested()) {
+            skybox.encode(encoder);
+        }
+        // the g-buffer is lit in tile memory once all of it was drawn
+        if let Some(lighting) = lighting.filter(|lighting| last && lighting.after_draws) {
+            self.encode_viewports(encoder, None, multiple_viewports);
+            lighting.encode(encoder, scene_properties);
+        }
+        if last {
+            self.fences.encode_render_updates(encoder);
+        }
+    }
+}
+
+// what a thread of `encode_parallel_chunks` encodes a chunk of a pass with
+struct ChunkWork<'a> {
+    render_pass: &'a RenderPass,
+    chunk: &'a [DrawItem],
+    encoder: Retained<ProtocolObject<dyn MTLRenderCommandEncoder>>,
+    scene_properties: &'a ProtocolObject<dyn MTLBuffer>,
+}
+
+// SAFETY: metal objects can be used from any thread. the pass and its draws hold `Rc`s, which
+// `encode_chunk` only reads, it never clones or drops them, and the scope joins the threads
+// before the pass can change or be dropped on the thread it belongs to
+unsafe impl Send for ChunkWork<'_> {}
+
+impl ChunkWork<'_> {
+    // taken into the thread as a whole by the call, a closure using the fields would capture
+    // them one by one
+    fn encode(self, ends: (bool, bool), counting: bool) {
+        let ChunkWork {
+            render_pass,
+            chunk,
+            encoder,
+            scene_properties,
+        } = self;
+        render_pass.encode_chunk(&encoder, chunk, scene_properties, ends, counting);
+        encoder.endEncoding();
+    }
+}
+
+type RenderCallback = Box<dyn Fn(&MetalRenderer, &mut RenderPass)>;
+
+// called on a metal completion thread once the gpu finished a frame
+type FrameCompleteHandler = Arc<dyn Fn() + Send + Sync>;
+
+type ResizeHandler = Box<dyn Fn(&MetalRenderer, NSSize)>;
+type DeviceChangeHandler = Box<dyn Fn(&MetalRenderer)>;
+
+// compute pipelines keyed by their kernel function name
+type ComputePipelineStates =
+    HashMap<String, Retained<ProtocolObject<dyn MTLComputePipelineState>>>;
+
+pub struct AppState {
+    logger: RefCell<Option<Logger>>,
+    // replaced when the device is removed, see `handle_device_removal`
+    device: RefCell<Option<Retained<ProtocolObject<dyn MTLDevice>>>>,
+    command_queue: RefCell<Option<Retained<ProtocolObject<dyn MTLCommandQueue>>>>,
+    library: RefCell<Option<Retained<ProtocolObject<dyn MTLLibrary>>>>,
+    // the compilation of the library started by `init`, the library is set once it's done
+    pending_library: RefCell<Option<LibraryCompilation>>,
+    // built from a small library of its own while the shader library compiles
+    placeholder_library: RefCell<Option<Retained<ProtocolObject<dyn MTLLibrary>>>>,
+    placeholder_pipelines: RefCell<PipelineCache>,
+    // pipelines compiling on a metal thread, a placeholder draws in their place until they're
+    // done
+    pending_pipelines: RefCell<PendingPipelines>,
+    shader_source: RefCell<String>,
+    shader_options: RefCell<ShaderOptions>,
+    shader_watcher: RefCell<Option<ShaderWatcher>>,
+    // drawing the frames in place of the application, see `load_fullscreen_shader`
+    fullscreen_shader: RefCell<Option<FullscreenShader>>,
+    pipeline_cache: RefCell<PipelineCache>,
+    // the arguments of the shaders of the pipelines in the caches
+    pipeline_bindings: RefCell<PipelineBindings>,
+    mesh_pipeline_states: RefCell<MeshPipelineStates>,
+    tile_pipeline_states: RefCell<TilePipelineStates>,
+    // shared with the renderers sharing the device, saved once they're all gone
+    pipeline_archive: RefCell<Option<Rc<PipelineArchive>>>,
+    compute_pipeline_states: RefCell<ComputePipelineStates>,
+    pixel_format: Cell<PixelFormat>,
+    color_space: Cell<ColorSpace>,
+    depth_format: Cell<Option<DepthFormat>>,
+    depth_stencil_state: RefCell<Option<Retained<ProtocolObject<dyn MTLDepthStencilState>>>>,
+    // the states built by `DepthStencilBuilder`
+    depth_stencil_states:
+        RefCell<HashMap<DepthStencilBuilder, Retained<ProtocolObject<dyn MTLDepthStencilState>>>>,
+    primitive_type: Cell<PrimitiveType>,
+    cull_mode: Cell<CullMode>,
+    front_facing: Cell<Winding>,
+    fill_mode: Cell<FillMode>,
+    gradient: Cell<Option<GradientProperties>>,
+    viewport: Cell<Option<MTLViewport>>,
+    scissor: Cell<Option<MTLScissorRect>>,
+    point_size: Cell<f32>,
+    // the scene time of every frame when set, instead of the seconds since the start
+    fixed_time: Cell<Option<f32>>,
+    camera: Cell<Camera>,
+    gpu_timer: RefCell<Option<GpuTimer>>,
+    frame_stats: Cell<FrameStats>,
+    frame_pacing: SharedFramePacing,
+    // the bits of the `gpu_frame_time` of the last completed frame, written by the completion
+    // handler of its command buffer
+    gpu_frame_time: Arc<AtomicU64>,
+    // what went wrong with the frames the gpu failed, written by the completion handlers and
+    // handled by the next frame
+    command_buffer_faults: Arc<Mutex<Vec<CommandBufferFault>>>,
+    enhanced_command_buffer_errors: Cell<bool>,
+    // the recoveries from gpu faults in a row and when the last one was
+    gpu_recoveries: Cell<usize>,
+    last_gpu_recovery: Cell<Option<Instant>>,
+    capture_path: RefCell<Option<PathBuf>>,
+    capture_frames_remaining: Cell<usize>,
+    vertex_buffer: RefCell<Option<GpuBuffer<VertexInput>>>,
+    last_command_buffer: RefCell<Option<Retained<ProtocolObject<dyn MTLCommandBuffer>>>>,
+    frames: RefCell<Option<Rc<FrameAllocator>>>,
+    min_content_size: Cell<NSSize>,
+    render_callback: RefCell<Option<RenderCallback>>,
+    render_graph_callback: RefCell<Option<RenderGraphCallback>>,
+    // the transient textures of the render graph, reused by the next frame
+    graph_textures: RefCell<Vec<RenderTarget>>,
+    graph_allocator: RefCell<Option<Rc<GpuAllocator>>>,
+    // the resources given to `register_purgeable`
+    purgeable: RefCell<Vec<Weak<ProtocolObject<dyn MTLResource>>>>,
+    memory_pressure: Cell<MemoryPressure>,
+    memory_pressure_source: MemoryPressureSource,
+    // whether the allocated memory exceeded the budget of the device last frame
+    over_memory_budget: Cell<bool>,
+    compute_callback: RefCell<Option<ComputeCallback>>,
+    ray_tracing: RefCell<RayTracing>,
+    shadows: RefCell<ShadowState>,
+    lights: RefCell<LightState>,
+    shadow_callback: RefCell<Option<RenderCallback>>,
+    skybox: RefCell<SkyboxState>,
+    deferred: RefCell<DeferredState>,
+    // the events of `wait_for_event` and `signal_event`
+    frame_events: RefCell<FrameEvents>,
+    // the queue of `upload_texture` for the assets, see `upload_queue`
+    upload_queue: RefCell<Option<Rc<GpuQueue>>>,
+    // what the `RenderHandle`s of other threads sent, see `render_handle`
+    render_commands: RefCell<RenderCommands>,
+    // the surface the frames are copied into, see `set_output_surface`
+    output_surface: RefCell<Option<SharedSurface>>,
+    #[cfg(feature = "metalfx")]
+    upscaling: RefCell<UpscalingState>,
+    #[cfg(feature = "core-image")]
+    image_filters: RefCell<ImageFilterState>,
+    post_process: RefCell<PostProcessState>,
+    rasterization_rate: RefCell<RasterizationRateState>,
+    picking: RefCell<PickingState>,
+    pending_screenshots: RefCell<Vec<PendingScreenshot>>,
+    overlay: RefCell<Overlay>,
+    text: RefCell<TextState>,
+    #[cfg(feature = "egui")]
+    egui: RefCell<EguiState>,
+    #[cfg(feature = "egui")]
+    egui_callback: RefCell<Option<EguiCallback>>,
+    // created with the first imgui call, imgui allows a single context
+    #[cfg(feature = "imgui")]
+    imgui: RefCell<Option<ImguiState>>,
+    #[cfg(feature = "imgui")]
+    imgui_callback: RefCell<Option<ImguiCallback>>,
+    #[cfg(feature = "recording")]
+    recording: RefCell<Option<Recording>>,
+    #[cfg(feature = "audio")]
+    audio: RefCell<Option<AudioCapture>>,
+    input: RefCell<InputState>,
+    cursor_mode: Cell<CursorMode>,
+    update_callback: RefCell<Option<UpdateCallback>>,
+    last_update: Cell<Option<Instant>>,
+    fixed_update_callback: RefCell<Option<FixedUpdateCallback>>,
+    time: Cell<Time>,
+    frame_complete_handler: RefCell<Option<FrameCompleteHandler>>,
+    resize_handler: RefCell<Option<ResizeHandler>>,
+    // names the command buffers of the frames, see `set_label`
+    label: RefCell<Option<String>>,
+    drawable_format_handler: RefCell<Option<DrawableFormatHandler>>,
+    // the drawable size the custom viewport and scissor rect were given for
+    drawable_size: Cell<NSSize>,
+    backend: Cell<Backend>,
+    device_selector: RefCell<DeviceSelector>,
+    // a renderer sharing the device of another observes the devices on its own and moves along
+    device_observer: OnceCell<DeviceObserver>,
+    device_change_handler: RefCell<Option<DeviceChangeHandler>>,
+    redraw_mode: Cell<RedrawMode>,
+    // a view drawing on demand draws the next frame the display link gets to
+    needs_redraw: Cell<bool>,
+    // the window is hidden, its view is paused whatever the redraw mode
+    occluded: Cell<bool>,
+    visibility_observer: OnceCell<VisibilityObserver>,
+    // see `set_smooth_live_resize`
+    smooth_live_resize: Cell<bool>,
+    // whether the view presented with the transaction before the live resize in progress
+    live_resize: Cell<Option<bool>>,
+    minimum_frame_duration: Cell<Option<f64>>,
+    window: OnceCell<Retained<NativeWindow>>,
+    // the view the view of the renderer is added to, the content view of the window when unset
+    host_view: OnceCell<Retained<NativeView>>,
+    surface: OnceCell<Surface>,
+}
+
+fn compile_options(shader_options: &ShaderOptions) -> Retained<MTLCompileOptions> {
+    let compile_options = MTLCompileOptions::new();
+    if let Some(language_version) = shader_options.language_version {
+        compile_options.setLanguageVersion(language_version);
+    }
+    compile_options.setFastMathEnabled(shader_options.fast_math_enabled);
+    let (names, values): (Vec<_>, Vec<_>) = shader_options
+        .preprocessor_macros
+        .iter()
+        .map(|(name, value)| {
+            let value = Retained::into_super(NSString::from_str(value));
+            (NSString::from_str(name), value)
+        })
+        .unzip();
+    let names: Vec<&NSString> = names.iter().map(|name| &**name).collect();
+    let preprocessor_macros = NSDictionary::from_vec(&names, values);
+    unsafe { compile_options.setPreprocessorMacros(Some(&preprocessor_macros)) };
+    compile_options
+}
+
+// declare the Objective-C class machinery
+declare_class!(
+    pub struct MetalRenderer;
+
+    // SAFETY:
+    // - The superclass NSObject does not have any subclassing requirements.
+    // - Main thread only mutability is correct, since this is an application delegate.
+    // - `Delegate` does not implement `Drop`.
+    unsafe impl ClassType for MetalRenderer {
+        type Super = NSObject;
+        type Mutability = MainThreadOnly;
+        const NAME: &'static str = "MetalRenderer";
+    }
+
+    impl DeclaredClass for MetalRenderer {
+        type Ivars = AppState;
+    }
+
+    unsafe impl NSObjectProtocol for MetalRenderer {}
+
+    // define the delegate methods for the `MTKViewDelegate` protocol, metalkit views are only
+    // used on macos
+    #[cfg(target_os = "macos")]
+    unsafe impl MTKViewDelegate for MetalRenderer {
+        #[method(drawInMTKView:)]
+        #[allow(non_snake_case)]
+        unsafe fn drawInMTKView(&self, _mtk_view: &MTKView) {
+            self.render_frame();
+        }
+
+        #[method(mtkView:drawableSizeWillChange:)]
+        #[allow(non_snake_case)]
+        unsafe fn mtkView_drawableSizeWillChange(&self, _view: &MTKView, size: NSSize) {
+            self.drawable_size_changed(size);
+        }
+    }
+);
+
+impl MetalRenderer {
+    // records, commits and presents a frame, driven by the MTKView or the display link of the
+    // layer depending on the backend
+    pub(crate) fn render_frame(&self) {
+        let _span = tracing::debug_span!("encode_frame").entered();
+        // a view drawing on demand pauses again after the frame it was asked for
+        let needed = self.ivars().needs_redraw.replace(false);
+        if needed && self.ivars().redraw_mode.get() == RedrawMode::OnDemand {
+            self.update_paused();
+        }
+        // move off an unplugged device before the frame touches it
+        self.check_devices();
+        // report the frames the gpu failed since the last one, and recover from a hang or a
+        // restart of the gpu
+        self.check_gpu_faults();
+        let command_queue = self.command_queue();
+
+        // start a requested gpu capture, it covers all the work of its frames and stops with
+        // the last one
+        let _capture = self.advance_capture().then_some(CaptureGuard);
+
+        // pick up the library compiled at startup and hot reloaded shaders before any pipeline of
+        // the frame is looked up
+        self.finish_library_compilation();
+        self.reload_shaders();
+        // a view drawing on demand keeps drawing until the placeholders are replaced
+        if self.is_compiling() {
+            self.set_needs_redraw();
+        }
+
+        // give up memory when the os is short of it
+        self.check_memory();
+
+        // let the application react to the input before recording the frame, and take in
+        // what the other threads sent
+        self.update();
+        self.apply_render_commands();
+
+        // metal can't create drawables without pixels, skip drawing while the view is that small
+        let surface = self.ivars().surface.get().unwrap();
+        let drawable_size = surface.drawable_size();
+        if drawable_size.width <= 1. || drawable_size.height <= 1. {
+            return;
+        }
+
+        // prepare for drawing
+        let (current_drawable, drawable_texture, pass_descriptor) = match surface.next_frame() {
+            Ok(frame) => frame,
+            Err(reason) => {
+                self.log(LogLevel::Warn, &format!("Dropped frame: {reason}."));
+                return;
+            }
+        };
+        let Some(command_buffer) = self.frame_command_buffer(&command_queue) else {
+            self.log(LogLevel::Warn, "Dropped frame: failed to create a command buffer.");
+            return;
+        };
+        self.label_frame(&command_buffer);
+        // write the scene properties of the frame, waiting for a free slot first so that the
+        // frame buffers allocated while recording don't overwrite a frame on the gpu
+        let frames = self.frames();
+        // a post-processed frame renders into an hdr target, the passes of the chain draw it
+        // into the drawable
+        let post_processed = self.prepare_post_process(drawable_size);
+        // an upscaled frame is rendered smaller, with a jittered camera for the temporal scaler
+        #[cfg(feature = "metalfx")]
+        let upscaled = self.prepare_upscaling(drawable_size);
+        #[cfg(feature = "metalfx")]
+        let render_size = upscaled
+            .as_ref()
+            .map_or(drawable_size, |upscaled| upscaled.render_size);
+        #[cfg(not(feature = "metalfx"))]
+        let render_size = drawable_size;
+        // a frame rendered at varying rates renders into a smaller target in the coordinates of
+        // the drawable, and is stretched over it after its passes
+        let rate_mapped = self.prepare_rasterization_rate(drawable_size);
+        #[cfg(feature = "metalfx")]
+        let rate_mapped = rate_mapped.filter(|_| upscaled.is_none());
+        // the local lights are culled in tiles of the frame before its draws
+        let light_tiles = self.prepare_light_tiles(render_size, rate_mapped.is_some());
+        let viewport = self.viewport(render_size);
+        let aspect = (viewport.width / viewport.height) as f32;
+        let camera = self.ivars().camera.get();
+        let view_projection = camera.view_projection_matrix(aspect);
+        #[cfg(feature = "metalfx")]
+        let view_projection = match &upscaled {
+            Some(upscaled) => upscaled.jitter(&view_projection),
+            None => view_projection,
+        };
+        let edr_headroom = if self.pixel_format().is_hdr() {
+            self.edr_headroom().current as f32
+        } else {
+            1.
+        };
+        #[cfg(feature = "audio")]
+        let audio_bands = self.analyze_audio();
+        #[cfg(not(feature = "audio"))]
+        let audio_bands = [0.; AUDIO_BANDS];
+        let scene_properties = frames.acquire(
+            view_projection,
+            self.ivars().point_size.get(),
+            edr_headroom,
+            self.light_properties(light_tiles.as_ref().map_or([0; 3], LightTiles::grid)),
+            camera.view_origin(),
+            audio_bands,
+            self.ivars().fixed_time.get(),
+        );
+        let recording_start = Instant::now();
+
+        // record the dispatches of the frame, they run before its draws
+        let mut compute_pass = ComputePass::default();
+        if let Some(compute_callback) = self.ivars().compute_callback.borrow().as_ref() {
+            compute_callback(self, &mut compute_pass);
+        }
+        // a fullscreen shader replaces the draws of the frame, and so does a ray traced scene
+        // otherwise, its rays are traced after the dispatches of the callback
+        let fullscreen_shader = self.has_fullscreen_shader();
+        let ray_traced = if fullscreen_shader {
+            None
+        } else {
+            self.record_ray_tracing(&mut compute_pass, drawable_size)
+        };
+        let culled_lights = light_tiles.map(|light_tiles| {
+            self.record_light_culling(&mut compute_pass, &light_tiles, render_size)
+        });
+        // the casters of the shadows are drawn from the light first
+        let shadow_map = (!fullscreen_shader)
+            .then(|| self.prepare_shadow_map())
+            .flatten();
+        let mut shadow_pass = shadow_map
+            .as_ref()
+            .and_then(|(shadow_map, _)| self.record_shadow_pass(shadow_map, &scene_properties));
+
+        // record the draws of the frame, by default just the geometry in a single pass
+        let background = self.ivars().gradient.get().map(|gradient| {
+            let pipeline_state =
+                self.render_pipeline_state("vertex_fullscreen", "fragment_gradient");
+            (pipeline_state, gradient)
+        });
+        let mut frame_passes = match self.ivars().render_graph_callback.borrow().as_ref() {
+            Some(render_graph_callback) if ray_traced.is_none() && !fullscreen_shader => {
+                self.record_render_graph(render_graph_callback, drawable_size, background)
+            }
+            _ => {
+                let mut render_pass = self.frame_render_pass(render_size);
+                // the pass of a ray traced frame only clears, the picture is copied over it
+                if fullscreen_shader {
+                    self.record_fullscreen_shader(&mut render_pass, render_size, audio_bands);
+                } else if ray_traced.is_none() {
+                    self.prepare_deferred(
+                        &mut render_pass,
+                        render_size,
+                        &view_projection,
+                        shadow_map.clone(),
+                    );
+                    match self.ivars().render_callback.borrow().as_ref() {
+                        Some(render_callback) => render_callback(self, &mut render_pass),
+                        None => self.draw_geometry(&mut render_pass),
+                    }
+                    self.draw_submitted(&mut render_pass);
+                    render_pass.background = background;
+                    render_pass.shadow_map = shadow_map;
+                    render_pass.culled_lights = culled_lights;
+                    render_pass.skybox = self.prepare_skybox(aspect);
+                }
+                let target = FrameTarget::Backbuffer {
+                    first: true,
+                    later_write: false,
+                };
+                // an upscaled frame renders into the smaller textures
+                #[cfg(feature = "metalfx")]
+                let target = match &upscaled {
+                    Some(upscaled) => FrameTarget::Texture(upscaled.pass_descriptor.clone()),
+                    None => target,
+                };
+                let target = match &rate_mapped {
+                    Some(rate_mapped) => FrameTarget::Texture(rate_mapped.pass_descriptor.clone()),
+                    None => target,
+                };
+                let target = match &post_processed {
+                    Some(post_processed) => {
+                        FrameTarget::Texture(post_processed.pass_descriptor.clone())
+                    }
+                    None => target,
+                };
+                vec![FramePass {
+                    target,
+                    render_pass,
+                }]
+            }
+        };
+        // leave out the draws outside the view, the shadow pass sees the scene from the light
+        let mut counts = (0, 0);
+        let culled_passes = frame_passes
+            .iter_mut()
+            .map(|frame_pass| (&mut frame_pass.render_pass, view_projection))
+            .chain(
+                shadow_pass
+                    .as_mut()
+                    .map(|shadow_pass| (&mut shadow_pass.render_pass, shadow_pass.view_projection)),
+            );
+        for (render_pass, view_projection) in culled_passes {
+            let (drawn, culled) = render_pass.cull(&view_projection);
+            counts = (counts.0 + drawn, counts.1 + culled);
+        }
+        if let Some(post_processed) = post_processed {
+            frame_passes.extend(post_processed.passes);
+        }
+        // the tagged draws are drawn again with their ids under the picked point
+        let pick_pass = self.record_pick_pass(&frame_passes, drawable_size);
+
+        #[cfg(feature = "egui")]
+        let egui_draw = self.record_egui(&drawable_texture);
+        #[cfg(feature = "imgui")]
+        let imgui_draw = self.record_imgui(&drawable_texture);
+        let text_draw = self.record_text(&drawable_texture);
+        let overlay_draw = self.record_overlay(&drawable_texture);
+
+        frames.finish();
+
+        let compute_pass_descriptor = (!compute_pass.is_empty())
+            .then(|| unsafe { MTLComputePassDescriptor::computePassDescriptor() });
+        let gpu_timer_sampling =
+            self.prepare_gpu_timer(&pass_descriptor, compute_pass_descriptor.as_deref());
+        #[cfg(feature = "metalfx")]
+        if let Some(upscaled) = &upscaled {
+            upscaled.take_gpu_timer_samples(&pass_descriptor);
+        }
+        if let Some(rate_mapped) = &rate_mapped {
+            rate_mapped.take_gpu_timer_samples(&pass_descriptor);
+        }
+        self.encode_event_waits(&command_buffer);
+        if let Some(compute_pass_descriptor) = &compute_pass_descriptor {
+            if !compute_pass.encode(
+                &command_buffer,
+                compute_pass_descriptor,
+                Some(&scene_properties),
+            ) {
+                frames.release();
+                self.log(LogLevel::Warn, "Dropped frame: failed to create a compute encoder.");
+                return;
+            }
+        }
+        if let Some(shadow_pass) = &shadow_pass {
+            let ShadowPass {
+                pass_descriptor,
+                render_pass,
+                scene_properties,
+                ..
+            } = shadow_pass;
+            if !render_pass.encode(&command_buffer, pass_descriptor, scene_properties) {
+                frames.release();
+                self.log(LogLevel::Warn, "Dropped frame: failed to create a shadow encoder.");
+                return;
+            }
+        }
+        for frame_pass in &frame_passes {
+            if !frame_pass.encode(&command_buffer, &pass_descriptor, &scene_properties) {
+                frames.release();
+                self.log(LogLevel::Warn, "Dropped frame: failed to create a render encoder.");
+                return;
+            }
+        }
+        if let Some(pick_pass) = &pick_pass {
+            self.encode_pick_pass(&command_buffer, pick_pass, &scene_properties);
+        }
+        if let Some(output) = &ray_traced {
+            self.encode_drawable_copy(&command_buffer, output, &drawable_texture);
+        }
+        #[cfg(feature = "metalfx")]
+        if let Some(upscaled) = &upscaled {
+            self.encode_upscaling(&command_buffer, upscaled, &drawable_texture);
+        }
+        if rate_mapped.is_some() {
+            self.encode_rasterization_rate_resolve(&command_buffer, &drawable_texture);
+        }
+        #[cfg(feature = "core-image")]
+        self.encode_image_filters(&command_buffer, &drawable_texture);
+        if let Some(text_draw) = &text_draw {
+            if !text_draw.encode(&command_buffer, &drawable_texture) {
+                self.log(LogLevel::Warn, "Failed to create a render encoder for the text.");
+            }
+        }
+        #[cfg(feature = "egui")]
+        if let Some(egui_draw) = &egui_draw {
+            if !egui_draw.encode(&command_buffer, &drawable_texture) {
+                self.log(LogLevel::Warn, "Failed to create a render encoder for the ui.");
+            }
+        }
+        #[cfg(feature = "imgui")]
+        if let Some(imgui_draw) = &imgui_draw {
+            if !imgui_draw.encode(&command_buffer, &drawable_texture) {
+                self.log(LogLevel::Warn, "Failed to create a render encoder for imgui.");
+            }
+        }
+        if let Some(overlay_draw) = &overlay_draw {
+            if !overlay_draw.encode(&command_buffer, &drawable_texture) {
+                self.log(LogLevel::Warn, "Failed to create a render encoder for the overlay.");
+            }
+        }
+        #[cfg(feature = "recording")]
+        self.record_frame();
+        self.encode_screenshots(&command_buffer, &drawable_texture);
+        self.encode_output_surface(&command_buffer, &drawable_texture);
+        self.encode_event_signals(&command_buffer);
+
+        // free the slot of the frame once the gpu is done with it. the block only holds on
+        // to the semaphore and the handler, never to the delegate
+        let frames_in_flight = frames.frames_in_flight.clone();
+        let frame_complete_handler = self.ivars().frame_complete_handler.borrow().clone();
+        let gpu_frame_time = self.ivars().gpu_frame_time.clone();
+        let command_buffer_faults = self.ivars().command_buffer_faults.clone();
+        let completed_handler = RcBlock::new(
+            move |command_buffer: NonNull<ProtocolObject<dyn MTLCommandBuffer>>| {
+                let command_buffer = unsafe { command_buffer.as_ref() };
+                if command_buffer.status() == MTLCommandBufferStatus::Error {
+                    let fault = CommandBufferFault::new(command_buffer);
+                    command_buffer_faults.lock().unwrap().push(fault);
+                }
+                let (start, end) =
+                    unsafe { (command_buffer.GPUStartTime(), command_buffer.GPUEndTime()) };
+                gpu_frame_time.store((end - start).to_bits(), Ordering::Relaxed);
+                frames_in_flight.signal();
+                if let Some(frame_complete_handler) = &frame_complete_handler {
+                    frame_complete_handler();
+                }
+            },
+        );
+        // metal copies the block, so it can be released after this frame
+        unsafe {
+            command_buffer.addCompletedHandler(&*completed_handler as *const _ as *mut _)
+        };
+
+        // schedule the command buffer for display and commit, when presenting with the core
+        // animation transaction the drawable can only be presented once the work is scheduled.
+        // an offscreen frame stays in its texture
+        let minimum_frame_duration = self.ivars().minimum_frame_duration.get();
+        if let Some(current_drawable) = &current_drawable {
+            self.track_presentation(ProtocolObject::from_ref(&**current_drawable));
+        }
+        match &current_drawable {
+            None => command_buffer.commit(),
+            Some(current_drawable) if surface.presents_with_transaction() => {
+                let drawable: &ProtocolObject<dyn MTLDrawable> =
+                    ProtocolObject::from_ref(&**current_drawable);
+                command_buffer.commit();
+                command_buffer.waitUntilScheduled();
+                match minimum_frame_duration {
+                    Some(duration) => unsafe { drawable.presentAfterMinimumDuration(duration) },
+                    None => drawable.present(),
+                }
+            }
+            Some(current_drawable) => {
+                let drawable: &ProtocolObject<dyn MTLDrawable> =
+                    ProtocolObject::from_ref(&**current_drawable);
+                match minimum_frame_duration {
+                    Some(duration) => unsafe {
+                        command_buffer.presentDrawable_afterMinimumDuration(drawable, duration)
+                    },
+                    None => command_buffer.presentDrawable(drawable),
+                }
+                command_buffer.commit();
+            }
+        }
+        self.ivars()
+            .last_command_buffer
+            .replace(Some(command_buffer.clone()));
+        let mut frame_stats = self.ivars().frame_stats.get();
+        frame_stats.cpu_frame_time = Some(recording_start.elapsed().as_secs_f64());
+        (frame_stats.draws, frame_stats.culled_draws) = counts;
+        self.ivars().frame_stats.set(frame_stats);
+
+        if gpu_timer_sampling {
+            if let Some(gpu_timer) = self.ivars().gpu_timer.borrow_mut().as_mut() {
+                gpu_timer.pending = Some(command_buffer);
+            }
+        }
+    }
+
+    fn drawable_size_changed(&self, size: NSSize) {
+        // the view and `resize` may both report the same change
+        if self.ivars().drawable_size.get() == size {
+            return;
+        }
+        let _span = tracing::debug_span!("resize", width = size.width, height = size.height)
+            .entered();
+        self.log(
+            LogLevel::Debug,
+            &format!("Drawable size changed to {}x{}.", size.width, size.height),
+        );
+
+        // a custom viewport and scissor rect keep covering the same part of the view
+        let previous_size = self.ivars().drawable_size.replace(size);
+        if previous_size.width > 0. && previous_size.height > 0. {
+            let scale_x = size.width / previous_size.width;
+            let scale_y = size.height / previous_size.height;
+            if let Some(viewport) = self.ivars().viewport.get() {
+                self.ivars().viewport.set(Some(MTLViewport {
+                    originX: viewport.originX * scale_x,
+                    originY: viewport.originY * scale_y,
+                    width: viewport.width * scale_x,
+                    height: viewport.height * scale_y,
+                    ..viewport
+                }));
+            }
+            if let Some(scissor) = self.ivars().scissor.get() {
+                let scale = |value: usize, scale: f64| (value as f64 * scale).round() as usize;
+                self.ivars().scissor.set(Some(MTLScissorRect {
+                    x: scale(scissor.x, scale_x),
+                    y: scale(scissor.y, scale_y),
+                    width: scale(scissor.width, scale_x),
+                    height: scale(scissor.height, scale_y),
+                }));
+            }
+        }
+
+        // the projection follows the aspect ratio of the viewport every frame, only the
+        // resources of the application sized like the drawable need to be recreated
+        self.set_needs_redraw();
+        if let Some(resize_handler) = self.ivars().resize_handler.borrow().as_ref() {
+            resize_handler(self, size);
+        }
+    }
+
+    // creates the device, the view and the default pipeline. the errors are logged as well,
+    // so an application can fall back or tell the user before giving up
+    pub fn init(&self) -> Result<(), RendererError> {
+        let _span = tracing::info_span!("init", backend = ?self.ivars().backend.get()).entered();
+        // a second init would add another view to the window
+        if self.ivars().surface.get().is_some() {
+            return Err(RendererError::AlreadyInitialized);
+        }
+        let window = self.ivars().window.get();
+        let shares_device = self.ivars().device.borrow().is_some();
+        // get the selected device, unless the renderer shares the one of another window
+        if !shares_device {
+            let device = self
+                .select_device()
+                .ok_or(RendererError::DeviceUnavailable)
+                .inspect_err(|error| self.log(LogLevel::Error, &format!("{error}.")))?;
+            self.ivars().device.replace(Some(device));
+        }
+        let device = &self.device();
+        self.log(LogLevel::Info, &format!("Using device {}.", device.name()));
+        // move to another device when this one is unplugged
+        if let Some(device_observer) = DeviceObserver::new() {
+            let _ = self.ivars().device_observer.set(device_observer);
+        }
+
+        // preload the pipelines compiled by earlier runs, a shared device comes with the
+        // archive of its renderer
+        if !shares_device {
+            if let Some(path) = default_archive_path(device) {
+                self.open_pipeline_archive(path);
+            }
+        }
+
+        // create the command queue, a shared device comes with the queue of its renderer
+        if self.ivars().command_queue.borrow().is_none() {
+            let command_queue = device
+                .newCommandQueue()
+                .ok_or(RendererError::CommandQueueUnavailable)
+                .inspect_err(|error| self.log(LogLevel::Error, &format!("{error}.")))?;
+            self.ivars().command_queue.replace(Some(command_queue));
+        }
+
+        // create the view the frames are drawn into
+        let surface = match (window, self.ivars().backend.get()) {
+            #[cfg(target_os = "macos")]
+            (Some(window), Backend::MetalKit) => Surface::new_metal_kit(window, device, self),
+            // metalkit views are appkit views, on ios the frames always go to a layer
+            (Some(window), _) => Surface::new_metal_layer(window, device, self),
+            // a headless renderer starts with the size given to `new_headless`
+            (None, _) => Surface::new_offscreen(self.ivars().drawable_size.get(), device),
+        };
+
+        self.load_shaders()?;
+
+        // configure the window
+        if let Some(window) = window {
+            let view = self.host_view().unwrap();
+            unsafe { view.addSubview(surface.view().unwrap()) };
+            surface.set_frame(view.bounds());
+
+            //window.setContentView(Some(&mtk_view));
+            // the window of a window handle belongs to the host, it's left as it is
+            if self.ivars().host_view.get().is_none() {
+                window.configure(self.ivars().min_content_size.get());
+            }
+
+            // stop drawing while the window is hidden, and follow its live resizes
+            #[cfg_attr(target_os = "ios", allow(unused_mut))]
+            let mut visibility_observer = VisibilityObserver::new(self, window);
+            #[cfg(target_os = "macos")]
+            visibility_observer.observe_live_resize(self, window);
+            let _ = self.ivars().visibility_observer.set(visibility_observer);
+        }
+
+        // initialize the delegate state
+        self.ivars().drawable_size.set(surface.drawable_size());
+        self.ivars()
+            .surface
+            .set(surface)
+            .map_err(|_| RendererError::AlreadyInitialized)
+            .inspect_err(|error| self.log(LogLevel::Error, &format!("{error}.")))?;
+        let frames = FrameAllocator::new(device)
+            .inspect_err(|error| self.log(LogLevel::Error, &format!("{error}.")))?;
+        self.ivars().frames.replace(Some(Rc::new(frames)));
+
+        // create the default pipeline, a placeholder while the shaders compile, then configure
+        // the drawable
+        let descriptor = self.pipeline_descriptor("vertex_main", "fragment_main");
+        self.try_render_pipeline_state_for(&descriptor)?;
+        self.set_pixel_format(self.ivars().pixel_format.get());
+
+        // upload the default geometry
+        self.set_vertices(&triangle_vertices());
+        Ok(())
+    }
+
+    // loads the shaders precompiled by build.rs on the device, or compiles them when they
+    // weren't
+    fn load_shaders(&self) -> Result<(), RendererError> {
+        #[cfg(precompiled_shaders)]
+        {
+            let library = Self::new_library_with_data(&self.device(), PRECOMPILED_LIBRARY)
+                .map_err(|error| RendererError::ShaderCompilation(error.0))
+                .inspect_err(|error| self.log(LogLevel::Error, &format!("{error}.")))?;
+            self.ivars().library.replace(Some(library));
+        }
+        // on a metal thread, the frames are drawn with a placeholder pipeline until it's done
+        #[cfg(not(precompiled_shaders))]
+        {
+            let source = self.ivars().shader_source.borrow().clone();
+            let shader_options = self.ivars().shader_options.borrow().clone();
+            let library = self.compile_library_async(&source, &shader_options);
+            self.ivars().pending_library.replace(Some(library));
+        }
+        Ok(())
+    }
+
+    fn compile_library(
+        &self,
+        device: &ProtocolObject<dyn MTLDevice>,
+        shader_options: &ShaderOptions,
+    ) -> Result<Retained<ProtocolObject<dyn MTLLibrary>>, RendererError> {
+        let compile_options = compile_options(shader_options);
+        let source = NSString::from_str(&self.ivars().shader_source.borrow());
+        // the error is also set when the compilation succeeds with warnings
+        let mut error: Option<Retained<NSError>> = None;
+        let library: Option<Retained<ProtocolObject<dyn MTLLibrary>>> = unsafe {
+            msg_send_id![
+                device,
+                newLibraryWithSource: &*source,
+                options: &*compile_options,
+                error: &mut error
+            ]
+        };
+        let description = error.map(|error| error.localizedDescription().to_string());
+        self.log_compilation(library.is_some(), description.clone());
+        library.ok_or_else(|| RendererError::ShaderCompilation(description.unwrap_or_default()))
+    }
+
+    fn log_compilation(&self, compiled: bool, description: Option<String>) {
+        match (compiled, description) {
+            (true, Some(warnings)) => {
+                let message = format!("Shader compilation warnings: {warnings}");
+                self.log(LogLevel::Warn, &message)
+            }
+            (true, None) => self.log(LogLevel::Info, "Compiled shader library."),
+            (false, description) => {
+                let description = description.unwrap_or_default();
+                let message = format!("Shader compilation failed: {description}");
+                self.log(LogLevel::Error, &message)
+            }
+        }
+    }
+
+    fn new_library_with_data(
+        device: &ProtocolObject<dyn MTLDevice>,
+        data: &[u8],
+    ) -> Result<Retained<ProtocolObject<dyn MTLLibrary>>, LibraryError> {
+        // a null destructor makes dispatch copy the bytes
+        let null = core::ptr::null();
+        let dispatch_data =
+            unsafe { dispatch_data_create(data.as_ptr().cast(), data.len(), null, null) };
+        let mut error: Option<Retained<NSError>> = None;
+        let library: Option<Retained<ProtocolObject<dyn MTLLibrary>>> = unsafe {
+            msg_send_id![device, newLibraryWithData: dispatch_data, error: &mut error]
+        };
+        unsafe { dispatch_release(dispatch_data) };
+        library.ok_or_else(|| {
+            let description = error.map(|error| error.localizedDescription().to_string());
+            LibraryError(description.unwrap_or_default())
+        })
+    }
+
+    // replaces the shader library with the .metallib at `path`, built with `xcrun metal` and
+    // `xcrun metallib`. the pipelines are recreated from the new library
+    pub fn load_library(&self, path: impl AsRef<Path>) -> Result<(), LibraryError> {
+        let path = path.as_ref().to_string_lossy();
+        let url = unsafe { NSURL::fileURLWithPath(&NSString::from_str(&path)) };
+        let library = unsafe { self.device().newLibraryWithURL_error(&url) }
+            .map_err(|error| LibraryError(error.localizedDescription().to_string()))?;
+        self.set_library(library);
+        Ok(())
+    }
+
+    // same as `load_library` for a .metallib already in memory, e.g. from `include_bytes!`
+    pub fn load_library_data(&self, data: &[u8]) -> Result<(), LibraryError> {
+        let library = Self::new_library_with_data(&self.device(), data)?;
+        self.set_library(library);
+        Ok(())
+    }
+
+    fn set_library(&self, library: Retained<ProtocolObject<dyn MTLLibrary>>) {
+        self.log(LogLevel::Info, "Loaded shader library.");
+        self.ivars().pending_library.replace(None);
+        self.ivars().library.replace(Some(library));
+        self.clear_pipeline_caches();
+        self.pipeline_state();
+    }
+
+    // drops the pipelines built from the previous shader library, they're recreated on use
+    fn clear_pipeline_caches(&self) {
+        self.ivars().pipeline_cache.borrow_mut().clear();
+        self.ivars().pipeline_bindings.borrow_mut().clear();
+        self.ivars().mesh_pipeline_states.borrow_mut().clear();
+        self.ivars().tile_pipeline_states.borrow_mut().clear();
+        self.ivars().compute_pipeline_states.borrow_mut().clear();
+        self.ivars().deferred.borrow_mut().clear_pipeline_states();
+        self.ivars().overlay.borrow_mut().clear_pipeline_state();
+        self.ivars().text.borrow_mut().clear_pipeline_state();
+        #[cfg(feature = "egui")]
+        self.ivars().egui.borrow_mut().clear_pipeline_state();
+        #[cfg(feature = "imgui")]
+        if let Some(imgui) = self.ivars().imgui.borrow_mut().as_mut() {
+            imgui.clear_pipeline_state();
+        }
+        self.restart_pending_pipelines();
+    }
+
+    // the shader library, waiting for the one `init` started compiling. when it failed to
+    // compile it's the library of the placeholders, the functions looked up in it are missing
+    // and the pipelines built from it fail with a `RendererError`
+    fn library(&self) -> Result<Retained<ProtocolObject<dyn MTLLibrary>>, RendererError> {
+        let pending_library = self.ivars().pending_library.take();
+        if let Some(pending_library) = pending_library {
+            // the error was logged with the diagnostics of the compiler
+            let _ = self.install_library(pending_library.wait(), pending_library.warnings());
+        }
+        let library = self.ivars().library.borrow().clone();
+        match library {
+            Some(library) => Ok(library),
+            None => self.placeholder_library(),
+        }
+    }
+
+    // recompiles the shader library, the pipelines are recreated from the new library. the
+    // library in use is kept when the compilation fails
+    pub fn set_shader_options(&self, shader_options: &ShaderOptions) -> Result<(), RendererError> {
+        let library = self.compile_library(&self.device(), shader_options)?;
+        self.ivars().pending_library.replace(None);
+        self.ivars().shader_options.replace(shader_options.clone());
+        self.ivars().library.replace(Some(library));
+        self.clear_pipeline_caches();
+        let descriptor = self.pipeline_descriptor("vertex_main", "fragment_main");
+        self.try_render_pipeline_state_for(&descriptor)?;
+        Ok(())
+    }
+
+    // recompiles the shaders from `path` whenever the file changes on disk, e.g. the
+    // triangle.metal of the source tree. the compilation runs on a metal thread and the new
+    // library is swapped in at the start of the next frame, a failed one keeps the old library
+    pub fn watch_shaders(&self, path: impl AsRef<Path>) -> notify::Result<()> {
+        // editors often save by replacing the file, so the directory is watched instead
+        let path = fs::canonicalize(path)?;
+        let changed = Arc::new(AtomicBool::new(false));
+        let mut watcher = notify::recommended_watcher({
+            let path = path.clone();
+            let changed = changed.clone();
+            move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let modified = event.kind.is_create() || event.kind.is_modify();
+                if modified && event.paths.contains(&path) {
+                    changed.store(true, Ordering::Release);
+                }
+            }
+        })?;
+        let directory = path.parent().unwrap_or(&path);
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+
+        let message = format!("Watching {} for shader changes.", path.display());
+        self.log(LogLevel::Info, &message);
+        self.ivars().shader_watcher.replace(Some(ShaderWatcher {
+            path,
+            _watcher: watcher,
+            changed,
+            compiling: None,
+        }));
+        Ok(())
+    }
+
+    // swaps the shaders for the ones in `path`, like a .metal file dropped onto the window. the
+    // file is compiled for the next frame and watched from then on like with `watch_shaders`,
+    // the current library stays when it fails to compile
+    pub fn load_shader_file(&self, path: impl AsRef<Path>) -> notify::Result<()> {
+        self.watch_shaders(path)?;
+        if let Some(shader_watcher) = self.ivars().shader_watcher.borrow().as_ref() {
+            shader_watcher.changed.store(true, Ordering::Release);
+        }
+        self.set_needs_redraw();
+        Ok(())
+    }
+
+    // swaps in the library of a finished hot reload and starts the next one once the watched
+    // shaders changed again, only one compilation runs at a time
+    fn reload_shaders(&self) {
+        let mut shader_watcher = self.ivars().shader_watcher.borrow_mut();
+        let Some(shader_watcher) = shader_watcher.as_mut() else {
+            return;
+        };
+
+        if let Some(compilation) = &shader_watcher.compiling {
+            let Some(result) = compilation.library.try_take() else {
+                return;
+            };
+            match result {
+                Ok(library) => {
+                    self.log_compilation(true, compilation.library.warnings());
+                    let source = compilation.source.clone();
+                    self.ivars().shader_source.replace(source);
+                    self.ivars().library.replace(Some(library));
+                    self.clear_pipeline_caches();
+                }
+                Err(error) => self.log_compilation(false, Some(error.0)),
+            }
+            shader_watcher.compiling = None;
+        }
+
+        if !shader_watcher.changed.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let source = match fs::read_to_string(&shader_watcher.path) {
+            Ok(source) => source,
+            Err(error) => {
+                let message = format!("Failed to read {}: {error}", shader_watcher.path.display());
+                self.log(LogLevel::Warn, &message);
+                return;
+            }
+        };
+        self.log(LogLevel::Info, "Shader source changed, recompiling.");
+
+        let shader_options = self.ivars().shader_options.borrow().clone();
+        let library = self.compile_library_async(&source, &shader_options);
+        shader_watcher.compiling = Some(ShaderCompilation { source, library });
+    }
+
+    // the metal descriptor of `descriptor` with the functions of `library`, looking them up in
+    // the pipeline archive before compiling them
+    pub(crate) fn mtl_pipeline_descriptor(
+        &self,
+        library: &ProtocolObject<dyn MTLLibrary>,
+        descriptor: &PipelineDescriptor,
+    ) -> Retained<MTLRenderPipelineDescriptor> {
+        let pipeline_descriptor = MTLRenderPipelineDescriptor::new();
+
+        let color_attachment = unsafe {
+            pipeline_descriptor
+                .colorAttachments()
+                .objectAtIndexedSubscript(0)
+        };
+        color_attachment.setPixelFormat(descriptor.color_format);
+        descriptor.blend_mode.configure(&color_attachment);
+        if let Some(depth_format) = descriptor.depth_format {
+            pipeline_descriptor.setDepthAttachmentPixelFormat(depth_format);
+            if depth_format == MTLPixelFormat::Depth32Float_Stencil8 {
+                pipeline_descriptor.setStencilAttachmentPixelFormat(depth_format);
+            }
+        }
+        pipeline_descriptor.setRasterSampleCount(descriptor.sample_count);
+        pipeline_descriptor.setSupportIndirectCommandBuffers(descriptor.indirect_commands);
+        pipeline_descriptor
+            .setVertexDescriptor(descriptor.vertex_layout.mtl_vertex_descriptor().as_deref());
+
+        // configure the vertex shader
+        let vertex_function =
+            library.newFunctionWithName(&NSString::from_str(&descriptor.vertex_function));
+        pipeline_descriptor.setVertexFunction(vertex_function.as_deref());
+
+        // configure the fragment shader
+        let fragment_function =
+            library.newFunctionWithName(&NSString::from_str(&descriptor.fragment_function));
+        pipeline_descriptor.setFragmentFunction(fragment_function.as_deref());
+
+        if let Some(pipeline_archive) = self.ivars().pipeline_archive.borrow().as_ref() {
+            pipeline_archive.attach(&pipeline_descriptor);
+        }
+        pipeline_descriptor
+    }
+
+    pub(crate) fn create_pipeline_state(
+        &self,
+        descriptor: &PipelineDescriptor,
+    ) -> Retained<ProtocolObject<dyn MTLRenderPipelineState>> {
+        self.library()
+            .and_then(|library| self.create_pipeline_state_with_library(&library, descriptor))
+            .expect("Failed to create a pipeline state.")
+    }
+
+    pub(crate) fn create_pipeline_state_with_library(
+        &self,
+        library: &ProtocolObject<dyn MTLLibrary>,
+        descriptor: &PipelineDescriptor,
+    ) -> Result<Retained<ProtocolObject<dyn MTLRenderPipelineState>>, RendererError> {
+        let device = &self.device();
+        let pipeline_descriptor = self.mtl_pipeline_descriptor(library, descriptor);
+
+        // create the pipeline state with the arguments of its shaders, debug builds also check
+        // it reads the vertex buffers with the layouts the renderer writes them with
+        let options = if cfg!(debug_assertions) {
+            MTLPipelineOption::ArgumentInfo | MTLPipelineOption::BufferTypeInfo
+        } else {
+            MTLPipelineOption::ArgumentInfo
+        };
+        let mut reflection: Option<Retained<MTLRenderPipelineReflection>> = None;
+        let pipeline_state: Result<_, Retained<NSError>> = unsafe {
+            msg_send_id![
+                device,
+                newRenderPipelineStateWithDescriptor: &*pipeline_descriptor,
+                options: options,
+                reflection: &mut reflection,
+                error: _
+            ]
+        };
+        if let Some(reflection) = reflection.as_ref().filter(|_| cfg!(debug_assertions)) {
+            validate_vertex_buffer_layouts(reflection)
+                .inspect_err(|error| self.log(LogLevel::Error, &error.to_string()))
+                .map_err(|error| RendererError::PipelineCreation(error.to_string()))?;
+        }
+        let pipeline_state = pipeline_state
+            .map_err(|error| {
+                RendererError::PipelineCreation(error.localizedDescription().to_string())
+            })
+            .inspect_err(|error| self.log(LogLevel::Error, &error.to_string()))?;
+        if let Some(reflection) = &reflection {
+            self.ivars()
+                .pipeline_bindings
+                .borrow_mut()
+                .insert(&pipeline_state, reflection);
+        }
+        if let Some(pipeline_archive) = self.ivars().pipeline_archive.borrow().as_ref() {
+            if let Err(error) = pipeline_archive.add(&pipeline_descriptor) {
+                self.log(LogLevel::Warn, &format!("{error}."));
+            }
+        }
+        Ok(pipeline_state)
+    }
+
+    // replaces the geometry drawn from the next frame on. the vertex buffer is only reallocated
+    // when it's too small, otherwise the vertices are copied into it once the gpu is done with it
+    pub fn set_vertices(&self, vertices: &[VertexInput]) {
+        let mut vertex_buffer = self.ivars().vertex_buffer.borrow_mut();
+        match vertex_buffer.as_mut() {
+            Some(buffer) => {
+                // the previous frame may still read the buffer, wait for it before overwriting.
+                // frames in flight keep a replaced buffer alive, so growing needs no wait
+                if buffer.buffer().length() >= core::mem::size_of_val(vertices) {
+                    if let Some(command_buffer) = self.ivars().last_command_buffer.take() {
+                        unsafe { command_buffer.waitUntilCompleted() };
+                    }
+                }
+                buffer.update(vertices);
+            }
+            None => *vertex_buffer = Some(self.create_gpu_buffer(vertices)),
+        }
+        self.set_needs_redraw();
+    }
+
+    // records a draw of the geometry set with `set_vertices`, spinning about the origin
+    pub fn draw_geometry(&self, render_pass: &mut RenderPass) {
+        let vertex_buffer = self.ivars().vertex_buffer.borrow();
+        if let Some(vertex_buffer) = &*vertex_buffer {
+            render_pass.draw(
+                &self.render_pipeline_state("vertex_spinning", "fragment_main"),
+                vertex_buffer.buffer(),
+                self.ivars().primitive_type.get(),
+                0..vertex_buffer.len(),
+            );
+        }
+    }
+
+    // draws a polyline through `points`, `width` pixels wide before the camera zoom. the
+    // vertices are generated every call, so it's meant for a handful of lines like gizmos and grids
+    pub fn draw_lines(
+        &self,
+        render_pass: &mut RenderPass,
+        points: &[(f32, f32, f32)],
+        width: f32,
+        color: MTLPackedFloat3,
+    ) {
+        let vertices = thick_line_vertices(points, width, color, self.drawable_size());
+        if vertices.is_empty() {
+            return;
+        }
+        render_pass.draw(
+            &self.pipeline_state(),
+            &self.frame_buffer(&vertices),
+            PrimitiveType::TriangleStrip,
+            0..vertices.len(),
+        );
+    }
+
+    // a pass into the drawable with the culling, fill mode, viewport, scissor rect and depth
+    // test of the renderer
+    fn frame_render_pass(&self, drawable_size: NSSize) -> RenderPass {
+        RenderPass {
+            cull_mode: self.ivars().cull_mode.get(),
+            front_facing: self.ivars().front_facing.get(),
+            fill_mode: self.ivars().fill_mode.get(),
+            // always set, otherwise a draw with its own viewport would leave it changed for
+            // the draws after it
+            viewport: Some(self.viewport(drawable_size)),
+            scissor: self.scissor_rect(drawable_size),
+            depth_stencil_state: self.ivars().depth_stencil_state.borrow().clone(),
+            ..Default::default()
+        }
+    }
+
+    // replaces the default per-frame draws with the ones recorded by `render_callback`
+    pub fn set_render_callback(&self, render_callback: impl Fn(&Self, &mut RenderPass) + 'static) {
+        self.ivars().render_callback.replace(Some(Box::new(render_callback)));
+    }
+
+    // registers a handler called once the gpu finished executing each frame. it runs on a
+    // metal completion thread rather than the main thread, so it can't touch the renderer
+    pub fn on_frame_complete(&self, frame_complete_handler: impl Fn() + Send + Sync + 'static) {
+        self.ivars()
+            .frame_complete_handler
+            .replace(Some(Arc::new(frame_complete_handler)));
+    }
+
+    // the pipeline drawing the geometry with `vertex_main` and `fragment_main`
+    pub fn pipeline_state(&self) -> Retained<ProtocolObject<dyn MTLRenderPipelineState>> {
+        self.render_pipeline_state("vertex_main", "fragment_main")
+    }
+
+    // returns the pipeline for a pair of shader functions with the formats of the view, it's
+    // created on first use
+    pub fn render_pipeline_state(
+        &self,
+        vertex_function: &str,
+        fragment_function: &str,
+    ) -> Retained<ProtocolObject<dyn MTLRenderPipelineState>> {
+        let descriptor = self.pipeline_descriptor(vertex_function, fragment_function);
+        self.render_pipeline_state_for(&descriptor)
+    }
+
+    // returns the pipeline built from `descriptor` from the cache of the renderer, it's
+    // created on first use and rebuilt after the shaders changed
+    pub fn render_pipeline_state_for(
+        &self,
+        descriptor: &PipelineDescriptor,
+    ) -> Retained<ProtocolObject<dyn MTLRenderPipelineState>> {
+        self.try_render_pipeline_state_for(descriptor)
+            .expect("Failed to create a pipeline state.")
+    }
+
+    // same as `render_pipeline_state_for`, returning the error when metal can't build the
+    // pipeline
+    pub fn try_render_pipeline_state_for(
+        &self,
+        descriptor: &PipelineDescriptor,
+    ) -> Result<Retained<ProtocolObject<dyn MTLRenderPipelineState>>, RendererError> {
+        if let Some(pipeline_state) = self.ivars().pipeline_cache.borrow().get(descriptor) {
+            return Ok(pipeline_state);
+        }
+        if !self.finish_compilations(descriptor) {
+            return self.placeholder_pipeline_state(descriptor);
+        }
+        if let Some(pipeline_state) = self.ivars().pipeline_cache.borrow().get(descriptor) {
+            return Ok(pipeline_state);
+        }
+
+        let library = self.library()?;
+        let pipeline_state = self.create_pipeline_state_with_library(&library, descriptor)?;
+        self.ivars()
+            .pipeline_cache
+            .borrow_mut()
+            .insert(descriptor.clone(), pipeline_state.clone());
+        Ok(pipeline_state)
+    }
+
+    pub fn device(&self) -> Retained<ProtocolObject<dyn MTLDevice>> {
+        self.ivars().device.borrow().clone().unwrap()
+    }
+
+    pub(crate) fn command_queue(&self) -> Retained<ProtocolObject<dyn MTLCommandQueue>> {
+        self.ivars().command_queue.borrow().clone().unwrap()
+    }
+
+    fn frames(&self) -> Rc<FrameAllocator> {
+        self.ivars().frames.borrow().clone().unwrap()
+    }
+
+    // creates an argument table for the argument struct `function_name` takes at `buffer_index`
+    pub fn create_argument_table(
+        &self,
+        function_name: &str,
+        buffer_index: usize,
+    ) -> ArgumentTable {
+        let library = self.library().expect("Failed to load the shader library.");
+        let function = library
+            .newFunctionWithName(&NSString::from_str(function_name))
+            .expect("Failed to find the argument buffer function.");
+        ArgumentTable::new(&self.device(), &function, buffer_index)
+    }
+
+    pub fn create_vertex_buffer(&self, vertices: &[VertexInput]) -> Retained<ProtocolObject<dyn MTLBuffer>> {
+        new_shared_buffer(&self.device(), vertices).expect("Failed to create a vertex buffer.")
+    }
+
+    // a buffer with `data` for the draws of the frame being recorded, like vertices generated
+    // every frame. its memory is recycled once the gpu completed the frame, so the buffer must
+    // not be kept for later frames
+    pub fn frame_buffer<T: Copy>(&self, data: &[T]) -> Retained<ProtocolObject<dyn MTLBuffer>> {
+        self.frames().allocate(data)
+    }
+
+    // uploads `data` once, for geometry or instances that change rarely or not at all
+    pub fn create_gpu_buffer<T: Copy>(&self, data: &[T]) -> GpuBuffer<T> {
+        GpuBuffer::new(&self.device(), data)
+    }
+
+    pub fn primitive_type(&self) -> PrimitiveType {
+        self.ivars().primitive_type.get()
+    }
+
+    pub fn set_primitive_type(&self, primitive_type: PrimitiveType) {
+        self.ivars().primitive_type.set(primitive_type);
+    }
+
+    // the size of the drawable in pixels, the unit of viewports and scissor rects
+    pub fn drawable_size(&self) -> NSSize {
+        self.ivars().surface.get().unwrap().drawable_size()
+    }
+
+    // renders into a region of the drawable given in pixels, the depth range is mapped
+    // to `znear..zfar`. the region is scaled along when the drawable is resized
+    pub fn set_viewport(&self, x: f64, y: f64, width: f64, height: f64, znear: f64, zfar: f64) {
+        self.ivars().drawable_size.set(self.drawable_size());
+        self.ivars().viewport.set(Some(MTLViewport {
+            originX: x,
+            originY: y,
+            width,
+            height,
+            znear,
+            zfar,
+        }));
+    }
+
+    // discards the fragments outside of a region of the drawable given in pixels
+    pub fn set_scissor(&self, x: usize, y: usize, width: usize, height: usize) {
+        self.ivars().drawable_size.set(self.drawable_size());
+        self.ivars().scissor.set(Some(MTLScissorRect {
+            x,
+            y,
+            width,
+            height,
+        }));
+    }
+
+    // whether `set_viewport` replaced the default viewport covering the whole drawable
+    pub fn has_custom_viewport(&self) -> bool {
+        self.ivars().viewport.get().is_some()
+    }
+
+    // renders to the whole drawable again
+    pub fn reset_viewport(&self) {
+        self.ivars().viewport.set(None);
+        self.ivars().scissor.set(None);
+    }
+
+    fn viewport(&self, drawable_size: NSSize) -> MTLViewport {
+        self.ivars().viewport.get().unwrap_or(MTLViewport {
+            originX: 0.,
+            originY: 0.,
+            width: drawable_size.width,
+            height: drawable_size.height,
+            znear: 0.,
+            zfar: 1.,
+        })
+    }
+
+    // metal rejects scissor rects reaching outside of the render target, so clamp it to the
+    // drawable which may have shrunk since the rect was set
+    fn scissor_rect(&self, drawable_size: NSSize) -> Option<MTLScissorRect> {
+        let scissor = self.ivars().scissor.get()?;
+        let (drawable_width, drawable_height) =
+            (drawable_size.width as usize, drawable_size.height as usize);
+        let x = scissor.x.min(drawable_width);
+        let y = scissor.y.min(drawable_height);
+        Some(MTLScissorRect {
+            x,
+            y,
+            width: scissor.width.min(drawable_width - x),
+            height: scissor.height.min(drawable_height - y),
+        })
+    }
+
+    // the pixels per point of the screen the window is on, 1 for a headless renderer
+    pub fn scale_factor(&self) -> f64 {
+        self.ivars()
+            .window
+            .get()
+            .map_or(1., |window| window.scale_factor())
+    }
+
+    // converts a position in points relative to the top left corner of the view, like the
+    // cursor position of tao's events, to the coordinates of the geometry under it
+    pub fn world_from_screen(&self, x: f64, y: f64) -> (f32, f32) {
+        let scale_factor = self.scale_factor();
+        let viewport = self.viewport(self.drawable_size());
+        // metal's device coordinates point up while the window coordinates point down
+        let ndc_x = (x * scale_factor - viewport.originX) / viewport.width * 2. - 1.;
+        let ndc_y = 1. - (y * scale_factor - viewport.originY) / viewport.height * 2.;
+        // undo the camera, onto the z = 0 plane of 2d geometry
+        let aspect = (viewport.width / viewport.height) as f32;
+        let camera = self.ivars().camera.get();
+        camera.unproject((ndc_x as f32, ndc_y as f32), aspect)
+    }
+
+    // whether a point in world coordinates lies on one of the triangles of the geometry set
+    // with `set_vertices`, always false unless it's drawn as triangles
+    pub fn hit_test_triangle(&self, point: (f32, f32)) -> bool {
+        // the geometry spins with the scene time, undo the rotation of the latest frame
+        let frames = self.ivars().frames.borrow();
+        let time = frames.as_ref().map_or(0., |frames| frames.time.get());
+        let (sin, cos) = time.sin_cos();
+        let point = (point.0 * cos + point.1 * sin, point.1 * cos - point.0 * sin);
+
+        let vertex_buffer = self.ivars().vertex_buffer.borrow();
+        let Some(vertex_buffer) = &*vertex_buffer else {
+            return false;
+        };
+        let vertices = vertex_buffer.contents();
+        let hit = |triangle: &[VertexInput]| {
+            let [a, b, c] = [0, 1, 2].map(|i| (triangle[i].position.x, triangle[i].position.y));
+            triangle_contains(point, a, b, c)
+        };
+        match self.ivars().primitive_type.get() {
+            PrimitiveType::Triangle => vertices.chunks_exact(3).any(hit),
+            PrimitiveType::TriangleStrip => vertices.windows(3).any(hit),
+            _ => false,
+        }
+    }
+
+    pub fn info(&self) -> RendererInfo {
+        let surface = self.ivars().surface.get().unwrap();
+        let window = self.ivars().window.get();
+        RendererInfo {
+            device_name: self.device().name().to_string(),
+            color_pixel_format: surface.color_pixel_format(),
+            depth_stencil_pixel_format: surface.depth_stencil_pixel_format(),
+            sample_count: surface.sample_count(),
+            drawable_size: surface.drawable_size(),
+            max_frames_per_second: window.and_then(|window| window.max_frames_per_second()),
+        }
+    }
+
+    pub fn camera(&self) -> Camera {
+        self.ivars().camera.get()
+    }
+
+    pub fn set_camera(&self, camera: Camera) {
+        self.ivars().camera.set(camera);
+        self.set_needs_redraw();
+    }
+
+    pub fn cull_mode(&self) -> CullMode {
+        self.ivars().cull_mode.get()
+    }
+
+    // culling is off by default, so triangles show regardless of their winding
+    pub fn set_cull_mode(&self, cull_mode: CullMode) {
+        self.ivars().cull_mode.set(cull_mode);
+    }
+
+    pub fn front_facing(&self) -> Winding {
+        self.ivars().front_facing.get()
+    }
+
+    pub fn set_front_facing(&self, front_facing: Winding) {
+        self.ivars().front_facing.set(front_facing);
+    }
+
+    pub fn fill_mode(&self) -> FillMode {
+        self.ivars().fill_mode.get()
+    }
+
+    pub fn set_fill_mode(&self, fill_mode: FillMode) {
+        self.ivars().fill_mode.set(fill_mode);
+    }
+
+    pub fn has_gradient_background(&self) -> bool {
+        self.ivars().gradient.get().is_some()
+    }
+
+    pub fn set_background(&self, background: Background) {
+        match background {
+            Background::Solid {
+                color: [red, green, blue],
+            } => {
+                self.ivars().gradient.set(None);
+                self.set_clear_color(MTLClearColor {
+                    red,
+                    green,
+                    blue,
+                    alpha: 1.,
+                });
+            }
+            Background::Gradient { top, bottom, speed } => {
+                let gradient = GradientProperties { top, bottom, speed };
+                self.ivars().gradient.set(Some(gradient));
+                self.set_needs_redraw();
+            }
+        }
+    }
+
+    pub fn set_clear_color(&self, clear_color: MTLClearColor) {
+        self.ivars().surface.get().unwrap().set_clear_color(clear_color);
+        self.set_needs_redraw();
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.ivars().surface.get().unwrap().sample_count()
+    }
+
+    // multisampled antialiasing with `sample_count` samples per pixel, 1 turns it off.
+    // returns false and keeps the current sample count if the device doesn't support it
+    pub fn set_sample_count(&self, sample_count: usize) -> bool {
+        if !self.device().supportsTextureSampleCount(sample_count) {
+            let message = format!("Sample count {sample_count} is not supported.");
+            self.log(LogLevel::Warn, &message);
+            return false;
+        }
+        self.ivars().surface.get().unwrap().set_sample_count(sample_count);
+        // the pipelines are cached by sample count, build the default one right away
+        self.pipeline_state();
+        true
+    }
+
+    pub fn apply_config(&self, config: &RendererConfig) {
+        // a headless renderer keeps its size
+        if let Some(window) = self.ivars().window.get() {
+            let [width, height] = config.window_size;
+            window.set_content_frame(NSSize::new(width, height), config.window_position);
+        }
+
+        let [red, green, blue, alpha] = config.clear_color;
+        self.set_clear_color(MTLClearColor {
+            red,
+            green,
+            blue,
+            alpha,
+        });
+        self.set_color_space(config.color_space);
+        self.set_pixel_format(config.pixel_format);
+        // a config saved on another mac may ask for more samples than this gpu supports, fall
+        // back to the closest count below it
+        let device = self.device();
+        let sample_count = (1..=config.sample_count)
+            .rev()
+            .find(|&sample_count| device.supportsTextureSampleCount(sample_count))
+            .unwrap_or(1);
+        self.set_sample_count(sample_count);
+        self.set_fill_mode(config.fill_mode);
+        self.set_render_path(config.render_path);
+        self.set_preferred_frames_per_second(config.preferred_frames_per_second);
+        self.set_vsync(config.vsync);
+        self.set_maximum_drawable_count(config.maximum_drawable_count);
+        if config.transparent != self.is_transparent() {
+            self.set_transparent(config.transparent);
+        }
+        // the backend only takes effect when the config is applied before `init`
+        if config.backend != self.backend() {
+            self.set_backend(config.backend);
+        }
+    }
+
+    pub fn current_config(&self) -> RendererConfig {
+        let surface = self.ivars().surface.get().unwrap();
+        // a headless renderer saves the size of its frames
+        let (content_size, window_position) = match self.ivars().window.get() {
+            Some(window) => window.content_frame(),
+            None => (surface.drawable_size(), None),
+        };
+        let clear_color = surface.clear_color();
+        RendererConfig {
+            window_size: [content_size.width, content_size.height],
+            window_position,
+            clear_color: [
+                clear_color.red,
+                clear_color.green,
+                clear_color.blue,
+                clear_color.alpha,
+            ],
+            pixel_format: self.pixel_format(),
+            color_space: self.color_space(),
+            sample_count: surface.sample_count(),
+            fill_mode: self.ivars().fill_mode.get(),
+            render_path: self.render_path(),
+            backend: self.backend(),
+            preferred_frames_per_second: surface.preferred_frames_per_second(),
+            vsync: self.vsync(),
+            maximum_drawable_count: self.maximum_drawable_count(),
+            transparent: self.is_transparent(),
+        }
+    }
+
+    pub fn point_size(&self) -> f32 {
+        self.ivars().point_size.get()
+    }
+
+    // size in pixels of the rasterized points when drawing `PrimitiveType::Point`
+    pub fn set_point_size(&self, point_size: f32) {
+        self.ivars().point_size.set(point_size);
+    }
+
+    pub fn fixed_time(&self) -> Option<f32> {
+        self.ivars().fixed_time.get()
+    }
+
+    // freezes the time the shaders animate by at `fixed_time` seconds, so the same scene always
+    // renders the same frame. None lets it run again
+    pub fn set_fixed_time(&self, fixed_time: Option<f32>) {
+        self.ivars().fixed_time.set(fixed_time);
+    }
+
+    // synchronizes presentation with the core animation transaction of the window, so the
+    // contents stay in step with the window frame during a live resize instead of tearing.
+    // this blocks every frame until its commands are scheduled on the gpu, which costs
+    // throughput, so it's best enabled only for views that get resized a lot
+    pub fn set_presents_with_transaction(&self, presents_with_transaction: bool) {
+        let surface = self.ivars().surface.get().unwrap();
+        surface.set_presents_with_transaction(presents_with_transaction);
+    }
+
+    pub fn preferred_frames_per_second(&self) -> isize {
+        self.ivars().surface.get().unwrap().preferred_frames_per_second()
+    }
+
+    // caps the frame rate of continuous drawing, e.g. at 30 to save power. the screen's refresh
+    // rate is the upper limit, turn vsync off as well to run uncapped
+    pub fn set_preferred_frames_per_second(&self, frames_per_second: isize) {
+        let surface = self.ivars().surface.get().unwrap();
+        surface.set_preferred_frames_per_second(frames_per_second);
+    }
+
+    pub fn minimum_frame_duration(&self) -> Option<f64> {
+        self.ivars().minimum_frame_duration.get()
+    }
+
+    // keeps every frame on screen for at least `minimum_frame_duration` seconds, so a ProMotion
+    // display only refreshes as often as the content needs, e.g. 1/24 for video. it can change
+    // from frame to frame, say from the update callback, while the frame rate cap stays at the
+    // highest rate wanted. None shows frames with the next refresh. systems before macOS
+    // 10.15.4 can't pace single frames, there the frame rate is capped instead
+    pub fn set_minimum_frame_duration(&self, minimum_frame_duration: Option<f64>) {
+        let version = NSOperatingSystemVersion {
+            majorVersion: 10,
+            minorVersion: 15,
+            patchVersion: 4,
+        };
+        let supported =
+            unsafe { NSProcessInfo::processInfo().isOperatingSystemAtLeastVersion(version) };
+        if !supported {
+            if let Some(duration) = minimum_frame_duration {
+                self.set_preferred_frames_per_second((1. / duration).round() as isize);
+            }
+            return;
+        }
+        self.ivars().minimum_frame_duration.set(minimum_frame_duration);
+    }
+
+    // none for a headless renderer
+    fn metal_layer(&self) -> Option<Retained<CAMetalLayer>> {
+        self.ivars().surface.get().unwrap().metal_layer()
+    }
+
+    pub fn vsync(&self) -> bool {
+        #[cfg(target_os = "macos")]
+        return self
+            .metal_layer()
+            .is_some_and(|metal_layer| unsafe { metal_layer.displaySyncEnabled() });
+        // the layers of ios always wait for the refresh
+        #[cfg(target_os = "ios")]
+        self.metal_layer().is_some()
+    }
+
+    // waits for the next refresh of the screen to show a frame, on by default. only macos can
+    // turn it off
+    pub fn set_vsync(&self, vsync: bool) {
+        #[cfg(target_os = "macos")]
+        if let Some(metal_layer) = self.metal_layer() {
+            unsafe { metal_layer.setDisplaySyncEnabled(vsync) };
+        }
+        #[cfg(target_os = "ios")]
+        if !vsync {
+            self.log(LogLevel::Warn, "Vsync can't be turned off on iOS.");
+        }
+    }
+
+    // 1 for a headless renderer, which renders into a single texture
+    pub fn maximum_drawable_count(&self) -> usize {
+        self.metal_layer()
+            .map_or(1, |metal_layer| unsafe { metal_layer.maximumDrawableCount() })
+    }
+
+    // how many drawables the layer cycles through, core animation only allows 2 or 3.
+    // returns false and keeps the current count otherwise
+    pub fn set_maximum_drawable_count(&self, maximum_drawable_count: usize) -> bool {
+        let Some(metal_layer) = self.metal_layer() else {
+            return false;
+        };
+        if !(2..=3).contains(&maximum_drawable_count) {
+            let message =
+                format!("Maximum drawable count {maximum_drawable_count} is not supported.");
+            self.log(LogLevel::Warn, &message);
+            return false;
+        }
+        unsafe { metal_layer.setMaximumDrawableCount(maximum_drawable_count) };
+        true
+    }
+
+    // false for a headless renderer, whose frames keep their alpha anyway
+    pub fn is_transparent(&self) -> bool {
+        self.metal_layer()
+            .is_some_and(|metal_layer| !metal_layer.isOpaque())
+    }
+
+    // composites the frames over what's behind the window with their alpha, for overlays and
+    // huds. the window loses its background and the view clears to a transparent black, core
+    // animation takes the colors as premultiplied by their alpha. a gradient background still
+    // covers the whole view, and a solid one makes the clear color opaque again
+    pub fn set_transparent(&self, transparent: bool) {
+        let Some(metal_layer) = self.metal_layer() else {
+            return;
+        };
+        metal_layer.setOpaque(!transparent);
+        if let Some(window) = self.ivars().window.get() {
+            window.set_transparent(transparent);
+        }
+        let clear_color = if transparent {
+            MTLClearColor {
+                red: 0.,
+                green: 0.,
+                blue: 0.,
+                alpha: 0.,
+            }
+        } else {
+            let clear_color = self.ivars().surface.get().unwrap().clear_color();
+            MTLClearColor {
+                alpha: 1.,
+                ..clear_color
+            }
+        };
+        self.set_clear_color(clear_color);
+    }
+
+    pub fn is_benchmarking(&self) -> bool {
+        self.ivars().gpu_timer.borrow().is_some()
+    }
+
+    // enables gpu timestamp sampling of the render and compute passes, reported through
+    // `frame_stats`. returns false if the device can't sample timestamp counters
+    pub fn set_benchmark_mode(&self, enabled: bool) -> bool {
+        self.ivars().frame_stats.set(FrameStats::default());
+        if !enabled {
+            self.ivars().gpu_timer.replace(None);
+            return true;
+        }
+
+        let device = &self.device();
+        if !device.supportsCounterSampling(MTLCounterSamplingPoint::AtStageBoundary) {
+            self.log(LogLevel::Warn, "Counter sampling at stage boundaries is not supported.");
+            return false;
+        }
+
+        // find the timestamp counter set
+        let timestamp_counter_set = unsafe { device.counterSets() }.and_then(|counter_sets| {
+            counter_sets.iter_retained().find(|counter_set| unsafe {
+                counter_set.name().isEqualToString(MTLCommonCounterSetTimestamp)
+            })
+        });
+        let Some(timestamp_counter_set) = timestamp_counter_set else {
+            self.log(LogLevel::Warn, "Timestamp counters are not supported.");
+            return false;
+        };
+
+        // create a buffer for the start and end timestamps of the render and compute passes
+        let descriptor = unsafe { MTLCounterSampleBufferDescriptor::new() };
+        unsafe {
+            descriptor.setCounterSet(Some(&timestamp_counter_set));
+            descriptor.setStorageMode(MTLStorageMode::Shared);
+            descriptor.setSampleCount(4);
+        }
+        let sample_buffer =
+            match unsafe { device.newCounterSampleBufferWithDescriptor_error(&descriptor) } {
+                Ok(sample_buffer) => sample_buffer,
+                Err(error) => {
+                    let message = format!(
+                        "Failed to create a counter sample buffer: {}",
+                        error.localizedDescription()
+                    );
+                    self.log(LogLevel::Warn, &message);
+                    return false;
+                }
+            };
+
+        let (mut cpu_timestamp, mut gpu_timestamp) = (0, 0);
+        unsafe {
+            device.sampleTimestamps_gpuTimestamp(
+                NonNull::from(&mut cpu_timestamp),
+                NonNull::from(&mut gpu_timestamp),
+            )
+        };
+        self.ivars().gpu_timer.replace(Some(GpuTimer {
+            sample_buffer,
+            pending: None,
+            compute_sampled: false,
+            cpu_timestamp,
+            gpu_timestamp,
+        }));
+        true
+    }
+
+    pub fn frame_stats(&self) -> FrameStats {
+        let gpu_frame_time = f64::from_bits(self.ivars().gpu_frame_time.load(Ordering::Relaxed));
+        let (allocated_memory, memory_budget) = self.memory_usage();
+        let mut frame_stats = FrameStats {
+            gpu_frame_time: (gpu_frame_time > 0.).then_some(gpu_frame_time),
+            allocated_memory,
+            memory_budget,
+            ..self.ivars().frame_stats.get()
+        };
+        self.ivars()
+            .frame_pacing
+            .lock()
+            .unwrap()
+            .write_stats(&mut frame_stats);
+        frame_stats
+    }
+
+    // resolves the timestamps of a finished frame and attaches the sample buffer to the passes
+    // if it's not in use by the gpu anymore. returns whether this frame is sampled
+    fn prepare_gpu_timer(
+        &self,
+        pass_descriptor: &MTLRenderPassDescriptor,
+        compute_pass_descriptor: Option<&MTLComputePassDescriptor>,
+    ) -> bool {
+        let mut gpu_timer = self.ivars().gpu_timer.borrow_mut();
+        let Some(gpu_timer) = gpu_timer.as_mut() else {
+            return false;
+        };
+
+        if let Some(command_buffer) = &gpu_timer.pending {
+            if command_buffer.status() != MTLCommandBufferStatus::Completed {
+                // the previous measurement is still in flight, skip sampling this frame
+                unsafe {
+                    pass_descriptor
+                        .sampleBufferAttachments()
+                        .objectAtIndexedSubscript(0)
+                        .setSampleBuffer(None)
+                };
+                return false;
+            }
+            gpu_timer.pending = None;
+
+            let data = unsafe { gpu_timer.sample_buffer.resolveCounterRange(NSRange::new(0, 4)) };
+            if let Some(data) = data {
+                let timestamps: Vec<u64> = data
+                    .bytes()
+                    .chunks_exact(core::mem::size_of::<u64>())
+                    .map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+                    .collect();
+                if let [start, end, compute_start, compute_end] = timestamps[..] {
+                    // correlate the cpu and gpu clocks to get the length of a gpu tick
+                    let device = &self.device();
+                    let (mut cpu_timestamp, mut gpu_timestamp) = (0, 0);
+                    unsafe {
+                        device.sampleTimestamps_gpuTimestamp(
+                            NonNull::from(&mut cpu_timestamp),
+                            NonNull::from(&mut gpu_timestamp),
+                        )
+                    };
+                    let cpu_elapsed = cpu_timestamp.saturating_sub(gpu_timer.cpu_timestamp);
+                    let gpu_elapsed = gpu_timestamp.saturating_sub(gpu_timer.gpu_timestamp);
+                    let nanoseconds_per_tick = if cpu_elapsed > 0 && gpu_elapsed > 0 {
+                        cpu_elapsed as f64 / gpu_elapsed as f64
+                    } else {
+                        1.
+                    };
+
+                    // failed samples are reported as `u64::MAX`
+                    let elapsed = |start: u64, end: u64| {
+                        (start < end && end != u64::MAX)
+                            .then(|| (end - start) as f64 * nanoseconds_per_tick * 1e-9)
+                    };
+                    let mut frame_stats = self.ivars().frame_stats.get();
+                    if let Some(gpu_pass_time) = elapsed(start, end) {
+                        frame_stats.gpu_pass_time = Some(gpu_pass_time);
+                    }
+                    frame_stats.gpu_compute_time = gpu_timer
+                        .compute_sampled
+                        .then(|| elapsed(compute_start, compute_end))
+                        .flatten();
+                    self.ivars().frame_stats.set(frame_stats);
+                }
+            }
+        }
+
+        let attachment = unsafe {
+            pass_descriptor
+                .sampleBufferAttachments()
+                .objectAtIndexedSubscript(0)
+        };
+        attachment.setSampleBuffer(Some(&gpu_timer.sample_buffer));
+        unsafe {
+            attachment.setStartOfVertexSampleIndex(0);
+            attachment.setEndOfFragmentSampleIndex(1);
+        }
+        if let Some(compute_pass_descriptor) = compute_pass_descriptor {
+            unsafe {
+                let attachment = compute_pass_descriptor
+                    .sampleBufferAttachments()
+                    .objectAtIndexedSubscript(0);
+                attachment.setSampleBuffer(Some(&gpu_timer.sample_buffer));
+                attachment.setStartOfEncoderSampleIndex(2);
+                attachment.setEndOfEncoderSampleIndex(3);
+            }
+        }
+        gpu_timer.compute_sampled = compute_pass_descriptor.is_some();
+        true
+    }
+
+    // writes a gpu trace of the next frame to `path`, the `.gputrace` can be opened in Xcode
+    pub fn capture_next_frame(&self, path: impl AsRef<Path>) -> Result<(), CaptureError> {
+        self.trigger_gpu_capture(path, 1)
+    }
+
+    // writes a gpu trace of the next `frame_count` frames to `path`, at least one. it works
+    // in release builds launched outside of Xcode as long as capturing is enabled
+    pub fn trigger_gpu_capture(
+        &self,
+        path: impl AsRef<Path>,
+        frame_count: usize,
+    ) -> Result<(), CaptureError> {
+        let capture_manager = unsafe { MTLCaptureManager::sharedCaptureManager() };
+        if !capture_manager.supportsDestination(MTLCaptureDestination::GPUTraceDocument) {
+            return Err(CaptureError::NotEnabled);
+        }
+        if capture_manager.isCapturing() || self.ivars().capture_frames_remaining.get() > 0 {
+            return Err(CaptureError::AlreadyCapturing);
+        }
+
+        self.ivars().capture_path.replace(Some(path.as_ref().to_owned()));
+        self.ivars().capture_frames_remaining.set(frame_count.max(1));
+        Ok(())
+    }
+
+    // begins the capture requested by `trigger_gpu_capture` and counts the frame towards it,
+    // returns whether the capture ends with this frame
+    fn advance_capture(&self) -> bool {
+        let frames_remaining = self.ivars().capture_frames_remaining.get();
+        if frames_remaining == 0 {
+            return false;
+        }
+        if let Some(path) = self.ivars().capture_path.take() {
+            if !self.start_capture(&path) {
+                self.ivars().capture_frames_remaining.set(0);
+                return false;
+            }
+        }
+        self.ivars().capture_frames_remaining.set(frames_remaining - 1);
+        frames_remaining == 1
+    }
+
+    // starts capturing all the work of the device into `path`, returns whether it's running
+    fn start_capture(&self, path: &Path) -> bool {
+        let device = &self.device();
+        let descriptor = MTLCaptureDescriptor::new();
+        let output_url =
+            unsafe { NSURL::fileURLWithPath(&NSString::from_str(&path.to_string_lossy())) };
+        // the capture object is untyped, it can be a device, a command queue or a capture scope
+        let capture_object: Retained<AnyObject> = unsafe { Retained::cast(device.clone()) };
+        unsafe { descriptor.setCaptureObject(Some(&capture_object)) };
+        descriptor.setDestination(MTLCaptureDestination::GPUTraceDocument);
+        descriptor.setOutputURL(Some(&output_url));
+
+        let capture_manager = unsafe { MTLCaptureManager::sharedCaptureManager() };
+        match capture_manager.startCaptureWithDescriptor_error(&descriptor) {
+            Ok(()) => {
+                let frame_count = self.ivars().capture_frames_remaining.get();
+                let plural = if frame_count == 1 { "" } else { "s" };
+                let message =
+                    format!("Capturing {frame_count} frame{plural} to {}.", path.display());
+                self.log(LogLevel::Info, &message);
+                true
+            }
+            Err(error) => {
+                let message = format!("Failed to start capture: {}", error.localizedDescription());
+                self.log(LogLevel::Error, &message);
+                false
+            }
+        }
+    }
+
+    // installs a hook that receives the renderer's diagnostics, next to the events they're
+    // emitted as for a `tracing` subscriber
+    pub fn set_logger(&self, logger: impl Fn(LogLevel, &str) + 'static) {
+        self.ivars().logger.replace(Some(Box::new(logger)));
+    }
+
+    fn log(&self, level: LogLevel, message: &str) {
+        match level {
+            LogLevel::Error => tracing::error!("{message}"),
+            LogLevel::Warn => tracing::warn!("{message}"),
+            LogLevel::Info => tracing::info!("{message}"),
+            LogLevel::Debug => tracing::debug!("{message}"),
+        }
+        if let Some(logger) = self.ivars().logger.borrow().as_ref() {
+            logger(level, message);
+        }
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.ivars().pixel_format.get()
+    }
+
+    pub fn depth_format(&self) -> Option<DepthFormat> {
+        self.ivars().depth_format.get()
+    }
+
+    // gives the view a depth buffer, cleared to the far plane every frame, or removes it with
+    // None. draws pass the depth test with `LessEqual`, so the ones at the same depth still
+    // cover each other in the order they're recorded
+    pub fn set_depth_format(&self, depth_format: Option<DepthFormat>) {
+        let pixel_format = depth_format.map_or(MTLPixelFormat::Invalid, |depth_format| {
+            depth_format.mtl_pixel_format()
+        });
+        let surface = self.ivars().surface.get().unwrap();
+        surface.set_depth_stencil_pixel_format(pixel_format);
+
+        let depth_stencil_state = depth_format.map(|_| {
+            let descriptor = unsafe { MTLDepthStencilDescriptor::new() };
+            descriptor.setDepthCompareFunction(MTLCompareFunction::LessEqual);
+            descriptor.setDepthWriteEnabled(true);
+            self.device()
+                .newDepthStencilStateWithDescriptor(&descriptor)
+                .expect("Failed to create a depth stencil state.")
+        });
+        self.ivars().depth_stencil_state.replace(depth_stencil_state);
+        self.ivars().depth_format.set(depth_format);
+        // the pipelines are cached by depth format, build the default one right away
+        self.pipeline_state();
+    }
+
+    // switches the drawable to `pixel_format` and rebuilds the pipeline to match it.
+    // the shaders output linear color, so in the sRGB format the hardware does the
+    // gamma encoding on write and vertex colors are not gamma corrected twice
+    pub fn set_pixel_format(&self, pixel_format: PixelFormat) {
+        self.set_drawable_format(pixel_format, self.color_space());
+    }
+
+    // tags the drawables with `color_space`, without one the view shows the colors unmatched in
+    // the gamut of the screen
+    pub fn set_color_space(&self, color_space: ColorSpace) {
+        self.set_drawable_format(self.pixel_format(), color_space);
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.ivars().color_space.get()
+    }
+
+    fn configure_drawable(&self, pixel_format: PixelFormat, color_space: ColorSpace) {
+        let surface = self.ivars().surface.get().unwrap();
+        unsafe {
+            let cg_color_space = CGColorSpaceCreateWithName(color_space.cg_name(pixel_format));
+            surface.set_color_pixel_format(pixel_format.mtl_pixel_format(), cg_color_space);
+            CGColorSpaceRelease(cg_color_space);
+        }
+        // hdr output needs the layer to ask for the extended range
+        if let Some(metal_layer) = surface.metal_layer() {
+            unsafe { metal_layer.setWantsExtendedDynamicRangeContent(pixel_format.is_hdr()) };
+        }
+    }
+
+    // the headroom of the screen the window is on, the frames in `PixelFormat::Rgba16Float` can
+    // use colors up to `current` and the shaders get it as `SceneProperties::edr_headroom`
+    pub fn edr_headroom(&self) -> EdrHeadroom {
+        self.ivars()
+            .window
+            .get()
+            .map_or_else(EdrHeadroom::default, |window| window.edr_headroom())
+    }
+
+    // the smallest size in points the window content can be resized to
+    pub fn set_min_content_size(&self, min_content_size: NSSize) {
+        self.ivars().min_content_size.set(min_content_size);
+        if let Some(window) = self.ivars().window.get() {
+            window.set_min_content_size(min_content_size);
+        }
+    }
+
+    // a hidden window stays paused in either mode until it's shown again
+    pub fn set_redraw_mode(&self, redraw_mode: RedrawMode) {
+        self.ivars().redraw_mode.set(redraw_mode);
+        self.update_paused();
+    }
+
+    pub fn redraw_mode(&self) -> RedrawMode {
+        match self.ivars().surface.get().unwrap() {
+            // frames are only drawn by `render_frame`
+            Surface::Offscreen(_) => RedrawMode::OnDemand,
+            _ => self.ivars().redraw_mode.get(),
+        }
+    }
+
+    // asks a view drawing on demand for a frame with the next refresh of the display, for
+    // changes that don't come with an event of the window. the renderer calls it when what it
+    // draws changes, like the camera, the background, the drawable size or a pipeline that was
+    // drawn with a placeholder. several calls before the frame still draw it once
+    pub fn set_needs_redraw(&self) {
+        let needed = self.ivars().needs_redraw.replace(true);
+        if !needed && self.ivars().redraw_mode.get() == RedrawMode::OnDemand {
+            self.update_paused();
+        }
+    }
+
+    // stops the display link of the view while it draws on demand and nothing changed, or while
+    // it's hidden
+    pub(crate) fn update_paused(&self) {
+        let on_demand = self.ivars().redraw_mode.get() == RedrawMode::OnDemand;
+        let paused = (on_demand && !self.ivars().needs_redraw.get()) || self.ivars().occluded.get();
+        if let Some(surface) = self.ivars().surface.get() {
+            surface.set_paused(paused);
+        }
+    }
+
+    // renders a frame right away when the view draws on demand, meant to be called from
+    // tao's `RedrawRequested` after `Window::request_redraw`. a headless renderer always draws
+    // on demand
+    pub fn redraw(&self) {
+        if self.redraw_mode() == RedrawMode::OnDemand {
+            match self.ivars().surface.get().unwrap() {
+                #[cfg(target_os = "macos")]
+                Surface::MetalKit(mtk_view) => unsafe { mtk_view.draw() },
+                Surface::MetalLayer(_) | Surface::Offscreen(_) => self.render_frame(),
+            }
+        }
+    }
+
+    // called with the new drawable size in pixels after the view was resized, to recreate
+    // resources that have to match the drawable like offscreen render targets
+    pub fn on_resize(&self, resize_handler: impl Fn(&Self, NSSize) + 'static) {
+        self.ivars().resize_handler.replace(Some(Box::new(resize_handler)));
+    }
+
+    // the view the view of the renderer is added to, none for a headless renderer
+    fn host_view(&self) -> Option<Retained<NativeView>> {
+        match self.ivars().host_view.get() {
+            Some(host_view) => Some(host_view.clone()),
+            None => self.ivars().window.get()?.content_view(),
+        }
+    }
+
+    // keeps the view sized to the window content, or to the view of a window handle, and its
+    // drawable to the pixels it covers. the view of the metal layer backend on macos follows on
+    // its own, the metalkit one needs this on every resize and change of the scale factor
+    pub fn resize(&self) {
+        let surface = self.ivars().surface.get().unwrap();
+        let Some(host_view) = self.host_view() else {
+            return;
+        };
+        if let Some(size) = surface.set_frame(host_view.bounds()) {
+            self.drawable_size_changed(size);
+            self.draw_live_resize_frame();
+        }
+    }
+
+    // renders with the device and command queue of `renderer`, so the windows of an application
+    // can use each other's buffers and textures and their frames go to the gpu in one queue.
+    // only before `init`, `renderer` has to be initialized already
+    pub fn share_device(&self, renderer: &MetalRenderer) {
+        if self.ivars().surface.get().is_some() {
+            self.log(LogLevel::Warn, "The device can only be shared before init.");
+            return;
+        }
+        if self.ivars().device.borrow().is_some() {
+            self.log(LogLevel::Warn, "The renderer already shares a device.");
+            return;
+        }
+        self.ivars().device.replace(Some(renderer.device()));
+        self.ivars()
+            .command_queue
+            .replace(Some(renderer.command_queue()));
+        let pipeline_archive = renderer.ivars().pipeline_archive.borrow().clone();
+        self.ivars().pipeline_archive.replace(pipeline_archive);
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.ivars().backend.get()
+    }
+
+    // picks how frames are presented, only before `init` creates the view
+    pub fn set_backend(&self, backend: Backend) {
+        if self.ivars().surface.get().is_some() {
+            let message = format!("Backend {backend:?} can only be set before init.");
+            self.log(LogLevel::Warn, &message);
+            return;
+        }
+        self.ivars().backend.set(backend);
+    }
+
+    pub fn new(tao_window: &Window) -> Result<Retained<Self>, RendererError> {
+        let window = retained_window(tao_window)?;
+        Ok(Self::with_window(Some(window)))
+    }
+
+    // a renderer drawing into the view of any window that provides a handle, like the windows
+    // of winit or of a native ui
+    pub fn from_window_handle(
+        window: &impl HasWindowHandle,
+    ) -> Result<Retained<Self>, RendererError> {
+        let handle = window
+            .window_handle()
+            .map_err(|_| RendererError::WindowUnavailable)?;
+        // SAFETY: the handle is borrowed from the window, so its view is alive
+        unsafe { Self::from_raw_window_handle(handle.as_raw()) }
+    }
+
+    // a renderer drawing into the view of `handle`, which has to be `RawWindowHandle::AppKit`
+    // on macos and `RawWindowHandle::UiKit` on ios. the view of the renderer is added to it and
+    // sized to its bounds by `resize`, the view doesn't have to be the content view of its
+    // window. the window of the view is left as the host set it up
+    //
+    // SAFETY: the view pointer of `handle` has to point to a live NSView or UIView for the
+    // duration of the call. the renderer retains the view and its window, so the host may
+    // release them afterwards, and it keeps drawing into the view until it's dropped
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn from_raw_window_handle(
+        handle: RawWindowHandle,
+    ) -> Result<Retained<Self>, RendererError> {
+        let (view, window) = unsafe { handle_view(handle) }?;
+        let renderer = Self::with_window(Some(window));
+        let _ = renderer.ivars().host_view.set(view);
+        Ok(renderer)
+    }
+
+    // a renderer drawing into `window`, or offscreen without one
+    pub(crate) fn with_window(window: Option<Retained<NativeWindow>>) -> Retained<Self> {
+        let mtm = MainThreadMarker::new().unwrap();
+        let this = mtm.alloc();
+
+        // initialize the delegate state
+        let this = this.set_ivars(AppState {
+            logger: RefCell::default(),
+            device: RefCell::default(),
+            command_queue: RefCell::default(),
+            library: RefCell::default(),
+            pending_library: RefCell::default(),
+            placeholder_library: RefCell::default(),
+            placeholder_pipelines: RefCell::default(),
+            pending_pipelines: RefCell::default(),
+            shader_source: RefCell::new(include_str!("triangle.metal").to_owned()),
+            shader_options: RefCell::default(),
+            shader_watcher: RefCell::default(),
+            fullscreen_shader: RefCell::default(),
+            pipeline_cache: RefCell::default(),
+            pipeline_bindings: RefCell::default(),
+            mesh_pipeline_states: RefCell::default(),
+            tile_pipeline_states: RefCell::default(),
+            pipeline_archive: RefCell::default(),
+            compute_pipeline_states: RefCell::default(),
+            pixel_format: Cell::new(PixelFormat::Bgra8Unorm),
+            color_space: Cell::default(),
+            depth_format: Cell::default(),
+            depth_stencil_state: RefCell::default(),
+            depth_stencil_states: RefCell::default(),
+            primitive_type: Cell::new(PrimitiveType::Triangle),
+            cull_mode: Cell::default(),
+            front_facing: Cell::default(),
+            fill_mode: Cell::default(),
+            gradient: Cell::default(),
+            viewport: Cell::default(),
+            scissor: Cell::default(),
+            point_size: Cell::new(1.),
+            fixed_time: Cell::default(),
+            camera: Cell::default(),
+            gpu_timer: RefCell::default(),
+            frame_stats: Cell::default(),
+            frame_pacing: SharedFramePacing::default(),
+            gpu_frame_time: Arc::default(),
+            command_buffer_faults: Arc::default(),
+            enhanced_command_buffer_errors: Cell::new(cfg!(debug_assertions)),
+            gpu_recoveries: Cell::new(0),
+            last_gpu_recovery: Cell::default(),
+            capture_path: RefCell::default(),
+            capture_frames_remaining: Cell::new(0),
+            vertex_buffer: RefCell::default(),
+            last_command_buffer: RefCell::default(),
+            frames: RefCell::default(),
+            min_content_size: Cell::new(NSSize::new(64., 64.)),
+            render_callback: RefCell::default(),
+            render_graph_callback: RefCell::default(),
+            graph_textures: RefCell::default(),
+            graph_allocator: RefCell::default(),
+            purgeable: RefCell::default(),
+            memory_pressure: Cell::default(),
+            memory_pressure_source: MemoryPressureSource::new(),
+            over_memory_budget: Cell::new(false),
+            compute_callback: RefCell::default(),
+            ray_tracing: RefCell::default(),
+            shadows: RefCell::default(),
+            lights: RefCell::default(),
+            shadow_callback: RefCell::default(),
+            skybox: RefCell::default(),
+            deferred: RefCell::default(),
+            frame_events: RefCell::default(),
+            upload_queue: RefCell::default(),
+            render_commands: RefCell::default(),
+            output_surface: RefCell::default(),
+            #[cfg(feature = "metalfx")]
+            upscaling: RefCell::default(),
+            #[cfg(feature = "core-image")]
+            image_filters: RefCell::default(),
+            post_process: RefCell::default(),
+            rasterization_rate: RefCell::default(),
+            picking: RefCell::default(),
+            pending_screenshots: RefCell::default(),
+            overlay: RefCell::default(),
+            text: RefCell::default(),
+            #[cfg(feature = "egui")]
+            egui: RefCell::default(),
+            #[cfg(feature = "egui")]
+            egui_callback: RefCell::default(),
+            #[cfg(feature = "imgui")]
+            imgui: RefCell::default(),
+            #[cfg(feature = "imgui")]
+            imgui_callback: RefCell::default(),
+            #[cfg(feature = "recording")]
+            recording: RefCell::default(),
+            #[cfg(feature = "audio")]
+            audio: RefCell::default(),
+            input: RefCell::default(),
+            cursor_mode: Cell::default(),
+            update_callback: RefCell::default(),
+            last_update: Cell::new(None),
+            fixed_update_callback: RefCell::default(),
+            time: Cell::default(),
+            frame_complete_handler: RefCell::default(),
+            resize_handler: RefCell::default(),
+            label: RefCell::default(),
+            drawable_format_handler: RefCell::default(),
+            drawable_size: Cell::new(NSSize::new(0., 0.)),
+            backend: Cell::default(),
+            device_selector: RefCell::default(),
+            device_observer: OnceCell::new(),
+            device_change_handler: RefCell::default(),
+            redraw_mode: Cell::new(RedrawMode::Continuous),
+            needs_redraw: Cell::new(false),
+            occluded: Cell::new(false),
+            smooth_live_resize: Cell::new(true),
+            live_resize: Cell::default(),
+            visibility_observer: OnceCell::new(),
+            minimum_frame_duration: Cell::default(),
+            window: window.map(OnceCell::from).unwrap_or_default(),
+            host_view: OnceCell::new(),
+            surface: OnceCell::new(),
+        });
+
+        unsafe { msg_send_id![super(this), init] }
+    }
+}
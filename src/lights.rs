@@ -0,0 +1,307 @@
+// the point, spot and extra directional lights of a scene besides the directional light of the
+// shadows. they're copied into a buffer every frame and a kernel lists the ones reaching each
+// tile of the screen ahead of the draws, so `fragment_pbr` only shades a fragment with the
+// lights of its tile rather than looping over all of them
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSSize;
+use objc2_metal::{MTLBuffer, MTLDevice, MTLRenderCommandEncoder, MTLResourceOptions};
+
+use crate::{
+    shadow::normalize, ComputePass, DirectionalLight, MetalRenderer, RenderPass, RenderPath,
+};
+
+type Buffer = Retained<ProtocolObject<dyn MTLBuffer>>;
+
+// the fragment buffer indices of the lights and the lists of their tiles in `fragment_pbr`
+const LIGHTS_INDEX: usize = 10;
+const TILE_LIGHTS_INDEX: usize = 11;
+
+// the width and height of a tile in pixels
+const LIGHT_TILE_SIZE: usize = 16;
+
+// `MAX_LIGHTS_PER_TILE` in triangle.metal, the lights past it are left out of the tile
+const MAX_LIGHTS_PER_TILE: usize = 64;
+
+// the kinds of `LightData` in triangle.metal
+const DIRECTIONAL: u32 = 0;
+const POINT: u32 = 1;
+const SPOT: u32 = 2;
+
+// a light shining in all directions from a point, fading out with the square of the distance
+// and reaching no farther than its range
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    // scales the color, a white light of 1 lights a white surface facing it from 1 away to 1
+    pub intensity: f32,
+    pub range: f32,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        PointLight {
+            position: [0.; 3],
+            color: [1., 1., 1.],
+            intensity: 1.,
+            range: 5.,
+        }
+    }
+}
+
+// a point light shining in a cone, at its full intensity inside the inner angle and fading
+// out toward the outer one. the angles are in radians from the direction of the cone
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpotLight {
+    pub position: [f32; 3],
+    // the direction the light travels in, it doesn't have to be normalized
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        SpotLight {
+            position: [0., 2., 0.],
+            direction: [0., -1., 0.],
+            color: [1., 1., 1.],
+            intensity: 1.,
+            range: 5.,
+            inner_angle: 0.3,
+            outer_angle: 0.5,
+        }
+    }
+}
+
+// a light of `set_lights`. the directional ones light every tile, without shadows or an
+// ambient share, those only come with the light of `set_directional_light`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Light {
+    Directional(DirectionalLight),
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+// a light in the buffer of the frame, `LightData` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct LightData {
+    // the range in w, 0 for directional lights
+    position: [f32; 4],
+    // scaled by the intensity, the cosine of the inner angle of spot lights in w
+    color: [f32; 4],
+    // normalized, the cosine of the outer angle of spot lights in w
+    direction: [f32; 4],
+    kind: u32,
+    // the float4s align the shader's struct to 16 bytes
+    _padding: [u32; 3],
+}
+
+impl From<&Light> for LightData {
+    fn from(light: &Light) -> Self {
+        let scaled = |[red, green, blue]: [f32; 3], intensity: f32, w: f32| {
+            [red * intensity, green * intensity, blue * intensity, w]
+        };
+        let (position, color, direction, kind) = match *light {
+            Light::Directional(light) => {
+                let [x, y, z] = normalize(light.direction);
+                let color = scaled(light.color, 1., 0.);
+                ([0.; 4], color, [x, y, z, 0.], DIRECTIONAL)
+            }
+            Light::Point(light) => {
+                let [x, y, z] = light.position;
+                let color = scaled(light.color, light.intensity, 0.);
+                ([x, y, z, light.range], color, [0.; 4], POINT)
+            }
+            Light::Spot(light) => {
+                let [x, y, z] = light.position;
+                let inner = light.inner_angle.cos();
+                let color = scaled(light.color, light.intensity, inner);
+                let [dx, dy, dz] = normalize(light.direction);
+                let outer = light.outer_angle.max(light.inner_angle).cos();
+                ([x, y, z, light.range], color, [dx, dy, dz, outer], SPOT)
+            }
+        };
+        LightData {
+            position,
+            color,
+            direction,
+            kind,
+            _padding: [0; 3],
+        }
+    }
+}
+
+// the parameters of `cull_lights`, `LightCulling` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct LightCulling {
+    // the viewport the lights are projected into, the origin in xy and the size in zw
+    viewport: [f32; 4],
+    light_count: u32,
+    _padding: [u32; 3],
+}
+
+#[derive(Default)]
+pub(crate) struct LightState {
+    lights: Vec<Light>,
+    // the lists of the lights of the tiles, written by `cull_lights` every frame
+    tile_lights: Option<Buffer>,
+}
+
+impl LightState {
+    // drops the lists, they're created again with the next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.tile_lights = None;
+    }
+}
+
+// how the frame is split into the tiles its lights are culled in
+pub(crate) struct LightTiles {
+    size: usize,
+    columns: usize,
+    rows: usize,
+}
+
+impl LightTiles {
+    // for the scene properties of the frame
+    pub(crate) fn grid(&self) -> [u32; 3] {
+        [self.size as u32, self.columns as u32, self.rows as u32]
+    }
+}
+
+// the lights of a frame and the lists of the ones reaching each tile, bound to the fragment
+// functions of its pass
+#[derive(Clone)]
+pub(crate) struct CulledLights {
+    lights: Buffer,
+    tile_lights: Buffer,
+}
+
+impl RenderPass {
+    pub(crate) fn encode_lights(&self, encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>) {
+        if let Some(culled_lights) = &self.culled_lights {
+            unsafe {
+                encoder.setFragmentBuffer_offset_atIndex(
+                    Some(&culled_lights.lights),
+                    0,
+                    LIGHTS_INDEX,
+                );
+                encoder.setFragmentBuffer_offset_atIndex(
+                    Some(&culled_lights.tile_lights),
+                    0,
+                    TILE_LIGHTS_INDEX,
+                );
+            }
+        }
+    }
+}
+
+impl MetalRenderer {
+    // lights the draws shaded with `fragment_pbr` in the forward path besides the directional
+    // light, which keeps the shadows and the ambient light. a tile shades no more than 64 of
+    // them, the ones past it in `lights` are left out. the passes of render targets, render
+    // graphs and the deferred path aren't lit by them
+    pub fn set_lights(&self, lights: Vec<Light>) {
+        self.ivars().lights.borrow_mut().lights = lights;
+    }
+
+    pub fn lights(&self) -> Vec<Light> {
+        self.ivars().lights.borrow().lights.clone()
+    }
+
+    // the tiles of a frame of `render_size`, none when it has no lights to cull
+    pub(crate) fn prepare_light_tiles(
+        &self,
+        render_size: NSSize,
+        rate_mapped: bool,
+    ) -> Option<LightTiles> {
+        if self.ivars().lights.borrow().lights.is_empty()
+            || self.ivars().render_graph_callback.borrow().is_some()
+            || self.render_path() == RenderPath::Deferred
+            || self.is_ray_tracing()
+            || self.has_fullscreen_shader()
+        {
+            return None;
+        }
+        let (width, height) = (render_size.width as usize, render_size.height as usize);
+        // the fragments of a frame at varying rates aren't where the tiles are on the screen,
+        // a single tile covers it all
+        let size = if rate_mapped {
+            width.max(height).max(1)
+        } else {
+            LIGHT_TILE_SIZE
+        };
+        Some(LightTiles {
+            size,
+            columns: width.div_ceil(size).max(1),
+            rows: height.div_ceil(size).max(1),
+        })
+    }
+
+    // copies the lights into the frame and records the dispatch of `cull_lights` listing the
+    // ones of each tile, one thread per tile
+    pub(crate) fn record_light_culling(
+        &self,
+        compute_pass: &mut ComputePass,
+        light_tiles: &LightTiles,
+        render_size: NSSize,
+    ) -> CulledLights {
+        let light_data: Vec<LightData> = {
+            let lights = self.ivars().lights.borrow();
+            lights.lights.iter().map(LightData::from).collect()
+        };
+        let viewport = self.viewport(render_size);
+        let culling = LightCulling {
+            viewport: [
+                viewport.originX as f32,
+                viewport.originY as f32,
+                viewport.width as f32,
+                viewport.height as f32,
+            ],
+            light_count: light_data.len() as u32,
+            _padding: [0; 3],
+        };
+        let lights = self.frame_buffer(&light_data);
+        let culling = self.frame_buffer(&[culling]);
+
+        // a count and the indices of the lights per tile, only the gpu reads and writes them
+        let length = light_tiles.columns
+            * light_tiles.rows
+            * (MAX_LIGHTS_PER_TILE + 1)
+            * core::mem::size_of::<u32>();
+        let tile_lights = {
+            let mut state = self.ivars().lights.borrow_mut();
+            match state.tile_lights.take() {
+                Some(tile_lights) if tile_lights.length() >= length => tile_lights,
+                _ => self
+                    .device()
+                    .newBufferWithLength_options(
+                        length,
+                        MTLResourceOptions::MTLResourceStorageModePrivate,
+                    )
+                    .expect("Failed to create a buffer for the lights of the tiles."),
+            }
+        };
+        self.ivars().lights.borrow_mut().tile_lights = Some(tile_lights.clone());
+
+        compute_pass
+            .push_debug_group("Light culling")
+            .dispatch(
+                &self.compute_pipeline_state("cull_lights"),
+                (light_tiles.columns, light_tiles.rows, 1),
+            )
+            .with_buffer(1, &lights)
+            .with_buffer(2, &tile_lights)
+            .with_buffer(3, &culling)
+            .pop_debug_group();
+        CulledLights {
+            lights,
+            tile_lights,
+        }
+    }
+}
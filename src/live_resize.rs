@@ -0,0 +1,88 @@
+// smooth live resizing of macos windows. while an edge of the window is dragged, the frames are
+// presented with the core animation transaction moving the edge, and the frame of every new
+// size is drawn right away instead of with the next display refresh, so the contents track the
+// window instead of lagging behind it
+use objc2::DeclaredClass;
+
+use crate::MetalRenderer;
+
+#[cfg(target_os = "macos")]
+use objc2_app_kit::{
+    NSWindowDidEndLiveResizeNotification, NSWindowWillStartLiveResizeNotification,
+};
+
+#[cfg(target_os = "macos")]
+use crate::{platform::NativeWindow, visibility::VisibilityObserver, LogLevel};
+
+#[cfg(target_os = "macos")]
+impl VisibilityObserver {
+    // follows the live resizes of `window`
+    pub(crate) fn observe_live_resize(&mut self, renderer: &MetalRenderer, window: &NativeWindow) {
+        self.observe(
+            renderer,
+            unsafe { NSWindowWillStartLiveResizeNotification },
+            Some(window),
+            MetalRenderer::start_live_resize,
+        );
+        self.observe(
+            renderer,
+            unsafe { NSWindowDidEndLiveResizeNotification },
+            Some(window),
+            MetalRenderer::end_live_resize,
+        );
+    }
+}
+
+impl MetalRenderer {
+    // whether live resizes of the window present with the transaction and draw every size as
+    // it comes, on by default. it makes every frame of a resize block until it's scheduled
+    pub fn set_smooth_live_resize(&self, smooth_live_resize: bool) {
+        self.ivars().smooth_live_resize.set(smooth_live_resize);
+    }
+
+    pub fn smooth_live_resize(&self) -> bool {
+        self.ivars().smooth_live_resize.get()
+    }
+
+    pub fn is_in_live_resize(&self) -> bool {
+        self.ivars().live_resize.get().is_some()
+    }
+
+    // draws the frame of the size the drawable was just given while the window is live
+    // resized, before appkit commits the transaction resizing the window
+    pub(crate) fn draw_live_resize_frame(&self) {
+        if self.is_in_live_resize() {
+            self.render_frame();
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl MetalRenderer {
+    fn start_live_resize(&self) {
+        let Some(surface) = self.ivars().surface.get() else {
+            return;
+        };
+        if !self.smooth_live_resize() || self.is_in_live_resize() {
+            return;
+        }
+        let presents_with_transaction = surface.presents_with_transaction();
+        self.ivars()
+            .live_resize
+            .set(Some(presents_with_transaction));
+        surface.set_presents_with_transaction(true);
+        self.log(LogLevel::Debug, "Started a live resize.");
+    }
+
+    fn end_live_resize(&self) {
+        let Some(presents_with_transaction) = self.ivars().live_resize.take() else {
+            return;
+        };
+        if let Some(surface) = self.ivars().surface.get() {
+            surface.set_presents_with_transaction(presents_with_transaction);
+        }
+        self.log(LogLevel::Debug, "Ended a live resize.");
+        // a view drawing on demand draws the final size once more, presented as usual
+        self.set_needs_redraw();
+    }
+}
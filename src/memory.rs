@@ -0,0 +1,206 @@
+use core::ffi::c_void;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use dispatch::ffi::{
+    dispatch_function_t, dispatch_get_main_queue, dispatch_object_s, dispatch_object_t,
+    dispatch_queue_t, dispatch_release, dispatch_resume, dispatch_set_context,
+    dispatch_set_finalizer_f,
+};
+use objc2::{
+    rc::{Retained, Weak},
+    runtime::ProtocolObject,
+    DeclaredClass,
+};
+use objc2_metal::{MTLDevice, MTLPurgeableState, MTLResource};
+
+use crate::{LogLevel, MetalRenderer};
+
+// the memory pressure source isn't in the bindings of the dispatch crate
+extern "C" {
+    static _dispatch_source_type_memorypressure: dispatch_object_s;
+    fn dispatch_source_create(
+        kind: *const dispatch_object_s,
+        handle: usize,
+        mask: usize,
+        queue: dispatch_queue_t,
+    ) -> dispatch_object_t;
+    fn dispatch_source_get_data(source: dispatch_object_t) -> usize;
+    fn dispatch_source_set_event_handler_f(source: dispatch_object_t, handler: dispatch_function_t);
+    fn dispatch_source_cancel(source: dispatch_object_t);
+}
+
+// the DISPATCH_MEMORYPRESSURE_* levels
+const PRESSURE_NORMAL: usize = 0x1;
+const PRESSURE_WARN: usize = 0x2;
+const PRESSURE_CRITICAL: usize = 0x4;
+
+// how short the system is on memory
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPressure {
+    #[default]
+    Normal,
+    // memory is getting scarce, caches that can be rebuilt should be given up
+    Warning,
+    // the system is about to terminate processes to free memory
+    Critical,
+}
+
+// the context of the event handler, freed by the finalizer of the source
+struct SourceContext {
+    source: dispatch_object_t,
+    level: Arc<AtomicUsize>,
+}
+
+extern "C" fn pressure_changed(context: *mut c_void) {
+    let context = unsafe { &*(context as *const SourceContext) };
+    let level = unsafe { dispatch_source_get_data(context.source) };
+    context.level.store(level, Ordering::Relaxed);
+}
+
+extern "C" fn free_context(context: *mut c_void) {
+    drop(unsafe { Box::from_raw(context as *mut SourceContext) });
+}
+
+// the memory pressure notifications of the os. they're delivered on the main queue and picked
+// up by the next frame, while no frames are drawn they wait
+pub(crate) struct MemoryPressureSource {
+    source: dispatch_object_t,
+    // the level of the last notification the renderer hasn't handled yet, 0 without one
+    level: Arc<AtomicUsize>,
+}
+
+impl MemoryPressureSource {
+    pub(crate) fn new() -> Self {
+        let level = Arc::new(AtomicUsize::new(0));
+        unsafe {
+            let source = dispatch_source_create(
+                &_dispatch_source_type_memorypressure,
+                0,
+                PRESSURE_NORMAL | PRESSURE_WARN | PRESSURE_CRITICAL,
+                dispatch_get_main_queue(),
+            );
+            assert!(
+                !source.is_null(),
+                "Failed to create a memory pressure source."
+            );
+            let context = Box::new(SourceContext {
+                source,
+                level: level.clone(),
+            });
+            dispatch_set_context(source, Box::into_raw(context) as *mut c_void);
+            dispatch_set_finalizer_f(source, free_context);
+            dispatch_source_set_event_handler_f(source, pressure_changed);
+            dispatch_resume(source);
+            Self { source, level }
+        }
+    }
+
+    // the pressure notified since the last call, if any
+    pub(crate) fn take(&self) -> Option<MemoryPressure> {
+        let level = self.level.swap(0, Ordering::Relaxed);
+        if level & PRESSURE_CRITICAL != 0 {
+            Some(MemoryPressure::Critical)
+        } else if level & PRESSURE_WARN != 0 {
+            Some(MemoryPressure::Warning)
+        } else if level & PRESSURE_NORMAL != 0 {
+            Some(MemoryPressure::Normal)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for MemoryPressureSource {
+    fn drop(&mut self) {
+        unsafe {
+            dispatch_source_cancel(self.source);
+            dispatch_release(self.source);
+        }
+    }
+}
+
+impl MetalRenderer {
+    // the bytes of all the resources of the device, and the bytes it can use before its
+    // performance suffers
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let device = self.device();
+        (
+            device.currentAllocatedSize(),
+            device.recommendedMaxWorkingSetSize() as usize,
+        )
+    }
+
+    pub fn memory_pressure(&self) -> MemoryPressure {
+        self.ivars().memory_pressure.get()
+    }
+
+    // lets the os reclaim `resource` under memory pressure, for caches whose contents can be
+    // rebuilt. the renderer only holds it weakly. before every use of the resource its owner
+    // has to call `reclaim_purgeable`
+    pub fn register_purgeable(&self, resource: &ProtocolObject<dyn MTLResource>) {
+        let resource = unsafe { Retained::retain(resource as *const _ as *mut _) }.unwrap();
+        let mut purgeable = self.ivars().purgeable.borrow_mut();
+        purgeable.retain(|resource| resource.load().is_some());
+        purgeable.push(Weak::from_retained(&resource));
+    }
+
+    // makes a resource given to `register_purgeable` usable by the gpu again. returns false
+    // when the os discarded its contents in the meantime, they have to be written again
+    pub fn reclaim_purgeable(&self, resource: &ProtocolObject<dyn MTLResource>) -> bool {
+        resource.setPurgeableState(MTLPurgeableState::NonVolatile) != MTLPurgeableState::Empty
+    }
+
+    // gives up what the renderer can do without at `pressure`: the textures the render graph
+    // keeps for the next frame and the heaps they leave empty. the registered purgeable
+    // resources become volatile on a warning and are discarded when it's critical. called for
+    // the notifications of the os, applications can call it to free memory on their own
+    pub fn handle_memory_pressure(&self, pressure: MemoryPressure) {
+        self.ivars().memory_pressure.set(pressure);
+        let state = match pressure {
+            MemoryPressure::Normal => return,
+            MemoryPressure::Warning => MTLPurgeableState::Volatile,
+            MemoryPressure::Critical => MTLPurgeableState::Empty,
+        };
+        self.ivars().graph_textures.borrow_mut().clear();
+        if let Some(graph_allocator) = self.ivars().graph_allocator.borrow().as_ref() {
+            graph_allocator.trim();
+        }
+        let mut purgeable = self.ivars().purgeable.borrow_mut();
+        purgeable.retain(|resource| resource.load().is_some());
+        for resource in purgeable.iter().filter_map(Weak::load) {
+            resource.setPurgeableState(state);
+        }
+        self.log(
+            LogLevel::Warn,
+            &format!(
+                "Memory pressure {pressure:?}: released the cached render graph textures and \
+                 marked {} resources purgeable.",
+                purgeable.len()
+            ),
+        );
+    }
+
+    // handles the memory pressure notified since the last frame, and warns when the resources
+    // of the device outgrow what it can use efficiently
+    pub(crate) fn check_memory(&self) {
+        if let Some(pressure) = self.ivars().memory_pressure_source.take() {
+            self.handle_memory_pressure(pressure);
+        }
+        let (allocated, budget) = self.memory_usage();
+        let over_budget = allocated > budget;
+        if over_budget && !self.ivars().over_memory_budget.get() {
+            self.log(
+                LogLevel::Warn,
+                &format!(
+                    "GPU memory over budget: {} of {} MB allocated.",
+                    allocated >> 20,
+                    budget >> 20
+                ),
+            );
+        }
+        self.ivars().over_memory_budget.set(over_budget);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{fmt, path::Path};
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{MTLBuffer, MTLPackedFloat3, MTLRenderPipelineState};
+use tobj::LoadError;
+
+use crate::{
+    new_shared_buffer, BoundingBox, LogLevel, MetalRenderer, PrimitiveType, RenderPass, VertexInput,
+};
+
+// indexed triangles in gpu buffers, drawn with `RenderPass::draw_mesh`
+pub struct Mesh {
+    pub vertex_buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    // 32-bit indices into `vertex_buffer`, three per triangle
+    pub index_buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    pub index_count: usize,
+    // around the vertices, in their space
+    pub bounds: BoundingBox,
+}
+
+#[derive(Debug)]
+pub struct MeshError(pub LoadError);
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to load the mesh: {}", self.0)
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+// merges the models of an OBJ file into one vertex and index list. the vertices take the
+// colors of the file, or show their normals when it has none, or are white otherwise
+fn obj_vertices(models: &[tobj::Model]) -> (Vec<VertexInput>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for model in models {
+        let mesh = &model.mesh;
+        let first_index = vertices.len() as u32;
+        indices.extend(mesh.indices.iter().map(|index| first_index + index));
+
+        let packed = |values: &[f32], i: usize| MTLPackedFloat3 {
+            x: values[i * 3],
+            y: values[i * 3 + 1],
+            z: values[i * 3 + 2],
+        };
+        vertices.extend((0..mesh.positions.len() / 3).map(|i| {
+            let color = if !mesh.vertex_color.is_empty() {
+                packed(&mesh.vertex_color, i)
+            } else if !mesh.normals.is_empty() {
+                let normal = packed(&mesh.normals, i);
+                MTLPackedFloat3 {
+                    x: normal.x * 0.5 + 0.5,
+                    y: normal.y * 0.5 + 0.5,
+                    z: normal.z * 0.5 + 0.5,
+                }
+            } else {
+                MTLPackedFloat3 {
+                    x: 1.,
+                    y: 1.,
+                    z: 1.,
+                }
+            };
+            VertexInput {
+                position: packed(&mesh.positions, i),
+                color,
+            }
+        }));
+    }
+    (vertices, indices)
+}
+
+// the vertices and indices of the OBJ file at `path`, on any thread
+pub(crate) fn read_obj(path: &Path) -> Result<(Vec<VertexInput>, Vec<u32>), MeshError> {
+    let (models, _materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(MeshError)?;
+    Ok(obj_vertices(&models))
+}
+
+impl MetalRenderer {
+    // parses a Wavefront OBJ file, its faces are triangulated and the materials are ignored
+    pub fn load_mesh(&self, path: impl AsRef<Path>) -> Result<Mesh, MeshError> {
+        let (vertices, indices) = read_obj(path.as_ref())?;
+        let message = format!(
+            "Loaded a mesh with {} vertices and {} triangles.",
+            vertices.len(),
+            indices.len() / 3
+        );
+        self.log(LogLevel::Debug, &message);
+        Ok(self.create_mesh(&vertices, &indices))
+    }
+
+    // an empty mesh, like the one of an OBJ file without faces, is drawn without triangles
+    pub fn create_mesh(&self, vertices: &[VertexInput], indices: &[u32]) -> Mesh {
+        let index_buffer =
+            new_shared_buffer(&self.device(), indices).expect("Failed to create an index buffer.");
+        Mesh {
+            vertex_buffer: self.create_vertex_buffer(vertices),
+            index_buffer,
+            index_count: indices.len(),
+            bounds: BoundingBox::around_vertices(vertices),
+        }
+    }
+}
+
+impl RenderPass {
+    pub fn draw_mesh(
+        &mut self,
+        pipeline_state: &Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        mesh: &Mesh,
+    ) -> &mut Self {
+        self.draw_indexed(
+            pipeline_state,
+            &mesh.vertex_buffer,
+            &mesh.index_buffer,
+            PrimitiveType::Triangle,
+            0..mesh.index_count,
+        )
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use objc2::{msg_send_id, rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::{NSError, NSString};
+use objc2_metal::{
+    MTLDevice, MTLGPUFamily, MTLLibrary, MTLMeshRenderPipelineDescriptor, MTLPipelineOption,
+    MTLPixelFormat, MTLRenderPipelineReflection, MTLRenderPipelineState,
+};
+
+use crate::{BlendMode, LogLevel, MetalRenderer, PrimitiveType, RenderPass, RendererError};
+
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+
+// the triangles of a meshlet of `mesh_spinning`, one thread of its mesh threadgroup each
+const MESHLET_TRIANGLE_COUNT: usize = 32;
+
+// the threads of an object threadgroup metal guarantees
+const MAX_OBJECT_THREADS: usize = 1024;
+
+// the state a mesh render pipeline is built from, an object stage is optional
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshPipelineDescriptor {
+    pub object_function: Option<String>,
+    pub mesh_function: String,
+    pub fragment_function: String,
+    pub blend_mode: BlendMode,
+    pub color_format: MTLPixelFormat,
+    // a depth format with stencil is also used for the stencil attachment
+    pub depth_format: Option<MTLPixelFormat>,
+    pub sample_count: usize,
+}
+
+pub(crate) type MeshPipelineStates = HashMap<MeshPipelineDescriptor, PipelineState>;
+
+impl MetalRenderer {
+    // whether the device runs object and mesh functions, apple silicon gpus from the a14 and
+    // m1 on and the other gpus of metal 3
+    pub fn supports_mesh_shaders(&self) -> bool {
+        let device = self.device();
+        device.supportsFamily(MTLGPUFamily::Apple7) || device.supportsFamily(MTLGPUFamily::Metal3)
+    }
+
+    // a descriptor for the shader functions with the formats and the sample count the view has
+    // now, opaque
+    pub fn mesh_pipeline_descriptor(
+        &self,
+        object_function: Option<&str>,
+        mesh_function: &str,
+        fragment_function: &str,
+    ) -> MeshPipelineDescriptor {
+        MeshPipelineDescriptor {
+            object_function: object_function.map(str::to_owned),
+            mesh_function: mesh_function.to_owned(),
+            fragment_function: fragment_function.to_owned(),
+            blend_mode: BlendMode::default(),
+            color_format: self.color_format(),
+            depth_format: self
+                .depth_format()
+                .map(|depth_format| depth_format.mtl_pixel_format()),
+            sample_count: self.sample_count(),
+        }
+    }
+
+    // returns the mesh pipeline for the shader functions with the formats of the view, it's
+    // created on first use. only on devices that `supports_mesh_shaders`
+    pub fn mesh_pipeline_state(
+        &self,
+        object_function: Option<&str>,
+        mesh_function: &str,
+        fragment_function: &str,
+    ) -> PipelineState {
+        let descriptor =
+            self.mesh_pipeline_descriptor(object_function, mesh_function, fragment_function);
+        self.mesh_pipeline_state_for(&descriptor)
+            .expect("Failed to create a mesh pipeline state.")
+    }
+
+    // the mesh pipeline built from `descriptor`, cached like `render_pipeline_state_for`
+    pub fn mesh_pipeline_state_for(
+        &self,
+        descriptor: &MeshPipelineDescriptor,
+    ) -> Result<PipelineState, RendererError> {
+        if let Some(pipeline_state) = self.ivars().mesh_pipeline_states.borrow().get(descriptor) {
+            return Ok(pipeline_state.clone());
+        }
+        if !self.supports_mesh_shaders() {
+            let message = "The device doesn't support mesh shaders".to_owned();
+            return Err(RendererError::PipelineCreation(message));
+        }
+        let pipeline_state = self.create_mesh_pipeline_state(&*self.library()?, descriptor)?;
+        self.ivars()
+            .mesh_pipeline_states
+            .borrow_mut()
+            .insert(descriptor.clone(), pipeline_state.clone());
+        Ok(pipeline_state)
+    }
+
+    fn create_mesh_pipeline_state(
+        &self,
+        library: &ProtocolObject<dyn MTLLibrary>,
+        descriptor: &MeshPipelineDescriptor,
+    ) -> Result<PipelineState, RendererError> {
+        let function = |name: &str| library.newFunctionWithName(&NSString::from_str(name));
+        let pipeline_descriptor = unsafe { MTLMeshRenderPipelineDescriptor::new() };
+        unsafe {
+            let color_attachment = pipeline_descriptor
+                .colorAttachments()
+                .objectAtIndexedSubscript(0);
+            color_attachment.setPixelFormat(descriptor.color_format);
+            descriptor.blend_mode.configure(&color_attachment);
+            if let Some(depth_format) = descriptor.depth_format {
+                pipeline_descriptor.setDepthAttachmentPixelFormat(depth_format);
+                if depth_format == MTLPixelFormat::Depth32Float_Stencil8 {
+                    pipeline_descriptor.setStencilAttachmentPixelFormat(depth_format);
+                }
+            }
+            pipeline_descriptor.setRasterSampleCount(descriptor.sample_count);
+
+            let object_function = descriptor.object_function.as_deref().and_then(function);
+            pipeline_descriptor.setObjectFunction(object_function.as_deref());
+            // the library has no mesh functions when it was compiled for a language version
+            // before metal 3
+            let Some(mesh_function) = function(&descriptor.mesh_function) else {
+                let message = format!("The library has no function {}", descriptor.mesh_function);
+                return Err(RendererError::PipelineCreation(message));
+            };
+            pipeline_descriptor.setMeshFunction(Some(&mesh_function));
+            let fragment_function = function(&descriptor.fragment_function);
+            pipeline_descriptor.setFragmentFunction(fragment_function.as_deref());
+        }
+
+        // there's only the asynchronous variant in the bindings
+        let device = &self.device();
+        let mut reflection: Option<Retained<MTLRenderPipelineReflection>> = None;
+        let pipeline_state: Result<PipelineState, Retained<NSError>> = unsafe {
+            msg_send_id![
+                device,
+                newRenderPipelineStateWithMeshDescriptor: &*pipeline_descriptor,
+                options: MTLPipelineOption::empty(),
+                reflection: &mut reflection,
+                error: _
+            ]
+        };
+        pipeline_state
+            .map_err(|error| {
+                RendererError::PipelineCreation(error.localizedDescription().to_string())
+            })
+            .inspect_err(|error| self.log(LogLevel::Error, &error.to_string()))
+    }
+
+    // records the geometry set with `set_vertices` through `object_meshlets` and
+    // `mesh_spinning`, split into meshlets of 32 triangles. falls back to `draw_geometry` when
+    // the device has no mesh shaders, the library was compiled without them or the geometry
+    // isn't a triangle list of at most 1024 triangles
+    pub fn draw_geometry_meshlets(&self, render_pass: &mut RenderPass) {
+        let primitive_type = self.ivars().primitive_type.get();
+        let vertex_buffer = self.ivars().vertex_buffer.borrow();
+        let Some(vertex_buffer) = &*vertex_buffer else {
+            return;
+        };
+        // the object function gets the triangle count as the size of its threadgroup
+        let triangle_count = vertex_buffer.len() / 3;
+        if !self.supports_mesh_shaders()
+            || primitive_type != PrimitiveType::Triangle
+            || !(1..=MAX_OBJECT_THREADS).contains(&triangle_count)
+        {
+            self.draw_geometry(render_pass);
+            return;
+        }
+        let descriptor = self.mesh_pipeline_descriptor(
+            Some("object_meshlets"),
+            "mesh_spinning",
+            "fragment_meshlet",
+        );
+        let Ok(pipeline_state) = self.mesh_pipeline_state_for(&descriptor) else {
+            self.draw_geometry(render_pass);
+            return;
+        };
+        render_pass.draw_mesh_threadgroups(
+            &pipeline_state,
+            vertex_buffer.buffer(),
+            (1, 1, 1),
+            (triangle_count, 1, 1),
+            (MESHLET_TRIANGLE_COUNT, 1, 1),
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use objc2::{
+    class, msg_send, msg_send_id,
+    rc::Retained,
+    runtime::{AnyObject, ProtocolObject},
+    DeclaredClass,
+};
+use objc2_foundation::NSSize;
+use objc2_metal::{
+    MTLClearColor, MTLCommandBuffer, MTLCommandEncoder, MTLPixelFormat, MTLRenderPassDescriptor,
+    MTLStoreAction, MTLTexture, MTLTextureUsage,
+};
+
+use crate::{
+    camera::{multiply, Matrix, IDENTITY},
+    target::{attachment_texture, render_pass_descriptor},
+    LogLevel, MetalRenderer, PixelFormat,
+};
+
+// the scalers aren't in the bindings, they're messaged through the objective-c runtime
+#[link(name = "MetalFX", kind = "framework")]
+extern "C" {}
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the motion vectors the temporal scaler reads, the frames have none and leave them at zero
+const MOTION_FORMAT: MTLPixelFormat = MTLPixelFormat::RG16Float;
+
+// the length of the sequence of camera jitters of the temporal scaler
+const JITTER_PHASES: u32 = 32;
+
+// the MetalFX scaler upscaling the frames to the drawable
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Upscaler {
+    // sharpens a single frame, works with any view
+    Spatial,
+    // accumulates the frames rendered with a jittered camera, reconstructs more detail but
+    // needs a depth format and smears what moves fast since there are no motion vectors
+    Temporal,
+}
+
+// how much smaller than the drawable the upscaled frames are rendered
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UpscalingQuality {
+    Performance,
+    #[default]
+    Balanced,
+    Quality,
+    UltraQuality,
+}
+
+impl UpscalingQuality {
+    // the size of the rendered frames relative to the drawable, along each axis
+    pub fn render_scale(self) -> f64 {
+        match self {
+            UpscalingQuality::Performance => 0.5,
+            UpscalingQuality::Balanced => 0.59,
+            UpscalingQuality::Quality => 0.67,
+            UpscalingQuality::UltraQuality => 0.77,
+        }
+    }
+}
+
+// the textures and the scaler of the sizes and formats of the last upscaled frame
+struct UpscalingResources {
+    upscaler: Upscaler,
+    render_size: (usize, usize),
+    output_size: (usize, usize),
+    pixel_format: MTLPixelFormat,
+    scaler: Retained<AnyObject>,
+    color: Texture,
+    depth: Option<Texture>,
+    // cleared by the first frame using it
+    motion: Option<Texture>,
+    output: Texture,
+    // the scaler drops its history with the next frame
+    reset: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct UpscalingState {
+    upscaler: Option<Upscaler>,
+    quality: UpscalingQuality,
+    frame_index: u32,
+    resources: Option<UpscalingResources>,
+}
+
+impl UpscalingState {
+    // drops the scaler and its textures, they're created again with the next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.resources = None;
+    }
+}
+
+// a frame rendered smaller and upscaled to the drawable by `encode_upscaling`
+pub(crate) struct UpscaledFrame {
+    pub(crate) render_size: NSSize,
+    // the pass of the frame into the small color and depth textures
+    pub(crate) pass_descriptor: Retained<MTLRenderPassDescriptor>,
+    // the offset of the camera in pixels of the rendered frame, y pointing down
+    jitter: (f32, f32),
+}
+
+impl UpscaledFrame {
+    // moves the clip space of `view_projection` by the jitter of the frame
+    pub(crate) fn jitter(&self, view_projection: &Matrix) -> Matrix {
+        let (x, y) = self.jitter;
+        let mut translation = IDENTITY;
+        translation[3][0] = 2. * x / self.render_size.width as f32;
+        translation[3][1] = -2. * y / self.render_size.height as f32;
+        multiply(&translation, view_projection)
+    }
+
+    // moves the gpu timer samples of the view onto the pass of the frame, the view's pass
+    // isn't encoded
+    pub(crate) fn take_gpu_timer_samples(&self, view_pass_descriptor: &MTLRenderPassDescriptor) {
+        unsafe {
+            let samples = view_pass_descriptor
+                .sampleBufferAttachments()
+                .objectAtIndexedSubscript(0);
+            self.pass_descriptor
+                .sampleBufferAttachments()
+                .setObject_atIndexedSubscript(Some(&samples), 0);
+        }
+    }
+}
+
+// the element of the halton sequence of `base` at `index`, between 0 and 1
+fn halton(mut index: u32, base: u32) -> f32 {
+    let (mut fraction, mut result) = (1., 0.);
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// how the scalers read the colors of the view
+fn color_processing_mode(pixel_format: PixelFormat) -> isize {
+    match pixel_format {
+        // values encoded for display
+        PixelFormat::Bgra8Unorm => 0,
+        // linear values, the texture decodes them when read
+        PixelFormat::Bgra8UnormSrgb => 1,
+        // extended range linear values
+        PixelFormat::Rgba16Float => 2,
+    }
+}
+
+impl MetalRenderer {
+    // whether the device runs the scaler, MetalFX needs macOS 13 and an apple silicon gpu or a
+    // recent discrete one
+    pub fn supports_upscaler(&self, upscaler: Upscaler) -> bool {
+        let device = self.device();
+        let device: &ProtocolObject<dyn objc2_metal::MTLDevice> = &device;
+        unsafe {
+            match upscaler {
+                Upscaler::Spatial => {
+                    msg_send![class!(MTLFXSpatialScalerDescriptor), supportsDevice: device]
+                }
+                Upscaler::Temporal => {
+                    msg_send![class!(MTLFXTemporalScalerDescriptor), supportsDevice: device]
+                }
+            }
+        }
+    }
+
+    // renders the frames at a fraction of the drawable size and upscales them with `upscaler`,
+    // none renders them at full size. only the frames of the render callback are upscaled, not
+    // the ones of a render graph, a custom viewport, multisampling or post-processing. the
+    // upscaled frames are copied to the drawables, which stop being framebuffer only
+    pub fn set_upscaler(&self, upscaler: Option<Upscaler>) {
+        if upscaler.is_some() {
+            if let Some(surface) = self.ivars().surface.get() {
+                surface.set_framebuffer_only(false);
+            }
+        }
+        self.ivars().upscaling.borrow_mut().upscaler = upscaler;
+    }
+
+    pub fn upscaler(&self) -> Option<Upscaler> {
+        self.ivars().upscaling.borrow().upscaler
+    }
+
+    // the knob trading detail for speed, takes effect with the next frame
+    pub fn set_upscaling_quality(&self, quality: UpscalingQuality) {
+        self.ivars().upscaling.borrow_mut().quality = quality;
+    }
+
+    pub fn upscaling_quality(&self) -> UpscalingQuality {
+        self.ivars().upscaling.borrow().quality
+    }
+
+    // the smaller frame the draws of this frame go to, none when it isn't upscaled. the
+    // textures and the scaler are recreated when the sizes or the formats changed
+    pub(crate) fn prepare_upscaling(&self, drawable_size: NSSize) -> Option<UpscaledFrame> {
+        let mut upscaling = self.ivars().upscaling.borrow_mut();
+        let mut upscaler = upscaling.upscaler?;
+        if self.ivars().render_graph_callback.borrow().is_some()
+            || self.ivars().viewport.get().is_some()
+            || self.sample_count() > 1
+            || self.is_ray_tracing()
+            || self.is_post_processing()
+        {
+            return None;
+        }
+        if upscaler == Upscaler::Temporal && self.depth_format().is_none() {
+            upscaler = Upscaler::Spatial;
+        }
+        if !self.supports_upscaler(upscaler) {
+            return None;
+        }
+
+        let output_size = (drawable_size.width as usize, drawable_size.height as usize);
+        let scale = upscaling.quality.render_scale();
+        let render_size = (
+            ((drawable_size.width * scale) as usize).max(1),
+            ((drawable_size.height * scale) as usize).max(1),
+        );
+        let pixel_format = self.pixel_format().mtl_pixel_format();
+        let reusable = upscaling.resources.as_ref().is_some_and(|resources| {
+            resources.upscaler == upscaler
+                && resources.render_size == render_size
+                && resources.output_size == output_size
+                && resources.pixel_format == pixel_format
+                && resources.depth.as_ref().map(|depth| depth.pixelFormat())
+                    == self.depth_format().map(|format| format.mtl_pixel_format())
+        });
+        if !reusable {
+            upscaling.resources =
+                Some(self.create_upscaling_resources(upscaler, render_size, output_size)?);
+        }
+
+        // a new jitter every frame for the temporal scaler, centered on the pixels
+        let jitter = match upscaler {
+            Upscaler::Spatial => (0., 0.),
+            Upscaler::Temporal => {
+                upscaling.frame_index = (upscaling.frame_index + 1) % JITTER_PHASES;
+                let index = upscaling.frame_index + 1;
+                (halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+            }
+        };
+        let resources = upscaling.resources.as_ref().unwrap();
+        let clear_color = self.ivars().surface.get().unwrap().clear_color();
+        let pass_descriptor = render_pass_descriptor(
+            &resources.color,
+            None,
+            resources.depth.as_deref(),
+            clear_color,
+        );
+        // the temporal scaler reads the depth after the pass
+        if resources.depth.is_some() && upscaler == Upscaler::Temporal {
+            pass_descriptor
+                .depthAttachment()
+                .setStoreAction(MTLStoreAction::Store);
+        }
+        Some(UpscaledFrame {
+            render_size: NSSize::new(render_size.0 as f64, render_size.1 as f64),
+            pass_descriptor,
+            jitter,
+        })
+    }
+
+    fn create_upscaling_resources(
+        &self,
+        upscaler: Upscaler,
+        (render_width, render_height): (usize, usize),
+        (output_width, output_height): (usize, usize),
+    ) -> Option<UpscalingResources> {
+        let device = self.device();
+        let pixel_format = self.pixel_format().mtl_pixel_format();
+        let depth_format = self.depth_format().map(|format| format.mtl_pixel_format());
+        let processing_mode = color_processing_mode(self.pixel_format());
+
+        let scaler: Option<Retained<AnyObject>> = unsafe {
+            let descriptor: Retained<AnyObject> = match upscaler {
+                Upscaler::Spatial => msg_send_id![class!(MTLFXSpatialScalerDescriptor), new],
+                Upscaler::Temporal => msg_send_id![class!(MTLFXTemporalScalerDescriptor), new],
+            };
+            let _: () = msg_send![&descriptor, setColorTextureFormat: pixel_format];
+            let _: () = msg_send![&descriptor, setOutputTextureFormat: pixel_format];
+            let _: () = msg_send![&descriptor, setInputWidth: render_width];
+            let _: () = msg_send![&descriptor, setInputHeight: render_height];
+            let _: () = msg_send![&descriptor, setOutputWidth: output_width];
+            let _: () = msg_send![&descriptor, setOutputHeight: output_height];
+            let device: &ProtocolObject<dyn objc2_metal::MTLDevice> = &device;
+            match upscaler {
+                Upscaler::Spatial => {
+                    let _: () = msg_send![&descriptor, setColorProcessingMode: processing_mode];
+                    msg_send_id![&descriptor, newSpatialScalerWithDevice: device]
+                }
+                Upscaler::Temporal => {
+                    let _: () = msg_send![&descriptor, setDepthTextureFormat: depth_format?];
+                    let _: () = msg_send![&descriptor, setMotionTextureFormat: MOTION_FORMAT];
+                    let _: () = msg_send![&descriptor, setAutoExposureEnabled: false];
+                    msg_send_id![&descriptor, newTemporalScalerWithDevice: device]
+                }
+            }
+        };
+        let Some(scaler) = scaler else {
+            self.log(LogLevel::Warn, "Failed to create a MetalFX scaler.");
+            return None;
+        };
+
+        let usage = |scaler_usage: MTLTextureUsage| scaler_usage | MTLTextureUsage::RenderTarget;
+        let (color_usage, output_usage): (MTLTextureUsage, MTLTextureUsage) = unsafe {
+            (
+                msg_send![&scaler, colorTextureUsage],
+                msg_send![&scaler, outputTextureUsage],
+            )
+        };
+        let render_size = (render_width, render_height);
+        let output_size = (output_width, output_height);
+        let color = attachment_texture(&device, pixel_format, render_size, 1, usage(color_usage));
+        let output = attachment_texture(&device, pixel_format, output_size, 1, output_usage);
+        let depth = depth_format.map(|depth_format| {
+            let depth_usage = match upscaler {
+                Upscaler::Spatial => MTLTextureUsage::RenderTarget,
+                Upscaler::Temporal => usage(unsafe { msg_send![&scaler, depthTextureUsage] }),
+            };
+            attachment_texture(&device, depth_format, render_size, 1, depth_usage)
+        });
+        let motion = (upscaler == Upscaler::Temporal).then(|| {
+            let motion_usage = usage(unsafe { msg_send![&scaler, motionTextureUsage] });
+            attachment_texture(&device, MOTION_FORMAT, render_size, 1, motion_usage)
+        });
+        Some(UpscalingResources {
+            upscaler,
+            render_size,
+            output_size,
+            pixel_format,
+            scaler,
+            color,
+            depth,
+            motion,
+            output,
+            reset: true,
+        })
+    }
+
+    // upscales the frame rendered into the smaller textures and copies it over the drawable
+    pub(crate) fn encode_upscaling(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        upscaled: &UpscaledFrame,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) {
+        let mut upscaling = self.ivars().upscaling.borrow_mut();
+        let Some(resources) = upscaling.resources.as_mut() else {
+            return;
+        };
+        let (width, height) = resources.render_size;
+        let scaler = &resources.scaler;
+        unsafe {
+            let color: &ProtocolObject<dyn MTLTexture> = &resources.color;
+            let output: &ProtocolObject<dyn MTLTexture> = &resources.output;
+            let _: () = msg_send![scaler, setColorTexture: color];
+            let _: () = msg_send![scaler, setOutputTexture: output];
+            let _: () = msg_send![scaler, setInputContentWidth: width];
+            let _: () = msg_send![scaler, setInputContentHeight: height];
+        }
+        if let (Some(depth), Some(motion)) = (&resources.depth, &resources.motion) {
+            if resources.reset {
+                clear_texture(command_buffer, motion);
+            }
+            let (jitter_x, jitter_y) = upscaled.jitter;
+            unsafe {
+                let depth: &ProtocolObject<dyn MTLTexture> = depth;
+                let motion: &ProtocolObject<dyn MTLTexture> = motion;
+                let _: () = msg_send![scaler, setDepthTexture: depth];
+                let _: () = msg_send![scaler, setMotionTexture: motion];
+                let _: () = msg_send![scaler, setJitterOffsetX: jitter_x];
+                let _: () = msg_send![scaler, setJitterOffsetY: jitter_y];
+                let _: () = msg_send![scaler, setReset: resources.reset];
+            }
+        }
+        resources.reset = false;
+        unsafe {
+            let _: () = msg_send![scaler, encodeToCommandBuffer: command_buffer];
+        }
+        self.encode_drawable_copy(command_buffer, &resources.output, drawable_texture);
+    }
+}
+
+// zeroes `texture` with an empty render pass
+fn clear_texture(
+    command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+    texture: &ProtocolObject<dyn MTLTexture>,
+) {
+    let clear_color = MTLClearColor {
+        red: 0.,
+        green: 0.,
+        blue: 0.,
+        alpha: 0.,
+    };
+    let descriptor = render_pass_descriptor(texture, None, None, clear_color);
+    if let Some(encoder) = command_buffer.renderCommandEncoderWithDescriptor(&descriptor) {
+        encoder.endEncoding();
+    }
+}
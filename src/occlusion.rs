@@ -0,0 +1,136 @@
+use core::cell::RefCell;
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_foundation::NSCopying;
+use objc2_metal::{
+    MTLBuffer, MTLCommandBuffer, MTLCommandBufferStatus, MTLDevice, MTLRenderPassDescriptor,
+    MTLResourceOptions,
+};
+
+use crate::{MetalRenderer, RenderPass};
+
+// the counts of the samples passing the depth and stencil tests for the draws of a render
+// pass, one per query. the counts of a frame are read back once the gpu completed it, the
+// frames recorded in the meantime don't write them. only one pass of a frame can use them
+pub struct OcclusionQueries {
+    // a count of 8 bytes per query
+    buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    count: usize,
+    // the command buffer writing the counts until they're read back
+    pending: RefCell<Option<Retained<ProtocolObject<dyn MTLCommandBuffer>>>>,
+    // the counts of the last frame read back, none before the first
+    results: RefCell<Option<Vec<u64>>>,
+}
+
+impl OcclusionQueries {
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // the samples the draws of `query` left visible in the last completed frame measuring
+    // them, none until one completed. zero for a query without draws in that frame
+    pub fn samples_passed(&self, query: usize) -> Option<u64> {
+        self.read_back();
+        let results = self.results.borrow();
+        results
+            .as_ref()
+            .and_then(|results| results.get(query).copied())
+    }
+
+    // all the counts of the last completed frame measuring them
+    pub fn results(&self) -> Option<Vec<u64>> {
+        self.read_back();
+        self.results.borrow().clone()
+    }
+
+    // copies the counts out of the buffer once the frame writing them completed
+    fn read_back(&self) {
+        let mut pending = self.pending.borrow_mut();
+        let Some(command_buffer) = pending.as_ref() else {
+            return;
+        };
+        match command_buffer.status() {
+            MTLCommandBufferStatus::Completed => {
+                let counts = unsafe {
+                    core::slice::from_raw_parts(
+                        self.buffer.contents().cast::<u64>().as_ptr(),
+                        self.count,
+                    )
+                };
+                self.results.replace(Some(counts.to_vec()));
+                *pending = None;
+            }
+            // the counts of a failed frame are incomplete, they're dropped
+            MTLCommandBufferStatus::Error => *pending = None,
+            _ => (),
+        }
+    }
+
+    // starts measuring the pass encoded into `command_buffer`, unless the counts of an earlier
+    // frame are still in flight. returns whether the pass writes the counts
+    pub(crate) fn begin(&self, command_buffer: &ProtocolObject<dyn MTLCommandBuffer>) -> bool {
+        self.read_back();
+        let mut pending = self.pending.borrow_mut();
+        if pending.is_some() {
+            return false;
+        }
+        unsafe {
+            core::ptr::write_bytes(self.buffer.contents().cast::<u64>().as_ptr(), 0, self.count)
+        };
+        let command_buffer = command_buffer as *const _ as *mut _;
+        *pending = unsafe { Retained::retain(command_buffer) };
+        true
+    }
+
+    // a copy of `pass_descriptor` writing the counts into the buffer of the queries
+    pub(crate) fn pass_descriptor(
+        &self,
+        pass_descriptor: &MTLRenderPassDescriptor,
+    ) -> Retained<MTLRenderPassDescriptor> {
+        let descriptor = pass_descriptor.copy();
+        descriptor.setVisibilityResultBuffer(Some(&self.buffer));
+        descriptor
+    }
+}
+
+impl MetalRenderer {
+    // creates `count` queries for `RenderPass::with_occlusion_queries`
+    pub fn create_occlusion_queries(&self, count: usize) -> OcclusionQueries {
+        let buffer = self
+            .device()
+            .newBufferWithLength_options(
+                (count * core::mem::size_of::<u64>()).max(1),
+                MTLResourceOptions::MTLResourceStorageModeShared,
+            )
+            .expect("Failed to create a visibility result buffer.");
+        OcclusionQueries {
+            buffer,
+            count,
+            pending: RefCell::default(),
+            results: RefCell::default(),
+        }
+    }
+}
+
+impl RenderPass {
+    // measures the draws of the pass given a query with `with_occlusion_query` into `queries`
+    pub fn with_occlusion_queries(&mut self, queries: &Rc<OcclusionQueries>) -> &mut Self {
+        self.occlusion_queries = Some(queries.clone());
+        self
+    }
+
+    // counts the samples of the last recorded draw that pass the depth and stencil tests into
+    // `query`, added to the counts of the other draws of the query. the pass needs queries
+    // from `with_occlusion_queries`
+    pub fn with_occlusion_query(&mut self, query: usize) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.occlusion_query = Some(query);
+        }
+        self
+    }
+}
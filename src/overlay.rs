@@ -0,0 +1,342 @@
+use std::{collections::VecDeque, time::Instant};
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSString;
+use objc2_metal::{
+    MTLBlendFactor, MTLBuffer, MTLCommandBuffer, MTLCommandEncoder, MTLDevice, MTLLibrary,
+    MTLLoadAction, MTLPixelFormat, MTLPrimitiveType, MTLRenderCommandEncoder,
+    MTLRenderPassDescriptor, MTLRenderPipelineDescriptor, MTLRenderPipelineState, MTLStoreAction,
+    MTLTexture,
+};
+
+use crate::{LogLevel, MetalRenderer};
+
+// the number of frames the graph of the overlay shows, the rates are averaged over them too
+const FRAME_HISTORY: usize = 120;
+
+// frames taking longer than this fill the height of the graph
+const GRAPH_FRAME_TIME: f64 = 1. / 30.;
+
+// a rectangle of the overlay, the `OverlayQuad` struct in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct OverlayQuad {
+    // the top left corner in pixels from the top left of the drawable
+    position: [f32; 2],
+    size: [f32; 2],
+    // premultiplied by alpha
+    color: [f32; 4],
+}
+
+// the rows of a 3x5 pixel glyph from the top, the highest of the 3 bits is the leftmost pixel.
+// only the characters of the overlay are drawn, the others are left blank
+fn glyph(character: char) -> [u8; 5] {
+    match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' | 'S' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'P' => [0b111, 0b101, 0b111, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        _ => [0; 5],
+    }
+}
+
+// a time in seconds as milliseconds, or a dash when it wasn't measured
+fn milliseconds(time: Option<f64>) -> String {
+    time.map_or("-".to_owned(), |time| format!("{:.2} MS", time * 1e3))
+}
+
+// the frame rate counter drawn on top of the frames
+#[derive(Default)]
+pub(crate) struct Overlay {
+    visible: bool,
+    // created for the pixel format of the drawable
+    pipeline_state: Option<(
+        MTLPixelFormat,
+        Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    )>,
+    last_frame: Option<Instant>,
+    // the intervals between the latest frames in seconds, the oldest first
+    frame_times: VecDeque<f64>,
+}
+
+impl Overlay {
+    // drops the pipeline, it's recreated from the current shader library on the next frame
+    pub(crate) fn clear_pipeline_state(&mut self) {
+        self.pipeline_state = None;
+    }
+
+    // counts a frame towards the rates and the graph
+    fn frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            if self.frame_times.len() == FRAME_HISTORY {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back((now - last_frame).as_secs_f64());
+        }
+    }
+
+    // the average time between the frames of the history
+    fn average_frame_time(&self) -> Option<f64> {
+        let total: f64 = self.frame_times.iter().sum();
+        (total > 0.).then(|| total / self.frame_times.len() as f64)
+    }
+}
+
+// lays the quads of the overlay out, `unit` pixels per pixel of the glyphs
+fn overlay_quads(lines: &[String], frame_times: &VecDeque<f64>, unit: f32) -> Vec<OverlayQuad> {
+    let margin = 4. * unit;
+    let padding = 2. * unit;
+    let line_height = 7. * unit;
+    let graph_height = 16. * unit;
+    // a bar of half a glyph pixel per frame
+    let bar_width = unit / 2.;
+
+    let text_width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as f32 * 4. * unit;
+    let graph_width = FRAME_HISTORY as f32 * bar_width;
+    let text_height = lines.len() as f32 * line_height;
+    let mut quads = vec![OverlayQuad {
+        position: [margin, margin],
+        size: [
+            text_width.max(graph_width) + padding * 2.,
+            text_height + graph_height + padding * 2.,
+        ],
+        color: [0., 0., 0., 0.6],
+    }];
+
+    let origin = [margin + padding, margin + padding];
+    for (row, line) in lines.iter().enumerate() {
+        for (column, character) in line.chars().enumerate() {
+            for (y, bits) in glyph(character).into_iter().enumerate() {
+                for x in (0..3).filter(|x| bits & (0b100 >> x) != 0) {
+                    quads.push(OverlayQuad {
+                        position: [
+                            origin[0] + (column * 4 + x) as f32 * unit,
+                            origin[1] + row as f32 * line_height + y as f32 * unit,
+                        ],
+                        size: [unit, unit],
+                        color: [1., 1., 1., 1.],
+                    });
+                }
+            }
+        }
+    }
+
+    // the frame times as bars growing up from the bottom, the newest on the right
+    let graph_bottom = origin[1] + text_height + graph_height;
+    let first_bar = FRAME_HISTORY - frame_times.len();
+    for (index, frame_time) in frame_times.iter().enumerate() {
+        let height = (frame_time / GRAPH_FRAME_TIME).min(1.) as f32 * graph_height;
+        // green at 60 fps and above, yellow down to 30 fps and red below
+        let color = if *frame_time < 1. / 55. {
+            [0.2, 0.9, 0.3, 1.]
+        } else if *frame_time < GRAPH_FRAME_TIME {
+            [0.9, 0.8, 0.2, 1.]
+        } else {
+            [0.9, 0.2, 0.2, 1.]
+        };
+        quads.push(OverlayQuad {
+            position: [
+                origin[0] + (first_bar + index) as f32 * bar_width,
+                graph_bottom - height,
+            ],
+            size: [bar_width, height],
+            color,
+        });
+    }
+    quads
+}
+
+impl MetalRenderer {
+    // shows the frame rate, the frame time and the cpu and gpu time of the frames in the top
+    // left corner of the view, together with a graph of the latest frame times
+    pub fn set_overlay_visible(&self, visible: bool) {
+        let mut overlay = self.ivars().overlay.borrow_mut();
+        overlay.visible = visible;
+        // the time the overlay was hidden doesn't count as a frame
+        overlay.last_frame = None;
+        overlay.frame_times.clear();
+    }
+
+    pub fn is_overlay_visible(&self) -> bool {
+        self.ivars().overlay.borrow().visible
+    }
+
+    fn overlay_pipeline_state(
+        &self,
+        pixel_format: MTLPixelFormat,
+    ) -> Option<Retained<ProtocolObject<dyn MTLRenderPipelineState>>> {
+        if let Some((format, pipeline_state)) = &self.ivars().overlay.borrow().pipeline_state {
+            if *format == pixel_format {
+                return Some(pipeline_state.clone());
+            }
+        }
+
+        let pipeline_state =
+            self.create_overlay_pipeline_state("vertex_overlay", "fragment_overlay", pixel_format);
+        let mut overlay = self.ivars().overlay.borrow_mut();
+        match &pipeline_state {
+            Some(pipeline_state) => {
+                overlay.pipeline_state = Some((pixel_format, pipeline_state.clone()))
+            }
+            None => overlay.visible = false,
+        }
+        pipeline_state
+    }
+
+    // creates a pipeline blending premultiplied colors over a finished frame of `pixel_format`,
+    // after the multisampled pass resolved and without depth. returns None for a library built
+    // without the functions, like a precompiled one
+    pub(crate) fn create_overlay_pipeline_state(
+        &self,
+        vertex_function: &str,
+        fragment_function: &str,
+        pixel_format: MTLPixelFormat,
+    ) -> Option<Retained<ProtocolObject<dyn MTLRenderPipelineState>>> {
+        let library = self
+            .library()
+            .inspect_err(|error| self.log(LogLevel::Error, &format!("{error}.")))
+            .ok()?;
+        let (Some(vertex_function), Some(fragment_function)) = (
+            library.newFunctionWithName(&NSString::from_str(vertex_function)),
+            library.newFunctionWithName(&NSString::from_str(fragment_function)),
+        ) else {
+            let message = format!(
+                "The shader library has no {vertex_function} and {fragment_function} functions."
+            );
+            self.log(LogLevel::Warn, &message);
+            return None;
+        };
+
+        let pipeline_descriptor = MTLRenderPipelineDescriptor::new();
+        let color_attachment = unsafe {
+            pipeline_descriptor
+                .colorAttachments()
+                .objectAtIndexedSubscript(0)
+        };
+        color_attachment.setPixelFormat(pixel_format);
+        color_attachment.setBlendingEnabled(true);
+        color_attachment.setSourceRGBBlendFactor(MTLBlendFactor::One);
+        color_attachment.setDestinationRGBBlendFactor(MTLBlendFactor::OneMinusSourceAlpha);
+        color_attachment.setSourceAlphaBlendFactor(MTLBlendFactor::OneMinusDestinationAlpha);
+        color_attachment.setDestinationAlphaBlendFactor(MTLBlendFactor::One);
+        pipeline_descriptor.setVertexFunction(Some(&vertex_function));
+        pipeline_descriptor.setFragmentFunction(Some(&fragment_function));
+
+        let pipeline_state = self
+            .device()
+            .newRenderPipelineStateWithDescriptor_error(&pipeline_descriptor)
+            .inspect_err(|error| {
+                self.log(
+                    LogLevel::Error,
+                    &format!("Pipeline creation failed: {}", error.localizedDescription()),
+                )
+            })
+            .expect("Failed to create an overlay pipeline state.");
+        Some(pipeline_state)
+    }
+
+    // counts the frame and lays the overlay out for `drawable_texture` while the frame is
+    // recorded, returns None while it's hidden
+    pub(crate) fn record_overlay(
+        &self,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> Option<OverlayDraw> {
+        let (lines, frame_times) = {
+            let mut overlay = self.ivars().overlay.borrow_mut();
+            if !overlay.visible {
+                return None;
+            }
+            overlay.frame();
+            let frame_time = overlay.average_frame_time();
+            let frame_stats = self.frame_stats();
+            let lines = [
+                format!(
+                    "FPS {}",
+                    frame_time.map_or("-".to_owned(), |time| format!("{:.1}", 1. / time))
+                ),
+                format!("FRAME {}", milliseconds(frame_time)),
+                format!("CPU {}", milliseconds(frame_stats.cpu_frame_time)),
+                format!("GPU {}", milliseconds(frame_stats.gpu_frame_time)),
+            ];
+            (lines, overlay.frame_times.clone())
+        };
+        let pipeline_state = self.overlay_pipeline_state(drawable_texture.pixelFormat())?;
+
+        // glyph pixels of 2 points
+        let scale_factor = self.scale_factor();
+        let quads = overlay_quads(&lines, &frame_times, (2. * scale_factor) as f32);
+        Some(OverlayDraw {
+            pipeline_state,
+            quad_buffer: self.frame_buffer(&quads),
+            quad_count: quads.len(),
+        })
+    }
+}
+
+// the overlay of a frame, drawn in a pass of its own after the frame
+pub(crate) struct OverlayDraw {
+    pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    quad_buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    quad_count: usize,
+}
+
+impl OverlayDraw {
+    // draws over the finished frame in `drawable_texture`, returns false if the encoder
+    // couldn't be created
+    pub(crate) fn encode(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> bool {
+        let descriptor = MTLRenderPassDescriptor::renderPassDescriptor();
+        let color_attachment = unsafe { descriptor.colorAttachments().objectAtIndexedSubscript(0) };
+        color_attachment.setTexture(Some(drawable_texture));
+        color_attachment.setLoadAction(MTLLoadAction::Load);
+        color_attachment.setStoreAction(MTLStoreAction::Store);
+        let Some(encoder) = command_buffer.renderCommandEncoderWithDescriptor(&descriptor) else {
+            return false;
+        };
+
+        let drawable_size = [
+            drawable_texture.width() as f32,
+            drawable_texture.height() as f32,
+        ];
+        encoder.setRenderPipelineState(&self.pipeline_state);
+        unsafe {
+            encoder.setVertexBuffer_offset_atIndex(Some(&self.quad_buffer), 0, 0);
+            encoder.setVertexBytes_length_atIndex(
+                NonNull::from(&drawable_size).cast::<c_void>(),
+                core::mem::size_of_val(&drawable_size),
+                1,
+            );
+            encoder.drawPrimitives_vertexStart_vertexCount_instanceCount(
+                MTLPrimitiveType::TriangleStrip,
+                0,
+                4,
+                self.quad_count,
+            );
+        }
+        encoder.endEncoding();
+        true
+    }
+}
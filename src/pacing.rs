@@ -0,0 +1,98 @@
+// when the frames reach the screen. every drawable reports the time it was shown, or that it
+// never was, which tells a steady 60 or 120 hz from one that stutters although the cpu and the
+// gpu times look fine
+use std::sync::{Arc, Mutex};
+
+use core::ptr::NonNull;
+
+use block2::RcBlock;
+use objc2::{runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::MTLDrawable;
+use objc2_quartz_core::CACurrentMediaTime;
+
+use crate::{platform::NativeWindowExt, FrameStats, MetalRenderer};
+
+pub(crate) type SharedFramePacing = Arc<Mutex<FramePacing>>;
+
+// the presentations of the frames so far, written by the presented handlers of the drawables
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FramePacing {
+    // when the last frame shown reached the screen, in the seconds of `CACurrentMediaTime`
+    last_presented: Option<f64>,
+    present_interval: Option<f64>,
+    present_latency: Option<f64>,
+    presented_frames: u64,
+    missed_frames: u64,
+    dropped_frames: u64,
+}
+
+impl FramePacing {
+    // a drawable presented at `requested` was shown at `presented`, or was dropped when that's
+    // 0. it's late when it took more than half a refresh longer than `frame_interval` to follow
+    // the frame before it
+    fn presented(&mut self, requested: f64, presented: f64, frame_interval: Option<f64>) {
+        if presented <= 0. {
+            self.dropped_frames += 1;
+            return;
+        }
+        self.presented_frames += 1;
+        self.present_latency = Some((presented - requested).max(0.));
+        if let Some(last_presented) = self.last_presented.replace(presented) {
+            let interval = presented - last_presented;
+            self.present_interval = Some(interval);
+            if frame_interval.is_some_and(|frame_interval| interval > frame_interval * 1.5) {
+                self.missed_frames += 1;
+            }
+        }
+    }
+
+    pub(crate) fn write_stats(&self, frame_stats: &mut FrameStats) {
+        frame_stats.present_interval = self.present_interval;
+        frame_stats.present_latency = self.present_latency;
+        frame_stats.presented_frames = self.presented_frames;
+        frame_stats.missed_frames = self.missed_frames;
+        frame_stats.dropped_frames = self.dropped_frames;
+    }
+}
+
+impl MetalRenderer {
+    // counts the presentation of `drawable` into the stats, called right before it's presented
+    pub(crate) fn track_presentation(&self, drawable: &ProtocolObject<dyn MTLDrawable>) {
+        let frame_pacing = self.ivars().frame_pacing.clone();
+        let frame_interval = self.target_frame_interval();
+        let requested = CACurrentMediaTime();
+        let presented_handler =
+            RcBlock::new(move |drawable: NonNull<ProtocolObject<dyn MTLDrawable>>| {
+                let presented = unsafe { drawable.as_ref().presentedTime() };
+                let mut frame_pacing = frame_pacing.lock().unwrap();
+                frame_pacing.presented(requested, presented, frame_interval);
+            });
+        // metal copies the block, so it can be released after this frame
+        unsafe { drawable.addPresentedHandler(&*presented_handler as *const _ as *mut _) };
+    }
+
+    // the seconds a frame should stay on screen, none when the frames don't wait for the
+    // refresh of the screen
+    fn target_frame_interval(&self) -> Option<f64> {
+        if !self.vsync() {
+            return None;
+        }
+        let max_frames_per_second = self
+            .ivars()
+            .window
+            .get()
+            .and_then(|window| window.max_frames_per_second())
+            .unwrap_or(60);
+        let frames_per_second = match self.preferred_frames_per_second() {
+            0 => max_frames_per_second,
+            preferred => preferred.min(max_frames_per_second),
+        };
+        let frame_interval = 1. / frames_per_second.max(1) as f64;
+        Some(frame_interval.max(self.minimum_frame_duration().unwrap_or(0.)))
+    }
+
+    // starts counting the missed and dropped frames from zero, e.g. after a level loaded
+    pub fn reset_frame_pacing(&self) {
+        *self.ivars().frame_pacing.lock().unwrap() = FramePacing::default();
+    }
+}
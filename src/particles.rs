@@ -0,0 +1,102 @@
+use std::time::Instant;
+
+use core::cell::Cell;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{MTLBuffer, MTLDevice, MTLResourceOptions};
+
+use crate::{ComputePass, MetalRenderer, PrimitiveType, RenderPass, VertexInput};
+
+// the state of a particle in `compute_particles`, only the gpu reads and writes it
+#[allow(dead_code)]
+#[repr(C)]
+struct Particle {
+    position: [f32; 3],
+    velocity: [f32; 3],
+    // seconds since the particle was emitted, it's emitted again once it lived its lifetime
+    age: f32,
+    lifetime: f32,
+}
+
+// the parameters of `compute_particles` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct ParticleProperties {
+    emitter: [f32; 3],
+    delta_time: f32,
+    gravity: f32,
+}
+
+// a fountain of particles simulated on the gpu. `update` records a dispatch moving them and
+// writing their vertices, which `draw` renders as points in the same frame
+pub struct ParticleSystem {
+    particles: Retained<ProtocolObject<dyn MTLBuffer>>,
+    vertices: Retained<ProtocolObject<dyn MTLBuffer>>,
+    count: usize,
+    last_update: Cell<Option<Instant>>,
+    // where the particles are emitted, in world units
+    pub emitter: [f32; 3],
+    // the downward acceleration in units per second squared
+    pub gravity: f32,
+}
+
+impl ParticleSystem {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // advances the particles by the time passed since the previous update, the first update
+    // only emits them
+    pub fn update(&self, renderer: &MetalRenderer, compute_pass: &mut ComputePass) {
+        let now = Instant::now();
+        let last_update = self.last_update.replace(Some(now));
+        let delta_time = last_update.map_or(0., |last_update| (now - last_update).as_secs_f32());
+        let properties = renderer.frame_buffer(&[ParticleProperties {
+            emitter: self.emitter,
+            // a long stall, like a window being dragged, would fling the particles apart
+            delta_time: delta_time.min(0.1),
+            gravity: self.gravity,
+        }]);
+        compute_pass
+            .dispatch(
+                &renderer.compute_pipeline_state("compute_particles"),
+                (self.count, 1, 1),
+            )
+            .with_buffer(1, &self.vertices)
+            .with_buffer(2, &self.particles)
+            .with_buffer(3, &properties);
+    }
+
+    // draws the particles where the last update left them, with the point size of the renderer
+    pub fn draw(&self, renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+        render_pass.draw(
+            &renderer.pipeline_state(),
+            &self.vertices,
+            PrimitiveType::Point,
+            0..self.count,
+        );
+    }
+}
+
+impl MetalRenderer {
+    // creates `count` particles, emitted from the origin once the first update runs
+    pub fn create_particle_system(&self, count: usize) -> ParticleSystem {
+        // new buffers are zeroed, which the kernel takes for particles yet to be emitted
+        let buffer = |size: usize| {
+            self.device()
+                .newBufferWithLength_options(
+                    (count * size).max(1),
+                    MTLResourceOptions::MTLResourceStorageModePrivate,
+                )
+                .expect("Failed to create a particle buffer.")
+        };
+        ParticleSystem {
+            particles: buffer(core::mem::size_of::<Particle>()),
+            vertices: buffer(core::mem::size_of::<VertexInput>()),
+            count,
+            last_update: Cell::new(None),
+            emitter: [0., 0., 0.],
+            gravity: 1.,
+        }
+    }
+}
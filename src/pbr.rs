@@ -0,0 +1,249 @@
+use image::{Rgba, RgbaImage};
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLDevice, MTLSamplerAddressMode, MTLSamplerDescriptor, MTLSamplerMinMagFilter,
+    MTLSamplerMipFilter, MTLTexture,
+};
+
+use crate::{ArgumentTable, MetalRenderer};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the members of `PbrMaterialArguments` in triangle.metal
+const ALBEDO_INDEX: usize = 0;
+const METALLIC_ROUGHNESS_INDEX: usize = 1;
+const NORMAL_INDEX: usize = 2;
+const SAMPLER_INDEX: usize = 3;
+const BASE_COLOR_INDEX: usize = 4;
+const METALLIC_INDEX: usize = 5;
+const ROUGHNESS_INDEX: usize = 6;
+const NORMAL_SCALE_INDEX: usize = 7;
+
+// the metallic roughness material of glTF, shaded by `fragment_pbr`. the factors scale what the
+// textures hold, the textures that are missing leave the factors as they are
+#[derive(Clone, Debug)]
+pub struct PbrMaterial {
+    // linear rgba, multiplied with the albedo texture
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    // scales the tilt of the normals of the normal texture
+    pub normal_scale: f32,
+    // srgb, from `create_texture`
+    pub albedo_texture: Option<Texture>,
+    // the roughness in green and the metalness in blue, from `create_linear_texture`
+    pub metallic_roughness_texture: Option<Texture>,
+    // tangent space normals, from `create_linear_texture`
+    pub normal_texture: Option<Texture>,
+}
+
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        PbrMaterial {
+            base_color: [1.; 4],
+            metallic: 0.,
+            roughness: 0.5,
+            normal_scale: 1.,
+            albedo_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+        }
+    }
+}
+
+// the attributes of a vertex `vertex_pbr` reads at index 6 besides its `VertexInput`,
+// `SurfaceVertex` in triangle.metal
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub struct SurfaceVertex {
+    pub normal: [f32; 3],
+    // the direction the texture coordinates grow along u, w is 1 or -1 for the direction
+    // they grow along v
+    pub tangent: [f32; 4],
+    pub texture_coordinates: [f32; 2],
+}
+
+impl MetalRenderer {
+    // the argument table `fragment_pbr` takes for `material`, the missing textures are replaced
+    // by ones of a single texel that leave the factors as they are
+    pub fn create_pbr_material(&self, material: &PbrMaterial) -> ArgumentTable {
+        let texel = |texel, linear| {
+            let image = RgbaImage::from_pixel(1, 1, Rgba(texel));
+            if linear {
+                self.create_linear_texture(&image)
+            } else {
+                self.create_texture(&image)
+            }
+            .expect("Failed to create a material texture.")
+        };
+        let albedo = material
+            .albedo_texture
+            .clone()
+            .unwrap_or_else(|| texel([255; 4], false));
+        let metallic_roughness = material
+            .metallic_roughness_texture
+            .clone()
+            .unwrap_or_else(|| texel([255; 4], true));
+        // points straight out of the surface
+        let normal = material
+            .normal_texture
+            .clone()
+            .unwrap_or_else(|| texel([128, 128, 255, 255], true));
+
+        let sampler_descriptor = MTLSamplerDescriptor::new();
+        sampler_descriptor.setMinFilter(MTLSamplerMinMagFilter::Linear);
+        sampler_descriptor.setMagFilter(MTLSamplerMinMagFilter::Linear);
+        sampler_descriptor.setMipFilter(MTLSamplerMipFilter::Linear);
+        sampler_descriptor.setSAddressMode(MTLSamplerAddressMode::Repeat);
+        sampler_descriptor.setTAddressMode(MTLSamplerAddressMode::Repeat);
+        sampler_descriptor.setSupportArgumentBuffers(true);
+        let sampler = self
+            .device()
+            .newSamplerStateWithDescriptor(&sampler_descriptor)
+            .expect("Failed to create a material sampler.");
+
+        let mut arguments = self.create_argument_table("fragment_pbr", 0);
+        arguments.set_texture(ALBEDO_INDEX, &albedo);
+        arguments.set_texture(METALLIC_ROUGHNESS_INDEX, &metallic_roughness);
+        arguments.set_texture(NORMAL_INDEX, &normal);
+        arguments.set_sampler(SAMPLER_INDEX, &sampler);
+        arguments.set_constant(BASE_COLOR_INDEX, &material.base_color);
+        arguments.set_constant(METALLIC_INDEX, &material.metallic);
+        arguments.set_constant(ROUGHNESS_INDEX, &material.roughness);
+        arguments.set_constant(NORMAL_SCALE_INDEX, &material.normal_scale);
+        arguments
+    }
+}
+
+fn subtract(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: [f32; 3], factor: f32) -> [f32; 3] {
+    a.map(|value| value * factor)
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// zero stays zero
+fn normalize(vector: [f32; 3]) -> [f32; 3] {
+    let length = dot(vector, vector).sqrt();
+    if length > f32::EPSILON {
+        scale(vector, 1. / length)
+    } else {
+        vector
+    }
+}
+
+// any direction perpendicular to `normal`
+fn perpendicular(normal: [f32; 3]) -> [f32; 3] {
+    let axis = if normal[0].abs() < 0.9 {
+        [1., 0., 0.]
+    } else {
+        [0., 1., 0.]
+    };
+    normalize(cross(normal, axis))
+}
+
+// the surface attributes of the triangles `indices` lists. the normals that are missing are
+// the area weighted ones of the triangles around the vertices, the tangents that are missing
+// follow the texture coordinates, or any direction along the surface without them
+pub(crate) fn surface_vertices(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    normals: Option<Vec<[f32; 3]>>,
+    tangents: Option<Vec<[f32; 4]>>,
+    texture_coordinates: Option<Vec<[f32; 2]>>,
+) -> Vec<SurfaceVertex> {
+    let triangles = || {
+        indices
+            .chunks_exact(3)
+            .map(|triangle| [0, 1, 2].map(|vertex| triangle[vertex] as usize))
+            .filter(|triangle| triangle.iter().all(|&index| index < positions.len()))
+    };
+    let normals = normals.unwrap_or_else(|| {
+        let mut normals = vec![[0.; 3]; positions.len()];
+        for [a, b, c] in triangles() {
+            // the length of the cross product is twice the area of the triangle
+            let normal = cross(
+                subtract(positions[b], positions[a]),
+                subtract(positions[c], positions[a]),
+            );
+            for index in [a, b, c] {
+                normals[index] = add(normals[index], normal);
+            }
+        }
+        normals.into_iter().map(normalize).collect()
+    });
+    let uvs = texture_coordinates.unwrap_or_else(|| vec![[0.; 2]; positions.len()]);
+    let tangents = tangents.unwrap_or_else(|| {
+        let mut tangents = vec![[0.; 3]; positions.len()];
+        let mut bitangents = vec![[0.; 3]; positions.len()];
+        for [a, b, c] in triangles() {
+            let edge_1 = subtract(positions[b], positions[a]);
+            let edge_2 = subtract(positions[c], positions[a]);
+            let [du_1, dv_1] = [uvs[b][0] - uvs[a][0], uvs[b][1] - uvs[a][1]];
+            let [du_2, dv_2] = [uvs[c][0] - uvs[a][0], uvs[c][1] - uvs[a][1]];
+            let determinant = du_1 * dv_2 - du_2 * dv_1;
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+            let tangent = scale(
+                subtract(scale(edge_1, dv_2), scale(edge_2, dv_1)),
+                1. / determinant,
+            );
+            let bitangent = scale(
+                subtract(scale(edge_2, du_1), scale(edge_1, du_2)),
+                1. / determinant,
+            );
+            for index in [a, b, c] {
+                tangents[index] = add(tangents[index], tangent);
+                bitangents[index] = add(bitangents[index], bitangent);
+            }
+        }
+        tangents
+            .into_iter()
+            .zip(bitangents)
+            .zip(&normals)
+            .map(|((tangent, bitangent), &normal)| {
+                // made perpendicular to the normal, the handedness is where the bitangent lies
+                let tangent = normalize(subtract(tangent, scale(normal, dot(normal, tangent))));
+                let [x, y, z] = if dot(tangent, tangent) > 0. {
+                    tangent
+                } else {
+                    perpendicular(normal)
+                };
+                let sign = if dot(cross(normal, tangent), bitangent) < 0. {
+                    -1.
+                } else {
+                    1.
+                };
+                [x, y, z, sign]
+            })
+            .collect()
+    });
+    normals
+        .into_iter()
+        .zip(tangents)
+        .zip(uvs)
+        .map(|((normal, tangent), texture_coordinates)| SurfaceVertex {
+            normal,
+            tangent,
+            texture_coordinates,
+        })
+        .collect()
+}
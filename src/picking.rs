@@ -0,0 +1,320 @@
+// picking the entities drawn under a point of the view, for editor style interaction. the
+// draws tagged with `with_pick_id` are drawn again with their ids in place of their colors,
+// into a target of unsigned integers whose pixel under the point is read back once the gpu
+// completed the frame
+use objc2::{msg_send, rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSSize;
+use objc2_metal::{
+    MTLBuffer, MTLClearColor, MTLCommandBuffer, MTLCommandBufferStatus, MTLCommandEncoder,
+    MTLDevice, MTLOrigin, MTLPixelFormat, MTLRenderPassDescriptor, MTLResourceOptions,
+    MTLScissorRect, MTLSize, MTLTexture, MTLTextureUsage,
+};
+
+use crate::{
+    graph::FramePass,
+    reflection::BoundResource,
+    target::{attachment_texture, render_pass_descriptor, transient_attachment_texture},
+    BindingSlot, DepthStencilBuilder, DrawItem, LogLevel, MetalRenderer, PipelineDescriptor,
+    RenderPass,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+type CommandBuffer = Retained<ProtocolObject<dyn MTLCommandBuffer>>;
+
+// the ids the draws are tagged with, starting at 1. the background reads back as none
+pub type EntityId = u32;
+
+const PICK_FORMAT: MTLPixelFormat = MTLPixelFormat::R32Uint;
+const PICK_DEPTH_FORMAT: MTLPixelFormat = MTLPixelFormat::Depth32Float;
+
+// the index of the id in `fragment_pick`
+const PICK_ID_INDEX: usize = 0;
+
+// the ids and the depth of the draws at the size of the drawable
+struct PickTarget {
+    size: (usize, usize),
+    ids: Texture,
+    depth: Texture,
+}
+
+#[derive(Default)]
+pub(crate) struct PickingState {
+    // the point picked at every frame, in points from the top left of the view
+    point: Option<(f64, f64)>,
+    target: Option<PickTarget>,
+    // the id under the point, in shared memory
+    readback: Option<Retained<ProtocolObject<dyn MTLBuffer>>>,
+    // the frame reading the id back and the point it picked at, until it completed
+    pending: Option<(CommandBuffer, (f64, f64))>,
+    // the entity read back the last time and the point it was under
+    result: Option<((f64, f64), Option<EntityId>)>,
+}
+
+impl PickingState {
+    // drops the target and the readback buffer, they're created again with the next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.target = None;
+        self.readback = None;
+        self.pending = None;
+    }
+}
+
+// the tagged draws of a frame and where they're read back
+pub(crate) struct PickPass {
+    render_pass: RenderPass,
+    pass_descriptor: Retained<MTLRenderPassDescriptor>,
+    point: (f64, f64),
+    pixel: (usize, usize),
+}
+
+impl RenderPass {
+    // tags the last recorded draw with `id` for `MetalRenderer::pick`. only the draws of
+    // vertices are picked, not the indirect ones or the dispatches of mesh and tile functions
+    pub fn with_pick_id(&mut self, id: EntityId) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.pick_id = Some(id);
+        }
+        self
+    }
+}
+
+impl MetalRenderer {
+    // the entity drawn under `x`, `y`, in points from the top left of the view like the
+    // cursor position. the ids are read back without waiting for the gpu, so it returns the
+    // entity of the last completed frame picking at the point, none until one did or when
+    // nothing tagged was drawn there. the frames keep picking at the point until
+    // `stop_picking`, following what moves under it
+    pub fn pick(&self, x: f64, y: f64) -> Option<EntityId> {
+        self.read_back_pick();
+        let mut picking = self.ivars().picking.borrow_mut();
+        if picking.point != Some((x, y)) {
+            picking.point = Some((x, y));
+            drop(picking);
+            // a view drawing on demand draws a frame picking at the point
+            self.set_needs_redraw();
+            picking = self.ivars().picking.borrow_mut();
+        }
+        picking
+            .result
+            .filter(|(point, _)| *point == (x, y))
+            .and_then(|(_, entity)| entity)
+    }
+
+    // stops drawing the ids, the frames are drawn without them from the next one on
+    pub fn stop_picking(&self) {
+        let mut picking = self.ivars().picking.borrow_mut();
+        picking.point = None;
+        picking.result = None;
+    }
+
+    // takes the id out of the readback buffer once the frame writing it completed
+    fn read_back_pick(&self) {
+        let mut picking = self.ivars().picking.borrow_mut();
+        let Some((command_buffer, point)) = &picking.pending else {
+            return;
+        };
+        let point = *point;
+        match command_buffer.status() {
+            MTLCommandBufferStatus::Completed => {
+                let id = picking.readback.as_ref().map_or(0, |readback| unsafe {
+                    readback.contents().cast::<EntityId>().read()
+                });
+                picking.result = Some((point, (id != 0).then_some(id)));
+                picking.pending = None;
+            }
+            // the id of a failed frame is dropped, the next frame picks again
+            MTLCommandBufferStatus::Error => picking.pending = None,
+            _ => (),
+        }
+    }
+
+    // the tagged draws of `frame_passes` with the pipelines writing their ids, none while
+    // nothing is picked or the last pick is still in flight
+    pub(crate) fn record_pick_pass(
+        &self,
+        frame_passes: &[FramePass],
+        drawable_size: NSSize,
+    ) -> Option<PickPass> {
+        self.read_back_pick();
+        let point = {
+            let picking = self.ivars().picking.borrow();
+            if picking.pending.is_some() {
+                return None;
+            }
+            picking.point?
+        };
+        let scale_factor = self.scale_factor();
+        let (x, y) = (point.0 * scale_factor, point.1 * scale_factor);
+        if x < 0. || y < 0. || x >= drawable_size.width || y >= drawable_size.height {
+            self.ivars().picking.borrow_mut().result = Some((point, None));
+            return None;
+        }
+        let pixel = (x as usize, y as usize);
+
+        let items: Vec<DrawItem> = frame_passes
+            .iter()
+            .flat_map(|frame_pass| &frame_pass.render_pass.items)
+            .filter(|item| {
+                item.pick_id.is_some()
+                    && item.mesh_threadgroups.is_none()
+                    && !item.tile_dispatch
+                    && item.indirect_commands.is_none()
+            })
+            .filter_map(|item| self.pick_item(item))
+            .collect();
+        if items.is_empty() {
+            self.ivars().picking.borrow_mut().result = Some((point, None));
+            return None;
+        }
+
+        let size = (drawable_size.width as usize, drawable_size.height as usize);
+        let pass_descriptor = self.pick_pass_descriptor(size);
+        let render_pass = RenderPass {
+            items,
+            // only the pixel under the point is drawn
+            scissor: Some(MTLScissorRect {
+                x: pixel.0,
+                y: pixel.1,
+                width: 1,
+                height: 1,
+            }),
+            depth_stencil_state: Some(DepthStencilBuilder::new().build(self)),
+            label: Some("Picking".to_owned()),
+            ..self.frame_render_pass(drawable_size)
+        };
+        Some(PickPass {
+            render_pass,
+            pass_descriptor,
+            point,
+            pixel,
+        })
+    }
+
+    // `item` drawn with its id by the pipeline of its vertex function and `fragment_pick`,
+    // none when its pipeline didn't come from the pipeline cache
+    fn pick_item(&self, item: &DrawItem) -> Option<DrawItem> {
+        let descriptor = self
+            .ivars()
+            .pipeline_cache
+            .borrow()
+            .descriptor_of(&item.pipeline_state)?;
+        let descriptor = PipelineDescriptor {
+            fragment_function: "fragment_pick".to_owned(),
+            blend_mode: Default::default(),
+            color_format: PICK_FORMAT,
+            depth_format: Some(PICK_DEPTH_FORMAT),
+            sample_count: 1,
+            ..descriptor
+        };
+        let pipeline_state = self
+            .try_render_pipeline_state_for(&descriptor)
+            .inspect_err(|error| self.log(LogLevel::Warn, &format!("{error}.")))
+            .ok()?;
+        let id = self.frame_buffer(&[item.pick_id?]);
+        let slot = BindingSlot {
+            vertex: None,
+            fragment: Some(PICK_ID_INDEX),
+        };
+        let mut bindings = item.bindings.clone();
+        bindings.push((slot, BoundResource::Buffer(id)));
+        Some(DrawItem {
+            pipeline_state,
+            fragment_arguments: None,
+            occlusion_query: None,
+            depth_stencil: None,
+            bindings,
+            ..item.clone()
+        })
+    }
+
+    // the pass into the target of the ids, which is recreated when the drawable is resized
+    fn pick_pass_descriptor(&self, size: (usize, usize)) -> Retained<MTLRenderPassDescriptor> {
+        let mut picking = self.ivars().picking.borrow_mut();
+        if picking
+            .target
+            .as_ref()
+            .is_none_or(|target| target.size != size)
+        {
+            let device = self.device();
+            let ids =
+                attachment_texture(&device, PICK_FORMAT, size, 1, MTLTextureUsage::RenderTarget);
+            let depth = transient_attachment_texture(&device, PICK_DEPTH_FORMAT, size);
+            picking.target = Some(PickTarget { size, ids, depth });
+        }
+        let target = picking.target.as_ref().unwrap();
+        let background = MTLClearColor {
+            red: 0.,
+            green: 0.,
+            blue: 0.,
+            alpha: 0.,
+        };
+        render_pass_descriptor(&target.ids, None, Some(&target.depth), background)
+    }
+
+    // draws the ids and copies the one under the point into the readback buffer
+    pub(crate) fn encode_pick_pass(
+        &self,
+        command_buffer: &CommandBuffer,
+        pick_pass: &PickPass,
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+    ) {
+        let PickPass {
+            render_pass,
+            pass_descriptor,
+            point,
+            pixel,
+        } = pick_pass;
+        if !render_pass.encode(command_buffer, pass_descriptor, scene_properties) {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a render encoder for the picking.",
+            );
+            return;
+        }
+        let Some(blit_encoder) = command_buffer.blitCommandEncoder() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a blit encoder for the picking.",
+            );
+            return;
+        };
+        let mut picking = self.ivars().picking.borrow_mut();
+        let picking = &mut *picking;
+        let readback = picking.readback.get_or_insert_with(|| {
+            self.device()
+                .newBufferWithLength_options(
+                    core::mem::size_of::<EntityId>(),
+                    MTLResourceOptions::MTLResourceStorageModeShared,
+                )
+                .expect("Failed to create a buffer for the picking.")
+        });
+        let ids = &picking.target.as_ref().unwrap().ids;
+        let origin = MTLOrigin {
+            x: pixel.0,
+            y: pixel.1,
+            z: 0,
+        };
+        let size = MTLSize {
+            width: 1,
+            height: 1,
+            depth: 1,
+        };
+        // the selector doesn't fit on a line, it's sent with its parts on their own lines
+        let () = unsafe {
+            msg_send![
+                &blit_encoder,
+                copyFromTexture: &**ids,
+                sourceSlice: 0usize,
+                sourceLevel: 0usize,
+                sourceOrigin: origin,
+                sourceSize: size,
+                toBuffer: &**readback,
+                destinationOffset: 0usize,
+                destinationBytesPerRow: core::mem::size_of::<EntityId>(),
+                destinationBytesPerImage: core::mem::size_of::<EntityId>()
+            ]
+        };
+        blit_encoder.endEncoding();
+        picking.pending = Some((command_buffer.clone(), *point));
+    }
+}
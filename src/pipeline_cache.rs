@@ -0,0 +1,465 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use core::cell::Cell;
+
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::{
+    NSArray, NSError, NSFileManager, NSSearchPathDirectory, NSSearchPathDomainMask, NSString, NSURL,
+};
+use objc2_metal::{
+    MTLBinaryArchive, MTLBinaryArchiveDescriptor, MTLBlendFactor, MTLBlendOperation, MTLDevice,
+    MTLPixelFormat, MTLRenderPipelineColorAttachmentDescriptor, MTLRenderPipelineDescriptor,
+    MTLRenderPipelineState, MTLVertexDescriptor, MTLVertexFormat,
+};
+
+use crate::{LogLevel, MetalRenderer};
+
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+
+// how the color a pipeline outputs is combined with the color already in the target
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    // replaces it
+    #[default]
+    Opaque,
+    // blends by the alpha of the output, for straight alpha colors
+    Alpha,
+    // for colors already multiplied by their alpha
+    Premultiplied,
+    // adds the output weighted by its alpha, for glows and particles
+    Additive,
+    // multiplies it with the output, for tints and shadows
+    Multiply,
+    // the factors and operations of the blending set one by one
+    Custom(BlendState),
+}
+
+// the blend equations of the color and the alpha of a color attachment, each of them the
+// output times its source factor combined with the target times its destination factor
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlendState {
+    pub source_rgb: MTLBlendFactor,
+    pub destination_rgb: MTLBlendFactor,
+    pub rgb_operation: MTLBlendOperation,
+    pub source_alpha: MTLBlendFactor,
+    pub destination_alpha: MTLBlendFactor,
+    pub alpha_operation: MTLBlendOperation,
+}
+
+impl BlendState {
+    // adds the weighted output and target together, the operation of the blend modes
+    pub fn new(
+        source_rgb: MTLBlendFactor,
+        destination_rgb: MTLBlendFactor,
+        source_alpha: MTLBlendFactor,
+        destination_alpha: MTLBlendFactor,
+    ) -> Self {
+        Self {
+            source_rgb,
+            destination_rgb,
+            rgb_operation: MTLBlendOperation::Add,
+            source_alpha,
+            destination_alpha,
+            alpha_operation: MTLBlendOperation::Add,
+        }
+    }
+}
+
+impl BlendMode {
+    // none for opaque pipelines, which leave the blending off
+    pub fn blend_state(self) -> Option<BlendState> {
+        let blend_state = match self {
+            BlendMode::Opaque => return None,
+            BlendMode::Alpha => BlendState::new(
+                MTLBlendFactor::SourceAlpha,
+                MTLBlendFactor::OneMinusSourceAlpha,
+                MTLBlendFactor::One,
+                MTLBlendFactor::OneMinusSourceAlpha,
+            ),
+            BlendMode::Premultiplied => BlendState::new(
+                MTLBlendFactor::One,
+                MTLBlendFactor::OneMinusSourceAlpha,
+                MTLBlendFactor::One,
+                MTLBlendFactor::OneMinusSourceAlpha,
+            ),
+            BlendMode::Additive => BlendState::new(
+                MTLBlendFactor::SourceAlpha,
+                MTLBlendFactor::One,
+                MTLBlendFactor::One,
+                MTLBlendFactor::One,
+            ),
+            BlendMode::Multiply => BlendState::new(
+                MTLBlendFactor::DestinationColor,
+                MTLBlendFactor::Zero,
+                MTLBlendFactor::DestinationAlpha,
+                MTLBlendFactor::Zero,
+            ),
+            BlendMode::Custom(blend_state) => blend_state,
+        };
+        Some(blend_state)
+    }
+
+    pub(crate) fn configure(self, color_attachment: &MTLRenderPipelineColorAttachmentDescriptor) {
+        let Some(blend_state) = self.blend_state() else {
+            return;
+        };
+        color_attachment.setBlendingEnabled(true);
+        color_attachment.setSourceRGBBlendFactor(blend_state.source_rgb);
+        color_attachment.setDestinationRGBBlendFactor(blend_state.destination_rgb);
+        color_attachment.setRgbBlendOperation(blend_state.rgb_operation);
+        color_attachment.setSourceAlphaBlendFactor(blend_state.source_alpha);
+        color_attachment.setDestinationAlphaBlendFactor(blend_state.destination_alpha);
+        color_attachment.setAlphaBlendOperation(blend_state.alpha_operation);
+    }
+}
+
+// an attribute a `[[stage_in]]` vertex function reads at `[[attribute(n)]]`, n being its index
+// in the layout
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+    pub format: MTLVertexFormat,
+    // in bytes from the start of the vertex
+    pub offset: usize,
+    pub buffer_index: usize,
+}
+
+// the vertex descriptor of a pipeline. the default one is empty, for vertex functions reading
+// the vertex buffers themselves like most of the ones of triangle.metal. `VertexLayout::of`
+// takes it from the struct of the vertices
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub attributes: Vec<VertexAttribute>,
+    // the size of a vertex in bytes for every buffer index the attributes read from
+    pub strides: Vec<(usize, usize)>,
+}
+
+impl VertexLayout {
+    pub(crate) fn mtl_vertex_descriptor(&self) -> Option<Retained<MTLVertexDescriptor>> {
+        if self.attributes.is_empty() {
+            return None;
+        }
+        let descriptor = MTLVertexDescriptor::vertexDescriptor();
+        for (index, attribute) in self.attributes.iter().enumerate() {
+            let attribute_descriptor =
+                unsafe { descriptor.attributes().objectAtIndexedSubscript(index) };
+            attribute_descriptor.setFormat(attribute.format);
+            unsafe {
+                attribute_descriptor.setOffset(attribute.offset);
+                attribute_descriptor.setBufferIndex(attribute.buffer_index);
+            }
+        }
+        for &(buffer_index, stride) in &self.strides {
+            unsafe {
+                descriptor
+                    .layouts()
+                    .objectAtIndexedSubscript(buffer_index)
+                    .setStride(stride)
+            };
+        }
+        Some(descriptor)
+    }
+}
+
+// the state a render pipeline is built from, pipelines are cached by it
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDescriptor {
+    pub vertex_function: String,
+    pub fragment_function: String,
+    pub vertex_layout: VertexLayout,
+    pub blend_mode: BlendMode,
+    pub color_format: MTLPixelFormat,
+    // a depth format with stencil is also used for the stencil attachment
+    pub depth_format: Option<MTLPixelFormat>,
+    pub sample_count: usize,
+    // whether indirect command buffers can draw with the pipeline, see `CulledInstances`
+    pub indirect_commands: bool,
+}
+
+impl PipelineDescriptor {
+    // the same pipeline blending its output with `blend_mode`, e.g. for translucent geometry
+    // drawn after the opaque one
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    // the same pipeline fetching the vertices of a `[[stage_in]]` vertex function with
+    // `vertex_layout`, e.g. `VertexLayout::of::<VertexInput>(1)`
+    pub fn with_vertex_layout(mut self, vertex_layout: VertexLayout) -> Self {
+        self.vertex_layout = vertex_layout;
+        self
+    }
+}
+
+// render pipelines keyed by the state they were built from, so materials and objects drawn
+// the same way share one pipeline rather than building their own every frame
+#[derive(Default)]
+pub struct PipelineCache {
+    pipeline_states: HashMap<PipelineDescriptor, PipelineState>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, descriptor: &PipelineDescriptor) -> Option<PipelineState> {
+        self.pipeline_states.get(descriptor).cloned()
+    }
+
+    // returns the pipeline built from `descriptor`, building it with the shaders of
+    // `renderer` on first use
+    pub fn get_or_create(
+        &mut self,
+        renderer: &MetalRenderer,
+        descriptor: &PipelineDescriptor,
+    ) -> PipelineState {
+        if let Some(pipeline_state) = self.get(descriptor) {
+            return pipeline_state;
+        }
+        let pipeline_state = renderer.create_pipeline_state(descriptor);
+        self.insert(descriptor.clone(), pipeline_state.clone());
+        pipeline_state
+    }
+
+    pub fn insert(&mut self, descriptor: PipelineDescriptor, pipeline_state: PipelineState) {
+        self.pipeline_states.insert(descriptor, pipeline_state);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipeline_states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipeline_states.is_empty()
+    }
+
+    // drops every pipeline, needed once the shaders they were built from changed
+    pub fn clear(&mut self) {
+        self.pipeline_states.clear();
+    }
+
+    // the descriptors of the cached pipelines drawing into `color_format`
+    pub(crate) fn descriptors_with_color_format(
+        &self,
+        color_format: MTLPixelFormat,
+    ) -> Vec<PipelineDescriptor> {
+        self.pipeline_states
+            .keys()
+            .filter(|descriptor| descriptor.color_format == color_format)
+            .cloned()
+            .collect()
+    }
+
+    // the descriptor `pipeline_state` was built from, none for a pipeline built elsewhere
+    pub(crate) fn descriptor_of(
+        &self,
+        pipeline_state: &PipelineState,
+    ) -> Option<PipelineDescriptor> {
+        self.pipeline_states
+            .iter()
+            .find(|(_, cached)| Retained::as_ptr(cached) == Retained::as_ptr(pipeline_state))
+            .map(|(descriptor, _)| descriptor.clone())
+    }
+}
+
+impl MetalRenderer {
+    // a descriptor for the pair of shader functions with the formats and the sample count the
+    // view has now, opaque and without a vertex layout
+    pub fn pipeline_descriptor(
+        &self,
+        vertex_function: &str,
+        fragment_function: &str,
+    ) -> PipelineDescriptor {
+        PipelineDescriptor {
+            vertex_function: vertex_function.to_owned(),
+            fragment_function: fragment_function.to_owned(),
+            vertex_layout: VertexLayout::default(),
+            blend_mode: BlendMode::default(),
+            color_format: self.color_format(),
+            depth_format: self
+                .depth_format()
+                .map(|depth_format| depth_format.mtl_pixel_format()),
+            sample_count: self.sample_count(),
+            indirect_commands: false,
+        }
+    }
+}
+
+// a pipeline archive that couldn't be opened or written, holds the reason
+#[derive(Debug)]
+pub struct PipelineArchiveError(pub String);
+
+impl fmt::Display for PipelineArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to access the pipeline archive: {}", self.0)
+    }
+}
+
+impl std::error::Error for PipelineArchiveError {}
+
+impl From<Retained<NSError>> for PipelineArchiveError {
+    fn from(error: Retained<NSError>) -> Self {
+        PipelineArchiveError(error.localizedDescription().to_string())
+    }
+}
+
+fn file_url(path: &Path) -> Retained<NSURL> {
+    unsafe { NSURL::fileURLWithPath(&NSString::from_str(&path.to_string_lossy())) }
+}
+
+// where the pipelines of the application are archived for `device`, in its folder of the
+// user's Application Support. a file per gpu since the compiled code is specific to one
+pub(crate) fn default_archive_path(device: &ProtocolObject<dyn MTLDevice>) -> Option<PathBuf> {
+    let file_manager = unsafe { NSFileManager::defaultManager() };
+    let urls = unsafe {
+        file_manager.URLsForDirectory_inDomains(
+            NSSearchPathDirectory::NSApplicationSupportDirectory,
+            NSSearchPathDomainMask::NSUserDomainMask,
+        )
+    };
+    let application_support = unsafe { urls.firstObject()?.path() }?;
+    let executable = std::env::current_exe().ok()?;
+    let application = executable.file_stem()?;
+    let file_name = format!("pipelines-{:x}.metallib", device.registryID());
+    Some(
+        PathBuf::from(application_support.to_string())
+            .join(application)
+            .join(file_name),
+    )
+}
+
+// compiled pipelines kept on disk between runs. pipelines built while it's open look their
+// functions up in it before compiling them and are added to it, it's written back when it's
+// dropped with the last renderer using it
+pub(crate) struct PipelineArchive {
+    archive: Retained<ProtocolObject<dyn MTLBinaryArchive>>,
+    path: PathBuf,
+    // whether pipelines were added since it was loaded or saved
+    modified: Cell<bool>,
+}
+
+impl PipelineArchive {
+    // loads the archive at `path`, or starts an empty one when there's none yet or it can't be
+    // read, like one written by another version of the os
+    pub(crate) fn open(
+        device: &ProtocolObject<dyn MTLDevice>,
+        path: PathBuf,
+    ) -> Result<Self, PipelineArchiveError> {
+        let descriptor = MTLBinaryArchiveDescriptor::new();
+        let archive = if path.exists() {
+            descriptor.setUrl(Some(&file_url(&path)));
+            device
+                .newBinaryArchiveWithDescriptor_error(&descriptor)
+                .ok()
+        } else {
+            None
+        };
+        let archive = match archive {
+            Some(archive) => archive,
+            None => {
+                descriptor.setUrl(None);
+                device.newBinaryArchiveWithDescriptor_error(&descriptor)?
+            }
+        };
+        Ok(PipelineArchive {
+            archive,
+            path,
+            modified: Cell::new(false),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // lets pipelines built from `descriptor` find their functions in the archive
+    pub(crate) fn attach(&self, descriptor: &MTLRenderPipelineDescriptor) {
+        let archives = NSArray::from_slice(&[&*self.archive]);
+        descriptor.setBinaryArchives(Some(&archives));
+    }
+
+    // compiles the functions of the pipeline `descriptor` describes into the archive
+    pub(crate) fn add(
+        &self,
+        descriptor: &MTLRenderPipelineDescriptor,
+    ) -> Result<(), PipelineArchiveError> {
+        self.archive
+            .addRenderPipelineFunctionsWithDescriptor_error(descriptor)?;
+        self.modified.set(true);
+        Ok(())
+    }
+
+    // writes the archive to its path when pipelines were added to it
+    pub(crate) fn save(&self) -> Result<(), PipelineArchiveError> {
+        if !self.modified.get() {
+            return Ok(());
+        }
+        if let Some(directory) = self.path.parent() {
+            std::fs::create_dir_all(directory)
+                .map_err(|error| PipelineArchiveError(error.to_string()))?;
+        }
+        self.archive.serializeToURL_error(&file_url(&self.path))?;
+        self.modified.set(false);
+        Ok(())
+    }
+}
+
+impl Drop for PipelineArchive {
+    fn drop(&mut self) {
+        // there's nobody left to report a failure to, the next run builds the pipelines again
+        let _ = self.save();
+    }
+}
+
+impl MetalRenderer {
+    // keeps the compiled pipelines in the archive at `path` from now on, saving the previous
+    // archive first. None stops archiving them
+    pub fn set_pipeline_archive_path(&self, path: Option<PathBuf>) {
+        if let Err(error) = self.save_pipeline_archive() {
+            self.log(LogLevel::Warn, &format!("{error}."));
+        }
+        self.ivars().pipeline_archive.replace(None);
+        if let Some(path) = path {
+            self.open_pipeline_archive(path);
+        }
+    }
+
+    pub fn pipeline_archive_path(&self) -> Option<PathBuf> {
+        let pipeline_archive = self.ivars().pipeline_archive.borrow();
+        pipeline_archive
+            .as_ref()
+            .map(|pipeline_archive| pipeline_archive.path().to_owned())
+    }
+
+    // writes the pipelines compiled so far to the archive, which otherwise happens when the
+    // last renderer using it is dropped
+    pub fn save_pipeline_archive(&self) -> Result<(), PipelineArchiveError> {
+        match self.ivars().pipeline_archive.borrow().as_ref() {
+            Some(pipeline_archive) => pipeline_archive.save(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn open_pipeline_archive(&self, path: PathBuf) {
+        match PipelineArchive::open(&self.device(), path) {
+            Ok(pipeline_archive) => {
+                let message = format!(
+                    "Using the pipeline archive at {}.",
+                    pipeline_archive.path().display()
+                );
+                self.log(LogLevel::Debug, &message);
+                self.ivars()
+                    .pipeline_archive
+                    .replace(Some(Rc::new(pipeline_archive)));
+            }
+            Err(error) => self.log(LogLevel::Warn, &format!("{error}.")),
+        }
+    }
+}
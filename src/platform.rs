@@ -0,0 +1,220 @@
+// the windows and views of appkit on macos and of uikit on ios, behind the few operations the
+// renderer needs from them
+
+use objc2::rc::Retained;
+use objc2_foundation::NSSize;
+use raw_window_handle::RawWindowHandle;
+use tao::window::Window;
+
+use crate::{EdrHeadroom, RendererError};
+
+#[cfg(target_os = "macos")]
+pub(crate) use objc2_app_kit::{NSView as NativeView, NSWindow as NativeWindow};
+#[cfg(target_os = "ios")]
+pub(crate) use objc2_ui_kit::{UIView as NativeView, UIWindow as NativeWindow};
+
+#[cfg(target_os = "macos")]
+use objc2_app_kit::NSColor;
+#[cfg(target_os = "macos")]
+use objc2_foundation::NSPoint;
+#[cfg(target_os = "macos")]
+use tao::platform::macos::WindowExtMacOS;
+
+#[cfg(target_os = "ios")]
+use objc2::ClassType;
+#[cfg(target_os = "ios")]
+use objc2_ui_kit::UIColor;
+#[cfg(target_os = "ios")]
+use tao::platform::ios::WindowExtIOS;
+
+// returns a strong reference to the NSWindow or UIWindow backing a tao window.
+// tao owns the window and releases it when the tao `Window` is dropped, `ns_window` only lends
+// the pointer out. `Retained::retain` adds a reference of our own that is released when the
+// `Retained` is dropped, so the window outlives whichever of the two goes away first.
+// taking the pointer over with `Retained::from_raw` would instead release tao's reference a
+// second time on teardown
+pub(crate) fn retained_window(window: &Window) -> Result<Retained<NativeWindow>, RendererError> {
+    #[cfg(target_os = "macos")]
+    let native_window = window.ns_window() as *mut NativeWindow;
+    #[cfg(target_os = "ios")]
+    let native_window = window.ui_window() as *mut NativeWindow;
+    // SAFETY: the pointer comes from a live tao window, so it's a valid window for the
+    // duration of this call, and retaining it doesn't touch the reference tao holds
+    unsafe { Retained::retain(native_window) }.ok_or(RendererError::WindowUnavailable)
+}
+
+// the view of an appkit or uikit window handle and the window it's in, retained like
+// `retained_window`
+//
+// SAFETY: the handle has to point to a live view
+pub(crate) unsafe fn handle_view(
+    handle: RawWindowHandle,
+) -> Result<(Retained<NativeView>, Retained<NativeWindow>), RendererError> {
+    let view = match handle {
+        #[cfg(target_os = "macos")]
+        RawWindowHandle::AppKit(handle) => handle.ns_view.as_ptr(),
+        #[cfg(target_os = "ios")]
+        RawWindowHandle::UiKit(handle) => handle.ui_view.as_ptr(),
+        _ => return Err(RendererError::UnsupportedWindowHandle),
+    };
+    let view = unsafe { Retained::retain(view as *mut NativeView) }
+        .ok_or(RendererError::WindowUnavailable)?;
+    let window = view.window().ok_or(RendererError::WindowUnavailable)?;
+    Ok((view, window))
+}
+
+// what the renderer asks of its window. a uikit window fills its screen, the operations sizing
+// and placing it do nothing on ios
+pub(crate) trait NativeWindowExt {
+    // the pixels per point of the screen the window is on
+    fn scale_factor(&self) -> f64;
+
+    // the view the view of the renderer is added to
+    fn content_view(&self) -> Option<Retained<NativeView>>;
+
+    fn max_frames_per_second(&self) -> Option<isize>;
+
+    fn edr_headroom(&self) -> EdrHeadroom;
+
+    // the size of the content in points and the origin of the window frame
+    fn content_frame(&self) -> (NSSize, Option<[f64; 2]>);
+
+    // centers the window without an origin
+    fn set_content_frame(&self, size: NSSize, origin: Option<[f64; 2]>);
+
+    fn set_min_content_size(&self, min_content_size: NSSize);
+
+    // sets up a window the renderer was just created in. the title and the position belong to
+    // the application
+    fn configure(&self, min_content_size: NSSize);
+
+    // lets what's behind the window show through wherever its views are transparent
+    fn set_transparent(&self, transparent: bool);
+}
+
+#[cfg(target_os = "macos")]
+impl NativeWindowExt for NativeWindow {
+    fn scale_factor(&self) -> f64 {
+        self.backingScaleFactor()
+    }
+
+    fn content_view(&self) -> Option<Retained<NativeView>> {
+        self.contentView()
+    }
+
+    fn max_frames_per_second(&self) -> Option<isize> {
+        self.screen()
+            .map(|screen| unsafe { screen.maximumFramesPerSecond() })
+    }
+
+    fn edr_headroom(&self) -> EdrHeadroom {
+        let Some(screen) = self.screen() else {
+            return EdrHeadroom::default();
+        };
+        unsafe {
+            EdrHeadroom {
+                current: screen.maximumExtendedDynamicRangeColorComponentValue(),
+                potential: screen.maximumPotentialExtendedDynamicRangeColorComponentValue(),
+                reference: screen.maximumReferenceExtendedDynamicRangeColorComponentValue(),
+            }
+        }
+    }
+
+    fn content_frame(&self) -> (NSSize, Option<[f64; 2]>) {
+        let frame = self.frame();
+        let content_size = self.contentRectForFrameRect(frame).size;
+        (content_size, Some([frame.origin.x, frame.origin.y]))
+    }
+
+    fn set_content_frame(&self, size: NSSize, origin: Option<[f64; 2]>) {
+        self.setContentSize(size);
+        match origin {
+            Some([x, y]) => unsafe { self.setFrameOrigin(NSPoint::new(x, y)) },
+            None => self.center(),
+        }
+    }
+
+    fn set_min_content_size(&self, min_content_size: NSSize) {
+        unsafe { self.setContentMinSize(min_content_size) };
+    }
+
+    fn configure(&self, min_content_size: NSSize) {
+        self.set_min_content_size(min_content_size);
+    }
+
+    fn set_transparent(&self, transparent: bool) {
+        let background_color = if transparent {
+            unsafe { NSColor::clearColor() }
+        } else {
+            unsafe { NSColor::windowBackgroundColor() }
+        };
+        self.setOpaque(!transparent);
+        self.setBackgroundColor(Some(&background_color));
+    }
+}
+
+#[cfg(target_os = "ios")]
+impl NativeWindowExt for NativeWindow {
+    fn scale_factor(&self) -> f64 {
+        self.screen().scale()
+    }
+
+    // the window is the root of its views, and the renderer has no view controller of its own
+    fn content_view(&self) -> Option<Retained<NativeView>> {
+        Some(Retained::into_super(self.retain()))
+    }
+
+    fn max_frames_per_second(&self) -> Option<isize> {
+        Some(self.screen().maximumFramesPerSecond())
+    }
+
+    // ios has no reference presets, the reference headroom is the one of sdr
+    fn edr_headroom(&self) -> EdrHeadroom {
+        let screen = self.screen();
+        unsafe {
+            EdrHeadroom {
+                current: screen.currentEDRHeadroom(),
+                potential: screen.potentialEDRHeadroom(),
+                reference: 1.,
+            }
+        }
+    }
+
+    fn content_frame(&self) -> (NSSize, Option<[f64; 2]>) {
+        (self.bounds().size, None)
+    }
+
+    fn set_content_frame(&self, _size: NSSize, _origin: Option<[f64; 2]>) {}
+
+    fn set_min_content_size(&self, _min_content_size: NSSize) {}
+
+    fn configure(&self, _min_content_size: NSSize) {}
+
+    // the views of the app behind the window show through, there's nothing else on ios
+    fn set_transparent(&self, transparent: bool) {
+        let background_color = if transparent {
+            unsafe { UIColor::clearColor() }
+        } else {
+            unsafe { UIColor::systemBackgroundColor() }
+        };
+        unsafe { self.setOpaque(!transparent) };
+        self.setBackgroundColor(Some(&background_color));
+    }
+}
+
+// the pixels per point of the screen `view` is on, 1 outside a window
+pub(crate) fn view_scale_factor(view: &NativeView) -> f64 {
+    view.window().map_or(1., |window| window.scale_factor())
+}
+
+// the size in pixels of the bounds of `view`
+pub(crate) fn backing_size(view: &NativeView) -> NSSize {
+    #[cfg(target_os = "macos")]
+    return unsafe { view.convertRectToBacking(view.bounds()) }.size;
+    #[cfg(target_os = "ios")]
+    {
+        let size = view.bounds().size;
+        let scale_factor = view_scale_factor(view);
+        NSSize::new(size.width * scale_factor, size.height * scale_factor)
+    }
+}
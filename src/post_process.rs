@@ -0,0 +1,293 @@
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSSize;
+use objc2_metal::{
+    MTLBuffer, MTLDevice, MTLPixelFormat, MTLRenderPassDescriptor, MTLResourceOptions, MTLTexture,
+    MTLTextureUsage,
+};
+
+use crate::{
+    graph::{FramePass, FrameTarget},
+    target::attachment_texture,
+    ArgumentTable, MetalRenderer, PipelineDescriptor, PrimitiveType, RenderPass, RenderTarget,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the scene is rendered with values above 1 kept for the bloom and the tone mapping
+const HDR_FORMAT: MTLPixelFormat = MTLPixelFormat::RGBA16Float;
+
+// the members of `PostProcessArguments` in triangle.metal
+const SOURCE_INDEX: usize = 0;
+const BLOOM_INDEX: usize = 1;
+const EXPOSURE_INDEX: usize = 2;
+const BLOOM_THRESHOLD_INDEX: usize = 3;
+const BLOOM_INTENSITY_INDEX: usize = 4;
+const TONEMAP_INDEX: usize = 5;
+
+// the stages of the chain the frames go through before they reach the drawable, each can be
+// turned off on its own
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PostProcess {
+    // maps the scene into the range of the drawable with a filmic curve, it's clipped otherwise
+    pub tonemap: bool,
+    // scales the scene before it's tone mapped
+    pub exposure: f32,
+    // blurs what's brighter than the threshold and adds it over the scene
+    pub bloom: bool,
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    // smooths the edges of the tone mapped frame
+    pub fxaa: bool,
+}
+
+impl Default for PostProcess {
+    fn default() -> Self {
+        PostProcess {
+            tonemap: true,
+            exposure: 1.,
+            bloom: true,
+            bloom_threshold: 0.8,
+            bloom_intensity: 0.6,
+            fxaa: true,
+        }
+    }
+}
+
+// a fullscreen pass of the chain, into `target` or the drawable when there's none
+struct PostPass {
+    fragment_function: &'static str,
+    target: Option<RenderTarget>,
+    arguments: Rc<ArgumentTable>,
+}
+
+// the targets and the passes of the chain for the settings, the size and the formats of the
+// last post-processed frame
+struct PostProcessResources {
+    settings: PostProcess,
+    size: (usize, usize),
+    drawable_format: MTLPixelFormat,
+    scene: RenderTarget,
+    passes: Vec<PostPass>,
+    // the fullscreen triangle has no vertices, the draws still take a buffer
+    placeholder: Retained<ProtocolObject<dyn MTLBuffer>>,
+}
+
+#[derive(Default)]
+pub(crate) struct PostProcessState {
+    settings: Option<PostProcess>,
+    resources: Option<PostProcessResources>,
+}
+
+impl PostProcessState {
+    // drops the targets and pipelines of the chain, they're created again with the next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.resources = None;
+    }
+}
+
+// a frame drawn into the hdr target and through the passes of the chain into the drawable
+pub(crate) struct PostProcessedFrame {
+    // the pass of the frame into the hdr target
+    pub(crate) pass_descriptor: Retained<MTLRenderPassDescriptor>,
+    pub(crate) passes: Vec<FramePass>,
+}
+
+impl MetalRenderer {
+    // renders the frames into an hdr texture and draws them into the drawable through the
+    // stages of `post_process`, none draws them straight into it. only the frames of the render
+    // callback are post-processed, not the ones of a render graph or the ray traced ones, and
+    // the text, the ui and the overlay are drawn after the chain
+    pub fn set_post_process(&self, post_process: Option<PostProcess>) {
+        self.ivars().post_process.borrow_mut().settings = post_process;
+    }
+
+    pub fn post_process(&self) -> Option<PostProcess> {
+        self.ivars().post_process.borrow().settings
+    }
+
+    // whether the draws of the next frame go into the hdr target of the chain
+    pub(crate) fn is_post_processing(&self) -> bool {
+        self.ivars().post_process.borrow().settings.is_some()
+            && self.ivars().render_graph_callback.borrow().is_none()
+            && !self.is_ray_tracing()
+    }
+
+    // the pixel format the draws of the frame render into, the hdr format while the frames are
+    // post-processed and the one of the view otherwise. the pipelines and render targets of the
+    // renderer are created with it
+    pub fn color_format(&self) -> MTLPixelFormat {
+        if self.is_post_processing() {
+            HDR_FORMAT
+        } else {
+            self.pixel_format().mtl_pixel_format()
+        }
+    }
+
+    // the target the draws of this frame go to and the passes drawing it into the drawable,
+    // none when the frame isn't post-processed. the targets and the passes are recreated when
+    // the settings, the size or the formats changed
+    pub(crate) fn prepare_post_process(&self, drawable_size: NSSize) -> Option<PostProcessedFrame> {
+        if !self.is_post_processing() {
+            return None;
+        }
+        let settings = self.post_process()?;
+        let size = (drawable_size.width as usize, drawable_size.height as usize);
+        let drawable_format = self.pixel_format().mtl_pixel_format();
+        // the state is only borrowed mutably to store new resources, creating them looks up
+        // the color format
+        let reusable = self
+            .ivars()
+            .post_process
+            .borrow()
+            .resources
+            .as_ref()
+            .is_some_and(|resources| {
+                resources.settings == settings
+                    && resources.size == size
+                    && resources.drawable_format == drawable_format
+                    && resources.scene.is_compatible_with(self)
+            });
+        if !reusable {
+            let resources = self.create_post_process_resources(settings, size);
+            self.ivars().post_process.borrow_mut().resources = Some(resources);
+        }
+        let post_process = self.ivars().post_process.borrow();
+        let resources = post_process.resources.as_ref().unwrap();
+
+        let passes = resources
+            .passes
+            .iter()
+            .map(|pass| {
+                let color_format = pass
+                    .target
+                    .as_ref()
+                    .map_or(drawable_format, |target| target.texture().pixelFormat());
+                let descriptor = PipelineDescriptor {
+                    color_format,
+                    depth_format: None,
+                    sample_count: 1,
+                    ..self.pipeline_descriptor("vertex_fullscreen", pass.fragment_function)
+                };
+                let mut render_pass = RenderPass::default();
+                render_pass
+                    .draw(
+                        &self.render_pipeline_state_for(&descriptor),
+                        &resources.placeholder,
+                        PrimitiveType::Triangle,
+                        0..3,
+                    )
+                    .with_fragment_arguments(&pass.arguments);
+                let target = match &pass.target {
+                    Some(target) => FrameTarget::Texture(target.pass_descriptor()),
+                    None => FrameTarget::Backbuffer {
+                        first: true,
+                        later_write: false,
+                    },
+                };
+                FramePass {
+                    target,
+                    render_pass,
+                }
+            })
+            .collect();
+        Some(PostProcessedFrame {
+            pass_descriptor: resources.scene.pass_descriptor(),
+            passes,
+        })
+    }
+
+    fn create_post_process_resources(
+        &self,
+        settings: PostProcess,
+        (width, height): (usize, usize),
+    ) -> PostProcessResources {
+        let device = self.device();
+        let drawable_format = self.pixel_format().mtl_pixel_format();
+        let usage = MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead;
+        let create_target = |pixel_format, size| {
+            let color = attachment_texture(&device, pixel_format, size, 1, usage);
+            RenderTarget::new(&color, None, None)
+        };
+
+        // the scene target has the sample count and the depth format of the view
+        let mut scene = self.create_render_target(width, height);
+        scene.clear_color = self.ivars().surface.get().unwrap().clear_color();
+        let scene_texture = scene.texture().clone();
+
+        let arguments =
+            |fragment_function: &str, source: &Texture, bloom: Option<&RenderTarget>| {
+                let mut table = self.create_argument_table(fragment_function, 0);
+                table.set_texture(SOURCE_INDEX, source);
+                // the composite pass always samples a bloom texture, the scene adds nothing to it
+                // with the intensity at zero
+                table.set_texture(
+                    BLOOM_INDEX,
+                    bloom.map_or(&scene_texture, |bloom| bloom.texture()),
+                );
+                let bloom_intensity = if bloom.is_some() {
+                    settings.bloom_intensity
+                } else {
+                    0.
+                };
+                table.set_constant(EXPOSURE_INDEX, &settings.exposure);
+                table.set_constant(BLOOM_THRESHOLD_INDEX, &settings.bloom_threshold);
+                table.set_constant(BLOOM_INTENSITY_INDEX, &bloom_intensity);
+                table.set_constant(TONEMAP_INDEX, &(settings.tonemap as u32));
+                Rc::new(table)
+            };
+
+        let mut passes = Vec::new();
+        // the bright parts are blurred at half the size, back and forth between two targets
+        let bloom = settings.bloom.then(|| {
+            let size = ((width / 2).max(1), (height / 2).max(1));
+            let bright = create_target(HDR_FORMAT, size);
+            let blurred = create_target(HDR_FORMAT, size);
+            passes.push(PostPass {
+                fragment_function: "fragment_bright_pass",
+                target: Some(bright.clone()),
+                arguments: arguments("fragment_bright_pass", &scene_texture, None),
+            });
+            passes.push(PostPass {
+                fragment_function: "fragment_blur_horizontal",
+                target: Some(blurred.clone()),
+                arguments: arguments("fragment_blur_horizontal", bright.texture(), None),
+            });
+            passes.push(PostPass {
+                fragment_function: "fragment_blur_vertical",
+                target: Some(bright.clone()),
+                arguments: arguments("fragment_blur_vertical", blurred.texture(), None),
+            });
+            bright
+        });
+        // the antialiasing reads the composited frame in the format of the drawable
+        let composited = settings
+            .fxaa
+            .then(|| create_target(drawable_format, (width, height)));
+        passes.push(PostPass {
+            fragment_function: "fragment_composite",
+            target: composited.clone(),
+            arguments: arguments("fragment_composite", &scene_texture, bloom.as_ref()),
+        });
+        if let Some(composited) = &composited {
+            passes.push(PostPass {
+                fragment_function: "fragment_fxaa",
+                target: None,
+                arguments: arguments("fragment_fxaa", composited.texture(), None),
+            });
+        }
+
+        let placeholder = device
+            .newBufferWithLength_options(4, MTLResourceOptions::MTLResourceStorageModeShared)
+            .expect("Failed to create a buffer for the post-processing.");
+        PostProcessResources {
+            settings,
+            size: (width, height),
+            drawable_format,
+            scene,
+            passes,
+            placeholder,
+        }
+    }
+}
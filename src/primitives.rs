@@ -0,0 +1,154 @@
+// shapes built on the cpu, for drawing without model files. the triangles are clockwise seen
+// from outside like the front faces metal defaults to, and the vertices show their normals as
+// colors like the ones of an OBJ file without colors
+use std::f32::consts::{PI, TAU};
+
+use objc2_metal::MTLPackedFloat3;
+
+use crate::{SurfaceVertex, VertexInput};
+
+// the vertices and triangles of a shape, for `MetalRenderer::create_mesh`. `surface` holds the
+// normals, tangents and texture coordinates of the vertices for shading them with `vertex_pbr`
+#[derive(Clone, Default)]
+pub struct MeshData {
+    pub vertices: Vec<VertexInput>,
+    pub surface: Vec<SurfaceVertex>,
+    // three per triangle
+    pub indices: Vec<u32>,
+}
+
+// a point of a surface with its normal and the direction its `u` grows along
+type SurfacePoint = ([f32; 3], [f32; 3], [f32; 3]);
+
+impl MeshData {
+    // a `width` by `height` rectangle in the xy plane around the origin, facing +z
+    pub fn quad(width: f32, height: f32) -> Self {
+        Self::plane_along([0., 0., 1.], [1., 0., 0.], [0., 1., 0.], [width, height])
+    }
+
+    // a `width` by `depth` floor in the xz plane around the origin, facing +y and split into
+    // `subdivisions` rows and columns of quads
+    pub fn plane(width: f32, depth: f32, subdivisions: u32) -> Self {
+        let subdivisions = subdivisions.max(1);
+        Self::grid(subdivisions, subdivisions, |u, v| {
+            let position = [(u - 0.5) * width, 0., (0.5 - v) * depth];
+            (position, [0., 1., 0.], [1., 0., 0.])
+        })
+    }
+
+    // a cube of `size` around the origin, each face with texture coordinates of its own
+    pub fn cube(size: f32) -> Self {
+        let faces = [
+            ([0., 0., 1.], [1., 0., 0.], [0., 1., 0.]),
+            ([0., 0., -1.], [-1., 0., 0.], [0., 1., 0.]),
+            ([1., 0., 0.], [0., 0., -1.], [0., 1., 0.]),
+            ([-1., 0., 0.], [0., 0., 1.], [0., 1., 0.]),
+            ([0., 1., 0.], [1., 0., 0.], [0., 0., -1.]),
+            ([0., -1., 0.], [1., 0., 0.], [0., 0., 1.]),
+        ];
+        let mut cube = Self::default();
+        for (normal, right, up) in faces {
+            let mut face = Self::plane_along(normal, right, up, [size, size]);
+            let offset: [f32; 3] = normal.map(|n| n * size / 2.);
+            for vertex in &mut face.vertices {
+                vertex.position.x += offset[0];
+                vertex.position.y += offset[1];
+                vertex.position.z += offset[2];
+            }
+            cube.append(face);
+        }
+        cube
+    }
+
+    // a sphere of `radius` around the origin with `segments` quads around its equator and
+    // `rings` from pole to pole, the texture wraps around it once
+    pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Self {
+        Self::grid(segments.max(3), rings.max(2), |u, v| {
+            let (sin_longitude, cos_longitude) = (u * TAU).sin_cos();
+            let (sin_latitude, cos_latitude) = ((v - 0.5) * PI).sin_cos();
+            let normal = [
+                sin_longitude * cos_latitude,
+                sin_latitude,
+                cos_longitude * cos_latitude,
+            ];
+            let tangent = [cos_longitude, 0., -sin_longitude];
+            (normal.map(|n| n * radius), normal, tangent)
+        })
+    }
+
+    // a ring around the y axis, its tube of `minor_radius` circles `major_radius` from the
+    // origin. `segments` quads go around the ring and `sides` around the tube
+    pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> Self {
+        Self::grid(segments.max(3), sides.max(3), |u, v| {
+            let (sin_ring, cos_ring) = (u * TAU).sin_cos();
+            let (sin_tube, cos_tube) = (v * TAU).sin_cos();
+            let normal = [sin_ring * cos_tube, sin_tube, cos_ring * cos_tube];
+            let center = [sin_ring * major_radius, 0., cos_ring * major_radius];
+            let position = [0, 1, 2].map(|i| center[i] + normal[i] * minor_radius);
+            (position, normal, [cos_ring, 0., -sin_ring])
+        })
+    }
+
+    // adds the vertices and triangles of `other`, e.g. to draw several shapes at once
+    pub fn append(&mut self, other: MeshData) {
+        let first_index = self.vertices.len() as u32;
+        self.vertices.extend(other.vertices);
+        self.surface.extend(other.surface);
+        self.indices
+            .extend(other.indices.into_iter().map(|index| first_index + index));
+    }
+
+    // a rectangle of `size` around the origin facing `normal`, with u growing to `right` and
+    // v to `up`
+    fn plane_along(normal: [f32; 3], right: [f32; 3], up: [f32; 3], size: [f32; 2]) -> Self {
+        Self::grid(1, 1, |u, v| {
+            let position =
+                [0, 1, 2].map(|i| right[i] * (u - 0.5) * size[0] + up[i] * (v - 0.5) * size[1]);
+            (position, normal, right)
+        })
+    }
+
+    // a surface of `columns` by `rows` quads, `point` gives its points from u and v between 0
+    // and 1. the surface faces the side u cross v points to, the texture is upright with v
+    // growing upwards
+    fn grid(columns: u32, rows: u32, point: impl Fn(f32, f32) -> SurfacePoint) -> Self {
+        let mut grid = Self::default();
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (u, v) = (column as f32 / columns as f32, row as f32 / rows as f32);
+                let (position, normal, tangent) = point(u, v);
+                let [x, y, z] = position;
+                let [nx, ny, nz] = normal;
+                grid.vertices.push(VertexInput {
+                    position: MTLPackedFloat3 { x, y, z },
+                    color: MTLPackedFloat3 {
+                        x: nx * 0.5 + 0.5,
+                        y: ny * 0.5 + 0.5,
+                        z: nz * 0.5 + 0.5,
+                    },
+                });
+                grid.surface.push(SurfaceVertex {
+                    normal,
+                    tangent: [tangent[0], tangent[1], tangent[2], 1.],
+                    texture_coordinates: [u, 1. - v],
+                });
+            }
+        }
+        let index = |column: u32, row: u32| row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let (bottom_left, bottom_right) = (index(column, row), index(column + 1, row));
+                let (top_left, top_right) = (index(column, row + 1), index(column + 1, row + 1));
+                grid.indices.extend([
+                    bottom_left,
+                    top_left,
+                    top_right,
+                    bottom_left,
+                    top_right,
+                    bottom_right,
+                ]);
+            }
+        }
+        grid
+    }
+}
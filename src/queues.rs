@@ -0,0 +1,133 @@
+// command queues besides the one of the frames, for uploads and compute work that would hold
+// up the command buffer of the next frame. the gpu runs their command buffers alongside the
+// frames, a shared event counting the submissions of a queue tells when one is done and lets
+// the frames wait for it
+use core::{cell::Cell, time::Duration};
+use std::{cell::RefCell, rc::Rc};
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSString;
+use objc2_metal::{MTLCommandBuffer, MTLCommandQueue, MTLComputePassDescriptor, MTLDevice};
+
+use crate::{ComputePass, GpuEvent, LogLevel, MetalRenderer};
+
+// a command queue of its own. every submission gets the next value of the event of the queue,
+// which the gpu signals once its command buffer completed
+pub struct GpuQueue {
+    command_queue: Retained<ProtocolObject<dyn MTLCommandQueue>>,
+    label: String,
+    event: GpuEvent,
+    // the value of the latest submission
+    submitted: Cell<u64>,
+    // the events the next submission waits for
+    waits: RefCell<Vec<(GpuEvent, u64)>>,
+}
+
+impl GpuQueue {
+    pub fn command_queue(&self) -> &Retained<ProtocolObject<dyn MTLCommandQueue>> {
+        &self.command_queue
+    }
+
+    // the shared event the submissions signal
+    pub fn event(&self) -> &GpuEvent {
+        &self.event
+    }
+
+    // the value of the latest submission, 0 before the first one
+    pub fn submitted_value(&self) -> u64 {
+        self.submitted.get()
+    }
+
+    // the value of the latest submission the gpu completed
+    pub fn completed_value(&self) -> u64 {
+        self.event.signaled_value().unwrap_or_default()
+    }
+
+    // whether the gpu completed the submission that returned `value`, and the ones before it
+    pub fn is_complete(&self, value: u64) -> bool {
+        self.completed_value() >= value
+    }
+
+    // blocks until the gpu completed the submission that returned `value`, returns false once
+    // `timeout` passed first
+    pub fn wait(&self, value: u64, timeout: Duration) -> bool {
+        self.event.wait(value, timeout)
+    }
+
+    // the next submission starts on the gpu once `event` reached `value`, e.g. the event a
+    // frame signals after writing what the work of the queue reads
+    pub fn wait_for_event(&self, event: &GpuEvent, value: u64) {
+        self.waits.borrow_mut().push((event.clone(), value));
+    }
+
+    // commits a command buffer of the queue with the commands `encode` encodes into it, and
+    // returns the value the event of the queue reaches once the gpu completed it
+    pub fn submit(&self, encode: impl FnOnce(&ProtocolObject<dyn MTLCommandBuffer>)) -> u64 {
+        let command_buffer = self
+            .command_queue
+            .commandBuffer()
+            .expect("Failed to create a command buffer.");
+        command_buffer.setLabel(Some(&NSString::from_str(&self.label)));
+        for (event, value) in self.waits.take() {
+            command_buffer.encodeWaitForEvent_value(event.event(), value);
+        }
+        encode(&command_buffer);
+        let value = self.submitted.get() + 1;
+        command_buffer.encodeSignalEvent_value(self.event.event(), value);
+        command_buffer.commit();
+        self.submitted.set(value);
+        value
+    }
+}
+
+impl MetalRenderer {
+    // a command queue for work the frames don't wait for, named `label` in the gpu tools.
+    // metal runs the command buffers of different queues in any order, so the frames using
+    // its results wait for it with `wait_for_queue` or check `is_complete` before they do.
+    // a queue belongs to the device it was created on, another one is needed after a device
+    // change
+    pub fn create_queue(&self, label: &str) -> GpuQueue {
+        let command_queue = self
+            .device()
+            .newCommandQueue()
+            .expect("Failed to create a command queue.");
+        command_queue.setLabel(Some(&NSString::from_str(label)));
+        GpuQueue {
+            command_queue,
+            label: label.to_owned(),
+            event: self.create_shared_event(),
+            submitted: Cell::new(0),
+            waits: RefCell::default(),
+        }
+    }
+
+    // the queue `upload_texture` copies the textures of the assets with, created on first use
+    // and again after a device change
+    pub fn upload_queue(&self) -> Rc<GpuQueue> {
+        let mut upload_queue = self.ivars().upload_queue.borrow_mut();
+        upload_queue
+            .get_or_insert_with(|| Rc::new(self.create_queue("Upload queue")))
+            .clone()
+    }
+
+    // the next frame starts on the gpu once `queue` completed the submission that returned
+    // `value`, for results the frame needs even if it has to wait for them
+    pub fn wait_for_queue(&self, queue: &GpuQueue, value: u64) {
+        self.wait_for_event(queue.event(), value);
+    }
+
+    // runs the dispatches of `compute_pass` on `queue` alongside the frames, e.g. a simulation
+    // taking longer than a frame. the kernels don't get the scene properties at buffer 0, those
+    // belong to the frames. returns the value of the submission
+    pub fn submit_compute(&self, queue: &GpuQueue, compute_pass: &ComputePass) -> u64 {
+        queue.submit(|command_buffer| {
+            let pass_descriptor = unsafe { MTLComputePassDescriptor::computePassDescriptor() };
+            if !compute_pass.encode(command_buffer, &pass_descriptor, None) {
+                self.log(
+                    LogLevel::Warn,
+                    "Failed to create a compute encoder for the queue.",
+                );
+            }
+        })
+    }
+}
@@ -0,0 +1,325 @@
+// variable rasterization rates on the apple gpus supporting them. a rate map shrinks the regions
+// of a pass where the rates are low, the pass renders into textures of the smaller physical
+// size while its viewport and its draws stay in the coordinates of the full screen size. the
+// frames of the renderer are stretched back over the drawable by a fullscreen pass
+use std::ptr::NonNull;
+
+use objc2::{rc::Retained, runtime::ProtocolObject, ClassType, DeclaredClass};
+use objc2_foundation::{NSSize, NSString};
+use objc2_metal::{
+    MTLBuffer, MTLCommandBuffer, MTLCommandEncoder, MTLDevice, MTLLoadAction, MTLPrimitiveType,
+    MTLRasterizationRateLayerDescriptor, MTLRasterizationRateMap,
+    MTLRasterizationRateMapDescriptor, MTLRenderCommandEncoder, MTLRenderPassDescriptor,
+    MTLResourceOptions, MTLSize, MTLTexture, MTLViewport,
+};
+
+use crate::{
+    target::render_pass_descriptor, LogLevel, MetalRenderer, PipelineDescriptor, RenderPath,
+    RenderTarget,
+};
+
+// the rates sampled across each axis by `RasterizationRates::foveated`
+const FOVEATED_SAMPLES: usize = 8;
+
+// the distance from the focus the rates start falling off at and the one they reach the
+// peripheral rate at, in fractions of the view
+const FOVEA_RADIUS: f32 = 0.2;
+const PERIPHERY_RADIUS: f32 = 0.5;
+
+// how densely the regions of a frame are rasterized, as rates between 0 and 1 sampled at even
+// steps across the width and down the height. a region gets the product of the rates of its
+// column and its row, interpolated between the samples
+#[derive(Clone, Debug, PartialEq)]
+pub struct RasterizationRates {
+    pub horizontal: Vec<f32>,
+    pub vertical: Vec<f32>,
+}
+
+impl RasterizationRates {
+    // the full rate around `focus`, between 0 and 1 across the view from the top left, falling
+    // off to `peripheral_rate` toward the edges
+    pub fn foveated(focus: (f32, f32), peripheral_rate: f32) -> Self {
+        let peripheral_rate = peripheral_rate.clamp(0., 1.);
+        let samples = |focus: f32| {
+            (0..FOVEATED_SAMPLES)
+                .map(|index| {
+                    let position = (index as f32 + 0.5) / FOVEATED_SAMPLES as f32;
+                    let falloff = ((position - focus).abs() - FOVEA_RADIUS)
+                        / (PERIPHERY_RADIUS - FOVEA_RADIUS);
+                    1. + (peripheral_rate - 1.) * falloff.clamp(0., 1.)
+                })
+                .collect()
+        };
+        RasterizationRates {
+            horizontal: samples(focus.0),
+            vertical: samples(focus.1),
+        }
+    }
+}
+
+// the rates of a pass over a screen of a fixed size, bound to the passes of render targets of
+// its physical size with `RenderTarget::set_rasterization_rate_map`
+#[derive(Clone)]
+pub struct RasterizationRateMap {
+    map: Retained<ProtocolObject<dyn MTLRasterizationRateMap>>,
+}
+
+impl RasterizationRateMap {
+    // for the shaders of the application mapping between the screen and the physical
+    // coordinates themselves
+    pub fn map(&self) -> &Retained<ProtocolObject<dyn MTLRasterizationRateMap>> {
+        &self.map
+    }
+
+    // the size the viewport of the passes covers
+    pub fn screen_size(&self) -> (usize, usize) {
+        let size = unsafe { self.map.screenSize() };
+        (size.width, size.height)
+    }
+
+    // the size of the textures the passes render into
+    pub fn physical_size(&self) -> (usize, usize) {
+        let size = unsafe { self.map.physicalSizeForLayer(0) };
+        (size.width, size.height)
+    }
+
+    // the viewport covering the whole screen, the passes into a target with the map draw in it
+    pub(crate) fn screen_viewport(&self) -> MTLViewport {
+        let (width, height) = self.screen_size();
+        MTLViewport {
+            originX: 0.,
+            originY: 0.,
+            width: width as f64,
+            height: height as f64,
+            znear: 0.,
+            zfar: 1.,
+        }
+    }
+}
+
+// the target with the map and the parameters of the shader stretching the frames for the rates
+// and the size of the last frame
+struct RateMapResources {
+    rates: RasterizationRates,
+    screen_size: (usize, usize),
+    scene: RenderTarget,
+    parameters: Retained<ProtocolObject<dyn MTLBuffer>>,
+}
+
+#[derive(Default)]
+pub(crate) struct RasterizationRateState {
+    rates: Option<RasterizationRates>,
+    resources: Option<RateMapResources>,
+}
+
+impl RasterizationRateState {
+    // drops the map and its target, they're created again with the next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.resources = None;
+    }
+}
+
+// a frame rendered at the rates of the map and stretched over the drawable by
+// `encode_rasterization_rate_resolve`
+pub(crate) struct RateMappedFrame {
+    // the pass of the frame into the target of the physical size
+    pub(crate) pass_descriptor: Retained<MTLRenderPassDescriptor>,
+}
+
+impl RateMappedFrame {
+    // moves the gpu timer samples of the view onto the pass of the frame, the view's pass
+    // isn't encoded
+    pub(crate) fn take_gpu_timer_samples(&self, view_pass_descriptor: &MTLRenderPassDescriptor) {
+        unsafe {
+            let samples = view_pass_descriptor
+                .sampleBufferAttachments()
+                .objectAtIndexedSubscript(0);
+            self.pass_descriptor
+                .sampleBufferAttachments()
+                .setObject_atIndexedSubscript(Some(&samples), 0);
+        }
+    }
+}
+
+impl MetalRenderer {
+    // whether the gpu rasterizes at varying rates, the apple gpus of macOS 10.15.4 and iOS 13
+    // and later do
+    pub fn supports_rasterization_rate_maps(&self) -> bool {
+        unsafe { self.device().supportsRasterizationRateMapWithLayerCount(1) }
+    }
+
+    // the map of `rates` over a screen of `screen_size`, none when the gpu doesn't support
+    // them or the rates have no samples
+    pub fn create_rasterization_rate_map(
+        &self,
+        (width, height): (usize, usize),
+        rates: &RasterizationRates,
+    ) -> Option<RasterizationRateMap> {
+        if !self.supports_rasterization_rate_maps()
+            || rates.horizontal.is_empty()
+            || rates.vertical.is_empty()
+        {
+            return None;
+        }
+        let mut horizontal = rates.horizontal.clone();
+        let mut vertical = rates.vertical.clone();
+        let sample_count = MTLSize {
+            width: horizontal.len(),
+            height: vertical.len(),
+            depth: 1,
+        };
+        let screen_size = MTLSize {
+            width,
+            height,
+            depth: 0,
+        };
+        let map = unsafe {
+            // the samples are copied into the descriptor
+            let layer =
+                MTLRasterizationRateLayerDescriptor::initWithSampleCount_horizontal_vertical(
+                    MTLRasterizationRateLayerDescriptor::alloc(),
+                    sample_count,
+                    NonNull::new(horizontal.as_mut_ptr()).unwrap(),
+                    NonNull::new(vertical.as_mut_ptr()).unwrap(),
+                );
+            let descriptor = MTLRasterizationRateMapDescriptor::new();
+            descriptor.setScreenSize(screen_size);
+            descriptor.setLayer_atIndex(Some(&layer), 0);
+            self.device()
+                .newRasterizationRateMapWithDescriptor(&descriptor)
+        };
+        let Some(map) = map else {
+            self.log(LogLevel::Warn, "Failed to create a rasterization rate map.");
+            return None;
+        };
+        Some(RasterizationRateMap { map })
+    }
+
+    // rasterizes the frames at `rates`, none at the full rate everywhere. only the frames of
+    // the render callback are rendered at varying rates, not the ones of a render graph, the
+    // deferred path, post-processing, upscaling or the ray traced ones, and the text, the ui
+    // and the overlay are drawn at the full rate after them
+    pub fn set_rasterization_rates(&self, rates: Option<RasterizationRates>) {
+        self.ivars().rasterization_rate.borrow_mut().rates = rates;
+    }
+
+    pub fn rasterization_rates(&self) -> Option<RasterizationRates> {
+        self.ivars().rasterization_rate.borrow().rates.clone()
+    }
+
+    // the target of the physical size the draws of this frame go to, none when the frame is
+    // rasterized at the full rate. the map and the target are recreated when the rates, the
+    // size or the formats changed
+    pub(crate) fn prepare_rasterization_rate(
+        &self,
+        drawable_size: NSSize,
+    ) -> Option<RateMappedFrame> {
+        let rates = self.rasterization_rates()?;
+        if self.ivars().render_graph_callback.borrow().is_some()
+            || self.render_path() == RenderPath::Deferred
+            || self.is_ray_tracing()
+            || self.is_post_processing()
+        {
+            return None;
+        }
+        let screen_size = (drawable_size.width as usize, drawable_size.height as usize);
+        let reusable = self
+            .ivars()
+            .rasterization_rate
+            .borrow()
+            .resources
+            .as_ref()
+            .is_some_and(|resources| {
+                resources.rates == rates
+                    && resources.screen_size == screen_size
+                    && resources.scene.is_compatible_with(self)
+            });
+        if !reusable {
+            let resources = self.create_rate_map_resources(rates, screen_size)?;
+            self.ivars().rasterization_rate.borrow_mut().resources = Some(resources);
+        }
+        let rasterization_rate = self.ivars().rasterization_rate.borrow();
+        let resources = rasterization_rate.resources.as_ref().unwrap();
+        Some(RateMappedFrame {
+            pass_descriptor: resources.scene.pass_descriptor(),
+        })
+    }
+
+    fn create_rate_map_resources(
+        &self,
+        rates: RasterizationRates,
+        screen_size: (usize, usize),
+    ) -> Option<RateMapResources> {
+        let map = self.create_rasterization_rate_map(screen_size, &rates)?;
+        let (width, height) = map.physical_size();
+        // the scene target has the sample count and the depth format of the view
+        let mut scene = self.create_render_target(width.max(1), height.max(1));
+        scene.clear_color = self.ivars().surface.get().unwrap().clear_color();
+        scene.set_rasterization_rate_map(Some(&map));
+
+        let size_and_align = unsafe { map.map.parameterBufferSizeAndAlign() };
+        let parameters = self
+            .device()
+            .newBufferWithLength_options(
+                size_and_align.size.max(1),
+                MTLResourceOptions::MTLResourceStorageModeShared,
+            )
+            .expect("Failed to create a buffer for the rasterization rate map.");
+        unsafe { map.map.copyParameterDataToBuffer_offset(&parameters, 0) };
+        let message = format!(
+            "Rasterizing {}x{} frames into {width}x{height} pixels.",
+            screen_size.0, screen_size.1
+        );
+        self.log(LogLevel::Debug, &message);
+        Some(RateMapResources {
+            rates,
+            screen_size,
+            scene,
+            parameters,
+        })
+    }
+
+    // stretches the frame rendered at the rates of the map over the drawable
+    pub(crate) fn encode_rasterization_rate_resolve(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) {
+        let rasterization_rate = self.ivars().rasterization_rate.borrow();
+        let Some(resources) = rasterization_rate.resources.as_ref() else {
+            return;
+        };
+        let descriptor = PipelineDescriptor {
+            color_format: drawable_texture.pixelFormat(),
+            depth_format: None,
+            sample_count: 1,
+            ..self.pipeline_descriptor("vertex_fullscreen", "fragment_rasterization_rate")
+        };
+        let pipeline_state = self.render_pipeline_state_for(&descriptor);
+        let clear_color = self.ivars().surface.get().unwrap().clear_color();
+        let pass_descriptor = render_pass_descriptor(drawable_texture, None, None, clear_color);
+        // every pixel of the drawable is drawn over
+        unsafe {
+            pass_descriptor
+                .colorAttachments()
+                .objectAtIndexedSubscript(0)
+                .setLoadAction(MTLLoadAction::DontCare);
+        }
+        let Some(encoder) = command_buffer.renderCommandEncoderWithDescriptor(&pass_descriptor)
+        else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a render encoder for the rasterization rates.",
+            );
+            return;
+        };
+        encoder.setLabel(Some(&NSString::from_str("Rasterization rate resolve")));
+        encoder.setRenderPipelineState(&pipeline_state);
+        unsafe {
+            encoder.setFragmentTexture_atIndex(Some(resources.scene.texture()), 0);
+            encoder.setFragmentBuffer_offset_atIndex(Some(&resources.parameters), 0, 0);
+            encoder.drawPrimitives_vertexStart_vertexCount(MTLPrimitiveType::Triangle, 0, 3);
+        }
+        encoder.endEncoding();
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use objc2::DeclaredClass;
+
+use crate::{MetalRenderer, ScreenshotError};
+
+// the state of a recording in progress
+pub(crate) struct Recording {
+    directory: PathBuf,
+    frame_count: usize,
+    // the first frame that failed to save, frames are written on background threads
+    error: Arc<Mutex<Option<ScreenshotError>>>,
+}
+
+impl MetalRenderer {
+    // saves every frame drawn from now on as a numbered png in `directory`, frame_00000.png
+    // onwards. the frames are converted and written like `capture_frame` does, the sequence can
+    // be turned into a video with `ffmpeg -framerate 60 -i frame_%05d.png recording.mp4`
+    pub fn start_recording(&self, directory: impl AsRef<Path>) -> io::Result<()> {
+        fs::create_dir_all(&directory)?;
+        if let Some(surface) = self.ivars().surface.get() {
+            surface.set_framebuffer_only(false);
+        }
+        self.ivars().recording.replace(Some(Recording {
+            directory: directory.as_ref().to_owned(),
+            frame_count: 0,
+            error: Arc::default(),
+        }));
+        Ok(())
+    }
+
+    // stops the recording and returns the number of frames it recorded, or the first error
+    // saving them. the last frames may still be written after it returns. returns None when
+    // nothing was being recorded
+    pub fn stop_recording(&self) -> Option<Result<usize, ScreenshotError>> {
+        let recording = self.ivars().recording.take()?;
+        let error = recording.error.lock().unwrap().take();
+        Some(match error {
+            Some(error) => Err(error),
+            None => Ok(recording.frame_count),
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.ivars().recording.borrow().is_some()
+    }
+
+    // queues the capture of the frame being rendered when recording
+    pub(crate) fn record_frame(&self) {
+        let mut recording = self.ivars().recording.borrow_mut();
+        let Some(recording) = recording.as_mut() else {
+            return;
+        };
+        let path = recording
+            .directory
+            .join(format!("frame_{:05}.png", recording.frame_count));
+        recording.frame_count += 1;
+        let error = recording.error.clone();
+        self.capture_frame(path, move |result| {
+            if let Err(frame_error) = result {
+                error.lock().unwrap().get_or_insert(frame_error);
+            }
+        });
+    }
+}
@@ -0,0 +1,199 @@
+// the arguments of the shaders of a pipeline by name, taken from the reflection metal returns
+// with it. draws bind their buffers and textures to the slots of the names rather than to the
+// indices of the `[[buffer(n)]]` and `[[texture(n)]]` attributes, which can change without the
+// rust side noticing
+use std::{collections::HashMap, rc::Rc};
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::NSArray;
+use objc2_metal::{
+    MTLBinding, MTLBindingType, MTLBuffer, MTLRenderCommandEncoder, MTLRenderPipelineReflection,
+    MTLRenderPipelineState, MTLSamplerState, MTLTexture,
+};
+
+use crate::{MetalRenderer, RenderPass};
+
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+
+// where an argument is bound in each of the stages using it, e.g. the scene properties both
+// shaders read
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BindingSlot {
+    pub vertex: Option<usize>,
+    pub fragment: Option<usize>,
+}
+
+// the buffers, textures and samplers the shaders of a pipeline take, keyed by the names of
+// their arguments
+#[derive(Clone, Debug, Default)]
+pub struct ShaderBindings {
+    vertex: HashMap<String, (MTLBindingType, usize)>,
+    fragment: HashMap<String, (MTLBindingType, usize)>,
+}
+
+impl ShaderBindings {
+    fn new(reflection: &MTLRenderPipelineReflection) -> Self {
+        Self {
+            vertex: bindings(&*unsafe { reflection.vertexBindings() }),
+            fragment: bindings(&*unsafe { reflection.fragmentBindings() }),
+        }
+    }
+
+    // the slot of the buffer argument `name`, like the `[[buffer(0)]]` of `properties`
+    pub fn buffer(&self, name: &str) -> Option<BindingSlot> {
+        self.slot(name, MTLBindingType::Buffer)
+    }
+
+    pub fn texture(&self, name: &str) -> Option<BindingSlot> {
+        self.slot(name, MTLBindingType::Texture)
+    }
+
+    pub fn sampler(&self, name: &str) -> Option<BindingSlot> {
+        self.slot(name, MTLBindingType::Sampler)
+    }
+
+    // none when neither shader has an argument of that name and type
+    fn slot(&self, name: &str, binding_type: MTLBindingType) -> Option<BindingSlot> {
+        let index = |bindings: &HashMap<String, (MTLBindingType, usize)>| {
+            bindings
+                .get(name)
+                .filter(|(found_type, _)| *found_type == binding_type)
+                .map(|(_, index)| *index)
+        };
+        let slot = BindingSlot {
+            vertex: index(&self.vertex),
+            fragment: index(&self.fragment),
+        };
+        (slot != BindingSlot::default()).then_some(slot)
+    }
+}
+
+fn bindings(
+    bindings: &NSArray<ProtocolObject<dyn MTLBinding>>,
+) -> HashMap<String, (MTLBindingType, usize)> {
+    bindings
+        .iter_retained()
+        .map(|binding| unsafe {
+            (
+                binding.name().to_string(),
+                (binding.r#type(), binding.index()),
+            )
+        })
+        .collect()
+}
+
+// the bindings of the pipelines the renderer built, keyed by the address of the pipeline. the
+// pipelines are kept alive with them, so no other pipeline can take over an address
+#[derive(Default)]
+pub(crate) struct PipelineBindings(HashMap<usize, (PipelineState, Rc<ShaderBindings>)>);
+
+impl PipelineBindings {
+    pub(crate) fn insert(
+        &mut self,
+        pipeline_state: &PipelineState,
+        reflection: &MTLRenderPipelineReflection,
+    ) {
+        let bindings = Rc::new(ShaderBindings::new(reflection));
+        self.0.insert(
+            Retained::as_ptr(pipeline_state) as usize,
+            (pipeline_state.clone(), bindings),
+        );
+    }
+
+    // drops the bindings of the pipelines built from the previous shader library
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl MetalRenderer {
+    // the arguments of the shaders of `pipeline_state` by name, none for pipelines the renderer
+    // didn't build from a `PipelineDescriptor` or built before its shaders last changed
+    pub fn shader_bindings(&self, pipeline_state: &PipelineState) -> Option<Rc<ShaderBindings>> {
+        let pipeline_bindings = self.ivars().pipeline_bindings.borrow();
+        pipeline_bindings
+            .0
+            .get(&(Retained::as_ptr(pipeline_state) as usize))
+            .map(|(_, bindings)| bindings.clone())
+    }
+}
+
+// a resource bound to the slot of an argument of a draw
+#[derive(Clone)]
+pub(crate) enum BoundResource {
+    Buffer(Retained<ProtocolObject<dyn MTLBuffer>>),
+    Texture(Retained<ProtocolObject<dyn MTLTexture>>),
+    Sampler(Retained<ProtocolObject<dyn MTLSamplerState>>),
+}
+
+impl BoundResource {
+    pub(crate) fn encode(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+        slot: BindingSlot,
+    ) {
+        unsafe {
+            if let Some(index) = slot.vertex {
+                match self {
+                    BoundResource::Buffer(buffer) => {
+                        encoder.setVertexBuffer_offset_atIndex(Some(buffer), 0, index)
+                    }
+                    BoundResource::Texture(texture) => {
+                        encoder.setVertexTexture_atIndex(Some(texture), index)
+                    }
+                    BoundResource::Sampler(sampler) => {
+                        encoder.setVertexSamplerState_atIndex(Some(sampler), index)
+                    }
+                }
+            }
+            if let Some(index) = slot.fragment {
+                match self {
+                    BoundResource::Buffer(buffer) => {
+                        encoder.setFragmentBuffer_offset_atIndex(Some(buffer), 0, index)
+                    }
+                    BoundResource::Texture(texture) => {
+                        encoder.setFragmentTexture_atIndex(Some(texture), index)
+                    }
+                    BoundResource::Sampler(sampler) => {
+                        encoder.setFragmentSamplerState_atIndex(Some(sampler), index)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl RenderPass {
+    // binds `buffer` to a slot of the shaders of the last recorded draw, in place of what the
+    // pass binds there
+    pub fn with_buffer(
+        &mut self,
+        slot: BindingSlot,
+        buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+    ) -> &mut Self {
+        self.bind(slot, BoundResource::Buffer(buffer.clone()))
+    }
+
+    pub fn with_texture(
+        &mut self,
+        slot: BindingSlot,
+        texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+    ) -> &mut Self {
+        self.bind(slot, BoundResource::Texture(texture.clone()))
+    }
+
+    pub fn with_sampler(
+        &mut self,
+        slot: BindingSlot,
+        sampler: &Retained<ProtocolObject<dyn MTLSamplerState>>,
+    ) -> &mut Self {
+        self.bind(slot, BoundResource::Sampler(sampler.clone()))
+    }
+
+    fn bind(&mut self, slot: BindingSlot, resource: BoundResource) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.bindings.push((slot, resource));
+        }
+        self
+    }
+}
@@ -0,0 +1,234 @@
+// the renderer for the threads besides the main one. the renderer and its metal objects stay on
+// the main thread, a `RenderHandle` sends what other threads want drawn and changed through a
+// channel instead, and the frames take it in before they're recorded. a game or a simulation
+// running on a thread of its own hands its draws over like this every tick
+use std::{
+    collections::BTreeMap,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use objc2::DeclaredClass;
+use objc2_metal::{MTLClearColor, MTLPackedFloat3};
+
+use crate::{
+    camera::Matrix, scene_graph::draw_instances, Camera, InstanceData, Light, LogLevel, Material,
+    Mesh, MetalRenderer, PrimitiveType, RenderPass, VertexInput,
+};
+
+// a mesh registered with `add_shared_mesh`. the draw lists of other threads refer to the meshes
+// and materials through ids, since the metal objects can't leave the main thread
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(usize);
+
+// a material registered with `add_shared_material`, draws without one are drawn with
+// `vertex_instanced` and `fragment_main`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(usize);
+
+// the draws of a frame built on another thread, copies of shared meshes and lines like the
+// ones of `DebugDraw`
+#[derive(Clone, Default)]
+pub struct DrawList {
+    instances: Vec<(MeshId, Option<MaterialId>, Matrix)>,
+    lines: Vec<VertexInput>,
+}
+
+impl DrawList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a copy of `mesh` moved by `transform`, in the color of `material`. the copies of a mesh
+    // with the same material are drawn together as the instances of a single draw
+    pub fn draw(&mut self, mesh: MeshId, material: Option<MaterialId>, transform: Matrix) {
+        self.instances.push((mesh, material, transform));
+    }
+
+    pub fn line(&mut self, from: [f32; 3], to: [f32; 3], color: [f32; 3]) {
+        let vertex = |[x, y, z]: [f32; 3]| VertexInput {
+            position: MTLPackedFloat3 { x, y, z },
+            color: MTLPackedFloat3 {
+                x: color[0],
+                y: color[1],
+                z: color[2],
+            },
+        };
+        self.lines.push(vertex(from));
+        self.lines.push(vertex(to));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty() && self.lines.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.lines.clear();
+    }
+}
+
+enum RenderCommand {
+    Draws(DrawList),
+    Camera(Camera),
+    Lights(Vec<Light>),
+    ClearColor(MTLClearColor),
+    Run(Box<dyn FnOnce(&MetalRenderer) + Send>),
+}
+
+// sends draws and changes to the renderer from any thread, it can be cloned for every thread
+// that needs one. what's sent is applied before the next frame is recorded, in the order it
+// was sent. a view drawing on demand only picks it up with the next frame it's asked for, the
+// handle can't ask for one from another thread
+#[derive(Clone)]
+pub struct RenderHandle {
+    sender: Sender<RenderCommand>,
+}
+
+impl RenderHandle {
+    // false once the renderer is gone
+    fn send(&self, command: RenderCommand) -> bool {
+        self.sender.send(command).is_ok()
+    }
+
+    // draws `draw_list` with every frame from the next one on, until the next draw list
+    // replaces it. a thread ticking slower than the frames doesn't leave frames without its
+    // draws
+    pub fn submit_draws(&self, draw_list: DrawList) -> bool {
+        self.send(RenderCommand::Draws(draw_list))
+    }
+
+    pub fn set_camera(&self, camera: Camera) -> bool {
+        self.send(RenderCommand::Camera(camera))
+    }
+
+    // like `MetalRenderer::set_lights`
+    pub fn set_lights(&self, lights: Vec<Light>) -> bool {
+        self.send(RenderCommand::Lights(lights))
+    }
+
+    pub fn set_clear_color(&self, clear_color: MTLClearColor) -> bool {
+        self.send(RenderCommand::ClearColor(clear_color))
+    }
+
+    // runs `update` with the renderer on the main thread before the next frame, for the
+    // changes without a command of their own, like writing into the buffers of the renderer
+    pub fn run(&self, update: impl FnOnce(&MetalRenderer) + Send + 'static) -> bool {
+        self.send(RenderCommand::Run(Box::new(update)))
+    }
+}
+
+// the channel of the handles and what they refer to
+pub(crate) struct RenderCommands {
+    sender: Sender<RenderCommand>,
+    receiver: Receiver<RenderCommand>,
+    meshes: Vec<Rc<Mesh>>,
+    materials: Vec<Rc<Material>>,
+    // the latest draw list, drawn every frame
+    draws: DrawList,
+}
+
+impl Default for RenderCommands {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        RenderCommands {
+            sender,
+            receiver,
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            draws: DrawList::default(),
+        }
+    }
+}
+
+impl MetalRenderer {
+    // a handle other threads send draws and changes to the renderer through
+    pub fn render_handle(&self) -> RenderHandle {
+        let render_commands = self.ivars().render_commands.borrow();
+        RenderHandle {
+            sender: render_commands.sender.clone(),
+        }
+    }
+
+    // makes `mesh` drawable in the draw lists of the handles
+    pub fn add_shared_mesh(&self, mesh: Rc<Mesh>) -> MeshId {
+        let mut render_commands = self.ivars().render_commands.borrow_mut();
+        render_commands.meshes.push(mesh);
+        MeshId(render_commands.meshes.len() - 1)
+    }
+
+    pub fn add_shared_material(&self, material: Rc<Material>) -> MaterialId {
+        let mut render_commands = self.ivars().render_commands.borrow_mut();
+        render_commands.materials.push(material);
+        MaterialId(render_commands.materials.len() - 1)
+    }
+
+    // applies what the handles sent since the last frame
+    pub(crate) fn apply_render_commands(&self) {
+        // the commands run without the borrow, a closure may register meshes or get a handle
+        let commands: Vec<RenderCommand> = {
+            let render_commands = self.ivars().render_commands.borrow();
+            render_commands.receiver.try_iter().collect()
+        };
+        for command in commands {
+            match command {
+                RenderCommand::Draws(draw_list) => self.set_submitted_draws(draw_list),
+                RenderCommand::Camera(camera) => self.set_camera(camera),
+                RenderCommand::Lights(lights) => self.set_lights(lights),
+                RenderCommand::ClearColor(clear_color) => self.set_clear_color(clear_color),
+                RenderCommand::Run(update) => update(self),
+            }
+        }
+    }
+
+    // keeps `draw_list` for the frames. the draws of ids the renderer didn't hand out, like the
+    // ones of another renderer, are left out instead of failing the frames
+    fn set_submitted_draws(&self, mut draw_list: DrawList) {
+        let mut render_commands = self.ivars().render_commands.borrow_mut();
+        let mesh_count = render_commands.meshes.len();
+        let material_count = render_commands.materials.len();
+        let count = draw_list.instances.len();
+        draw_list.instances.retain(|&(MeshId(mesh), material, _)| {
+            mesh < mesh_count
+                && material.is_none_or(|MaterialId(material)| material < material_count)
+        });
+        let skipped = count - draw_list.instances.len();
+        render_commands.draws = draw_list;
+        drop(render_commands);
+        if skipped > 0 {
+            let message = format!("Skipped {skipped} draws of unknown meshes or materials.");
+            self.log(LogLevel::Warn, &message);
+        }
+    }
+
+    // records the latest draw list of the handles into the pass of the frame, the copies
+    // grouped by their meshes and materials
+    pub(crate) fn draw_submitted(&self, render_pass: &mut RenderPass) {
+        let render_commands = self.ivars().render_commands.borrow();
+        let mut draws: BTreeMap<_, Vec<InstanceData>> = BTreeMap::new();
+        for &(mesh, material, transform) in &render_commands.draws.instances {
+            let color = material.map_or([1.; 4], |MaterialId(index)| {
+                render_commands.materials[index].color
+            });
+            draws
+                .entry((mesh, material))
+                .or_default()
+                .push(InstanceData { transform, color });
+        }
+        for ((MeshId(mesh), material), instances) in draws {
+            let mesh = &render_commands.meshes[mesh];
+            let material = material.map(|MaterialId(index)| &*render_commands.materials[index]);
+            draw_instances(self, render_pass, mesh, material, &instances);
+        }
+
+        let lines = &render_commands.draws.lines;
+        if !lines.is_empty() {
+            render_pass.draw(
+                &self.render_pipeline_state("vertex_debug", "fragment_main"),
+                &self.frame_buffer(lines),
+                PrimitiveType::Line,
+                0..lines.len(),
+            );
+        }
+    }
+}
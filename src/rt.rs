@@ -0,0 +1,256 @@
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::{CGSize, NSArray};
+use objc2_metal::{
+    MTLAccelerationStructure, MTLAccelerationStructureCommandEncoder,
+    MTLAccelerationStructureTriangleGeometryDescriptor, MTLBlitCommandEncoder, MTLBuffer,
+    MTLCommandBuffer, MTLCommandEncoder, MTLCommandQueue, MTLDevice, MTLIndexType,
+    MTLPrimitiveAccelerationStructureDescriptor, MTLResourceOptions, MTLTexture,
+};
+
+use crate::{ComputePass, LogLevel, Mesh, MetalRenderer, Projection, VertexInput};
+
+// the kernel argument buffer indices of `trace_primary_rays`, after the scene properties
+const RAY_CAMERA_INDEX: usize = 1;
+const ACCELERATION_STRUCTURE_INDEX: usize = 2;
+const VERTICES_INDEX: usize = 3;
+const INDICES_INDEX: usize = 4;
+
+// the rays of `trace_primary_rays`, their origins and directions at the center of the view and
+// how they change across it in normalized device coordinates
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct RayCamera {
+    origin: [f32; 4],
+    origin_x: [f32; 4],
+    origin_y: [f32; 4],
+    direction: [f32; 4],
+    direction_x: [f32; 4],
+    direction_y: [f32; 4],
+    // written where the rays hit nothing
+    background: [f32; 4],
+}
+
+// the triangles of a mesh in a primitive acceleration structure built on the gpu. the vertex and
+// index buffers are kept for shading the hits with the colors of the mesh
+pub struct AccelerationStructure {
+    structure: Retained<ProtocolObject<dyn MTLAccelerationStructure>>,
+    vertex_buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    index_buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    triangle_count: usize,
+}
+
+impl AccelerationStructure {
+    pub fn triangle_count(&self) -> usize {
+        self.triangle_count
+    }
+
+    // the bytes of gpu memory the structure takes
+    pub fn size(&self) -> usize {
+        unsafe { self.structure.size() }
+    }
+}
+
+// the scene traced in place of the draws of the frame, and the texture the rays are written to
+#[derive(Default)]
+pub(crate) struct RayTracing {
+    scene: Option<AccelerationStructure>,
+    enabled: bool,
+    // the size and the pixel format of the drawable, recreated when they change
+    output: Option<Retained<ProtocolObject<dyn MTLTexture>>>,
+}
+
+impl RayTracing {
+    // drops the scene, which has to be built again on the new device, and the output
+    pub(crate) fn release_device_resources(&mut self) {
+        self.scene = None;
+        self.output = None;
+    }
+}
+
+impl MetalRenderer {
+    // whether the device builds acceleration structures and intersects rays with them in
+    // kernels, apple silicon gpus and the discrete gpus of recent macs
+    pub fn supports_raytracing(&self) -> bool {
+        self.device().supportsRaytracing()
+    }
+
+    // builds the triangles of `mesh` into an acceleration structure, none when the device has no
+    // ray tracing. the build is committed to the command queue of the renderer, so the frames
+    // after it see it finished without waiting
+    pub fn build_acceleration_structure(&self, mesh: &Mesh) -> Option<AccelerationStructure> {
+        if !self.supports_raytracing() {
+            self.log(LogLevel::Warn, "The device doesn't support ray tracing.");
+            return None;
+        }
+        let device = self.device();
+        let triangle_count = mesh.index_count / 3;
+        let geometry = MTLAccelerationStructureTriangleGeometryDescriptor::descriptor();
+        geometry.setVertexBuffer(Some(&mesh.vertex_buffer));
+        geometry.setVertexStride(core::mem::size_of::<VertexInput>());
+        geometry.setIndexBuffer(Some(&mesh.index_buffer));
+        unsafe { geometry.setIndexType(MTLIndexType::UInt32) };
+        geometry.setTriangleCount(triangle_count);
+        // every hit is the closest, there are no intersection functions to skip
+        unsafe { geometry.setOpaque(true) };
+        let descriptor = MTLPrimitiveAccelerationStructureDescriptor::descriptor();
+        descriptor.setGeometryDescriptors(Some(&NSArray::from_slice(&[&**geometry])));
+
+        let sizes = device.accelerationStructureSizesWithDescriptor(&descriptor);
+        let structure = device
+            .newAccelerationStructureWithSize(sizes.accelerationStructureSize)
+            .expect("Failed to create an acceleration structure.");
+        let scratch_buffer = device
+            .newBufferWithLength_options(
+                sizes.buildScratchBufferSize,
+                MTLResourceOptions::MTLResourceStorageModePrivate,
+            )
+            .expect("Failed to create an acceleration structure scratch buffer.");
+
+        let command_queue = &self.command_queue();
+        let Some(command_buffer) = command_queue.commandBuffer() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a command buffer for a build.",
+            );
+            return None;
+        };
+        let Some(encoder) = command_buffer.accelerationStructureCommandEncoder() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create an acceleration structure encoder.",
+            );
+            return None;
+        };
+        encoder.buildAccelerationStructure_descriptor_scratchBuffer_scratchBufferOffset(
+            &structure,
+            &descriptor,
+            &scratch_buffer,
+            0,
+        );
+        encoder.endEncoding();
+        // the scratch buffer is retained by the command buffer until the build completed
+        command_buffer.commit();
+
+        Some(AccelerationStructure {
+            structure,
+            vertex_buffer: mesh.vertex_buffer.clone(),
+            index_buffer: mesh.index_buffer.clone(),
+            triangle_count,
+        })
+    }
+
+    // the scene traced while ray tracing is on, built with `build_acceleration_structure`
+    pub fn set_ray_traced_scene(&self, scene: Option<AccelerationStructure>) {
+        self.ivars().ray_tracing.borrow_mut().scene = scene;
+    }
+
+    // when on, the frames show the ray traced scene instead of the render callback. the rays
+    // are written to a texture that's copied to the drawable, the drawables stop being framebuffer
+    // only to take the copy
+    pub fn set_ray_tracing(&self, enabled: bool) {
+        if enabled {
+            if let Some(surface) = self.ivars().surface.get() {
+                surface.set_framebuffer_only(false);
+            }
+        }
+        self.ivars().ray_tracing.borrow_mut().enabled = enabled;
+    }
+
+    // whether the frames are ray traced, it takes a scene and a device with ray tracing
+    pub fn is_ray_tracing(&self) -> bool {
+        let ray_tracing = self.ivars().ray_tracing.borrow();
+        ray_tracing.enabled && ray_tracing.scene.is_some()
+    }
+
+    // records a dispatch of `trace_primary_rays` covering the drawable, and returns the texture
+    // it writes for `encode_drawable_copy`. none when the frame isn't ray traced
+    pub(crate) fn record_ray_tracing(
+        &self,
+        compute_pass: &mut ComputePass,
+        drawable_size: CGSize,
+    ) -> Option<Retained<ProtocolObject<dyn MTLTexture>>> {
+        if !self.is_ray_tracing() {
+            return None;
+        }
+        let (width, height) = (drawable_size.width as usize, drawable_size.height as usize);
+        let pixel_format = self.pixel_format().mtl_pixel_format();
+        let mut ray_tracing = self.ivars().ray_tracing.borrow_mut();
+        let output = match ray_tracing.output.take() {
+            Some(output)
+                if output.width() == width
+                    && output.height() == height
+                    && output.pixelFormat() == pixel_format =>
+            {
+                output
+            }
+            _ => self.create_storage_texture(width, height, pixel_format),
+        };
+        ray_tracing.output = Some(output.clone());
+        let scene = ray_tracing.scene.as_ref().unwrap();
+
+        let camera = self.ivars().camera.get();
+        let aspect = (drawable_size.width / drawable_size.height) as f32;
+        let (center, direction) = camera.ray((0., 0.), aspect);
+        let (origin_x, direction_x) = camera.ray((1., 0.), aspect);
+        let (origin_y, direction_y) = camera.ray((0., 1.), aspect);
+        let change = |to: [f32; 3], from: [f32; 3]| {
+            let [x, y, z] = [0, 1, 2].map(|i| to[i] - from[i]);
+            [x, y, z, 0.]
+        };
+        // the orthographic views keep the geometry up to a unit in front of the target
+        let origin = match camera.projection {
+            Projection::Orthographic => [0, 1, 2].map(|i| center[i] - direction[i]),
+            Projection::Perspective { .. } => center,
+        };
+        let clear_color = self.ivars().surface.get().unwrap().clear_color();
+        let ray_camera = RayCamera {
+            origin: [origin[0], origin[1], origin[2], 1.],
+            origin_x: change(origin_x, center),
+            origin_y: change(origin_y, center),
+            direction: [direction[0], direction[1], direction[2], 0.],
+            direction_x: change(direction_x, direction),
+            direction_y: change(direction_y, direction),
+            background: [
+                clear_color.red as f32,
+                clear_color.green as f32,
+                clear_color.blue as f32,
+                clear_color.alpha as f32,
+            ],
+        };
+
+        compute_pass
+            .dispatch(
+                &self.compute_pipeline_state("trace_primary_rays"),
+                (width, height, 1),
+            )
+            .with_buffer(RAY_CAMERA_INDEX, &self.frame_buffer(&[ray_camera]))
+            .with_acceleration_structure(ACCELERATION_STRUCTURE_INDEX, &scene.structure)
+            .with_buffer(VERTICES_INDEX, &scene.vertex_buffer)
+            .with_buffer(INDICES_INDEX, &scene.index_buffer)
+            .with_texture(0, &output);
+        Some(output)
+    }
+
+    // copies a picture of the size and pixel format of the drawable over it, for the frames
+    // rendered elsewhere. a drawable created while the drawables were still framebuffer only
+    // can't be copied to, that frame keeps what its passes drew
+    pub(crate) fn encode_drawable_copy(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        source: &ProtocolObject<dyn MTLTexture>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) {
+        if drawable_texture.isFramebufferOnly() {
+            return;
+        }
+        let Some(encoder) = command_buffer.blitCommandEncoder() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a blit encoder for the frame.",
+            );
+            return;
+        };
+        unsafe { encoder.copyFromTexture_toTexture(source, drawable_texture) };
+        encoder.endEncoding();
+    }
+}
@@ -0,0 +1,537 @@
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+
+use image::RgbaImage;
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{MTLBuffer, MTLPackedFloat3, MTLRenderPipelineState};
+
+use crate::{
+    animation::{AnimationPlayer, NodeTransform, Skeleton, Skin},
+    camera::{multiply, Matrix, IDENTITY},
+    pbr::surface_vertices,
+    AnimationClip, ArgumentTable, GpuBuffer, LogLevel, Mesh, MetalRenderer, PbrMaterial,
+    RenderPass, SkinVertex, SurfaceVertex, VertexInput,
+};
+
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+
+// a mesh primitive of a glTF scene with its material
+struct ScenePrimitive {
+    mesh: Mesh,
+    surface: GpuBuffer<SurfaceVertex>,
+    // the arguments of `fragment_pbr`, shared by the primitives of a material
+    material: Rc<ArgumentTable>,
+    // the index of the skin moving the vertices, which are then in the space of their mesh
+    skin: Option<(usize, GpuBuffer<SkinVertex>)>,
+}
+
+// the meshes of a glTF scene in gpu buffers, drawn with `Scene::draw`, and its animations
+pub struct Scene {
+    primitives: Vec<ScenePrimitive>,
+    skeleton: Skeleton,
+    clips: Vec<AnimationClip>,
+    player: RefCell<AnimationPlayer>,
+    // the matrices of the joints of each skin in the pose of the last `Scene::advance`
+    joint_matrices: RefCell<Vec<Vec<Matrix>>>,
+}
+
+// a primitive read from the file, before it's moved into buffers
+struct PrimitiveData {
+    vertices: Vec<VertexInput>,
+    indices: Vec<u32>,
+    normals: Option<Vec<[f32; 3]>>,
+    tangents: Option<Vec<[f32; 4]>>,
+    texture_coordinates: Option<Vec<[f32; 2]>>,
+    material: Option<usize>,
+    skin: Option<(usize, Vec<SkinVertex>)>,
+}
+
+fn transform_point(matrix: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2]
+        .map(|row| matrix[0][row] * x + matrix[1][row] * y + matrix[2][row] * z + matrix[3][row])
+}
+
+fn transform_direction(matrix: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| matrix[0][row] * x + matrix[1][row] * y + matrix[2][row] * z)
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(vector: [f32; 3]) -> [f32; 3] {
+    let length = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    vector.map(|value| value / length.max(f32::EPSILON))
+}
+
+// the normals go through the cofactors of the linear part of `matrix`, so they stay
+// perpendicular to the surface when it's scaled unevenly. a mirroring transform flips them back
+fn transform_normal(matrix: &Matrix, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    let [column_x, column_y, column_z] = [0, 1, 2].map(|column| {
+        let [a, b, c, _] = matrix[column];
+        [a, b, c]
+    });
+    let cofactors = [
+        cross(column_y, column_z),
+        cross(column_z, column_x),
+        cross(column_x, column_y),
+    ];
+    let determinant: f32 = (0..3).map(|i| column_x[i] * cofactors[0][i]).sum();
+    let sign = if determinant < 0. { -1. } else { 1. };
+    normalize(
+        [0, 1, 2].map(|i| sign * (cofactors[0][i] * x + cofactors[1][i] * y + cofactors[2][i] * z)),
+    )
+}
+
+// the position of a skinned vertex in the pose of `joint_matrices`
+fn skin_position(position: [f32; 3], skin: &SkinVertex, joint_matrices: &[Matrix]) -> [f32; 3] {
+    let mut skinned = [0.; 3];
+    for (&joint, &weight) in skin.joints.iter().zip(&skin.weights) {
+        if let Some(matrix) = joint_matrices.get(joint as usize) {
+            let moved = transform_point(matrix, position);
+            for axis in 0..3 {
+                skinned[axis] += moved[axis] * weight;
+            }
+        }
+    }
+    skinned
+}
+
+// the 8-bit formats expanded to RGBA, None for 16-bit and float images
+fn rgba_image(data: &gltf::image::Data) -> Option<RgbaImage> {
+    use gltf::image::Format;
+    let pixels = match data.format {
+        Format::R8G8B8A8 => data.pixels.clone(),
+        Format::R8G8B8 => data
+            .pixels
+            .chunks_exact(3)
+            .flat_map(|texel| [texel[0], texel[1], texel[2], 255])
+            .collect(),
+        Format::R8G8 => data
+            .pixels
+            .chunks_exact(2)
+            .flat_map(|texel| [texel[0], texel[1], 0, 255])
+            .collect(),
+        Format::R8 => data
+            .pixels
+            .iter()
+            .flat_map(|&red| [red, red, red, 255])
+            .collect(),
+        _ => return None,
+    };
+    RgbaImage::from_raw(data.width, data.height, pixels)
+}
+
+// reads the triangle primitives of `node` and its children into world space, the skinned ones
+// are left in the space of their mesh where the joints move them from
+fn read_node(
+    node: &gltf::Node,
+    parent_transform: &Matrix,
+    buffers: &[gltf::buffer::Data],
+    primitives: &mut Vec<PrimitiveData>,
+) {
+    let node_transform = multiply(parent_transform, &node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let skin = match (node.skin(), reader.read_joints(0), reader.read_weights(0)) {
+                (Some(skin), Some(joints), Some(weights)) => {
+                    let vertices = joints
+                        .into_u16()
+                        .zip(weights.into_f32())
+                        .map(|(joints, weights)| SkinVertex { joints, weights })
+                        .collect();
+                    Some((skin.index(), vertices))
+                }
+                _ => None,
+            };
+            let transform = if skin.is_some() {
+                IDENTITY
+            } else {
+                node_transform
+            };
+            // the base color of the material is left to `fragment_pbr`, it multiplies these
+            let colors: Vec<[f32; 4]> = match reader.read_colors(0) {
+                Some(colors) => colors.into_rgba_f32().collect(),
+                None => vec![[1.; 4]; positions.len()],
+            };
+            let vertices: Vec<VertexInput> = positions
+                .zip(colors)
+                .map(|(position, color)| {
+                    let [x, y, z] = transform_point(&transform, position);
+                    let [red, green, blue] = [color[0], color[1], color[2]];
+                    VertexInput {
+                        position: MTLPackedFloat3 { x, y, z },
+                        color: MTLPackedFloat3 {
+                            x: red,
+                            y: green,
+                            z: blue,
+                        },
+                    }
+                })
+                .collect();
+            let indices = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+            let normals = reader.read_normals().map(|normals| {
+                normals
+                    .map(|normal| transform_normal(&transform, normal))
+                    .collect()
+            });
+            let tangents = reader.read_tangents().map(|tangents| {
+                tangents
+                    .map(|[x, y, z, sign]| {
+                        let [x, y, z] = normalize(transform_direction(&transform, [x, y, z]));
+                        [x, y, z, sign]
+                    })
+                    .collect()
+            });
+            let texture_coordinates = reader
+                .read_tex_coords(0)
+                .map(|texture_coordinates| texture_coordinates.into_f32().collect());
+            primitives.push(PrimitiveData {
+                vertices,
+                indices,
+                normals,
+                tangents,
+                texture_coordinates,
+                material: primitive.material().index(),
+                skin,
+            });
+        }
+    }
+    for child in node.children() {
+        read_node(&child, &node_transform, buffers, primitives);
+    }
+}
+
+// scales and moves the primitives into a box around the origin, where the default camera frames
+// them whatever units the file uses. the skinned primitives are fitted in the pose of
+// `joint_matrices`, the returned transform moves them along with their joints
+fn fit_into_view(primitives: &mut [PrimitiveData], joint_matrices: &[Vec<Matrix>]) -> Matrix {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for primitive in primitives.iter() {
+        for (index, vertex) in primitive.vertices.iter().enumerate() {
+            let position = [vertex.position.x, vertex.position.y, vertex.position.z];
+            let position = match &primitive.skin {
+                Some((skin, skin_vertices)) => {
+                    skin_position(position, &skin_vertices[index], &joint_matrices[*skin])
+                }
+                None => position,
+            };
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+        }
+    }
+    let center = [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2.);
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0., f32::max);
+    if extent <= 0. {
+        return IDENTITY;
+    }
+    // the largest side spans 90% of the view
+    let scale = 1.8 / extent;
+    for vertex in primitives
+        .iter_mut()
+        .filter(|primitive| primitive.skin.is_none())
+        .flat_map(|primitive| &mut primitive.vertices)
+    {
+        let position = &mut vertex.position;
+        position.x = (position.x - center[0]) * scale;
+        position.y = (position.y - center[1]) * scale;
+        position.z = (position.z - center[2]) * scale;
+    }
+    [
+        [scale, 0., 0., 0.],
+        [0., scale, 0., 0.],
+        [0., 0., scale, 0.],
+        [0, 1, 2, 3].map(|axis| if axis < 3 { -center[axis] * scale } else { 1. }),
+    ]
+}
+
+impl MetalRenderer {
+    // imports the default scene of a .gltf or .glb file, or its first scene without a default.
+    // the node transforms are applied to the vertices and the metallic roughness materials are
+    // uploaded with their textures. the normals and tangents the file leaves out are computed.
+    // the skinned meshes are moved by their joints on the gpu, which the animations of the file
+    // pose, the meshes of animated nodes without a skin keep their rest pose
+    pub fn load_scene(&self, path: impl AsRef<Path>) -> Result<Scene, gltf::Error> {
+        let (document, buffers, images) = gltf::import(path)?;
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next());
+
+        let mut primitives = Vec::new();
+        for node in scene.iter().flat_map(|scene| scene.nodes()) {
+            read_node(&node, &IDENTITY, &buffers, &mut primitives);
+        }
+        let mut parents = vec![None; document.nodes().len()];
+        for node in document.nodes() {
+            for child in node.children() {
+                parents[child.index()] = Some(node.index());
+            }
+        }
+        let mut skeleton = Skeleton {
+            rest_pose: document
+                .nodes()
+                .map(|node| NodeTransform::from_gltf(node.transform()))
+                .collect(),
+            parents,
+            skins: document
+                .skins()
+                .map(|skin| Skin::read(&skin, &buffers))
+                .collect(),
+            root: IDENTITY,
+        };
+        skeleton.root = fit_into_view(
+            &mut primitives,
+            &skeleton.joint_matrices(&skeleton.rest_pose),
+        );
+        let clips: Vec<AnimationClip> = document
+            .animations()
+            .map(|animation| AnimationClip::read(&animation, &buffers))
+            .collect();
+
+        // the images the materials share are uploaded once, as colors or as linear data
+        let mut textures = HashMap::new();
+        let mut texture = |texture: gltf::Texture, linear: bool| {
+            let index = texture.source().index();
+            textures
+                .entry((index, linear))
+                .or_insert_with(|| {
+                    let Some(image) = rgba_image(&images[index]) else {
+                        let message = format!("Skipped a {:?} texture.", images[index].format);
+                        self.log(LogLevel::Warn, &message);
+                        return None;
+                    };
+                    let texture = if linear {
+                        self.create_linear_texture(&image)
+                    } else {
+                        self.create_texture(&image)
+                    };
+                    texture
+                        .inspect_err(|error| self.log(LogLevel::Warn, &error.to_string()))
+                        .ok()
+                })
+                .clone()
+        };
+        let materials: Vec<Rc<ArgumentTable>> = document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                let material = PbrMaterial {
+                    base_color: pbr.base_color_factor(),
+                    metallic: pbr.metallic_factor(),
+                    roughness: pbr.roughness_factor(),
+                    normal_scale: material.normal_texture().map_or(1., |info| info.scale()),
+                    albedo_texture: pbr
+                        .base_color_texture()
+                        .and_then(|info| texture(info.texture(), false)),
+                    metallic_roughness_texture: pbr
+                        .metallic_roughness_texture()
+                        .and_then(|info| texture(info.texture(), true)),
+                    normal_texture: material
+                        .normal_texture()
+                        .and_then(|info| texture(info.texture(), true)),
+                };
+                Rc::new(self.create_pbr_material(&material))
+            })
+            .collect();
+        // the primitives without a material get the default one of glTF
+        let default_material = Rc::new(self.create_pbr_material(&PbrMaterial {
+            metallic: 1.,
+            roughness: 1.,
+            ..Default::default()
+        }));
+
+        let primitives: Vec<ScenePrimitive> = primitives
+            .into_iter()
+            .map(|primitive| {
+                let positions: Vec<[f32; 3]> = primitive
+                    .vertices
+                    .iter()
+                    .map(|vertex| [vertex.position.x, vertex.position.y, vertex.position.z])
+                    .collect();
+                let surface = surface_vertices(
+                    &positions,
+                    &primitive.indices,
+                    primitive.normals,
+                    primitive.tangents,
+                    primitive.texture_coordinates,
+                );
+                ScenePrimitive {
+                    mesh: self.create_mesh(&primitive.vertices, &primitive.indices),
+                    surface: self.create_gpu_buffer(&surface),
+                    material: primitive
+                        .material
+                        .map_or(&default_material, |material| &materials[material])
+                        .clone(),
+                    skin: primitive
+                        .skin
+                        .map(|(skin, vertices)| (skin, self.create_gpu_buffer(&vertices))),
+                }
+            })
+            .collect();
+        let message = format!(
+            "Imported a scene with {} primitives and {} animations.",
+            primitives.len(),
+            clips.len()
+        );
+        self.log(LogLevel::Info, &message);
+        let joint_matrices = skeleton.joint_matrices(&skeleton.rest_pose);
+        Ok(Scene {
+            primitives,
+            skeleton,
+            clips,
+            player: RefCell::default(),
+            joint_matrices: RefCell::new(joint_matrices),
+        })
+    }
+}
+
+impl Scene {
+    // the animations of the file, in its order
+    pub fn animation_clips(&self) -> &[AnimationClip] {
+        &self.clips
+    }
+
+    // plays `clip` from its start in a loop, or resumes it when it's the paused clip. a clip
+    // playing before stops
+    pub fn play(&self, clip: usize) {
+        if clip < self.clips.len() {
+            self.player.borrow_mut().play(clip);
+        }
+    }
+
+    // holds the pose until the clip is played again
+    pub fn pause(&self) {
+        self.player.borrow_mut().pause();
+    }
+
+    // fades from the playing clip to `clip` over `seconds`, both keep playing meanwhile.
+    // without a playing clip it plays `clip` right away
+    pub fn blend(&self, clip: usize, seconds: f32) {
+        if clip < self.clips.len() {
+            self.player.borrow_mut().blend(clip, seconds);
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.player.borrow().is_playing()
+    }
+
+    // the clip playing or paused, the one faded to while blending
+    pub fn current_clip(&self) -> Option<usize> {
+        self.player.borrow().current_clip()
+    }
+
+    // moves the clips `seconds` ahead and poses the joints for the next draws, called from the
+    // update callback or the fixed updates
+    pub fn advance(&self, seconds: f32) {
+        if self.skeleton.skins.is_empty() {
+            return;
+        }
+        let mut player = self.player.borrow_mut();
+        player.advance(seconds, &self.clips);
+        let pose = player.pose(&self.clips, &self.skeleton.rest_pose);
+        self.joint_matrices
+            .replace(self.skeleton.joint_matrices(&pose));
+    }
+
+    // the joint matrices of every skin for the draws of this frame
+    fn joint_buffers(
+        &self,
+        renderer: &MetalRenderer,
+    ) -> Vec<Retained<ProtocolObject<dyn MTLBuffer>>> {
+        self.joint_matrices
+            .borrow()
+            .iter()
+            .map(|joint_matrices| renderer.frame_buffer(joint_matrices))
+            .collect()
+    }
+
+    // records the primitives shaded with `vertex_pbr` and `fragment_pbr`, the skinned ones
+    // posed by `vertex_pbr_skinned`. deferred frames draw them into the g-buffer with
+    // `fragment_gbuffer` instead
+    pub fn draw(&self, renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+        match render_pass.deferred_pass() {
+            Some(gbuffer_pass) => {
+                let pipeline_state =
+                    renderer.gbuffer_pipeline_state("vertex_pbr", "fragment_gbuffer");
+                let skinned_pipeline_state =
+                    renderer.gbuffer_pipeline_state("vertex_pbr_skinned", "fragment_gbuffer");
+                self.draw_with(
+                    renderer,
+                    gbuffer_pass,
+                    [&pipeline_state, &skinned_pipeline_state],
+                );
+            }
+            None => {
+                let pipeline_state = renderer.render_pipeline_state("vertex_pbr", "fragment_pbr");
+                let skinned_pipeline_state =
+                    renderer.render_pipeline_state("vertex_pbr_skinned", "fragment_pbr");
+                self.draw_with(
+                    renderer,
+                    render_pass,
+                    [&pipeline_state, &skinned_pipeline_state],
+                );
+            }
+        }
+    }
+
+    // records the primitives with the pipelines of the static and the skinned ones
+    fn draw_with(
+        &self,
+        renderer: &MetalRenderer,
+        render_pass: &mut RenderPass,
+        [pipeline_state, skinned_pipeline_state]: [&PipelineState; 2],
+    ) {
+        let joint_buffers = self.joint_buffers(renderer);
+        for primitive in &self.primitives {
+            match &primitive.skin {
+                Some((skin, skin_vertices)) => render_pass
+                    .draw_mesh(skinned_pipeline_state, &primitive.mesh)
+                    .with_skin(skin_vertices, &joint_buffers[*skin]),
+                // the vertices of the static primitives are in the world already
+                None => render_pass
+                    .draw_mesh(pipeline_state, &primitive.mesh)
+                    .with_bounds(primitive.mesh.bounds),
+            }
+            .with_surface_attributes(&primitive.surface)
+            .with_fragment_arguments(&primitive.material);
+        }
+    }
+
+    // records the primitives into the shadow map, from the shadow callback
+    pub fn draw_shadows(&self, renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+        let pipeline_state = renderer.shadow_pipeline_state("vertex_main");
+        let skinned_pipeline_state = renderer.shadow_pipeline_state("vertex_skinned");
+        let joint_buffers = self.joint_buffers(renderer);
+        for primitive in &self.primitives {
+            match &primitive.skin {
+                Some((skin, skin_vertices)) => {
+                    render_pass
+                        .draw_mesh(&skinned_pipeline_state, &primitive.mesh)
+                        .with_skin(skin_vertices, &joint_buffers[*skin]);
+                }
+                None => {
+                    render_pass
+                        .draw_mesh(&pipeline_state, &primitive.mesh)
+                        .with_bounds(primitive.mesh.bounds);
+                }
+            }
+        }
+    }
+}
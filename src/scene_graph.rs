@@ -0,0 +1,247 @@
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::MTLRenderPipelineState;
+
+use crate::{
+    camera::{multiply, Matrix, IDENTITY},
+    ArgumentTable, InstanceData, Mesh, MetalRenderer, RenderPass,
+};
+
+// how the mesh of a node is shaded. the vertex function of the pipeline places the vertices
+// with the `InstanceData` at index 3 like `vertex_instanced`, which holds the world transform
+// of the node and `color`
+#[derive(Clone)]
+pub struct Material {
+    pub pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    // bound to the fragment shader argument buffer at index 0
+    pub fragment_arguments: Option<Rc<ArgumentTable>>,
+    // multiplied with the vertex colors
+    pub color: [f32; 4],
+}
+
+// a node of a `SceneGraph`, only valid for the graph that added it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // relative to the parent, or the world for the roots
+    transform: Matrix,
+    // the product of the transforms from the root down to this node
+    world_transform: Matrix,
+    // the transform changed since the world transform was last updated, the ones of the
+    // descendants are then out of date as well
+    dirty: bool,
+    visible: bool,
+    mesh: Option<Rc<Mesh>>,
+    material: Option<Rc<Material>>,
+}
+
+// a hierarchy of transforms with the meshes hanging off them. moving a node moves its whole
+// subtree, the world transforms are only updated for the subtrees that moved since the last
+// `update` or `draw`
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds a node without a mesh under `parent`, or as a root without one
+    pub fn add_node(&mut self, parent: Option<NodeId>, transform: Matrix) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            parent,
+            children: Vec::new(),
+            transform,
+            world_transform: transform,
+            dirty: true,
+            visible: true,
+            mesh: None,
+            material: None,
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    // moves `node` with its subtree under `parent`, or to the roots without one. a parent
+    // within the subtree would make a cycle, it's refused and false is returned
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) -> bool {
+        if parent.is_some_and(|parent| self.is_in_subtree(parent, node)) {
+            return false;
+        }
+        let siblings = match self.nodes[node.0].parent {
+            Some(old_parent) => &mut self.nodes[old_parent.0].children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != node);
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(node),
+            None => self.roots.push(node),
+        }
+        let node = &mut self.nodes[node.0];
+        node.parent = parent;
+        node.dirty = true;
+        true
+    }
+
+    // whether `node` is `root` or lies below it
+    fn is_in_subtree(&self, mut node: NodeId, root: NodeId) -> bool {
+        loop {
+            if node == root {
+                return true;
+            }
+            match self.nodes[node.0].parent {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.nodes[node.0].parent
+    }
+
+    pub fn children(&self, node: NodeId) -> &[NodeId] {
+        &self.nodes[node.0].children
+    }
+
+    // column major, relative to the parent of the node
+    pub fn set_transform(&mut self, node: NodeId, transform: Matrix) {
+        let node = &mut self.nodes[node.0];
+        node.transform = transform;
+        node.dirty = true;
+    }
+
+    pub fn transform(&self, node: NodeId) -> Matrix {
+        self.nodes[node.0].transform
+    }
+
+    // the transform from the space of the node to the world, up to date with the transforms
+    // set before the last `update` or `draw`
+    pub fn world_transform(&self, node: NodeId) -> Matrix {
+        self.nodes[node.0].world_transform
+    }
+
+    // the mesh drawn at the node, shared with any other nodes drawing it. nodes without a
+    // material draw it with `vertex_instanced` and `fragment_main`
+    pub fn set_mesh(&mut self, node: NodeId, mesh: Option<Rc<Mesh>>) {
+        self.nodes[node.0].mesh = mesh;
+    }
+
+    pub fn set_material(&mut self, node: NodeId, material: Option<Rc<Material>>) {
+        self.nodes[node.0].material = material;
+    }
+
+    // hides or shows the node together with its subtree
+    pub fn set_visible(&mut self, node: NodeId, visible: bool) {
+        self.nodes[node.0].visible = visible;
+    }
+
+    pub fn is_visible(&self, node: NodeId) -> bool {
+        self.nodes[node.0].visible
+    }
+
+    // brings the world transforms of the nodes that moved and of their subtrees up to date
+    pub fn update(&mut self) {
+        // the nodes still to visit, with the world transform of their parent and whether it
+        // changed
+        let mut stack: Vec<_> = self
+            .roots
+            .iter()
+            .map(|&root| (root, IDENTITY, false))
+            .collect();
+        while let Some((id, parent_transform, parent_changed)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world_transform = multiply(&parent_transform, &node.transform);
+                node.dirty = false;
+            }
+            let world_transform = node.world_transform;
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child, world_transform, changed)),
+            );
+        }
+    }
+
+    // updates the world transforms and records a draw of the mesh of every visible node, in
+    // the order of a depth first walk from the roots
+    pub fn draw(&mut self, renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+        self.update();
+        let mut stack: Vec<_> = self.roots.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id.0];
+            if !node.visible {
+                continue;
+            }
+            stack.extend(node.children.iter().rev());
+            let Some(mesh) = &node.mesh else {
+                continue;
+            };
+            let color = node
+                .material
+                .as_ref()
+                .map_or([1.; 4], |material| material.color);
+            let instance = InstanceData {
+                transform: node.world_transform,
+                color,
+            };
+            draw_instances(
+                renderer,
+                render_pass,
+                mesh,
+                node.material.as_deref(),
+                &[instance],
+            );
+        }
+    }
+}
+
+// records one instanced draw of `mesh` shaded with `material`, or with `vertex_instanced` and
+// `fragment_main` without one, bounded by the boxes of the instances. the transforms change
+// from frame to frame, so the instances go through a buffer of the frame rather than one the
+// frames in flight share
+pub(crate) fn draw_instances(
+    renderer: &MetalRenderer,
+    render_pass: &mut RenderPass,
+    mesh: &Mesh,
+    material: Option<&Material>,
+    instances: &[InstanceData],
+) {
+    match material {
+        Some(material) => {
+            render_pass.draw_mesh(&material.pipeline_state, mesh);
+            if let Some(fragment_arguments) = &material.fragment_arguments {
+                render_pass.with_fragment_arguments(fragment_arguments);
+            }
+        }
+        None => {
+            let pipeline_state =
+                renderer.render_pipeline_state("vertex_instanced", "fragment_main");
+            render_pass.draw_mesh(&pipeline_state, mesh);
+        }
+    }
+    if let Some(item) = render_pass.items.last_mut() {
+        item.instances = Some((renderer.frame_buffer(instances), instances.len()));
+    }
+    // culled as a whole, when every instance is outside the view
+    let bounds = instances
+        .iter()
+        .map(|instance| mesh.bounds.transformed(&instance.transform))
+        .reduce(|bounds, instance_bounds| bounds.union(&instance_bounds));
+    if let Some(bounds) = bounds {
+        render_pass.with_bounds(bounds);
+    }
+}
@@ -0,0 +1,260 @@
+// a display or a window captured with ScreenCaptureKit into metal textures, e.g. for a
+// magnifier or for streaming them. the stream delivers the frames on a queue of its own like a
+// camera, the latest one waits there until `update` picks it up on the main thread
+use std::sync::{Arc, Condvar, Mutex};
+
+use core::ptr;
+
+use block2::RcBlock;
+use core_graphics::display::CGMainDisplayID;
+use objc2::{
+    class, msg_send, msg_send_id,
+    rc::{Allocated, Retained},
+    runtime::{AnyObject, Bool, MessageReceiver, ProtocolObject},
+    sel,
+};
+use objc2_foundation::{CGRect, NSArray, NSError, NSString};
+use objc2_metal::MTLTexture;
+
+use crate::{
+    capture::{CaptureDelegate, CaptureQueue, CaptureState, STREAM_OUTPUT_SCREEN},
+    video::{CMTime, VideoTextures, PIXEL_FORMAT_BGRA},
+    MetalRenderer, VideoError,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the stream only delivers a frame when the screen changed, at most this often
+const MINIMUM_FRAME_INTERVAL: f64 = 1. / 60.;
+
+// the frames the stream can have in flight before it waits for their pixel buffers
+const QUEUE_DEPTH: isize = 5;
+
+#[link(name = "ScreenCaptureKit", kind = "framework")]
+extern "C" {}
+
+// what's captured
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScreenSource {
+    // the display with the menu bar
+    MainDisplay,
+    // a display by its core graphics id
+    Display(u32),
+    // the first window on screen whose title or application name contains the text
+    Window(String),
+}
+
+// the objects ScreenCaptureKit hands to its completion handlers, which don't change after
+struct Shareable(Retained<AnyObject>);
+
+unsafe impl Send for Shareable {}
+
+// the content the capture can choose from, none while ScreenCaptureKit is looking it up
+type SharedContent = Arc<(Mutex<Option<Result<Shareable, String>>>, Condvar)>;
+
+// a display or a window being captured, from `MetalRenderer::capture_screen`
+pub struct ScreenCapture {
+    stream: Retained<AnyObject>,
+    // the stream only holds on to its output and its delegate weakly
+    _delegate: Retained<CaptureDelegate>,
+    _queue: CaptureQueue,
+    state: Arc<CaptureState>,
+    textures: VideoTextures,
+}
+
+impl ScreenCapture {
+    // picks up the latest frame of the stream, called once a frame before using the texture.
+    // returns the texture of the latest frame, none until the first one is captured
+    pub fn update(&mut self) -> Option<&Texture> {
+        if let Some(pixel_buffer) = self.state.take_frame() {
+            self.textures.push(pixel_buffer.0);
+        }
+        self.texture()
+    }
+
+    // the texture of the latest frame, in place until the next one is picked up
+    pub fn texture(&self) -> Option<&Texture> {
+        self.textures.latest()
+    }
+
+    // why the stream stopped, e.g. the captured window was closed
+    pub fn error(&self) -> Option<String> {
+        self.state.error()
+    }
+}
+
+impl Drop for ScreenCapture {
+    fn drop(&mut self) {
+        unsafe {
+            let _: () = msg_send![
+                &self.stream,
+                stopCaptureWithCompletionHandler: None::<&block2::Block<dyn Fn(*mut NSError)>>
+            ];
+        }
+    }
+}
+
+impl MetalRenderer {
+    // captures `source` into textures of the device of the renderer at the pixel size it has
+    // on screen. blocks until ScreenCaptureKit listed the displays and windows, the frames start
+    // coming in after that. the user has to allow the application to record the screen in the
+    // privacy settings first, needs macOS 12.3
+    pub fn capture_screen(&self, source: ScreenSource) -> Result<ScreenCapture, VideoError> {
+        let textures = VideoTextures::new(&self.device())?;
+        let content = shareable_content()?;
+        let scale_factor = self.scale_factor();
+        unsafe {
+            let (filter, frame) = match source {
+                ScreenSource::MainDisplay => display_filter(&content, CGMainDisplayID())?,
+                ScreenSource::Display(display_id) => display_filter(&content, display_id)?,
+                ScreenSource::Window(name) => window_filter(&content, &name)?,
+            };
+
+            let configuration: Retained<AnyObject> =
+                msg_send_id![class!(SCStreamConfiguration), new];
+            let width = (frame.size.width * scale_factor) as usize;
+            let height = (frame.size.height * scale_factor) as usize;
+            let _: () = msg_send![&configuration, setWidth: width.max(1)];
+            let _: () = msg_send![&configuration, setHeight: height.max(1)];
+            let _: () = msg_send![&configuration, setPixelFormat: PIXEL_FORMAT_BGRA];
+            let _: () = msg_send![
+                &configuration,
+                setMinimumFrameInterval: CMTime::from_seconds(MINIMUM_FRAME_INTERVAL)
+            ];
+            let _: () = msg_send![&configuration, setQueueDepth: QUEUE_DEPTH];
+            let _: () = msg_send![&configuration, setShowsCursor: true];
+
+            let state = Arc::<CaptureState>::default();
+            let delegate = CaptureDelegate::new(state.clone());
+            let queue = CaptureQueue::new(c"rust-tao-metal.screen-capture");
+            let stream: Allocated<AnyObject> = msg_send_id![class!(SCStream), alloc];
+            let stream: Retained<AnyObject> = msg_send_id![
+                stream,
+                initWithFilter: &*filter,
+                configuration: &*configuration,
+                delegate: &*delegate
+            ];
+            // `type` can't be a part of a selector in `msg_send!`
+            let mut error: *mut NSError = ptr::null_mut();
+            let added: Bool = (&*stream).send_message(
+                sel!(addStreamOutput:type:sampleHandlerQueue:error:),
+                (
+                    &*delegate as &AnyObject,
+                    STREAM_OUTPUT_SCREEN,
+                    queue.as_ptr(),
+                    &mut error,
+                ),
+            );
+            if !added.as_bool() {
+                let message = Retained::retain(error)
+                    .map(|error| error.localizedDescription().to_string())
+                    .unwrap_or_default();
+                return Err(VideoError::ScreenCapture(message));
+            }
+            let completion_handler = RcBlock::new({
+                let state = state.clone();
+                move |error: *mut NSError| {
+                    if let Some(error) = error.as_ref() {
+                        state.set_error(error);
+                    }
+                }
+            });
+            let _: () = msg_send![&stream, startCaptureWithCompletionHandler: &*completion_handler];
+            Ok(ScreenCapture {
+                stream,
+                _delegate: delegate,
+                _queue: queue,
+                state,
+                textures,
+            })
+        }
+    }
+}
+
+// the displays and the windows on screen, waits for ScreenCaptureKit to list them
+fn shareable_content() -> Result<Retained<AnyObject>, VideoError> {
+    let shared = SharedContent::default();
+    let completion_handler = RcBlock::new({
+        let shared = shared.clone();
+        move |content: *mut AnyObject, error: *mut NSError| {
+            let content = match unsafe { Retained::retain(content) } {
+                Some(content) => Ok(Shareable(content)),
+                None => Err(unsafe { error.as_ref() }
+                    .map(|error| error.localizedDescription().to_string())
+                    .unwrap_or_default()),
+            };
+            let (result, condvar) = &*shared;
+            *result.lock().unwrap() = Some(content);
+            condvar.notify_all();
+        }
+    });
+    unsafe {
+        let _: () = msg_send![
+            class!(SCShareableContent),
+            getShareableContentExcludingDesktopWindows: true,
+            onScreenWindowsOnly: true,
+            completionHandler: &*completion_handler
+        ];
+    }
+    let (result, condvar) = &*shared;
+    let mut result = condvar
+        .wait_while(result.lock().unwrap(), |result| result.is_none())
+        .unwrap();
+    result
+        .take()
+        .expect("Failed to wait for the shareable content.")
+        .map(|Shareable(content)| content)
+        .map_err(VideoError::ScreenCapture)
+}
+
+// a filter of the display `display_id` and its frame in points
+unsafe fn display_filter(
+    content: &AnyObject,
+    display_id: u32,
+) -> Result<(Retained<AnyObject>, CGRect), VideoError> {
+    let displays: Retained<NSArray<AnyObject>> = msg_send_id![content, displays];
+    let display = displays
+        .iter()
+        .find(|display| {
+            let id: u32 = msg_send![*display, displayID];
+            id == display_id
+        })
+        .ok_or_else(|| VideoError::ScreenCapture(format!("There is no display {display_id}.")))?;
+    let frame: CGRect = msg_send![display, frame];
+    let filter: Allocated<AnyObject> = msg_send_id![class!(SCContentFilter), alloc];
+    let filter = msg_send_id![
+        filter,
+        initWithDisplay: display,
+        excludingWindows: &*NSArray::<AnyObject>::new()
+    ];
+    Ok((filter, frame))
+}
+
+// a filter of the window `name` matches and its frame in points
+unsafe fn window_filter(
+    content: &AnyObject,
+    name: &str,
+) -> Result<(Retained<AnyObject>, CGRect), VideoError> {
+    let windows: Retained<NSArray<AnyObject>> = msg_send_id![content, windows];
+    let window = windows
+        .iter()
+        .find(|window| window_matches(window, name))
+        .ok_or_else(|| VideoError::ScreenCapture(format!("There is no window {name}.")))?;
+    let frame: CGRect = msg_send![window, frame];
+    let filter: Allocated<AnyObject> = msg_send_id![class!(SCContentFilter), alloc];
+    let filter = msg_send_id![filter, initWithDesktopIndependentWindow: window];
+    Ok((filter, frame))
+}
+
+// whether the title of `window` or the name of its application contains `name`
+unsafe fn window_matches(window: &AnyObject, name: &str) -> bool {
+    let title: Option<Retained<NSString>> = msg_send_id![window, title];
+    let application: Option<Retained<AnyObject>> = msg_send_id![window, owningApplication];
+    let application_name: Option<Retained<NSString>> = application
+        .as_ref()
+        .and_then(|application| msg_send_id![application, applicationName]);
+    [title, application_name]
+        .iter()
+        .flatten()
+        .any(|text| text.to_string().contains(name))
+}
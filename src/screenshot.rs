@@ -0,0 +1,352 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use core::{cell::RefCell, ptr::NonNull};
+
+use block2::RcBlock;
+use image::{ImageError, RgbaImage};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLBlitCommandEncoder, MTLBuffer, MTLCommandBuffer, MTLCommandBufferStatus, MTLCommandEncoder,
+    MTLCommandQueue, MTLDevice, MTLOrigin, MTLPixelFormat, MTLResourceOptions, MTLSize, MTLTexture,
+};
+
+use crate::{LogLevel, MetalRenderer, RenderTarget};
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    // only 8-bit BGRA and RGBA or 16-bit float RGBA textures can be saved
+    UnsupportedFormat(MTLPixelFormat),
+    // the command buffer of the captured frame failed on the gpu
+    Gpu(String),
+    // the image couldn't be encoded or written
+    Encode(ImageError),
+    // the renderer didn't draw a frame to read back, e.g. while its size is empty
+    NoFrame,
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::UnsupportedFormat(pixel_format) => {
+                write!(f, "Can't capture a {pixel_format:?} texture")
+            }
+            ScreenshotError::Gpu(error) => write!(f, "The captured frame failed: {error}"),
+            ScreenshotError::Encode(error) => write!(f, "Failed to save the capture: {error}"),
+            ScreenshotError::NoFrame => write!(f, "No frame was drawn to capture"),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+impl From<ImageError> for ScreenshotError {
+    fn from(error: ImageError) -> Self {
+        ScreenshotError::Encode(error)
+    }
+}
+
+// called on a background thread once the capture is written or failed
+type ScreenshotHandler = Box<dyn FnOnce(Result<(), ScreenshotError>) + Send>;
+
+// a capture waiting for the next frame the renderer draws
+pub(crate) struct PendingScreenshot {
+    // the drawable of the frame when unset
+    target: Option<RenderTarget>,
+    path: PathBuf,
+    capture_handler: ScreenshotHandler,
+}
+
+// the number of bytes of a pixel for the formats a capture can convert to png
+fn bytes_per_pixel(pixel_format: MTLPixelFormat) -> Option<usize> {
+    match pixel_format {
+        MTLPixelFormat::BGRA8Unorm
+        | MTLPixelFormat::BGRA8Unorm_sRGB
+        | MTLPixelFormat::RGBA8Unorm
+        | MTLPixelFormat::RGBA8Unorm_sRGB => Some(4),
+        MTLPixelFormat::RGBA16Float => Some(8),
+        _ => None,
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f32::from(half & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0. => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1. + mantissa / 1024.) * 2f32.powi(exponent - 15),
+    }
+}
+
+// encodes a linear extended sRGB value to 8-bit sRGB, clipping what lies outside of the sdr range
+fn linear_to_srgb8(value: f32) -> u8 {
+    let value = if value.is_nan() {
+        0.
+    } else {
+        value.clamp(0., 1.)
+    };
+    let encoded = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    };
+    (encoded * 255.).round() as u8
+}
+
+// converts the rows the blit copied into 8-bit RGBA
+fn to_rgba8(pixel_format: MTLPixelFormat, data: &[u8]) -> Vec<u8> {
+    match pixel_format {
+        MTLPixelFormat::BGRA8Unorm | MTLPixelFormat::BGRA8Unorm_sRGB => data
+            .chunks_exact(4)
+            .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+            .collect(),
+        MTLPixelFormat::RGBA16Float => data
+            .chunks_exact(8)
+            .flat_map(|pixel| {
+                let channel = |index: usize| {
+                    f16_to_f32(u16::from_le_bytes([pixel[index * 2], pixel[index * 2 + 1]]))
+                };
+                // alpha is stored linearly
+                let alpha = (channel(3).clamp(0., 1.) * 255.).round() as u8;
+                [
+                    linear_to_srgb8(channel(0)),
+                    linear_to_srgb8(channel(1)),
+                    linear_to_srgb8(channel(2)),
+                    alpha,
+                ]
+            })
+            .collect(),
+        _ => data.to_vec(),
+    }
+}
+
+impl MetalRenderer {
+    // saves the drawable of the next frame as a png at `path`. the frame is read back once the
+    // gpu finished it and encoded on a background thread, which then calls `capture_handler`.
+    // drawables can only be read back when they're not just render targets, so the view stops
+    // creating framebuffer only drawables from the first capture on. views drawing on demand
+    // capture the frame of their next redraw
+    pub fn capture_frame(
+        &self,
+        path: impl AsRef<Path>,
+        capture_handler: impl FnOnce(Result<(), ScreenshotError>) + Send + 'static,
+    ) {
+        if let Some(surface) = self.ivars().surface.get() {
+            surface.set_framebuffer_only(false);
+        }
+        self.ivars()
+            .pending_screenshots
+            .borrow_mut()
+            .push(PendingScreenshot {
+                target: None,
+                path: path.as_ref().to_owned(),
+                capture_handler: Box::new(capture_handler),
+            });
+    }
+
+    // like `capture_frame`, saving `target` as the passes of the next frame left it
+    pub fn capture_render_target(
+        &self,
+        target: &RenderTarget,
+        path: impl AsRef<Path>,
+        capture_handler: impl FnOnce(Result<(), ScreenshotError>) + Send + 'static,
+    ) {
+        self.ivars()
+            .pending_screenshots
+            .borrow_mut()
+            .push(PendingScreenshot {
+                target: Some(target.clone()),
+                path: path.as_ref().to_owned(),
+                capture_handler: Box::new(capture_handler),
+            });
+    }
+
+    // copies `texture` into memory the cpu can read once the work queued before is done, and
+    // waits for it. takes 8-bit and half float color textures, half floats are encoded to sRGB
+    pub fn read_texture(
+        &self,
+        texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> Result<RgbaImage, ScreenshotError> {
+        let pixel_format = texture.pixelFormat();
+        let bytes_per_pixel = bytes_per_pixel(pixel_format)
+            .ok_or(ScreenshotError::UnsupportedFormat(pixel_format))?;
+        let (width, height) = (texture.width(), texture.height());
+        let bytes_per_row = width * bytes_per_pixel;
+        let buffer = self
+            .device()
+            .newBufferWithLength_options(
+                bytes_per_row * height,
+                MTLResourceOptions::MTLResourceStorageModeShared,
+            )
+            .expect("Failed to create a capture buffer.");
+        let command_buffer = self
+            .command_queue()
+            .commandBuffer()
+            .expect("Failed to create a command buffer.");
+        let encoder = command_buffer
+            .blitCommandEncoder()
+            .expect("Failed to create a blit encoder.");
+        unsafe {
+            encoder.copyFromTexture_sourceSlice_sourceLevel_sourceOrigin_sourceSize_toBuffer_destinationOffset_destinationBytesPerRow_destinationBytesPerImage(
+                texture,
+                0,
+                0,
+                MTLOrigin { x: 0, y: 0, z: 0 },
+                MTLSize {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                &buffer,
+                0,
+                bytes_per_row,
+                bytes_per_row * height,
+            )
+        };
+        encoder.endEncoding();
+        command_buffer.commit();
+        unsafe { command_buffer.waitUntilCompleted() };
+        if command_buffer.status() == MTLCommandBufferStatus::Error {
+            let error = unsafe { command_buffer.error() }
+                .map(|error| error.localizedDescription().to_string())
+                .unwrap_or_default();
+            return Err(ScreenshotError::Gpu(error));
+        }
+        let data = unsafe {
+            core::slice::from_raw_parts(buffer.contents().as_ptr().cast::<u8>(), buffer.length())
+        };
+        let rgba = to_rgba8(pixel_format, data);
+        Ok(RgbaImage::from_raw(width as u32, height as u32, rgba)
+            .expect("Failed to wrap the captured pixels."))
+    }
+
+    // copies the textures of the pending captures into buffers at the end of the frame, and
+    // saves them once `command_buffer` completed. a drawable still created before the first
+    // capture can't be read, its capture waits for the next frame
+    pub(crate) fn encode_screenshots(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) {
+        let mut pending_screenshots = self.ivars().pending_screenshots.borrow_mut();
+        if pending_screenshots.is_empty() {
+            return;
+        }
+        let Some(encoder) = command_buffer.blitCommandEncoder() else {
+            self.log(
+                LogLevel::Warn,
+                "Failed to create a blit encoder for a capture.",
+            );
+            return;
+        };
+
+        let mut deferred = Vec::new();
+        for capture in pending_screenshots.drain(..) {
+            let texture: &ProtocolObject<dyn MTLTexture> = match &capture.target {
+                Some(target) => target.texture(),
+                None => drawable_texture,
+            };
+            if texture.isFramebufferOnly() {
+                // the capture was requested before the view was created
+                if let Some(surface) = self.ivars().surface.get() {
+                    surface.set_framebuffer_only(false);
+                }
+                deferred.push(capture);
+                continue;
+            }
+            let pixel_format = texture.pixelFormat();
+            let Some(bytes_per_pixel) = bytes_per_pixel(pixel_format) else {
+                (capture.capture_handler)(Err(ScreenshotError::UnsupportedFormat(pixel_format)));
+                continue;
+            };
+            let (width, height) = (texture.width(), texture.height());
+            let bytes_per_row = width * bytes_per_pixel;
+            let buffer = self
+                .device()
+                .newBufferWithLength_options(
+                    bytes_per_row * height,
+                    MTLResourceOptions::MTLResourceStorageModeShared,
+                )
+                .expect("Failed to create a capture buffer.");
+            unsafe {
+                encoder.copyFromTexture_sourceSlice_sourceLevel_sourceOrigin_sourceSize_toBuffer_destinationOffset_destinationBytesPerRow_destinationBytesPerImage(
+                    texture,
+                    0,
+                    0,
+                    MTLOrigin { x: 0, y: 0, z: 0 },
+                    MTLSize {
+                        width,
+                        height,
+                        depth: 1,
+                    },
+                    &buffer,
+                    0,
+                    bytes_per_row,
+                    bytes_per_row * height,
+                )
+            };
+            add_capture_handler(
+                command_buffer,
+                buffer,
+                pixel_format,
+                (width, height),
+                capture,
+            );
+        }
+        encoder.endEncoding();
+        pending_screenshots.extend(deferred);
+    }
+}
+
+// reads `buffer` back once `command_buffer` completed and writes it as a png from a thread of
+// its own, the completion thread only copies the pixels out
+fn add_capture_handler(
+    command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+    buffer: Retained<ProtocolObject<dyn MTLBuffer>>,
+    pixel_format: MTLPixelFormat,
+    (width, height): (usize, usize),
+    capture: PendingScreenshot,
+) {
+    // the block is an `Fn`, the capture is handed over on its single call
+    let capture = RefCell::new(Some(capture));
+    let completed_handler = RcBlock::new(
+        move |command_buffer: NonNull<ProtocolObject<dyn MTLCommandBuffer>>| {
+            let Some(PendingScreenshot {
+                path,
+                capture_handler,
+                ..
+            }) = capture.take()
+            else {
+                return;
+            };
+            let command_buffer = unsafe { command_buffer.as_ref() };
+            if command_buffer.status() == MTLCommandBufferStatus::Error {
+                let error = unsafe { command_buffer.error() }
+                    .map(|error| error.localizedDescription().to_string())
+                    .unwrap_or_default();
+                capture_handler(Err(ScreenshotError::Gpu(error)));
+                return;
+            }
+            let data = unsafe {
+                core::slice::from_raw_parts(
+                    buffer.contents().as_ptr().cast::<u8>(),
+                    buffer.length(),
+                )
+            };
+            let rgba = to_rgba8(pixel_format, data);
+            thread::spawn(move || {
+                let image = RgbaImage::from_raw(width as u32, height as u32, rgba)
+                    .expect("Failed to wrap the captured pixels.");
+                capture_handler(image.save(path).map_err(ScreenshotError::from));
+            });
+        },
+    );
+    // metal copies the block, so it can be released after this frame
+    unsafe { command_buffer.addCompletedHandler(&*completed_handler as *const _ as *mut _) };
+}
@@ -0,0 +1,299 @@
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLBuffer, MTLCompareFunction, MTLDepthStencilDescriptor, MTLDepthStencilState, MTLDevice,
+    MTLLoadAction, MTLPixelFormat, MTLRenderPassDescriptor, MTLRenderPipelineState,
+    MTLSamplerAddressMode, MTLSamplerDescriptor, MTLSamplerMinMagFilter, MTLSamplerState,
+    MTLStoreAction, MTLTexture, MTLTextureUsage,
+};
+
+use crate::{
+    camera::{multiply, Matrix, IDENTITY},
+    target::attachment_texture,
+    MetalRenderer, PipelineDescriptor, RenderPass, SceneProperties,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+type SamplerState = Retained<ProtocolObject<dyn MTLSamplerState>>;
+
+// the map the draws of a frame sample the shadows from, with its comparison sampler
+pub(crate) type ShadowMap = (Texture, SamplerState);
+
+// the fragment texture and sampler index of the shadow map, after the ones of the materials
+pub(crate) const SHADOW_MAP_INDEX: usize = 8;
+
+const SHADOW_MAP_FORMAT: MTLPixelFormat = MTLPixelFormat::Depth32Float;
+
+// a light shining in one direction from far away, like the sun. the draws of the render
+// callback shaded with `vertex_shadowed` and `fragment_shadowed` take its color and, when it
+// casts shadows, the ones of the draws of the shadow callback
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DirectionalLight {
+    // the direction the light travels in, it doesn't have to be normalized
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    // the share of the light reaching the parts in shadow
+    pub ambient: f32,
+    pub cast_shadows: bool,
+    // the width and height of the shadow map in texels
+    pub shadow_map_size: usize,
+    // half the size of the box about the origin the shadows are cast in
+    pub shadow_extent: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            direction: [-0.5, -1., -0.5],
+            color: [1., 1., 1.],
+            ambient: 0.3,
+            cast_shadows: true,
+            shadow_map_size: 2048,
+            shadow_extent: 2.,
+        }
+    }
+}
+
+impl DirectionalLight {
+    // looks along the light at the box of the shadows, its depth ranges from the side facing
+    // the light at 0 to the far side at 1
+    pub(crate) fn view_projection_matrix(&self) -> Matrix {
+        let back = normalize(self.direction.map(|value| -value));
+        // any up works for the shadows, as long as it isn't parallel to the light
+        let up = if back[1].abs() > 0.99 {
+            [0., 0., 1.]
+        } else {
+            [0., 1., 0.]
+        };
+        let right = normalize(cross(up, back));
+        let up = cross(back, right);
+        let view = [
+            [right[0], up[0], back[0], 0.],
+            [right[1], up[1], back[1], 0.],
+            [right[2], up[2], back[2], 0.],
+            [0., 0., 0., 1.],
+        ];
+        let scale = 1. / self.shadow_extent;
+        let projection = [
+            [scale, 0., 0., 0.],
+            [0., scale, 0., 0.],
+            [0., 0., -0.5 * scale, 0.],
+            [0., 0., 0.5, 1.],
+        ];
+        multiply(&projection, &view)
+    }
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub(crate) fn normalize(vector: [f32; 3]) -> [f32; 3] {
+    let length = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    vector.map(|value| value / length.max(f32::EPSILON))
+}
+
+// the light in the scene properties, `LightProperties` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub(crate) struct LightProperties {
+    view_projection: Matrix,
+    // the ambient share in w
+    color: [f32; 4],
+    // normalized, w is 0
+    direction: [f32; 4],
+    // whether the shadow map is bound and rendered
+    cast_shadows: u32,
+    // the size in pixels, the columns and the rows of the tiles the local lights are culled
+    // in, zeros without local lights
+    light_tiles: [u32; 3],
+}
+
+impl Default for LightProperties {
+    // unlit, the colors of the vertices are kept by `fragment_shadowed`
+    fn default() -> Self {
+        LightProperties {
+            view_projection: IDENTITY,
+            color: [1., 1., 1., 1.],
+            // into the view, for the shading that needs a direction
+            direction: [0., 0., -1., 0.],
+            cast_shadows: 0,
+            light_tiles: [0; 3],
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ShadowState {
+    light: Option<DirectionalLight>,
+    // recreated when the size of the light's map changes
+    shadow_map: Option<Texture>,
+    sampler: Option<SamplerState>,
+    depth_stencil_state: Option<Retained<ProtocolObject<dyn MTLDepthStencilState>>>,
+}
+
+impl ShadowState {
+    // drops what was created on the device, the light stays and gets a new map next frame
+    pub(crate) fn release_device_resources(&mut self) {
+        self.shadow_map = None;
+        self.sampler = None;
+        self.depth_stencil_state = None;
+    }
+}
+
+// the depth only pass of a frame from the light, encoded ahead of the frame passes
+pub(crate) struct ShadowPass {
+    pub(crate) pass_descriptor: Retained<MTLRenderPassDescriptor>,
+    pub(crate) render_pass: RenderPass,
+    // the scene properties of the frame, seen from the light
+    pub(crate) scene_properties: Retained<ProtocolObject<dyn MTLBuffer>>,
+    // the view of the light the draws are culled against
+    pub(crate) view_projection: Matrix,
+}
+
+impl MetalRenderer {
+    // lights the draws shaded with `fragment_shadowed`, none leaves them unlit
+    pub fn set_directional_light(&self, light: Option<DirectionalLight>) {
+        self.ivars().shadows.borrow_mut().light = light;
+    }
+
+    pub fn directional_light(&self) -> Option<DirectionalLight> {
+        self.ivars().shadows.borrow().light
+    }
+
+    // records the draws casting the shadows of the directional light, called every frame it
+    // casts them before the render callback. the draws need pipelines from
+    // `shadow_pipeline_state` and see the scene through the light
+    pub fn set_shadow_callback(&self, shadow_callback: impl Fn(&Self, &mut RenderPass) + 'static) {
+        self.ivars()
+            .shadow_callback
+            .replace(Some(Box::new(shadow_callback)));
+    }
+
+    // a pipeline drawing only the depth of `vertex_function` into the shadow map
+    pub fn shadow_pipeline_state(
+        &self,
+        vertex_function: &str,
+    ) -> Retained<ProtocolObject<dyn MTLRenderPipelineState>> {
+        let descriptor = PipelineDescriptor {
+            // there's no fragment function without a color attachment
+            fragment_function: String::new(),
+            color_format: MTLPixelFormat::Invalid,
+            depth_format: Some(SHADOW_MAP_FORMAT),
+            sample_count: 1,
+            ..self.pipeline_descriptor(vertex_function, "")
+        };
+        self.render_pipeline_state_for(&descriptor)
+    }
+
+    // the light of the frame for its scene properties, with the tiles of its local lights
+    pub(crate) fn light_properties(&self, light_tiles: [u32; 3]) -> LightProperties {
+        let Some(light) = self.directional_light() else {
+            return LightProperties {
+                light_tiles,
+                ..Default::default()
+            };
+        };
+        let [red, green, blue] = light.color;
+        let [x, y, z] = normalize(light.direction);
+        LightProperties {
+            view_projection: light.view_projection_matrix(),
+            color: [red, green, blue, light.ambient],
+            direction: [x, y, z, 0.],
+            cast_shadows: light.cast_shadows as u32,
+            light_tiles,
+        }
+    }
+
+    // the shadow map and the comparison sampler the draws of the frame sample it with, none
+    // without a directional light. the map is bound whether or not the light casts shadows,
+    // so that the shaders reading it can be used either way
+    pub(crate) fn prepare_shadow_map(&self) -> Option<ShadowMap> {
+        let mut shadows = self.ivars().shadows.borrow_mut();
+        let light = shadows.light?;
+        let size = light.shadow_map_size.max(1);
+        let device = self.device();
+        let shadow_map = match shadows.shadow_map.take() {
+            Some(shadow_map) if shadow_map.width() == size => shadow_map,
+            _ => attachment_texture(
+                &device,
+                SHADOW_MAP_FORMAT,
+                (size, size),
+                1,
+                MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead,
+            ),
+        };
+        shadows.shadow_map = Some(shadow_map.clone());
+        let sampler = shadows
+            .sampler
+            .get_or_insert_with(|| {
+                // passes where the fragment is no farther from the light than the map, the
+                // linear filtering blends the comparisons of 4 texels
+                let descriptor = MTLSamplerDescriptor::new();
+                descriptor.setMinFilter(MTLSamplerMinMagFilter::Linear);
+                descriptor.setMagFilter(MTLSamplerMinMagFilter::Linear);
+                descriptor.setSAddressMode(MTLSamplerAddressMode::ClampToEdge);
+                descriptor.setTAddressMode(MTLSamplerAddressMode::ClampToEdge);
+                descriptor.setCompareFunction(MTLCompareFunction::LessEqual);
+                device
+                    .newSamplerStateWithDescriptor(&descriptor)
+                    .expect("Failed to create the shadow sampler.")
+            })
+            .clone();
+        Some((shadow_map, sampler))
+    }
+
+    // records the draws of the shadow callback into `shadow_map` with the scene properties of
+    // the frame seen from the light, none when the light casts no shadows
+    pub(crate) fn record_shadow_pass(
+        &self,
+        shadow_map: &Texture,
+        scene_properties: &ProtocolObject<dyn MTLBuffer>,
+    ) -> Option<ShadowPass> {
+        if !self.directional_light()?.cast_shadows {
+            return None;
+        }
+        let shadow_callback = self.ivars().shadow_callback.borrow();
+        let shadow_callback = shadow_callback.as_ref()?;
+
+        let depth_stencil_state = self
+            .ivars()
+            .shadows
+            .borrow_mut()
+            .depth_stencil_state
+            .get_or_insert_with(|| {
+                let descriptor = unsafe { MTLDepthStencilDescriptor::new() };
+                descriptor.setDepthCompareFunction(MTLCompareFunction::LessEqual);
+                descriptor.setDepthWriteEnabled(true);
+                self.device()
+                    .newDepthStencilStateWithDescriptor(&descriptor)
+                    .expect("Failed to create the shadow depth stencil state.")
+            })
+            .clone();
+        let mut render_pass = RenderPass {
+            depth_stencil_state: Some(depth_stencil_state),
+            label: Some("Shadow pass".to_owned()),
+            ..Default::default()
+        };
+        shadow_callback(self, &mut render_pass);
+
+        let mut properties =
+            unsafe { scene_properties.contents().cast::<SceneProperties>().read() };
+        properties.view_projection = properties.light.view_projection;
+        let pass_descriptor = MTLRenderPassDescriptor::renderPassDescriptor();
+        let depth_attachment = pass_descriptor.depthAttachment();
+        depth_attachment.setTexture(Some(shadow_map));
+        depth_attachment.setLoadAction(MTLLoadAction::Clear);
+        depth_attachment.setStoreAction(MTLStoreAction::Store);
+        depth_attachment.setClearDepth(1.);
+        Some(ShadowPass {
+            pass_descriptor,
+            render_pass,
+            scene_properties: self.frame_buffer(&[properties]),
+            view_projection: properties.view_projection,
+        })
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use image::Rgba32FImage;
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLCommandBuffer, MTLCommandEncoder, MTLCommandQueue, MTLCompareFunction,
+    MTLComputeCommandEncoder, MTLComputePipelineState, MTLDepthStencilDescriptor,
+    MTLDepthStencilState, MTLDevice, MTLOrigin, MTLPixelFormat, MTLPrimitiveType, MTLRegion,
+    MTLRenderCommandEncoder, MTLRenderPipelineState, MTLSize, MTLStorageMode, MTLTexture,
+    MTLTextureDescriptor, MTLTextureUsage,
+};
+
+use crate::{texture::MAX_TEXTURE_SIZE, LogLevel, MetalRenderer, TextureError};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the faces of the cubemaps stop at this size, the details of larger images would be lost in
+// the view anyway
+const MAX_FACE_SIZE: usize = 2048;
+
+// the rays of `fragment_skybox` through the center of the view and how they change across it
+// in normalized device coordinates, `SkyboxProperties` in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct SkyboxProperties {
+    direction: [f32; 4],
+    direction_x: [f32; 4],
+    direction_y: [f32; 4],
+}
+
+#[derive(Default)]
+pub(crate) struct SkyboxState {
+    environment_map: Option<Texture>,
+    depth_stencil_state: Option<Retained<ProtocolObject<dyn MTLDepthStencilState>>>,
+}
+
+// the skybox of a frame. with a depth buffer it's drawn after the geometry where the depth
+// buffer was left at the far plane, without one it's drawn first like the background
+pub(crate) struct Skybox {
+    pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    environment_map: Texture,
+    depth_stencil_state: Option<Retained<ProtocolObject<dyn MTLDepthStencilState>>>,
+    properties: SkyboxProperties,
+}
+
+impl Skybox {
+    // whether the geometry is drawn first, the skybox then only fills what it left uncovered
+    pub(crate) fn is_depth_tested(&self) -> bool {
+        self.depth_stencil_state.is_some()
+    }
+
+    pub(crate) fn encode(&self, encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>) {
+        if let Some(depth_stencil_state) = &self.depth_stencil_state {
+            encoder.setDepthStencilState(Some(depth_stencil_state));
+        }
+        encoder.setRenderPipelineState(&self.pipeline_state);
+        unsafe {
+            encoder.setFragmentTexture_atIndex(Some(&self.environment_map), 0);
+            encoder.setFragmentBytes_length_atIndex(
+                NonNull::from(&self.properties).cast::<c_void>(),
+                core::mem::size_of_val(&self.properties),
+                0,
+            );
+            // one triangle covering the viewport at the far plane
+            encoder.drawPrimitives_vertexStart_vertexCount(MTLPrimitiveType::Triangle, 0, 3);
+        }
+    }
+}
+
+impl MetalRenderer {
+    // decodes an equirectangular image, like the .hdr files of sky panoramas, and converts it
+    // with `create_environment_map`
+    pub fn load_environment_map(&self, path: impl AsRef<Path>) -> Result<Texture, TextureError> {
+        let image = image::open(path)?.into_rgba32f();
+        self.create_environment_map(&image)
+    }
+
+    // projects an equirectangular image onto the faces of a half float cubemap in a compute
+    // pass, a quarter of the image wide each. like the mipmaps of `create_texture`, the pass
+    // runs ahead of the frames committed after this call
+    pub fn create_environment_map(&self, image: &Rgba32FImage) -> Result<Texture, TextureError> {
+        let (width, height) = image.dimensions();
+        if !(1..=MAX_TEXTURE_SIZE).contains(&width) || !(1..=MAX_TEXTURE_SIZE).contains(&height) {
+            return Err(TextureError::InvalidSize { width, height });
+        }
+        let device = self.device();
+
+        let descriptor = unsafe {
+            MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                MTLPixelFormat::RGBA32Float,
+                width as usize,
+                height as usize,
+                false,
+            )
+        };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead);
+        let equirectangular = device
+            .newTextureWithDescriptor(&descriptor)
+            .expect("Failed to create an equirectangular texture.");
+        let region = MTLRegion {
+            origin: MTLOrigin { x: 0, y: 0, z: 0 },
+            size: MTLSize {
+                width: width as usize,
+                height: height as usize,
+                depth: 1,
+            },
+        };
+        unsafe {
+            equirectangular.replaceRegion_mipmapLevel_withBytes_bytesPerRow(
+                region,
+                0,
+                NonNull::from(image.as_raw().as_slice()).cast::<c_void>(),
+                width as usize * core::mem::size_of::<[f32; 4]>(),
+            )
+        };
+
+        let face_size = (width as usize / 4).clamp(1, MAX_FACE_SIZE);
+        let descriptor = unsafe {
+            MTLTextureDescriptor::textureCubeDescriptorWithPixelFormat_size_mipmapped(
+                MTLPixelFormat::RGBA16Float,
+                face_size,
+                false,
+            )
+        };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead | MTLTextureUsage::ShaderWrite);
+        descriptor.setStorageMode(MTLStorageMode::Private);
+        let cubemap = device
+            .newTextureWithDescriptor(&descriptor)
+            .expect("Failed to create an environment map.");
+
+        let pipeline_state = self.compute_pipeline_state("equirectangular_to_cubemap");
+        let command_queue = &self.command_queue();
+        let command_buffer = command_queue
+            .commandBuffer()
+            .expect("Failed to create a command buffer.");
+        let encoder = command_buffer
+            .computeCommandEncoder()
+            .expect("Failed to create a compute encoder.");
+        encoder.setComputePipelineState(&pipeline_state);
+        unsafe {
+            encoder.setTexture_atIndex(Some(&equirectangular), 0);
+            encoder.setTexture_atIndex(Some(&cubemap), 1);
+        }
+        // a thread per texel of the six faces
+        let threads_width = pipeline_state.threadExecutionWidth();
+        let threads_per_threadgroup = MTLSize {
+            width: threads_width,
+            height: pipeline_state.maxTotalThreadsPerThreadgroup() / threads_width,
+            depth: 1,
+        };
+        let threads = MTLSize {
+            width: face_size,
+            height: face_size,
+            depth: 6,
+        };
+        encoder.dispatchThreads_threadsPerThreadgroup(threads, threads_per_threadgroup);
+        encoder.endEncoding();
+        // the equirectangular texture is retained by the command buffer until it completed
+        command_buffer.commit();
+
+        let message = format!("Created an environment map with {face_size}x{face_size} faces.");
+        self.log(LogLevel::Debug, &message);
+        Ok(cubemap)
+    }
+
+    // draws `environment_map` around the scene of the frames of the render callback, none
+    // leaves the clear color or the background there. the map is a cubemap, from
+    // `create_environment_map` or any other. orthographic views look along a single direction
+    // of it
+    pub fn set_skybox(&self, environment_map: Option<&Texture>) {
+        self.ivars().skybox.borrow_mut().environment_map = environment_map.cloned();
+    }
+
+    pub fn skybox(&self) -> Option<Texture> {
+        self.ivars().skybox.borrow().environment_map.clone()
+    }
+
+    // the skybox of a frame seen by the camera in a viewport of `aspect`, none without one
+    pub(crate) fn prepare_skybox(&self, aspect: f32) -> Option<Skybox> {
+        let environment_map = self.skybox()?;
+        let camera = self.ivars().camera.get();
+        let (_, direction) = camera.ray((0., 0.), aspect);
+        let (_, direction_x) = camera.ray((1., 0.), aspect);
+        let (_, direction_y) = camera.ray((0., 1.), aspect);
+        let change = |to: [f32; 3]| {
+            let [x, y, z] = [0, 1, 2].map(|i| to[i] - direction[i]);
+            [x, y, z, 0.]
+        };
+        let properties = SkyboxProperties {
+            direction: [direction[0], direction[1], direction[2], 0.],
+            direction_x: change(direction_x),
+            direction_y: change(direction_y),
+        };
+
+        // passes where nothing nearer than the far plane was drawn, and keeps the depth there
+        let depth_stencil_state = self.depth_format().map(|_| {
+            self.ivars()
+                .skybox
+                .borrow_mut()
+                .depth_stencil_state
+                .get_or_insert_with(|| {
+                    let descriptor = unsafe { MTLDepthStencilDescriptor::new() };
+                    descriptor.setDepthCompareFunction(MTLCompareFunction::LessEqual);
+                    descriptor.setDepthWriteEnabled(false);
+                    self.device()
+                        .newDepthStencilStateWithDescriptor(&descriptor)
+                        .expect("Failed to create the skybox depth stencil state.")
+                })
+                .clone()
+        });
+        Some(Skybox {
+            pipeline_state: self.render_pipeline_state("vertex_skybox", "fragment_skybox"),
+            environment_map,
+            depth_stencil_state,
+            properties,
+        })
+    }
+}
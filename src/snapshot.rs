@@ -0,0 +1,306 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use image::{ImageError, Rgba, RgbaImage};
+
+use crate::{LogLevel, MetalRenderer, ScreenshotError};
+
+// the largest difference `color_delta` reports, between black and white
+const MAX_DELTA: f32 = 35215.;
+
+// how far a frame may stray from its reference. gpus and drivers round differently, so an
+// exact match is rarely what a snapshot should ask for
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SnapshotTolerance {
+    // the perceived color difference below which a pixel counts as matching, from 0 for an
+    // exact match to 1 for any color
+    pub pixel_threshold: f32,
+    // the share of the pixels that may differ by more than the threshold
+    pub max_mismatched: f32,
+}
+
+impl Default for SnapshotTolerance {
+    fn default() -> Self {
+        Self {
+            pixel_threshold: 0.1,
+            max_mismatched: 0.001,
+        }
+    }
+}
+
+// how a frame compares to its reference
+#[derive(Clone, Debug)]
+pub struct SnapshotDiff {
+    pub mismatched_pixels: usize,
+    pub total_pixels: usize,
+    // the largest perceived difference of a pixel, from 0 to 1
+    pub max_difference: f32,
+    // the reference faded to gray with the mismatched pixels in red
+    pub diff_image: RgbaImage,
+}
+
+impl SnapshotDiff {
+    pub fn mismatched_share(&self) -> f32 {
+        self.mismatched_pixels as f32 / self.total_pixels.max(1) as f32
+    }
+
+    pub fn passes(&self, tolerance: &SnapshotTolerance) -> bool {
+        self.mismatched_share() <= tolerance.max_mismatched
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} pixels differ ({:.2}%), by up to {:.3}",
+            self.mismatched_pixels,
+            self.total_pixels,
+            self.mismatched_share() * 1e2,
+            self.max_difference
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    // the frame couldn't be rendered or read back
+    Render(ScreenshotError),
+    // there was no reference yet, the frame was saved as the reference at the path
+    MissingReference(PathBuf),
+    // the frame and the reference differ in size, as width and height
+    SizeMismatch {
+        actual: (u32, u32),
+        reference: (u32, u32),
+    },
+    // the frame differs from the reference by more than the tolerance. the frame and the diff
+    // image were saved next to the reference
+    Mismatch(SnapshotDiff),
+    // the reference couldn't be loaded or the images couldn't be written
+    Image(ImageError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Render(error) => write!(f, "{error}"),
+            SnapshotError::MissingReference(path) => {
+                write!(
+                    f,
+                    "No reference snapshot, saved the frame as {}",
+                    path.display()
+                )
+            }
+            SnapshotError::SizeMismatch { actual, reference } => write!(
+                f,
+                "The frame is {}x{} while the reference is {}x{}",
+                actual.0, actual.1, reference.0, reference.1
+            ),
+            SnapshotError::Mismatch(diff) => {
+                write!(f, "The frame differs from the reference: {diff}")
+            }
+            SnapshotError::Image(error) => write!(f, "Failed to load or save a snapshot: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<ScreenshotError> for SnapshotError {
+    fn from(error: ScreenshotError) -> Self {
+        SnapshotError::Render(error)
+    }
+}
+
+impl From<ImageError> for SnapshotError {
+    fn from(error: ImageError) -> Self {
+        SnapshotError::Image(error)
+    }
+}
+
+fn luma(pixel: [f32; 3]) -> f32 {
+    pixel[0] * 0.2989 + pixel[1] * 0.5866 + pixel[2] * 0.1145
+}
+
+// the colors blended over white, so transparent pixels compare by what they'd show
+fn blend_over_white(pixel: Rgba<u8>) -> [f32; 3] {
+    let alpha = pixel[3] as f32 / 255.;
+    [0, 1, 2].map(|channel| 255. + (pixel[channel] as f32 - 255.) * alpha)
+}
+
+// the perceived difference of two colors, the distance in the YIQ color space weighted like
+// the eye weighs brightness over hue
+fn color_delta(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    if a == b {
+        return 0.;
+    }
+    let (a, b) = (blend_over_white(a), blend_over_white(b));
+    let in_phase = |pixel: [f32; 3]| pixel[0] * 0.5960 - pixel[1] * 0.2742 - pixel[2] * 0.3218;
+    let quadrature = |pixel: [f32; 3]| pixel[0] * 0.2115 - pixel[1] * 0.5226 + pixel[2] * 0.3111;
+    let y = luma(a) - luma(b);
+    let i = in_phase(a) - in_phase(b);
+    let q = quadrature(a) - quadrature(b);
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+// compares `actual` to `reference` pixel by pixel, counting the pixels that look different
+// beyond the threshold of `tolerance`
+pub fn compare_images(
+    actual: &RgbaImage,
+    reference: &RgbaImage,
+    tolerance: &SnapshotTolerance,
+) -> Result<SnapshotDiff, SnapshotError> {
+    if actual.dimensions() != reference.dimensions() {
+        return Err(SnapshotError::SizeMismatch {
+            actual: actual.dimensions(),
+            reference: reference.dimensions(),
+        });
+    }
+    let threshold = tolerance.pixel_threshold.clamp(0., 1.);
+    let mut diff_image = RgbaImage::new(reference.width(), reference.height());
+    let mut mismatched_pixels = 0;
+    let mut max_difference = 0f32;
+    for ((actual, reference), diff) in actual
+        .pixels()
+        .zip(reference.pixels())
+        .zip(diff_image.pixels_mut())
+    {
+        let difference = (color_delta(*actual, *reference) / MAX_DELTA).sqrt();
+        max_difference = max_difference.max(difference);
+        *diff = if difference > threshold {
+            mismatched_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let gray = 255. - (255. - luma(blend_over_white(*reference))) * 0.1;
+            let gray = gray.round() as u8;
+            Rgba([gray, gray, gray, 255])
+        };
+    }
+    Ok(SnapshotDiff {
+        mismatched_pixels,
+        total_pixels: (reference.width() * reference.height()) as usize,
+        max_difference,
+        diff_image,
+    })
+}
+
+// `reference` with `suffix` appended to its file stem, e.g. triangle.diff.png
+fn sibling_path(reference: &Path, suffix: &str) -> PathBuf {
+    let stem = reference.file_stem().unwrap_or_default().to_string_lossy();
+    reference.with_file_name(format!("{stem}.{suffix}.png"))
+}
+
+impl MetalRenderer {
+    // renders a frame of a headless renderer and compares it to the png at `reference`. a
+    // missing reference is created from the frame, and so is every reference while the
+    // UPDATE_SNAPSHOTS environment variable is set. when the frame is off, it's saved next to
+    // the reference along with an image marking the pixels that differ. the frames depend on
+    // the scene time, which `set_fixed_time` freezes
+    pub fn check_snapshot(
+        &self,
+        reference: impl AsRef<Path>,
+        tolerance: &SnapshotTolerance,
+    ) -> Result<SnapshotDiff, SnapshotError> {
+        let reference = reference.as_ref();
+        let actual = self.render_offscreen()?;
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+        if update || !reference.exists() {
+            if let Some(directory) = reference.parent() {
+                std::fs::create_dir_all(directory).map_err(ImageError::IoError)?;
+            }
+            actual.save(reference)?;
+            if !update {
+                return Err(SnapshotError::MissingReference(reference.to_owned()));
+            }
+            let message = format!("Updated the snapshot {}.", reference.display());
+            self.log(LogLevel::Info, &message);
+        }
+        let reference_image = image::open(reference)?.into_rgba8();
+        let diff = compare_images(&actual, &reference_image, tolerance)?;
+        if !diff.passes(tolerance) {
+            actual.save(sibling_path(reference, "actual"))?;
+            diff.diff_image.save(sibling_path(reference, "diff"))?;
+            return Err(SnapshotError::Mismatch(diff));
+        }
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba(color))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = filled(8, 8, [40, 120, 200, 255]);
+        let diff = compare_images(&image, &image, &SnapshotTolerance::default()).unwrap();
+        assert_eq!(diff.mismatched_pixels, 0);
+        assert_eq!(diff.total_pixels, 64);
+        assert_eq!(diff.max_difference, 0.);
+        assert!(diff.passes(&SnapshotTolerance::default()));
+    }
+
+    #[test]
+    fn differing_pixel_is_marked() {
+        let reference = filled(10, 10, [0, 0, 0, 255]);
+        let mut actual = reference.clone();
+        actual.put_pixel(3, 4, Rgba([255, 255, 255, 255]));
+        let tolerance = SnapshotTolerance::default();
+        let diff = compare_images(&actual, &reference, &tolerance).unwrap();
+        assert_eq!(diff.mismatched_pixels, 1);
+        assert!(diff.max_difference > 0.9);
+        assert_eq!(*diff.diff_image.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert_ne!(*diff.diff_image.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        // 1% of the pixels is more than the default allows, but not more than 2%
+        assert!(!diff.passes(&tolerance));
+        assert!(diff.passes(&SnapshotTolerance {
+            max_mismatched: 0.02,
+            ..tolerance
+        }));
+    }
+
+    #[test]
+    fn small_differences_are_within_the_threshold() {
+        let reference = filled(4, 4, [128, 128, 128, 255]);
+        let actual = filled(4, 4, [130, 129, 127, 255]);
+        let diff = compare_images(&actual, &reference, &SnapshotTolerance::default()).unwrap();
+        assert_eq!(diff.mismatched_pixels, 0);
+        assert!(diff.max_difference > 0.);
+        let exact = SnapshotTolerance {
+            pixel_threshold: 0.,
+            max_mismatched: 0.,
+        };
+        let diff = compare_images(&actual, &reference, &exact).unwrap();
+        assert_eq!(diff.mismatched_pixels, 16);
+    }
+
+    #[test]
+    fn transparent_pixels_compare_over_white() {
+        let reference = filled(2, 2, [255, 255, 255, 255]);
+        let actual = filled(2, 2, [0, 0, 0, 0]);
+        let diff = compare_images(&actual, &reference, &SnapshotTolerance::default()).unwrap();
+        assert_eq!(diff.max_difference, 0.);
+    }
+
+    #[test]
+    fn different_sizes_are_an_error() {
+        let result = compare_images(
+            &filled(4, 4, [0; 4]),
+            &filled(4, 2, [0; 4]),
+            &SnapshotTolerance::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(SnapshotError::SizeMismatch {
+                actual: (4, 4),
+                reference: (4, 2),
+            })
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+// textures too large to keep in memory at full resolution, backed by a sparse heap their tiles
+// are mapped into as they're needed. the application asks for the tiles it wants, one by one,
+// by region or by the distance of the camera, and `update_residency` maps them, fills them
+// through the loader of the texture and unmaps the ones no longer wanted
+use std::{cmp::Reverse, collections::HashSet};
+
+use core::ptr::NonNull;
+
+use objc2::{msg_send, rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLCommandBuffer, MTLCommandEncoder, MTLCommandQueue, MTLDevice, MTLGPUFamily,
+    MTLHazardTrackingMode, MTLHeap, MTLHeapDescriptor, MTLHeapType, MTLOrigin, MTLPixelFormat,
+    MTLRegion, MTLResourceOptions, MTLResourceStateCommandEncoder, MTLSize,
+    MTLSparseTextureMappingMode, MTLStorageMode, MTLTexture, MTLTextureDescriptor, MTLTextureType,
+    MTLTextureUsage,
+};
+
+use crate::{allocator::align_up, LogLevel, MetalRenderer};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the texels of the region of a mipmap level, rows of tightly packed texels of an uncompressed
+// format from the top left
+pub type TileLoader = Box<dyn Fn(usize, MTLRegion) -> Vec<u8>>;
+
+// a tile of a mipmap level, counted in tiles from the top left
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SparseTile {
+    pub level: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+// a texture whose tiles are only in memory while they're resident. the smallest levels share
+// the tail of the texture, which stays resident and is filled with the first update. sampling
+// a tile that isn't resident returns zeros
+pub struct SparseTexture {
+    texture: Texture,
+    heap: Retained<ProtocolObject<dyn MTLHeap>>,
+    // the size of a tile in texels and in bytes
+    tile_size: (usize, usize),
+    tile_bytes: usize,
+    loader: TileLoader,
+    resident: HashSet<SparseTile>,
+    wanted: HashSet<SparseTile>,
+    tail_filled: bool,
+}
+
+impl SparseTexture {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn tile_size(&self) -> (usize, usize) {
+        self.tile_size
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.texture.mipmapLevelCount()
+    }
+
+    // the first level of the tail, the ones from it on aren't made of tiles
+    pub fn tail_level(&self) -> usize {
+        self.texture.firstMipmapInTail().min(self.level_count())
+    }
+
+    // the number of tiles across and down `level`, none for the levels of the tail
+    pub fn tile_count(&self, level: usize) -> (usize, usize) {
+        if level >= self.tail_level() {
+            return (0, 0);
+        }
+        let (width, height) = self.level_size(level);
+        (
+            width.div_ceil(self.tile_size.0),
+            height.div_ceil(self.tile_size.1),
+        )
+    }
+
+    pub fn is_resident(&self, tile: SparseTile) -> bool {
+        self.resident.contains(&tile)
+    }
+
+    pub fn resident_tile_count(&self) -> usize {
+        self.resident.len()
+    }
+
+    // the most detailed level whose tiles are all resident, the tail when there's none. a
+    // sampler clamped to it never reads a tile that isn't
+    pub fn finest_resident_level(&self) -> usize {
+        (0..self.tail_level())
+            .find(|&level| self.tiles_of(level).all(|tile| self.is_resident(tile)))
+            .unwrap_or(self.tail_level())
+    }
+
+    // makes `tile` resident with the next update
+    pub fn request_tile(&mut self, tile: SparseTile) {
+        if tile.level < self.tail_level() {
+            self.wanted.insert(tile);
+        }
+    }
+
+    // unmaps `tile` with the next update
+    pub fn evict_tile(&mut self, tile: SparseTile) {
+        self.wanted.remove(&tile);
+    }
+
+    // makes the tiles covering the `width` by `height` texels at `origin` of `level` resident
+    pub fn request_region(
+        &mut self,
+        level: usize,
+        origin: (usize, usize),
+        (width, height): (usize, usize),
+    ) {
+        let (tile_width, tile_height) = self.tile_size;
+        let (columns, rows) = self.tile_count(level);
+        let end_x = (origin.0 + width).div_ceil(tile_width).min(columns);
+        let end_y = (origin.1 + height).div_ceil(tile_height).min(rows);
+        for y in origin.1 / tile_height..end_y {
+            for x in origin.0 / tile_width..end_x {
+                self.wanted.insert(SparseTile { level, x, y });
+            }
+        }
+    }
+
+    // wants the levels detailed enough for a texture seen from `distance`, the full
+    // resolution up to `full_detail_distance` and one level less for every doubling of the
+    // distance past it. the tiles of the more detailed levels are evicted
+    pub fn request_for_distance(&mut self, distance: f32, full_detail_distance: f32) {
+        let ratio = distance / full_detail_distance.max(f32::EPSILON);
+        let level = (ratio.max(1.).log2().floor() as usize).min(self.tail_level());
+        self.wanted = (level..self.tail_level())
+            .flat_map(|level| self.tiles_of(level))
+            .collect();
+    }
+
+    fn level_size(&self, level: usize) -> (usize, usize) {
+        (
+            (self.texture.width() >> level).max(1),
+            (self.texture.height() >> level).max(1),
+        )
+    }
+
+    fn tiles_of(&self, level: usize) -> impl Iterator<Item = SparseTile> {
+        let (columns, rows) = self.tile_count(level);
+        (0..rows).flat_map(move |y| (0..columns).map(move |x| SparseTile { level, x, y }))
+    }
+
+    // the texels of `level` covered by `tile`, the tiles at the right and bottom edges are cut
+    // off by the size of the level
+    fn tile_region(&self, tile: SparseTile) -> MTLRegion {
+        let (width, height) = self.level_size(tile.level);
+        let (tile_width, tile_height) = self.tile_size;
+        let origin = MTLOrigin {
+            x: tile.x * tile_width,
+            y: tile.y * tile_height,
+            z: 0,
+        };
+        MTLRegion {
+            origin,
+            size: MTLSize {
+                width: tile_width.min(width - origin.x),
+                height: tile_height.min(height - origin.y),
+                depth: 1,
+            },
+        }
+    }
+
+    // the tiles the heap has room for besides the tail
+    fn capacity(&self) -> usize {
+        self.heap
+            .size()
+            .saturating_sub(self.texture.tailSizeInBytes())
+            / self.tile_bytes
+    }
+}
+
+impl MetalRenderer {
+    // whether the gpu maps the tiles of textures on its own, the apple gpus from the A13 on do
+    pub fn supports_sparse_textures(&self) -> bool {
+        self.device().supportsFamily(MTLGPUFamily::Apple6)
+    }
+
+    // a `width` by `height` texture with a full mip chain whose tiles take at most
+    // `memory_budget` bytes, none when the gpu doesn't support sparse textures. `loader` gives
+    // the texels of the tiles as they're made resident
+    pub fn create_sparse_texture(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_format: MTLPixelFormat,
+        memory_budget: usize,
+        loader: impl Fn(usize, MTLRegion) -> Vec<u8> + 'static,
+    ) -> Option<SparseTexture> {
+        if !self.supports_sparse_textures() {
+            return None;
+        }
+        let device = self.device();
+        let descriptor = unsafe {
+            MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                pixel_format,
+                width,
+                height,
+                true,
+            )
+        };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead);
+        descriptor.setStorageMode(MTLStorageMode::Private);
+
+        let tile_bytes = unsafe { device.sparseTileSizeInBytes() };
+        let tile_size = unsafe {
+            device.sparseTileSizeWithTextureType_pixelFormat_sampleCount(
+                MTLTextureType::MTLTextureType2D,
+                pixel_format,
+                1,
+            )
+        };
+        let heap_descriptor = unsafe { MTLHeapDescriptor::new() };
+        heap_descriptor.setType(MTLHeapType::Sparse);
+        heap_descriptor.setStorageMode(MTLStorageMode::Private);
+        // the mappings and the uploads are ordered with the frames sampling the texture
+        heap_descriptor.setHazardTrackingMode(MTLHazardTrackingMode::Tracked);
+        heap_descriptor.setSize(align_up(memory_budget.max(tile_bytes), tile_bytes));
+        let Some(heap) = device.newHeapWithDescriptor(&heap_descriptor) else {
+            self.log(LogLevel::Warn, "Failed to create a sparse heap.");
+            return None;
+        };
+        let Some(texture) = heap.newTextureWithDescriptor(&descriptor) else {
+            self.log(LogLevel::Warn, "Failed to create a sparse texture.");
+            return None;
+        };
+        let message = format!(
+            "Created a {width}x{height} sparse texture with {}x{} tiles.",
+            tile_size.width, tile_size.height
+        );
+        self.log(LogLevel::Debug, &message);
+        Some(SparseTexture {
+            texture,
+            heap,
+            tile_size: (tile_size.width, tile_size.height),
+            tile_bytes,
+            loader: Box::new(loader),
+            resident: HashSet::new(),
+            wanted: HashSet::new(),
+            tail_filled: false,
+        })
+    }
+
+    // maps the tiles of `texture` that were asked for and fills them, and unmaps the ones
+    // that were evicted, ahead of the frames committed after this call. when the heap is
+    // full, the least detailed tiles are mapped first and the rest wait for room
+    pub fn update_residency(&self, texture: &mut SparseTexture) {
+        let evicted: Vec<_> = texture
+            .resident
+            .difference(&texture.wanted)
+            .copied()
+            .collect();
+        let mut mapped: Vec<_> = texture
+            .wanted
+            .difference(&texture.resident)
+            .copied()
+            .collect();
+        let room = texture
+            .capacity()
+            .saturating_sub(texture.resident.len() - evicted.len());
+        if mapped.len() > room {
+            mapped.sort_by_key(|tile| (Reverse(tile.level), tile.y, tile.x));
+            let deferred = mapped.len() - room;
+            mapped.truncate(room);
+            let message =
+                format!("Deferred {deferred} tiles of a sparse texture for lack of room.");
+            self.log(LogLevel::Debug, &message);
+        }
+        if evicted.is_empty() && mapped.is_empty() && texture.tail_filled {
+            return;
+        }
+
+        let command_buffer = self
+            .command_queue()
+            .commandBuffer()
+            .expect("Failed to create a command buffer.");
+        let encoder = unsafe { command_buffer.resourceStateCommandEncoder() }
+            .expect("Failed to create a resource state encoder.");
+        let tile_region = |tile: &SparseTile| MTLRegion {
+            origin: MTLOrigin {
+                x: tile.x,
+                y: tile.y,
+                z: 0,
+            },
+            size: MTLSize {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+        };
+        let update_mapping = |mode, tile: &SparseTile| unsafe {
+            encoder.updateTextureMapping_mode_region_mipLevel_slice(
+                &texture.texture,
+                mode,
+                tile_region(tile),
+                tile.level,
+                0,
+            )
+        };
+        for tile in &evicted {
+            update_mapping(MTLSparseTextureMappingMode::Unmap, tile);
+        }
+        for tile in &mapped {
+            update_mapping(MTLSparseTextureMappingMode::Map, tile);
+        }
+        // the levels of the tail are mapped all at once
+        let tail_level = texture.tail_level();
+        if !texture.tail_filled && tail_level < texture.level_count() {
+            let tail = SparseTile {
+                level: tail_level,
+                x: 0,
+                y: 0,
+            };
+            update_mapping(MTLSparseTextureMappingMode::Map, &tail);
+        }
+        encoder.endEncoding();
+
+        // the tiles, then every level of the tail
+        let mut uploads: Vec<_> = mapped
+            .iter()
+            .map(|&tile| (tile.level, texture.tile_region(tile)))
+            .collect();
+        if !texture.tail_filled {
+            uploads.extend((tail_level..texture.level_count()).map(|level| {
+                let (width, height) = texture.level_size(level);
+                let size = MTLSize {
+                    width,
+                    height,
+                    depth: 1,
+                };
+                let origin = MTLOrigin { x: 0, y: 0, z: 0 };
+                (level, MTLRegion { origin, size })
+            }));
+        }
+        let blit_encoder = command_buffer
+            .blitCommandEncoder()
+            .expect("Failed to create a blit encoder.");
+        for (level, region) in uploads {
+            let texels = (texture.loader)(level, region);
+            if texels.is_empty() || !texels.len().is_multiple_of(region.size.height) {
+                let message =
+                    format!("The loader returned no whole rows of texels for level {level}.");
+                self.log(LogLevel::Warn, &message);
+                continue;
+            }
+            let staging = unsafe {
+                self.device().newBufferWithBytes_length_options(
+                    NonNull::from(texels.as_slice()).cast(),
+                    texels.len(),
+                    MTLResourceOptions::MTLResourceStorageModeShared,
+                )
+            }
+            .expect("Failed to create a staging buffer for a tile.");
+            // the selector doesn't fit on a line, it's sent with its parts on their own lines
+            let () = unsafe {
+                msg_send![
+                    &blit_encoder,
+                    copyFromBuffer: &*staging,
+                    sourceOffset: 0usize,
+                    sourceBytesPerRow: texels.len() / region.size.height,
+                    sourceBytesPerImage: texels.len(),
+                    sourceSize: region.size,
+                    toTexture: &*texture.texture,
+                    destinationSlice: 0usize,
+                    destinationLevel: level,
+                    destinationOrigin: region.origin
+                ]
+            };
+        }
+        blit_encoder.endEncoding();
+        command_buffer.commit();
+
+        for tile in &evicted {
+            texture.resident.remove(tile);
+        }
+        texture.resident.extend(&mapped);
+        texture.tail_filled = true;
+        let message = format!(
+            "Mapped {} and unmapped {} tiles of a sparse texture.",
+            mapped.len(),
+            evicted.len()
+        );
+        self.log(LogLevel::Debug, &message);
+    }
+}
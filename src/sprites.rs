@@ -0,0 +1,191 @@
+use std::rc::Rc;
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLDevice, MTLPackedFloat3, MTLSamplerAddressMode, MTLSamplerDescriptor,
+    MTLSamplerMinMagFilter, MTLSamplerMipFilter, MTLTexture,
+};
+
+use crate::{
+    camera::{Matrix, IDENTITY},
+    ArgumentTable, MetalRenderer, PrimitiveType, RenderPass, VertexInput,
+};
+
+// the corners of the unit quad of a sprite with their texture coordinates in its uv rect, top
+// left first
+const CORNERS: [([f32; 2], [f32; 2]); 4] = [
+    ([-0.5, 0.5], [0., 0.]),
+    ([0.5, 0.5], [1., 0.]),
+    ([-0.5, -0.5], [0., 1.]),
+    ([0.5, -0.5], [1., 1.]),
+];
+
+// the two counterclockwise triangles of a sprite
+const QUAD_INDICES: [u32; 6] = [2, 3, 1, 2, 1, 0];
+
+// a textured quad of a `SpriteBatch`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sprite {
+    // column major, places the unit quad spanning -0.5 to 0.5 on x and y in the world
+    pub transform: Matrix,
+    // the part of the texture shown, in texture coordinates with the first row at the top
+    pub uv_origin: [f32; 2],
+    pub uv_size: [f32; 2],
+    // multiplied with the texture
+    pub tint: [f32; 3],
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Sprite {
+            transform: IDENTITY,
+            uv_origin: [0., 0.],
+            uv_size: [1., 1.],
+            tint: [1., 1., 1.],
+        }
+    }
+}
+
+impl Sprite {
+    // a sprite of `size` centered on `position` in the z = 0 plane, turned counterclockwise by
+    // `rotation` radians
+    pub fn new(position: [f32; 2], size: [f32; 2], rotation: f32) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+        Sprite {
+            transform: [
+                [cos * size[0], sin * size[0], 0., 0.],
+                [-sin * size[1], cos * size[1], 0., 0.],
+                [0., 0., 1., 0.],
+                [position[0], position[1], 0., 1.],
+            ],
+            ..Default::default()
+        }
+    }
+
+    // shows `size` of the texture from `origin`, a frame of a sprite sheet or an atlas
+    pub fn with_uv_rect(mut self, origin: [f32; 2], size: [f32; 2]) -> Self {
+        self.uv_origin = origin;
+        self.uv_size = size;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: [f32; 3]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    fn transform_point(&self, [x, y]: [f32; 2]) -> MTLPackedFloat3 {
+        let m = &self.transform;
+        let row = |i: usize| m[0][i] * x + m[1][i] * y + m[3][i];
+        let w = row(3);
+        MTLPackedFloat3 {
+            x: row(0) / w,
+            y: row(1) / w,
+            z: row(2) / w,
+        }
+    }
+}
+
+// accumulates sprites over a frame and records them with one draw per texture. texels with an
+// alpha below one half are discarded rather than blended, so the sprites don't need to be
+// drawn back to front and sorting them by texture keeps them correct with depth testing
+#[derive(Default)]
+pub struct SpriteBatch {
+    sprites: Vec<(Rc<ArgumentTable>, Sprite)>,
+}
+
+impl SpriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds a sprite showing the texture of `texture_arguments`, made by
+    // `create_sprite_arguments`
+    pub fn push(&mut self, texture_arguments: &Rc<ArgumentTable>, sprite: Sprite) {
+        self.sprites.push((texture_arguments.clone(), sprite));
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    // records the sprites into `render_pass` and empties the batch, sprites sharing a texture
+    // keep the order they were pushed in
+    pub fn flush(&mut self, renderer: &MetalRenderer, render_pass: &mut RenderPass) {
+        if self.sprites.is_empty() {
+            return;
+        }
+        self.sprites
+            .sort_by_key(|(arguments, _)| Rc::as_ptr(arguments));
+        let pipeline_state =
+            renderer.render_pipeline_state("vertex_textured_mesh", "fragment_sprite");
+        for group in self.sprites.chunk_by(|(a, _), (b, _)| Rc::ptr_eq(a, b)) {
+            let mut vertices = Vec::with_capacity(group.len() * CORNERS.len());
+            let mut texture_coordinates = Vec::with_capacity(group.len() * CORNERS.len());
+            let mut indices = Vec::with_capacity(group.len() * QUAD_INDICES.len());
+            for (_, sprite) in group {
+                let first = vertices.len() as u32;
+                for (corner, [u, v]) in CORNERS {
+                    vertices.push(VertexInput {
+                        position: sprite.transform_point(corner),
+                        color: MTLPackedFloat3 {
+                            x: sprite.tint[0],
+                            y: sprite.tint[1],
+                            z: sprite.tint[2],
+                        },
+                    });
+                    texture_coordinates.push([
+                        sprite.uv_origin[0] + u * sprite.uv_size[0],
+                        sprite.uv_origin[1] + v * sprite.uv_size[1],
+                    ]);
+                }
+                indices.extend(QUAD_INDICES.map(|index| first + index));
+            }
+            render_pass
+                .draw_indexed(
+                    &pipeline_state,
+                    &renderer.frame_buffer(&vertices),
+                    &renderer.frame_buffer(&indices),
+                    PrimitiveType::Triangle,
+                    0..indices.len(),
+                )
+                .with_texture_coordinates(&renderer.frame_buffer(&texture_coordinates))
+                .with_fragment_arguments(&group[0].0);
+        }
+        self.sprites.clear();
+    }
+}
+
+impl MetalRenderer {
+    // the argument buffer of `fragment_sprite` showing `texture`, with a trilinear sampler
+    // clamping to its edges
+    pub fn create_sprite_arguments(
+        &self,
+        texture: &Retained<ProtocolObject<dyn MTLTexture>>,
+    ) -> Rc<ArgumentTable> {
+        let sampler_descriptor = MTLSamplerDescriptor::new();
+        sampler_descriptor.setMinFilter(MTLSamplerMinMagFilter::Linear);
+        sampler_descriptor.setMagFilter(MTLSamplerMinMagFilter::Linear);
+        sampler_descriptor.setMipFilter(MTLSamplerMipFilter::Linear);
+        sampler_descriptor.setSAddressMode(MTLSamplerAddressMode::ClampToEdge);
+        sampler_descriptor.setTAddressMode(MTLSamplerAddressMode::ClampToEdge);
+        sampler_descriptor.setSupportArgumentBuffers(true);
+        let sampler = self
+            .device()
+            .newSamplerStateWithDescriptor(&sampler_descriptor)
+            .expect("Failed to create a sampler.");
+
+        let mut arguments = self.create_argument_table("fragment_sprite", 0);
+        arguments.set_texture(0, texture);
+        arguments.set_sampler(1, &sampler);
+        Rc::new(arguments)
+    }
+}
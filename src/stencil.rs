@@ -0,0 +1,172 @@
+// the stencil test of the draws, for masking draws to the pixels others covered like portals
+// and outlines. the stencil lives in the depth buffer of `DepthFormat::Depth32FloatStencil8`,
+// cleared to 0 every frame, a view without it ignores the stencil of the states
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLCompareFunction, MTLDepthStencilDescriptor, MTLDepthStencilState, MTLDevice,
+    MTLStencilDescriptor, MTLStencilOperation,
+};
+
+use crate::{MetalRenderer, RenderPass};
+
+type DepthStencilState = Retained<ProtocolObject<dyn MTLDepthStencilState>>;
+
+// the stencil test of the triangles facing one way. the reference of the draw and the
+// stencil, both masked by `read_mask`, are compared with `compare`, and the stencil is updated
+// with the operation of the outcome of the stencil and the depth tests in the bits of
+// `write_mask`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StencilFace {
+    pub compare: MTLCompareFunction,
+    pub stencil_failure: MTLStencilOperation,
+    pub depth_failure: MTLStencilOperation,
+    pub depth_stencil_pass: MTLStencilOperation,
+    pub read_mask: u32,
+    pub write_mask: u32,
+}
+
+impl Default for StencilFace {
+    // passes everywhere and keeps the stencil
+    fn default() -> Self {
+        StencilFace {
+            compare: MTLCompareFunction::Always,
+            stencil_failure: MTLStencilOperation::Keep,
+            depth_failure: MTLStencilOperation::Keep,
+            depth_stencil_pass: MTLStencilOperation::Keep,
+            read_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+}
+
+impl StencilFace {
+    // writes the reference of the draw wherever its samples pass the depth test, marking them
+    // for the draws after it
+    pub fn replace() -> Self {
+        StencilFace {
+            depth_stencil_pass: MTLStencilOperation::Replace,
+            ..Default::default()
+        }
+    }
+
+    // passes where the stencil holds the reference of the draw, inside what was marked
+    pub fn equal() -> Self {
+        StencilFace {
+            compare: MTLCompareFunction::Equal,
+            ..Default::default()
+        }
+    }
+
+    // passes where the stencil doesn't hold the reference of the draw, outside what was marked
+    pub fn not_equal() -> Self {
+        StencilFace {
+            compare: MTLCompareFunction::NotEqual,
+            ..Default::default()
+        }
+    }
+
+    fn mtl_stencil_descriptor(&self) -> Retained<MTLStencilDescriptor> {
+        let descriptor = unsafe { MTLStencilDescriptor::new() };
+        descriptor.setStencilCompareFunction(self.compare);
+        descriptor.setStencilFailureOperation(self.stencil_failure);
+        descriptor.setDepthFailureOperation(self.depth_failure);
+        descriptor.setDepthStencilPassOperation(self.depth_stencil_pass);
+        descriptor.setReadMask(self.read_mask);
+        descriptor.setWriteMask(self.write_mask);
+        descriptor
+    }
+}
+
+// the depth and the stencil test of draws with `RenderPass::with_depth_stencil`. it starts as
+// the depth test of the view, `LessEqual` and writing the depth, without a stencil test
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DepthStencilBuilder {
+    depth_compare: MTLCompareFunction,
+    depth_write: bool,
+    front_stencil: Option<StencilFace>,
+    back_stencil: Option<StencilFace>,
+}
+
+impl Default for DepthStencilBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DepthStencilBuilder {
+    pub fn new() -> Self {
+        DepthStencilBuilder {
+            depth_compare: MTLCompareFunction::LessEqual,
+            depth_write: true,
+            front_stencil: None,
+            back_stencil: None,
+        }
+    }
+
+    pub fn with_depth_compare(mut self, depth_compare: MTLCompareFunction) -> Self {
+        self.depth_compare = depth_compare;
+        self
+    }
+
+    // whether the samples passing the tests write their depth
+    pub fn with_depth_write(mut self, depth_write: bool) -> Self {
+        self.depth_write = depth_write;
+        self
+    }
+
+    // the stencil test of the triangles facing either way, and of points and lines
+    pub fn with_stencil(self, stencil: StencilFace) -> Self {
+        self.with_front_stencil(stencil).with_back_stencil(stencil)
+    }
+
+    pub fn with_front_stencil(mut self, stencil: StencilFace) -> Self {
+        self.front_stencil = Some(stencil);
+        self
+    }
+
+    pub fn with_back_stencil(mut self, stencil: StencilFace) -> Self {
+        self.back_stencil = Some(stencil);
+        self
+    }
+
+    // the state of the tests, created on first use and cached by the renderer
+    pub fn build(&self, renderer: &MetalRenderer) -> DepthStencilState {
+        let states = &renderer.ivars().depth_stencil_states;
+        if let Some(state) = states.borrow().get(self) {
+            return state.clone();
+        }
+        let descriptor = unsafe { MTLDepthStencilDescriptor::new() };
+        descriptor.setDepthCompareFunction(self.depth_compare);
+        descriptor.setDepthWriteEnabled(self.depth_write);
+        if let Some(front_stencil) = &self.front_stencil {
+            let stencil_descriptor = front_stencil.mtl_stencil_descriptor();
+            descriptor.setFrontFaceStencil(Some(&stencil_descriptor));
+        }
+        if let Some(back_stencil) = &self.back_stencil {
+            let stencil_descriptor = back_stencil.mtl_stencil_descriptor();
+            descriptor.setBackFaceStencil(Some(&stencil_descriptor));
+        }
+        let state = renderer
+            .device()
+            .newDepthStencilStateWithDescriptor(&descriptor)
+            .expect("Failed to create a depth stencil state.");
+        states.borrow_mut().insert(*self, state.clone());
+        state
+    }
+}
+
+impl RenderPass {
+    // tests the last recorded draw with `depth_stencil_state` in place of the depth test of
+    // the pass, comparing the stencil with `reference`. the draws after it go back to the test
+    // of the pass
+    pub fn with_depth_stencil(
+        &mut self,
+        depth_stencil_state: &DepthStencilState,
+        reference: u32,
+    ) -> &mut Self {
+        if let Some(item) = self.items.last_mut() {
+            item.depth_stencil = Some((depth_stencil_state.clone(), reference));
+        }
+        self
+    }
+}
@@ -0,0 +1,745 @@
+use core::cell::{Cell, RefCell};
+
+use objc2::{
+    declare_class, msg_send, msg_send_id, mutability::MainThreadOnly, rc::Retained, rc::Weak,
+    runtime::ProtocolObject, sel, ClassType, DeclaredClass,
+};
+#[cfg(target_os = "macos")]
+use objc2_app_kit::{NSAutoresizingMaskOptions, NSResponder, NSView};
+use objc2_foundation::{
+    MainThreadMarker, NSObject, NSObjectProtocol, NSRect, NSRunLoop, NSRunLoopCommonModes, NSSize,
+};
+use objc2_metal::{
+    MTLClearColor, MTLDevice, MTLPixelFormat, MTLRenderPassDescriptor, MTLTexture, MTLTextureUsage,
+};
+#[cfg(target_os = "macos")]
+use objc2_metal_kit::MTKView;
+#[cfg(target_os = "macos")]
+use objc2_quartz_core::CALayer;
+use objc2_quartz_core::{CADisplayLink, CAFrameRateRange, CAMetalDrawable, CAMetalLayer};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    platform::{backing_size, view_scale_factor, NativeView, NativeWindow, NativeWindowExt},
+    target::{attachment_texture, render_pass_descriptor},
+    CGColorSpace, MetalRenderer,
+};
+
+// how the renderer presents its frames, it can only be picked before `MetalRenderer::init`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Backend {
+    // an MTKView, drawing from its own timer or on demand. metalkit views are appkit views, on
+    // ios the renderer draws into a layer either way
+    #[default]
+    MetalKit,
+    // a CAMetalLayer on the content view of the window, drawn from a display link of the
+    // renderer, for applications that want full control over the frame timing
+    MetalLayer,
+}
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the drawable of a frame, none offscreen, the texture the frame ends up in and the render pass
+// drawing into it
+type Frame = (
+    Option<Retained<ProtocolObject<dyn CAMetalDrawable>>>,
+    Texture,
+    Retained<MTLRenderPassDescriptor>,
+);
+
+// the multisampled color and the depth texture a layer or an offscreen surface renders into,
+// MTKView manages its own
+struct Attachments {
+    size: (usize, usize),
+    sample_count: usize,
+    color_format: MTLPixelFormat,
+    depth_format: MTLPixelFormat,
+    color: Option<Texture>,
+    depth: Option<Texture>,
+    output: Option<Texture>,
+}
+
+declare_class!(
+    // calls back into the renderer on every display refresh. the display link retains its
+    // target, so the target only holds on to the renderer weakly
+    struct DisplayLinkTarget;
+
+    unsafe impl ClassType for DisplayLinkTarget {
+        type Super = NSObject;
+        type Mutability = MainThreadOnly;
+        const NAME: &'static str = "MetalRendererDisplayLinkTarget";
+    }
+
+    impl DeclaredClass for DisplayLinkTarget {
+        type Ivars = Weak<MetalRenderer>;
+    }
+
+    unsafe impl NSObjectProtocol for DisplayLinkTarget {}
+
+    unsafe impl DisplayLinkTarget {
+        #[method(step:)]
+        fn step(&self, _display_link: &CADisplayLink) {
+            if let Some(renderer) = self.ivars().load() {
+                renderer.render_frame();
+            }
+        }
+    }
+);
+
+#[cfg(target_os = "macos")]
+struct MetalLayerViewIvars {
+    layer: Retained<CAMetalLayer>,
+    renderer: Weak<MetalRenderer>,
+}
+
+#[cfg(target_os = "macos")]
+declare_class!(
+    // a layer backed view whose backing layer is the metal layer. it's sized with the view it's
+    // added to, and keeps the drawable at the pixels it covers whenever it's resized or moved to
+    // a screen of another scale factor, telling the renderer about the new size
+    struct MetalLayerView;
+
+    unsafe impl ClassType for MetalLayerView {
+        #[inherits(NSResponder, NSObject)]
+        type Super = NSView;
+        type Mutability = MainThreadOnly;
+        const NAME: &'static str = "MetalRendererLayerView";
+    }
+
+    impl DeclaredClass for MetalLayerView {
+        type Ivars = MetalLayerViewIvars;
+    }
+
+    unsafe impl MetalLayerView {
+        #[method_id(makeBackingLayer)]
+        fn make_backing_layer(&self) -> Retained<CALayer> {
+            Retained::into_super(self.ivars().layer.clone())
+        }
+
+        // the layer is drawn by the renderer, never by appkit
+        #[method(wantsUpdateLayer)]
+        fn wants_update_layer(&self) -> bool {
+            true
+        }
+
+        #[method(setFrameSize:)]
+        fn set_frame_size(&self, size: NSSize) {
+            let _: () = unsafe { msg_send![super(self), setFrameSize: size] };
+            self.update_drawable_size();
+        }
+
+        #[method(viewDidChangeBackingProperties)]
+        fn view_did_change_backing_properties(&self) {
+            let _: () = unsafe { msg_send![super(self), viewDidChangeBackingProperties] };
+            self.update_drawable_size();
+        }
+    }
+);
+
+#[cfg(target_os = "macos")]
+impl MetalLayerView {
+    fn new(
+        mtm: MainThreadMarker,
+        frame: NSRect,
+        layer: &Retained<CAMetalLayer>,
+        renderer: &MetalRenderer,
+    ) -> Retained<Self> {
+        let view = mtm.alloc().set_ivars(MetalLayerViewIvars {
+            layer: layer.clone(),
+            renderer: Weak::new(renderer),
+        });
+        let view: Retained<Self> = unsafe { msg_send_id![super(view), initWithFrame: frame] };
+        view.setWantsLayer(true);
+        unsafe {
+            view.setAutoresizingMask(
+                NSAutoresizingMaskOptions::NSViewWidthSizable
+                    | NSAutoresizingMaskOptions::NSViewHeightSizable,
+            )
+        };
+        view
+    }
+
+    fn update_drawable_size(&self) {
+        let layer = &self.ivars().layer;
+        layer.setContentsScale(view_scale_factor(self));
+        let size = backing_size(self);
+        if unsafe { layer.drawableSize() } == size {
+            return;
+        }
+        unsafe { layer.setDrawableSize(size) };
+        let Some(renderer) = self.ivars().renderer.load() else {
+            return;
+        };
+        // `init` takes the size from the surface once it's set
+        if renderer.ivars().surface.get().is_some() {
+            renderer.drawable_size_changed(size);
+            renderer.draw_live_resize_frame();
+        }
+    }
+}
+
+// stops the display link with the surface, it would keep firing otherwise
+struct DisplayLink(Retained<CADisplayLink>);
+
+impl Drop for DisplayLink {
+    fn drop(&mut self) {
+        unsafe { self.0.invalidate() };
+    }
+}
+
+pub(crate) struct LayerSurface {
+    // hosts the layer on top of the content view of the window
+    view: Retained<NativeView>,
+    layer: Retained<CAMetalLayer>,
+    device: RefCell<Retained<ProtocolObject<dyn MTLDevice>>>,
+    clear_color: Cell<MTLClearColor>,
+    sample_count: Cell<usize>,
+    depth_stencil_pixel_format: Cell<MTLPixelFormat>,
+    attachments: RefCell<Option<Attachments>>,
+    display_link: DisplayLink,
+}
+
+impl LayerSurface {
+    fn render_pass_descriptor(
+        &self,
+        drawable: &ProtocolObject<dyn CAMetalDrawable>,
+    ) -> Retained<MTLRenderPassDescriptor> {
+        let size = unsafe { self.layer.drawableSize() };
+        let (color, depth, _) = current_attachments(
+            &self.attachments,
+            &self.device.borrow(),
+            (size.width as usize, size.height as usize),
+            self.sample_count.get(),
+            unsafe { self.layer.pixelFormat() },
+            self.depth_stencil_pixel_format.get(),
+            false,
+        );
+        let drawable_texture = unsafe { drawable.texture() };
+        // multisampled frames are resolved into the drawable
+        match &color {
+            Some(color) => render_pass_descriptor(
+                color,
+                Some(&drawable_texture),
+                depth.as_deref(),
+                self.clear_color.get(),
+            ),
+            None => render_pass_descriptor(
+                &drawable_texture,
+                None,
+                depth.as_deref(),
+                self.clear_color.get(),
+            ),
+        }
+    }
+}
+
+// the color and depth attachments for the size and formats of the frame, recreated only when
+// one of them changed. an offscreen surface also gets the texture the frame ends up in
+#[allow(clippy::too_many_arguments)]
+fn current_attachments(
+    attachments: &RefCell<Option<Attachments>>,
+    device: &ProtocolObject<dyn MTLDevice>,
+    size: (usize, usize),
+    sample_count: usize,
+    color_format: MTLPixelFormat,
+    depth_format: MTLPixelFormat,
+    offscreen: bool,
+) -> (Option<Texture>, Option<Texture>, Option<Texture>) {
+    let mut attachments = attachments.borrow_mut();
+    let current = attachments.as_ref().is_some_and(|attachments| {
+        attachments.size == size
+            && attachments.sample_count == sample_count
+            && attachments.color_format == color_format
+            && attachments.depth_format == depth_format
+    });
+    if !current {
+        *attachments = Some(Attachments {
+            size,
+            sample_count,
+            color_format,
+            depth_format,
+            // single sampled frames render straight into the drawable
+            color: (sample_count > 1).then(|| {
+                attachment_texture(
+                    device,
+                    color_format,
+                    size,
+                    sample_count,
+                    MTLTextureUsage::RenderTarget,
+                )
+            }),
+            depth: (depth_format != MTLPixelFormat::Invalid).then(|| {
+                attachment_texture(
+                    device,
+                    depth_format,
+                    size,
+                    sample_count,
+                    MTLTextureUsage::RenderTarget,
+                )
+            }),
+            // usable like a drawable that isn't framebuffer only
+            output: offscreen.then(|| {
+                attachment_texture(
+                    device,
+                    color_format,
+                    size,
+                    1,
+                    MTLTextureUsage::RenderTarget
+                        | MTLTextureUsage::ShaderRead
+                        | MTLTextureUsage::ShaderWrite,
+                )
+            }),
+        });
+    }
+    let attachments = attachments.as_ref().unwrap();
+    (
+        attachments.color.clone(),
+        attachments.depth.clone(),
+        attachments.output.clone(),
+    )
+}
+
+// renders into textures of its own instead of a window, for `MetalRenderer::new_headless`
+pub(crate) struct OffscreenSurface {
+    device: RefCell<Retained<ProtocolObject<dyn MTLDevice>>>,
+    // in pixels
+    size: Cell<NSSize>,
+    color_format: Cell<MTLPixelFormat>,
+    clear_color: Cell<MTLClearColor>,
+    sample_count: Cell<usize>,
+    depth_stencil_pixel_format: Cell<MTLPixelFormat>,
+    attachments: RefCell<Option<Attachments>>,
+}
+
+impl OffscreenSurface {
+    // the texture the frame ends up in and a render pass clearing and drawing into it
+    fn next_frame(&self) -> (Texture, Retained<MTLRenderPassDescriptor>) {
+        let size = self.size.get();
+        let (color, depth, output) = current_attachments(
+            &self.attachments,
+            &self.device.borrow(),
+            (size.width as usize, size.height as usize),
+            self.sample_count.get(),
+            self.color_format.get(),
+            self.depth_stencil_pixel_format.get(),
+            true,
+        );
+        let output = output.unwrap();
+        let descriptor = match &color {
+            Some(color) => render_pass_descriptor(
+                color,
+                Some(&output),
+                depth.as_deref(),
+                self.clear_color.get(),
+            ),
+            None => render_pass_descriptor(&output, None, depth.as_deref(), self.clear_color.get()),
+        };
+        (output, descriptor)
+    }
+}
+
+// what the renderer draws into and presents, picked with `Backend`, or offscreen without a
+// window
+pub(crate) enum Surface {
+    #[cfg(target_os = "macos")]
+    MetalKit(Retained<MTKView>),
+    MetalLayer(LayerSurface),
+    Offscreen(OffscreenSurface),
+}
+
+impl Surface {
+    #[cfg(target_os = "macos")]
+    pub(crate) fn new_metal_kit(
+        window: &NativeWindow,
+        device: &Retained<ProtocolObject<dyn MTLDevice>>,
+        renderer: &MetalRenderer,
+    ) -> Self {
+        let mtm = MainThreadMarker::new().unwrap();
+        let frame_rect = window.frame();
+        let mtk_view =
+            unsafe { MTKView::initWithFrame_device(mtm.alloc(), frame_rect, Some(device)) };
+        unsafe {
+            mtk_view.setDelegate(Some(ProtocolObject::from_ref(renderer)));
+            // the drawable is sized with the backing scale factor in `set_frame`, rather than
+            // from the frame of the view whenever appkit lays it out
+            mtk_view.setAutoResizeDrawable(false);
+        }
+        #[cfg(target_os = "macos")]
+        Surface::MetalKit(mtk_view)
+    }
+
+    pub(crate) fn new_metal_layer(
+        window: &NativeWindow,
+        device: &Retained<ProtocolObject<dyn MTLDevice>>,
+        renderer: &MetalRenderer,
+    ) -> Self {
+        let mtm = MainThreadMarker::new().unwrap();
+        let layer = unsafe { CAMetalLayer::new() };
+        unsafe {
+            layer.setDevice(Some(device));
+            layer.setPixelFormat(MTLPixelFormat::BGRA8Unorm);
+        }
+        layer.setContentsScale(window.scale_factor());
+
+        #[cfg(target_os = "macos")]
+        let view = Retained::into_super(MetalLayerView::new(mtm, window.frame(), &layer, renderer));
+        // the layer of a uikit view can't be replaced, the metal layer goes on top of it and
+        // follows its bounds in `set_frame`
+        #[cfg(target_os = "ios")]
+        let view = {
+            let view = NativeView::initWithFrame(mtm.alloc(), window.bounds());
+            view.layer().addSublayer(&layer);
+            view
+        };
+
+        // the display link of a view follows the screen the view is on, on ios it's the screen
+        // of the device
+        let target = mtm.alloc().set_ivars(Weak::new(renderer));
+        let target: Retained<DisplayLinkTarget> = unsafe { msg_send_id![super(target), init] };
+        #[cfg(target_os = "macos")]
+        let display_link: Retained<CADisplayLink> =
+            unsafe { msg_send_id![&view, displayLinkWithTarget: &*target, selector: sel!(step:)] };
+        #[cfg(target_os = "ios")]
+        let display_link: Retained<CADisplayLink> = unsafe {
+            msg_send_id![
+                CADisplayLink::class(),
+                displayLinkWithTarget: &*target,
+                selector: sel!(step:)
+            ]
+        };
+        unsafe {
+            display_link.addToRunLoop_forMode(&NSRunLoop::mainRunLoop(), NSRunLoopCommonModes)
+        };
+
+        Surface::MetalLayer(LayerSurface {
+            view,
+            layer,
+            device: RefCell::new(device.clone()),
+            clear_color: Cell::new(MTLClearColor {
+                red: 0.,
+                green: 0.,
+                blue: 0.,
+                alpha: 1.,
+            }),
+            sample_count: Cell::new(1),
+            depth_stencil_pixel_format: Cell::new(MTLPixelFormat::Invalid),
+            attachments: RefCell::default(),
+            display_link: DisplayLink(display_link),
+        })
+    }
+
+    // `size` in pixels
+    pub(crate) fn new_offscreen(
+        size: NSSize,
+        device: &Retained<ProtocolObject<dyn MTLDevice>>,
+    ) -> Self {
+        Surface::Offscreen(OffscreenSurface {
+            device: RefCell::new(device.clone()),
+            size: Cell::new(size),
+            color_format: Cell::new(MTLPixelFormat::BGRA8Unorm),
+            clear_color: Cell::new(MTLClearColor {
+                red: 0.,
+                green: 0.,
+                blue: 0.,
+                alpha: 1.,
+            }),
+            sample_count: Cell::new(1),
+            depth_stencil_pixel_format: Cell::new(MTLPixelFormat::Invalid),
+            attachments: RefCell::default(),
+        })
+    }
+
+    pub(crate) fn view(&self) -> Option<&NativeView> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => Some(mtk_view),
+            Surface::MetalLayer(surface) => Some(&surface.view),
+            Surface::Offscreen(_) => None,
+        }
+    }
+
+    // draws with `device` from the next frame on, the attachments are recreated on it
+    pub(crate) fn set_device(&self, device: &Retained<ProtocolObject<dyn MTLDevice>>) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.setDevice(Some(device)) },
+            Surface::MetalLayer(surface) => {
+                unsafe { surface.layer.setDevice(Some(device)) };
+                surface.device.replace(device.clone());
+                surface.attachments.replace(None);
+            }
+            Surface::Offscreen(surface) => {
+                surface.device.replace(device.clone());
+                surface.attachments.replace(None);
+            }
+        }
+    }
+
+    pub(crate) fn metal_layer(&self) -> Option<Retained<CAMetalLayer>> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.layer() }
+                .map(|layer| unsafe { Retained::cast::<CAMetalLayer>(layer) }),
+            Surface::MetalLayer(surface) => Some(surface.layer.clone()),
+            Surface::Offscreen(_) => None,
+        }
+    }
+
+    // sizes the view like `frame` and its drawable to the pixels the view covers, which on a
+    // retina screen are twice its size in points. returns the new drawable size when it changed
+    pub(crate) fn set_frame(&self, frame: NSRect) -> Option<NSSize> {
+        let view = self.view()?;
+        #[cfg(target_os = "macos")]
+        unsafe {
+            view.setFrame(frame)
+        };
+        #[cfg(target_os = "ios")]
+        view.setFrame(frame);
+        let size = backing_size(view);
+        let scale_factor = view_scale_factor(view);
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe {
+                if let Some(layer) = mtk_view.layer() {
+                    layer.setContentsScale(scale_factor);
+                }
+                if mtk_view.drawableSize() == size {
+                    return None;
+                }
+                mtk_view.setDrawableSize(size);
+            },
+            Surface::MetalLayer(surface) => unsafe {
+                #[cfg(target_os = "ios")]
+                surface.layer.setFrame(view.bounds());
+                surface.layer.setContentsScale(scale_factor);
+                if surface.layer.drawableSize() == size {
+                    return None;
+                }
+                surface.layer.setDrawableSize(size);
+            },
+            Surface::Offscreen(_) => return None,
+        }
+        Some(size)
+    }
+
+    // the texture the last frame of an offscreen surface ended up in
+    pub(crate) fn offscreen_output(&self) -> Option<Texture> {
+        let Surface::Offscreen(surface) = self else {
+            return None;
+        };
+        surface.attachments.borrow().as_ref()?.output.clone()
+    }
+
+    // resizes an offscreen surface, in pixels. returns whether the size changed
+    pub(crate) fn set_offscreen_size(&self, size: NSSize) -> bool {
+        let Surface::Offscreen(surface) = self else {
+            return false;
+        };
+        surface.size.replace(size) != size
+    }
+
+    pub(crate) fn drawable_size(&self) -> NSSize {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.drawableSize() },
+            Surface::MetalLayer(surface) => unsafe { surface.layer.drawableSize() },
+            Surface::Offscreen(surface) => surface.size.get(),
+        }
+    }
+
+    // the drawable of the frame and a render pass clearing and drawing into it
+    pub(crate) fn next_frame(&self) -> Result<Frame, &'static str> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => {
+                let drawable =
+                    unsafe { mtk_view.currentDrawable() }.ok_or("no drawable available")?;
+                let descriptor = unsafe { mtk_view.currentRenderPassDescriptor() }
+                    .ok_or("no render pass descriptor available")?;
+                let texture = unsafe { drawable.texture() };
+                Ok((Some(drawable), texture, descriptor))
+            }
+            Surface::MetalLayer(surface) => {
+                let drawable =
+                    unsafe { surface.layer.nextDrawable() }.ok_or("no drawable available")?;
+                let descriptor = surface.render_pass_descriptor(&drawable);
+                let texture = unsafe { drawable.texture() };
+                Ok((Some(drawable), texture, descriptor))
+            }
+            Surface::Offscreen(surface) => {
+                let (texture, descriptor) = surface.next_frame();
+                Ok((None, texture, descriptor))
+            }
+        }
+    }
+
+    pub(crate) fn color_pixel_format(&self) -> MTLPixelFormat {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.colorPixelFormat() },
+            Surface::MetalLayer(surface) => unsafe { surface.layer.pixelFormat() },
+            Surface::Offscreen(surface) => surface.color_format.get(),
+        }
+    }
+
+    // `color_space` is a CGColorSpaceRef, null for the default color space
+    pub(crate) fn set_color_pixel_format(
+        &self,
+        pixel_format: MTLPixelFormat,
+        color_space: *mut CGColorSpace,
+    ) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe {
+                mtk_view.setColorPixelFormat(pixel_format);
+                let _: () = msg_send![mtk_view, setColorspace: color_space];
+            },
+            Surface::MetalLayer(surface) => unsafe {
+                surface.layer.setPixelFormat(pixel_format);
+                let _: () = msg_send![&surface.layer, setColorspace: color_space];
+            },
+            // the pixels are read back as they are, without color matching
+            Surface::Offscreen(surface) => surface.color_format.set(pixel_format),
+        }
+    }
+
+    pub(crate) fn depth_stencil_pixel_format(&self) -> MTLPixelFormat {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.depthStencilPixelFormat() },
+            Surface::MetalLayer(surface) => surface.depth_stencil_pixel_format.get(),
+            Surface::Offscreen(surface) => surface.depth_stencil_pixel_format.get(),
+        }
+    }
+
+    // the depth is cleared to the far plane every frame
+    pub(crate) fn set_depth_stencil_pixel_format(&self, pixel_format: MTLPixelFormat) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe {
+                mtk_view.setDepthStencilPixelFormat(pixel_format);
+                mtk_view.setClearDepth(1.);
+            },
+            Surface::MetalLayer(surface) => surface.depth_stencil_pixel_format.set(pixel_format),
+            Surface::Offscreen(surface) => surface.depth_stencil_pixel_format.set(pixel_format),
+        }
+    }
+
+    pub(crate) fn sample_count(&self) -> usize {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.sampleCount() },
+            Surface::MetalLayer(surface) => surface.sample_count.get(),
+            Surface::Offscreen(surface) => surface.sample_count.get(),
+        }
+    }
+
+    pub(crate) fn set_sample_count(&self, sample_count: usize) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.setSampleCount(sample_count) },
+            Surface::MetalLayer(surface) => surface.sample_count.set(sample_count),
+            Surface::Offscreen(surface) => surface.sample_count.set(sample_count),
+        }
+    }
+
+    pub(crate) fn clear_color(&self) -> MTLClearColor {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.clearColor() },
+            Surface::MetalLayer(surface) => surface.clear_color.get(),
+            Surface::Offscreen(surface) => surface.clear_color.get(),
+        }
+    }
+
+    pub(crate) fn set_clear_color(&self, clear_color: MTLClearColor) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.setClearColor(clear_color) },
+            Surface::MetalLayer(surface) => surface.clear_color.set(clear_color),
+            Surface::Offscreen(surface) => surface.clear_color.set(clear_color),
+        }
+    }
+
+    pub(crate) fn presents_with_transaction(&self) -> bool {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.presentsWithTransaction() },
+            Surface::MetalLayer(surface) => unsafe { surface.layer.presentsWithTransaction() },
+            Surface::Offscreen(_) => false,
+        }
+    }
+
+    pub(crate) fn set_presents_with_transaction(&self, presents_with_transaction: bool) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe {
+                mtk_view.setPresentsWithTransaction(presents_with_transaction)
+            },
+            Surface::MetalLayer(surface) => unsafe {
+                surface
+                    .layer
+                    .setPresentsWithTransaction(presents_with_transaction)
+            },
+            Surface::Offscreen(_) => (),
+        }
+    }
+
+    pub(crate) fn set_framebuffer_only(&self, framebuffer_only: bool) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.setFramebufferOnly(framebuffer_only) },
+            Surface::MetalLayer(surface) => unsafe {
+                surface.layer.setFramebufferOnly(framebuffer_only)
+            },
+            // the output can always be read
+            Surface::Offscreen(_) => (),
+        }
+    }
+
+    pub(crate) fn preferred_frames_per_second(&self) -> isize {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe { mtk_view.preferredFramesPerSecond() },
+            Surface::MetalLayer(surface) => {
+                let range = unsafe { surface.display_link.0.preferredFrameRateRange() };
+                range.preferred as isize
+            }
+            Surface::Offscreen(_) => 0,
+        }
+    }
+
+    // the display link still fires at most once per refresh of the screen
+    pub(crate) fn set_preferred_frames_per_second(&self, frames_per_second: isize) {
+        match self {
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe {
+                mtk_view.setPreferredFramesPerSecond(frames_per_second)
+            },
+            Surface::MetalLayer(surface) => {
+                let rate = frames_per_second as f32;
+                let range = CAFrameRateRange {
+                    minimum: rate,
+                    maximum: rate,
+                    preferred: rate,
+                };
+                unsafe { surface.display_link.0.setPreferredFrameRateRange(range) };
+            }
+            Surface::Offscreen(_) => (),
+        }
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        match self {
+            // a paused view stops its display link, setNeedsDisplay still lets appkit redraw
+            // it after it's resized or exposed
+            #[cfg(target_os = "macos")]
+            Surface::MetalKit(mtk_view) => unsafe {
+                mtk_view.setPaused(paused);
+                mtk_view.setEnableSetNeedsDisplay(paused);
+            },
+            Surface::MetalLayer(surface) => unsafe { surface.display_link.0.setPaused(paused) },
+            Surface::Offscreen(_) => (),
+        }
+    }
+}
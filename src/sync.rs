@@ -0,0 +1,213 @@
+// synchronization the gpu doesn't do on its own. fences order the passes of a frame using the
+// resources of an untracked `GpuAllocator`, which metal leaves unordered, and events order the
+// command buffers of different queues, or the gpu and the cpu, by a value they count up
+use core::time::Duration;
+
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLCommandBuffer, MTLComputeCommandEncoder, MTLDevice, MTLEvent, MTLFence,
+    MTLRenderCommandEncoder, MTLRenderStages, MTLSharedEvent,
+};
+
+use crate::{MetalRenderer, RenderPass};
+
+// a fence of the passes of a frame. a pass updating it signals it once it's done with its
+// resources, the passes waiting for it start using theirs only after that. a pass only waits
+// for the updates of the passes encoded before it, on the same command queue
+#[derive(Clone)]
+pub struct GpuFence(Retained<ProtocolObject<dyn MTLFence>>);
+
+impl GpuFence {
+    pub fn fence(&self) -> &Retained<ProtocolObject<dyn MTLFence>> {
+        &self.0
+    }
+}
+
+// the fences a pass waits for before its first command and updates after its last one
+#[derive(Clone, Default)]
+pub(crate) struct PassFences {
+    pub(crate) waits: Vec<GpuFence>,
+    pub(crate) updates: Vec<GpuFence>,
+}
+
+impl PassFences {
+    pub(crate) fn encode_compute_waits(
+        &self,
+        encoder: &ProtocolObject<dyn MTLComputeCommandEncoder>,
+    ) {
+        for fence in &self.waits {
+            encoder.waitForFence(&fence.0);
+        }
+    }
+
+    pub(crate) fn encode_compute_updates(
+        &self,
+        encoder: &ProtocolObject<dyn MTLComputeCommandEncoder>,
+    ) {
+        for fence in &self.updates {
+            encoder.updateFence(&fence.0);
+        }
+    }
+
+    // the vertices wait, so the buffers the vertex functions read are already written
+    pub(crate) fn encode_render_waits(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+    ) {
+        for fence in &self.waits {
+            encoder.waitForFence_beforeStages(&fence.0, MTLRenderStages::MTLRenderStageVertex);
+        }
+    }
+
+    pub(crate) fn encode_render_updates(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+    ) {
+        for fence in &self.updates {
+            encoder.updateFence_afterStages(&fence.0, MTLRenderStages::MTLRenderStageFragment);
+        }
+    }
+}
+
+impl RenderPass {
+    // the draws of the pass wait for the passes updating `fence` before it, e.g. the compute
+    // pass writing their vertices in an untracked heap
+    pub fn wait_for_fence(&mut self, fence: &GpuFence) -> &mut Self {
+        self.fences.waits.push(fence.clone());
+        self
+    }
+
+    // signals `fence` once the draws of the pass are done
+    pub fn update_fence(&mut self, fence: &GpuFence) -> &mut Self {
+        self.fences.updates.push(fence.clone());
+        self
+    }
+}
+
+#[derive(Clone)]
+enum Event {
+    Device(Retained<ProtocolObject<dyn MTLEvent>>),
+    Shared(Retained<ProtocolObject<dyn MTLSharedEvent>>),
+}
+
+// a counter command buffers wait for and signal, starting at 0. command buffers waiting for a
+// value start once the event reached it, whichever queue signals it. a shared event can also
+// be signaled and waited for by the cpu
+#[derive(Clone)]
+pub struct GpuEvent(Event);
+
+impl GpuEvent {
+    pub fn event(&self) -> &ProtocolObject<dyn MTLEvent> {
+        match &self.0 {
+            Event::Device(event) => event,
+            Event::Shared(event) => ProtocolObject::from_ref(&**event),
+        }
+    }
+
+    pub fn is_shared(&self) -> bool {
+        matches!(self.0, Event::Shared(_))
+    }
+
+    // the value the event reached, none for an event that isn't shared
+    pub fn signaled_value(&self) -> Option<u64> {
+        match &self.0 {
+            Event::Shared(event) => Some(unsafe { event.signaledValue() }),
+            Event::Device(_) => None,
+        }
+    }
+
+    // sets the event to `value` from the cpu, starting the command buffers waiting for it.
+    // returns false for an event that isn't shared
+    pub fn signal(&self, value: u64) -> bool {
+        match &self.0 {
+            Event::Shared(event) => {
+                unsafe { event.setSignaledValue(value) };
+                true
+            }
+            Event::Device(_) => false,
+        }
+    }
+
+    // blocks until the event reaches `value`, returns false once `timeout` passed first or for
+    // an event that isn't shared
+    pub fn wait(&self, value: u64, timeout: Duration) -> bool {
+        match &self.0 {
+            Event::Shared(event) => unsafe {
+                let milliseconds = timeout.as_millis().min(u64::MAX as u128) as u64;
+                event.waitUntilSignaledValue_timeoutMS(value, milliseconds)
+            },
+            Event::Device(_) => false,
+        }
+    }
+}
+
+// the events the next frame waits for before its first pass and signals after its last one
+#[derive(Default)]
+pub(crate) struct FrameEvents {
+    waits: Vec<(GpuEvent, u64)>,
+    signals: Vec<(GpuEvent, u64)>,
+}
+
+impl FrameEvents {
+    pub(crate) fn clear(&mut self) {
+        self.waits.clear();
+        self.signals.clear();
+    }
+}
+
+impl MetalRenderer {
+    pub fn create_fence(&self) -> GpuFence {
+        GpuFence(self.device().newFence().expect("Failed to create a fence."))
+    }
+
+    // an event only command buffers signal and wait for, cheaper than a shared one
+    pub fn create_event(&self) -> GpuEvent {
+        let event = self
+            .device()
+            .newEvent()
+            .expect("Failed to create an event.");
+        GpuEvent(Event::Device(event))
+    }
+
+    // an event the cpu can also signal and wait for
+    pub fn create_shared_event(&self) -> GpuEvent {
+        let event = self
+            .device()
+            .newSharedEvent()
+            .expect("Failed to create a shared event.");
+        GpuEvent(Event::Shared(event))
+    }
+
+    // the next frame starts on the gpu once `event` reached `value`. called from the callbacks
+    // of a frame, it's the frame they record
+    pub fn wait_for_event(&self, event: &GpuEvent, value: u64) {
+        let mut frame_events = self.ivars().frame_events.borrow_mut();
+        frame_events.waits.push((event.clone(), value));
+    }
+
+    // sets `event` to `value` once the gpu finished the next frame, like `wait_for_event`
+    pub fn signal_event(&self, event: &GpuEvent, value: u64) {
+        let mut frame_events = self.ivars().frame_events.borrow_mut();
+        frame_events.signals.push((event.clone(), value));
+    }
+
+    // encoded before the passes of the frame. the events stay queued until the frame is
+    // committed, a dropped frame leaves them to the next one
+    pub(crate) fn encode_event_waits(&self, command_buffer: &ProtocolObject<dyn MTLCommandBuffer>) {
+        for (event, value) in &self.ivars().frame_events.borrow().waits {
+            command_buffer.encodeWaitForEvent_value(event.event(), *value);
+        }
+    }
+
+    // encoded after the passes of the frame, which is committed next
+    pub(crate) fn encode_event_signals(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+    ) {
+        let mut frame_events = self.ivars().frame_events.borrow_mut();
+        for (event, value) in &frame_events.signals {
+            command_buffer.encodeSignalEvent_value(event.event(), *value);
+        }
+        frame_events.clear();
+    }
+}
@@ -0,0 +1,454 @@
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLClearColor, MTLDevice, MTLGPUFamily, MTLLoadAction, MTLPixelFormat, MTLRenderPassDescriptor,
+    MTLStorageMode, MTLStoreAction, MTLTexture, MTLTextureDescriptor, MTLTextureType,
+    MTLTextureUsage,
+};
+
+use crate::{
+    allocator::{align_up, GpuAllocator, HeapAllocation},
+    debug_labels::label_resource,
+    MetalRenderer, RasterizationRateMap, RenderPass,
+};
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// a texture to render into, private to the gpu. `sample_count` above 1 makes it multisampled
+pub(crate) fn attachment_texture(
+    device: &ProtocolObject<dyn MTLDevice>,
+    pixel_format: MTLPixelFormat,
+    size: (usize, usize),
+    sample_count: usize,
+    usage: MTLTextureUsage,
+) -> Texture {
+    create_attachment(
+        device,
+        pixel_format,
+        size,
+        sample_count,
+        usage,
+        MTLStorageMode::Private,
+    )
+}
+
+// a single sampled attachment only living through the pass rendering into it, in tile memory
+// where the gpu supports it
+pub(crate) fn transient_attachment_texture(
+    device: &ProtocolObject<dyn MTLDevice>,
+    pixel_format: MTLPixelFormat,
+    size: (usize, usize),
+) -> Texture {
+    create_attachment(
+        device,
+        pixel_format,
+        size,
+        1,
+        MTLTextureUsage::RenderTarget,
+        transient_storage_mode(device),
+    )
+}
+
+// whether the gpu renders in tiles and can keep attachments in tile memory only, true for
+// apple gpus
+fn supports_memoryless(device: &ProtocolObject<dyn MTLDevice>) -> bool {
+    device.supportsFamily(MTLGPUFamily::Apple1)
+}
+
+// attachments only living through the pass rendering into them stay in tile memory without
+// any backing in device memory where the gpu supports it. they can't be loaded at the start
+// of a pass nor stored at the end, multisampled color can only be resolved
+fn transient_storage_mode(device: &ProtocolObject<dyn MTLDevice>) -> MTLStorageMode {
+    if supports_memoryless(device) {
+        MTLStorageMode::Memoryless
+    } else {
+        MTLStorageMode::Private
+    }
+}
+
+fn create_attachment(
+    device: &ProtocolObject<dyn MTLDevice>,
+    pixel_format: MTLPixelFormat,
+    size: (usize, usize),
+    sample_count: usize,
+    usage: MTLTextureUsage,
+    storage_mode: MTLStorageMode,
+) -> Texture {
+    let descriptor = attachment_descriptor(pixel_format, size, sample_count, usage, storage_mode);
+    device
+        .newTextureWithDescriptor(&descriptor)
+        .expect("Failed to create a render target.")
+}
+
+fn attachment_descriptor(
+    pixel_format: MTLPixelFormat,
+    (width, height): (usize, usize),
+    sample_count: usize,
+    usage: MTLTextureUsage,
+    storage_mode: MTLStorageMode,
+) -> Retained<MTLTextureDescriptor> {
+    let descriptor = unsafe {
+        MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+            pixel_format,
+            width,
+            height,
+            false,
+        )
+    };
+    if sample_count > 1 {
+        descriptor.setTextureType(MTLTextureType::MTLTextureType2DMultisample);
+        unsafe { descriptor.setSampleCount(sample_count) };
+    }
+    descriptor.setUsage(usage);
+    descriptor.setStorageMode(storage_mode);
+    descriptor
+}
+
+// a render pass clearing `color` and `depth`, multisampled color is resolved into `resolve`.
+// only the color is kept after the pass
+pub(crate) fn render_pass_descriptor(
+    color: &ProtocolObject<dyn MTLTexture>,
+    resolve: Option<&ProtocolObject<dyn MTLTexture>>,
+    depth: Option<&ProtocolObject<dyn MTLTexture>>,
+    clear_color: MTLClearColor,
+) -> Retained<MTLRenderPassDescriptor> {
+    let descriptor = MTLRenderPassDescriptor::renderPassDescriptor();
+    let color_attachment = unsafe { descriptor.colorAttachments().objectAtIndexedSubscript(0) };
+    color_attachment.setTexture(Some(color));
+    match resolve {
+        Some(resolve) => {
+            color_attachment.setResolveTexture(Some(resolve));
+            color_attachment.setStoreAction(MTLStoreAction::MultisampleResolve);
+        }
+        None => color_attachment.setStoreAction(MTLStoreAction::Store),
+    }
+    color_attachment.setLoadAction(MTLLoadAction::Clear);
+    color_attachment.setClearColor(clear_color);
+
+    if let Some(depth) = depth {
+        let depth_attachment = descriptor.depthAttachment();
+        depth_attachment.setTexture(Some(depth));
+        depth_attachment.setLoadAction(MTLLoadAction::Clear);
+        depth_attachment.setStoreAction(MTLStoreAction::DontCare);
+        depth_attachment.setClearDepth(1.);
+        if depth.pixelFormat() == MTLPixelFormat::Depth32Float_Stencil8 {
+            let stencil_attachment = descriptor.stencilAttachment();
+            stencil_attachment.setTexture(Some(depth));
+            stencil_attachment.setLoadAction(MTLLoadAction::Clear);
+            stencil_attachment.setStoreAction(MTLStoreAction::DontCare);
+        }
+    }
+    descriptor
+}
+
+// textures to render into instead of the view, drawn with `RenderPass::render_to` and sampled
+// by the draws after it. the pipelines of the renderer are built for the pixel formats and
+// the sample count of the view, a target has to use the same ones to be drawn with them
+#[derive(Clone)]
+pub struct RenderTarget {
+    color: Texture,
+    // the single sampled texture a multisampled color attachment is resolved into
+    resolve: Option<Texture>,
+    depth: Option<Texture>,
+    // the depth is kept after the pass rather than dropped, for `depth_texture`
+    store_depth: bool,
+    // the memory of the heap the textures are placed in, for targets built in an allocator
+    allocation: Option<HeapAllocation>,
+    // the rates the passes into the target are rasterized at
+    rasterization_rate_map: Option<RasterizationRateMap>,
+    pub clear_color: MTLClearColor,
+}
+
+impl RenderTarget {
+    // wraps textures created by the application, they need the render target usage. with a
+    // `resolve` texture, `color` is multisampled and resolved into it at the end of the pass
+    pub fn new(color: &Texture, resolve: Option<&Texture>, depth: Option<&Texture>) -> Self {
+        RenderTarget {
+            color: color.clone(),
+            resolve: resolve.cloned(),
+            depth: depth.cloned(),
+            store_depth: false,
+            allocation: None,
+            rasterization_rate_map: None,
+            clear_color: MTLClearColor {
+                red: 0.,
+                green: 0.,
+                blue: 0.,
+                alpha: 1.,
+            },
+        }
+    }
+
+    // the texture to sample once the pass rendered, the resolved one for multisampled targets
+    pub fn texture(&self) -> &Texture {
+        self.resolve.as_ref().unwrap_or(&self.color)
+    }
+
+    // the depth the last pass into the target left, only for targets built with
+    // `RenderTargetBuilder::with_stored_depth`
+    pub fn depth_texture(&self) -> Option<&Texture> {
+        self.depth.as_ref().filter(|_| self.store_depth)
+    }
+
+    // names the textures of the target in gpu captures, the resolve and the depth texture
+    // after the color one
+    pub fn set_label(&self, label: &str) {
+        label_resource(&*self.color, label);
+        if let Some(resolve) = &self.resolve {
+            label_resource(&**resolve, &format!("{label} resolve"));
+        }
+        if let Some(depth) = &self.depth {
+            label_resource(&**depth, &format!("{label} depth"));
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.color.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.color.height()
+    }
+
+    // whether the pipelines of `renderer` can draw into the target, they stop matching when
+    // the pixel format, the depth format or the sample count of the renderer changes
+    pub fn is_compatible_with(&self, renderer: &MetalRenderer) -> bool {
+        let depth_format = self.depth.as_ref().map(|depth| depth.pixelFormat());
+        self.color.pixelFormat() == renderer.color_format()
+            && self.color.sampleCount() == renderer.sample_count()
+            && depth_format
+                == renderer
+                    .depth_format()
+                    .map(|format| format.mtl_pixel_format())
+    }
+
+    // renders the passes into the target at the rates of `map`, the target has to have the
+    // physical size of the map. the passes of `RenderPass::render_to` draw in its screen size
+    pub fn set_rasterization_rate_map(&mut self, map: Option<&RasterizationRateMap>) {
+        self.rasterization_rate_map = map.cloned();
+    }
+
+    pub fn rasterization_rate_map(&self) -> Option<&RasterizationRateMap> {
+        self.rasterization_rate_map.as_ref()
+    }
+
+    pub(crate) fn allocation(&self) -> Option<&HeapAllocation> {
+        self.allocation.as_ref()
+    }
+
+    pub(crate) fn has_depth(&self) -> bool {
+        self.depth.is_some()
+    }
+
+    pub(crate) fn pass_descriptor(&self) -> Retained<MTLRenderPassDescriptor> {
+        let descriptor = render_pass_descriptor(
+            &self.color,
+            self.resolve.as_deref(),
+            self.depth.as_deref(),
+            self.clear_color,
+        );
+        if self.store_depth {
+            descriptor
+                .depthAttachment()
+                .setStoreAction(MTLStoreAction::Store);
+        }
+        if let Some(map) = &self.rasterization_rate_map {
+            descriptor.setRasterizationRateMap(Some(map.map()));
+        }
+        descriptor
+    }
+}
+
+impl RenderPass {
+    // records a pass into `target`, encoded ahead of this one so the draws recorded here can
+    // sample `target.texture()`. it starts with the culling, winding and fill mode of this
+    // pass and the depth test when the target has a depth attachment, drawing into the whole
+    // target with the camera of the frame
+    pub fn render_to(&mut self, target: &RenderTarget) -> &mut RenderPass {
+        let render_pass = RenderPass {
+            cull_mode: self.cull_mode,
+            front_facing: self.front_facing,
+            fill_mode: self.fill_mode,
+            depth_stencil_state: target.depth.as_ref().and(self.depth_stencil_state.clone()),
+            viewport: target
+                .rasterization_rate_map
+                .as_ref()
+                .map(RasterizationRateMap::screen_viewport),
+            ..Default::default()
+        };
+        self.offscreen.push((target.clone(), render_pass));
+        &mut self.offscreen.last_mut().unwrap().1
+    }
+}
+
+// the size and the attachments of a `RenderTarget`, which has the color format of the
+// renderer and the depth format and sample count the view has when it's built. by default the
+// multisampled color and the depth are dropped at the end of every pass, but kept in textures
+#[derive(Copy, Clone, Debug)]
+pub struct RenderTargetBuilder {
+    width: usize,
+    height: usize,
+    store_depth: bool,
+    memoryless: bool,
+}
+
+impl RenderTargetBuilder {
+    pub fn new(width: usize, height: usize) -> Self {
+        RenderTargetBuilder {
+            width,
+            height,
+            store_depth: false,
+            memoryless: false,
+        }
+    }
+
+    // keeps the depth after every pass, to be sampled through `RenderTarget::depth_texture`
+    pub fn with_stored_depth(mut self) -> Self {
+        self.store_depth = true;
+        self
+    }
+
+    // keeps the attachments dropped at the end of a pass in tile memory only on the gpus
+    // rendering in tiles, which saves their memory and the bandwidth of writing them out.
+    // the target can then only be drawn into by passes clearing it, like `render_to` does
+    pub fn with_memoryless_attachments(mut self) -> Self {
+        self.memoryless = true;
+        self
+    }
+
+    // the descriptors of the color, the resolve and the depth texture of the target
+    fn descriptors(&self, renderer: &MetalRenderer) -> [Option<Retained<MTLTextureDescriptor>>; 3] {
+        let device = renderer.device();
+        let size = (self.width, self.height);
+        let sample_count = renderer.sample_count();
+        let pixel_format = renderer.color_format();
+        let transient = |pixel_format| {
+            attachment_descriptor(
+                pixel_format,
+                size,
+                sample_count,
+                MTLTextureUsage::RenderTarget,
+                transient_storage_mode(&device),
+            )
+        };
+        let private = |pixel_format, sample_count, usage| {
+            attachment_descriptor(
+                pixel_format,
+                size,
+                sample_count,
+                usage,
+                MTLStorageMode::Private,
+            )
+        };
+        let sampled_usage = MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead;
+        let (color, resolve) = if sample_count > 1 {
+            let color = if self.memoryless {
+                transient(pixel_format)
+            } else {
+                private(pixel_format, sample_count, MTLTextureUsage::RenderTarget)
+            };
+            let resolve = private(pixel_format, 1, sampled_usage);
+            (color, Some(resolve))
+        } else {
+            (private(pixel_format, 1, sampled_usage), None)
+        };
+        let depth = renderer.depth_format().map(|depth_format| {
+            let pixel_format = depth_format.mtl_pixel_format();
+            match (self.store_depth, self.memoryless) {
+                (true, _) => private(pixel_format, sample_count, sampled_usage),
+                (false, true) => transient(pixel_format),
+                (false, false) => {
+                    private(pixel_format, sample_count, MTLTextureUsage::RenderTarget)
+                }
+            }
+        });
+        [Some(color), resolve, depth]
+    }
+
+    fn target(&self, [color, resolve, depth]: [Option<Texture>; 3]) -> RenderTarget {
+        RenderTarget {
+            store_depth: self.store_depth,
+            ..RenderTarget::new(&color.unwrap(), resolve.as_ref(), depth.as_ref())
+        }
+    }
+
+    pub fn build(&self, renderer: &MetalRenderer) -> RenderTarget {
+        let device = renderer.device();
+        self.target(self.descriptors(renderer).map(|descriptor| {
+            descriptor.map(|descriptor| {
+                device
+                    .newTextureWithDescriptor(&descriptor)
+                    .expect("Failed to create a render target.")
+            })
+        }))
+    }
+
+    // like `build`, with the textures placed together in one allocation of `allocator`
+    pub fn build_in(&self, renderer: &MetalRenderer, allocator: &GpuAllocator) -> RenderTarget {
+        let device = renderer.device();
+        let descriptors = self.descriptors(renderer);
+        // laid out from an offset aligned for all of them
+        let (mut size, mut align) = (0, 1);
+        for descriptor in descriptors.iter().flatten() {
+            if descriptor.storageMode() != MTLStorageMode::Memoryless {
+                let size_and_align = device.heapTextureSizeAndAlignWithDescriptor(descriptor);
+                size = align_up(size, size_and_align.align) + size_and_align.size;
+                align = align.max(size_and_align.align);
+            }
+        }
+        let allocation = allocator.allocate(size.max(1), align, MTLStorageMode::Private);
+        self.place(renderer, descriptors, &allocation)
+            .expect("Failed to place a render target in a heap.")
+    }
+
+    // like `build_in`, with the textures placed in the memory of `allocation` where they
+    // alias the resources already placed in it. none when they don't fit
+    pub fn build_aliasing(
+        &self,
+        renderer: &MetalRenderer,
+        allocation: &HeapAllocation,
+    ) -> Option<RenderTarget> {
+        self.place(renderer, self.descriptors(renderer), allocation)
+    }
+
+    // places the textures one after the other in `allocation`, the memoryless ones have no
+    // memory to place and are created on their own
+    fn place(
+        &self,
+        renderer: &MetalRenderer,
+        descriptors: [Option<Retained<MTLTextureDescriptor>>; 3],
+        allocation: &HeapAllocation,
+    ) -> Option<RenderTarget> {
+        if allocation.storage_mode() != MTLStorageMode::Private {
+            return None;
+        }
+        let device = renderer.device();
+        let mut offset = 0;
+        let mut textures = [None, None, None];
+        for (texture, descriptor) in textures.iter_mut().zip(descriptors) {
+            let Some(descriptor) = descriptor else {
+                continue;
+            };
+            if descriptor.storageMode() == MTLStorageMode::Memoryless {
+                *texture = device.newTextureWithDescriptor(&descriptor);
+                continue;
+            }
+            let size_and_align = device.heapTextureSizeAndAlignWithDescriptor(&descriptor);
+            let start = align_up(allocation.offset() + offset, size_and_align.align);
+            offset = start - allocation.offset();
+            *texture = Some(allocation.new_texture(&descriptor, offset)?);
+            offset += size_and_align.size;
+        }
+        Some(RenderTarget {
+            allocation: Some(allocation.clone()),
+            ..self.target(textures)
+        })
+    }
+}
+
+impl MetalRenderer {
+    // creates a `width` by `height` target the pipelines of the renderer can draw into, with
+    // the `color_format` of the renderer and the depth format and sample count the view has now
+    pub fn create_render_target(&self, width: usize, height: usize) -> RenderTarget {
+        RenderTargetBuilder::new(width, height).build(self)
+    }
+}
@@ -0,0 +1,465 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use core_foundation::base::CFIndex;
+use core_graphics::{
+    base::{kCGImageAlphaNone, CGFloat},
+    color_space::CGColorSpace,
+    context::CGContext,
+    font::CGGlyph,
+    geometry::{CGPoint, CGRect, CGSize},
+};
+use core_text::{
+    font::{self as ct_font, CTFont},
+    font_descriptor::kCTFontOrientationDefault,
+};
+use objc2::{rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_metal::{
+    MTLBuffer, MTLCommandBuffer, MTLCommandEncoder, MTLDevice, MTLLoadAction, MTLOrigin,
+    MTLPixelFormat, MTLPrimitiveType, MTLRegion, MTLRenderCommandEncoder, MTLRenderPassDescriptor,
+    MTLRenderPipelineState, MTLSize, MTLStoreAction, MTLTexture, MTLTextureDescriptor,
+    MTLTextureUsage,
+};
+
+use crate::MetalRenderer;
+
+// the width and height of the glyph atlas of a font in pixels
+const ATLAS_SIZE: usize = 1024;
+
+// empty pixels around every glyph of the atlas, so sampling between pixels doesn't pick up
+// its neighbours
+const GLYPH_PADDING: usize = 1;
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+type Buffer = Retained<ProtocolObject<dyn MTLBuffer>>;
+
+// how the lines of a text line up with its position
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TextAlign {
+    // the lines start at the position
+    #[default]
+    Left,
+    // the lines are centered on the position
+    Center,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    // straight RGBA, blended over the frame
+    pub color: [f32; 4],
+    pub align: TextAlign,
+    // lines wider than this many points wrap at the spaces between their words, words wider
+    // than it get a line of their own
+    pub max_width: Option<f32>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            color: [1., 1., 1., 1.],
+            align: TextAlign::Left,
+            max_width: None,
+        }
+    }
+}
+
+// a quad showing a glyph of the atlas, the `TextQuad` struct in triangle.metal
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub(crate) struct TextQuad {
+    // the top left corner in points from the top left of the view
+    position: [f32; 2],
+    size: [f32; 2],
+    // the glyph in the atlas, in texture coordinates
+    uv_origin: [f32; 2],
+    uv_size: [f32; 2],
+    // premultiplied by alpha
+    color: [f32; 4],
+}
+
+// a rasterized glyph, in pixels of the atlas
+#[derive(Copy, Clone)]
+struct Glyph {
+    // None for glyphs without pixels like spaces, and the ones that didn't fit in the atlas
+    atlas_origin: Option<[usize; 2]>,
+    size: [usize; 2],
+    // from the pen on the baseline to the top left corner of the glyph, y grows down
+    offset: [f32; 2],
+    advance: f32,
+}
+
+// the glyphs rasterized so far, packed into rows from the top left of the atlas
+#[derive(Default)]
+struct Atlas {
+    glyphs: HashMap<char, Glyph>,
+    // where the next glyph goes and the height of the tallest glyph of the row
+    cursor: [usize; 2],
+    row_height: usize,
+}
+
+// a font rasterized with CoreText, its glyphs are added to the atlas as they're first drawn
+pub struct Font {
+    font: CTFont,
+    // the pixels per point the glyphs are rasterized with, the backing scale factor of the
+    // view the font was created for
+    scale: f32,
+    texture: Texture,
+    atlas: RefCell<Atlas>,
+}
+
+impl Font {
+    // the distance between the baselines of two lines in points
+    pub fn line_height(&self) -> f32 {
+        (self.font.ascent() + self.font.descent() + self.font.leading()) as f32 / self.scale
+    }
+
+    // the width and height of `text` in points when drawn with `max_width`
+    pub fn text_size(&self, text: &str, max_width: Option<f32>) -> (f32, f32) {
+        let lines = self.lines(text, max_width);
+        let width = lines.iter().map(|(_, width)| *width).fold(0., f32::max);
+        (width, lines.len() as f32 * self.line_height())
+    }
+
+    fn glyph(&self, character: char) -> Glyph {
+        if let Some(glyph) = self.atlas.borrow().glyphs.get(&character) {
+            return *glyph;
+        }
+        let glyph = self.rasterize(character);
+        self.atlas.borrow_mut().glyphs.insert(character, glyph);
+        glyph
+    }
+
+    // the advance of `text` in points
+    fn advance(&self, text: &str) -> f32 {
+        text.chars()
+            .map(|character| self.glyph(character).advance)
+            .sum()
+    }
+
+    // draws the glyph of `character` into the atlas. characters the font has no glyph for
+    // show its missing glyph
+    fn rasterize(&self, character: char) -> Glyph {
+        let mut characters = [0; 2];
+        let characters = character.encode_utf16(&mut characters);
+        let mut glyphs: [CGGlyph; 2] = [0; 2];
+        unsafe {
+            self.font.get_glyphs_for_characters(
+                characters.as_ptr(),
+                glyphs.as_mut_ptr(),
+                characters.len() as CFIndex,
+            )
+        };
+        let glyph = glyphs[0];
+        let mut advance = CGSize::new(0., 0.);
+        unsafe {
+            self.font
+                .get_advances_for_glyphs(kCTFontOrientationDefault, &glyph, &mut advance, 1)
+        };
+        let advance = advance.width as f32 / self.scale;
+
+        // the pixels the glyph covers, y grows up from the baseline
+        let bounds = self
+            .font
+            .get_bounding_rects_for_glyphs(kCTFontOrientationDefault, &[glyph]);
+        if bounds.size.width <= 0. || bounds.size.height <= 0. {
+            return Glyph {
+                atlas_origin: None,
+                size: [0, 0],
+                offset: [0., 0.],
+                advance,
+            };
+        }
+        let padding = GLYPH_PADDING as CGFloat;
+        let left = bounds.origin.x.floor() - padding;
+        let bottom = bounds.origin.y.floor() - padding;
+        let width = ((bounds.origin.x + bounds.size.width).ceil() + padding - left) as usize;
+        let height = ((bounds.origin.y + bounds.size.height).ceil() + padding - bottom) as usize;
+        let glyph_metrics = Glyph {
+            atlas_origin: None,
+            size: [width, height],
+            offset: [left as f32, -(bottom as f32 + height as f32)],
+            advance,
+        };
+
+        let mut atlas = self.atlas.borrow_mut();
+        if atlas.cursor[0] + width > ATLAS_SIZE {
+            atlas.cursor = [0, atlas.cursor[1] + atlas.row_height];
+            atlas.row_height = 0;
+        }
+        if atlas.cursor[1] + height > ATLAS_SIZE {
+            // the atlas is full, the glyph is left out
+            return glyph_metrics;
+        }
+        let origin = atlas.cursor;
+        atlas.cursor[0] += width;
+        atlas.row_height = atlas.row_height.max(height);
+
+        // white coverage on black, the rows of the bitmap start at the top
+        let mut context = CGContext::create_bitmap_context(
+            None,
+            width,
+            height,
+            8,
+            width,
+            &CGColorSpace::create_device_gray(),
+            kCGImageAlphaNone,
+        );
+        context.set_gray_fill_color(0., 1.);
+        context.fill_rect(CGRect::new(
+            &CGPoint::new(0., 0.),
+            &CGSize::new(width as CGFloat, height as CGFloat),
+        ));
+        context.set_gray_fill_color(1., 1.);
+        self.font
+            .draw_glyphs(&[glyph], &[CGPoint::new(-left, -bottom)], context.clone());
+        let region = MTLRegion {
+            origin: MTLOrigin {
+                x: origin[0],
+                y: origin[1],
+                z: 0,
+            },
+            size: MTLSize {
+                width,
+                height,
+                depth: 1,
+            },
+        };
+        unsafe {
+            self.texture
+                .replaceRegion_mipmapLevel_withBytes_bytesPerRow(
+                    region,
+                    0,
+                    NonNull::from(context.data()).cast::<c_void>(),
+                    context.bytes_per_row(),
+                )
+        };
+        Glyph {
+            atlas_origin: Some(origin),
+            ..glyph_metrics
+        }
+    }
+
+    // splits `text` into lines at its line breaks and where it's wider than `max_width`, with
+    // their widths in points
+    fn lines(&self, text: &str, max_width: Option<f32>) -> Vec<(String, f32)> {
+        let space = self.glyph(' ').advance;
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let Some(max_width) = max_width else {
+                lines.push((paragraph.to_owned(), self.advance(paragraph)));
+                continue;
+            };
+            let mut line = String::new();
+            let mut line_width = 0.;
+            for word in paragraph.split(' ') {
+                let word_width = self.advance(word);
+                if !line.is_empty() && line_width + space + word_width > max_width {
+                    lines.push((std::mem::take(&mut line), line_width));
+                    line_width = 0.;
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                    line_width += space;
+                }
+                line.push_str(word);
+                line_width += word_width;
+            }
+            lines.push((line, line_width));
+        }
+        lines
+    }
+
+    // lays `text` out into quads, with the top of its first line at `position`
+    fn layout(&self, text: &str, (x, y): (f32, f32), style: &TextStyle) -> Vec<TextQuad> {
+        let [red, green, blue, alpha] = style.color;
+        let color = [red * alpha, green * alpha, blue * alpha, alpha];
+        let ascent = self.font.ascent() as f32 / self.scale;
+        let atlas_size = ATLAS_SIZE as f32;
+        let mut quads = Vec::new();
+        for (row, (line, width)) in self.lines(text, style.max_width).iter().enumerate() {
+            let mut pen = match style.align {
+                TextAlign::Left => x,
+                TextAlign::Center => x - width / 2.,
+            };
+            let baseline = y + ascent + row as f32 * self.line_height();
+            for character in line.chars() {
+                let glyph = self.glyph(character);
+                if let Some([u, v]) = glyph.atlas_origin {
+                    let [width, height] = glyph.size.map(|size| size as f32);
+                    quads.push(TextQuad {
+                        position: [
+                            pen + glyph.offset[0] / self.scale,
+                            baseline + glyph.offset[1] / self.scale,
+                        ],
+                        size: [width / self.scale, height / self.scale],
+                        uv_origin: [u as f32 / atlas_size, v as f32 / atlas_size],
+                        uv_size: [width / atlas_size, height / atlas_size],
+                        color,
+                    });
+                }
+                pen += glyph.advance;
+            }
+        }
+        quads
+    }
+}
+
+// the text queued for the next frame
+#[derive(Default)]
+pub(crate) struct TextState {
+    // the atlas of the font and the quads of every `draw_text` call
+    queued: Vec<(Texture, Vec<TextQuad>)>,
+    // created for the pixel format of the drawable
+    pipeline_state: Option<(
+        MTLPixelFormat,
+        Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    )>,
+}
+
+impl TextState {
+    // drops the pipeline, it's recreated from the current shader library on the next frame
+    pub(crate) fn clear_pipeline_state(&mut self) {
+        self.pipeline_state = None;
+    }
+}
+
+// the text of a frame, drawn over the scene in a pass of its own
+pub(crate) struct TextDraw {
+    pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    // the atlas, the quads and their number for every `draw_text` call
+    draws: Vec<(Texture, Buffer, usize)>,
+    // in points
+    view_size: [f32; 2],
+}
+
+impl TextDraw {
+    // draws over the scene in `drawable_texture`, returns false if the encoder couldn't be
+    // created
+    pub(crate) fn encode(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> bool {
+        let descriptor = MTLRenderPassDescriptor::renderPassDescriptor();
+        let color_attachment = unsafe { descriptor.colorAttachments().objectAtIndexedSubscript(0) };
+        color_attachment.setTexture(Some(drawable_texture));
+        color_attachment.setLoadAction(MTLLoadAction::Load);
+        color_attachment.setStoreAction(MTLStoreAction::Store);
+        let Some(encoder) = command_buffer.renderCommandEncoderWithDescriptor(&descriptor) else {
+            return false;
+        };
+
+        encoder.setRenderPipelineState(&self.pipeline_state);
+        unsafe {
+            encoder.setVertexBytes_length_atIndex(
+                NonNull::from(&self.view_size).cast::<c_void>(),
+                core::mem::size_of_val(&self.view_size),
+                1,
+            );
+        }
+        for (texture, quads, quad_count) in &self.draws {
+            unsafe {
+                encoder.setVertexBuffer_offset_atIndex(Some(quads), 0, 0);
+                encoder.setFragmentTexture_atIndex(Some(texture), 0);
+                encoder.drawPrimitives_vertexStart_vertexCount_instanceCount(
+                    MTLPrimitiveType::TriangleStrip,
+                    0,
+                    4,
+                    *quad_count,
+                );
+            }
+        }
+        encoder.endEncoding();
+        true
+    }
+}
+
+impl MetalRenderer {
+    // creates a font of `size` points, `name` is the PostScript or full name of an installed
+    // font and the system font is used without one. returns None when there's no font of that
+    // name. the glyphs are rasterized for the backing scale factor of the window at the time
+    pub fn create_font(&self, name: Option<&str>, size: f32) -> Option<Font> {
+        let scale = self.scale_factor() as f32;
+        let pixel_size = f64::from(size * scale);
+        let font = match name {
+            Some(name) => ct_font::new_from_name(name, pixel_size).ok()?,
+            None => {
+                ct_font::new_ui_font_for_language(ct_font::kCTFontSystemFontType, pixel_size, None)
+            }
+        };
+
+        let descriptor = unsafe {
+            MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                MTLPixelFormat::R8Unorm,
+                ATLAS_SIZE,
+                ATLAS_SIZE,
+                false,
+            )
+        };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead);
+        let texture = self
+            .device()
+            .newTextureWithDescriptor(&descriptor)
+            .expect("Failed to create a glyph atlas.");
+        Some(Font {
+            font,
+            scale,
+            texture,
+            atlas: RefCell::default(),
+        })
+    }
+
+    // draws `text` over the next frame, after the scene and before the ui and the overlay.
+    // `position` is the top left of the first line in points from the top left corner of the
+    // view, or its top center for centered text. call it from the render callback to draw the
+    // text every frame
+    pub fn draw_text(&self, font: &Font, text: &str, position: (f32, f32), style: &TextStyle) {
+        let quads = font.layout(text, position, style);
+        if !quads.is_empty() {
+            let mut text = self.ivars().text.borrow_mut();
+            text.queued.push((font.texture.clone(), quads));
+        }
+    }
+
+    fn text_pipeline_state(
+        &self,
+        pixel_format: MTLPixelFormat,
+    ) -> Option<Retained<ProtocolObject<dyn MTLRenderPipelineState>>> {
+        if let Some((format, pipeline_state)) = &self.ivars().text.borrow().pipeline_state {
+            if *format == pixel_format {
+                return Some(pipeline_state.clone());
+            }
+        }
+        let pipeline_state =
+            self.create_overlay_pipeline_state("vertex_text", "fragment_text", pixel_format)?;
+        self.ivars().text.borrow_mut().pipeline_state =
+            Some((pixel_format, pipeline_state.clone()));
+        Some(pipeline_state)
+    }
+
+    // uploads the text queued for the frame being recorded, returns None without any
+    pub(crate) fn record_text(
+        &self,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> Option<TextDraw> {
+        let queued = std::mem::take(&mut self.ivars().text.borrow_mut().queued);
+        if queued.is_empty() {
+            return None;
+        }
+        let pipeline_state = self.text_pipeline_state(drawable_texture.pixelFormat())?;
+        let scale = self.scale_factor() as f32;
+        let draws = queued
+            .into_iter()
+            .map(|(texture, quads)| (texture, self.frame_buffer(&quads), quads.len()))
+            .collect();
+        Some(TextDraw {
+            pipeline_state,
+            draws,
+            view_size: [
+                drawable_texture.width() as f32 / scale,
+                drawable_texture.height() as f32 / scale,
+            ],
+        })
+    }
+}
@@ -0,0 +1,451 @@
+use std::{fmt, fs, io, path::Path};
+
+use core::{ffi::c_void, ptr::NonNull};
+
+use image::{ImageError, RgbaImage};
+use ktx2::{Format, ParseError};
+use objc2::{msg_send, rc::Retained, runtime::ProtocolObject};
+use objc2_metal::{
+    MTLBlitCommandEncoder, MTLCommandBuffer, MTLCommandEncoder, MTLCommandQueue, MTLDevice,
+    MTLGPUFamily, MTLOrigin, MTLPixelFormat, MTLRegion, MTLResourceOptions, MTLSize,
+    MTLStorageMode, MTLTexture, MTLTextureDescriptor, MTLTextureUsage,
+};
+
+use crate::{GpuQueue, LogLevel, MetalRenderer};
+
+// the largest 2d texture every mac gpu family supports
+pub(crate) const MAX_TEXTURE_SIZE: u32 = 16384;
+
+#[derive(Debug)]
+pub enum TextureError {
+    // the file couldn't be read or isn't a PNG or JPEG image
+    Decode(ImageError),
+    // the image is empty or larger than `MAX_TEXTURE_SIZE` in one of its dimensions
+    InvalidSize { width: u32, height: u32 },
+    Io(io::Error),
+    // the data isn't a well formed KTX2 container
+    Container(ParseError),
+    // a KTX2 feature the loader doesn't handle, like supercompression or cube maps
+    Unsupported(String),
+    // the gpu can't sample textures of this compressed format
+    UnsupportedByDevice(MTLPixelFormat),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Decode(error) => write!(f, "Failed to decode the image: {error}"),
+            TextureError::InvalidSize { width, height } => write!(
+                f,
+                "A {width}x{height} image can't be uploaded, textures are 1 to \
+                 {MAX_TEXTURE_SIZE} pixels wide and high"
+            ),
+            TextureError::Io(error) => write!(f, "Failed to read the texture: {error}"),
+            TextureError::Container(error) => write!(f, "Malformed KTX2 container: {error}"),
+            TextureError::Unsupported(feature) => write!(f, "Unsupported KTX2 texture: {feature}"),
+            TextureError::UnsupportedByDevice(pixel_format) => {
+                write!(f, "The GPU doesn't support {pixel_format:?} textures")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<ImageError> for TextureError {
+    fn from(error: ImageError) -> Self {
+        TextureError::Decode(error)
+    }
+}
+
+impl From<io::Error> for TextureError {
+    fn from(error: io::Error) -> Self {
+        TextureError::Io(error)
+    }
+}
+
+impl From<ParseError> for TextureError {
+    fn from(error: ParseError) -> Self {
+        TextureError::Container(error)
+    }
+}
+
+// the texture compression families, each one needs its own gpu support
+#[derive(Copy, Clone, PartialEq)]
+enum Compression {
+    None,
+    // desktop formats, supported by intel and apple silicon macs
+    Bc,
+    // mobile formats, supported by apple gpus only
+    Etc2,
+    Astc,
+}
+
+// how the texels of a pixel format are stored, uncompressed formats have 1x1 blocks
+struct BlockFormat {
+    pixel_format: MTLPixelFormat,
+    compression: Compression,
+    block_width: u32,
+    block_height: u32,
+    // in bytes
+    block_size: usize,
+}
+
+impl BlockFormat {
+    fn uncompressed(pixel_format: MTLPixelFormat, texel_size: usize) -> Option<Self> {
+        Some(Self {
+            pixel_format,
+            compression: Compression::None,
+            block_width: 1,
+            block_height: 1,
+            block_size: texel_size,
+        })
+    }
+
+    fn compressed(
+        pixel_format: MTLPixelFormat,
+        compression: Compression,
+        block_width: u32,
+        block_height: u32,
+    ) -> Option<Self> {
+        // all the supported block compressed formats but BC1, BC4 and ETC2 RGB use 16 bytes
+        let block_size = match pixel_format {
+            MTLPixelFormat::BC1_RGBA
+            | MTLPixelFormat::BC1_RGBA_sRGB
+            | MTLPixelFormat::BC4_RUnorm
+            | MTLPixelFormat::ETC2_RGB8
+            | MTLPixelFormat::ETC2_RGB8_sRGB => 8,
+            _ => 16,
+        };
+        Some(Self {
+            pixel_format,
+            compression,
+            block_width,
+            block_height,
+            block_size,
+        })
+    }
+
+    // the metal pixel format a vulkan format of a KTX2 container maps to
+    fn from_ktx2(format: Format) -> Option<Self> {
+        use Compression::{Astc, Bc, Etc2};
+        use MTLPixelFormat as Mtl;
+        match format {
+            Format::R8G8B8A8_UNORM => Self::uncompressed(Mtl::RGBA8Unorm, 4),
+            Format::R8G8B8A8_SRGB => Self::uncompressed(Mtl::RGBA8Unorm_sRGB, 4),
+            Format::BC1_RGBA_UNORM_BLOCK => Self::compressed(Mtl::BC1_RGBA, Bc, 4, 4),
+            Format::BC1_RGBA_SRGB_BLOCK => Self::compressed(Mtl::BC1_RGBA_sRGB, Bc, 4, 4),
+            Format::BC3_UNORM_BLOCK => Self::compressed(Mtl::BC3_RGBA, Bc, 4, 4),
+            Format::BC3_SRGB_BLOCK => Self::compressed(Mtl::BC3_RGBA_sRGB, Bc, 4, 4),
+            Format::BC4_UNORM_BLOCK => Self::compressed(Mtl::BC4_RUnorm, Bc, 4, 4),
+            Format::BC5_UNORM_BLOCK => Self::compressed(Mtl::BC5_RGUnorm, Bc, 4, 4),
+            Format::BC7_UNORM_BLOCK => Self::compressed(Mtl::BC7_RGBAUnorm, Bc, 4, 4),
+            Format::BC7_SRGB_BLOCK => Self::compressed(Mtl::BC7_RGBAUnorm_sRGB, Bc, 4, 4),
+            Format::ETC2_R8G8B8_UNORM_BLOCK => Self::compressed(Mtl::ETC2_RGB8, Etc2, 4, 4),
+            Format::ETC2_R8G8B8_SRGB_BLOCK => Self::compressed(Mtl::ETC2_RGB8_sRGB, Etc2, 4, 4),
+            Format::ETC2_R8G8B8A8_UNORM_BLOCK => Self::compressed(Mtl::EAC_RGBA8, Etc2, 4, 4),
+            Format::ETC2_R8G8B8A8_SRGB_BLOCK => Self::compressed(Mtl::EAC_RGBA8_sRGB, Etc2, 4, 4),
+            Format::ASTC_4x4_UNORM_BLOCK => Self::compressed(Mtl::ASTC_4x4_LDR, Astc, 4, 4),
+            Format::ASTC_4x4_SRGB_BLOCK => Self::compressed(Mtl::ASTC_4x4_sRGB, Astc, 4, 4),
+            Format::ASTC_5x5_UNORM_BLOCK => Self::compressed(Mtl::ASTC_5x5_LDR, Astc, 5, 5),
+            Format::ASTC_5x5_SRGB_BLOCK => Self::compressed(Mtl::ASTC_5x5_sRGB, Astc, 5, 5),
+            Format::ASTC_6x6_UNORM_BLOCK => Self::compressed(Mtl::ASTC_6x6_LDR, Astc, 6, 6),
+            Format::ASTC_6x6_SRGB_BLOCK => Self::compressed(Mtl::ASTC_6x6_sRGB, Astc, 6, 6),
+            Format::ASTC_8x8_UNORM_BLOCK => Self::compressed(Mtl::ASTC_8x8_LDR, Astc, 8, 8),
+            Format::ASTC_8x8_SRGB_BLOCK => Self::compressed(Mtl::ASTC_8x8_sRGB, Astc, 8, 8),
+            Format::ASTC_10x10_UNORM_BLOCK => Self::compressed(Mtl::ASTC_10x10_LDR, Astc, 10, 10),
+            Format::ASTC_10x10_SRGB_BLOCK => Self::compressed(Mtl::ASTC_10x10_sRGB, Astc, 10, 10),
+            Format::ASTC_12x12_UNORM_BLOCK => Self::compressed(Mtl::ASTC_12x12_LDR, Astc, 12, 12),
+            Format::ASTC_12x12_SRGB_BLOCK => Self::compressed(Mtl::ASTC_12x12_sRGB, Astc, 12, 12),
+            _ => None,
+        }
+    }
+
+    // the bytes per row and per image of a mip level
+    fn level_layout(&self, width: u32, height: u32) -> (usize, usize) {
+        let bytes_per_row = width.div_ceil(self.block_width) as usize * self.block_size;
+        (bytes_per_row, height.div_ceil(self.block_height) as usize * bytes_per_row)
+    }
+}
+
+impl MetalRenderer {
+    // decodes a PNG or JPEG file and uploads it with `create_texture`
+    pub fn load_texture(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        let image = image::open(path)?.into_rgba8();
+        self.create_texture(&image)
+    }
+
+    // uploads `image` to a texture with a full mip chain. the texels are sRGB encoded, so
+    // sampling returns linear colors like the shaders output. the mipmaps are generated on the
+    // gpu ahead of the frames committed after this call
+    pub fn create_texture(
+        &self,
+        image: &RgbaImage,
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        self.upload_image(image, MTLPixelFormat::RGBA8Unorm_sRGB)
+    }
+
+    // same as `create_texture` for data that isn't a color, like normal or roughness maps,
+    // sampled as it's stored
+    pub fn create_linear_texture(
+        &self,
+        image: &RgbaImage,
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        self.upload_image(image, MTLPixelFormat::RGBA8Unorm)
+    }
+
+    // uploads `image` like `create_texture`, copying it into gpu memory and generating its
+    // mipmaps on `queue` instead, so neither the cpu nor the frames wait for a large image.
+    // the texture is ready once the queue completed the returned value, see `GpuQueue`
+    pub fn upload_texture(
+        &self,
+        queue: &GpuQueue,
+        image: &RgbaImage,
+    ) -> Result<(Retained<ProtocolObject<dyn MTLTexture>>, u64), TextureError> {
+        let (width, height) = image.dimensions();
+        let pixel_format = MTLPixelFormat::RGBA8Unorm_sRGB;
+        let texture =
+            self.mipmapped_texture(width, height, pixel_format, Some(MTLStorageMode::Private))?;
+        // the gpu copies out of a staging buffer, which is released with the command buffer
+        let staging_buffer = unsafe {
+            self.device().newBufferWithBytes_length_options(
+                NonNull::from(image.as_raw().as_slice()).cast::<c_void>(),
+                image.as_raw().len(),
+                MTLResourceOptions::MTLResourceStorageModeShared,
+            )
+        }
+        .expect("Failed to create a staging buffer.");
+
+        let value = queue.submit(|command_buffer| {
+            let blit_encoder = command_buffer
+                .blitCommandEncoder()
+                .expect("Failed to create a blit encoder.");
+            let size = MTLSize {
+                width: width as usize,
+                height: height as usize,
+                depth: 1,
+            };
+            // the selector doesn't fit on a line, it's sent with its parts on their own lines
+            let () = unsafe {
+                msg_send![
+                    &blit_encoder,
+                    copyFromBuffer: &*staging_buffer,
+                    sourceOffset: 0usize,
+                    sourceBytesPerRow: width as usize * 4,
+                    sourceBytesPerImage: image.as_raw().len(),
+                    sourceSize: size,
+                    toTexture: &*texture,
+                    destinationSlice: 0usize,
+                    destinationLevel: 0usize,
+                    destinationOrigin: MTLOrigin { x: 0, y: 0, z: 0 }
+                ]
+            };
+            if texture.mipmapLevelCount() > 1 {
+                blit_encoder.generateMipmapsForTexture(&texture);
+            }
+            blit_encoder.endEncoding();
+        });
+
+        let message = format!(
+            "Uploading a {width}x{height} texture with {} mipmap levels.",
+            texture.mipmapLevelCount()
+        );
+        self.log(LogLevel::Debug, &message);
+        Ok((texture, value))
+    }
+
+    // an empty texture for an image of `width` by `height` with room for a full mip chain, in
+    // the storage mode metal picks for textures without `storage_mode`
+    fn mipmapped_texture(
+        &self,
+        width: u32,
+        height: u32,
+        pixel_format: MTLPixelFormat,
+        storage_mode: Option<MTLStorageMode>,
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        if !(1..=MAX_TEXTURE_SIZE).contains(&width) || !(1..=MAX_TEXTURE_SIZE).contains(&height) {
+            return Err(TextureError::InvalidSize { width, height });
+        }
+        let descriptor = unsafe {
+            MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                pixel_format,
+                width as usize,
+                height as usize,
+                true,
+            )
+        };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead);
+        if let Some(storage_mode) = storage_mode {
+            descriptor.setStorageMode(storage_mode);
+        }
+        Ok(self
+            .device()
+            .newTextureWithDescriptor(&descriptor)
+            .expect("Failed to create a texture."))
+    }
+
+    fn upload_image(
+        &self,
+        image: &RgbaImage,
+        pixel_format: MTLPixelFormat,
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        let (width, height) = image.dimensions();
+        let texture = self.mipmapped_texture(width, height, pixel_format, None)?;
+
+        let region = MTLRegion {
+            origin: MTLOrigin { x: 0, y: 0, z: 0 },
+            size: MTLSize {
+                width: width as usize,
+                height: height as usize,
+                depth: 1,
+            },
+        };
+        unsafe {
+            texture.replaceRegion_mipmapLevel_withBytes_bytesPerRow(
+                region,
+                0,
+                NonNull::from(image.as_raw().as_slice()).cast::<c_void>(),
+                width as usize * 4,
+            )
+        };
+
+        // downsample the base level into the smaller ones
+        let mipmap_level_count = texture.mipmapLevelCount();
+        if mipmap_level_count > 1 {
+            let command_queue = &self.command_queue();
+            let command_buffer = command_queue
+                .commandBuffer()
+                .expect("Failed to create a command buffer.");
+            let blit_encoder = command_buffer
+                .blitCommandEncoder()
+                .expect("Failed to create a blit encoder.");
+            blit_encoder.generateMipmapsForTexture(&texture);
+            blit_encoder.endEncoding();
+            command_buffer.commit();
+        }
+
+        let message =
+            format!("Created a {width}x{height} texture with {mipmap_level_count} mipmap levels.");
+        self.log(LogLevel::Debug, &message);
+        Ok(texture)
+    }
+
+    // a texture in gpu memory that kernels write and shaders sample, like the output of a
+    // `ComputePass`. its contents are undefined until a kernel wrote them
+    pub fn create_storage_texture(
+        &self,
+        width: usize,
+        height: usize,
+        pixel_format: MTLPixelFormat,
+    ) -> Retained<ProtocolObject<dyn MTLTexture>> {
+        let descriptor = unsafe {
+            MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                pixel_format,
+                width,
+                height,
+                false,
+            )
+        };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead | MTLTextureUsage::ShaderWrite);
+        descriptor.setStorageMode(MTLStorageMode::Private);
+        self.device()
+            .newTextureWithDescriptor(&descriptor)
+            .expect("Failed to create a storage texture.")
+    }
+
+    // reads a KTX2 file and uploads it with `create_ktx2_texture`
+    pub fn load_ktx2_texture(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        self.create_ktx2_texture(&fs::read(path)?)
+    }
+
+    // uploads a 2d texture in a KTX2 container with the mip chain it comes with. besides
+    // RGBA8 it takes the BC, ETC2 and LDR ASTC compressed formats, provided the gpu supports them
+    pub fn create_ktx2_texture(
+        &self,
+        data: &[u8],
+    ) -> Result<Retained<ProtocolObject<dyn MTLTexture>>, TextureError> {
+        let reader = ktx2::Reader::new(data)?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            return Err(TextureError::Unsupported(format!("{scheme:?} supercompression")));
+        }
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+            let message = "3d textures, texture arrays and cube maps".to_owned();
+            return Err(TextureError::Unsupported(message));
+        }
+        let format = header.format.ok_or_else(|| {
+            TextureError::Unsupported("undefined format, e.g. basis universal".to_owned())
+        })?;
+        let block_format = BlockFormat::from_ktx2(format)
+            .ok_or_else(|| TextureError::Unsupported(format!("{format:?} format")))?;
+
+        let device = self.device();
+        let supported = match block_format.compression {
+            Compression::None => true,
+            Compression::Bc => device.supportsBCTextureCompression(),
+            Compression::Etc2 | Compression::Astc => device.supportsFamily(MTLGPUFamily::Apple2),
+        };
+        if !supported {
+            return Err(TextureError::UnsupportedByDevice(block_format.pixel_format));
+        }
+
+        let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+        if width > MAX_TEXTURE_SIZE || height > MAX_TEXTURE_SIZE {
+            return Err(TextureError::InvalidSize { width, height });
+        }
+        let descriptor = unsafe {
+            MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+                block_format.pixel_format,
+                width as usize,
+                height as usize,
+                false,
+            )
+        };
+        // a level count of 0 asks the loader to generate the mipmaps, only the base level is used
+        let levels = reader.levels().len();
+        unsafe { descriptor.setMipmapLevelCount(levels) };
+        descriptor.setUsage(MTLTextureUsage::ShaderRead);
+        let texture = device
+            .newTextureWithDescriptor(&descriptor)
+            .expect("Failed to create a texture.");
+
+        for (level, level_data) in reader.levels().enumerate() {
+            let level_width = (width >> level).max(1);
+            let level_height = (height >> level).max(1);
+            let (bytes_per_row, bytes_per_image) =
+                block_format.level_layout(level_width, level_height);
+            if level_data.data.len() < bytes_per_image {
+                return Err(TextureError::Container(ParseError::UnexpectedEnd));
+            }
+            let region = MTLRegion {
+                origin: MTLOrigin { x: 0, y: 0, z: 0 },
+                size: MTLSize {
+                    width: level_width as usize,
+                    height: level_height as usize,
+                    depth: 1,
+                },
+            };
+            unsafe {
+                texture.replaceRegion_mipmapLevel_withBytes_bytesPerRow(
+                    region,
+                    level,
+                    NonNull::from(level_data.data).cast::<c_void>(),
+                    bytes_per_row,
+                )
+            };
+        }
+
+        let message = format!(
+            "Created a {width}x{height} {:?} texture with {levels} mipmap levels.",
+            block_format.pixel_format
+        );
+        self.log(LogLevel::Debug, &message);
+        Ok(texture)
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use objc2::{msg_send_id, rc::Retained, runtime::ProtocolObject, DeclaredClass};
+use objc2_foundation::{NSCopying, NSError, NSString};
+use objc2_metal::{
+    MTLBuffer, MTLDevice, MTLGPUFamily, MTLLibrary, MTLPipelineOption, MTLPixelFormat,
+    MTLRenderPassDescriptor, MTLRenderPipelineReflection, MTLRenderPipelineState,
+    MTLTileRenderPipelineDescriptor,
+};
+
+use crate::{LogLevel, MetalRenderer, PrimitiveType, RenderPass, RendererError};
+
+type PipelineState = Retained<ProtocolObject<dyn MTLRenderPipelineState>>;
+
+// the state a tile render pipeline is built from. the color format and the sample count have
+// to be the ones of the pass dispatching it
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TilePipelineDescriptor {
+    pub tile_function: String,
+    pub color_format: MTLPixelFormat,
+    pub sample_count: usize,
+}
+
+pub(crate) type TilePipelineStates = HashMap<TilePipelineDescriptor, PipelineState>;
+
+// the tiles of a pass with tile dispatches and the memory they get, zeros leave the defaults of
+// metal
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TileConfig {
+    // in pixels, 16 or 32 wide and 8, 16 or 32 high
+    pub tile_width: usize,
+    pub tile_height: usize,
+    // the bytes of an explicit imageblock per sample, on top of the color attachments
+    pub imageblock_sample_length: usize,
+    // the threadgroup memory of a tile, bound at index 0 of the tile functions
+    pub threadgroup_memory_length: usize,
+}
+
+impl TileConfig {
+    // a copy of `pass_descriptor` with the tiles set
+    pub(crate) fn pass_descriptor(
+        &self,
+        pass_descriptor: &MTLRenderPassDescriptor,
+    ) -> Retained<MTLRenderPassDescriptor> {
+        let descriptor = pass_descriptor.copy();
+        if self.tile_width > 0 {
+            descriptor.setTileWidth(self.tile_width);
+        }
+        if self.tile_height > 0 {
+            descriptor.setTileHeight(self.tile_height);
+        }
+        unsafe {
+            descriptor.setImageblockSampleLength(self.imageblock_sample_length);
+            descriptor.setThreadgroupMemoryLength(self.threadgroup_memory_length);
+        }
+        descriptor
+    }
+}
+
+impl MetalRenderer {
+    // whether the device runs tile functions within render passes, apple silicon gpus from
+    // the a11 on
+    pub fn supports_tile_shading(&self) -> bool {
+        self.device().supportsFamily(MTLGPUFamily::Apple4)
+    }
+
+    // a descriptor for `tile_function` with the color format and the sample count the view
+    // has now
+    pub fn tile_pipeline_descriptor(&self, tile_function: &str) -> TilePipelineDescriptor {
+        TilePipelineDescriptor {
+            tile_function: tile_function.to_owned(),
+            color_format: self.color_format(),
+            sample_count: self.sample_count(),
+        }
+    }
+
+    // returns the tile pipeline for `tile_function` with the formats of the view, it's created
+    // on first use. only on devices that `supports_tile_shading`
+    pub fn tile_pipeline_state(&self, tile_function: &str) -> PipelineState {
+        self.tile_pipeline_state_for(&self.tile_pipeline_descriptor(tile_function))
+            .expect("Failed to create a tile pipeline state.")
+    }
+
+    // the tile pipeline built from `descriptor`, cached like `render_pipeline_state_for`
+    pub fn tile_pipeline_state_for(
+        &self,
+        descriptor: &TilePipelineDescriptor,
+    ) -> Result<PipelineState, RendererError> {
+        if let Some(pipeline_state) = self.ivars().tile_pipeline_states.borrow().get(descriptor) {
+            return Ok(pipeline_state.clone());
+        }
+        if !self.supports_tile_shading() {
+            let message = "The device doesn't support tile shading".to_owned();
+            return Err(RendererError::PipelineCreation(message));
+        }
+        let pipeline_state = self.create_tile_pipeline_state(&*self.library()?, descriptor)?;
+        self.ivars()
+            .tile_pipeline_states
+            .borrow_mut()
+            .insert(descriptor.clone(), pipeline_state.clone());
+        Ok(pipeline_state)
+    }
+
+    fn create_tile_pipeline_state(
+        &self,
+        library: &ProtocolObject<dyn MTLLibrary>,
+        descriptor: &TilePipelineDescriptor,
+    ) -> Result<PipelineState, RendererError> {
+        let Some(tile_function) =
+            library.newFunctionWithName(&NSString::from_str(&descriptor.tile_function))
+        else {
+            let message = format!("Tile function {} not found", descriptor.tile_function);
+            return Err(RendererError::PipelineCreation(message));
+        };
+        let pipeline_descriptor = unsafe { MTLTileRenderPipelineDescriptor::new() };
+        unsafe {
+            pipeline_descriptor.setTileFunction(&tile_function);
+            pipeline_descriptor
+                .colorAttachments()
+                .objectAtIndexedSubscript(0)
+                .setPixelFormat(descriptor.color_format);
+            pipeline_descriptor.setRasterSampleCount(descriptor.sample_count);
+            // a thread for every pixel of the tile
+            pipeline_descriptor.setThreadgroupSizeMatchesTileSize(true);
+        }
+
+        // there's only the asynchronous variant in the bindings
+        let device = &self.device();
+        let mut reflection: Option<Retained<MTLRenderPipelineReflection>> = None;
+        let pipeline_state: Result<PipelineState, Retained<NSError>> = unsafe {
+            msg_send_id![
+                device,
+                newRenderPipelineStateWithTileDescriptor: &*pipeline_descriptor,
+                options: MTLPipelineOption::empty(),
+                reflection: &mut reflection,
+                error: _
+            ]
+        };
+        pipeline_state
+            .map_err(|error| {
+                RendererError::PipelineCreation(error.localizedDescription().to_string())
+            })
+            .inspect_err(|error| self.log(LogLevel::Error, &error.to_string()))
+    }
+}
+
+impl RenderPass {
+    // lays out the tiles of the pass for its tile dispatches
+    pub fn with_tile_config(&mut self, tile_config: TileConfig) -> &mut Self {
+        self.tile_config = Some(tile_config);
+        self
+    }
+
+    // runs the tile function of `pipeline_state` on every tile of the pass with a thread per
+    // pixel, in between the draws recorded before and after it. it works on what they left in
+    // tile memory, which the tile function gets as an imageblock, with the scene properties at
+    // index 0 and `buffer` at index 1
+    pub fn dispatch_tiles(
+        &mut self,
+        pipeline_state: &PipelineState,
+        buffer: &Retained<ProtocolObject<dyn MTLBuffer>>,
+    ) -> &mut Self {
+        self.draw(pipeline_state, buffer, PrimitiveType::Triangle, 0..0);
+        if let Some(item) = self.items.last_mut() {
+            item.tile_dispatch = true;
+        }
+        self
+    }
+}
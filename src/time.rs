@@ -0,0 +1,108 @@
+// the clock of the fixed updates. the frames come at whatever rate the display and the gpu
+// allow, the fixed updates run at a steady rate behind them, as many times per frame as the time
+// since the last frame makes up, so the simulation steps the same way at 60 or 120 hz
+use objc2::DeclaredClass;
+
+use crate::MetalRenderer;
+
+// the step of the fixed updates by default, in seconds
+pub const DEFAULT_FIXED_TIMESTEP: f32 = 1. / 60.;
+
+// the most fixed updates run for a frame. a frame coming late after a hitch or a pause skips the
+// rest rather than falling further behind with every slow frame
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+// how far the fixed updates got
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Time {
+    step: f32,
+    // the time of the frames the fixed updates haven't caught up with yet, less than a step
+    // after the updates of a frame
+    accumulator: f32,
+    ticks: u64,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            step: DEFAULT_FIXED_TIMESTEP,
+            accumulator: 0.,
+            ticks: 0,
+        }
+    }
+}
+
+impl Time {
+    // the seconds of a fixed update
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    // the fixed updates run so far
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    // the seconds the fixed updates simulated so far
+    pub fn elapsed(&self) -> f64 {
+        self.ticks as f64 * f64::from(self.step)
+    }
+
+    // how far the frame is between the last fixed update and the next one, from 0 to 1. the
+    // draws can blend the states of the last two updates by it to move smoothly between them
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.step
+    }
+
+    // adds the time of a frame and returns the number of fixed updates to run for it
+    fn advance(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed;
+        let steps = (self.accumulator / self.step) as u32;
+        self.accumulator -= steps as f32 * self.step;
+        if steps > MAX_STEPS_PER_FRAME {
+            self.accumulator = 0.;
+        }
+        steps.min(MAX_STEPS_PER_FRAME)
+    }
+}
+
+pub(crate) type FixedUpdateCallback = Box<dyn Fn(&MetalRenderer, f32)>;
+
+impl MetalRenderer {
+    // called every `fixed_timestep` seconds of the frames with the step, before the update
+    // callback of the frame. a frame can run it several times or not at all
+    pub fn set_fixed_update_callback(&self, fixed_update_callback: impl Fn(&Self, f32) + 'static) {
+        self.ivars()
+            .fixed_update_callback
+            .replace(Some(Box::new(fixed_update_callback)));
+    }
+
+    pub fn time(&self) -> Time {
+        self.ivars().time.get()
+    }
+
+    // the seconds of a fixed update, the time the updates already fell behind is kept
+    pub fn set_fixed_timestep(&self, fixed_timestep: f32) {
+        let mut time = self.ivars().time.get();
+        time.step = fixed_timestep.max(f32::EPSILON);
+        self.ivars().time.set(time);
+    }
+
+    // runs the fixed updates the `elapsed` seconds since the last frame make up
+    pub(crate) fn fixed_update(&self, elapsed: f32) {
+        let mut time = self.ivars().time.get();
+        let steps = time.advance(elapsed);
+        self.ivars().time.set(time);
+        for _ in 0..steps {
+            if let Some(fixed_update_callback) =
+                self.ivars().fixed_update_callback.borrow().as_ref()
+            {
+                fixed_update_callback(self, time.step);
+            }
+            // the callback sees the ticks of the updates before it
+            let mut time = self.ivars().time.get();
+            time.ticks += 1;
+            self.ivars().time.set(time);
+        }
+    }
+}
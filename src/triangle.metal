@@ -1,7 +1,37 @@
 #include <metal_stdlib>
+#include <metal_raytracing>
+
+// the directional light of the renderer, white and unlit without one
+struct LightProperties {
+    metal::float4x4 view_projection;
+    // the share of the light reaching the shadows in w
+    metal::float4 color;
+    // the direction the light travels in
+    metal::float4 direction;
+    // whether the shadow map is rendered
+    uint cast_shadows;
+    // the size in pixels, the columns and the rows of the tiles of `cull_lights`, 0 without
+    // local lights
+    uint light_tile_size;
+    uint light_tile_columns;
+    uint light_tile_rows;
+};
+
+// the frequency bands of the captured sound, `AUDIO_BANDS` in lib.rs
+constant uint AUDIO_BANDS = 8;
 
 struct SceneProperties {
+    metal::float4x4 view_projection;
     float time;
+    float point_size;
+    // how much brighter than SDR white the drawable can show, 1 unless it's HDR
+    float edr_headroom;
+    LightProperties light;
+    // the eye of perspective views, or the direction towards orthographic ones with a w of 0
+    metal::float4 view_origin;
+    // the levels of the frequency bands of the captured sound from the lowest to the highest,
+    // from 0 for silence to 1, all 0 without a capture
+    float audio_bands[AUDIO_BANDS];
 };
 
 struct VertexInput {
@@ -12,27 +42,1652 @@ struct VertexInput {
 struct VertexOutput {
     metal::float4 position [[position]];
     metal::float4 color;
+    float point_size [[point_size]];
 };
 
+// views a vertex at `position` in the world through the camera
+static VertexOutput view_vertex(
+    device const SceneProperties& properties,
+    metal::float3 position,
+    metal::float3 color
+) {
+    VertexOutput out;
+    out.position = properties.view_projection * metal::float4(position, 1);
+    out.color = metal::float4(color, 1);
+    out.point_size = properties.point_size;
+    return out;
+}
+
 vertex VertexOutput vertex_main(
     device const SceneProperties& properties [[buffer(0)]],
     device const VertexInput* vertices [[buffer(1)]],
     uint vertex_idx [[vertex_id]]
 ) {
-    VertexOutput out;
     VertexInput in = vertices[vertex_idx];
-    out.position =
-        metal::float4(
-            metal::float2x2(
-                metal::cos(properties.time), -metal::sin(properties.time),
-                metal::sin(properties.time),  metal::cos(properties.time)
-            ) * in.position.xy,
-            in.position.z,
-            1);
-    out.color = metal::float4(in.color, 1);
+    return view_vertex(properties, in.position, in.color);
+}
+
+struct VertexAttributes {
+    metal::float3 position [[attribute(0)]];
+    metal::float3 color [[attribute(1)]];
+};
+
+// like `vertex_main`, with the vertices fetched by the vertex layout of the pipeline, the one of
+// `VertexInput` in the buffer at index 1
+vertex VertexOutput vertex_attributes(
+    device const SceneProperties& properties [[buffer(0)]],
+    VertexAttributes in [[stage_in]]
+) {
+    return view_vertex(properties, in.position, in.color);
+}
+
+// like `vertex_main`, brightened past SDR white as far as the screen shows it
+vertex VertexOutput vertex_glow(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    return view_vertex(properties, in.position, in.color * properties.edr_headroom);
+}
+
+struct ShadowedOutput {
+    metal::float4 position [[position]];
+    metal::float4 color;
+    // the position in the clip space of the light
+    metal::float4 light_position;
+    float ambient [[flat]];
+    uint cast_shadows [[flat]];
+};
+
+// like `vertex_main`, lit by the directional light
+vertex ShadowedOutput vertex_shadowed(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    metal::float4 position = metal::float4(in.position, 1);
+    ShadowedOutput out;
+    out.position = properties.view_projection * position;
+    out.color = metal::float4(in.color * properties.light.color.rgb, 1);
+    out.light_position = properties.light.view_projection * position;
+    out.ambient = properties.light.color.a;
+    out.cast_shadows = properties.light.cast_shadows;
+    return out;
+}
+
+// the share of the light reaching a fragment at `light_position` in the clip space of the
+// light, 1 without shadows. the comparisons of 3 by 3 texels around the fragment are averaged to
+// soften the edges
+static float light_reaching(
+    metal::float4 light_position,
+    uint cast_shadows,
+    metal::depth2d<float> shadow_map,
+    metal::sampler shadow_sampler
+) {
+    if (!cast_shadows) {
+        return 1;
+    }
+    metal::float3 position = light_position.xyz / light_position.w;
+    metal::float2 uv = metal::float2(position.x * 0.5 + 0.5, 0.5 - position.y * 0.5);
+    metal::float2 texel = 1 / metal::float2(shadow_map.get_width(), shadow_map.get_height());
+    // keeps the surfaces from shadowing themselves
+    float depth = position.z - 0.002;
+    float lit = 0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            metal::float2 offset = metal::float2(x, y) * texel;
+            lit += shadow_map.sample_compare(shadow_sampler, uv + offset, depth);
+        }
+    }
+    return lit / 9;
+}
+
+// darkens the fragments the shadow map hides from the light down to the ambient share
+fragment metal::float4 fragment_shadowed(
+    ShadowedOutput in [[stage_in]],
+    metal::depth2d<float> shadow_map [[texture(8)]],
+    metal::sampler shadow_sampler [[sampler(8)]]
+) {
+    float lit = light_reaching(in.light_position, in.cast_shadows, shadow_map, shadow_sampler);
+    return metal::float4(in.color.rgb * metal::mix(in.ambient, 1.0, lit), in.color.a);
+}
+
+// the lines of a `DebugDraw`, pulled slightly towards the camera so that lines lying on a
+// surface, like the edges of its bounds, win the depth test against it
+vertex VertexOutput vertex_debug(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    VertexOutput out = view_vertex(properties, in.position, in.color);
+    out.position.z -= 1e-4 * out.position.w;
+    return out;
+}
+
+// spins the vertices about the origin, one radian per second of the scene time
+vertex VertexOutput vertex_spinning(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    metal::float2 position =
+        metal::float2x2(
+            metal::cos(properties.time), metal::sin(properties.time),
+            -metal::sin(properties.time), metal::cos(properties.time)
+        ) * in.position.xy;
+    return view_vertex(properties, metal::float3(position, in.position.z), in.color);
+}
+
+struct InstanceData {
+    metal::float4x4 transform;
+    metal::float4 color;
+};
+
+// places every instance of the vertices with its own transform, tinted by its color
+vertex VertexOutput vertex_instanced(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const InstanceData* instances [[buffer(3)]],
+    uint vertex_idx [[vertex_id]],
+    uint instance_idx [[instance_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    InstanceData instance = instances[instance_idx];
+    metal::float4 position = instance.transform * metal::float4(in.position, 1);
+    VertexOutput out = view_vertex(properties, position.xyz / position.w, in.color);
+    out.color *= instance.color;
+    return out;
+}
+
+struct ViewportOutput {
+    metal::float4 position [[position]];
+    metal::float4 color;
+    float point_size [[point_size]];
+    uint viewport [[viewport_array_index]];
+};
+
+// like `vertex_instanced`, every instance drawn into the viewport of the pass of its index
+vertex ViewportOutput vertex_viewports(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const InstanceData* instances [[buffer(3)]],
+    uint vertex_idx [[vertex_id]],
+    uint instance_idx [[instance_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    InstanceData instance = instances[instance_idx];
+    metal::float4 position = instance.transform * metal::float4(in.position, 1);
+    VertexOutput viewed = view_vertex(properties, position.xyz / position.w, in.color);
+    ViewportOutput out;
+    out.position = viewed.position;
+    out.color = viewed.color * instance.color;
+    out.point_size = viewed.point_size;
+    out.viewport = instance_idx;
+    return out;
+}
+
+// the placement of a draw, bound through a single argument buffer rather than one buffer each
+struct TransformArguments {
+    metal::float4x4 transform [[id(0)]];
+    metal::float4 color [[id(1)]];
+};
+
+// places the vertices with the transform of its argument table, tinted by its color
+vertex VertexOutput vertex_transformed(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    constant TransformArguments& arguments [[buffer(4)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    metal::float4 position = arguments.transform * metal::float4(in.position, 1);
+    VertexOutput out = view_vertex(properties, position.xyz / position.w, in.color);
+    out.color *= arguments.color;
     return out;
 }
 
 fragment metal::float4 fragment_main(VertexOutput in [[stage_in]]) {
     return in.color;
 }
+
+// like `fragment_main`, multiplied with the color of a buffer the draw binds by its name
+fragment metal::float4 fragment_tinted(
+    VertexOutput in [[stage_in]],
+    constant metal::float4& tint [[buffer(0)]]
+) {
+    return in.color * tint;
+}
+
+
+struct TexturedOutput {
+    metal::float4 position [[position]];
+    metal::float4 color;
+    metal::float2 uv;
+};
+
+// maps a texture onto a quad drawn as a 4 vertex triangle strip, with the corners in the order
+// bottom left, bottom right, top left, top right
+vertex TexturedOutput vertex_quad(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    VertexOutput viewed = view_vertex(properties, in.position, in.color);
+    TexturedOutput out;
+    out.position = viewed.position;
+    out.color = viewed.color;
+    // the first row of a texture is its top
+    out.uv = metal::float2(vertex_idx & 1, 1 - (vertex_idx >> 1));
+    return out;
+}
+
+// like `vertex_main`, with the texture coordinates in a buffer of their own
+vertex TexturedOutput vertex_textured_mesh(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const metal::packed_float2* texture_coordinates [[buffer(2)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    VertexOutput viewed = view_vertex(properties, in.position, in.color);
+    TexturedOutput out;
+    out.position = viewed.position;
+    out.color = viewed.color;
+    out.uv = texture_coordinates[vertex_idx];
+    return out;
+}
+
+struct TextureArguments {
+    metal::texture2d<float> texture [[id(0)]];
+    metal::sampler sampler [[id(1)]];
+};
+
+fragment metal::float4 fragment_textured(
+    TexturedOutput in [[stage_in]],
+    constant TextureArguments& arguments [[buffer(0)]]
+) {
+    return arguments.texture.sample(arguments.sampler, in.uv) * in.color;
+}
+
+// the frames of a `VideoPlayer`, bound by the name of the texture
+fragment metal::float4 fragment_video(
+    TexturedOutput in [[stage_in]],
+    metal::texture2d<float> video [[texture(0)]]
+) {
+    constexpr metal::sampler sampler(metal::filter::linear, metal::address::clamp_to_edge);
+    return video.sample(sampler, in.uv) * in.color;
+}
+
+// the sprites of a `SpriteBatch`, which aren't blended: texels less than half opaque are
+// discarded so that overlapping sprites can be drawn in any order
+fragment metal::float4 fragment_sprite(
+    TexturedOutput in [[stage_in]],
+    constant TextureArguments& arguments [[buffer(0)]]
+) {
+    metal::float4 color = arguments.texture.sample(arguments.sampler, in.uv) * in.color;
+    if (color.a < 0.5) {
+        metal::discard_fragment();
+    }
+    return color;
+}
+
+// resources of a material, bound through a single argument buffer
+struct MaterialArguments {
+    metal::texture2d<float> texture [[id(0)]];
+    metal::sampler sampler [[id(1)]];
+    device const metal::float4* tint [[id(2)]];
+};
+
+fragment metal::float4 fragment_material(
+    VertexOutput in [[stage_in]],
+    constant MaterialArguments& material [[buffer(0)]]
+) {
+    // tile the texture over the screen, one texel every 32 pixels
+    metal::float4 texel = material.texture.sample(material.sampler, in.position.xy / 32.0);
+    return texel * *material.tint * in.color;
+}
+
+// how far the boundary of the gradient sways, can be defined through the shader options
+#ifndef GRADIENT_SWAY_AMOUNT
+#define GRADIENT_SWAY_AMOUNT 0.25
+#endif
+
+struct GradientProperties {
+    metal::packed_float3 top;
+    metal::packed_float3 bottom;
+    float speed;
+};
+
+struct FullscreenOutput {
+    metal::float4 position [[position]];
+    metal::float2 uv;
+};
+
+// a triangle covering the whole viewport, drawn with 3 vertices and no vertex buffer
+vertex FullscreenOutput vertex_fullscreen(uint vertex_idx [[vertex_id]]) {
+    metal::float2 uv = metal::float2((vertex_idx << 1) & 2, vertex_idx & 2);
+    FullscreenOutput out;
+    out.position = metal::float4(uv * 2 - 1, 0, 1);
+    out.uv = uv;
+    return out;
+}
+
+fragment metal::float4 fragment_gradient(
+    FullscreenOutput in [[stage_in]],
+    constant SceneProperties& properties [[buffer(0)]],
+    constant GradientProperties& gradient [[buffer(1)]]
+) {
+    // the boundary between the colors sways over time, waving across the view, and the bass of
+    // the captured sound makes it swing wider and the colors brighter
+    float bass = (properties.audio_bands[0] + properties.audio_bands[1]) / 2;
+    float sway = GRADIENT_SWAY_AMOUNT * (1 + 2 * bass)
+        * metal::sin(properties.time * gradient.speed + in.uv.x * M_PI_F);
+    float blend = metal::saturate(in.uv.y + sway);
+    return metal::float4(metal::mix(gradient.bottom, gradient.top, blend) * (1 + bass), 1);
+}
+
+// the rays through the center of the view and how they change across it, the camera's rays
+struct SkyboxProperties {
+    metal::float4 direction;
+    metal::float4 direction_x;
+    metal::float4 direction_y;
+};
+
+// `vertex_fullscreen` at the far plane, where the depth test keeps what the draws covered
+vertex FullscreenOutput vertex_skybox(uint vertex_idx [[vertex_id]]) {
+    metal::float2 uv = metal::float2((vertex_idx << 1) & 2, vertex_idx & 2);
+    FullscreenOutput out;
+    out.position = metal::float4(uv * 2 - 1, 1, 1);
+    out.uv = uv;
+    return out;
+}
+
+fragment metal::float4 fragment_skybox(
+    FullscreenOutput in [[stage_in]],
+    constant SkyboxProperties& skybox [[buffer(0)]],
+    metal::texturecube<float> environment [[texture(0)]]
+) {
+    constexpr metal::sampler environment_sampler(metal::filter::linear);
+    metal::float2 position = in.uv * 2 - 1;
+    metal::float3 direction = skybox.direction.xyz + skybox.direction_x.xyz * position.x
+        + skybox.direction_y.xyz * position.y;
+    return metal::float4(environment.sample(environment_sampler, direction).rgb, 1);
+}
+
+// the texel of an equirectangular image in the direction of a texel of a cubemap face. the
+// faces are in the order of metal's slices, +x, -x, +y, -y, +z and -z, their v grows downwards
+kernel void equirectangular_to_cubemap(
+    metal::texture2d<float, metal::access::read> equirectangular [[texture(0)]],
+    metal::texturecube<half, metal::access::write> cubemap [[texture(1)]],
+    metal::uint3 gid [[thread_position_in_grid]]
+) {
+    uint size = cubemap.get_width();
+    metal::float2 uv = (metal::float2(gid.xy) + 0.5) / size * 2 - 1;
+    metal::float3 direction;
+    switch (gid.z) {
+        case 0: direction = metal::float3(1, -uv.y, -uv.x); break;
+        case 1: direction = metal::float3(-1, -uv.y, uv.x); break;
+        case 2: direction = metal::float3(uv.x, 1, uv.y); break;
+        case 3: direction = metal::float3(uv.x, -1, -uv.y); break;
+        case 4: direction = metal::float3(uv.x, -uv.y, 1); break;
+        default: direction = metal::float3(-uv.x, -uv.y, -1); break;
+    }
+    direction = metal::normalize(direction);
+
+    // the longitude grows to the right from the negative z axis, the latitude from the top
+    int width = equirectangular.get_width();
+    int height = equirectangular.get_height();
+    float longitude = metal::atan2(direction.x, -direction.z) / (2 * M_PI_F) + 0.5;
+    float latitude = metal::acos(metal::clamp(direction.y, -1.0, 1.0)) / M_PI_F;
+    metal::float2 texel = metal::float2(longitude, latitude) * metal::float2(width, height) - 0.5;
+
+    // bilinear, wrapping around the longitude and clamped at the poles
+    metal::float2 base = metal::floor(texel);
+    metal::float2 weight = texel - base;
+    int x0 = (int(base.x) % width + width) % width;
+    int x1 = (x0 + 1) % width;
+    int y0 = metal::clamp(int(base.y), 0, height - 1);
+    int y1 = metal::clamp(int(base.y) + 1, 0, height - 1);
+    metal::float4 top = metal::mix(
+        equirectangular.read(metal::uint2(x0, y0)),
+        equirectangular.read(metal::uint2(x1, y0)),
+        weight.x
+    );
+    metal::float4 bottom = metal::mix(
+        equirectangular.read(metal::uint2(x0, y1)),
+        equirectangular.read(metal::uint2(x1, y1)),
+        weight.x
+    );
+    cubemap.write(metal::half4(metal::mix(top, bottom, weight.y)), gid.xy, gid.z);
+}
+
+// lays a line of vertices out on a wave rolling along the bottom of the view, one per thread.
+// the line spans the view however many vertices it has
+kernel void compute_wave(
+    device const SceneProperties& properties [[buffer(0)]],
+    device VertexInput* vertices [[buffer(1)]],
+    uint index [[thread_position_in_grid]],
+    uint count [[threads_per_grid]]
+) {
+    float x = count > 1 ? float(index) / float(count - 1) * 2 - 1 : 0;
+    float y = 0.1 * metal::sin(x * 2 * M_PI_F + properties.time * 2) - 0.85;
+    vertices[index].position = metal::packed_float3(x, y, 0);
+    vertices[index].color = metal::packed_float3(0.5 + 0.5 * x, 0.8, 0.5 - 0.5 * x);
+}
+
+// the brightness of a pixel of a camera frame, the pixels past the edges repeat the ones on them
+static float frame_luminance(metal::texture2d<float, metal::access::read> frame, int2 position) {
+    int2 last = int2(frame.get_width(), frame.get_height()) - 1;
+    float3 color = frame.read(uint2(metal::clamp(position, int2(0), last))).rgb;
+    return metal::dot(color, float3(0.2126, 0.7152, 0.0722));
+}
+
+// outlines the edges of a camera frame with a sobel filter over a dimmed gray copy of it, one
+// pixel per thread
+kernel void camera_edges(
+    metal::texture2d<float, metal::access::read> frame [[texture(0)]],
+    metal::texture2d<float, metal::access::write> output [[texture(1)]],
+    uint2 gid [[thread_position_in_grid]]
+) {
+    int2 position = int2(gid);
+    float samples[3][3];
+    for (int y = 0; y < 3; y++) {
+        for (int x = 0; x < 3; x++) {
+            samples[y][x] = frame_luminance(frame, position + int2(x - 1, y - 1));
+        }
+    }
+    float horizontal = samples[0][2] + 2 * samples[1][2] + samples[2][2]
+        - samples[0][0] - 2 * samples[1][0] - samples[2][0];
+    float vertical = samples[2][0] + 2 * samples[2][1] + samples[2][2]
+        - samples[0][0] - 2 * samples[0][1] - samples[0][2];
+    float edge = metal::saturate(metal::length(float2(horizontal, vertical)));
+    float gray = samples[1][1] * 0.4;
+    output.write(float4(metal::mix(float3(gray), float3(1, 0.8, 0.2), edge), 1), gid);
+}
+
+// the arguments of the image filters of `apply_compute_filters`
+struct FilterProperties {
+    int2 direction;
+    float sigma;
+    float level;
+};
+
+// one direction of a separable gaussian blur, the taps reach three standard deviations out and
+// repeat the pixels on the edges past them
+kernel void filter_gaussian_blur(
+    metal::texture2d<float, metal::access::read> input [[texture(0)]],
+    metal::texture2d<float, metal::access::write> output [[texture(1)]],
+    constant FilterProperties &properties [[buffer(0)]],
+    uint2 gid [[thread_position_in_grid]]
+) {
+    int2 last = int2(input.get_width(), input.get_height()) - 1;
+    int radius = int(metal::ceil(properties.sigma * 3));
+    float4 sum = 0;
+    float weights = 0;
+    for (int tap = -radius; tap <= radius; tap++) {
+        float weight = radius > 0
+            ? metal::exp(-float(tap * tap) / (2 * properties.sigma * properties.sigma))
+            : 1;
+        int2 position = metal::clamp(int2(gid) + properties.direction * tap, int2(0), last);
+        sum += input.read(uint2(position)) * weight;
+        weights += weight;
+    }
+    output.write(sum / weights, gid);
+}
+
+// the gradient of the luminance with a sobel operator, as a gray level
+kernel void filter_sobel(
+    metal::texture2d<float, metal::access::read> input [[texture(0)]],
+    metal::texture2d<float, metal::access::write> output [[texture(1)]],
+    uint2 gid [[thread_position_in_grid]]
+) {
+    int2 position = int2(gid);
+    float samples[3][3];
+    for (int y = 0; y < 3; y++) {
+        for (int x = 0; x < 3; x++) {
+            samples[y][x] = frame_luminance(input, position + int2(x - 1, y - 1));
+        }
+    }
+    float horizontal = samples[0][2] + 2 * samples[1][2] + samples[2][2]
+        - samples[0][0] - 2 * samples[1][0] - samples[2][0];
+    float vertical = samples[2][0] + 2 * samples[2][1] + samples[2][2]
+        - samples[0][0] - 2 * samples[0][1] - samples[0][2];
+    float edge = metal::length(float2(horizontal, vertical));
+    output.write(float4(float3(edge), 1), gid);
+}
+
+// white where the luminance reaches the level, black elsewhere
+kernel void filter_threshold(
+    metal::texture2d<float, metal::access::read> input [[texture(0)]],
+    metal::texture2d<float, metal::access::write> output [[texture(1)]],
+    constant FilterProperties &properties [[buffer(0)]],
+    uint2 gid [[thread_position_in_grid]]
+) {
+    float4 color = input.read(gid);
+    float luminance = metal::dot(color.rgb, float3(0.2126, 0.7152, 0.0722));
+    output.write(float4(float3(metal::step(properties.level, luminance)), color.a), gid);
+}
+
+struct Particle {
+    metal::packed_float3 position;
+    metal::packed_float3 velocity;
+    float age;
+    float lifetime;
+};
+
+struct ParticleProperties {
+    metal::packed_float3 emitter;
+    float delta_time;
+    float gravity;
+};
+
+// a pseudo random number between 0 and 1 from a seed, a PCG hash
+static float random(uint seed) {
+    uint state = seed * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return float((word >> 22u) ^ word) / 4294967295.0;
+}
+
+// moves a particle of a fountain and writes its vertex, one particle per thread. particles that
+// lived their lifetime are emitted again with a new random velocity upwards
+kernel void compute_particles(
+    device const SceneProperties& properties [[buffer(0)]],
+    device VertexInput* vertices [[buffer(1)]],
+    device Particle* particles [[buffer(2)]],
+    constant ParticleProperties& settings [[buffer(3)]],
+    uint index [[thread_position_in_grid]]
+) {
+    Particle particle = particles[index];
+    particle.age += settings.delta_time;
+    if (particle.age >= particle.lifetime) {
+        uint seed = index * 3 + metal::as_type<uint>(properties.time);
+        float angle = random(seed) * 2 * M_PI_F;
+        float spread = random(seed + 1) * 0.3;
+        float speed = 0.8 + random(seed + 2) * 0.4;
+        particle.velocity = metal::packed_float3(
+            metal::cos(angle) * spread,
+            1,
+            metal::sin(angle) * spread
+        ) * speed;
+        // the buffer starts out zeroed, spread the first particles over their lifetimes so
+        // they don't all leave at once
+        bool first = particle.lifetime == 0;
+        particle.lifetime = 1 + random(seed + 3) * 1.5;
+        particle.age = first ? random(seed + 4) * particle.lifetime : 0;
+        particle.position = settings.emitter + particle.velocity * particle.age;
+        particle.position.y -= 0.5 * settings.gravity * particle.age * particle.age;
+        particle.velocity.y -= settings.gravity * particle.age;
+    } else {
+        particle.velocity.y -= settings.gravity * settings.delta_time;
+        particle.position += particle.velocity * settings.delta_time;
+    }
+    particles[index] = particle;
+
+    // from yellow to a faded red over the life of the particle
+    float life = particle.age / particle.lifetime;
+    vertices[index].position = particle.position;
+    vertices[index].color =
+        metal::mix(metal::float3(1, 0.9, 0.3), metal::float3(0.4, 0.05, 0), life);
+}
+
+// the box of a mesh in the space of its vertices, and the draws of its instances
+struct CullingProperties {
+    metal::packed_float3 bounds_min;
+    uint instance_count;
+    metal::packed_float3 bounds_max;
+    uint index_count;
+};
+
+struct IndirectArguments {
+    metal::command_buffer commands [[id(0)]];
+};
+
+// encodes a draw of an instance of a mesh into the indirect command buffer when its box is in
+// view, one instance per thread. the box is outside when all its corners lie beyond the same
+// plane of the clip volume
+kernel void cull_instances(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const InstanceData* instances [[buffer(1)]],
+    constant CullingProperties& culling [[buffer(2)]],
+    device const IndirectArguments& arguments [[buffer(3)]],
+    device const uint* indices [[buffer(4)]],
+    uint index [[thread_position_in_grid]]
+) {
+    if (index >= culling.instance_count) {
+        return;
+    }
+    metal::render_command command(arguments.commands, index);
+    metal::float4x4 transform = properties.view_projection * instances[index].transform;
+    uint outside = 0x3f;
+    for (uint corner = 0; corner < 8; corner++) {
+        metal::float3 position = metal::select(
+            metal::float3(culling.bounds_min),
+            metal::float3(culling.bounds_max),
+            metal::bool3(corner & 1, corner & 2, corner & 4)
+        );
+        metal::float4 clip = transform * metal::float4(position, 1);
+        outside &= uint(clip.x < -clip.w) | uint(clip.x > clip.w) << 1
+            | uint(clip.y < -clip.w) << 2 | uint(clip.y > clip.w) << 3
+            | uint(clip.z < 0) << 4 | uint(clip.z > clip.w) << 5;
+    }
+    if (outside != 0) {
+        command.reset();
+        return;
+    }
+    // the instance id of the vertex function counts from the base instance
+    command.draw_indexed_primitives(
+        metal::primitive_type::triangle,
+        culling.index_count,
+        indices,
+        1,
+        0,
+        index
+    );
+}
+
+// the color attachment of a pass as its tile functions see it
+struct TileColor {
+    half4 color [[color(0)]];
+};
+
+// darkens every other row of pixels of what the pass drew so far by `strength`, in tile
+// memory in the middle of the pass. the rows scroll down over time
+kernel void tile_scanlines(
+    metal::imageblock<TileColor, metal::imageblock_layout_implicit> block,
+    device const SceneProperties& properties [[buffer(0)]],
+    constant float& strength [[buffer(1)]],
+    ushort2 position [[thread_position_in_threadgroup]],
+    uint2 pixel [[thread_position_in_grid]]
+) {
+    uint row = pixel.y + uint(properties.time * 30);
+    if (row % 2 == 0) {
+        return;
+    }
+    // the samples of multisampled targets one by one
+    for (ushort sample = 0; sample < block.get_num_samples(); sample++) {
+        TileColor tile = block.read(position, sample, metal::imageblock_data_rate::sample);
+        tile.color.rgb *= half(1 - strength);
+        block.write(tile, position, sample, metal::imageblock_data_rate::sample);
+    }
+}
+
+struct OverlayQuad {
+    // the top left corner in pixels from the top left of the drawable
+    metal::packed_float2 position;
+    metal::packed_float2 size;
+    metal::packed_float4 color;
+};
+
+struct OverlayOutput {
+    metal::float4 position [[position]];
+    metal::float4 color;
+};
+
+// places the corners of a quad per instance in pixels, drawn as a triangle strip
+vertex OverlayOutput vertex_overlay(
+    device const OverlayQuad* quads [[buffer(0)]],
+    constant metal::float2& drawable_size [[buffer(1)]],
+    uint vertex_idx [[vertex_id]],
+    uint instance_idx [[instance_id]]
+) {
+    OverlayQuad quad = quads[instance_idx];
+    metal::float2 corner = metal::float2(vertex_idx & 1, vertex_idx >> 1);
+    metal::float2 pixel = quad.position + corner * quad.size;
+    // pixels grow down from the top left, clip space up from the center
+    metal::float2 clip = pixel / drawable_size * metal::float2(2, -2) + metal::float2(-1, 1);
+    OverlayOutput out;
+    out.position = metal::float4(clip, 0, 1);
+    out.color = quad.color;
+    return out;
+}
+
+fragment metal::float4 fragment_overlay(OverlayOutput in [[stage_in]]) {
+    return in.color;
+}
+
+// a glyph of a text, drawn as an instanced quad
+struct TextQuad {
+    // the top left corner in points from the top left of the view
+    metal::packed_float2 position;
+    metal::packed_float2 size;
+    // the glyph in the atlas
+    metal::packed_float2 uv_origin;
+    metal::packed_float2 uv_size;
+    // premultiplied by alpha
+    metal::packed_float4 color;
+};
+
+struct TextOutput {
+    metal::float4 position [[position]];
+    metal::float2 uv;
+    metal::float4 color;
+};
+
+vertex TextOutput vertex_text(
+    device const TextQuad* quads [[buffer(0)]],
+    constant metal::float2& view_size [[buffer(1)]],
+    uint vertex_idx [[vertex_id]],
+    uint instance_idx [[instance_id]]
+) {
+    TextQuad quad = quads[instance_idx];
+    metal::float2 corner = metal::float2(vertex_idx & 1, vertex_idx >> 1);
+    metal::float2 point = quad.position + corner * quad.size;
+    metal::float2 clip = point / view_size * metal::float2(2, -2) + metal::float2(-1, 1);
+    TextOutput out;
+    out.position = metal::float4(clip, 0, 1);
+    out.uv = quad.uv_origin + corner * quad.uv_size;
+    out.color = quad.color;
+    return out;
+}
+
+// the atlas stores the coverage of the glyphs
+fragment metal::float4 fragment_text(
+    TextOutput in [[stage_in]],
+    metal::texture2d<float> atlas [[texture(0)]]
+) {
+    constexpr metal::sampler atlas_sampler(metal::filter::linear);
+    return in.color * atlas.sample(atlas_sampler, in.uv).r;
+}
+
+// a vertex of the ui libraries, egui and imgui
+struct UiVertex {
+    // in points from the top left corner of the view
+    metal::packed_float2 position;
+    metal::packed_float2 uv;
+    // sRGB encoded, egui premultiplies alpha and imgui doesn't
+    metal::uchar4 color;
+};
+
+struct UiOutput {
+    metal::float4 position [[position]];
+    metal::float2 uv;
+    metal::float4 color;
+};
+
+vertex UiOutput vertex_ui(
+    device const UiVertex* vertices [[buffer(0)]],
+    constant metal::float2& screen_size [[buffer(1)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    UiVertex in = vertices[vertex_idx];
+    UiOutput out;
+    metal::float2 clip = in.position / screen_size * metal::float2(2, -2) + metal::float2(-1, 1);
+    out.position = metal::float4(clip, 0, 1);
+    out.uv = in.uv;
+    out.color = metal::float4(in.color) / 255;
+    return out;
+}
+
+// blends like egui in sRGB, drawables storing linear colors get them decoded
+fragment metal::float4 fragment_egui(
+    UiOutput in [[stage_in]],
+    constant uint& linear_output [[buffer(0)]],
+    metal::texture2d<float> texture [[texture(0)]],
+    metal::sampler texture_sampler [[sampler(0)]]
+) {
+    metal::float4 color = in.color * texture.sample(texture_sampler, in.uv);
+    if (linear_output) {
+        metal::float3 low = color.rgb / 12.92;
+        metal::float3 high = metal::pow((color.rgb + 0.055) / 1.055, 2.4);
+        color.rgb = metal::select(high, low, color.rgb <= 0.04045);
+    }
+    return color;
+}
+
+// like fragment_egui for imgui's colors, which aren't premultiplied
+fragment metal::float4 fragment_imgui(
+    UiOutput in [[stage_in]],
+    constant uint& linear_output [[buffer(0)]],
+    metal::texture2d<float> texture [[texture(0)]],
+    metal::sampler texture_sampler [[sampler(0)]]
+) {
+    metal::float4 color = in.color * texture.sample(texture_sampler, in.uv);
+    if (linear_output) {
+        metal::float3 low = color.rgb / 12.92;
+        metal::float3 high = metal::pow((color.rgb + 0.055) / 1.055, 2.4);
+        color.rgb = metal::select(high, low, color.rgb <= 0.04045);
+    }
+    return metal::float4(color.rgb * color.a, color.a);
+}
+
+// the lengths of the arrays of a `BindlessTable`
+#define BINDLESS_TEXTURE_CAPACITY 128
+#define BINDLESS_BUFFER_CAPACITY 64
+
+// the resources of a `BindlessTable`, picked by index. the entries have no ids, so they're laid
+// out one after the other the way metal 3 devices get them written
+struct BindlessResources {
+    metal::array<metal::texture2d<float>, BINDLESS_TEXTURE_CAPACITY> textures;
+    device const float* buffers[BINDLESS_BUFFER_CAPACITY];
+};
+
+struct BindlessOutput {
+    metal::float4 position [[position]];
+    metal::float4 color;
+    metal::float2 uv;
+    uint texture_index [[flat]];
+};
+
+// places every instance of a quad like `vertex_quad` with its `InstanceData`, instance i shows
+// texture i of the bindless table
+vertex BindlessOutput vertex_bindless(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const InstanceData* instances [[buffer(3)]],
+    uint vertex_idx [[vertex_id]],
+    uint instance_idx [[instance_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    InstanceData instance = instances[instance_idx];
+    metal::float4 position = instance.transform * metal::float4(in.position, 1);
+    VertexOutput viewed = view_vertex(properties, position.xyz / position.w, in.color);
+    BindlessOutput out;
+    out.position = viewed.position;
+    out.color = viewed.color * instance.color;
+    out.uv = metal::float2(vertex_idx & 1, 1 - (vertex_idx >> 1));
+    out.texture_index = instance_idx;
+    return out;
+}
+
+fragment metal::float4 fragment_bindless(
+    BindlessOutput in [[stage_in]],
+    constant BindlessResources& resources [[buffer(5)]]
+) {
+    constexpr metal::sampler sampler(metal::filter::linear, metal::address::clamp_to_edge);
+    return resources.textures[in.texture_index].sample(sampler, in.uv) * in.color;
+}
+
+// the object and mesh functions need metal 3, the library still builds without them for the
+// language versions before it and `draw_geometry_meshlets` falls back to `draw_geometry`
+#if __METAL_VERSION__ >= 300
+
+// the triangles of a meshlet, the threads of a mesh threadgroup of `mesh_spinning`
+#define MESHLET_TRIANGLE_COUNT 32
+
+struct MeshletPayload {
+    uint triangle_count;
+};
+
+struct MeshletVertex {
+    metal::float4 position [[position]];
+    metal::float4 color;
+};
+
+using Meshlet = metal::mesh<
+    MeshletVertex, void, MESHLET_TRIANGLE_COUNT * 3, MESHLET_TRIANGLE_COUNT,
+    metal::topology::triangle
+>;
+
+// splits a triangle list into meshlets, it gets a thread per triangle and launches a mesh
+// threadgroup for every 32 of them
+[[object]] void object_meshlets(
+    object_data MeshletPayload& payload [[payload]],
+    metal::mesh_grid_properties grid,
+    uint thread_idx [[thread_index_in_threadgroup]],
+    uint triangle_count [[threads_per_threadgroup]]
+) {
+    if (thread_idx == 0) {
+        payload.triangle_count = triangle_count;
+        uint meshlet_count = (triangle_count + MESHLET_TRIANGLE_COUNT - 1) / MESHLET_TRIANGLE_COUNT;
+        grid.set_threadgroups_per_grid(uint3(meshlet_count, 1, 1));
+    }
+}
+
+// spins the triangles of a meshlet about the origin like `vertex_spinning`, a thread per
+// triangle
+[[mesh]] void mesh_spinning(
+    Meshlet output,
+    const object_data MeshletPayload& payload [[payload]],
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    uint meshlet_idx [[threadgroup_position_in_grid]],
+    uint thread_idx [[thread_index_in_threadgroup]]
+) {
+    uint first = meshlet_idx * MESHLET_TRIANGLE_COUNT;
+    uint count = metal::min(payload.triangle_count - first, uint(MESHLET_TRIANGLE_COUNT));
+    if (thread_idx == 0) {
+        output.set_primitive_count(count);
+    }
+    if (thread_idx >= count) {
+        return;
+    }
+    metal::float2x2 rotation = metal::float2x2(
+        metal::cos(properties.time), metal::sin(properties.time),
+        -metal::sin(properties.time), metal::cos(properties.time)
+    );
+    for (uint corner = 0; corner < 3; corner++) {
+        uint index = thread_idx * 3 + corner;
+        VertexInput in = vertices[first * 3 + index];
+        metal::float2 position = rotation * in.position.xy;
+        VertexOutput viewed =
+            view_vertex(properties, metal::float3(position, in.position.z), in.color);
+        MeshletVertex out;
+        out.position = viewed.position;
+        out.color = viewed.color;
+        output.set_vertex(index, out);
+        output.set_index(index, index);
+    }
+}
+
+fragment metal::float4 fragment_meshlet(MeshletVertex in [[stage_in]]) {
+    return in.color;
+}
+
+#endif
+
+struct RayCamera {
+    metal::float4 origin;
+    metal::float4 origin_x;
+    metal::float4 origin_y;
+    metal::float4 direction;
+    metal::float4 direction_x;
+    metal::float4 direction_y;
+    metal::float4 background;
+};
+
+// traces a ray through every pixel of `output` and writes the vertex colors of the closest
+// triangle, darkened where it's seen at a grazing angle, or the background where there's none
+kernel void trace_primary_rays(
+    constant RayCamera& camera [[buffer(1)]],
+    metal::raytracing::primitive_acceleration_structure scene [[buffer(2)]],
+    device const VertexInput* vertices [[buffer(3)]],
+    device const uint* indices [[buffer(4)]],
+    metal::texture2d<float, metal::access::write> output [[texture(0)]],
+    metal::uint2 pixel [[thread_position_in_grid]],
+    metal::uint2 size [[threads_per_grid]]
+) {
+    metal::float2 ndc = (metal::float2(pixel) + 0.5) / metal::float2(size) * 2 - 1;
+    ndc.y = -ndc.y;
+    metal::raytracing::ray ray;
+    ray.origin = (camera.origin + camera.origin_x * ndc.x + camera.origin_y * ndc.y).xyz;
+    ray.direction = metal::normalize(
+        (camera.direction + camera.direction_x * ndc.x + camera.direction_y * ndc.y).xyz
+    );
+    ray.min_distance = 0;
+    ray.max_distance = INFINITY;
+
+    metal::raytracing::intersector<metal::raytracing::triangle_data> intersector;
+    auto hit = intersector.intersect(ray, scene);
+    if (hit.type != metal::raytracing::intersection_type::triangle) {
+        output.write(camera.background, pixel);
+        return;
+    }
+    uint first = hit.primitive_id * 3;
+    VertexInput a = vertices[indices[first]];
+    VertexInput b = vertices[indices[first + 1]];
+    VertexInput c = vertices[indices[first + 2]];
+    metal::float2 barycentric = hit.triangle_barycentric_coord;
+    metal::float3 color = metal::float3(a.color) * (1 - barycentric.x - barycentric.y)
+        + metal::float3(b.color) * barycentric.x + metal::float3(c.color) * barycentric.y;
+    metal::float3 normal = metal::normalize(metal::cross(
+        metal::float3(b.position) - metal::float3(a.position),
+        metal::float3(c.position) - metal::float3(a.position)
+    ));
+    float facing = metal::abs(metal::dot(normal, ray.direction));
+    output.write(metal::float4(color * (0.25 + 0.75 * facing), 1), pixel);
+}
+
+// the input of a post-processing pass, the texture before it and the settings of the chain
+struct PostProcessArguments {
+    metal::texture2d<float> source [[id(0)]];
+    // the blurred bright parts, added by `fragment_composite`
+    metal::texture2d<float> bloom [[id(1)]];
+    float exposure [[id(2)]];
+    float bloom_threshold [[id(3)]];
+    float bloom_intensity [[id(4)]];
+    uint tonemap [[id(5)]];
+};
+
+// the texture coordinates of the pixel of a fullscreen triangle, textures start at the top
+static metal::float2 source_uv(FullscreenOutput in) {
+    return metal::float2(in.uv.x, 1 - in.uv.y);
+}
+
+static float luminance(metal::float3 color) {
+    return metal::dot(color, metal::float3(0.2126, 0.7152, 0.0722));
+}
+
+// keeps what's brighter than the threshold, drawn into a target of half the size so the
+// filtering averages 4 pixels
+fragment metal::float4 fragment_bright_pass(
+    FullscreenOutput in [[stage_in]],
+    constant PostProcessArguments& arguments [[buffer(0)]]
+) {
+    constexpr metal::sampler linear(metal::filter::linear, metal::address::clamp_to_edge);
+    metal::float3 color = arguments.source.sample(linear, source_uv(in)).rgb;
+    float brightness = luminance(color);
+    float excess = metal::max(brightness - arguments.bloom_threshold, 0.0);
+    float kept = excess / metal::max(brightness, 1e-4);
+    return metal::float4(color * kept, 1);
+}
+
+// a gaussian blur of 9 taps along `direction`, in texels
+static metal::float4 blur(
+    FullscreenOutput in,
+    metal::texture2d<float> source,
+    metal::float2 direction
+) {
+    constexpr metal::sampler linear(metal::filter::linear, metal::address::clamp_to_edge);
+    const float weights[] = {0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216};
+    metal::float2 texel = direction / metal::float2(source.get_width(), source.get_height());
+    metal::float2 uv = source_uv(in);
+    metal::float3 color = source.sample(linear, uv).rgb * weights[0];
+    for (int i = 1; i < 5; i++) {
+        color += source.sample(linear, uv + texel * i).rgb * weights[i];
+        color += source.sample(linear, uv - texel * i).rgb * weights[i];
+    }
+    return metal::float4(color, 1);
+}
+
+fragment metal::float4 fragment_blur_horizontal(
+    FullscreenOutput in [[stage_in]],
+    constant PostProcessArguments& arguments [[buffer(0)]]
+) {
+    return blur(in, arguments.source, metal::float2(1, 0));
+}
+
+fragment metal::float4 fragment_blur_vertical(
+    FullscreenOutput in [[stage_in]],
+    constant PostProcessArguments& arguments [[buffer(0)]]
+) {
+    return blur(in, arguments.source, metal::float2(0, 1));
+}
+
+// the filmic curve of the academy color encoding system, fitted by Krzysztof Narkowicz
+static metal::float3 tonemap_aces(metal::float3 color) {
+    return metal::saturate(color * (2.51 * color + 0.03) / (color * (2.43 * color + 0.59) + 0.14));
+}
+
+// adds the bloom to the scene, exposes it and maps it into the range of the drawable
+fragment metal::float4 fragment_composite(
+    FullscreenOutput in [[stage_in]],
+    constant PostProcessArguments& arguments [[buffer(0)]]
+) {
+    constexpr metal::sampler linear(metal::filter::linear, metal::address::clamp_to_edge);
+    metal::float2 uv = source_uv(in);
+    metal::float3 color = arguments.source.sample(linear, uv).rgb;
+    color += arguments.bloom.sample(linear, uv).rgb * arguments.bloom_intensity;
+    color *= arguments.exposure;
+    if (arguments.tonemap) {
+        color = tonemap_aces(color);
+    }
+    return metal::float4(color, 1);
+}
+
+// fast approximate antialiasing, blurs along the edges found in the luminance of the pixels
+// around each one
+fragment metal::float4 fragment_fxaa(
+    FullscreenOutput in [[stage_in]],
+    constant PostProcessArguments& arguments [[buffer(0)]]
+) {
+    constexpr metal::sampler linear(metal::filter::linear, metal::address::clamp_to_edge);
+    constexpr float reduce_min = 1.0 / 128;
+    constexpr float reduce_multiplier = 1.0 / 8;
+    constexpr float span_max = 8;
+    metal::texture2d<float> source = arguments.source;
+    metal::float2 texel = 1 / metal::float2(source.get_width(), source.get_height());
+    metal::float2 uv = source_uv(in);
+    float north_west = luminance(source.sample(linear, uv + metal::float2(-1, -1) * texel).rgb);
+    float north_east = luminance(source.sample(linear, uv + metal::float2(1, -1) * texel).rgb);
+    float south_west = luminance(source.sample(linear, uv + metal::float2(-1, 1) * texel).rgb);
+    float south_east = luminance(source.sample(linear, uv + metal::float2(1, 1) * texel).rgb);
+    float center = luminance(source.sample(linear, uv).rgb);
+    float luma_min = metal::min(
+        center,
+        metal::min(metal::min(north_west, north_east), metal::min(south_west, south_east))
+    );
+    float luma_max = metal::max(
+        center,
+        metal::max(metal::max(north_west, north_east), metal::max(south_west, south_east))
+    );
+
+    metal::float2 direction = metal::float2(
+        (south_west + south_east) - (north_west + north_east),
+        (north_west + south_west) - (north_east + south_east)
+    );
+    float reduce = metal::max(
+        (north_west + north_east + south_west + south_east) * 0.25 * reduce_multiplier,
+        reduce_min
+    );
+    float scale = 1 / (metal::min(metal::abs(direction.x), metal::abs(direction.y)) + reduce);
+    direction = metal::clamp(direction * scale, -span_max, span_max) * texel;
+
+    metal::float3 inner = 0.5 * source.sample(linear, uv - direction / 6).rgb
+        + 0.5 * source.sample(linear, uv + direction / 6).rgb;
+    metal::float3 outer = 0.5 * inner
+        + 0.25 * source.sample(linear, uv - direction / 2).rgb
+        + 0.25 * source.sample(linear, uv + direction / 2).rgb;
+    float outer_luma = luminance(outer);
+    bool overshoots = outer_luma < luma_min || outer_luma > luma_max;
+    return metal::float4(overshoots ? inner : outer, 1);
+}
+
+// the attributes of a vertex the physically based shading needs besides `VertexInput`
+struct SurfaceVertex {
+    metal::packed_float3 normal;
+    // the direction the texture coordinates grow along u, w is the sign of the one along v
+    metal::packed_float4 tangent;
+    metal::packed_float2 uv;
+};
+
+struct PbrOutput {
+    metal::float4 position [[position]];
+    metal::float4 color;
+    metal::float3 normal;
+    metal::float4 tangent;
+    metal::float2 uv;
+    // towards the eye
+    metal::float3 view;
+    metal::float4 light_position;
+    metal::float3 light_direction [[flat]];
+    metal::float4 light_color [[flat]];
+    uint cast_shadows [[flat]];
+    metal::float3 world_position;
+    // the size, the columns and the rows of the tiles of the local lights
+    metal::uint3 light_tiles [[flat]];
+};
+
+// the varyings of a vertex at `position` in world space with the attributes of `surface`
+static PbrOutput pbr_output(
+    device const SceneProperties& properties,
+    metal::float3 position,
+    metal::float3 color,
+    SurfaceVertex surface
+) {
+    metal::float4 view_origin = properties.view_origin;
+    PbrOutput out;
+    out.position = properties.view_projection * metal::float4(position, 1);
+    out.color = metal::float4(color, 1);
+    out.normal = surface.normal;
+    out.tangent = surface.tangent;
+    out.uv = surface.uv;
+    out.view = view_origin.xyz - position * view_origin.w;
+    out.light_position = properties.light.view_projection * metal::float4(position, 1);
+    out.light_direction = properties.light.direction.xyz;
+    out.light_color = properties.light.color;
+    out.cast_shadows = properties.light.cast_shadows;
+    out.world_position = position;
+    LightProperties light = properties.light;
+    out.light_tiles = metal::uint3(
+        light.light_tile_size,
+        light.light_tile_columns,
+        light.light_tile_rows
+    );
+    return out;
+}
+
+// the vertices of a mesh in world space, with their surface attributes at index 6
+vertex PbrOutput vertex_pbr(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const SurfaceVertex* surfaces [[buffer(6)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    return pbr_output(properties, in.position, in.color, surfaces[vertex_idx]);
+}
+
+// the joints of a skinned vertex, indices into the joint matrices of its skin, and their
+// weights
+struct SkinVertex {
+    metal::ushort4 joints;
+    metal::packed_float4 weights;
+};
+
+// the blend of the joint matrices moving a skinned vertex
+static metal::float4x4 skin_matrix(SkinVertex skin, device const metal::float4x4* joint_matrices) {
+    return joint_matrices[skin.joints.x] * skin.weights.x
+        + joint_matrices[skin.joints.y] * skin.weights.y
+        + joint_matrices[skin.joints.z] * skin.weights.z
+        + joint_matrices[skin.joints.w] * skin.weights.w;
+}
+
+// `vertex_pbr` for the vertices of a skinned mesh, in the space of their mesh, the joint
+// weights at index 7 and the joint matrices of the pose at index 8
+vertex PbrOutput vertex_pbr_skinned(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const SurfaceVertex* surfaces [[buffer(6)]],
+    device const SkinVertex* skins [[buffer(7)]],
+    device const metal::float4x4* joint_matrices [[buffer(8)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    VertexInput in = vertices[vertex_idx];
+    SurfaceVertex surface = surfaces[vertex_idx];
+    metal::float4x4 skin = skin_matrix(skins[vertex_idx], joint_matrices);
+    metal::float3 position = (skin * metal::float4(in.position, 1)).xyz;
+    // the joints scale evenly, the normals stay perpendicular to the surface
+    surface.normal = metal::normalize((skin * metal::float4(surface.normal, 0)).xyz);
+    metal::float3 tangent = metal::normalize((skin * metal::float4(surface.tangent.xyz, 0)).xyz);
+    surface.tangent = metal::float4(tangent, surface.tangent.w);
+    return pbr_output(properties, position, in.color, surface);
+}
+
+// the skinned vertices of `vertex_pbr_skinned` without the shading, for the shadow map
+vertex metal::float4 vertex_skinned(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const VertexInput* vertices [[buffer(1)]],
+    device const SkinVertex* skins [[buffer(7)]],
+    device const metal::float4x4* joint_matrices [[buffer(8)]],
+    uint vertex_idx [[vertex_id]]
+) {
+    metal::float4x4 skin = skin_matrix(skins[vertex_idx], joint_matrices);
+    return properties.view_projection * skin * metal::float4(vertices[vertex_idx].position, 1);
+}
+
+// the textures and factors of a `PbrMaterial`, the factors scale what the textures hold
+struct PbrMaterialArguments {
+    metal::texture2d<float> albedo [[id(0)]];
+    // the roughness in green and the metalness in blue, as in glTF
+    metal::texture2d<float> metallic_roughness [[id(1)]];
+    // tangent space normals
+    metal::texture2d<float> normal [[id(2)]];
+    metal::sampler sampler [[id(3)]];
+    metal::float4 base_color [[id(4)]];
+    float metallic [[id(5)]];
+    float roughness [[id(6)]];
+    float normal_scale [[id(7)]];
+};
+
+// what a `PbrMaterial` makes of a fragment of `vertex_pbr`
+struct PbrSurface {
+    metal::float4 albedo;
+    // the normal of the normal map, in world space
+    metal::float3 normal;
+    float metallic;
+    float roughness;
+};
+
+static PbrSurface pbr_surface(PbrOutput in, constant PbrMaterialArguments& material) {
+    PbrSurface surface;
+    surface.albedo = material.albedo.sample(material.sampler, in.uv) * material.base_color;
+    surface.albedo *= in.color;
+    metal::float4 metallic_roughness = material.metallic_roughness.sample(material.sampler, in.uv);
+    surface.roughness = metal::clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+    surface.metallic = metal::saturate(metallic_roughness.b * material.metallic);
+
+    // the normal of the map, in the frame of the interpolated normal and tangent
+    metal::float3 normal = metal::normalize(in.normal);
+    metal::float3 tangent = in.tangent.xyz - normal * metal::dot(normal, in.tangent.xyz);
+    tangent = metal::normalize(tangent);
+    metal::float3 bitangent = metal::cross(normal, tangent) * in.tangent.w;
+    metal::float3 mapped = material.normal.sample(material.sampler, in.uv).xyz * 2 - 1;
+    mapped.xy *= material.normal_scale;
+    surface.normal = metal::normalize(metal::float3x3(tangent, bitangent, normal) * mapped);
+    return surface;
+}
+
+// the metallic roughness model of glTF: a lambertian diffuse term and a specular one of GGX
+// distributed microfacets with Smith's shadowing and Schlick's fresnel, for the `radiance`
+// arriving from the direction of `light`
+static metal::float3 pbr_reflect(
+    PbrSurface surface,
+    metal::float3 view,
+    metal::float3 light,
+    metal::float3 radiance
+) {
+    metal::float3 normal = surface.normal;
+    metal::float3 halfway = metal::normalize(light + view);
+    float n_dot_l = metal::saturate(metal::dot(normal, light));
+    float n_dot_v = metal::max(metal::dot(normal, view), 1e-4);
+    float n_dot_h = metal::saturate(metal::dot(normal, halfway));
+    float v_dot_h = metal::saturate(metal::dot(view, halfway));
+
+    metal::float3 albedo = surface.albedo.rgb;
+    float metallic = surface.metallic;
+    float roughness = surface.roughness;
+    metal::float3 f0 = metal::mix(metal::float3(0.04), albedo, metallic);
+    metal::float3 fresnel = f0 + (1 - f0) * metal::pow(1 - v_dot_h, 5);
+    float alpha = roughness * roughness;
+    float d = n_dot_h * n_dot_h * (alpha * alpha - 1) + 1;
+    float distribution = alpha * alpha / (M_PI_F * d * d);
+    float k = (roughness + 1) * (roughness + 1) / 8;
+    float geometry = n_dot_v / (n_dot_v * (1 - k) + k) * n_dot_l / (n_dot_l * (1 - k) + k);
+    metal::float3 specular = distribution * geometry * fresnel / (4 * n_dot_v * n_dot_l + 1e-4);
+    metal::float3 diffuse = (1 - fresnel) * (1 - metallic) * albedo / M_PI_F;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+// `pbr_reflect` lit by the directional light. the ambient share of the light lights the
+// diffuse color everywhere, like `fragment_shadowed` the surfaces keep their colors without a
+// light. `lit` is the share of the light the shadows let through
+static metal::float3 pbr_shade(
+    PbrSurface surface,
+    metal::float3 view,
+    metal::float3 light_direction,
+    metal::float4 light_color,
+    float lit
+) {
+    float ambient = light_color.a;
+    // a light of color 1 lights a white diffuse surface facing it to 1
+    metal::float3 radiance = light_color.rgb * M_PI_F * (1 - ambient);
+    metal::float3 color = pbr_reflect(surface, view, -light_direction, radiance) * lit;
+    // there's no environment to reflect, the metals take the ambient light like the rest
+    return color + surface.albedo.rgb * light_color.rgb * ambient;
+}
+
+// the most lights `cull_lights` lists for a tile, `MAX_LIGHTS_PER_TILE` in lights.rs
+constant uint MAX_LIGHTS_PER_TILE = 64;
+
+// the kinds of `LightData`
+constant uint LIGHT_DIRECTIONAL = 0;
+constant uint LIGHT_SPOT = 2;
+
+// a light of `set_lights`, `LightData` in lights.rs
+struct LightData {
+    // the range in w, 0 for directional lights
+    metal::float4 position;
+    // scaled by the intensity, the cosine of the inner angle of spot lights in w
+    metal::float4 color;
+    // the direction the light travels in, the cosine of the outer angle of spot lights in w
+    metal::float4 direction;
+    // `LIGHT_DIRECTIONAL`, 1 for a point light or `LIGHT_SPOT`
+    uint kind;
+};
+
+// the lights `cull_lights` listed for the tile of `pixel`, shaded with `pbr_reflect`. the point
+// and spot lights fade with the square of the distance, windowed to reach 0 at their range
+static metal::float3 local_lighting(
+    PbrSurface surface,
+    metal::float3 view,
+    metal::float3 position,
+    metal::float2 pixel,
+    metal::uint3 tiles,
+    device const LightData* lights,
+    device const uint* tile_lights
+) {
+    metal::float3 color = 0;
+    if (tiles.x == 0) {
+        return color;
+    }
+    metal::uint2 tile = metal::min(metal::uint2(pixel) / tiles.x, tiles.yz - 1);
+    uint tile_index = tile.y * tiles.y + tile.x;
+    device const uint* list = tile_lights + tile_index * (MAX_LIGHTS_PER_TILE + 1);
+    for (uint index = 0; index < list[0]; index++) {
+        LightData light = lights[list[1 + index]];
+        metal::float3 towards = -light.direction.xyz;
+        float attenuation = 1;
+        if (light.kind != LIGHT_DIRECTIONAL) {
+            metal::float3 offset = light.position.xyz - position;
+            float distance_squared = metal::max(metal::dot(offset, offset), 1e-4);
+            towards = offset * metal::rsqrt(distance_squared);
+            float ratio = distance_squared / (light.position.w * light.position.w);
+            float window = metal::saturate(1 - ratio * ratio);
+            attenuation = window * window / distance_squared;
+        }
+        if (light.kind == LIGHT_SPOT) {
+            float cosine = metal::dot(-towards, light.direction.xyz);
+            attenuation *= metal::smoothstep(light.direction.w, light.color.w, cosine);
+        }
+        // like the directional light, a light of color 1 lights a white surface facing it to 1
+        metal::float3 radiance = light.color.rgb * M_PI_F * attenuation;
+        color += pbr_reflect(surface, view, towards, radiance);
+    }
+    return color;
+}
+
+// a `PbrMaterial` shaded with `pbr_shade` and the local lights of its tile
+fragment metal::float4 fragment_pbr(
+    PbrOutput in [[stage_in]],
+    constant PbrMaterialArguments& material [[buffer(0)]],
+    device const LightData* lights [[buffer(10)]],
+    device const uint* tile_lights [[buffer(11)]],
+    metal::depth2d<float> shadow_map [[texture(8)]],
+    metal::sampler shadow_sampler [[sampler(8)]]
+) {
+    PbrSurface surface = pbr_surface(in, material);
+    float lit = light_reaching(in.light_position, in.cast_shadows, shadow_map, shadow_sampler);
+    metal::float3 view = metal::normalize(in.view);
+    metal::float3 color = pbr_shade(surface, view, in.light_direction, in.light_color, lit);
+    color += local_lighting(
+        surface,
+        view,
+        in.world_position,
+        in.position.xy,
+        in.light_tiles,
+        lights,
+        tile_lights
+    );
+    return metal::float4(color, surface.albedo.a);
+}
+
+// the parameters of `cull_lights`, `LightCulling` in lights.rs
+struct LightCulling {
+    // the viewport the lights are projected into, the origin in xy and the size in zw
+    metal::float4 viewport;
+    uint light_count;
+};
+
+// whether `light` reaches the pixels between `tile_min` and `tile_max`. a point or spot light
+// reaches the ones the box around its range covers on screen, all of them when the box
+// crosses the plane of the eye, and a directional light all of them
+static bool light_covers(
+    metal::float4x4 view_projection,
+    metal::float4 viewport,
+    LightData light,
+    metal::float2 tile_min,
+    metal::float2 tile_max
+) {
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        return true;
+    }
+    float range = light.position.w;
+    metal::float2 low = INFINITY;
+    metal::float2 high = -INFINITY;
+    uint behind = 0;
+    for (uint corner = 0; corner < 8; corner++) {
+        // the bits of the corner pick the side of the box along x, y and z
+        metal::bool3 positive = (metal::uint3(corner) & metal::uint3(1, 2, 4)) != 0;
+        metal::float3 offset = metal::select(metal::float3(-range), metal::float3(range), positive);
+        metal::float4 clip = view_projection * metal::float4(light.position.xyz + offset, 1);
+        if (clip.w <= 0) {
+            behind++;
+            continue;
+        }
+        metal::float2 ndc = metal::float2(clip.x, -clip.y) / clip.w;
+        metal::float2 pixel = viewport.xy + (ndc * 0.5 + 0.5) * viewport.zw;
+        low = metal::min(low, pixel);
+        high = metal::max(high, pixel);
+    }
+    if (behind > 0) {
+        return behind < 8;
+    }
+    return metal::all(low < tile_max) && metal::all(high > tile_min);
+}
+
+// lists the lights reaching a tile of the frame for `local_lighting`, the count followed by
+// the indices of up to `MAX_LIGHTS_PER_TILE` of them. one thread per tile
+kernel void cull_lights(
+    device const SceneProperties& properties [[buffer(0)]],
+    device const LightData* lights [[buffer(1)]],
+    device uint* tile_lights [[buffer(2)]],
+    constant LightCulling& culling [[buffer(3)]],
+    metal::uint2 tile [[thread_position_in_grid]]
+) {
+    LightProperties light = properties.light;
+    if (tile.x >= light.light_tile_columns || tile.y >= light.light_tile_rows) {
+        return;
+    }
+    float tile_size = light.light_tile_size;
+    metal::float2 tile_min = metal::float2(tile) * tile_size;
+    metal::float2 tile_max = tile_min + tile_size;
+    uint tile_index = tile.y * light.light_tile_columns + tile.x;
+    device uint* list = tile_lights + tile_index * (MAX_LIGHTS_PER_TILE + 1);
+    uint count = 0;
+    for (uint index = 0; index < culling.light_count && count < MAX_LIGHTS_PER_TILE; index++) {
+        LightData data = lights[index];
+        if (light_covers(properties.view_projection, culling.viewport, data, tile_min, tile_max)) {
+            list[1 + count] = index;
+            count++;
+        }
+    }
+    list[0] = count;
+}
+
+// the attachments of the g-buffer of the deferred path after the lighting at 0: the albedo
+// with the metalness in alpha, the normal with the roughness in w and the depth
+struct GBufferOutput {
+    metal::float4 albedo [[color(1)]];
+    metal::float4 normal [[color(2)]];
+    float depth [[color(3)]];
+};
+
+// `fragment_pbr` for the deferred path, writing the surface into the g-buffer to be lit later.
+// the surfaces are opaque there, the alpha of the material is left out
+fragment GBufferOutput fragment_gbuffer(
+    PbrOutput in [[stage_in]],
+    constant PbrMaterialArguments& material [[buffer(0)]]
+) {
+    PbrSurface surface = pbr_surface(in, material);
+    GBufferOutput out;
+    out.albedo = metal::float4(surface.albedo.rgb, surface.metallic);
+    out.normal = metal::float4(surface.normal, surface.roughness);
+    out.depth = in.position.z;
+    return out;
+}
+
+// the view of the deferred lighting, `DeferredProperties` in deferred.rs
+struct DeferredProperties {
+    // from clip space back to the world
+    metal::float4x4 inverse_view_projection;
+    // the origin and the size of the viewport in pixels
+    metal::float4 viewport;
+};
+
+// the directional light reflected by the surface the g-buffer holds at the pixel `position`
+static metal::float4 deferred_light(
+    constant SceneProperties& properties,
+    constant DeferredProperties& deferred,
+    metal::float2 position,
+    metal::float4 albedo,
+    metal::float4 normal,
+    float depth,
+    metal::depth2d<float> shadow_map,
+    metal::sampler shadow_sampler
+) {
+    metal::float2 uv = (position - deferred.viewport.xy) / deferred.viewport.zw;
+    metal::float4 clip = metal::float4(uv.x * 2 - 1, 1 - uv.y * 2, depth, 1);
+    metal::float4 world = deferred.inverse_view_projection * clip;
+    metal::float3 world_position = world.xyz / world.w;
+
+    PbrSurface surface;
+    surface.albedo = metal::float4(albedo.rgb, 1);
+    surface.metallic = albedo.a;
+    surface.normal = metal::normalize(normal.xyz);
+    surface.roughness = normal.w;
+    metal::float4 view_origin = properties.view_origin;
+    metal::float3 view = metal::normalize(view_origin.xyz - world_position * view_origin.w);
+    LightProperties light = properties.light;
+    metal::float4 light_position = light.view_projection * metal::float4(world_position, 1);
+    float lit = light_reaching(light_position, light.cast_shadows, shadow_map, shadow_sampler);
+    metal::float3 color = pbr_shade(surface, view, light.direction.xyz, light.color, lit);
+    return metal::float4(color, 1);
+}
+
+// lights the g-buffer in the pass filling it, reading it from tile memory. the pixels no
+// surface covered keep the depth of the far plane and stay transparent
+fragment metal::float4 fragment_deferred_tile(
+    FullscreenOutput in [[stage_in]],
+    constant SceneProperties& properties [[buffer(0)]],
+    constant DeferredProperties& deferred [[buffer(1)]],
+    metal::float4 albedo [[color(1)]],
+    metal::float4 normal [[color(2)]],
+    float depth [[color(3)]],
+    metal::depth2d<float> shadow_map [[texture(8)]],
+    metal::sampler shadow_sampler [[sampler(8)]]
+) {
+    if (depth >= 1) {
+        metal::discard_fragment();
+    }
+    return deferred_light(
+        properties,
+        deferred,
+        in.position.xy,
+        albedo,
+        normal,
+        depth,
+        shadow_map,
+        shadow_sampler
+    );
+}
+
+// the color and the depth of a deferred surface in the pass of the frame, so that the draws
+// after it are depth tested against it
+struct DeferredOutput {
+    metal::float4 color [[color(0)]];
+    float depth [[depth(any)]];
+};
+
+// copies what `fragment_deferred_tile` lit into the pass of the frame, leaving the background
+// where no surface was drawn
+fragment DeferredOutput fragment_deferred_composite(
+    FullscreenOutput in [[stage_in]],
+    metal::texture2d<float, metal::access::read> lighting [[texture(0)]],
+    metal::texture2d<float, metal::access::read> depth [[texture(1)]]
+) {
+    uint2 position = uint2(in.position.xy);
+    DeferredOutput out;
+    out.depth = depth.read(position).r;
+    if (out.depth >= 1) {
+        metal::discard_fragment();
+    }
+    out.color = lighting.read(position);
+    return out;
+}
+
+// lights the g-buffer written out by the pass filling it in the pass of the frame, where the
+// tile memory can't be read back by the pass filling it
+fragment DeferredOutput fragment_deferred_lighting(
+    FullscreenOutput in [[stage_in]],
+    constant SceneProperties& properties [[buffer(0)]],
+    constant DeferredProperties& deferred [[buffer(1)]],
+    metal::texture2d<float, metal::access::read> albedo [[texture(0)]],
+    metal::texture2d<float, metal::access::read> normal [[texture(1)]],
+    metal::texture2d<float, metal::access::read> depth [[texture(2)]],
+    metal::depth2d<float> shadow_map [[texture(8)]],
+    metal::sampler shadow_sampler [[sampler(8)]]
+) {
+    uint2 position = uint2(in.position.xy);
+    DeferredOutput out;
+    out.depth = depth.read(position).r;
+    if (out.depth >= 1) {
+        metal::discard_fragment();
+    }
+    out.color = deferred_light(
+        properties,
+        deferred,
+        in.position.xy,
+        albedo.read(position),
+        normal.read(position),
+        out.depth,
+        shadow_map,
+        shadow_sampler
+    );
+    return out;
+}
+
+// stretches a frame rendered at the rates of a rasterization rate map over the drawable, the
+// pixels of the drawable are the coordinates of the screen the map was made for
+fragment metal::float4 fragment_rasterization_rate(
+    FullscreenOutput in [[stage_in]],
+    metal::texture2d<float> source [[texture(0)]],
+    constant metal::rasterization_rate_map_data& map_data [[buffer(0)]]
+) {
+    constexpr metal::sampler linear(metal::filter::linear, metal::address::clamp_to_edge);
+    metal::rasterization_rate_map_decoder map(map_data);
+    metal::float2 physical = map.map_screen_to_physical_coordinates(in.position.xy);
+    metal::float2 size = metal::float2(source.get_width(), source.get_height());
+    return source.sample(linear, physical / size);
+}
+
+// the id of the entity a draw is picked as, written in place of its color into the target the
+// renderer reads the picked pixel back from. it takes nothing from the vertex function, so it
+// pairs with any of them
+fragment uint fragment_pick(constant uint& id [[buffer(0)]]) {
+    return id;
+}
@@ -0,0 +1,211 @@
+use core::{ffi::c_void, mem::size_of, ptr::NonNull};
+
+use objc2::{rc::Retained, runtime::ProtocolObject};
+#[cfg(target_os = "macos")]
+use objc2_app_kit::{NSPasteboard, NSPasteboardTypeString};
+use objc2_foundation::NSString;
+use objc2_metal::{
+    MTLBuffer, MTLCommandBuffer, MTLCommandEncoder, MTLDevice, MTLIndexType, MTLLoadAction,
+    MTLOrigin, MTLPixelFormat, MTLPrimitiveType, MTLRegion, MTLRenderCommandEncoder,
+    MTLRenderPassDescriptor, MTLRenderPipelineState, MTLSamplerState, MTLScissorRect, MTLSize,
+    MTLStoreAction, MTLTexture, MTLTextureDescriptor, MTLTextureUsage,
+};
+#[cfg(target_os = "ios")]
+use objc2_ui_kit::UIPasteboard;
+
+// the `UiVertex` struct in triangle.metal, egui's vertices are copied into it and imgui's share
+// its layout
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub(crate) struct UiVertex {
+    // in points from the top left corner of the view
+    pub(crate) position: [f32; 2],
+    pub(crate) uv: [f32; 2],
+    // sRGB encoded
+    pub(crate) color: [u8; 4],
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn clipboard_text() -> Option<String> {
+    let pasteboard = unsafe { NSPasteboard::generalPasteboard() };
+    unsafe { pasteboard.stringForType(NSPasteboardTypeString) }.map(|text| text.to_string())
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn set_clipboard_text(text: &str) {
+    let pasteboard = unsafe { NSPasteboard::generalPasteboard() };
+    unsafe {
+        pasteboard.clearContents();
+        pasteboard.setString_forType(&NSString::from_str(text), NSPasteboardTypeString);
+    }
+}
+
+#[cfg(target_os = "ios")]
+pub(crate) fn clipboard_text() -> Option<String> {
+    let pasteboard = unsafe { UIPasteboard::generalPasteboard() };
+    unsafe { pasteboard.string() }.map(|text| text.to_string())
+}
+
+#[cfg(target_os = "ios")]
+pub(crate) fn set_clipboard_text(text: &str) {
+    let pasteboard = unsafe { UIPasteboard::generalPasteboard() };
+    unsafe { pasteboard.setString(Some(&NSString::from_str(text))) };
+}
+
+// an 8-bit RGBA texture of the ui, filled with `upload_ui_texture`
+pub(crate) fn create_ui_texture(
+    device: &ProtocolObject<dyn MTLDevice>,
+    width: usize,
+    height: usize,
+) -> Retained<ProtocolObject<dyn MTLTexture>> {
+    let descriptor = unsafe {
+        MTLTextureDescriptor::texture2DDescriptorWithPixelFormat_width_height_mipmapped(
+            MTLPixelFormat::RGBA8Unorm,
+            width,
+            height,
+            false,
+        )
+    };
+    descriptor.setUsage(MTLTextureUsage::ShaderRead);
+    device
+        .newTextureWithDescriptor(&descriptor)
+        .expect("Failed to create a ui texture.")
+}
+
+// replaces the `width` by `height` pixels at `x`, `y` with tightly packed RGBA `pixels`
+pub(crate) fn upload_ui_texture(
+    texture: &ProtocolObject<dyn MTLTexture>,
+    (x, y): (usize, usize),
+    (width, height): (usize, usize),
+    pixels: &[u8],
+) {
+    let region = MTLRegion {
+        origin: MTLOrigin { x, y, z: 0 },
+        size: MTLSize {
+            width,
+            height,
+            depth: 1,
+        },
+    };
+    unsafe {
+        texture.replaceRegion_mipmapLevel_withBytes_bytesPerRow(
+            region,
+            0,
+            NonNull::from(pixels).cast::<c_void>(),
+            width * 4,
+        )
+    };
+}
+
+// the scissor rect of a clip rect in pixels, clamped to the drawable. None when nothing of it
+// is visible
+pub(crate) fn scissor_rect(
+    [left, top, right, bottom]: [f32; 4],
+    (width, height): (usize, usize),
+) -> Option<MTLScissorRect> {
+    let clamp = |value: f32, max: usize| value.round().clamp(0., max as f32) as usize;
+    let (left, top) = (clamp(left, width), clamp(top, height));
+    let (right, bottom) = (clamp(right, width), clamp(bottom, height));
+    (right > left && bottom > top).then_some(MTLScissorRect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    })
+}
+
+// a mesh of the ui with the resources it's drawn with
+pub(crate) struct UiMesh {
+    pub(crate) scissor_rect: MTLScissorRect,
+    // `UiVertex` and u32 indices
+    pub(crate) vertices: Retained<ProtocolObject<dyn MTLBuffer>>,
+    pub(crate) indices: Retained<ProtocolObject<dyn MTLBuffer>>,
+    // the first vertex and index of the mesh in the buffers, which may hold several
+    pub(crate) vertex_offset: usize,
+    pub(crate) index_offset: usize,
+    pub(crate) index_count: usize,
+    pub(crate) texture: Retained<ProtocolObject<dyn MTLTexture>>,
+    pub(crate) sampler: Retained<ProtocolObject<dyn MTLSamplerState>>,
+}
+
+// the ui of a frame, drawn in a pass of its own after the scene
+pub(crate) struct UiDraw {
+    pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+    meshes: Vec<UiMesh>,
+    // in points
+    screen_size: [f32; 2],
+    // whether the drawable stores linear colors, the ui's are sRGB encoded
+    linear_output: u32,
+}
+
+impl UiDraw {
+    pub(crate) fn new(
+        pipeline_state: Retained<ProtocolObject<dyn MTLRenderPipelineState>>,
+        meshes: Vec<UiMesh>,
+        screen_size: [f32; 2],
+        pixel_format: MTLPixelFormat,
+    ) -> Self {
+        let linear_output = matches!(
+            pixel_format,
+            MTLPixelFormat::BGRA8Unorm_sRGB | MTLPixelFormat::RGBA16Float
+        );
+        UiDraw {
+            pipeline_state,
+            meshes,
+            screen_size,
+            linear_output: linear_output.into(),
+        }
+    }
+
+    // draws over the scene in `drawable_texture`, returns false if the encoder couldn't be
+    // created
+    pub(crate) fn encode(
+        &self,
+        command_buffer: &ProtocolObject<dyn MTLCommandBuffer>,
+        drawable_texture: &ProtocolObject<dyn MTLTexture>,
+    ) -> bool {
+        let descriptor = MTLRenderPassDescriptor::renderPassDescriptor();
+        let color_attachment = unsafe { descriptor.colorAttachments().objectAtIndexedSubscript(0) };
+        color_attachment.setTexture(Some(drawable_texture));
+        color_attachment.setLoadAction(MTLLoadAction::Load);
+        color_attachment.setStoreAction(MTLStoreAction::Store);
+        let Some(encoder) = command_buffer.renderCommandEncoderWithDescriptor(&descriptor) else {
+            return false;
+        };
+
+        encoder.setRenderPipelineState(&self.pipeline_state);
+        unsafe {
+            encoder.setVertexBytes_length_atIndex(
+                NonNull::from(&self.screen_size).cast::<c_void>(),
+                core::mem::size_of_val(&self.screen_size),
+                1,
+            );
+            encoder.setFragmentBytes_length_atIndex(
+                NonNull::from(&self.linear_output).cast::<c_void>(),
+                core::mem::size_of_val(&self.linear_output),
+                0,
+            );
+        }
+        for mesh in &self.meshes {
+            encoder.setScissorRect(mesh.scissor_rect);
+            unsafe {
+                encoder.setVertexBuffer_offset_atIndex(
+                    Some(&mesh.vertices),
+                    mesh.vertex_offset * size_of::<UiVertex>(),
+                    0,
+                );
+                encoder.setFragmentTexture_atIndex(Some(&mesh.texture), 0);
+                encoder.setFragmentSamplerState_atIndex(Some(&mesh.sampler), 0);
+                encoder.drawIndexedPrimitives_indexCount_indexType_indexBuffer_indexBufferOffset(
+                    MTLPrimitiveType::Triangle,
+                    mesh.index_count,
+                    MTLIndexType::UInt32,
+                    &mesh.indices,
+                    mesh.index_offset * size_of::<u32>(),
+                );
+            }
+        }
+        encoder.endEncoding();
+        true
+    }
+}
@@ -0,0 +1,108 @@
+// vertex layouts taken from the rust structs of the vertices, so the offsets and formats of
+// the attributes a `[[stage_in]]` vertex function reads can't drift apart from the fields
+use objc2_metal::{MTLPackedFloat3, MTLVertexFormat};
+
+use crate::{VertexAttribute, VertexInput, VertexLayout};
+
+// a `#[repr(C)]` struct of vertex attributes, implemented with `impl_vertex!`
+pub trait Vertex: Copy {
+    // an attribute for each field, in the order of their `[[attribute(n)]]`
+    fn attributes() -> Vec<VertexAttribute>;
+}
+
+// the types of the fields of a `Vertex` and the format metal reads them with
+pub trait VertexFormat {
+    const FORMAT: MTLVertexFormat;
+}
+
+impl VertexFormat for f32 {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::Float;
+}
+
+impl VertexFormat for [f32; 2] {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::Float2;
+}
+
+impl VertexFormat for [f32; 3] {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::Float3;
+}
+
+impl VertexFormat for [f32; 4] {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::Float4;
+}
+
+impl VertexFormat for MTLPackedFloat3 {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::Float3;
+}
+
+impl VertexFormat for u32 {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::UInt;
+}
+
+impl VertexFormat for [u32; 2] {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::UInt2;
+}
+
+impl VertexFormat for [u32; 4] {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::UInt4;
+}
+
+impl VertexFormat for i32 {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::Int;
+}
+
+// colors, read as a float4 from 0 to 1
+impl VertexFormat for [u8; 4] {
+    const FORMAT: MTLVertexFormat = MTLVertexFormat::UChar4Normalized;
+}
+
+// the format of the field `field` returns, for `impl_vertex!`
+#[doc(hidden)]
+pub fn field_format<V, T: VertexFormat>(_field: impl Fn(&V) -> &T) -> MTLVertexFormat {
+    T::FORMAT
+}
+
+// implements `Vertex` for a `#[repr(C)]` struct from the names of its fields, in the order of
+// their attributes. every field needs a type implementing `VertexFormat`:
+//
+//     impl_vertex!(VertexInput { position, color });
+#[macro_export]
+macro_rules! impl_vertex {
+    ($vertex:ty { $($field:ident),+ $(,)? }) => {
+        impl $crate::Vertex for $vertex {
+            fn attributes() -> ::std::vec::Vec<$crate::VertexAttribute> {
+                ::std::vec![$(
+                    $crate::VertexAttribute {
+                        format: $crate::field_format(|vertex: &$vertex| &vertex.$field),
+                        offset: ::core::mem::offset_of!($vertex, $field),
+                        buffer_index: 0,
+                    },
+                )+]
+            }
+        }
+    };
+}
+
+impl_vertex!(VertexInput { position, color });
+
+impl VertexLayout {
+    // the layout of a buffer of `V` bound at `buffer_index`
+    pub fn of<V: Vertex>(buffer_index: usize) -> Self {
+        Self::default().with_vertex::<V>(buffer_index)
+    }
+
+    // adds the attributes of a buffer of `V` bound at `buffer_index`, numbered after the ones
+    // already in the layout, e.g. for the instances next to the vertices
+    pub fn with_vertex<V: Vertex>(mut self, buffer_index: usize) -> Self {
+        self.attributes.extend(
+            V::attributes()
+                .into_iter()
+                .map(|attribute| VertexAttribute {
+                    buffer_index,
+                    ..attribute
+                }),
+        );
+        self.strides.push((buffer_index, core::mem::size_of::<V>()));
+        self
+    }
+}
@@ -0,0 +1,393 @@
+// videos played by AVFoundation into metal textures. the frames are decoded into pixel buffers
+// metal can read, which a core video texture cache wraps into textures without copying them
+use std::{collections::VecDeque, fmt, io, path::Path};
+
+use core::{
+    ffi::c_void,
+    ptr::{self, NonNull},
+};
+
+use core_foundation::base::CFRelease;
+use objc2::{
+    class, msg_send, msg_send_id,
+    rc::{Allocated, Retained},
+    runtime::{AnyObject, ProtocolObject},
+    Encode, Encoding, RefEncode,
+};
+use objc2_foundation::{NSDictionary, NSNumber, NSString, NSURL};
+use objc2_metal::{MTLDevice, MTLPixelFormat, MTLTexture};
+use objc2_quartz_core::CACurrentMediaTime;
+
+use crate::MetalRenderer;
+
+type Texture = Retained<ProtocolObject<dyn MTLTexture>>;
+
+// the frames in flight may still sample the textures of the last frames, their pixel buffers
+// are only handed back to the player once they're this many frames old
+const RETAINED_FRAMES: usize = 3;
+
+// the pixel buffers of the frames are 32-bit BGRA, 'BGRA' as a four character code
+pub(crate) const PIXEL_FORMAT_BGRA: u32 = u32::from_be_bytes(*b"BGRA");
+
+// a time of the video, `value / timescale` seconds
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct CMTime {
+    value: i64,
+    timescale: i32,
+    flags: u32,
+    epoch: i64,
+}
+
+// the flag of times that hold a number rather than being invalid or indefinite
+const CM_TIME_FLAGS_VALID: u32 = 1;
+
+impl CMTime {
+    pub(crate) fn from_seconds(seconds: f64) -> Self {
+        CMTime {
+            value: (seconds * 600.) as i64,
+            timescale: 600,
+            flags: CM_TIME_FLAGS_VALID,
+            epoch: 0,
+        }
+    }
+
+    fn seconds(self) -> Option<f64> {
+        let valid = self.flags & CM_TIME_FLAGS_VALID != 0 && self.timescale > 0;
+        valid.then(|| self.value as f64 / f64::from(self.timescale))
+    }
+}
+
+unsafe impl Encode for CMTime {
+    const ENCODING: Encoding = Encoding::Struct(
+        "?",
+        &[i64::ENCODING, i32::ENCODING, u32::ENCODING, i64::ENCODING],
+    );
+}
+
+unsafe impl RefEncode for CMTime {
+    const ENCODING_REF: Encoding = Encoding::Pointer(&Self::ENCODING);
+}
+
+// opaque core video buffers, the pixel buffers and the textures of the cache
+#[repr(C)]
+pub(crate) struct CVBuffer {
+    _private: [u8; 0],
+}
+
+unsafe impl RefEncode for CVBuffer {
+    const ENCODING_REF: Encoding = Encoding::Pointer(&Encoding::Struct("__CVBuffer", &[]));
+}
+
+#[repr(C)]
+struct CVMetalTextureCache {
+    _private: [u8; 0],
+}
+
+// the player isn't in the bindings, it's messaged through the objective-c runtime
+#[link(name = "AVFoundation", kind = "framework")]
+extern "C" {}
+
+#[link(name = "CoreVideo", kind = "framework")]
+extern "C" {
+    static kCVPixelBufferPixelFormatTypeKey: &'static NSString;
+    static kCVPixelBufferMetalCompatibilityKey: &'static NSString;
+    fn CVMetalTextureCacheCreate(
+        allocator: *const c_void,
+        cache_attributes: *const c_void,
+        metal_device: &ProtocolObject<dyn MTLDevice>,
+        texture_attributes: *const c_void,
+        cache_out: *mut *mut CVMetalTextureCache,
+    ) -> i32;
+    fn CVMetalTextureCacheCreateTextureFromImage(
+        allocator: *const c_void,
+        texture_cache: *mut CVMetalTextureCache,
+        source_image: *mut CVBuffer,
+        texture_attributes: *const c_void,
+        pixel_format: MTLPixelFormat,
+        width: usize,
+        height: usize,
+        plane_index: usize,
+        texture_out: *mut *mut CVBuffer,
+    ) -> i32;
+    fn CVMetalTextureCacheFlush(texture_cache: *mut CVMetalTextureCache, options: u64);
+    fn CVMetalTextureGetTexture(image: *mut CVBuffer) -> *mut ProtocolObject<dyn MTLTexture>;
+    fn CVPixelBufferGetWidth(pixel_buffer: *mut CVBuffer) -> usize;
+    fn CVPixelBufferGetHeight(pixel_buffer: *mut CVBuffer) -> usize;
+}
+
+#[derive(Debug)]
+pub enum VideoError {
+    Io(io::Error),
+    // the core video status the texture cache couldn't be created with
+    TextureCache(i32),
+    NoCamera,
+    // the user or a policy didn't allow the application to use the camera
+    CameraDenied,
+    // why the camera couldn't be opened or captured from
+    Camera(String),
+    // why the screen couldn't be captured, e.g. without the permission to record it
+    ScreenCapture(String),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoError::Io(error) => write!(f, "Failed to open the video: {error}"),
+            VideoError::TextureCache(status) => {
+                write!(f, "Failed to create a video texture cache: {status}")
+            }
+            VideoError::NoCamera => write!(f, "There is no camera to capture from."),
+            VideoError::CameraDenied => write!(f, "The access to the camera was denied."),
+            VideoError::Camera(message) => write!(f, "Failed to open the camera: {message}"),
+            VideoError::ScreenCapture(message) => {
+                write!(f, "Failed to capture the screen: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+impl From<io::Error> for VideoError {
+    fn from(error: io::Error) -> Self {
+        VideoError::Io(error)
+    }
+}
+
+// a frame of the video as a texture, the core video texture keeps the pixel buffer and the
+// texture alive
+struct VideoFrame {
+    texture: Texture,
+    image: NonNull<CVBuffer>,
+}
+
+impl Drop for VideoFrame {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.image.as_ptr().cast()) };
+    }
+}
+
+// the textures of the last frames of a video or a camera, wrapped around their pixel buffers
+// by a core video texture cache
+pub(crate) struct VideoTextures {
+    texture_cache: NonNull<CVMetalTextureCache>,
+    // the latest frame last
+    frames: VecDeque<VideoFrame>,
+}
+
+impl VideoTextures {
+    pub(crate) fn new(device: &ProtocolObject<dyn MTLDevice>) -> Result<Self, VideoError> {
+        let mut texture_cache = ptr::null_mut();
+        let status = unsafe {
+            CVMetalTextureCacheCreate(
+                ptr::null(),
+                ptr::null(),
+                device,
+                ptr::null(),
+                &mut texture_cache,
+            )
+        };
+        let texture_cache = NonNull::new(texture_cache)
+            .filter(|_| status == 0)
+            .ok_or(VideoError::TextureCache(status))?;
+        Ok(Self {
+            texture_cache,
+            frames: VecDeque::new(),
+        })
+    }
+
+    // makes the BGRA `pixel_buffer` the latest frame, the caller keeps its own reference to it
+    pub(crate) fn push(&mut self, pixel_buffer: NonNull<CVBuffer>) {
+        if let Some(frame) = self.wrap_pixel_buffer(pixel_buffer) {
+            self.frames.push_back(frame);
+            if self.frames.len() > RETAINED_FRAMES {
+                self.frames.pop_front();
+            }
+        }
+        // the textures of the cache no frame holds on to anymore are freed
+        unsafe { CVMetalTextureCacheFlush(self.texture_cache.as_ptr(), 0) };
+    }
+
+    pub(crate) fn latest(&self) -> Option<&Texture> {
+        self.frames.back().map(|frame| &frame.texture)
+    }
+
+    // wraps a pixel buffer into a texture of the cache
+    fn wrap_pixel_buffer(&self, pixel_buffer: NonNull<CVBuffer>) -> Option<VideoFrame> {
+        let pixel_buffer = pixel_buffer.as_ptr();
+        let mut image = ptr::null_mut();
+        let status = unsafe {
+            CVMetalTextureCacheCreateTextureFromImage(
+                ptr::null(),
+                self.texture_cache.as_ptr(),
+                pixel_buffer,
+                ptr::null(),
+                MTLPixelFormat::BGRA8Unorm_sRGB,
+                CVPixelBufferGetWidth(pixel_buffer),
+                CVPixelBufferGetHeight(pixel_buffer),
+                0,
+                &mut image,
+            )
+        };
+        let image = NonNull::new(image).filter(|_| status == 0)?;
+        let texture = unsafe { Retained::retain(CVMetalTextureGetTexture(image.as_ptr())) };
+        match texture {
+            Some(texture) => Some(VideoFrame { texture, image }),
+            None => {
+                unsafe { CFRelease(image.as_ptr().cast()) };
+                None
+            }
+        }
+    }
+}
+
+impl Drop for VideoTextures {
+    fn drop(&mut self) {
+        self.frames.clear();
+        unsafe { CFRelease(self.texture_cache.as_ptr().cast()) };
+    }
+}
+
+// a video playing from a file, its frames are drawn as textures like any other
+pub struct VideoPlayer {
+    player: Retained<AnyObject>,
+    item: Retained<AnyObject>,
+    output: Retained<AnyObject>,
+    textures: VideoTextures,
+    looping: bool,
+}
+
+impl VideoPlayer {
+    pub fn play(&self) {
+        unsafe { msg_send![&self.player, play] }
+    }
+
+    pub fn pause(&self) {
+        unsafe { msg_send![&self.player, pause] }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        let rate: f32 = unsafe { msg_send![&self.player, rate] };
+        rate != 0.
+    }
+
+    // starts over from the beginning once the end is reached
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    // the seconds played, and the length of the video once it's known
+    pub fn current_time(&self) -> Option<f64> {
+        let time: CMTime = unsafe { msg_send![&self.item, currentTime] };
+        time.seconds()
+    }
+
+    pub fn duration(&self) -> Option<f64> {
+        let duration: CMTime = unsafe { msg_send![&self.item, duration] };
+        duration.seconds()
+    }
+
+    pub fn seek(&self, seconds: f64) {
+        let time = CMTime::from_seconds(seconds);
+        unsafe { msg_send![&self.player, seekToTime: time] }
+    }
+
+    // why the video can't be played, e.g. a file of a format AVFoundation can't decode
+    pub fn error(&self) -> Option<String> {
+        let error: Option<Retained<AnyObject>> = unsafe { msg_send_id![&self.item, error] };
+        let description: Retained<NSString> =
+            unsafe { msg_send_id![&error?, localizedDescription] };
+        Some(description.to_string())
+    }
+
+    // picks up the frame the screen shows now, called once a frame before drawing the video.
+    // returns the texture of the latest frame, none until the first one is decoded
+    pub fn update(&mut self) -> Option<&Texture> {
+        let item_time: CMTime =
+            unsafe { msg_send![&self.output, itemTimeForHostTime: CACurrentMediaTime()] };
+        let has_new_frame: bool =
+            unsafe { msg_send![&self.output, hasNewPixelBufferForItemTime: item_time] };
+        if has_new_frame {
+            let pixel_buffer: *mut CVBuffer = unsafe {
+                msg_send![
+                    &self.output,
+                    copyPixelBufferForItemTime: item_time,
+                    itemTimeForDisplay: ptr::null_mut::<CMTime>()
+                ]
+            };
+            if let Some(pixel_buffer) = NonNull::new(pixel_buffer) {
+                self.textures.push(pixel_buffer);
+                unsafe { CFRelease(pixel_buffer.as_ptr().cast()) };
+            }
+        }
+        if self.looping {
+            let ended = self
+                .current_time()
+                .zip(self.duration())
+                .is_some_and(|(time, duration)| time >= duration);
+            if ended {
+                self.seek(0.);
+                self.play();
+            }
+        }
+        self.texture()
+    }
+
+    // the texture of the latest frame, in place until the next one is picked up
+    pub fn texture(&self) -> Option<&Texture> {
+        self.textures.latest()
+    }
+}
+
+impl Drop for VideoPlayer {
+    fn drop(&mut self) {
+        self.pause();
+    }
+}
+
+impl MetalRenderer {
+    // opens the video at `path` paused, it's decoded into textures of the device of the
+    // renderer. a file AVFoundation can't play leaves the texture empty and reports an `error`
+    pub fn open_video(&self, path: impl AsRef<Path>) -> Result<VideoPlayer, VideoError> {
+        let path = path.as_ref().canonicalize()?;
+        let url = unsafe { NSURL::fileURLWithPath(&NSString::from_str(&path.to_string_lossy())) };
+        let textures = VideoTextures::new(&self.device())?;
+
+        let attributes = pixel_buffer_attributes();
+        unsafe {
+            let item: Retained<AnyObject> =
+                msg_send_id![class!(AVPlayerItem), playerItemWithURL: &*url];
+            let output: Allocated<AnyObject> = msg_send_id![class!(AVPlayerItemVideoOutput), alloc];
+            let output: Retained<AnyObject> =
+                msg_send_id![output, initWithPixelBufferAttributes: &*attributes];
+            let _: () = msg_send![&item, addOutput: &*output];
+            let player: Retained<AnyObject> =
+                msg_send_id![class!(AVPlayer), playerWithPlayerItem: &*item];
+            Ok(VideoPlayer {
+                player,
+                item,
+                output,
+                textures,
+                looping: false,
+            })
+        }
+    }
+}
+
+// the settings of the pixel buffers the frames are decoded or captured into, BGRA that metal can
+// read without a copy
+pub(crate) fn pixel_buffer_attributes() -> Retained<NSDictionary<NSString, NSNumber>> {
+    unsafe {
+        NSDictionary::from_vec(
+            &[
+                kCVPixelBufferPixelFormatTypeKey,
+                kCVPixelBufferMetalCompatibilityKey,
+            ],
+            vec![
+                NSNumber::new_u32(PIXEL_FORMAT_BGRA),
+                NSNumber::new_bool(true),
+            ],
+        )
+    }
+}
@@ -0,0 +1,96 @@
+// the viewports and scissor rects of a render pass. the gpus drawing into several viewports at
+// once let the vertex function pick the one of every primitive with `[[viewport_array_index]]`,
+// like `vertex_viewports` does, e.g. for split screens drawn with a single draw
+use core::ptr::NonNull;
+
+use objc2::runtime::ProtocolObject;
+use objc2_metal::{
+    MTLCommandEncoder, MTLDevice, MTLGPUFamily, MTLRenderCommandEncoder, MTLScissorRect,
+    MTLViewport,
+};
+
+use crate::{MetalRenderer, RenderPass};
+
+// the most viewports and scissor rects metal takes for a pass
+pub const MAX_VIEWPORTS: usize = 16;
+
+impl RenderPass {
+    // the viewport of the draws of the pass without one of their own, in place of the one of
+    // the renderer
+    pub fn set_viewport(&mut self, viewport: MTLViewport) -> &mut Self {
+        self.set_viewports(&[viewport])
+    }
+
+    // limits the draws of the pass to `scissor`, which must lie within the render target
+    pub fn set_scissor(&mut self, scissor: MTLScissorRect) -> &mut Self {
+        self.set_scissors(&[scissor])
+    }
+
+    // the viewports the vertex function picks from, up to `MAX_VIEWPORTS`. the first one is
+    // the viewport of the primitives that don't pick one, and of all of them on the gpus
+    // without multiple viewports
+    pub fn set_viewports(&mut self, viewports: &[MTLViewport]) -> &mut Self {
+        self.viewport = viewports.first().copied();
+        self.viewports = viewports[..viewports.len().min(MAX_VIEWPORTS)].to_vec();
+        self
+    }
+
+    // a scissor rect for each of the viewports, each within the render target
+    pub fn set_scissors(&mut self, scissors: &[MTLScissorRect]) -> &mut Self {
+        self.scissor = scissors.first().copied();
+        self.scissors = scissors[..scissors.len().min(MAX_VIEWPORTS)].to_vec();
+        self
+    }
+
+    // whether the pass has more than one viewport or scissor rect for `encoder` to set
+    pub(crate) fn has_multiple_viewports(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+    ) -> bool {
+        if self.viewports.len() < 2 && self.scissors.len() < 2 {
+            return false;
+        }
+        let device = unsafe { encoder.device() };
+        supports_multiple_viewports(&device)
+    }
+
+    // sets the viewport of a draw, its own or the ones of the pass
+    pub(crate) fn encode_viewports(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+        viewport: Option<MTLViewport>,
+        multiple_viewports: bool,
+    ) {
+        if viewport.is_none() && multiple_viewports && self.viewports.len() > 1 {
+            let viewports = NonNull::from(self.viewports.as_slice()).cast();
+            unsafe { encoder.setViewports_count(viewports, self.viewports.len()) };
+        } else if let Some(viewport) = viewport.or(self.viewport) {
+            encoder.setViewport(viewport);
+        }
+    }
+
+    pub(crate) fn encode_scissors(
+        &self,
+        encoder: &ProtocolObject<dyn MTLRenderCommandEncoder>,
+        multiple_viewports: bool,
+    ) {
+        if multiple_viewports && self.scissors.len() > 1 {
+            let scissors = NonNull::from(self.scissors.as_slice()).cast();
+            unsafe { encoder.setScissorRects_count(scissors, self.scissors.len()) };
+        } else if let Some(scissor) = self.scissor {
+            encoder.setScissorRect(scissor);
+        }
+    }
+}
+
+impl MetalRenderer {
+    // whether the vertex functions can pick one of several viewports of a pass, the gpus of
+    // macs and apple silicon from the a12 on
+    pub fn supports_multiple_viewports(&self) -> bool {
+        supports_multiple_viewports(&self.device())
+    }
+}
+
+fn supports_multiple_viewports(device: &ProtocolObject<dyn MTLDevice>) -> bool {
+    device.supportsFamily(MTLGPUFamily::Mac2) || device.supportsFamily(MTLGPUFamily::Apple5)
+}
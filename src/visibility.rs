@@ -0,0 +1,129 @@
+// pauses the frames of a window nobody can see, an occluded or minimized window on macos and an
+// app in the background on ios, and resumes them once it's visible again
+use core::ptr::NonNull;
+
+use block2::RcBlock;
+use objc2::{
+    rc::{Retained, Weak},
+    DeclaredClass,
+};
+use objc2_foundation::{NSNotification, NSNotificationCenter, NSNotificationName, NSObject};
+
+use crate::{platform::NativeWindow, LogLevel, MetalRenderer};
+
+#[cfg(target_os = "macos")]
+use objc2_app_kit::{NSWindowDidChangeOcclusionStateNotification, NSWindowOcclusionState};
+#[cfg(target_os = "ios")]
+use objc2_ui_kit::{
+    UIApplicationDidEnterBackgroundNotification, UIApplicationWillEnterForegroundNotification,
+};
+
+// the notifications telling the renderer its window was hidden or shown, or live resized on
+// macos, removed from the notification center with the renderer
+pub(crate) struct VisibilityObserver {
+    observers: Vec<Retained<NSObject>>,
+}
+
+impl VisibilityObserver {
+    pub(crate) fn new(renderer: &MetalRenderer, window: &NativeWindow) -> Self {
+        let mut observer = Self {
+            observers: Vec::new(),
+        };
+        // the occlusion state covers minimized windows, windows behind others and windows on
+        // another space
+        #[cfg(target_os = "macos")]
+        observer.observe(
+            renderer,
+            unsafe { NSWindowDidChangeOcclusionStateNotification },
+            Some(window),
+            |renderer| {
+                let Some(window) = renderer.ivars().window.get() else {
+                    return;
+                };
+                let visible = window
+                    .occlusionState()
+                    .contains(NSWindowOcclusionState::Visible);
+                renderer.set_occluded(!visible);
+            },
+        );
+        // an app in the background must not use the gpu, its command buffers would fail
+        #[cfg(target_os = "ios")]
+        {
+            let _ = window;
+            observer.observe(
+                renderer,
+                unsafe { UIApplicationDidEnterBackgroundNotification },
+                None,
+                |renderer| renderer.set_occluded(true),
+            );
+            observer.observe(
+                renderer,
+                unsafe { UIApplicationWillEnterForegroundNotification },
+                None,
+                |renderer| renderer.set_occluded(false),
+            );
+        }
+        observer
+    }
+
+    // calls `handler` on the main thread for the notifications named `name` of `object`, for
+    // as long as the renderer is alive. the block only holds on to the renderer weakly
+    pub(crate) fn observe(
+        &mut self,
+        renderer: &MetalRenderer,
+        name: &NSNotificationName,
+        object: Option<&NativeWindow>,
+        handler: impl Fn(&MetalRenderer) + 'static,
+    ) {
+        let renderer = Weak::new(renderer);
+        let block = RcBlock::new(move |_notification: NonNull<NSNotification>| {
+            if let Some(renderer) = renderer.load() {
+                handler(&renderer);
+            }
+        });
+        let observer = unsafe {
+            NSNotificationCenter::defaultCenter().addObserverForName_object_queue_usingBlock(
+                Some(name),
+                object.map(|object| object.as_ref()),
+                None,
+                &block,
+            )
+        };
+        self.observers.push(observer);
+    }
+}
+
+impl Drop for VisibilityObserver {
+    fn drop(&mut self) {
+        let center = unsafe { NSNotificationCenter::defaultCenter() };
+        for observer in &self.observers {
+            unsafe { center.removeObserver(observer) };
+        }
+    }
+}
+
+impl MetalRenderer {
+    // pauses the frames while the window is hidden whatever the redraw mode, called by the
+    // renderer when the window is occluded, minimized or sent to the background. applications
+    // can call it for the windows they hide on their own
+    pub fn set_occluded(&self, occluded: bool) {
+        if self.ivars().occluded.replace(occluded) == occluded {
+            return;
+        }
+        let message = if occluded {
+            "Paused rendering while the window is hidden."
+        } else {
+            "Resumed rendering."
+        };
+        self.log(LogLevel::Debug, message);
+        // a view drawing on demand shows what changed while it was hidden
+        if !occluded {
+            self.ivars().needs_redraw.set(true);
+        }
+        self.update_paused();
+    }
+
+    pub fn is_occluded(&self) -> bool {
+        self.ivars().occluded.get()
+    }
+}
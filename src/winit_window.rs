@@ -0,0 +1,160 @@
+use objc2::{rc::Retained, DeclaredClass};
+use tao::{
+    event::MouseButton,
+    keyboard::{KeyCode, ModifiersState},
+};
+use winit::{
+    error::ExternalError,
+    event::{DeviceEvent, ElementState, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::PhysicalKey,
+    window::{CursorGrabMode, Window},
+};
+
+use crate::{CursorMode, InputState, MetalRenderer, RendererError};
+
+// maps the key codes winit and tao share, which are nearly all of them as both follow the w3c
+// names
+macro_rules! key_code {
+    ($key:expr, $($name:ident),* $(,)?) => {
+        match $key {
+            $(winit::keyboard::KeyCode::$name => Some(KeyCode::$name),)*
+            _ => None,
+        }
+    };
+}
+
+// the tao key of a winit key, none for the few tao doesn't know
+#[rustfmt::skip]
+fn key_code(key: PhysicalKey) -> Option<KeyCode> {
+    let PhysicalKey::Code(key) = key else {
+        return None;
+    };
+    key_code!(
+        key,
+        Backquote, Backslash, BracketLeft, BracketRight, Comma, Digit0, Digit1, Digit2, Digit3,
+        Digit4, Digit5, Digit6, Digit7, Digit8, Digit9, Equal, IntlBackslash, IntlRo, IntlYen, KeyA,
+        KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM, KeyN, KeyO, KeyP,
+        KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ, Minus, Period, Quote, Semicolon,
+        Slash, AltLeft, AltRight, Backspace, CapsLock, ContextMenu, ControlLeft, ControlRight,
+        Enter, SuperLeft, SuperRight, ShiftLeft, ShiftRight, Space, Tab, Convert, KanaMode, Lang1,
+        Lang2, Lang3, Lang4, Lang5, NonConvert, Delete, End, Help, Home, Insert, PageDown, PageUp,
+        ArrowDown, ArrowLeft, ArrowRight, ArrowUp, NumLock, Numpad0, Numpad1, Numpad2, Numpad3,
+        Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, NumpadAdd, NumpadBackspace,
+        NumpadClear, NumpadClearEntry, NumpadComma, NumpadDecimal, NumpadDivide, NumpadEnter,
+        NumpadEqual, NumpadHash, NumpadMemoryAdd, NumpadMemoryClear, NumpadMemoryRecall,
+        NumpadMemoryStore, NumpadMemorySubtract, NumpadMultiply, NumpadParenLeft, NumpadParenRight,
+        NumpadStar, NumpadSubtract, Escape, Fn, FnLock, PrintScreen, ScrollLock, Pause, BrowserBack,
+        BrowserFavorites, BrowserForward, BrowserHome, BrowserRefresh, BrowserSearch, BrowserStop,
+        Eject, LaunchApp1, LaunchApp2, LaunchMail, MediaPlayPause, MediaSelect, MediaStop,
+        MediaTrackNext, MediaTrackPrevious, Power, Sleep, AudioVolumeDown, AudioVolumeMute,
+        AudioVolumeUp, WakeUp, Hyper, Turbo, Abort, Resume, Suspend, Again, Copy, Cut, Find, Open,
+        Paste, Props, Select, Undo, Hiragana, Katakana, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10,
+        F11, F12, F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24, F25, F26, F27, F28,
+        F29, F30, F31, F32, F33, F34, F35
+    )
+}
+
+// the back and forward buttons are the fourth and fifth button of the mouse for tao
+fn mouse_button(button: winit::event::MouseButton) -> MouseButton {
+    match button {
+        winit::event::MouseButton::Left => MouseButton::Left,
+        winit::event::MouseButton::Right => MouseButton::Right,
+        winit::event::MouseButton::Middle => MouseButton::Middle,
+        winit::event::MouseButton::Back => MouseButton::Other(3),
+        winit::event::MouseButton::Forward => MouseButton::Other(4),
+        winit::event::MouseButton::Other(button) => MouseButton::Other(button),
+    }
+}
+
+impl InputState {
+    fn handle_winit_event(&mut self, event: &WindowEvent, scale_factor: f64) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                let Some(key) = key_code(event.physical_key) else {
+                    return;
+                };
+                match event.state {
+                    ElementState::Pressed => self.key_pressed(key, event.repeat),
+                    ElementState::Released => self.key_released(key),
+                }
+            }
+            // both lay the modifiers out in the same bits
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let bits = modifiers.state().bits();
+                self.set_modifiers(ModifiersState::from_bits_truncate(bits))
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => self.button_pressed(mouse_button(*button)),
+                ElementState::Released => self.button_released(mouse_button(*button)),
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_moved(position.to_logical::<f64>(scale_factor).into())
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor_left(),
+            WindowEvent::MouseWheel { delta, .. } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => self.scrolled((*x, *y)),
+                MouseScrollDelta::PixelDelta(position) => {
+                    self.scrolled_points(position.to_logical::<f64>(scale_factor).into())
+                }
+            },
+            WindowEvent::Touch(touch) => {
+                let position = touch.location.to_logical::<f64>(scale_factor).into();
+                match touch.phase {
+                    TouchPhase::Started => self.touch_started(touch.id, position),
+                    TouchPhase::Moved => self.touch_moved(touch.id, position),
+                    TouchPhase::Ended | TouchPhase::Cancelled => self.touch_ended(touch.id),
+                }
+            }
+            WindowEvent::Focused(false) => self.focus_lost(),
+            _ => (),
+        }
+    }
+}
+
+impl MetalRenderer {
+    // a renderer drawing into a winit window, set up like one for a tao window. the application
+    // calls `resize` for its `Resized` and `ScaleFactorChanged` events and `redraw` for
+    // `RedrawRequested`, just as with tao
+    pub fn new_winit(window: &Window) -> Result<Retained<Self>, RendererError> {
+        Self::from_window_handle(window)
+    }
+
+    // feeds an event of the renderer's winit window into its input state, the keys and buttons
+    // show up as the ones of tao. the egui and imgui integrations only take the events of tao
+    pub fn handle_winit_event(&self, event: &WindowEvent) {
+        let scale_factor = self.scale_factor();
+        self.ivars()
+            .input
+            .borrow_mut()
+            .handle_winit_event(event, scale_factor);
+    }
+
+    // feeds a device event of winit into the input state, like `handle_device_event`
+    pub fn handle_winit_device_event(&self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.ivars().input.borrow_mut().mouse_moved(*delta);
+        }
+    }
+
+    // `set_cursor_mode` for a winit window. winit confines the cursor on macOS only by locking
+    // it and locks it on Windows only by confining it, each falls back to the other
+    pub fn set_winit_cursor_mode(
+        &self,
+        window: &Window,
+        mode: CursorMode,
+    ) -> Result<(), ExternalError> {
+        let grab = |mode, fallback| {
+            window
+                .set_cursor_grab(mode)
+                .or_else(|_| window.set_cursor_grab(fallback))
+        };
+        match mode {
+            CursorMode::Normal | CursorMode::Hidden => window.set_cursor_grab(CursorGrabMode::None),
+            CursorMode::Confined => grab(CursorGrabMode::Confined, CursorGrabMode::Locked),
+            CursorMode::Locked => grab(CursorGrabMode::Locked, CursorGrabMode::Confined),
+        }?;
+        window.set_cursor_visible(matches!(mode, CursorMode::Normal | CursorMode::Confined));
+        self.ivars().cursor_mode.set(mode);
+        Ok(())
+    }
+}
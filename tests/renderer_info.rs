@@ -0,0 +1,29 @@
+// checks that `info` reports what a renderer drawing offscreen negotiated. like the snapshots it
+// runs on the main thread without the test harness, and it's skipped without a metal device
+use objc2_metal::MTLPixelFormat;
+use rust_tao_metal::{available_devices, DepthFormat, MetalRenderer};
+
+fn main() {
+    if available_devices().is_empty() {
+        eprintln!("Skipping the renderer info, there's no Metal device.");
+        return;
+    }
+    let renderer = MetalRenderer::new_headless(320, 240);
+    renderer.init().expect("Failed to initialize the renderer.");
+    renderer.set_depth_format(Some(DepthFormat::Depth32Float));
+    assert!(renderer.set_sample_count(4));
+
+    let info = renderer.info();
+    assert!(!info.device_name.is_empty());
+    assert_ne!(info.color_pixel_format, MTLPixelFormat::Invalid);
+    assert_eq!(
+        info.depth_stencil_pixel_format,
+        MTLPixelFormat::Depth32Float
+    );
+    assert_eq!(info.sample_count, 4);
+    assert_eq!(info.drawable_size.width, 320.);
+    assert_eq!(info.drawable_size.height, 240.);
+    // an offscreen renderer shows on no screen
+    assert_eq!(info.max_frames_per_second, None);
+    eprintln!("{info}");
+}
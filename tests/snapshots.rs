@@ -0,0 +1,59 @@
+// renders the scenes the pipeline setup has to keep drawing the same without a window and
+// compares them to the references in tests/snapshots, run with `cargo test --test snapshots`.
+// the renderer can only be created on the main thread, which the threads of the test harness
+// aren't, so it runs without the harness. it's skipped on machines without a metal device.
+// a missing reference is written from the frame and its scene skipped, UPDATE_SNAPSHOTS writes
+// all of them again
+use std::path::Path;
+
+use rust_tao_metal::{
+    available_devices, Background, DepthFormat, MetalRenderer, SnapshotError, SnapshotTolerance,
+};
+
+type SceneSetup = fn(&MetalRenderer);
+
+const SCENES: [(&str, SceneSetup); 3] = [
+    ("triangle", |_| ()),
+    ("gradient", |renderer| {
+        renderer.set_background(Background::Gradient {
+            top: [0.1, 0.2, 0.5],
+            bottom: [0.9, 0.5, 0.2],
+            speed: 0.5,
+        });
+        renderer.set_depth_format(Some(DepthFormat::Depth32Float));
+    }),
+    ("multisampled", |renderer| {
+        renderer.set_sample_count(4);
+    }),
+];
+
+fn main() {
+    if available_devices().is_empty() {
+        eprintln!("Skipping the snapshots, there's no Metal device.");
+        return;
+    }
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    let mut failed = false;
+    for (name, setup) in SCENES {
+        let renderer = MetalRenderer::new_headless(256, 256);
+        renderer.init().expect("Failed to initialize the renderer.");
+        renderer.set_fixed_time(Some(0.5));
+        setup(&renderer);
+        let reference = directory.join(format!("{name}.png"));
+        match renderer.check_snapshot(&reference, &SnapshotTolerance::default()) {
+            Ok(diff) => eprintln!("Snapshot {name} matches: {diff}."),
+            // the frame was written as the reference, there's nothing to compare it to until
+            // the next run. it's checked in once it looks right
+            Err(error @ SnapshotError::MissingReference(_)) => {
+                eprintln!("Skipping snapshot {name}: {error}.");
+            }
+            Err(error) => {
+                eprintln!("Snapshot {name} failed: {error}.");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}